@@ -0,0 +1,374 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Async client bindings for Keystore 2.0, for use by Rust system daemons that run on a tokio
+//! runtime.
+//!
+//! Loading key entries and creating operations go through IKeystoreAsyncService, whose oneway
+//! methods return immediately and report their result through a callback. So awaiting them
+//! does not hold a thread of the runtime, even if the KeyMint HAL is slow. Operations are driven
+//! through the async binder interface of IKeystoreOperation.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    KeyParameter::KeyParameter, SecurityLevel::SecurityLevel,
+};
+use android_security_keystoreasync::aidl::android::security::keystoreasync::{
+    ICreateOperationCallback::{BnCreateOperationCallback, ICreateOperationCallback},
+    IGetKeyEntryCallback::{BnGetKeyEntryCallback, IGetKeyEntryCallback},
+    IKeystoreAsyncService::IKeystoreAsyncService,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Authorization::Authorization, CreateOperationResponse::CreateOperationResponse,
+    IKeystoreOperation::IKeystoreOperationAsync, KeyDescriptor::KeyDescriptor,
+    KeyEntryResponse::KeyEntryResponse, KeyMetadata::KeyMetadata, KeyParameters::KeyParameters,
+    OperationChallenge::OperationChallenge, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use binder::{BinderFeatures, ExceptionCode, Interface, Status, StatusCode, Strong};
+use binder_tokio::Tokio;
+use message_macro::source_location_msg;
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+mod tests;
+
+/// Name of the asynchronous Keystore service.
+pub const ASYNC_SERVICE_NAME: &str = "android.security.keystoreasync";
+
+/// Keystore accepts at most this many bytes per call of `update`, `updateAad`, and `finish`.
+pub const MAX_CHUNK_SIZE: usize = 0x8000;
+
+/// Errors occurred during the interaction with Keystore.
+#[derive(Debug, Clone, Copy, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    /// Keystore failed the request with the given error code, i.e., a positive ResponseCode
+    /// or a negative KeyMint ErrorCode.
+    #[error("Keystore error code {0}")]
+    Keystore(i32),
+
+    /// Keystore dropped the request without completing it, e.g., because it died.
+    #[error("Keystore dropped the request")]
+    RequestDropped,
+
+    /// Wraps a Binder exception code other than a service specific exception.
+    #[error("Binder exception code {0:?}")]
+    Binder(ExceptionCode),
+
+    /// Wraps a Binder status code.
+    #[error("Binder transaction error {0:?}")]
+    BinderTransaction(StatusCode),
+}
+
+impl From<StatusCode> for Error {
+    fn from(s: StatusCode) -> Self {
+        Self::BinderTransaction(s)
+    }
+}
+
+impl From<Status> for Error {
+    fn from(s: Status) -> Self {
+        match s.exception_code() {
+            ExceptionCode::SERVICE_SPECIFIC => Self::Keystore(s.service_specific_error()),
+            ExceptionCode::TRANSACTION_FAILED => Self::BinderTransaction(s.transaction_error()),
+            e => Self::Binder(e),
+        }
+    }
+}
+
+/// Thread-safe channel for sending a value once and only once. If a value has
+/// already been send, subsequent calls to send will noop.
+struct SafeSender<T> {
+    inner: Mutex<Option<oneshot::Sender<T>>>,
+}
+
+impl<T> SafeSender<T> {
+    fn new(sender: oneshot::Sender<T>) -> Self {
+        Self { inner: Mutex::new(Some(sender)) }
+    }
+
+    fn send(&self, value: T) {
+        if let Some(inner) = self.inner.lock().unwrap().take() {
+            // The caller may have stopped waiting for the result and dropped the receiver.
+            // This is not an error.
+            let _ = inner.send(value);
+        }
+    }
+}
+
+struct GetKeyEntryCallback {
+    response_tx: SafeSender<Result<KeyEntryResponse, Error>>,
+}
+
+impl GetKeyEntryCallback {
+    fn new_native_binder(
+        response_tx: oneshot::Sender<Result<KeyEntryResponse, Error>>,
+    ) -> Strong<dyn IGetKeyEntryCallback> {
+        let result = Self { response_tx: SafeSender::new(response_tx) };
+        BnGetKeyEntryCallback::new_binder(result, BinderFeatures::default())
+    }
+}
+
+impl Interface for GetKeyEntryCallback {}
+
+impl IGetKeyEntryCallback for GetKeyEntryCallback {
+    fn onSuccess(&self, response: &KeyEntryResponse) -> binder::Result<()> {
+        self.response_tx.send(Ok(KeyEntryResponse {
+            iSecurityLevel: response.iSecurityLevel.clone(),
+            metadata: KeyMetadata {
+                key: response.metadata.key.clone(),
+                keySecurityLevel: response.metadata.keySecurityLevel,
+                certificate: response.metadata.certificate.clone(),
+                certificateChain: response.metadata.certificateChain.clone(),
+                authorizations: response
+                    .metadata
+                    .authorizations
+                    .iter()
+                    .map(|a| Authorization {
+                        securityLevel: a.securityLevel,
+                        keyParameter: a.keyParameter.clone(),
+                    })
+                    .collect(),
+                modificationTimeMs: response.metadata.modificationTimeMs,
+            },
+        }));
+        Ok(())
+    }
+    fn onError(&self, error_code: i32) -> binder::Result<()> {
+        self.response_tx.send(Err(Error::Keystore(error_code)));
+        Ok(())
+    }
+}
+
+struct CreateOperationCallback {
+    response_tx: SafeSender<Result<CreateOperationResponse, Error>>,
+}
+
+impl CreateOperationCallback {
+    fn new_native_binder(
+        response_tx: oneshot::Sender<Result<CreateOperationResponse, Error>>,
+    ) -> Strong<dyn ICreateOperationCallback> {
+        let result = Self { response_tx: SafeSender::new(response_tx) };
+        BnCreateOperationCallback::new_binder(result, BinderFeatures::default())
+    }
+}
+
+impl Interface for CreateOperationCallback {}
+
+impl ICreateOperationCallback for CreateOperationCallback {
+    fn onSuccess(&self, response: &CreateOperationResponse) -> binder::Result<()> {
+        self.response_tx.send(Ok(CreateOperationResponse {
+            iOperation: response.iOperation.clone(),
+            operationChallenge: response
+                .operationChallenge
+                .as_ref()
+                .map(|c| OperationChallenge { challenge: c.challenge }),
+            parameters: response
+                .parameters
+                .as_ref()
+                .map(|p| KeyParameters { keyParameter: p.keyParameter.clone() }),
+            upgradedBlob: response.upgradedBlob.clone(),
+        }));
+        Ok(())
+    }
+    fn onError(&self, error_code: i32) -> binder::Result<()> {
+        self.response_tx.send(Err(Error::Keystore(error_code)));
+        Ok(())
+    }
+}
+
+/// Waits for the result sent by a callback. If keystore drops the callback without calling it,
+/// the sender is dropped with it and the request fails with `Error::RequestDropped`.
+async fn wait_for_result<T>(rx: oneshot::Receiver<Result<T, Error>>) -> Result<T> {
+    match rx.await {
+        Ok(result) => result.context(source_location_msg!("Keystore failed the request.")),
+        Err(_) => Err(Error::RequestDropped)
+            .context(source_location_msg!("Waiting for the result of the request.")),
+    }
+}
+
+/// Async client of Keystore 2.0.
+pub struct KeystoreClient {
+    service: Strong<dyn IKeystoreAsyncService>,
+}
+
+impl KeystoreClient {
+    /// Connects to the asynchronous Keystore service.
+    pub fn new() -> Result<Self> {
+        let service = binder::get_interface(ASYNC_SERVICE_NAME)
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to connect to IKeystoreAsyncService."))?;
+        Ok(Self::from_service(service))
+    }
+
+    /// Creates a client that uses the given asynchronous Keystore service.
+    pub fn from_service(service: Strong<dyn IKeystoreAsyncService>) -> Self {
+        Self { service }
+    }
+
+    /// Loads the key entry described by `key`. See IKeystoreService::getKeyEntry.
+    pub async fn get_key_entry(&self, key: &KeyDescriptor) -> Result<KeyEntryResponse> {
+        let (tx, rx) = oneshot::channel();
+        let cb = GetKeyEntryCallback::new_native_binder(tx);
+        self.service
+            .getKeyEntry(key, &cb)
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to get key entry."))?;
+        wait_for_result(rx).await
+    }
+
+    /// Creates an operation with the key described by `key` on the given security level.
+    /// See IKeystoreSecurityLevel::createOperation.
+    pub async fn create_operation(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        forced: bool,
+    ) -> Result<Operation> {
+        let (tx, rx) = oneshot::channel();
+        let cb = CreateOperationCallback::new_native_binder(tx);
+        self.service
+            .createOperation(security_level, key, operation_parameters, forced, &cb)
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to create operation."))?;
+        Operation::new(wait_for_result(rx).await?)
+    }
+}
+
+/// An operation created by `KeystoreClient::create_operation`. Keystore aborts the operation
+/// when it is dropped before it was finished.
+pub struct Operation {
+    operation: Strong<dyn IKeystoreOperationAsync<Tokio>>,
+    challenge: Option<OperationChallenge>,
+    parameters: Option<KeyParameters>,
+    upgraded_blob: Option<Vec<u8>>,
+}
+
+impl Operation {
+    fn new(response: CreateOperationResponse) -> Result<Self> {
+        let CreateOperationResponse { iOperation, operationChallenge, parameters, upgradedBlob } =
+            response;
+        let operation = iOperation
+            .ok_or(Error::Keystore(ResponseCode::SYSTEM_ERROR.0))
+            .context(source_location_msg!("Keystore did not return an operation."))?;
+        Ok(Self {
+            operation: operation.into_async(),
+            challenge: operationChallenge,
+            parameters,
+            upgraded_blob: upgradedBlob,
+        })
+    }
+
+    /// Returns the challenge to be included in the auth token authorizing this operation, if any.
+    pub fn challenge(&self) -> Option<&OperationChallenge> {
+        self.challenge.as_ref()
+    }
+
+    /// Returns the parameters that KeyMint returned when the operation was begun, e.g., a
+    /// generated nonce.
+    pub fn parameters(&self) -> Option<&KeyParameters> {
+        self.parameters.as_ref()
+    }
+
+    /// Returns the upgraded blob of a `Domain::BLOB` key, if the key was upgraded.
+    pub fn upgraded_blob(&self) -> Option<&[u8]> {
+        self.upgraded_blob.as_deref()
+    }
+
+    /// Adds associated data to an AEAD operation. See IKeystoreOperation::updateAad.
+    pub async fn update_aad(&self, aad_input: &[u8]) -> Result<()> {
+        self.operation
+            .updateAad(aad_input)
+            .await
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to update AAD."))
+    }
+
+    /// Feeds `input`, which must not exceed `MAX_CHUNK_SIZE`, into the operation.
+    /// See IKeystoreOperation::update.
+    pub async fn update(&self, input: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.operation
+            .update(input)
+            .await
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to update operation."))
+    }
+
+    /// Finishes the operation. See IKeystoreOperation::finish.
+    pub async fn finish(
+        &self,
+        input: Option<&[u8]>,
+        signature: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.operation
+            .finish(input, signature)
+            .await
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to finish operation."))
+    }
+
+    /// Aborts the operation. See IKeystoreOperation::abort.
+    pub async fn abort(&self) -> Result<()> {
+        self.operation
+            .abort()
+            .await
+            .map_err(Error::from)
+            .context(source_location_msg!("Trying to abort operation."))
+    }
+
+    /// Streams everything that can be read from `reader` through the operation in chunks of
+    /// at most `MAX_CHUNK_SIZE` bytes and finishes it, verifying `signature` if given. Returns
+    /// the concatenated output of all steps. Should any step fail, the operation is aborted
+    /// when it is dropped.
+    pub async fn process<R: AsyncRead + Unpin>(
+        self,
+        reader: &mut R,
+        signature: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut chunk = vec![0; MAX_CHUNK_SIZE];
+        loop {
+            let len = read_chunk(reader, &mut chunk).await?;
+            if len == 0 {
+                break;
+            }
+            if let Some(out) = self.update(&chunk[..len]).await? {
+                output.extend_from_slice(&out);
+            }
+        }
+        if let Some(out) = self.finish(None, signature).await? {
+            output.extend_from_slice(&out);
+        }
+        Ok(output)
+    }
+}
+
+/// Fills `chunk` from `reader` unless the end of the input is reached first. Returns the
+/// number of bytes read, which is only less than the size of `chunk` at the end of the input.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, chunk: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < chunk.len() {
+        let n = reader
+            .read(&mut chunk[len..])
+            .await
+            .context(source_location_msg!("Trying to read operation input."))?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    Ok(len)
+}
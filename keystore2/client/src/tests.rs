@@ -0,0 +1,85 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for the async Keystore client.
+
+use super::*;
+
+fn tokio_rt() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+fn root_error<T: std::fmt::Debug>(result: Result<T>) -> Option<Error> {
+    result.unwrap_err().root_cause().downcast_ref::<Error>().copied()
+}
+
+#[test]
+fn test_get_key_entry_callback_success() {
+    let (tx, rx) = oneshot::channel();
+    let cb = GetKeyEntryCallback::new_native_binder(tx);
+    assert!(cb.onSuccess(&KeyEntryResponse::default()).is_ok());
+    // Only the first result is delivered.
+    assert!(cb.onError(ResponseCode::SYSTEM_ERROR.0).is_ok());
+    assert!(tokio_rt().block_on(wait_for_result(rx)).is_ok());
+}
+
+#[test]
+fn test_create_operation_callback_error() {
+    let (tx, rx) = oneshot::channel();
+    let cb = CreateOperationCallback::new_native_binder(tx);
+    assert!(cb.onError(ResponseCode::KEY_NOT_FOUND.0).is_ok());
+    assert_eq!(
+        root_error(tokio_rt().block_on(wait_for_result(rx))),
+        Some(Error::Keystore(ResponseCode::KEY_NOT_FOUND.0))
+    );
+}
+
+#[test]
+fn test_callback_dropped() {
+    let (tx, rx) = oneshot::channel();
+    let cb = GetKeyEntryCallback::new_native_binder(tx);
+    drop(cb);
+    assert_eq!(root_error(tokio_rt().block_on(wait_for_result(rx))), Some(Error::RequestDropped));
+}
+
+#[test]
+fn test_operation_without_binder() {
+    assert_eq!(
+        root_error(Operation::new(CreateOperationResponse::default())),
+        Some(Error::Keystore(ResponseCode::SYSTEM_ERROR.0))
+    );
+}
+
+#[test]
+fn test_status_to_error() {
+    assert_eq!(
+        Error::from(Status::new_service_specific_error(ResponseCode::LOCKED.0, None)),
+        Error::Keystore(ResponseCode::LOCKED.0)
+    );
+    assert_eq!(
+        Error::from(Status::new_exception(ExceptionCode::SECURITY, None)),
+        Error::Binder(ExceptionCode::SECURITY)
+    );
+}
+
+#[test]
+fn test_read_chunk() {
+    let rt = tokio_rt();
+    let input = vec![0xa5; MAX_CHUNK_SIZE + 10];
+    let mut reader = input.as_slice();
+    let mut chunk = vec![0; MAX_CHUNK_SIZE];
+    assert_eq!(rt.block_on(read_chunk(&mut reader, &mut chunk)).unwrap(), MAX_CHUNK_SIZE);
+    assert_eq!(rt.block_on(read_chunk(&mut reader, &mut chunk)).unwrap(), 10);
+    assert_eq!(rt.block_on(read_chunk(&mut reader, &mut chunk)).unwrap(), 0);
+}
@@ -0,0 +1,556 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host tool for inspecting a keystore2 database that was pulled from a device, e.g., with
+//! `adb pull /data/misc/keystore/persistent.sqlite`. If the device was not shut down cleanly,
+//! `persistent.sqlite-wal` must be pulled alongside it, or recent changes will be missing.
+//!
+//! The database is opened read-only. The `summary` command prints the schema version, the
+//! number of key entries per namespace, a breakdown of the stored blobs, and rows that refer to
+//! entries that no longer exist. The `key` command prints a single key entry with its metadata,
+//! blobs and parameters. Key blobs are never printed, only their sizes.
+//!
+//! The tool does not link against keystore2, so the numeric values of the database are decoded
+//! by the tables below, which must be kept in sync with `keystore2/src/database.rs`.
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Inspects a keystore2 database pulled from a device.")]
+struct Cli {
+    /// Path to the pulled persistent.sqlite.
+    db: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Prints the schema version, entry counts, a blob breakdown and orphaned rows (default).
+    Summary,
+    /// Prints the entry, metadata, blobs and parameters of a key.
+    Key {
+        /// Id of the key entry, i.e., the nspace of its `Domain::KEY_ID` descriptor.
+        id: i64,
+    },
+}
+
+/// Names of `Domain` values.
+const DOMAINS: &[&str] = &["APP", "GRANT", "SELINUX", "BLOB", "KEY_ID"];
+/// Names of `KeyType` values.
+const KEY_TYPES: &[&str] = &["Client", "Super"];
+/// Names of `KeyLifeCycle` values.
+const KEY_LIFECYCLES: &[&str] = &["Existing", "Live", "Unreferenced"];
+/// Names of `SubComponentType` values.
+const SUBCOMPONENT_TYPES: &[&str] = &["KEY_BLOB", "CERT", "CERT_CHAIN", "CERT_CHAIN_REF"];
+/// Names of `KeyMetaEntry` tags in declaration order.
+const KEY_METADATA_TAGS: &[&str] = &[
+    "CreationDate",
+    "AttestationExpirationDate",
+    "AttestationMacedPublicKey",
+    "AttestationRawPubKey",
+    "Sec1PublicKey",
+    "CreationBuildFingerprint",
+    "CreationOsPatchLevel",
+    "CreationVendorPatchLevel",
+    "CreationBootPatchLevel",
+    "CreationAttestationSource",
+    "CreationKmVersion",
+    "MaxValidityExpirationDate",
+    "InvalidationDate",
+];
+/// Names of `BlobMetaEntry` tags in declaration order.
+const BLOB_METADATA_TAGS: &[&str] =
+    &["EncryptedBy", "Salt", "Iv", "AeadTag", "KmUuid", "PublicKey", "MaxBootLevel"];
+
+/// `SubComponentType::KEY_BLOB`.
+const KEY_BLOB: i64 = 0;
+/// `KeyLifeCycle::Unreferenced`.
+const UNREFERENCED: i64 = 2;
+
+/// `Tag::APPLICATION_ID` and `Tag::APPLICATION_DATA`, which are redacted should they ever be
+/// recorded.
+const REDACTED_TAGS: &[i64] = &[0x9000_0259, 0x9000_02bc];
+
+/// Checks for rows that refer to rows that do not exist, or that are not referred to although
+/// they should be. Each query counts the offending rows. Subcomponent type 3 is
+/// `SubComponentType::CERT_CHAIN_REF`.
+const ORPHAN_CHECKS: &[(&str, &str)] = &[
+    (
+        "blob entries of missing key entries",
+        "SELECT COUNT(*) FROM blobentry WHERE keyentryid NOT IN (SELECT id FROM keyentry);",
+    ),
+    (
+        "blob metadata of missing blob entries",
+        "SELECT COUNT(*) FROM blobmetadata WHERE blobentryid NOT IN (SELECT id FROM blobentry);",
+    ),
+    (
+        "key parameters of missing key entries",
+        "SELECT COUNT(*) FROM keyparameter WHERE keyentryid NOT IN (SELECT id FROM keyentry);",
+    ),
+    (
+        "key metadata of missing key entries",
+        "SELECT COUNT(*) FROM keymetadata WHERE keyentryid NOT IN (SELECT id FROM keyentry);",
+    ),
+    (
+        "grants of missing key entries",
+        "SELECT COUNT(*) FROM grant WHERE keyentryid NOT IN (SELECT id FROM keyentry);",
+    ),
+    (
+        "certificate chain references without a chain",
+        "SELECT COUNT(*) FROM blobentry WHERE subcomponent_type = 3
+             AND blob NOT IN (SELECT digest FROM certchain);",
+    ),
+    (
+        "unreferenced certificate chains",
+        "SELECT COUNT(*) FROM certchain
+             WHERE digest NOT IN (SELECT blob FROM blobentry WHERE subcomponent_type = 3);",
+    ),
+];
+
+/// Returns the name of `value` in `names`, or the number itself if it is unknown.
+fn name_of(names: &[&str], value: i64) -> String {
+    usize::try_from(value)
+        .ok()
+        .and_then(|i| names.get(i))
+        .map_or_else(|| format!("unknown({value})"), |name| name.to_string())
+}
+
+/// Renders a column value of any type.
+fn render_value(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(r) => r.to_string(),
+        ValueRef::Text(t) => format!("{:?}", String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => format!("[{} bytes] {}", b.len(), hex::encode(b)),
+    }
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open {path:?}."))?;
+    conn.execute_batch("PRAGMA query_only = ON;").context("Failed to make database query only.")?;
+    Ok(conn)
+}
+
+/// Returns the schema version. Databases without a version table predate versioning and have
+/// version 0.
+fn schema_version(conn: &Connection) -> Result<i64> {
+    let has_version_table = conn
+        .query_row(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'version';",
+            [],
+            |_| Ok(()),
+        )
+        .optional()
+        .context("Failed to check for version table.")?
+        .is_some();
+    if !has_version_table {
+        return Ok(0);
+    }
+    conn.query_row("SELECT version FROM version WHERE id = 0;", [], |row| row.get(0))
+        .optional()
+        .context("Failed to read version.")
+        .map(|version| version.unwrap_or(0))
+}
+
+/// Number of key entries of one type and lifecycle state in one namespace.
+#[derive(Debug, PartialEq, Eq)]
+struct NamespaceCount {
+    domain: i64,
+    namespace: i64,
+    key_type: i64,
+    state: i64,
+    count: i64,
+}
+
+fn namespace_counts(conn: &Connection) -> Result<Vec<NamespaceCount>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT domain, namespace, key_type, state, COUNT(*) FROM keyentry
+             GROUP BY domain, namespace, key_type, state
+             ORDER BY domain, namespace, key_type, state;",
+        )
+        .context("Failed to prepare statement.")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(NamespaceCount {
+                domain: row.get(0)?,
+                namespace: row.get(1)?,
+                key_type: row.get(2)?,
+                state: row.get(3)?,
+                count: row.get(4)?,
+            })
+        })
+        .context("Failed to count key entries.")?;
+    rows.collect::<rusqlite::Result<_>>().context("Failed to read key entry counts.")
+}
+
+/// Number and total size of the blobs of one subcomponent type.
+#[derive(Debug, PartialEq, Eq)]
+struct BlobCount {
+    subcomponent_type: i64,
+    count: i64,
+    total_size: i64,
+}
+
+fn blob_counts(conn: &Connection) -> Result<Vec<BlobCount>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT subcomponent_type, COUNT(*), TOTAL(LENGTH(blob)) FROM blobentry
+             GROUP BY subcomponent_type ORDER BY subcomponent_type;",
+        )
+        .context("Failed to prepare statement.")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(BlobCount {
+                subcomponent_type: row.get(0)?,
+                count: row.get(1)?,
+                total_size: row.get::<_, f64>(2)? as i64,
+            })
+        })
+        .context("Failed to count blobs.")?;
+    rows.collect::<rusqlite::Result<_>>().context("Failed to read blob counts.")
+}
+
+/// Returns the number of key blobs that were superseded by a newer blob of the same key entry,
+/// and the number of unreferenced key entries. Both are removed by the garbage collector.
+fn pending_gc_counts(conn: &Connection) -> Result<(i64, i64)> {
+    let superseded = conn
+        .query_row(
+            "SELECT COUNT(*) FROM blobentry WHERE subcomponent_type = ?1 AND id NOT IN (
+                 SELECT MAX(id) FROM blobentry WHERE subcomponent_type = ?1 GROUP BY keyentryid
+             );",
+            params![KEY_BLOB],
+            |row| row.get(0),
+        )
+        .context("Failed to count superseded blobs.")?;
+    let unreferenced = conn
+        .query_row("SELECT COUNT(*) FROM keyentry WHERE state = ?;", params![UNREFERENCED], |row| {
+            row.get(0)
+        })
+        .context("Failed to count unreferenced key entries.")?;
+    Ok((superseded, unreferenced))
+}
+
+/// Runs the orphan checks and returns the description and count of every check that found
+/// offending rows.
+fn find_orphans(conn: &Connection) -> Result<Vec<(&'static str, i64)>> {
+    let mut orphans = Vec::new();
+    for (description, query) in ORPHAN_CHECKS {
+        let count: i64 = conn
+            .query_row(query, [], |row| row.get(0))
+            .with_context(|| format!("Failed to check for {description}."))?;
+        if count != 0 {
+            orphans.push((*description, count));
+        }
+    }
+    Ok(orphans)
+}
+
+fn print_summary(conn: &Connection, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "Schema version: {}", schema_version(conn)?)?;
+
+    writeln!(out, "\nKey entries:")?;
+    for c in namespace_counts(conn)? {
+        writeln!(
+            out,
+            "  {} {:>10}  {:<6}  {:<12}  {}",
+            name_of(DOMAINS, c.domain),
+            c.namespace,
+            name_of(KEY_TYPES, c.key_type),
+            name_of(KEY_LIFECYCLES, c.state),
+            c.count
+        )?;
+    }
+
+    writeln!(out, "\nBlobs:")?;
+    for c in blob_counts(conn)? {
+        writeln!(
+            out,
+            "  {:<14}  {:>6} blobs  {:>10} bytes",
+            name_of(SUBCOMPONENT_TYPES, c.subcomponent_type),
+            c.count,
+            c.total_size
+        )?;
+    }
+
+    let (superseded, unreferenced) = pending_gc_counts(conn)?;
+    writeln!(out, "\nAwaiting garbage collection:")?;
+    writeln!(out, "  superseded key blobs: {superseded}")?;
+    writeln!(out, "  unreferenced key entries: {unreferenced}")?;
+
+    writeln!(out, "\nOrphans:")?;
+    let orphans = find_orphans(conn)?;
+    if orphans.is_empty() {
+        writeln!(out, "  none")?;
+    }
+    for (description, count) in orphans {
+        writeln!(out, "  {description}: {count}")?;
+    }
+    Ok(())
+}
+
+fn print_key(conn: &Connection, id: i64, out: &mut impl Write) -> Result<()> {
+    let entry = conn
+        .query_row(
+            "SELECT key_type, domain, namespace, alias, state, km_uuid FROM keyentry
+             WHERE id = ?;",
+            params![id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    render_value(row.get_ref(3)?),
+                    row.get::<_, i64>(4)?,
+                    render_value(row.get_ref(5)?),
+                ))
+            },
+        )
+        .optional()
+        .context("Failed to load key entry.")?;
+    let Some((key_type, domain, namespace, alias, state, km_uuid)) = entry else {
+        writeln!(out, "No key entry with id {id}.")?;
+        return Ok(());
+    };
+    writeln!(out, "Key entry {id}:")?;
+    writeln!(out, "  key type: {}", name_of(KEY_TYPES, key_type))?;
+    writeln!(out, "  domain: {}", name_of(DOMAINS, domain))?;
+    writeln!(out, "  namespace: {namespace}")?;
+    writeln!(out, "  alias: {alias}")?;
+    writeln!(out, "  state: {}", name_of(KEY_LIFECYCLES, state))?;
+    writeln!(out, "  km uuid: {km_uuid}")?;
+
+    writeln!(out, "\nMetadata:")?;
+    let mut stmt = conn
+        .prepare("SELECT tag, data FROM keymetadata WHERE keyentryid = ? ORDER BY tag;")
+        .context("Failed to prepare statement.")?;
+    let mut rows = stmt.query(params![id]).context("Failed to load key metadata.")?;
+    while let Some(row) = rows.next().context("Failed to read key metadata.")? {
+        let tag: i64 = row.get(0)?;
+        writeln!(out, "  {}: {}", name_of(KEY_METADATA_TAGS, tag), render_value(row.get_ref(1)?))?;
+    }
+
+    writeln!(out, "\nBlobs:")?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, subcomponent_type, blob FROM blobentry WHERE keyentryid = ? ORDER BY id;",
+        )
+        .context("Failed to prepare statement.")?;
+    let blobs = stmt
+        .query_map(params![id], |row| {
+            let blob = row.get_ref(2)?;
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                blob.as_bytes().map_or(0, |b| b.len()),
+            ))
+        })
+        .context("Failed to load blob entries.")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("Failed to read blob entries.")?;
+    let mut stmt = conn
+        .prepare("SELECT tag, data FROM blobmetadata WHERE blobentryid = ? ORDER BY tag;")
+        .context("Failed to prepare statement.")?;
+    for (blob_id, subcomponent_type, size) in blobs {
+        writeln!(
+            out,
+            "  {} {blob_id}: {size} bytes",
+            name_of(SUBCOMPONENT_TYPES, subcomponent_type)
+        )?;
+        let mut rows = stmt.query(params![blob_id]).context("Failed to load blob metadata.")?;
+        while let Some(row) = rows.next().context("Failed to read blob metadata.")? {
+            let tag: i64 = row.get(0)?;
+            writeln!(
+                out,
+                "    {}: {}",
+                name_of(BLOB_METADATA_TAGS, tag),
+                render_value(row.get_ref(1)?)
+            )?;
+        }
+    }
+
+    writeln!(out, "\nParameters:")?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT tag, data, security_level FROM keyparameter WHERE keyentryid = ?
+             ORDER BY security_level, tag;",
+        )
+        .context("Failed to prepare statement.")?;
+    let mut rows = stmt.query(params![id]).context("Failed to load key parameters.")?;
+    while let Some(row) = rows.next().context("Failed to read key parameters.")? {
+        let tag: i64 = row.get(0)?;
+        let security_level: i64 = row.get(2)?;
+        let value = if REDACTED_TAGS.contains(&tag) {
+            "<redacted>".to_string()
+        } else {
+            render_value(row.get_ref(1)?)
+        };
+        writeln!(out, "  tag {tag:#010x} (security level {security_level}): {value}")?;
+    }
+
+    writeln!(out, "\nGrants:")?;
+    let mut stmt = conn
+        .prepare("SELECT id, grantee, access_vector FROM grant WHERE keyentryid = ? ORDER BY id;")
+        .context("Failed to prepare statement.")?;
+    let mut rows = stmt.query(params![id]).context("Failed to load grants.")?;
+    while let Some(row) = rows.next().context("Failed to read grants.")? {
+        let (grant_id, grantee, access_vector): (i64, i64, i64) =
+            (row.get(0)?, row.get(1)?, row.get(2)?);
+        writeln!(out, "  grant {grant_id}: grantee {grantee}, access vector {access_vector:#x}")?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let conn = open(&cli.db)?;
+    let mut out = std::io::stdout().lock();
+    match cli.command.unwrap_or(Command::Summary) {
+        Command::Summary => print_summary(&conn, &mut out),
+        Command::Key { id } => print_key(&conn, id, &mut out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    /// Creates a database with the schema of the current keystore2 version.
+    fn make_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE version (id INTEGER PRIMARY KEY, version INTEGER);
+             INSERT INTO version (id, version) VALUES (0, 2);
+             CREATE TABLE keyentry (id INTEGER UNIQUE, key_type INTEGER, domain INTEGER,
+                 namespace INTEGER, alias BLOB, state INTEGER, km_uuid BLOB);
+             CREATE TABLE blobentry (id INTEGER PRIMARY KEY, subcomponent_type INTEGER,
+                 keyentryid INTEGER, blob BLOB);
+             CREATE TABLE blobmetadata (id INTEGER PRIMARY KEY, blobentryid INTEGER,
+                 tag INTEGER, data ANY, UNIQUE (blobentryid, tag));
+             CREATE TABLE keyparameter (keyentryid INTEGER, tag INTEGER, data ANY,
+                 security_level INTEGER);
+             CREATE TABLE keymetadata (keyentryid INTEGER, tag INTEGER, data ANY,
+                 UNIQUE (keyentryid, tag));
+             CREATE TABLE grant (id INTEGER UNIQUE, grantee INTEGER, keyentryid INTEGER,
+                 access_vector INTEGER);
+             CREATE TABLE certchain (id INTEGER PRIMARY KEY, digest BLOB UNIQUE, chain BLOB);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn populate(conn: &Connection) {
+        conn.execute_batch(
+            "INSERT INTO keyentry VALUES (1, 0, 0, 10100, 'key1', 1, x'00');
+             INSERT INTO keyentry VALUES (2, 0, 0, 10100, 'key2', 1, x'00');
+             INSERT INTO keyentry VALUES (3, 0, 2, 102, 'key3', 2, x'00');
+             INSERT INTO blobentry VALUES (1, 0, 1, x'0102');
+             INSERT INTO blobentry VALUES (2, 0, 1, x'0304');
+             INSERT INTO blobentry VALUES (3, 3, 1, x'aa');
+             INSERT INTO blobentry VALUES (4, 0, 2, x'05');
+             INSERT INTO blobentry VALUES (5, 1, 7, x'06');
+             INSERT INTO blobmetadata VALUES (1, 2, 4, x'00');
+             INSERT INTO blobmetadata VALUES (2, 9, 1, x'00');
+             INSERT INTO keymetadata VALUES (1, 0, 1000);
+             INSERT INTO keyparameter VALUES (1, 805306371, 256, 1);
+             INSERT INTO keyparameter VALUES (1, 2415919705, x'1234', 1);
+             INSERT INTO grant VALUES (1, 10200, 1, 4);
+             INSERT INTO grant VALUES (2, 10200, 8, 4);
+             INSERT INTO certchain VALUES (1, x'bb', x'3000');",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let conn = make_db();
+        assert_eq!(schema_version(&conn).unwrap(), 2);
+        conn.execute_batch("DROP TABLE version;").unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_counts() {
+        let conn = make_db();
+        populate(&conn);
+        assert_eq!(
+            namespace_counts(&conn).unwrap(),
+            vec![
+                NamespaceCount { domain: 0, namespace: 10100, key_type: 0, state: 1, count: 2 },
+                NamespaceCount { domain: 2, namespace: 102, key_type: 0, state: 2, count: 1 },
+            ]
+        );
+        assert_eq!(
+            blob_counts(&conn).unwrap(),
+            vec![
+                BlobCount { subcomponent_type: 0, count: 3, total_size: 5 },
+                BlobCount { subcomponent_type: 1, count: 1, total_size: 1 },
+                BlobCount { subcomponent_type: 3, count: 1, total_size: 1 },
+            ]
+        );
+        assert_eq!(pending_gc_counts(&conn).unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let conn = make_db();
+        assert!(find_orphans(&conn).unwrap().is_empty());
+        populate(&conn);
+        assert_eq!(
+            find_orphans(&conn).unwrap(),
+            vec![
+                ("blob entries of missing key entries", 1),
+                ("blob metadata of missing blob entries", 1),
+                ("grants of missing key entries", 1),
+                ("certificate chain references without a chain", 1),
+                ("unreferenced certificate chains", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_key() {
+        let conn = make_db();
+        populate(&conn);
+        let mut out = Vec::new();
+        print_key(&conn, 1, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("alias: \"key1\""), "{out}");
+        assert!(out.contains("CreationDate: 1000"), "{out}");
+        assert!(out.contains("KEY_BLOB 2: 2 bytes\n    KmUuid: [1 bytes] 00"), "{out}");
+        assert!(out.contains("tag 0x30000003 (security level 1): 256"), "{out}");
+        assert!(out.contains("tag 0x90000259 (security level 1): <redacted>"), "{out}");
+        assert!(out.contains("grant 1: grantee 10200, access vector 0x4"), "{out}");
+        // Key blobs are never printed.
+        assert!(!out.contains("0102"), "{out}");
+
+        let mut out = Vec::new();
+        print_key(&conn, 42, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "No key entry with id 42.\n");
+    }
+}
@@ -13,29 +13,129 @@
 // limitations under the License.
 
 //! Implements the android.security.legacykeystore interface.
-
+//!
+//! Entries are encrypted at rest with AES-256-GCM. The encryption key is derived once per boot
+//! from an HMAC key bound to the TEE KeyMint instance and is only ever held in memory. It is
+//! only derived when an entry is read or written, so listing and removing entries works without
+//! KeyMint. The owner and alias of an entry are authenticated along with it, so an entry cannot
+//! be moved to another row of the database. Entries written before at-rest encryption was
+//! introduced are stored in the clear, and entries written before the owner and alias were
+//! authenticated are not bound to their row; they are still readable and get resealed the next
+//! time they are read or written.
+//!
+//! If the KeyMint key that the at-rest key is derived from is lost, e.g., because the keystore2
+//! database was wiped, a different at-rest key is derived and the encrypted entries can never be
+//! opened again. This is detected with a check value that is sealed under the at-rest key, see
+//! `DB::check_at_rest_key`. The entries that cannot be opened anymore are deleted, so that they
+//! read as missing and can be put again, instead of failing with a system error forever.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, Digest::Digest, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
+};
 use android_security_legacykeystore::aidl::android::security::legacykeystore::{
     ILegacyKeystore::BnLegacyKeystore, ILegacyKeystore::ILegacyKeystore,
     ILegacyKeystore::ERROR_ENTRY_NOT_FOUND, ILegacyKeystore::ERROR_PERMISSION_DENIED,
     ILegacyKeystore::ERROR_SYSTEM_ERROR, ILegacyKeystore::UID_SELF,
+    MigrationRequest::MigrationRequest, MigrationResult::MigrationResult,
+    MigrationStatus::MigrationStatus,
 };
 use android_security_legacykeystore::binder::{
     BinderFeatures, ExceptionCode, Result as BinderResult, Status as BinderStatus, Strong,
     ThreadState,
 };
+use android_system_keystore2::aidl::android::system::keystore2::{
+    IKeystoreSecurityLevel::IKeystoreSecurityLevel, IKeystoreService::IKeystoreService,
+};
 use anyhow::{Context, Result};
 use keystore2::{
-    async_task::AsyncTask, error::anyhow_error_to_cstring, globals::SUPER_KEY,
-    legacy_blob::LegacyBlobLoader, maintenance::DeleteListener, maintenance::Domain,
+    async_task::AsyncTask, database::KeyType, error::anyhow_error_to_cstring, globals::DB as KS_DB,
+    globals::SUPER_KEY, key_parameter::KeyParameterValue, legacy_blob::LegacyBlobLoader,
+    maintenance::DeleteListener, maintenance::Domain, raw_device::KeyMintDevice,
     utils::uid_to_android_user, utils::watchdog as wd,
 };
+use keystore2_crypto::{
+    aes_gcm_decrypt_with_aad, aes_gcm_encrypt_with_aad, ZVec, GCM_IV_LENGTH, TAG_LENGTH,
+};
 use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
 };
 
+mod migration;
+
+/// Envelope version of entries that were stored before at-rest encryption was introduced.
+const ENVELOPE_PLAINTEXT: i64 = 0;
+/// Envelope version of entries encrypted with AES-256-GCM under the at-rest key. The stored
+/// profile is the IV, followed by the tag, followed by the ciphertext.
+const ENVELOPE_AES_256_GCM: i64 = 1;
+/// Like `ENVELOPE_AES_256_GCM`, but the owner and alias of the entry are authenticated as
+/// additional data, see `entry_aad`. This is the envelope version of all new entries.
+const ENVELOPE_AES_256_GCM_BOUND: i64 = 2;
+
+/// The plaintext of the check value of the at-rest key, see `DB::check_at_rest_key`. It is
+/// sealed without additional data, which no entry uses.
+const AT_REST_KEY_CHECK: &[u8] = b"legacykeystore at-rest key check";
+
+/// Returns the additional data that binds an entry to its row: the owner in big endian, which
+/// has a fixed size, followed by the alias.
+fn entry_aad(owner: u32, alias: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + alias.len());
+    aad.extend_from_slice(&owner.to_be_bytes());
+    aad.extend_from_slice(alias.as_bytes());
+    aad
+}
+
+/// Encrypts `plaintext` under `key` with the additional data `aad` and returns the AES-256-GCM
+/// envelope.
+fn seal(key: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let (ciphertext, iv, tag) =
+        aes_gcm_encrypt_with_aad(plaintext, key, aad).context("In seal: Failed to encrypt.")?;
+    let mut envelope = Vec::with_capacity(iv.len() + tag.len() + ciphertext.len());
+    envelope.extend_from_slice(&iv);
+    envelope.extend_from_slice(&tag);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Returns the plaintext of the AES-256-GCM envelope `envelope` sealed with `aad`.
+fn open(key: &[u8], aad: &[u8], envelope: &[u8]) -> Result<ZVec> {
+    if envelope.len() < GCM_IV_LENGTH + TAG_LENGTH {
+        return Err(Error::sys()).context("In open: Envelope is truncated.");
+    }
+    let (iv, rest) = envelope.split_at(GCM_IV_LENGTH);
+    let (tag, ciphertext) = rest.split_at(TAG_LENGTH);
+    aes_gcm_decrypt_with_aad(ciphertext, iv, tag, key, aad).context("In open: Failed to decrypt.")
+}
+
+/// Encrypts the entry of `owner` and `alias` under `key` and returns the envelope of version
+/// `ENVELOPE_AES_256_GCM_BOUND`.
+fn seal_entry(key: &[u8], owner: u32, alias: &str, entry: &[u8]) -> Result<Vec<u8>> {
+    seal(key, &entry_aad(owner, alias), entry).context("In seal_entry.")
+}
+
+/// Returns the plaintext of the stored profile of `owner` and `alias` given its envelope
+/// version.
+fn open_entry(
+    key: &[u8],
+    owner: u32,
+    alias: &str,
+    envelope_version: i64,
+    profile: &[u8],
+) -> Result<Vec<u8>> {
+    let entry = match envelope_version {
+        ENVELOPE_PLAINTEXT => return Ok(profile.to_vec()),
+        ENVELOPE_AES_256_GCM => open(key, &[], profile),
+        ENVELOPE_AES_256_GCM_BOUND => open(key, &entry_aad(owner, alias), profile),
+        v => {
+            return Err(Error::sys())
+                .context(format!("In open_entry: Unknown envelope version {}.", v))
+        }
+    };
+    Ok(entry.context("In open_entry: Failed to open entry.")?.to_vec())
+}
+
 struct DB {
     conn: Connection,
 }
@@ -97,10 +197,70 @@ impl DB {
                 [],
             )
             .context("Failed to initialize \"profiles\" table.")?;
+            let has_envelope_version: bool = tx
+                .query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info('profiles')
+                     WHERE name = 'envelope_version';",
+                    [],
+                    |row| row.get(0),
+                )
+                .context("Failed to query \"profiles\" table info.")?;
+            if !has_envelope_version {
+                // Rows that predate at-rest encryption are plaintext.
+                tx.execute(
+                    "ALTER TABLE profiles ADD COLUMN envelope_version INTEGER NOT NULL DEFAULT 0;",
+                    [],
+                )
+                .context("Failed to add \"envelope_version\" column.")?;
+            }
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS at_rest_key_check (
+                     id INTEGER PRIMARY KEY,
+                     check_value BLOB NOT NULL);",
+                [],
+            )
+            .context("Failed to initialize \"at_rest_key_check\" table.")?;
             Ok(())
         })
     }
 
+    /// Checks that `at_rest_key` opens the check value that was sealed under the at-rest key of
+    /// the encrypted entries, and seals one if there is none yet. If it does not, the at-rest
+    /// key was lost, see the module documentation, so the encrypted entries are deleted and the
+    /// check value is sealed under `at_rest_key`. Plaintext entries are kept. Returns the number
+    /// of deleted entries. Entries that were sealed before there was a check value are assumed
+    /// to be sealed under the first key that is checked.
+    fn check_at_rest_key(&mut self, at_rest_key: &[u8]) -> Result<usize> {
+        let check_value = seal(at_rest_key, &[], AT_REST_KEY_CHECK)
+            .context("In check_at_rest_key: Failed to seal check value.")?;
+        self.with_transaction(TransactionBehavior::Immediate, |tx| {
+            let stored: Option<Vec<u8>> = tx
+                .query_row("SELECT check_value FROM at_rest_key_check WHERE id = 0;", [], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .context("In check_at_rest_key: Failed to load check value.")?;
+            let deleted = match stored {
+                Some(stored) => match open(at_rest_key, &[], &stored) {
+                    Ok(check) if &check[..] == AT_REST_KEY_CHECK => return Ok(0),
+                    _ => tx
+                        .execute(
+                            "DELETE FROM profiles WHERE envelope_version != ?;",
+                            params![ENVELOPE_PLAINTEXT],
+                        )
+                        .context("In check_at_rest_key: Failed to delete entries.")?,
+                },
+                None => 0,
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO at_rest_key_check (id, check_value) VALUES (0, ?);",
+                params![check_value],
+            )
+            .context("In check_at_rest_key: Failed to store check value.")?;
+            Ok(deleted)
+        })
+    }
+
     fn list(&mut self, caller_uid: u32) -> Result<Vec<String>> {
         self.with_transaction(TransactionBehavior::Deferred, |tx| {
             let mut stmt = tx
@@ -121,28 +281,86 @@ impl DB {
         })
     }
 
-    fn put(&mut self, caller_uid: u32, alias: &str, entry: &[u8]) -> Result<()> {
+    fn put(
+        &mut self,
+        at_rest_key: &[u8],
+        caller_uid: u32,
+        alias: &str,
+        entry: &[u8],
+    ) -> Result<()> {
         ensure_keystore_put_is_enabled()?;
+        let envelope = seal_entry(at_rest_key, caller_uid, alias, entry).context("In put.")?;
         self.with_transaction(TransactionBehavior::Immediate, |tx| {
             tx.execute(
-                "INSERT OR REPLACE INTO profiles (owner, alias, profile) values (?, ?, ?)",
-                params![caller_uid, alias, entry,],
+                "INSERT OR REPLACE INTO profiles (owner, alias, profile, envelope_version)
+                     values (?, ?, ?, ?)",
+                params![caller_uid, alias, envelope, ENVELOPE_AES_256_GCM_BOUND],
             )
             .context("In put: Failed to insert or replace.")?;
             Ok(())
         })
     }
 
-    fn get(&mut self, caller_uid: u32, alias: &str) -> Result<Option<Vec<u8>>> {
+    fn get(&mut self, at_rest_key: &[u8], caller_uid: u32, alias: &str) -> Result<Option<Vec<u8>>> {
         ensure_keystore_get_is_enabled()?;
-        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+        let stored = self.with_transaction(TransactionBehavior::Deferred, |tx| {
             tx.query_row(
-                "SELECT profile FROM profiles WHERE owner = ? AND alias = ?;",
+                "SELECT profile, envelope_version FROM profiles WHERE owner = ? AND alias = ?;",
                 params![caller_uid, alias],
-                |row| row.get(0),
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?)),
             )
             .optional()
             .context("In get: failed loading entry.")
+        })?;
+        let Some((profile, envelope_version)) = stored else {
+            return Ok(None);
+        };
+        let entry = open_entry(at_rest_key, caller_uid, alias, envelope_version, &profile)
+            .context("In get.")?;
+        if envelope_version != ENVELOPE_AES_256_GCM_BOUND {
+            if let Err(e) = self.reseal_entry(
+                at_rest_key,
+                caller_uid,
+                alias,
+                envelope_version,
+                &profile,
+                &entry,
+            ) {
+                log::warn!("In get: Failed to reseal entry. {:?}", e);
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    /// Replaces the stored `profile` of an entry of an older envelope version with the envelope
+    /// of version `ENVELOPE_AES_256_GCM_BOUND` of `entry`, unless the entry was changed
+    /// concurrently.
+    fn reseal_entry(
+        &mut self,
+        at_rest_key: &[u8],
+        caller_uid: u32,
+        alias: &str,
+        envelope_version: i64,
+        profile: &[u8],
+        entry: &[u8],
+    ) -> Result<()> {
+        let envelope =
+            seal_entry(at_rest_key, caller_uid, alias, entry).context("In reseal_entry.")?;
+        self.with_transaction(TransactionBehavior::Immediate, |tx| {
+            tx.execute(
+                "UPDATE profiles SET profile = ?, envelope_version = ?
+                     WHERE owner = ? AND alias = ? AND envelope_version = ? AND profile = ?;",
+                params![
+                    envelope,
+                    ENVELOPE_AES_256_GCM_BOUND,
+                    caller_uid,
+                    alias,
+                    envelope_version,
+                    profile
+                ],
+            )
+            .context("In reseal_entry: Failed to update entry.")?;
+            Ok(())
         })
     }
 
@@ -157,12 +375,49 @@ impl DB {
         Ok(removed == 1)
     }
 
-    fn remove_uid(&mut self, uid: u32) -> Result<()> {
+    fn list_owners(&mut self) -> Result<Vec<u32>> {
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare("SELECT DISTINCT owner FROM profiles ORDER BY owner ASC;")
+                .context("In list_owners: Failed to prepare statement.")?;
+
+            // See `list` for why this allow is necessary.
+            #[allow(clippy::let_and_return)]
+            let owners = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<u32>>>()
+                .context("In list_owners: query_map failed.");
+            owners
+        })
+    }
+
+    fn remove_prefix(&mut self, uid: u32, prefix: &str) -> Result<usize> {
+        self.with_transaction(TransactionBehavior::Immediate, |tx| {
+            let mut stmt = tx
+                .prepare("SELECT alias FROM profiles WHERE owner = ?;")
+                .context("In remove_prefix: Failed to prepare statement.")?;
+            let aliases = stmt
+                .query_map(params![uid], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()
+                .context("In remove_prefix: query_map failed.")?;
+            let mut removed = 0;
+            for alias in aliases.iter().filter(|alias| alias.starts_with(prefix)) {
+                removed += tx
+                    .execute(
+                        "DELETE FROM profiles WHERE owner = ? AND alias = ?;",
+                        params![uid, alias],
+                    )
+                    .context("In remove_prefix: Failed to delete row.")?;
+            }
+            Ok(removed)
+        })
+    }
+
+    fn remove_uid(&mut self, uid: u32) -> Result<usize> {
         self.with_transaction(TransactionBehavior::Immediate, |tx| {
             tx.execute("DELETE FROM profiles WHERE owner = ?;", params![uid])
                 .context("In remove_uid: Failed to delete.")
-        })?;
-        Ok(())
+        })
     }
 
     fn remove_user(&mut self, user_id: u32) -> Result<()> {
@@ -268,6 +523,8 @@ impl DeleteListener for LegacyKeystoreDeleteListener {
 pub struct LegacyKeystore {
     db_path: PathBuf,
     async_task: AsyncTask,
+    /// The at-rest encryption key, derived on first use and kept for the rest of the boot.
+    at_rest_key: Mutex<Option<Arc<ZVec>>>,
 }
 
 struct AsyncState {
@@ -284,6 +541,11 @@ impl LegacyKeystore {
     const WIFI_NAMESPACE: i64 = 102;
     const AID_WIFI: u32 = 1010;
 
+    const KEYSTORE_SERVICE_NAME: &'static str = "android.system.keystore2.IKeystoreService/default";
+
+    /// Alias of the KeyMint key from which the at-rest encryption key is derived.
+    const AT_REST_KEY_ALIAS: &'static str = "legacykeystore_at_rest_key";
+
     /// Creates a new LegacyKeystore instance.
     pub fn new_native_binder(
         path: &Path,
@@ -291,7 +553,11 @@ impl LegacyKeystore {
         let mut db_path = path.to_path_buf();
         db_path.push(Self::LEGACY_KEYSTORE_FILE_NAME);
 
-        let legacy_keystore = Arc::new(Self { db_path, async_task: Default::default() });
+        let legacy_keystore = Arc::new(Self {
+            db_path,
+            async_task: Default::default(),
+            at_rest_key: Default::default(),
+        });
         legacy_keystore.init_shelf(path);
         let service = LegacyKeystoreService { legacy_keystore: legacy_keystore.clone() };
         (
@@ -304,6 +570,68 @@ impl LegacyKeystore {
         DB::new(&self.db_path).context("In open_db: Failed to open db.")
     }
 
+    /// Returns the at-rest key, which only `get` and `put` need. When it is derived, the
+    /// entries that it cannot open are deleted, see `DB::check_at_rest_key`.
+    fn get_at_rest_key(&self) -> Result<Arc<ZVec>> {
+        // Holding the lock also serializes the lookup or generation of the KeyMint key.
+        let mut at_rest_key = self.at_rest_key.lock().unwrap();
+        if let Some(key) = at_rest_key.as_ref() {
+            return Ok(key.clone());
+        }
+        let key = Arc::new(Self::derive_at_rest_key().context("In get_at_rest_key.")?);
+        let deleted = self
+            .open_db()
+            .and_then(|mut db| db.check_at_rest_key(&key))
+            .context("In get_at_rest_key: Failed to check the at-rest key.")?;
+        if deleted > 0 {
+            log::error!(
+                "In get_at_rest_key: The at-rest key was lost. Deleted {} entries that can no \
+                 longer be decrypted.",
+                deleted
+            );
+        }
+        *at_rest_key = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Derives the at-rest encryption key by signing a fixed message with an HMAC key that is
+    /// bound to the TEE KeyMint instance. The KeyMint key is persistent, so entries remain
+    /// readable across reboots, but the derived key never leaves this process.
+    fn derive_at_rest_key() -> Result<ZVec> {
+        let km_dev = KeyMintDevice::get(SecurityLevel::TRUSTED_ENVIRONMENT)
+            .context("In derive_at_rest_key: Get TEE instance failed.")?;
+        let params = [
+            KeyParameterValue::Algorithm(Algorithm::HMAC).into(),
+            KeyParameterValue::Digest(Digest::SHA_2_256).into(),
+            KeyParameterValue::KeySize(256).into(),
+            KeyParameterValue::MinMacLength(256).into(),
+            KeyParameterValue::KeyPurpose(KeyPurpose::SIGN).into(),
+            KeyParameterValue::NoAuthRequired.into(),
+        ];
+        let key_desc = KeyMintDevice::internal_descriptor(Self::AT_REST_KEY_ALIAS.to_string());
+        let key = KS_DB.with(|db| {
+            let mut db = db.borrow_mut();
+            let (key_id_guard, key_entry) = km_dev
+                .lookup_or_generate_key(&mut db, &key_desc, KeyType::Client, &params, |_| true)
+                .context("In derive_at_rest_key: lookup_or_generate_key failed.")?;
+            km_dev
+                .use_key_in_one_step(
+                    &mut db,
+                    &key_id_guard,
+                    &key_entry,
+                    KeyPurpose::SIGN,
+                    &[
+                        KeyParameterValue::MacLength(256).into(),
+                        KeyParameterValue::Digest(Digest::SHA_2_256).into(),
+                    ],
+                    None,
+                    b"Create legacy keystore at-rest key",
+                )
+                .context("In derive_at_rest_key: use_key_in_one_step failed.")
+        })?;
+        ZVec::try_from(key).context("In derive_at_rest_key: conversion to ZVec failed.")
+    }
+
     fn get_effective_uid(uid: i32) -> Result<u32> {
         const AID_SYSTEM: u32 = 1000;
         let calling_uid = ThreadState::get_calling_uid();
@@ -326,28 +654,40 @@ impl LegacyKeystore {
 
     fn get(&self, alias: &str, uid: i32) -> Result<Vec<u8>> {
         ensure_keystore_get_is_enabled()?;
-        let mut db = self.open_db().context("In get.")?;
-        let uid = Self::get_effective_uid(uid).context("In get.")?;
+        self.load(alias, uid).context("In get.")
+    }
+
+    /// Loads an entry. Unlike `get`, this works even after `get` was disabled, so that entries
+    /// can still be migrated.
+    fn load(&self, alias: &str, uid: i32) -> Result<Vec<u8>> {
+        let mut db = self.open_db().context("In load.")?;
+        let uid = Self::get_effective_uid(uid).context("In load.")?;
+        let at_rest_key = self.get_at_rest_key().context("In load.")?;
 
-        if let Some(entry) = db.get(uid, alias).context("In get: Trying to load entry from DB.")? {
+        if let Some(entry) =
+            db.get(&at_rest_key, uid, alias).context("In load: Trying to load entry from DB.")?
+        {
             return Ok(entry);
         }
-        if self.get_legacy(uid, alias).context("In get: Trying to import legacy blob.")? {
+        if self.get_legacy(uid, alias).context("In load: Trying to import legacy blob.")? {
             // If we were able to import a legacy blob try again.
-            if let Some(entry) =
-                db.get(uid, alias).context("In get: Trying to load entry from DB.")?
+            if let Some(entry) = db
+                .get(&at_rest_key, uid, alias)
+                .context("In load: Trying to load entry from DB.")?
             {
                 return Ok(entry);
             }
         }
-        Err(Error::not_found()).context("In get: No such entry.")
+        Err(Error::not_found()).context("In load: No such entry.")
     }
 
     fn put(&self, alias: &str, uid: i32, entry: &[u8]) -> Result<()> {
         ensure_keystore_put_is_enabled()?;
         let uid = Self::get_effective_uid(uid).context("In put.")?;
         let mut db = self.open_db().context("In put.")?;
-        db.put(uid, alias, entry).context("In put: Trying to insert entry into DB.")?;
+        let at_rest_key = self.get_at_rest_key().context("In put.")?;
+        db.put(&at_rest_key, uid, alias, entry)
+            .context("In put: Trying to insert entry into DB.")?;
         // When replacing an entry, make sure that there is no stale legacy file entry.
         let _ = self.remove_legacy(uid, alias);
         Ok(())
@@ -388,7 +728,8 @@ impl LegacyKeystore {
             log::warn!("In LegacyKeystore::delete_namespace: {:?}", e);
         }
         let mut db = self.open_db().context("In LegacyKeystore::delete_namespace.")?;
-        db.remove_uid(uid).context("In LegacyKeystore::delete_namespace.")
+        db.remove_uid(uid).context("In LegacyKeystore::delete_namespace.")?;
+        Ok(())
     }
 
     fn delete_user(&self, user_id: u32) -> Result<()> {
@@ -410,6 +751,127 @@ impl LegacyKeystore {
         Ok(result)
     }
 
+    /// The bulk operations are reserved for the system server, which uses them to clean up
+    /// after uninstalled apps and removed users.
+    fn check_privileged_caller() -> Result<()> {
+        const AID_SYSTEM: u32 = 1000;
+        let calling_uid = ThreadState::get_calling_uid();
+        if calling_uid == AID_SYSTEM {
+            Ok(())
+        } else {
+            Err(Error::perm())
+                .with_context(|| format!("In check_privileged_caller: caller: {}.", calling_uid))
+        }
+    }
+
+    fn check_namespace(uid: i32) -> Result<u32> {
+        u32::try_from(uid)
+            .map_err(|_| Error::sys())
+            .with_context(|| format!("In check_namespace: Invalid namespace {}.", uid))
+    }
+
+    fn list_namespaces(&self) -> Result<Vec<i32>> {
+        Self::check_privileged_caller().context("In list_namespaces.")?;
+        let mut db = self.open_db().context("In list_namespaces.")?;
+        let owners = db.list_owners().context("In list_namespaces: Trying to list owners.")?;
+        Ok(owners.into_iter().map(|uid| uid as i32).collect())
+    }
+
+    fn remove_by_prefix(&self, prefix: &str, uid: i32) -> Result<i32> {
+        Self::check_privileged_caller().context("In remove_by_prefix.")?;
+        let uid = Self::check_namespace(uid).context("In remove_by_prefix.")?;
+        let mut removed = 0;
+        for alias in self.list_legacy(uid).context("In remove_by_prefix.")? {
+            if alias.starts_with(prefix)
+                && self.remove_legacy(uid, &alias).context("In remove_by_prefix.")?
+            {
+                removed += 1;
+            }
+        }
+        let mut db = self.open_db().context("In remove_by_prefix.")?;
+        removed +=
+            db.remove_prefix(uid, prefix).context("In remove_by_prefix: Trying to remove.")?;
+        Ok(removed as i32)
+    }
+
+    fn remove_namespace(&self, uid: i32) -> Result<i32> {
+        Self::check_privileged_caller().context("In remove_namespace.")?;
+        let uid = Self::check_namespace(uid).context("In remove_namespace.")?;
+        let mut removed = self.bulk_delete_uid(uid).context("In remove_namespace.")?;
+        let mut db = self.open_db().context("In remove_namespace.")?;
+        removed += db.remove_uid(uid).context("In remove_namespace: Trying to remove.")?;
+        Ok(removed as i32)
+    }
+
+    fn migrate_to_keystore(&self, requests: &[MigrationRequest]) -> Result<Vec<MigrationResult>> {
+        Self::check_privileged_caller().context("In migrate_to_keystore.")?;
+        // The Keystore 2.0 service lives in this process, so calling it preserves the identity
+        // of the caller, and the key is imported subject to the caller's permissions.
+        let keystore: Strong<dyn IKeystoreService> =
+            binder::get_interface(Self::KEYSTORE_SERVICE_NAME)
+                .map_err(|_| Error::sys())
+                .context("In migrate_to_keystore: Failed to get Keystore 2.0 service.")?;
+        let sec_level = keystore
+            .getSecurityLevel(SecurityLevel::TRUSTED_ENVIRONMENT)
+            .map_err(|_| Error::sys())
+            .context("In migrate_to_keystore: Failed to get security level.")?;
+        Ok(requests
+            .iter()
+            .map(|request| {
+                let mut result =
+                    MigrationResult { alias: request.alias.clone(), ..Default::default() };
+                if let Err((status, e)) =
+                    self.migrate_one(&*keystore, &*sec_level, request, &mut result)
+                {
+                    log::warn!(
+                        "In migrate_to_keystore: Migration of {:?} failed with {:?}: {:?}",
+                        request.alias,
+                        status,
+                        e
+                    );
+                    result.status = status;
+                    result.message = format!("{:?}", e);
+                }
+                result
+            })
+            .collect())
+    }
+
+    fn migrate_one(
+        &self,
+        keystore: &dyn IKeystoreService,
+        sec_level: &dyn IKeystoreSecurityLevel,
+        request: &MigrationRequest,
+        result: &mut MigrationResult,
+    ) -> std::result::Result<(), migration::Failure> {
+        let destination = &request.destination;
+        if !matches!(destination.domain, Domain::APP | Domain::SELINUX)
+            || destination.alias.is_none()
+        {
+            return Err((
+                MigrationStatus::INVALID_REQUEST,
+                anyhow::anyhow!("Invalid destination {:?}.", destination),
+            ));
+        }
+        let payload = self.load(&request.alias, request.uid).map_err(|e| {
+            let status = match e.root_cause().downcast_ref::<Error>() {
+                Some(Error::Error(ERROR_ENTRY_NOT_FOUND)) => MigrationStatus::NOT_FOUND,
+                Some(Error::Error(ERROR_PERMISSION_DENIED)) => MigrationStatus::INVALID_REQUEST,
+                _ => MigrationStatus::SYSTEM_ERROR,
+            };
+            (status, e)
+        })?;
+        let credential = migration::parse_payload(&payload, request.password.as_deref())
+            .map_err(|e| (MigrationStatus::PARSE_FAILED, e))?;
+        migration::store_credential(keystore, sec_level, destination, &credential, result)?;
+        if request.removeLegacyEntry {
+            self.remove(&request.alias, request.uid)
+                .map_err(|e| (MigrationStatus::REMOVE_FAILED, e))?;
+            result.legacyEntryRemoved = true;
+        }
+        Ok(())
+    }
+
     fn init_shelf(&self, path: &Path) {
         let mut db_path = path.to_path_buf();
         self.async_task.queue_hi(move |shelf| {
@@ -444,14 +906,20 @@ impl LegacyKeystore {
 
     fn get_legacy(&self, uid: u32, alias: &str) -> Result<bool> {
         let alias = alias.to_string();
+        let at_rest_key = self.get_at_rest_key().context("In get_legacy.")?;
         self.do_serialized(move |state| {
             if state.recently_imported.contains(&(uid, alias.clone())) {
                 return Ok(true);
             }
             let mut db = DB::new(&state.db_path).context("In open_db: Failed to open db.")?;
-            let imported =
-                Self::import_one_legacy_entry(uid, &alias, &state.legacy_loader, &mut db)
-                    .context("Trying to import legacy keystore entries.")?;
+            let imported = Self::import_one_legacy_entry(
+                uid,
+                &alias,
+                &state.legacy_loader,
+                &mut db,
+                &at_rest_key,
+            )
+            .context("Trying to import legacy keystore entries.")?;
             if imported {
                 state.recently_imported.insert((uid, alias));
             }
@@ -473,18 +941,23 @@ impl LegacyKeystore {
         })
     }
 
-    fn bulk_delete_uid(&self, uid: u32) -> Result<()> {
+    fn bulk_delete_uid(&self, uid: u32) -> Result<usize> {
         self.do_serialized(move |state| {
             let entries = state
                 .legacy_loader
                 .list_legacy_keystore_entries_for_uid(uid)
                 .context("In bulk_delete_uid: Trying to list entries.")?;
+            let mut removed = 0;
             for alias in entries.iter() {
-                if let Err(e) = state.legacy_loader.remove_legacy_keystore_entry(uid, alias) {
-                    log::warn!("In bulk_delete_uid: Failed to delete legacy entry. {:?}", e);
+                match state.legacy_loader.remove_legacy_keystore_entry(uid, alias) {
+                    Ok(true) => removed += 1,
+                    Ok(false) => {}
+                    Err(e) => {
+                        log::warn!("In bulk_delete_uid: Failed to delete legacy entry. {:?}", e)
+                    }
                 }
             }
-            Ok(())
+            Ok(removed)
         })
     }
 
@@ -510,6 +983,7 @@ impl LegacyKeystore {
         alias: &str,
         legacy_loader: &LegacyBlobLoader,
         db: &mut DB,
+        at_rest_key: &[u8],
     ) -> Result<bool> {
         let blob = legacy_loader
             .read_legacy_keystore_entry(uid, alias, |ciphertext, iv, tag, _salt, _key_size| {
@@ -525,7 +999,7 @@ impl LegacyKeystore {
             })
             .context("In import_one_legacy_entry: Trying to read legacy keystore entry.")?;
         if let Some(entry) = blob {
-            db.put(uid, alias, &entry)
+            db.put(at_rest_key, uid, alias, &entry)
                 .context("In import_one_legacy_entry: Trying to insert entry into DB.")?;
             legacy_loader
                 .remove_legacy_keystore_entry(uid, alias)
@@ -560,6 +1034,25 @@ impl ILegacyKeystore for LegacyKeystoreService {
         let _wp = wd::watch("ILegacyKeystore::list");
         self.legacy_keystore.list(prefix, uid).map_err(into_logged_binder)
     }
+    fn listNamespaces(&self) -> BinderResult<Vec<i32>> {
+        let _wp = wd::watch("ILegacyKeystore::listNamespaces");
+        self.legacy_keystore.list_namespaces().map_err(into_logged_binder)
+    }
+    fn removeByPrefix(&self, prefix: &str, uid: i32) -> BinderResult<i32> {
+        let _wp = wd::watch("ILegacyKeystore::removeByPrefix");
+        self.legacy_keystore.remove_by_prefix(prefix, uid).map_err(into_logged_binder)
+    }
+    fn removeNamespace(&self, uid: i32) -> BinderResult<i32> {
+        let _wp = wd::watch("ILegacyKeystore::removeNamespace");
+        self.legacy_keystore.remove_namespace(uid).map_err(into_logged_binder)
+    }
+    fn migrateToKeystore(
+        &self,
+        requests: &[MigrationRequest],
+    ) -> BinderResult<Vec<MigrationResult>> {
+        let _wp = wd::watch("ILegacyKeystore::migrateToKeystore");
+        self.legacy_keystore.migrate_to_keystore(requests).map_err(into_logged_binder)
+    }
 }
 
 #[cfg(test)]
@@ -576,6 +1069,18 @@ mod db_test {
     static TEST_BLOB2: &[u8] = &[2, 2, 3, 4, 5, 6, 7, 8, 9, 0];
     static TEST_BLOB3: &[u8] = &[3, 2, 3, 4, 5, 6, 7, 8, 9, 0];
     static TEST_BLOB4: &[u8] = &[3, 2, 3, 4, 5, 6, 7, 8, 9, 0];
+    static TEST_AT_REST_KEY: &[u8] = &[0x5a; 32];
+    static OTHER_AT_REST_KEY: &[u8] = &[0xa5; 32];
+
+    fn raw_entry(db: &DB, owner: u32, alias: &str) -> (Vec<u8>, i64) {
+        db.conn
+            .query_row(
+                "SELECT profile, envelope_version FROM profiles WHERE owner = ? AND alias = ?;",
+                params![owner, alias],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .expect("Failed to read raw entry.")
+    }
 
     #[test]
     fn test_entry_db() {
@@ -584,9 +1089,9 @@ mod db_test {
             .expect("Failed to open database.");
 
         // Insert three entries for owner 2.
-        db.put(2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
-        db.put(2, "test2", TEST_BLOB2).expect("Failed to insert test2.");
-        db.put(2, "test3", TEST_BLOB3).expect("Failed to insert test3.");
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
+        db.put(TEST_AT_REST_KEY, 2, "test2", TEST_BLOB2).expect("Failed to insert test2.");
+        db.put(TEST_AT_REST_KEY, 2, "test3", TEST_BLOB3).expect("Failed to insert test3.");
 
         // Check list returns all inserted aliases.
         assert_eq!(
@@ -598,13 +1103,22 @@ mod db_test {
         assert_eq!(Vec::<String>::new(), db.list(1).expect("Failed to list entries."));
 
         // Check the content of the three entries.
-        assert_eq!(Some(TEST_BLOB1), db.get(2, "test1").expect("Failed to get entry.").as_deref());
-        assert_eq!(Some(TEST_BLOB2), db.get(2, "test2").expect("Failed to get entry.").as_deref());
-        assert_eq!(Some(TEST_BLOB3), db.get(2, "test3").expect("Failed to get entry.").as_deref());
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+        assert_eq!(
+            Some(TEST_BLOB2),
+            db.get(TEST_AT_REST_KEY, 2, "test2").expect("Failed to get entry.").as_deref()
+        );
+        assert_eq!(
+            Some(TEST_BLOB3),
+            db.get(TEST_AT_REST_KEY, 2, "test3").expect("Failed to get entry.").as_deref()
+        );
 
         // Remove test2 and check and check that it is no longer retrievable.
         assert!(db.remove(2, "test2").expect("Failed to remove entry."));
-        assert!(db.get(2, "test2").expect("Failed to get entry.").is_none());
+        assert!(db.get(TEST_AT_REST_KEY, 2, "test2").expect("Failed to get entry.").is_none());
 
         // test2 should now no longer be in the list.
         assert_eq!(
@@ -614,10 +1128,168 @@ mod db_test {
 
         // Put on existing alias replaces it.
         // Verify test1 is TEST_BLOB1.
-        assert_eq!(Some(TEST_BLOB1), db.get(2, "test1").expect("Failed to get entry.").as_deref());
-        db.put(2, "test1", TEST_BLOB4).expect("Failed to replace test1.");
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB4).expect("Failed to replace test1.");
         // Verify test1 is TEST_BLOB4.
-        assert_eq!(Some(TEST_BLOB4), db.get(2, "test1").expect("Failed to get entry.").as_deref());
+        assert_eq!(
+            Some(TEST_BLOB4),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+    }
+
+    #[test]
+    fn test_entry_encrypted_at_rest() {
+        let test_dir = TempDir::new("encrypted_at_rest_test_").expect("Failed to create temp dir.");
+        let mut db = DB::new(&test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME))
+            .expect("Failed to open database.");
+
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
+        let (profile, envelope_version) = raw_entry(&db, 2, "test1");
+        assert_eq!(ENVELOPE_AES_256_GCM_BOUND, envelope_version);
+        assert_eq!(GCM_IV_LENGTH + TAG_LENGTH + TEST_BLOB1.len(), profile.len());
+        assert!(!profile.windows(TEST_BLOB1.len()).any(|w| w == TEST_BLOB1));
+
+        // The entry cannot be read with a different key.
+        assert!(db.get(OTHER_AT_REST_KEY, 2, "test1").is_err());
+    }
+
+    #[test]
+    fn test_swapped_entries_do_not_open() {
+        let test_dir = TempDir::new("swapped_entries_test_").expect("Failed to create temp dir.");
+        let mut db = DB::new(&test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME))
+            .expect("Failed to open database.");
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
+        db.put(TEST_AT_REST_KEY, 2, "test2", TEST_BLOB2).expect("Failed to insert test2.");
+        db.put(TEST_AT_REST_KEY, 3, "test1", TEST_BLOB3).expect("Failed to insert test1.");
+
+        // Each envelope is bound to its owner and alias, so swapping the stored envelopes of two
+        // rows makes both fail to open, whether the rows differ in alias or in owner.
+        let swap = |db: &DB, a: (u32, &str), b: (u32, &str)| {
+            let (profile_a, _) = raw_entry(db, a.0, a.1);
+            let (profile_b, _) = raw_entry(db, b.0, b.1);
+            for (owner, alias, profile) in [(a.0, a.1, profile_b), (b.0, b.1, profile_a)] {
+                db.conn
+                    .execute(
+                        "UPDATE profiles SET profile = ? WHERE owner = ? AND alias = ?;",
+                        params![profile, owner, alias],
+                    )
+                    .expect("Failed to swap entries.");
+            }
+        };
+        swap(&db, (2, "test1"), (2, "test2"));
+        assert!(db.get(TEST_AT_REST_KEY, 2, "test1").is_err());
+        assert!(db.get(TEST_AT_REST_KEY, 2, "test2").is_err());
+        assert_eq!(
+            Some(TEST_BLOB3),
+            db.get(TEST_AT_REST_KEY, 3, "test1").expect("Failed to get entry.").as_deref()
+        );
+        swap(&db, (2, "test2"), (3, "test1"));
+        assert!(db.get(TEST_AT_REST_KEY, 2, "test2").is_err());
+        assert!(db.get(TEST_AT_REST_KEY, 3, "test1").is_err());
+    }
+
+    #[test]
+    fn test_unbound_entry_is_resealed() {
+        let test_dir = TempDir::new("unbound_entry_test_").expect("Failed to create temp dir.");
+        let mut db = DB::new(&test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME))
+            .expect("Failed to open database.");
+
+        // An entry as it was sealed before its owner and alias were authenticated.
+        let profile = seal(TEST_AT_REST_KEY, &[], TEST_BLOB1).expect("Failed to seal entry.");
+        db.conn
+            .execute(
+                "INSERT INTO profiles (owner, alias, profile, envelope_version)
+                     values (?, ?, ?, ?)",
+                params![2, "test1", profile, ENVELOPE_AES_256_GCM],
+            )
+            .expect("Failed to insert unbound entry.");
+
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+        assert_eq!(ENVELOPE_AES_256_GCM_BOUND, raw_entry(&db, 2, "test1").1);
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+    }
+
+    #[test]
+    fn test_lost_at_rest_key() {
+        let test_dir = TempDir::new("lost_at_rest_key_test_").expect("Failed to create temp dir.");
+        let mut db = DB::new(&test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME))
+            .expect("Failed to open database.");
+
+        // The first key is recorded and checks out from then on.
+        assert_eq!(0, db.check_at_rest_key(TEST_AT_REST_KEY).expect("Failed to check key."));
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
+        db.put(TEST_AT_REST_KEY, 3, "test2", TEST_BLOB2).expect("Failed to insert test2.");
+        db.conn
+            .execute(
+                "INSERT INTO profiles (owner, alias, profile, envelope_version)
+                     values (?, ?, ?, ?)",
+                params![2, "plain", TEST_BLOB3, ENVELOPE_PLAINTEXT],
+            )
+            .expect("Failed to insert plaintext entry.");
+        assert_eq!(0, db.check_at_rest_key(TEST_AT_REST_KEY).expect("Failed to check key."));
+
+        // A different key means that the key was lost. The entries that it cannot open are
+        // deleted, and the plaintext entry is kept.
+        assert_eq!(2, db.check_at_rest_key(OTHER_AT_REST_KEY).expect("Failed to check key."));
+        assert_eq!(vec!["plain".to_string()], db.list(2).expect("Failed to list entries."));
+        assert_eq!(Vec::<String>::new(), db.list(3).expect("Failed to list entries."));
+        assert_eq!(
+            Some(TEST_BLOB3),
+            db.get(OTHER_AT_REST_KEY, 2, "plain").expect("Failed to get entry.").as_deref()
+        );
+
+        // The new key is recorded instead.
+        assert_eq!(0, db.check_at_rest_key(OTHER_AT_REST_KEY).expect("Failed to check key."));
+        db.put(OTHER_AT_REST_KEY, 2, "test1", TEST_BLOB4).expect("Failed to insert test1.");
+        assert_eq!(
+            Some(TEST_BLOB4),
+            db.get(OTHER_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+    }
+
+    #[test]
+    fn test_plaintext_entry_migration() {
+        let test_dir =
+            TempDir::new("plaintext_migration_test_").expect("Failed to create temp dir.");
+        let db_path = test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME).to_owned();
+
+        // Create a database as it was before at-rest encryption was introduced.
+        let conn = Connection::open(&db_path).expect("Failed to open connection.");
+        conn.execute(
+            "CREATE TABLE profiles (owner INTEGER, alias BLOB, profile BLOB, UNIQUE(owner, alias));",
+            [],
+        )
+        .expect("Failed to create table.");
+        conn.execute(
+            "INSERT INTO profiles (owner, alias, profile) values (?, ?, ?)",
+            params![2, "test1", TEST_BLOB1],
+        )
+        .expect("Failed to insert plaintext entry.");
+        drop(conn);
+
+        let mut db = DB::new(&db_path).expect("Failed to open database.");
+        let envelope_version = |db: &DB| -> i64 { raw_entry(db, 2, "test1").1 };
+        assert_eq!(ENVELOPE_PLAINTEXT, envelope_version(&db));
+
+        // Reading the plaintext entry returns it unchanged and encrypts it.
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
+        assert_eq!(ENVELOPE_AES_256_GCM_BOUND, envelope_version(&db));
+        assert_eq!(
+            Some(TEST_BLOB1),
+            db.get(TEST_AT_REST_KEY, 2, "test1").expect("Failed to get entry.").as_deref()
+        );
     }
 
     #[test]
@@ -627,9 +1299,9 @@ mod db_test {
             .expect("Failed to open database.");
 
         // Insert three entries for owner 2.
-        db.put(2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
-        db.put(2, "test2", TEST_BLOB2).expect("Failed to insert test2.");
-        db.put(3, "test3", TEST_BLOB3).expect("Failed to insert test3.");
+        db.put(TEST_AT_REST_KEY, 2, "test1", TEST_BLOB1).expect("Failed to insert test1.");
+        db.put(TEST_AT_REST_KEY, 2, "test2", TEST_BLOB2).expect("Failed to insert test2.");
+        db.put(TEST_AT_REST_KEY, 3, "test3", TEST_BLOB3).expect("Failed to insert test3.");
 
         db.remove_uid(2).expect("Failed to remove uid 2");
 
@@ -638,6 +1310,31 @@ mod db_test {
         assert_eq!(vec!["test3".to_string(),], db.list(3).expect("Failed to list entries."));
     }
 
+    #[test]
+    fn test_bulk_operations() {
+        let test_dir = TempDir::new("test_bulk_operations_").expect("Failed to create temp dir.");
+        let mut db = DB::new(&test_dir.build().push(LegacyKeystore::LEGACY_KEYSTORE_FILE_NAME))
+            .expect("Failed to open database.");
+
+        db.put(TEST_AT_REST_KEY, 2, "vpn_1", TEST_BLOB1).expect("Failed to insert vpn_1.");
+        db.put(TEST_AT_REST_KEY, 2, "vpn_2", TEST_BLOB2).expect("Failed to insert vpn_2.");
+        db.put(TEST_AT_REST_KEY, 2, "wifi_1", TEST_BLOB3).expect("Failed to insert wifi_1.");
+        db.put(TEST_AT_REST_KEY, 3, "vpn_1", TEST_BLOB4).expect("Failed to insert vpn_1.");
+        db.put(TEST_AT_REST_KEY, 5, "vpn_1", TEST_BLOB4).expect("Failed to insert vpn_1.");
+
+        assert_eq!(vec![2, 3, 5], db.list_owners().expect("Failed to list owners."));
+
+        // Only the entries of owner 2 with the given prefix are removed.
+        assert_eq!(2, db.remove_prefix(2, "vpn_").expect("Failed to remove by prefix."));
+        assert_eq!(vec!["wifi_1".to_string()], db.list(2).expect("Failed to list entries."));
+        assert_eq!(vec!["vpn_1".to_string()], db.list(3).expect("Failed to list entries."));
+        assert_eq!(0, db.remove_prefix(2, "vpn_").expect("Failed to remove by prefix."));
+
+        assert_eq!(1, db.remove_uid(3).expect("Failed to remove uid 3."));
+        assert_eq!(0, db.remove_uid(3).expect("Failed to remove uid 3."));
+        assert_eq!(vec![2, 5], db.list_owners().expect("Failed to list owners."));
+    }
+
     #[test]
     fn test_delete_user() {
         let test_dir = TempDir::new("test_delete_user_").expect("Failed to create temp dir.");
@@ -645,11 +1342,11 @@ mod db_test {
             .expect("Failed to open database.");
 
         // Insert three entries for owner 2.
-        db.put(2 + 2 * rustutils::users::AID_USER_OFFSET, "test1", TEST_BLOB1)
+        db.put(TEST_AT_REST_KEY, 2 + 2 * rustutils::users::AID_USER_OFFSET, "test1", TEST_BLOB1)
             .expect("Failed to insert test1.");
-        db.put(4 + 2 * rustutils::users::AID_USER_OFFSET, "test2", TEST_BLOB2)
+        db.put(TEST_AT_REST_KEY, 4 + 2 * rustutils::users::AID_USER_OFFSET, "test2", TEST_BLOB2)
             .expect("Failed to insert test2.");
-        db.put(3, "test3", TEST_BLOB3).expect("Failed to insert test3.");
+        db.put(TEST_AT_REST_KEY, 3, "test3", TEST_BLOB3).expect("Failed to insert test3.");
 
         db.remove_user(2).expect("Failed to remove user 2");
 
@@ -689,7 +1386,7 @@ mod db_test {
                 break;
             }
             let alias = format!("test_alias_{}", count);
-            db.put(1, &alias, TEST_BLOB1).expect("Failed to add entry (1).");
+            db.put(TEST_AT_REST_KEY, 1, &alias, TEST_BLOB1).expect("Failed to add entry (1).");
         }
 
         // Insert more keys from a different thread and into a different namespace.
@@ -702,7 +1399,7 @@ mod db_test {
                     return;
                 }
                 let alias = format!("test_alias_{}", count);
-                db.put(2, &alias, TEST_BLOB2).expect("Failed to add entry (2).");
+                db.put(TEST_AT_REST_KEY, 2, &alias, TEST_BLOB2).expect("Failed to add entry (2).");
             }
 
             // Then delete them again.
@@ -739,7 +1436,8 @@ mod db_test {
                 }
                 let mut db = DB::new(&db_path3).expect("Failed to open database.");
 
-                db.put(3, TEST_ALIAS, TEST_BLOB3).expect("Failed to add entry (3).");
+                db.put(TEST_AT_REST_KEY, 3, TEST_ALIAS, TEST_BLOB3)
+                    .expect("Failed to add entry (3).");
 
                 db.remove(3, TEST_ALIAS).expect("Remove failed (3).");
             }
@@ -755,7 +1453,7 @@ mod db_test {
                 let mut db = DB::new(&db_path).expect("Failed to open database.");
 
                 // This may return Some or None but it must not fail.
-                db.get(3, TEST_ALIAS).expect("Failed to get entry (4).");
+                db.get(TEST_AT_REST_KEY, 3, TEST_ALIAS).expect("Failed to get entry (4).");
             }
         });
 
@@ -0,0 +1,308 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Moves credentials that the Wi-Fi and VPN stacks stored in legacy keystore into Keystore 2.0
+//! key entries. A payload may be PKCS#12, PEM with any number of certificates and at most one
+//! private key, or a single DER encoded certificate. The private key is imported through the
+//! Keystore 2.0 service with the identity of the caller, so the usual permission checks apply,
+//! and the certificates are stored with the new key, leaf first. Payloads without a private key
+//! become certificate-only entries.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, Digest::Digest, EcCurve::EcCurve, KeyParameter::KeyParameter,
+    KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, PaddingMode::PaddingMode,
+    Tag::Tag,
+};
+use android_security_legacykeystore::aidl::android::security::legacykeystore::{
+    MigrationResult::MigrationResult, MigrationStatus::MigrationStatus,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    IKeystoreSecurityLevel::IKeystoreSecurityLevel, IKeystoreService::IKeystoreService,
+    KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use anyhow::{anyhow, Context, Result};
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::x509::X509;
+
+/// A failed migration step and the reason for the failure.
+pub(crate) type Failure = (MigrationStatus, anyhow::Error);
+
+/// The digests that migrated keys may be used with. Legacy keys were not restricted.
+const DIGESTS: &[Digest] = &[
+    Digest::NONE,
+    Digest::MD5,
+    Digest::SHA1,
+    Digest::SHA_2_224,
+    Digest::SHA_2_256,
+    Digest::SHA_2_384,
+    Digest::SHA_2_512,
+];
+
+/// The paddings that migrated RSA keys may be used with.
+const RSA_PADDINGS: &[PaddingMode] = &[
+    PaddingMode::NONE,
+    PaddingMode::RSA_PKCS1_1_5_SIGN,
+    PaddingMode::RSA_PSS,
+    PaddingMode::RSA_PKCS1_1_5_ENCRYPT,
+    PaddingMode::RSA_OAEP,
+];
+
+/// The contents of a legacy payload.
+pub(crate) struct Credential {
+    /// The private key, if any.
+    pub key: Option<PKey<Private>>,
+    /// The certificates. If there is a private key, the certificate of the key comes first.
+    pub certs: Vec<X509>,
+}
+
+impl Credential {
+    /// Moves the certificate that matches the private key to the front.
+    fn put_leaf_first(&mut self) {
+        let Some(key) = &self.key else { return };
+        if let Some(i) = self
+            .certs
+            .iter()
+            .position(|c| c.public_key().map(|pk| pk.public_eq(key)).unwrap_or(false))
+        {
+            let leaf = self.certs.remove(i);
+            self.certs.insert(0, leaf);
+        }
+    }
+}
+
+/// Parses a legacy payload. `password` is only used for PKCS#12 payloads and encrypted PEM
+/// private keys.
+pub(crate) fn parse_payload(payload: &[u8], password: Option<&str>) -> Result<Credential> {
+    let mut credential = if payload.trim_ascii_start().starts_with(b"-----BEGIN") {
+        let key = if contains(payload, b"PRIVATE KEY-----") {
+            let key = match password {
+                Some(password) => {
+                    PKey::private_key_from_pem_passphrase(payload, password.as_bytes())
+                }
+                None => PKey::private_key_from_pem(payload),
+            };
+            Some(key.context("In parse_payload: Failed to parse PEM private key.")?)
+        } else {
+            None
+        };
+        let certs = X509::stack_from_pem(payload)
+            .context("In parse_payload: Failed to parse PEM certificates.")?;
+        Credential { key, certs }
+    } else if let Ok(cert) = X509::from_der(payload) {
+        Credential { key: None, certs: vec![cert] }
+    } else {
+        let parsed = Pkcs12::from_der(payload)
+            .context("In parse_payload: Payload is neither PEM, DER, nor PKCS#12.")?
+            .parse2(password.unwrap_or(""))
+            .context("In parse_payload: Failed to decrypt PKCS#12 payload.")?;
+        let mut certs: Vec<X509> = parsed.cert.into_iter().collect();
+        certs.extend(parsed.ca.into_iter().flatten());
+        Credential { key: parsed.pkey, certs }
+    };
+    if credential.key.is_none() && credential.certs.is_empty() {
+        return Err(anyhow!("In parse_payload: Payload holds neither a key nor a certificate."));
+    }
+    credential.put_leaf_first();
+    Ok(credential)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn param(tag: Tag, value: KeyParameterValue) -> KeyParameter {
+    KeyParameter { tag, value }
+}
+
+/// Returns the parameters for importing `key`.
+fn import_params(key: &PKey<Private>) -> Result<Vec<KeyParameter>> {
+    let mut params = vec![
+        param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::SIGN)),
+        param(Tag::NO_AUTH_REQUIRED, KeyParameterValue::BoolValue(true)),
+    ];
+    params.extend(DIGESTS.iter().map(|d| param(Tag::DIGEST, KeyParameterValue::Digest(*d))));
+    match key.id() {
+        Id::RSA => {
+            let rsa = key.rsa().context("In import_params: Not an RSA key.")?;
+            let exponent = rsa
+                .e()
+                .to_vec()
+                .iter()
+                .try_fold(0i64, |e, b| e.checked_mul(256).map(|e| e | *b as i64))
+                .ok_or_else(|| anyhow!("In import_params: RSA exponent too large."))?;
+            params.extend([
+                param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::RSA)),
+                param(Tag::KEY_SIZE, KeyParameterValue::Integer(rsa.size() as i32 * 8)),
+                param(Tag::RSA_PUBLIC_EXPONENT, KeyParameterValue::LongInteger(exponent)),
+                param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::DECRYPT)),
+            ]);
+            params.extend(
+                RSA_PADDINGS
+                    .iter()
+                    .map(|p| param(Tag::PADDING, KeyParameterValue::PaddingMode(*p))),
+            );
+        }
+        Id::EC => {
+            let ec_key = key.ec_key().context("In import_params: Not an EC key.")?;
+            let curve = match ec_key.group().curve_name() {
+                Some(Nid::SECP224R1) => EcCurve::P_224,
+                Some(Nid::X9_62_PRIME256V1) => EcCurve::P_256,
+                Some(Nid::SECP384R1) => EcCurve::P_384,
+                Some(Nid::SECP521R1) => EcCurve::P_521,
+                curve => return Err(anyhow!("In import_params: Unsupported curve {curve:?}.")),
+            };
+            params.extend([
+                param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC)),
+                param(Tag::EC_CURVE, KeyParameterValue::EcCurve(curve)),
+            ]);
+        }
+        id => return Err(anyhow!("In import_params: Unsupported key type {id:?}.")),
+    }
+    Ok(params)
+}
+
+/// Stores `credential` in `destination` and records the progress in `result`. An existing entry
+/// in `destination` is replaced.
+pub(crate) fn store_credential(
+    keystore: &dyn IKeystoreService,
+    sec_level: &dyn IKeystoreSecurityLevel,
+    destination: &KeyDescriptor,
+    credential: &Credential,
+    result: &mut MigrationResult,
+) -> std::result::Result<(), Failure> {
+    let certs = credential
+        .certs
+        .iter()
+        .map(|c| c.to_der())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| {
+            (MigrationStatus::PARSE_FAILED, anyhow!("Failed to encode certificate: {e}"))
+        })?;
+
+    let Some(key) = &credential.key else {
+        // A certificate-only entry can only be created if there is no entry yet. Otherwise,
+        // updateSubcomponent would just replace the certificates of the existing entry.
+        match keystore.deleteKey(destination) {
+            Err(e) if e.service_specific_error() != ResponseCode::KEY_NOT_FOUND.0 => {
+                return Err((
+                    MigrationStatus::STORE_CERTIFICATES_FAILED,
+                    anyhow!("Failed to replace existing entry: {e:?}"),
+                ));
+            }
+            _ => {}
+        }
+        keystore.updateSubcomponent(destination, None, Some(certs.concat().as_slice())).map_err(
+            |e| {
+                (
+                    MigrationStatus::STORE_CERTIFICATES_FAILED,
+                    anyhow!("Failed to store certificates: {e:?}"),
+                )
+            },
+        )?;
+        result.certificateCount = certs.len() as i32;
+        return Ok(());
+    };
+
+    let params = import_params(key).map_err(|e| (MigrationStatus::PARSE_FAILED, e))?;
+    let key_data = key.private_key_to_pkcs8().map_err(|e| {
+        (MigrationStatus::PARSE_FAILED, anyhow!("Failed to encode private key: {e}"))
+    })?;
+    let metadata = sec_level
+        .importKey(destination, None, &params, 0, &key_data)
+        .map_err(|e| (MigrationStatus::IMPORT_FAILED, anyhow!("Failed to import key: {e:?}")))?;
+    result.keyImported = true;
+
+    if let Some((leaf, chain)) = certs.split_first() {
+        let chain = chain.concat();
+        keystore
+            .updateSubcomponent(
+                &metadata.key,
+                Some(leaf),
+                (!chain.is_empty()).then_some(chain.as_slice()),
+            )
+            .map_err(|e| {
+                (
+                    MigrationStatus::STORE_CERTIFICATES_FAILED,
+                    anyhow!("Failed to store certificates: {e:?}"),
+                )
+            })?;
+        result.certificateCount = certs.len() as i32;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn self_signed_cert(key: &PKey<Private>) -> X509 {
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_parse_pem_payload() {
+        let (ca_key, key) = (ec_key(), ec_key());
+        let (ca_cert, cert) = (self_signed_cert(&ca_key), self_signed_cert(&key));
+        let payload = [
+            ca_cert.to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+            cert.to_pem().unwrap(),
+        ]
+        .concat();
+
+        let credential = parse_payload(&payload, None).unwrap();
+        assert!(credential.key.unwrap().public_eq(&key));
+        assert_eq!(credential.certs.len(), 2);
+        assert_eq!(credential.certs[0].to_der().unwrap(), cert.to_der().unwrap());
+        assert_eq!(credential.certs[1].to_der().unwrap(), ca_cert.to_der().unwrap());
+    }
+
+    #[test]
+    fn test_parse_certificate_payloads() {
+        let cert = self_signed_cert(&ec_key());
+        for payload in [cert.to_der().unwrap(), cert.to_pem().unwrap()] {
+            let credential = parse_payload(&payload, None).unwrap();
+            assert!(credential.key.is_none());
+            assert_eq!(credential.certs.len(), 1);
+        }
+        assert!(parse_payload(b"not a credential", None).is_err());
+        assert!(parse_payload(b"-----BEGIN NOTHING-----\n-----END NOTHING-----\n", None).is_err());
+    }
+
+    #[test]
+    fn test_import_params() {
+        let params = import_params(&ec_key()).unwrap();
+        assert!(params.contains(&param(Tag::EC_CURVE, KeyParameterValue::EcCurve(EcCurve::P_256))));
+        assert!(
+            params.contains(&param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC)))
+        );
+        assert!(params.contains(&param(Tag::DIGEST, KeyParameterValue::Digest(Digest::NONE))));
+    }
+}
@@ -21,6 +21,7 @@ use std::{
     sync::{mpsc::Sender, Arc, Mutex},
 };
 
+use crate::calling_identity;
 use crate::error::anyhow_error_to_cstring;
 use crate::ks_err;
 use crate::utils::{compat_2_response_code, ui_opts_2_compat, watchdog as wd};
@@ -31,7 +32,7 @@ use android_security_apc::aidl::android::security::apc::{
 };
 use android_security_apc::binder::{
     BinderFeatures, ExceptionCode, Interface, Result as BinderResult, SpIBinder,
-    Status as BinderStatus, Strong, ThreadState,
+    Status as BinderStatus, Strong,
 };
 use anyhow::{Context, Result};
 use keystore2_apc_compat::ApcHal;
@@ -253,7 +254,7 @@ impl ApcManager {
         }
 
         // Perform rate limiting.
-        let uid = ThreadState::get_calling_uid();
+        let uid = calling_identity::get_calling_uid();
         match state.rate_limiting.get(&uid) {
             None => {}
             Some(rate_info) => {
@@ -0,0 +1,348 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the IKeystoreAsyncService AIDL interface, an asynchronous variant of
+//! the latency critical methods of IKeystoreService and IKeystoreSecurityLevel.
+//!
+//! The binder thread that receives a oneway transaction captures the caller's identity and
+//! queues the request on a pool of worker threads, where it is served by the synchronous
+//! implementation with the captured identity for the permission checks. This way, a request that
+//! waits for a slow KeyMint instance does not hold up the requests behind it, and it does not
+//! occupy a binder thread. Requests that are served concurrently may complete in any order. Every
+//! request completes with exactly one call of its callback.
+
+use crate::calling_identity::CallingIdentity;
+use crate::error::ResponseCode;
+use crate::utils::watchdog as wd;
+use crate::worker_pool::WorkerPool;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    KeyParameter::KeyParameter, SecurityLevel::SecurityLevel,
+};
+use android_security_keystoreasync::aidl::android::security::keystoreasync::{
+    ICreateOperationCallback::ICreateOperationCallback,
+    IGetKeyEntryCallback::IGetKeyEntryCallback,
+    IKeystoreAsyncService::{BnKeystoreAsyncService, IKeystoreAsyncService},
+};
+use android_security_keystoreasync::binder::{
+    BinderFeatures, ExceptionCode, Interface, Result as BinderResult, Status as BinderStatus,
+    Strong,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    CreateOperationResponse::CreateOperationResponse, IKeystoreService::IKeystoreService,
+    KeyDescriptor::KeyDescriptor, KeyEntryResponse::KeyEntryResponse,
+};
+use anyhow::Result;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// The maximum number of asynchronous requests that are served concurrently.
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// The time after which an idle worker thread terminates.
+const WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+static WORKERS: LazyLock<WorkerPool> =
+    LazyLock::new(|| WorkerPool::new(MAX_CONCURRENT_REQUESTS, WORKER_IDLE_TIMEOUT));
+
+/// Implementation of the IKeystoreAsyncService.
+pub struct KeystoreAsyncService {
+    service: Strong<dyn IKeystoreService>,
+}
+
+impl KeystoreAsyncService {
+    /// Create a new instance of the asynchronous Keystore service, which forwards the requests
+    /// to the given synchronous service.
+    pub fn new_native_binder(
+        service: Strong<dyn IKeystoreService>,
+    ) -> Result<Strong<dyn IKeystoreAsyncService>> {
+        Ok(BnKeystoreAsyncService::new_binder(
+            Self { service },
+            BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
+        ))
+    }
+}
+
+/// Returns the error code that the synchronous method threw as service specific error.
+/// Other binder errors are reported as `ResponseCode::SYSTEM_ERROR`.
+fn error_code(status: &BinderStatus) -> i32 {
+    match status.exception_code() {
+        ExceptionCode::SERVICE_SPECIFIC => status.service_specific_error(),
+        _ => ResponseCode::SYSTEM_ERROR.0,
+    }
+}
+
+fn log_delivery_failure(method: &str, result: BinderResult<()>) {
+    if let Err(e) = result {
+        log::error!("Failed to deliver the result of {method} to the caller: {e:?}");
+    }
+}
+
+/// Completes a getKeyEntry request with exactly one call of `callback`.
+fn deliver_key_entry(
+    result: BinderResult<KeyEntryResponse>,
+    callback: &Strong<dyn IGetKeyEntryCallback>,
+) {
+    let result = match result {
+        Ok(response) => callback.onSuccess(&response),
+        Err(status) => callback.onError(error_code(&status)),
+    };
+    log_delivery_failure("getKeyEntry", result);
+}
+
+/// Completes a createOperation request with exactly one call of `callback`. If the operation
+/// cannot be delivered, nobody can finish it, so it is aborted right away.
+fn deliver_create_operation(
+    result: BinderResult<CreateOperationResponse>,
+    callback: &Strong<dyn ICreateOperationCallback>,
+) {
+    let result = match result {
+        Ok(response) => {
+            let result = callback.onSuccess(&response);
+            if result.is_err() {
+                if let Some(operation) = &response.iOperation {
+                    if let Err(e) = operation.abort() {
+                        log::error!("Failed to abort the undelivered operation: {e:?}");
+                    }
+                }
+            }
+            result
+        }
+        Err(status) => callback.onError(error_code(&status)),
+    };
+    log_delivery_failure("createOperation", result);
+}
+
+impl Interface for KeystoreAsyncService {}
+
+impl IKeystoreAsyncService for KeystoreAsyncService {
+    fn getKeyEntry(
+        &self,
+        key: &KeyDescriptor,
+        callback: &Strong<dyn IGetKeyEntryCallback>,
+    ) -> BinderResult<()> {
+        let identity = CallingIdentity::capture();
+        let service = self.service.clone();
+        let key = key.clone();
+        let callback = callback.clone();
+        WORKERS.execute(move || {
+            let _wp = wd::watch("IKeystoreAsyncService::getKeyEntry");
+            let result = identity.run(|| service.getKeyEntry(&key));
+            deliver_key_entry(result, &callback);
+        });
+        Ok(())
+    }
+
+    fn createOperation(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        forced: bool,
+        callback: &Strong<dyn ICreateOperationCallback>,
+    ) -> BinderResult<()> {
+        let identity = CallingIdentity::capture();
+        let service = self.service.clone();
+        let key = key.clone();
+        let operation_parameters = operation_parameters.to_vec();
+        let callback = callback.clone();
+        WORKERS.execute(move || {
+            let _wp = wd::watch("IKeystoreAsyncService::createOperation");
+            let result = identity.run(|| {
+                service.getSecurityLevel(security_level).and_then(|sec_level| {
+                    sec_level.createOperation(&key, &operation_parameters, forced)
+                })
+            });
+            deliver_create_operation(result, &callback);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_security_keystoreasync::aidl::android::security::keystoreasync::{
+        ICreateOperationCallback::BnCreateOperationCallback,
+        IGetKeyEntryCallback::BnGetKeyEntryCallback,
+    };
+    use android_security_keystoreasync::binder::StatusCode;
+    use android_system_keystore2::aidl::android::system::keystore2::IKeystoreOperation::{
+        BnKeystoreOperation, IKeystoreOperation,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Records the calls of a fake callback.
+    #[derive(Default)]
+    struct Calls {
+        successes: usize,
+        errors: Vec<i32>,
+    }
+
+    struct FakeCallback {
+        calls: Arc<Mutex<Calls>>,
+        /// Whether delivering the result fails, as if the caller died.
+        fail: bool,
+    }
+
+    impl FakeCallback {
+        fn record(&self, error: Option<i32>) -> BinderResult<()> {
+            let mut calls = self.calls.lock().unwrap();
+            match error {
+                Some(code) => calls.errors.push(code),
+                None => calls.successes += 1,
+            }
+            if self.fail {
+                Err(StatusCode::DEAD_OBJECT.into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Interface for FakeCallback {}
+
+    impl IGetKeyEntryCallback for FakeCallback {
+        fn onSuccess(&self, _response: &KeyEntryResponse) -> BinderResult<()> {
+            self.record(None)
+        }
+        fn onError(&self, error_code: i32) -> BinderResult<()> {
+            self.record(Some(error_code))
+        }
+    }
+
+    impl ICreateOperationCallback for FakeCallback {
+        fn onSuccess(&self, _response: &CreateOperationResponse) -> BinderResult<()> {
+            self.record(None)
+        }
+        fn onError(&self, error_code: i32) -> BinderResult<()> {
+            self.record(Some(error_code))
+        }
+    }
+
+    fn new_create_operation_callback(
+        fail: bool,
+    ) -> (Strong<dyn ICreateOperationCallback>, Arc<Mutex<Calls>>) {
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let callback = BnCreateOperationCallback::new_binder(
+            FakeCallback { calls: calls.clone(), fail },
+            BinderFeatures::default(),
+        );
+        (callback, calls)
+    }
+
+    /// An operation that counts how often it was aborted.
+    struct FakeOperation {
+        aborted: Arc<Mutex<usize>>,
+    }
+
+    impl Interface for FakeOperation {}
+
+    impl IKeystoreOperation for FakeOperation {
+        fn updateAad(&self, _aad_input: &[u8]) -> BinderResult<()> {
+            Ok(())
+        }
+        fn update(&self, _input: &[u8]) -> BinderResult<Option<Vec<u8>>> {
+            Ok(None)
+        }
+        fn finish(
+            &self,
+            _input: Option<&[u8]>,
+            _signature: Option<&[u8]>,
+        ) -> BinderResult<Option<Vec<u8>>> {
+            Ok(None)
+        }
+        fn abort(&self) -> BinderResult<()> {
+            *self.aborted.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn new_operation_response() -> (CreateOperationResponse, Arc<Mutex<usize>>) {
+        let aborted = Arc::new(Mutex::new(0));
+        let operation = BnKeystoreOperation::new_binder(
+            FakeOperation { aborted: aborted.clone() },
+            BinderFeatures::default(),
+        );
+        let response = CreateOperationResponse {
+            iOperation: Some(operation),
+            operationChallenge: None,
+            parameters: None,
+            upgradedBlob: None,
+        };
+        (response, aborted)
+    }
+
+    #[test]
+    fn test_deliver_create_operation_success() {
+        let (callback, calls) = new_create_operation_callback(false);
+        let (response, aborted) = new_operation_response();
+
+        deliver_create_operation(Ok(response), &callback);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.successes, 1);
+        assert_eq!(calls.errors, Vec::<i32>::new());
+        assert_eq!(*aborted.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deliver_create_operation_error() {
+        let (callback, calls) = new_create_operation_callback(false);
+
+        deliver_create_operation(
+            Err(BinderStatus::new_service_specific_error(ResponseCode::KEY_NOT_FOUND.0, None)),
+            &callback,
+        );
+        deliver_create_operation(Err(StatusCode::DEAD_OBJECT.into()), &callback);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.successes, 0);
+        // Errors that are not service specific are reported as system error.
+        assert_eq!(calls.errors, vec![ResponseCode::KEY_NOT_FOUND.0, ResponseCode::SYSTEM_ERROR.0]);
+    }
+
+    #[test]
+    fn test_deliver_create_operation_delivery_failure() {
+        let (callback, calls) = new_create_operation_callback(true);
+        let (response, aborted) = new_operation_response();
+
+        deliver_create_operation(Ok(response), &callback);
+
+        // The callback was called once, and the operation that the caller did not receive was
+        // aborted.
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.successes, 1);
+        assert_eq!(calls.errors, Vec::<i32>::new());
+        assert_eq!(*aborted.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_deliver_key_entry_error() {
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let callback = BnGetKeyEntryCallback::new_binder(
+            FakeCallback { calls: calls.clone(), fail: true },
+            BinderFeatures::default(),
+        );
+
+        // A delivery failure is logged, and the callback is not retried.
+        deliver_key_entry(
+            Err(BinderStatus::new_service_specific_error(ResponseCode::PERMISSION_DENIED.0, None)),
+            &callback,
+        );
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.successes, 0);
+        assert_eq!(calls.errors, vec![ResponseCode::PERMISSION_DENIED.0]);
+    }
+}
@@ -14,21 +14,40 @@
 
 //! This module implements functions to log audit events to binary security log buffer for NIAP
 //! compliance.
+//!
+//! In addition, it implements a key use audit mode. If enabled for a set of namespaces, e.g.,
+//! the keys of a work profile, every use of a key in these namespaces is logged with caller,
+//! key id, purpose, and outcome. The most recent records are also retained in memory, subject
+//! to a limit on their number and age, and included in the dump state.
 
-use crate::globals::LOGS_HANDLER;
+use crate::globals::{DB, LOGS_HANDLER};
+use crate::operation::Outcome;
+use crate::utils::{get_current_time_in_milliseconds, uid_to_android_user};
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::KeyPurpose::KeyPurpose;
 use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, KeyDescriptor::KeyDescriptor,
 };
 use libc::uid_t;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{LazyLock, Mutex, RwLock};
 use structured_log::{structured_log, LOG_ID_SECURITY};
 
 const TAG_KEY_GENERATED: u32 = 210024;
 const TAG_KEY_IMPORTED: u32 = 210025;
 const TAG_KEY_DESTROYED: u32 = 210026;
 const TAG_KEY_INTEGRITY_VIOLATION: u32 = 210032;
+const TAG_KEY_USED: u32 = 210039;
 
 const FLAG_NAMESPACE: i64 = 0x80000000;
 
+/// Maximum number of key use records retained in memory.
+const MAX_RETAINED_KEY_USE_RECORDS: usize = 1000;
+/// Key use records older than this are discarded, in milliseconds.
+const KEY_USE_RECORD_RETENTION_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Key use audit state of keystore.
+pub static KEY_USE_AUDIT: LazyLock<KeyUseAudit> = LazyLock::new(Default::default);
+
 /// Encode key owner as either uid or namespace with a flag.
 fn key_owner(domain: Domain, nspace: i64, uid: i32) -> i32 {
     match domain {
@@ -74,3 +93,208 @@ fn log_key_event(tag: u32, key: &KeyDescriptor, calling_app: uid_t, success: boo
             structured_log!(log_id: LOG_ID_SECURITY, tag, i32::from(success), alias, owner);
     });
 }
+
+/// Selects the keys whose uses are audited.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyUseAuditConfig {
+    /// Android users whose app keys are audited.
+    pub user_ids: HashSet<u32>,
+    /// SELinux namespaces whose keys are audited.
+    pub selinux_namespaces: HashSet<i64>,
+}
+
+impl KeyUseAuditConfig {
+    fn is_empty(&self) -> bool {
+        self.user_ids.is_empty() && self.selinux_namespaces.is_empty()
+    }
+
+    fn covers(&self, domain: Domain, nspace: i64) -> bool {
+        match domain {
+            Domain::APP => u32::try_from(nspace)
+                .is_ok_and(|uid| self.user_ids.contains(&uid_to_android_user(uid))),
+            Domain::SELINUX => self.selinux_namespaces.contains(&nspace),
+            _ => false,
+        }
+    }
+}
+
+/// A key whose uses are audited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditedKey {
+    key_id: i64,
+    owner: i32,
+}
+
+/// A single audited use of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyUseRecord {
+    /// Boot time of the use in milliseconds.
+    pub time_ms: i64,
+    /// Uid of the caller.
+    pub caller_uid: uid_t,
+    /// Id of the key.
+    pub key_id: i64,
+    /// Purpose of the operation.
+    pub purpose: KeyPurpose,
+    /// Outcome of the operation.
+    pub outcome: Outcome,
+}
+
+/// Holds the key use audit configuration and the retained key use records.
+#[derive(Debug, Default)]
+pub struct KeyUseAudit {
+    config: RwLock<KeyUseAuditConfig>,
+    records: Mutex<VecDeque<KeyUseRecord>>,
+}
+
+impl KeyUseAudit {
+    /// Replaces the key use audit configuration. An empty configuration disables key use
+    /// auditing. Retained records are kept until they expire.
+    pub fn set_config(&self, config: KeyUseAuditConfig) {
+        log::info!(
+            "Auditing key uses of users {:?} and SELinux namespaces {:?}.",
+            config.user_ids,
+            config.selinux_namespaces
+        );
+        *self.config.write().unwrap() = config;
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.config.read().unwrap().is_empty()
+    }
+
+    fn audited_key(&self, key_id: i64, domain: Domain, nspace: i64) -> Option<AuditedKey> {
+        self.config
+            .read()
+            .unwrap()
+            .covers(domain, nspace)
+            .then(|| AuditedKey { key_id, owner: key_owner(domain, nspace, nspace as i32) })
+    }
+
+    fn retain(&self, record: KeyUseRecord) {
+        let mut records = self.records.lock().unwrap();
+        while records
+            .front()
+            .is_some_and(|r| record.time_ms - r.time_ms >= KEY_USE_RECORD_RETENTION_MS)
+        {
+            records.pop_front();
+        }
+        if records.len() >= MAX_RETAINED_KEY_USE_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Returns the retained key use records, oldest first.
+    pub fn records(&self) -> Vec<KeyUseRecord> {
+        let now = get_current_time_in_milliseconds();
+        let records = self.records.lock().unwrap();
+        records.iter().filter(|r| now - r.time_ms < KEY_USE_RECORD_RETENTION_MS).copied().collect()
+    }
+}
+
+/// Returns the key with the given id if its uses are audited. The owner of the key is only
+/// looked up if key use auditing is enabled, so that this is cheap otherwise.
+pub fn audited_key(key_id: i64) -> Option<AuditedKey> {
+    if !KEY_USE_AUDIT.is_enabled() {
+        return None;
+    }
+    match DB.with(|db| db.borrow_mut().load_key_descriptor(key_id)) {
+        Ok(Some(key)) => KEY_USE_AUDIT.audited_key(key_id, key.domain, key.nspace),
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("Failed to load key descriptor for audit log: {e:?}");
+            None
+        }
+    }
+}
+
+/// Logs the use of an audited key to NIAP audit log and retains a record of it.
+pub fn log_key_use(key: &AuditedKey, caller_uid: uid_t, purpose: KeyPurpose, outcome: Outcome) {
+    KEY_USE_AUDIT.retain(KeyUseRecord {
+        time_ms: get_current_time_in_milliseconds(),
+        caller_uid,
+        key_id: key.key_id,
+        purpose,
+        outcome,
+    });
+    let AuditedKey { key_id, owner } = *key;
+    let success = i32::from(outcome == Outcome::Success);
+    LOGS_HANDLER.queue_lo(move |_| {
+        let _result = structured_log!(
+            log_id: LOG_ID_SECURITY,
+            TAG_KEY_USED,
+            success,
+            key_id,
+            purpose.0,
+            caller_uid as i32,
+            owner
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::AID_USER_OFFSET;
+
+    const WORK_PROFILE_APP_UID: u32 = 10 * AID_USER_OFFSET + 10100;
+
+    fn record(time_ms: i64, key_id: i64) -> KeyUseRecord {
+        KeyUseRecord {
+            time_ms,
+            caller_uid: WORK_PROFILE_APP_UID,
+            key_id,
+            purpose: KeyPurpose::SIGN,
+            outcome: Outcome::Success,
+        }
+    }
+
+    #[test]
+    fn test_config_covers() {
+        let config = KeyUseAuditConfig {
+            user_ids: HashSet::from([10]),
+            selinux_namespaces: HashSet::from([102]),
+        };
+        assert!(config.covers(Domain::APP, WORK_PROFILE_APP_UID.into()));
+        assert!(!config.covers(Domain::APP, 10100));
+        assert!(config.covers(Domain::SELINUX, 102));
+        assert!(!config.covers(Domain::SELINUX, 101));
+        assert!(!config.covers(Domain::KEY_ID, 102));
+        assert!(!config.covers(Domain::APP, -1));
+    }
+
+    #[test]
+    fn test_audited_key() {
+        let audit = KeyUseAudit::default();
+        assert!(!audit.is_enabled());
+        assert_eq!(audit.audited_key(1, Domain::APP, WORK_PROFILE_APP_UID.into()), None);
+
+        audit.set_config(KeyUseAuditConfig { user_ids: HashSet::from([10]), ..Default::default() });
+        assert!(audit.is_enabled());
+        assert_eq!(
+            audit.audited_key(1, Domain::APP, WORK_PROFILE_APP_UID.into()),
+            Some(AuditedKey { key_id: 1, owner: WORK_PROFILE_APP_UID as i32 })
+        );
+        assert_eq!(audit.audited_key(2, Domain::APP, 10100), None);
+
+        audit.set_config(KeyUseAuditConfig::default());
+        assert!(!audit.is_enabled());
+    }
+
+    #[test]
+    fn test_record_retention() {
+        let audit = KeyUseAudit::default();
+        for key_id in 0..(MAX_RETAINED_KEY_USE_RECORDS as i64 + 10) {
+            audit.retain(record(0, key_id));
+        }
+        let records = audit.records.lock().unwrap().clone();
+        assert_eq!(records.len(), MAX_RETAINED_KEY_USE_RECORDS);
+        assert_eq!(records.front().map(|r| r.key_id), Some(10));
+
+        // A new record pushes out all records that have expired.
+        audit.retain(record(KEY_USE_RECORD_RETENTION_MS, 0));
+        let records = audit.records.lock().unwrap().clone();
+        assert_eq!(records, VecDeque::from([record(KEY_USE_RECORD_RETENTION_MS, 0)]));
+    }
+}
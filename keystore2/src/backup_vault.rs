@@ -0,0 +1,341 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module restores keys from cloud key vault snapshots. The restore flow recovers the
+//! recovery key of the vault into keystore and passes the wrapped keys of the snapshot to
+//! IKeystoreMaintenance::importFromBackupVault. Each wrapped key is decrypted by KeyMint with the
+//! recovery key, so that the key material never leaves the KeyMint instance and keystore, and
+//! imported into the same KeyMint instance.
+//!
+//! KeyMint reports the origin of every restored key as KeyOrigin::IMPORTED. The origin the key
+//! had on the device it was backed up from is recorded in the key metadata entry
+//! `KeyMetaEntry::RestoredOrigin`.
+
+use crate::audit_log::log_key_imported;
+use crate::calling_identity;
+use crate::database::{
+    BlobInfo, BlobMetaEntry, CertificateInfo, DateTime, KeyEntryLoadBits, KeyIdGuard, KeyMetaData,
+    KeyMetaEntry, KeyType, Uuid,
+};
+use crate::error::{map_km_error, Error};
+use crate::globals::{get_keymint_dev_by_uuid, DB, KEY_ENTRY_CACHE, LEGACY_IMPORTER, SUPER_KEY};
+use crate::key_parameter::KeyParameter as KsKeyParam;
+use crate::key_parameter::KeyParameterValue as KsKeyParamValue;
+use crate::ks_err;
+use crate::namespace_quotas::check_namespace_quota;
+use crate::permission::KeyPerm;
+use crate::security_level::KeystoreSecurityLevel;
+use crate::storage_tier::storage_tier;
+use crate::super_key::KeyBlob;
+use crate::utils::{
+    check_key_permission, key_characteristics_to_internal, key_parameters_to_authorizations,
+    uid_to_android_user, upgrade_keyblob_if_required_with, watchdog as wd,
+};
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    BlockMode::BlockMode, IKeyMintDevice::IKeyMintDevice, KeyCreationResult::KeyCreationResult,
+    KeyMintHardwareInfo::KeyMintHardwareInfo, KeyParameter::KeyParameter, KeyPurpose::KeyPurpose,
+    PaddingMode::PaddingMode, SecurityLevel::SecurityLevel,
+};
+use android_hardware_security_keymint::binder::Strong;
+use android_security_maintenance::aidl::android::security::maintenance::BackupVaultEntry::BackupVaultEntry;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, KeyMetadata::KeyMetadata,
+    ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use keystore2_crypto::ZVec;
+
+/// The length of the nonce that precedes the ciphertext of wrapped key material.
+const NONCE_LEN: usize = 12;
+
+/// The length of the GCM tag that follows the ciphertext of wrapped key material.
+const TAG_LEN: usize = 16;
+
+/// Splits wrapped key material into the nonce and the ciphertext followed by the tag, which is
+/// the input KeyMint expects for AES-GCM decryption.
+fn split_wrapped_key(wrapped: &[u8]) -> Result<(&[u8], &[u8])> {
+    if wrapped.len() < NONCE_LEN + TAG_LEN {
+        return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Wrapped key material of {} bytes is too short.", wrapped.len()));
+    }
+    Ok(wrapped.split_at(NONCE_LEN))
+}
+
+/// The recovery key of a vault and the KeyMint instance that owns it.
+struct RecoveryKey<'a> {
+    km_dev: Strong<dyn IKeyMintDevice>,
+    hw_info: KeyMintHardwareInfo,
+    km_uuid: Uuid,
+    /// Held until the key blob has been upgraded, so that the upgraded blob can be stored.
+    key_id_guard: Option<KeyIdGuard>,
+    km_blob: KeyBlob<'a>,
+    upgraded_blob: Option<Vec<u8>>,
+}
+
+impl RecoveryKey<'_> {
+    /// Decrypts `wrapped` key material with the recovery key.
+    fn unwrap(&mut self, wrapped: &[u8]) -> Result<ZVec> {
+        let (nonce, ciphertext) = split_wrapped_key(wrapped).context(ks_err!())?;
+        let params: Vec<KeyParameter> = vec![
+            KsKeyParamValue::BlockMode(BlockMode::GCM),
+            KsKeyParamValue::PaddingMode(PaddingMode::NONE),
+            KsKeyParamValue::Nonce(nonce.to_vec()),
+            KsKeyParamValue::MacLength(128),
+        ]
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+        let km_dev = &self.km_dev;
+        let key_id_guard = &mut self.key_id_guard;
+        let (km_uuid, km_blob) = (self.km_uuid, &self.km_blob);
+        let (begin_result, upgraded_blob) = upgrade_keyblob_if_required_with(
+            &**km_dev,
+            self.hw_info.versionNumber,
+            self.upgraded_blob.as_deref().unwrap_or(&**km_blob),
+            &[],
+            |blob| {
+                map_km_error({
+                    let _wp = wd::watch("backup_vault::unwrap: calling IKeyMintDevice::begin");
+                    km_dev.begin(KeyPurpose::DECRYPT, blob, &params, None)
+                })
+            },
+            |upgraded_blob| match key_id_guard.take() {
+                Some(kid) => KeystoreSecurityLevel::store_upgraded_keyblob(
+                    kid,
+                    Some(km_uuid),
+                    km_blob,
+                    upgraded_blob,
+                )
+                .context(ks_err!("store_upgraded_keyblob failed")),
+                None => Ok(()),
+            },
+        )
+        .context(ks_err!("Failed to begin decryption with the recovery key."))?;
+        if upgraded_blob.is_some() {
+            self.upgraded_blob = upgraded_blob;
+        }
+
+        let operation =
+            begin_result.operation.ok_or_else(Error::sys).context(ks_err!("Operation missing."))?;
+        let key_material = map_km_error({
+            let _wp = wd::watch("backup_vault::unwrap: calling IKeyMintOperation::finish");
+            operation.finish(Some(ciphertext), None, None, None, None)
+        })
+        .context(ks_err!("Failed to decrypt wrapped key material."))?;
+        ZVec::try_from(key_material).context(ks_err!())
+    }
+}
+
+/// Stores the key that KeyMint created from a backup vault entry as `key`.
+fn store_restored_key(
+    recovery_key: &RecoveryKey,
+    key: &KeyDescriptor,
+    user_id: u32,
+    origin: i32,
+    creation_result: KeyCreationResult,
+) -> Result<KeyMetadata> {
+    let KeyCreationResult {
+        keyBlob: key_blob,
+        keyCharacteristics: key_characteristics,
+        certificateChain: mut certificate_chain,
+    } = creation_result;
+
+    let mut cert_info = CertificateInfo::new(
+        match certificate_chain.len() {
+            0 => None,
+            _ => Some(certificate_chain.remove(0).encodedCertificate),
+        },
+        match certificate_chain.len() {
+            0 => None,
+            _ => Some(
+                certificate_chain
+                    .iter()
+                    .flat_map(|c| c.encodedCertificate.iter())
+                    .copied()
+                    .collect(),
+            ),
+        },
+    );
+
+    let mut key_parameters = key_characteristics_to_internal(key_characteristics);
+    key_parameters
+        .push(KsKeyParam::new(KsKeyParamValue::UserID(user_id as i32), SecurityLevel::SOFTWARE));
+
+    let creation_date = DateTime::now().context(ks_err!("Trying to make creation time."))?;
+
+    let key_id = DB
+        .with::<_, Result<KeyIdGuard>>(|db| {
+            let mut db = db.borrow_mut();
+            let (key_blob, mut blob_metadata) = SUPER_KEY
+                .read()
+                .unwrap()
+                .handle_super_encryption_on_key_init(
+                    &mut db,
+                    &LEGACY_IMPORTER,
+                    &key.domain,
+                    &key_parameters,
+                    None,
+                    storage_tier(key.domain, key.nspace),
+                    user_id,
+                    &key_blob,
+                )
+                .context(ks_err!("Failed to handle super encryption."))?;
+
+            let mut key_metadata = KeyMetaData::new();
+            key_metadata.add(KeyMetaEntry::CreationDate(creation_date));
+            key_metadata.add(KeyMetaEntry::RestoredOrigin(origin));
+            blob_metadata.add(BlobMetaEntry::KmUuid(recovery_key.km_uuid));
+
+            let key_id = db
+                .store_new_key(
+                    key,
+                    KeyType::Client,
+                    &key_parameters,
+                    &BlobInfo::new(&key_blob, &blob_metadata),
+                    &cert_info,
+                    &key_metadata,
+                    &recovery_key.km_uuid,
+                )
+                .context(ks_err!())?;
+            KEY_ENTRY_CACHE.invalidate_namespace(key.domain, key.nspace);
+            Ok(key_id)
+        })
+        .context(ks_err!())?;
+
+    Ok(KeyMetadata {
+        key: KeyDescriptor { domain: Domain::KEY_ID, nspace: key_id.id(), ..Default::default() },
+        keySecurityLevel: recovery_key.hw_info.securityLevel,
+        certificate: cert_info.take_cert(),
+        certificateChain: cert_info.take_cert_chain(),
+        authorizations: key_parameters_to_authorizations(key_parameters),
+        modificationTimeMs: creation_date.to_millis_epoch(),
+    })
+}
+
+/// Decrypts, imports and stores the key of one backup vault entry.
+fn restore_entry(
+    recovery_key: &mut RecoveryKey,
+    caller_uid: u32,
+    entry: &BackupVaultEntry,
+) -> Result<KeyMetadata> {
+    let key = &entry.key;
+    // For Domain::APP, the namespace is the UID of the owner of the key.
+    let user_id = match (key.domain, &key.alias) {
+        (Domain::APP, Some(_)) => u32::try_from(key.nspace)
+            .map(uid_to_android_user)
+            .map_err(|_| Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Invalid owner UID {}.", key.nspace))?,
+        (Domain::SELINUX, Some(_)) => uid_to_android_user(caller_uid),
+        _ => {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Invalid key descriptor {:?}.", key));
+        }
+    };
+
+    check_namespace_quota(caller_uid, key.domain, key.nspace, key.alias.as_deref())
+        .context(ks_err!("Namespace quota exceeded."))?;
+
+    let key_material =
+        recovery_key.unwrap(&entry.encryptedKeyMaterial).context(ks_err!("Failed to unwrap."))?;
+    let creation_result = map_km_error({
+        let _wp = wd::watch("backup_vault::restore_entry: calling IKeyMintDevice::importKey");
+        recovery_key.km_dev.importKey(
+            &entry.keyParameters,
+            entry.keyFormat,
+            &key_material,
+            None, /* attestKey */
+        )
+    })
+    .context(ks_err!("Trying to call importKey."))?;
+
+    store_restored_key(recovery_key, key, user_id, entry.origin.0, creation_result)
+        .context(ks_err!())
+}
+
+/// Restores the keys of `entries` using `recovery_key`, in order, and returns their metadata.
+/// The caller must have been authorized to restore backups; it must also have the `use`
+/// permission on the recovery key. Stops at the first entry that cannot be restored.
+pub fn import_from_backup_vault(
+    recovery_key: &KeyDescriptor,
+    entries: &[BackupVaultEntry],
+) -> Result<Vec<KeyMetadata>> {
+    let caller_uid = calling_identity::get_calling_uid();
+    let (key_id_guard, mut key_entry) = DB
+        .with(|db| {
+            db.borrow_mut().load_key_entry(
+                recovery_key,
+                KeyType::Client,
+                KeyEntryLoadBits::KM,
+                caller_uid,
+                |k, av| check_key_permission(KeyPerm::Use, k, &av),
+            )
+        })
+        .context(ks_err!("Failed to load the recovery key."))?;
+    let (blob, blob_metadata) = key_entry
+        .take_key_blob_info()
+        .ok_or_else(Error::sys)
+        .context(ks_err!("Recovery key has no key blob."))?;
+    let km_uuid = blob_metadata
+        .km_uuid()
+        .copied()
+        .ok_or_else(Error::sys)
+        .context(ks_err!("Recovery key has no KeyMint uuid."))?;
+    let (km_dev, hw_info) =
+        get_keymint_dev_by_uuid(&km_uuid).context(ks_err!("Failed to get KeyMint device."))?;
+    let km_blob = SUPER_KEY
+        .read()
+        .unwrap()
+        .unwrap_key_if_required(&blob_metadata, &blob)
+        .context(ks_err!("Failed to handle super encryption."))?;
+
+    let mut recovery_key = RecoveryKey {
+        km_dev,
+        hw_info,
+        km_uuid,
+        key_id_guard: Some(key_id_guard),
+        km_blob,
+        upgraded_blob: None,
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let result = restore_entry(&mut recovery_key, caller_uid, entry);
+            log_key_imported(&entry.key, caller_uid, result.is_ok());
+            result.context(ks_err!("Failed to restore entry {}.", i))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_wrapped_key() {
+        let wrapped: Vec<u8> = (0..40).collect();
+        let (nonce, ciphertext) = split_wrapped_key(&wrapped).unwrap();
+        assert_eq!(nonce, &wrapped[..NONCE_LEN]);
+        assert_eq!(ciphertext, &wrapped[NONCE_LEN..]);
+
+        // Empty key material still has a nonce and a tag.
+        assert!(split_wrapped_key(&wrapped[..NONCE_LEN + TAG_LEN]).is_ok());
+        let e = split_wrapped_key(&wrapped[..NONCE_LEN + TAG_LEN - 1]).unwrap_err();
+        assert_eq!(
+            e.root_cause().downcast_ref::<Error>(),
+            Some(&Error::Rc(ResponseCode::INVALID_ARGUMENT))
+        );
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module records how long the stages of keystore2 initialization take, so that boot time
+//! regressions attributable to keystore2 are caught by performance tests. Every stage is logged
+//! once per boot when it completes, in a fixed format that is meant to be parsed by tools:
+//!
+//! `keystore2_boot_stage: name=<stage> start_ms=<start> duration_ms=<duration>`
+//!
+//! where `start` is the time since keystore2 started. Some stages, like opening the database,
+//! are deferred until the first request, because /data may not be mounted when keystore2
+//! starts. The recorded stages are also part of the dump state.
+
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// The boot profile of this keystore2 process. It should be forced as early as possible, so
+/// that the start times of the stages are relative to the start of the process.
+pub static BOOT_PROFILE: LazyLock<BootProfile> = LazyLock::new(BootProfile::new);
+
+/// The timing of one initialization stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageTiming {
+    /// Name of the stage.
+    pub name: &'static str,
+    /// Time from the creation of the profile to the start of the stage.
+    pub start: Duration,
+    /// Duration of the stage.
+    pub duration: Duration,
+}
+
+impl std::fmt::Display for StageTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "keystore2_boot_stage: name={} start_ms={} duration_ms={}",
+            self.name,
+            self.start.as_millis(),
+            self.duration.as_millis()
+        )
+    }
+}
+
+/// Collects the timings of the initialization stages.
+#[derive(Debug)]
+pub struct BootProfile {
+    created: Instant,
+    stages: Mutex<Vec<StageTiming>>,
+}
+
+impl BootProfile {
+    fn new() -> Self {
+        Self { created: Instant::now(), stages: Default::default() }
+    }
+
+    /// Returns the time at which the profile was created.
+    pub fn created(&self) -> Instant {
+        self.created
+    }
+
+    /// Records that the stage `name`, which started at `started`, has completed. Only the first
+    /// completion of each stage is recorded, so stages that run again later, e.g., when a
+    /// security level is reconnected, do not distort the profile.
+    pub fn record(&self, name: &'static str, started: Instant) {
+        let timing = StageTiming {
+            name,
+            start: started.saturating_duration_since(self.created),
+            duration: started.elapsed(),
+        };
+        let mut stages = self.stages.lock().unwrap();
+        if stages.iter().any(|s| s.name == name) {
+            return;
+        }
+        log::info!("{timing}");
+        stages.push(timing);
+    }
+
+    /// Runs `f` as the stage `name` and records its timing.
+    pub fn time<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(name, started);
+        result
+    }
+
+    /// Returns the stages recorded so far in order of completion.
+    pub fn stages(&self) -> Vec<StageTiming> {
+        self.stages.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_once() {
+        let profile = BootProfile::new();
+        let value = profile.time("stage_a", || 42);
+        assert_eq!(value, 42);
+        profile.record("stage_b", profile.created());
+        profile.record("stage_a", Instant::now());
+
+        let stages = profile.stages();
+        assert_eq!(stages.iter().map(|s| s.name).collect::<Vec<_>>(), vec!["stage_a", "stage_b"]);
+        assert_eq!(stages[1].start, Duration::ZERO);
+        assert!(stages[1].duration >= stages[0].duration);
+    }
+
+    #[test]
+    fn test_display() {
+        let timing = StageTiming {
+            name: "db_open",
+            start: Duration::from_millis(1500),
+            duration: Duration::from_micros(25_900),
+        };
+        assert_eq!(
+            timing.to_string(),
+            "keystore2_boot_stage: name=db_open start_ms=1500 duration_ms=25"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module provides the identity of the caller of a binder call, i.e., its UID, PID, and
+//! SELinux context, which the permission checks are based on. It is normally taken from the
+//! binder thread state. Work that is done on behalf of a caller on another thread, e.g., the
+//! requests of the asynchronous service, captures the identity on the binder thread and runs
+//! with it on the worker thread.
+//!
+//! A binder identity token cannot carry the SELinux context, so the identity is restored in a
+//! thread local that takes precedence over the binder thread state.
+
+use binder::ThreadState;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+
+thread_local! {
+    /// The identity that the current thread runs with, if any.
+    static RESTORED: RefCell<Option<CallingIdentity>> = const { RefCell::new(None) };
+}
+
+/// The identity of the caller of a binder call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallingIdentity {
+    uid: u32,
+    pid: i32,
+    sid: Option<CString>,
+}
+
+impl CallingIdentity {
+    /// Returns an identity for tests that act on behalf of a given caller.
+    #[cfg(test)]
+    pub fn new(uid: u32, pid: i32, sid: Option<CString>) -> Self {
+        Self { uid, pid, sid }
+    }
+
+    /// Returns the identity of the caller that the current thread serves.
+    pub fn capture() -> Self {
+        Self {
+            uid: get_calling_uid(),
+            pid: get_calling_pid(),
+            sid: with_calling_sid(|sid| sid.map(CStr::to_owned)),
+        }
+    }
+
+    /// Runs `f` with this identity as the calling identity of the current thread.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        /// Restores the previous identity when dropped, even if `f` panics.
+        struct Restore(Option<CallingIdentity>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                RESTORED.with(|r| *r.borrow_mut() = self.0.take());
+            }
+        }
+
+        let _restore = Restore(RESTORED.with(|r| r.replace(Some(self.clone()))));
+        f()
+    }
+}
+
+/// Returns the UID of the caller, like `ThreadState::get_calling_uid`.
+pub fn get_calling_uid() -> u32 {
+    RESTORED
+        .with(|r| r.borrow().as_ref().map(|identity| identity.uid))
+        .unwrap_or_else(ThreadState::get_calling_uid)
+}
+
+/// Returns the PID of the caller, like `ThreadState::get_calling_pid`.
+pub fn get_calling_pid() -> i32 {
+    RESTORED
+        .with(|r| r.borrow().as_ref().map(|identity| identity.pid))
+        .unwrap_or_else(ThreadState::get_calling_pid)
+}
+
+/// Calls `f` with the SELinux context of the caller, like `ThreadState::with_calling_sid`.
+pub fn with_calling_sid<T, F>(f: F) -> T
+where
+    F: FnOnce(Option<&CStr>) -> T,
+{
+    // The context is copied, so that `f` may run with another identity.
+    match RESTORED.with(|r| r.borrow().as_ref().map(|identity| identity.sid.clone())) {
+        Some(sid) => f(sid.as_deref()),
+        None => ThreadState::with_calling_sid(f),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_with_identity() {
+        let own = CallingIdentity::capture();
+        let caller =
+            CallingIdentity { uid: 10001, pid: 42, sid: Some(c"u:r:untrusted_app:s0".into()) };
+
+        let captured = std::thread::spawn({
+            let caller = caller.clone();
+            move || {
+                caller.run(|| {
+                    assert_eq!(get_calling_uid(), 10001);
+                    assert_eq!(get_calling_pid(), 42);
+                    with_calling_sid(|sid| assert_eq!(sid, Some(c"u:r:untrusted_app:s0")));
+                    CallingIdentity::capture()
+                })
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(captured, caller);
+
+        // The previous identity is restored after `run`.
+        caller.run(|| {
+            own.run(|| assert_eq!(CallingIdentity::capture(), own));
+            assert_eq!(get_calling_uid(), 10001);
+        });
+        assert_eq!(CallingIdentity::capture(), own);
+    }
+}
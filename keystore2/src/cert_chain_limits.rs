@@ -0,0 +1,253 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module validates certificate chains that callers attach to keys with
+//! `updateSubcomponent`. A chain must be a concatenation of DER encoded certificates, and it
+//! must not exceed the configured number of certificates and total size. Without these limits,
+//! an app could bloat the database with chains that cannot even be returned by `getKeyEntry`
+//! because they exceed the maximum parcel size. The limits are configured with system
+//! properties:
+//!  * `keystore.cert_chain.max_certificates`: The maximum number of certificates in a chain.
+//!  * `keystore.cert_chain.max_size`: The maximum total size of a chain in bytes.
+//!  * `keystore.cert_chain.exempt_privileged`: If true (the default), chains stored into
+//!    `Domain::SELINUX` and chains stored by UIDs outside of the app range are not validated.
+//!
+//! A limit of 0 disables the respective check.
+
+use crate::error::Error;
+use crate::import_limits::{is_privileged, read_usize_property};
+use crate::ks_err;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+
+const MAX_CERTIFICATES_PROPERTY: &str = "keystore.cert_chain.max_certificates";
+const MAX_SIZE_PROPERTY: &str = "keystore.cert_chain.max_size";
+const EXEMPT_PRIVILEGED_PROPERTY: &str = "keystore.cert_chain.exempt_privileged";
+
+const DEFAULT_MAX_CERTIFICATES: usize = 10;
+const DEFAULT_MAX_SIZE: usize = 64 * 1024;
+
+/// The DER tag of a constructed SEQUENCE, which every X.509 certificate starts with.
+const DER_SEQUENCE_TAG: u8 = 0x30;
+
+/// Configuration of the certificate chain limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertChainLimitConfig {
+    /// Maximum number of certificates in a chain, 0 for no limit.
+    pub max_certificates: usize,
+    /// Maximum total size of a chain in bytes, 0 for no limit.
+    pub max_size: usize,
+    /// Whether chains stored by privileged callers are exempt from validation.
+    pub exempt_privileged: bool,
+}
+
+impl Default for CertChainLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_certificates: DEFAULT_MAX_CERTIFICATES,
+            max_size: DEFAULT_MAX_SIZE,
+            exempt_privileged: true,
+        }
+    }
+}
+
+impl CertChainLimitConfig {
+    /// Reads the configuration from system properties, using the defaults for properties that
+    /// are not set or cannot be parsed.
+    pub fn from_system_properties() -> Self {
+        let default = Self::default();
+        Self {
+            max_certificates: read_usize_property(
+                MAX_CERTIFICATES_PROPERTY,
+                default.max_certificates,
+            ),
+            max_size: read_usize_property(MAX_SIZE_PROPERTY, default.max_size),
+            exempt_privileged: rustutils::system_properties::read_bool(
+                EXEMPT_PRIVILEGED_PROPERTY,
+                default.exempt_privileged,
+            )
+            .unwrap_or(default.exempt_privileged),
+        }
+    }
+}
+
+/// Checks a certificate chain that `caller_uid` stores into `domain` against the limits
+/// configured by system properties.
+pub fn check_cert_chain(caller_uid: u32, domain: Domain, cert_chain: &[u8]) -> Result<()> {
+    check_cert_chain_with(
+        &CertChainLimitConfig::from_system_properties(),
+        caller_uid,
+        domain,
+        cert_chain,
+    )
+}
+
+fn check_cert_chain_with(
+    config: &CertChainLimitConfig,
+    caller_uid: u32,
+    domain: Domain,
+    cert_chain: &[u8],
+) -> Result<()> {
+    if config.exempt_privileged && is_privileged(caller_uid, domain) {
+        return Ok(());
+    }
+
+    if config.max_size != 0 && cert_chain.len() > config.max_size {
+        return Err(Error::Rc(ResponseCode::TOO_MUCH_DATA)).context(ks_err!(
+            "Certificate chain of {} bytes exceeds the limit of {} bytes.",
+            cert_chain.len(),
+            config.max_size
+        ));
+    }
+
+    let mut remaining = cert_chain;
+    let mut count = 0;
+    while !remaining.is_empty() {
+        let cert_len = der_sequence_len(remaining)
+            .context(ks_err!("Certificate {} of the chain is malformed.", count))?;
+        remaining = &remaining[cert_len..];
+        count += 1;
+        if config.max_certificates != 0 && count > config.max_certificates {
+            return Err(Error::Rc(ResponseCode::TOO_MUCH_DATA)).context(ks_err!(
+                "Certificate chain exceeds the limit of {} certificates.",
+                config.max_certificates
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the length including header of the DER encoded SEQUENCE at the start of `data`.
+fn der_sequence_len(data: &[u8]) -> Result<usize> {
+    let malformed = |reason: &str| {
+        Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!("{}", reason))
+    };
+    match data.first() {
+        Some(&DER_SEQUENCE_TAG) => {}
+        _ => return malformed("Expected a DER SEQUENCE."),
+    }
+    let (header_len, content_len) = match data.get(1) {
+        None => return malformed("Missing length."),
+        Some(&b) if b < 0x80 => (2, b as usize),
+        // Certificates larger than 4 GiB are not supported, and 0x80 would be the
+        // indefinite length form, which is not allowed in DER.
+        Some(&b) if (0x81..=0x84).contains(&b) => {
+            let num_bytes = (b & 0x7f) as usize;
+            let Some(len_bytes) = data.get(2..2 + num_bytes) else {
+                return malformed("Truncated length.");
+            };
+            let len = len_bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+            // DER requires the shortest possible encoding of the length.
+            if len_bytes[0] == 0 || len < 0x80 {
+                return malformed("Length is not minimally encoded.");
+            }
+            (2 + num_bytes, len)
+        }
+        Some(_) => return malformed("Unsupported length encoding."),
+    };
+    match header_len.checked_add(content_len) {
+        Some(total_len) if total_len <= data.len() => Ok(total_len),
+        _ => malformed("Truncated certificate."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_UID: u32 = 10100;
+
+    fn response_code(result: Result<()>) -> Option<ResponseCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Rc(rc)) => Some(*rc),
+            _ => None,
+        }
+    }
+
+    /// Returns a DER encoded SEQUENCE with `content_len` bytes of content.
+    fn der_sequence(content_len: usize) -> Vec<u8> {
+        let mut der = vec![DER_SEQUENCE_TAG];
+        match content_len {
+            0..=0x7f => der.push(content_len as u8),
+            0x80..=0xff => der.extend([0x81, content_len as u8]),
+            _ => der.extend([0x82, (content_len >> 8) as u8, content_len as u8]),
+        }
+        der.resize(der.len() + content_len, 0);
+        der
+    }
+
+    #[test]
+    fn test_der_sequence_len() {
+        for content_len in [0, 0x7f, 0x80, 0xff, 0x100, 0x1234] {
+            let der = der_sequence(content_len);
+            assert_eq!(der_sequence_len(&der).unwrap(), der.len());
+        }
+        // Wrong tag, indefinite length, non-minimal length and truncation.
+        for der in [
+            vec![0x31, 0x00],
+            vec![0x30, 0x80, 0x00, 0x00],
+            vec![0x30, 0x81, 0x05, 0, 0, 0, 0, 0],
+            vec![0x30, 0x82, 0x00, 0x80],
+            vec![0x30, 0x05, 0x00],
+            vec![0x30, 0x82, 0x01],
+            vec![0x30],
+        ] {
+            assert!(der_sequence_len(&der).is_err(), "{der:?}");
+        }
+    }
+
+    #[test]
+    fn test_check_cert_chain() {
+        let config =
+            CertChainLimitConfig { max_certificates: 2, max_size: 1000, ..Default::default() };
+        let chain = [der_sequence(100), der_sequence(200)].concat();
+        assert!(check_cert_chain_with(&config, APP_UID, Domain::APP, &chain).is_ok());
+
+        let too_long = [chain.clone(), der_sequence(0)].concat();
+        assert_eq!(
+            response_code(check_cert_chain_with(&config, APP_UID, Domain::APP, &too_long)),
+            Some(ResponseCode::TOO_MUCH_DATA)
+        );
+        let too_large = der_sequence(1000);
+        assert_eq!(
+            response_code(check_cert_chain_with(&config, APP_UID, Domain::APP, &too_large)),
+            Some(ResponseCode::TOO_MUCH_DATA)
+        );
+        let trailing_garbage = [chain.clone(), vec![0x00]].concat();
+        assert_eq!(
+            response_code(check_cert_chain_with(&config, APP_UID, Domain::APP, &trailing_garbage)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+
+        let unlimited = CertChainLimitConfig { max_certificates: 0, max_size: 0, ..config };
+        assert!(check_cert_chain_with(&unlimited, APP_UID, Domain::APP, &too_long).is_ok());
+        assert!(check_cert_chain_with(&unlimited, APP_UID, Domain::APP, &too_large).is_ok());
+    }
+
+    #[test]
+    fn test_privileged_exemption() {
+        let config = CertChainLimitConfig::default();
+        let garbage = [12u8; 32];
+        assert!(check_cert_chain_with(&config, APP_UID, Domain::SELINUX, &garbage).is_ok());
+        assert!(check_cert_chain_with(&config, 1000, Domain::APP, &garbage).is_ok());
+
+        let config = CertChainLimitConfig { exempt_privileged: false, ..config };
+        assert_eq!(
+            response_code(check_cert_chain_with(&config, 1000, Domain::SELINUX, &garbage)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+    }
+}
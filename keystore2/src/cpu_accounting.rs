@@ -0,0 +1,218 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements optional CPU time accounting for binder calls, so that CPU and power
+//! regressions in keystore, e.g., in its parameter and key blob parsing paths, can be
+//! attributed to an API and a caller without an external profiler. When the system property
+//! `keystore.cpu_accounting.enabled` is true at startup, the public binder entry points record
+//! the user and system CPU time that the serving thread spent in each call, as reported by
+//! `getrusage(RUSAGE_THREAD)`. The totals are aggregated per API and caller UID and pulled as
+//! CALL_CPU_STATS atoms. Time spent in other processes, e.g., in KeyMint, is not included.
+
+use crate::calling_identity;
+use crate::metrics_store::MetricsStore;
+use android_security_metrics::aidl::android::security::metrics::{
+    CallCpuStats::CallCpuStats, KeystoreAtom::KeystoreAtom,
+    KeystoreAtomPayload::KeystoreAtomPayload,
+};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+const ENABLED_PROPERTY: &str = "keystore.cpu_accounting.enabled";
+
+/// The UID under which calls are recorded once the number of distinct APIs and callers
+/// reaches the cardinality limit of an atom.
+const OVERFLOW_UID: i32 = -1;
+
+/// The CPU time accounting of this keystore instance.
+pub static CPU_ACCOUNTING: LazyLock<CpuAccounting> = LazyLock::new(|| {
+    CpuAccounting::new(
+        rustutils::system_properties::read_bool(ENABLED_PROPERTY, false).unwrap_or(false),
+    )
+});
+
+/// CPU time of a thread in microseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct CpuTime {
+    user_micros: i64,
+    system_micros: i64,
+}
+
+impl CpuTime {
+    /// Returns the CPU time consumed by the calling thread so far.
+    fn of_current_thread() -> Option<Self> {
+        // SAFETY: An all zero rusage is a valid value of the plain C struct.
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: The pointer is valid because it comes from a reference, and getrusage doesn't
+        // retain it beyond the call.
+        if unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) } != 0 {
+            return None;
+        }
+        let micros = |t: libc::timeval| t.tv_sec as i64 * 1_000_000 + t.tv_usec as i64;
+        Some(Self { user_micros: micros(usage.ru_utime), system_micros: micros(usage.ru_stime) })
+    }
+}
+
+/// The accumulated CPU time of the calls to one API by one caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct CallTotals {
+    calls: i64,
+    cpu_time: CpuTime,
+}
+
+/// Aggregates the CPU time of binder calls per API and caller UID.
+pub struct CpuAccounting {
+    enabled: bool,
+    totals: Mutex<HashMap<(&'static str, i32), CallTotals>>,
+}
+
+impl CpuAccounting {
+    fn new(enabled: bool) -> Self {
+        Self { enabled, totals: Default::default() }
+    }
+
+    /// Starts accounting the CPU time of the current binder call to `api`, which is recorded
+    /// when the returned guard is dropped. Returns None if accounting is disabled.
+    pub fn account(&'static self, api: &'static str) -> Option<CallCpuGuard> {
+        if !self.enabled {
+            return None;
+        }
+        let uid = calling_identity::get_calling_uid() as i32;
+        Some(CallCpuGuard { accounting: self, api, uid, start: CpuTime::of_current_thread()? })
+    }
+
+    fn record(&self, api: &'static str, uid: i32, cpu_time: CpuTime) {
+        let mut totals = self.totals.lock().unwrap();
+        let key = if totals.len() < MetricsStore::SINGLE_ATOM_STORE_MAX_SIZE
+            || totals.contains_key(&(api, uid))
+        {
+            (api, uid)
+        } else {
+            (api, OVERFLOW_UID)
+        };
+        let entry = totals.entry(key).or_default();
+        entry.calls += 1;
+        entry.cpu_time.user_micros += cpu_time.user_micros;
+        entry.cpu_time.system_micros += cpu_time.system_micros;
+    }
+
+    /// Returns the totals since startup as CALL_CPU_STATS atoms.
+    pub fn pull(&self) -> Vec<KeystoreAtom> {
+        let totals = self.totals.lock().unwrap();
+        let mut atoms: Vec<KeystoreAtom> = totals
+            .iter()
+            .map(|((api, uid), t)| KeystoreAtom {
+                payload: KeystoreAtomPayload::CallCpuStats(CallCpuStats {
+                    api: api.to_string(),
+                    uid: *uid,
+                    call_count: t.calls,
+                    user_time_micros: t.cpu_time.user_micros,
+                    system_time_micros: t.cpu_time.system_micros,
+                }),
+                count: 1,
+            })
+            .collect();
+        atoms.sort_by(|a, b| a.payload.cmp(&b.payload));
+        atoms
+    }
+}
+
+/// Records the CPU time of a binder call when dropped.
+pub struct CallCpuGuard {
+    accounting: &'static CpuAccounting,
+    api: &'static str,
+    uid: i32,
+    start: CpuTime,
+}
+
+impl Drop for CallCpuGuard {
+    fn drop(&mut self) {
+        if let Some(end) = CpuTime::of_current_thread() {
+            let delta = CpuTime {
+                user_micros: end.user_micros - self.start.user_micros,
+                system_micros: end.system_micros - self.start.system_micros,
+            };
+            self.accounting.record(self.api, self.uid, delta);
+        }
+    }
+}
+
+/// Starts accounting the CPU time of the current binder call to `api`. See
+/// `CpuAccounting::account`.
+pub fn account(api: &'static str) -> Option<CallCpuGuard> {
+    CPU_ACCOUNTING.account(api)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_of_current_thread() {
+        let start = CpuTime::of_current_thread().unwrap();
+        let mut x: u64 = 0;
+        for i in 0..10_000_000u64 {
+            x = std::hint::black_box(x.wrapping_add(i));
+        }
+        let end = CpuTime::of_current_thread().unwrap();
+        assert!(end.user_micros + end.system_micros > start.user_micros + start.system_micros);
+    }
+
+    #[test]
+    fn test_record_and_pull() {
+        static ACCOUNTING: LazyLock<CpuAccounting> = LazyLock::new(|| CpuAccounting::new(true));
+        let cpu_time = CpuTime { user_micros: 10, system_micros: 5 };
+        ACCOUNTING.record("IKeystoreService::getKeyEntry", 10001, cpu_time);
+        ACCOUNTING.record("IKeystoreService::getKeyEntry", 10001, cpu_time);
+        ACCOUNTING.record("IKeystoreService::deleteKey", 10001, cpu_time);
+        {
+            let _guard = ACCOUNTING.account("IKeystoreOperation::update").unwrap();
+        }
+
+        let atoms = ACCOUNTING.pull();
+        assert_eq!(atoms.len(), 3);
+        let stats = |api: &str| {
+            atoms
+                .iter()
+                .find_map(|a| match &a.payload {
+                    KeystoreAtomPayload::CallCpuStats(s) if s.api == api => Some(s.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let get_key_entry = stats("IKeystoreService::getKeyEntry");
+        assert_eq!(get_key_entry.uid, 10001);
+        assert_eq!(get_key_entry.call_count, 2);
+        assert_eq!(get_key_entry.user_time_micros, 20);
+        assert_eq!(get_key_entry.system_time_micros, 10);
+        assert_eq!(stats("IKeystoreOperation::update").call_count, 1);
+    }
+
+    #[test]
+    fn test_disabled() {
+        static ACCOUNTING: LazyLock<CpuAccounting> = LazyLock::new(|| CpuAccounting::new(false));
+        assert!(ACCOUNTING.account("IKeystoreService::getKeyEntry").is_none());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let accounting = CpuAccounting::new(true);
+        for uid in 0..MetricsStore::SINGLE_ATOM_STORE_MAX_SIZE as i32 + 10 {
+            accounting.record("IKeystoreService::listEntries", uid, CpuTime::default());
+        }
+        let totals = accounting.totals.lock().unwrap();
+        assert_eq!(totals.len(), MetricsStore::SINGLE_ATOM_STORE_MAX_SIZE + 1);
+        assert_eq!(totals[&("IKeystoreService::listEntries", OVERFLOW_UID)].calls, 10);
+    }
+}
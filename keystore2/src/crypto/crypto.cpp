@@ -24,6 +24,7 @@
 #include <openssl/ec.h>
 #include <openssl/ec_key.h>
 #include <openssl/ecdh.h>
+#include <openssl/err.h>
 #include <openssl/evp.h>
 #include <openssl/hkdf.h>
 #include <openssl/hmac.h>
@@ -83,10 +84,12 @@ bool randomBytes(uint8_t* out, size_t len) {
 /*
  * Encrypt 'len' data at 'in' with AES-GCM, using 128-bit or 256-bit key at 'key', 96-bit IV at
  * 'iv' and write output to 'out' (which may be the same location as 'in') and 128-bit tag to
- * 'tag'.
+ * 'tag'. The 'aad_len' bytes at 'aad' are authenticated but not encrypted; 'aad' may be null if
+ * 'aad_len' is 0.
  */
 bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
-                     size_t key_size, const uint8_t* iv, uint8_t* tag) {
+                     size_t key_size, const uint8_t* iv, uint8_t* tag, const uint8_t* aad,
+                     size_t aad_len) {
 
     // There can be 128-bit and 256-bit keys
     const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
@@ -100,6 +103,9 @@ bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
     uint8_t* out_pos = out_tmp.data();
     int out_len;
 
+    if (aad_len > 0 && !EVP_EncryptUpdate(ctx.get(), nullptr, &out_len, aad, aad_len)) {
+        return false;
+    }
     EVP_EncryptUpdate(ctx.get(), out_pos, &out_len, in, len);
     out_pos += out_len;
     EVP_EncryptFinal_ex(ctx.get(), out_pos, &out_len);
@@ -119,10 +125,12 @@ bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
 /*
  * Decrypt 'len' data at 'in' with AES-GCM, using 128-bit or 256-bit key at 'key', 96-bit IV at
  * 'iv', checking 128-bit tag at 'tag' and writing plaintext to 'out'(which may be the same
- * location as 'in').
+ * location as 'in'). The tag must also cover the 'aad_len' bytes at 'aad', which may be null if
+ * 'aad_len' is 0.
  */
 bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
-                     size_t key_size, const uint8_t* iv, const uint8_t* tag) {
+                     size_t key_size, const uint8_t* iv, const uint8_t* tag, const uint8_t* aad,
+                     size_t aad_len) {
 
     // There can be 128-bit and 256-bit keys
     const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
@@ -138,6 +146,9 @@ bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
     uint8_t* out_pos = out_tmp.data();
     int out_len;
 
+    if (aad_len > 0 && !EVP_DecryptUpdate(ctx.get(), nullptr, &out_len, aad, aad_len)) {
+        return false;
+    }
     EVP_DecryptUpdate(ctx.get(), out_pos, &out_len, in, len);
     out_pos += out_len;
     if (!EVP_DecryptFinal_ex(ctx.get(), out_pos, &out_len)) {
@@ -261,6 +272,53 @@ EC_KEY* ECKEYParsePrivateKey(const uint8_t* buf, size_t len) {
     return result;
 }
 
+// Returns true if the last error was caused by curve parameters that do not describe a curve
+// known to BoringSSL.
+static bool isUnknownGroupError() {
+    uint32_t err = ERR_peek_last_error();
+    return ERR_GET_LIB(err) == ERR_LIB_EC && ERR_GET_REASON(err) == EC_R_UNKNOWN_GROUP;
+}
+
+int ECKEYNormalizePrivateKey(const uint8_t* in, size_t in_len, uint8_t* buf, size_t len) {
+    CBS cbs;
+    CBS_init(&cbs, in, in_len);
+    bssl::UniquePtr<EVP_PKEY> pkey(EVP_parse_private_key(&cbs));
+    if (!pkey || CBS_len(&cbs) != 0) {
+        if (isUnknownGroupError()) {
+            ERR_clear_error();
+            return EC_NORMALIZE_UNSUPPORTED_CURVE;
+        }
+        ERR_clear_error();
+        // Not PKCS#8, so try SEC1. Without a group, the parameters must be encoded in the key.
+        CBS_init(&cbs, in, in_len);
+        bssl::UniquePtr<EC_KEY> ec_key(EC_KEY_parse_private_key(&cbs, nullptr));
+        if (!ec_key || CBS_len(&cbs) != 0) {
+            int result =
+                isUnknownGroupError() ? EC_NORMALIZE_UNSUPPORTED_CURVE : EC_NORMALIZE_PARSE_FAILED;
+            ERR_clear_error();
+            return result;
+        }
+        pkey.reset(EVP_PKEY_new());
+        if (!pkey || !EVP_PKEY_assign_EC_KEY(pkey.get(), ec_key.release())) {
+            return EC_NORMALIZE_PARSE_FAILED;
+        }
+    }
+    if (EVP_PKEY_id(pkey.get()) != EVP_PKEY_EC) {
+        return EC_NORMALIZE_PARSE_FAILED;
+    }
+
+    // BoringSSL always encodes the group of an EC key as named curve.
+    CBB cbb;
+    size_t out_len;
+    if (!CBB_init_fixed(&cbb, buf, len) || !EVP_marshal_private_key(&cbb, pkey.get()) ||
+        !CBB_finish(&cbb, nullptr, &out_len)) {
+        CBB_cleanup(&cbb);
+        ERR_clear_error();
+        return EC_NORMALIZE_BUFFER_TOO_SMALL;
+    }
+    return static_cast<int>(out_len);
+}
+
 size_t ECPOINTPoint2Oct(const EC_POINT* point, uint8_t* buf, size_t len) {
     EC_GROUP* group = EC_GROUP_new_by_curve_name(NID_secp521r1);
     point_conversion_form_t form = POINT_CONVERSION_UNCOMPRESSED;
@@ -319,3 +377,44 @@ int extractSubjectFromCertificate(const uint8_t* cert_buf, size_t cert_len, uint
     uint8_t* tmp = subject_buf;
     return i2d_X509_NAME(subject, &tmp);
 }
+
+int extractSubjectPublicKeyInfoFromCertificate(const uint8_t* cert_buf, size_t cert_len,
+                                               uint8_t* spki_buf, size_t spki_buf_len) {
+    if (!cert_buf || !spki_buf) {
+        ALOGE("extractSubjectPublicKeyInfoFromCertificate: received null pointer");
+        return 0;
+    }
+
+    const uint8_t* p = cert_buf;
+    bssl::UniquePtr<X509> cert(d2i_X509(nullptr /* Allocate X509 struct */, &p, cert_len));
+    if (!cert) {
+        ALOGE("extractSubjectPublicKeyInfoFromCertificate: failed to parse certificate");
+        return 0;
+    }
+
+    X509_PUBKEY* spki = X509_get_X509_PUBKEY(cert.get());
+    if (!spki) {
+        ALOGE("extractSubjectPublicKeyInfoFromCertificate: failed to retrieve public key");
+        return 0;
+    }
+
+    int spki_len = i2d_X509_PUBKEY(spki, nullptr /* Don't copy the data */);
+    if (spki_len < 0) {
+        ALOGE("extractSubjectPublicKeyInfoFromCertificate: error obtaining encoded public key "
+              "length");
+        return 0;
+    }
+
+    if (spki_len > spki_buf_len) {
+        // Return the public key length, negated, so the caller knows how much
+        // buffer space is required.
+        ALOGI("extractSubjectPublicKeyInfoFromCertificate: needed %d bytes for public key, "
+              "caller provided %zu",
+              spki_len, spki_buf_len);
+        return -spki_len;
+    }
+
+    // spki_buf has enough space.
+    uint8_t* tmp = spki_buf;
+    return i2d_X509_PUBKEY(spki, &tmp);
+}
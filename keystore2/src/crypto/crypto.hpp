@@ -26,10 +26,11 @@ extern "C" {
                   uint8_t* out, size_t out_size);
   bool randomBytes(uint8_t* out, size_t len);
   bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len,
-                       const uint8_t* key, size_t key_size, const uint8_t* iv, uint8_t* tag);
+                       const uint8_t* key, size_t key_size, const uint8_t* iv, uint8_t* tag,
+                       const uint8_t* aad, size_t aad_len);
   bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len,
                        const uint8_t* key, size_t key_size, const uint8_t* iv,
-                       const uint8_t* tag);
+                       const uint8_t* tag, const uint8_t* aad, size_t aad_len);
 
   // Copied from system/security/keystore/keymaster_enforcement.h.
   typedef uint64_t km_id_t;
@@ -65,6 +66,17 @@ extern "C" {
 
   EC_POINT* ECPOINTOct2Point(const uint8_t *buf, size_t len);
 
+  // Return values of ECKEYNormalizePrivateKey other than the length of the normalized key.
+  static const int EC_NORMALIZE_PARSE_FAILED = 0;
+  static const int EC_NORMALIZE_UNSUPPORTED_CURVE = -1;
+  static const int EC_NORMALIZE_BUFFER_TOO_SMALL = -2;
+
+  // Parses an EC private key given as PKCS#8 PrivateKeyInfo or as SEC1 ECPrivateKey, with
+  // either a named curve or explicit curve parameters, and writes it to buf as PKCS#8
+  // PrivateKeyInfo with a named curve. Explicit parameters are mapped to the named curve they
+  // describe. Returns the number of bytes written, or one of the codes above.
+  int ECKEYNormalizePrivateKey(const uint8_t *in, size_t in_len, uint8_t *buf, size_t len);
+
 }
 
 // Parse a DER-encoded X.509 certificate contained in cert_buf, with length
@@ -89,4 +101,12 @@ extern "C" {
 int extractSubjectFromCertificate(const uint8_t* cert_buf, size_t cert_len,
                                   uint8_t* subject_buf, size_t subject_buf_len);
 
+// Parse a DER-encoded X.509 certificate contained in cert_buf, with length
+// cert_len, extract the SubjectPublicKeyInfo, DER-encode it and write the
+// result to spki_buf, which has spki_buf_len capacity.
+//
+// The return value is overloaded as for extractSubjectFromCertificate.
+int extractSubjectPublicKeyInfoFromCertificate(const uint8_t* cert_buf, size_t cert_len,
+                                               uint8_t* spki_buf, size_t spki_buf_len);
+
 #endif  //  __CRYPTO_H__
@@ -83,6 +83,11 @@ pub enum Error {
     #[error("Failed to parse private key.")]
     ECKEYParsePrivateKeyFailed,
 
+    /// This is returned if the C implementation of ECKEYNormalizePrivateKey found explicit
+    /// curve parameters that do not describe a supported named curve.
+    #[error("Unsupported explicit EC curve parameters.")]
+    ECKEYUnsupportedCurve,
+
     /// This is returned if the C implementation of ECPOINTPoint2Oct returned 0.
     #[error("Failed to convert point to oct.")]
     ECPoint2OctFailed,
@@ -95,6 +100,11 @@ pub enum Error {
     #[error("Failed to extract certificate subject.")]
     ExtractSubjectFailed,
 
+    /// This is returned if the C implementation of extractSubjectPublicKeyInfoFromCertificate
+    /// failed.
+    #[error("Failed to extract certificate public key.")]
+    ExtractSubjectPublicKeyInfoFailed,
+
     /// This is returned if the C implementation of hmacSha256 failed.
     #[error("Failed to calculate HMAC-SHA256.")]
     HmacSha256Failed,
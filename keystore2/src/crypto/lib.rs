@@ -19,10 +19,12 @@ mod error;
 pub mod zvec;
 pub use error::Error;
 use keystore2_crypto_bindgen::{
-    extractSubjectFromCertificate, hmacSha256, randomBytes, AES_gcm_decrypt, AES_gcm_encrypt,
-    ECDHComputeKey, ECKEYGenerateKey, ECKEYMarshalPrivateKey, ECKEYParsePrivateKey,
-    ECPOINTOct2Point, ECPOINTPoint2Oct, EC_KEY_free, EC_KEY_get0_public_key, EC_POINT_free,
-    HKDFExpand, HKDFExtract, EC_KEY, EC_MAX_BYTES, EC_POINT, EVP_MAX_MD_SIZE, PBKDF2,
+    extractSubjectFromCertificate, extractSubjectPublicKeyInfoFromCertificate, hmacSha256,
+    randomBytes, AES_gcm_decrypt, AES_gcm_encrypt, ECDHComputeKey, ECKEYGenerateKey,
+    ECKEYMarshalPrivateKey, ECKEYNormalizePrivateKey, ECKEYParsePrivateKey, ECPOINTOct2Point,
+    ECPOINTPoint2Oct, EC_KEY_free, EC_KEY_get0_public_key, EC_POINT_free, HKDFExpand, HKDFExtract,
+    EC_KEY, EC_MAX_BYTES, EC_NORMALIZE_BUFFER_TOO_SMALL, EC_NORMALIZE_UNSUPPORTED_CURVE, EC_POINT,
+    EVP_MAX_MD_SIZE, PBKDF2,
 };
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -97,6 +99,18 @@ pub fn hmac_sha256(key: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
 /// freed. Input key is taken as a slice for flexibility, but it is recommended that it is held
 /// in a ZVec as well.
 pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result<ZVec, Error> {
+    aes_gcm_decrypt_with_aad(data, iv, tag, key, &[])
+}
+
+/// Like `aes_gcm_decrypt`, but the tag must also authenticate the additional data `aad`, which
+/// must be the same that was passed to `aes_gcm_encrypt_with_aad`.
+pub fn aes_gcm_decrypt_with_aad(
+    data: &[u8],
+    iv: &[u8],
+    tag: &[u8],
+    key: &[u8],
+    aad: &[u8],
+) -> Result<ZVec, Error> {
     // Old versions of aes_gcm_encrypt produced 16 byte IVs, but the last four bytes were ignored
     // so trim these to the correct size.
     let iv = match iv.len() {
@@ -116,8 +130,9 @@ pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result
     let mut result = ZVec::new(data.len())?;
 
     // Safety: The first two arguments must point to buffers with a size given by the third
-    // argument. We pass the length of the key buffer along with the key.
-    // The `iv` buffer must be 12 bytes and the `tag` buffer 16, which we check above.
+    // argument. We pass the length of the key buffer along with the key, and the length of the
+    // `aad` buffer along with it. The `iv` buffer must be 12 bytes and the `tag` buffer 16, which
+    // we check above.
     match unsafe {
         AES_gcm_decrypt(
             data.as_ptr(),
@@ -127,6 +142,8 @@ pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result
             key.len(),
             iv.as_ptr(),
             tag.as_ptr(),
+            aad.as_ptr(),
+            aad.len(),
         )
     } {
         true => Ok(result),
@@ -139,6 +156,17 @@ pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result
 /// the key length. The function generates an initialization vector. The return value is a tuple
 /// of `(ciphertext, iv, tag)`.
 pub fn aes_gcm_encrypt(plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error> {
+    aes_gcm_encrypt_with_aad(plaintext, key, &[])
+}
+
+/// Like `aes_gcm_encrypt`, but the tag also authenticates the additional data `aad`, which is
+/// not part of the ciphertext. Decryption with `aes_gcm_decrypt_with_aad` fails unless it is
+/// given the same additional data.
+pub fn aes_gcm_encrypt_with_aad(
+    plaintext: &[u8],
+    key: &[u8],
+    aad: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error> {
     let mut iv = vec![0; GCM_IV_LENGTH];
     // Safety: iv is GCM_IV_LENGTH bytes long.
     if !unsafe { randomBytes(iv.as_mut_ptr(), GCM_IV_LENGTH) } {
@@ -153,8 +181,9 @@ pub fn aes_gcm_encrypt(plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>
     let mut ciphertext: Vec<u8> = vec![0; plaintext.len()];
     let mut tag: Vec<u8> = vec![0; TAG_LENGTH];
     // Safety: The first two arguments must point to buffers with a size given by the third
-    // argument. We pass the length of the key buffer along with the key.
-    // The `iv` buffer must be 12 bytes and the `tag` buffer 16, which we check above.
+    // argument. We pass the length of the key buffer along with the key, and the length of the
+    // `aad` buffer along with it. The `iv` buffer must be 12 bytes and the `tag` buffer 16, which
+    // we check above.
     if unsafe {
         AES_gcm_encrypt(
             plaintext.as_ptr(),
@@ -164,6 +193,8 @@ pub fn aes_gcm_encrypt(plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>
             key.len(),
             iv.as_ptr(),
             tag.as_mut_ptr(),
+            aad.as_ptr(),
+            aad.len(),
         )
     } {
         Ok((ciphertext, iv, tag))
@@ -392,6 +423,28 @@ pub fn ec_key_parse_private_key(buf: &[u8]) -> Result<ECKey, Error> {
     }
 }
 
+/// Parses an EC private key given as PKCS#8 PrivateKeyInfo or as SEC1 ECPrivateKey, with a named
+/// curve or explicit curve parameters, and returns it as PKCS#8 PrivateKeyInfo with the named
+/// curve. Returns `Error::ECKEYUnsupportedCurve` if the explicit parameters do not describe a
+/// curve that BoringSSL supports.
+pub fn ec_key_normalize_private_key(key: &[u8]) -> Result<ZVec, Error> {
+    // Large enough for a P-521 key including its public key.
+    let mut buf = ZVec::new(512)?;
+    // Safety: ECKEYNormalizePrivateKey reads at most key.len() bytes from key and writes at most
+    // buf.len() bytes to buf.
+    let result =
+        unsafe { ECKEYNormalizePrivateKey(key.as_ptr(), key.len(), buf.as_mut_ptr(), buf.len()) };
+    match result {
+        len if len > 0 => {
+            buf.reduce_len(len as usize);
+            Ok(buf)
+        }
+        EC_NORMALIZE_UNSUPPORTED_CURVE => Err(Error::ECKEYUnsupportedCurve),
+        EC_NORMALIZE_BUFFER_TOO_SMALL => Err(Error::ECKEYMarshalPrivateKeyFailed),
+        _ => Err(Error::ECKEYParsePrivateKeyFailed),
+    }
+}
+
 /// Calls the boringssl EC_KEY_get0_public_key function.
 pub fn ec_key_get0_public_key(key: &ECKey) -> BorrowedECPoint {
     // Safety: The key is valid.
@@ -431,50 +484,56 @@ pub fn ec_point_oct_to_point(buf: &[u8]) -> Result<OwnedECPoint, Error> {
 
 /// Uses BoringSSL to extract the DER-encoded subject from a DER-encoded X.509 certificate.
 pub fn parse_subject_from_certificate(cert_buf: &[u8]) -> Result<Vec<u8>, Error> {
+    extract_from_certificate(cert_buf, extractSubjectFromCertificate)
+        .ok_or(Error::ExtractSubjectFailed)
+}
+
+/// Uses BoringSSL to extract the DER-encoded SubjectPublicKeyInfo from a DER-encoded X.509
+/// certificate.
+pub fn parse_subject_public_key_info_from_certificate(cert_buf: &[u8]) -> Result<Vec<u8>, Error> {
+    extract_from_certificate(cert_buf, extractSubjectPublicKeyInfoFromCertificate)
+        .ok_or(Error::ExtractSubjectPublicKeyInfoFailed)
+}
+
+/// Calls `extract`, which follows the calling convention of extractSubjectFromCertificate, and
+/// returns the extracted field, or None if the certificate could not be parsed.
+fn extract_from_certificate(
+    cert_buf: &[u8],
+    extract: unsafe extern "C" fn(*const u8, usize, *mut u8, usize) -> std::os::raw::c_int,
+) -> Option<Vec<u8>> {
     // Try with a 200-byte output buffer, should be enough in all but bizarre cases.
     let mut retval = vec![0; 200];
 
-    // Safety: extractSubjectFromCertificate reads at most cert_buf.len() bytes from cert_buf and
-    // writes at most retval.len() bytes to retval.
-    let mut size = unsafe {
-        extractSubjectFromCertificate(
-            cert_buf.as_ptr(),
-            cert_buf.len(),
-            retval.as_mut_ptr(),
-            retval.len(),
-        )
-    };
+    // Safety: extract reads at most cert_buf.len() bytes from cert_buf and writes at most
+    // retval.len() bytes to retval.
+    let mut size =
+        unsafe { extract(cert_buf.as_ptr(), cert_buf.len(), retval.as_mut_ptr(), retval.len()) };
 
     if size == 0 {
-        return Err(Error::ExtractSubjectFailed);
+        return None;
     }
 
     if size < 0 {
         // Our buffer wasn't big enough.  Make one that is just the right size and try again.
-        let negated_size = usize::try_from(-size).map_err(|_e| Error::ExtractSubjectFailed)?;
+        let negated_size = usize::try_from(-size).ok()?;
         retval = vec![0; negated_size];
 
-        // Safety: extractSubjectFromCertificate reads at most cert_buf.len() bytes from cert_buf
-        // and writes at most retval.len() bytes to retval.
+        // Safety: extract reads at most cert_buf.len() bytes from cert_buf and writes at most
+        // retval.len() bytes to retval.
         size = unsafe {
-            extractSubjectFromCertificate(
-                cert_buf.as_ptr(),
-                cert_buf.len(),
-                retval.as_mut_ptr(),
-                retval.len(),
-            )
+            extract(cert_buf.as_ptr(), cert_buf.len(), retval.as_mut_ptr(), retval.len())
         };
 
         if size <= 0 {
-            return Err(Error::ExtractSubjectFailed);
+            return None;
         }
     }
 
     // Reduce buffer size to the amount written.
-    let safe_size = usize::try_from(size).map_err(|_e| Error::ExtractSubjectFailed)?;
+    let safe_size = usize::try_from(size).ok()?;
     retval.truncate(safe_size);
 
-    Ok(retval)
+    Some(retval)
 }
 
 #[cfg(test)]
@@ -492,6 +551,18 @@ mod tests {
         assert_eq!(message[..], message2[..])
     }
 
+    #[test]
+    fn test_wrapper_roundtrip_with_aad() {
+        let key = generate_aes256_key().unwrap();
+        let message = b"totally awesome message";
+        let (cipher_text, iv, tag) = aes_gcm_encrypt_with_aad(message, &key, b"context").unwrap();
+        let message2 = aes_gcm_decrypt_with_aad(&cipher_text, &iv, &tag, &key, b"context").unwrap();
+        assert_eq!(message[..], message2[..]);
+        // The additional data is authenticated.
+        assert!(aes_gcm_decrypt_with_aad(&cipher_text, &iv, &tag, &key, b"other").is_err());
+        assert!(aes_gcm_decrypt(&cipher_text, &iv, &tag, &key).is_err());
+    }
+
     #[test]
     fn test_encrypt_decrypt() {
         let input = vec![0; 16];
@@ -511,6 +582,8 @@ mod tests {
                 16,
                 iv.as_ptr(),
                 tag.as_mut_ptr(),
+                std::ptr::null(),
+                0,
             );
             assert!(res);
             assert_ne!(out, input);
@@ -523,6 +596,8 @@ mod tests {
                 16,
                 iv.as_ptr(),
                 tag.as_ptr(),
+                std::ptr::null(),
+                0,
             );
             assert!(res);
             assert_eq!(out2, input);
@@ -591,6 +666,91 @@ mod tests {
         Ok(())
     }
 
+    // The same P-256 key encoded with a named curve and with explicit parameters.
+    const P256_SEC1_NAMED: &[u8] = &[
+        0x30, 0x77, 0x02, 0x01, 0x01, 0x04, 0x20, 0x84, 0x78, 0x5e, 0x18, 0xcb, 0x7c, 0x08, 0x2f,
+        0x49, 0x58, 0x31, 0xe8, 0x35, 0x48, 0x75, 0x7f, 0x27, 0x42, 0xf0, 0xd2, 0x11, 0x06, 0x7e,
+        0xbc, 0x6a, 0xf1, 0x3d, 0x4a, 0x95, 0x34, 0xd5, 0xaa, 0xa0, 0x0a, 0x06, 0x08, 0x2a, 0x86,
+        0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0xa1, 0x44, 0x03, 0x42, 0x00, 0x04, 0xda, 0x5d, 0x59,
+        0x27, 0xfc, 0x39, 0xcb, 0xa8, 0xa6, 0xcd, 0x57, 0x0b, 0xd5, 0xf6, 0x23, 0x46, 0x33, 0xe2,
+        0x6f, 0x9c, 0x56, 0x27, 0x72, 0xd6, 0xf2, 0x12, 0x08, 0x63, 0x15, 0x12, 0xe2, 0xdd, 0xe4,
+        0x7f, 0xc7, 0x0b, 0xdc, 0x90, 0x9e, 0xbd, 0xcb, 0x77, 0x28, 0x2a, 0x03, 0xb3, 0x74, 0x43,
+        0x81, 0xc6, 0x37, 0x86, 0xe5, 0x4a, 0x42, 0x80, 0x7c, 0x3d, 0x11, 0x43, 0x57, 0xd8, 0x1b,
+        0xe8,
+    ];
+    const P256_SEC1_EXPLICIT: &[u8] = &[
+        0x30, 0x82, 0x01, 0x68, 0x02, 0x01, 0x01, 0x04, 0x20, 0x84, 0x78, 0x5e, 0x18, 0xcb, 0x7c,
+        0x08, 0x2f, 0x49, 0x58, 0x31, 0xe8, 0x35, 0x48, 0x75, 0x7f, 0x27, 0x42, 0xf0, 0xd2, 0x11,
+        0x06, 0x7e, 0xbc, 0x6a, 0xf1, 0x3d, 0x4a, 0x95, 0x34, 0xd5, 0xaa, 0xa0, 0x81, 0xfa, 0x30,
+        0x81, 0xf7, 0x02, 0x01, 0x01, 0x30, 0x2c, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x01,
+        0x01, 0x02, 0x21, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x30, 0x5b, 0x04, 0x20, 0xff, 0xff, 0xff, 0xff, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfc, 0x04, 0x20, 0x5a,
+        0xc6, 0x35, 0xd8, 0xaa, 0x3a, 0x93, 0xe7, 0xb3, 0xeb, 0xbd, 0x55, 0x76, 0x98, 0x86, 0xbc,
+        0x65, 0x1d, 0x06, 0xb0, 0xcc, 0x53, 0xb0, 0xf6, 0x3b, 0xce, 0x3c, 0x3e, 0x27, 0xd2, 0x60,
+        0x4b, 0x03, 0x15, 0x00, 0xc4, 0x9d, 0x36, 0x08, 0x86, 0xe7, 0x04, 0x93, 0x6a, 0x66, 0x78,
+        0xe1, 0x13, 0x9d, 0x26, 0xb7, 0x81, 0x9f, 0x7e, 0x90, 0x04, 0x41, 0x04, 0x6b, 0x17, 0xd1,
+        0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40, 0xf2, 0x77, 0x03,
+        0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98, 0xc2, 0x96, 0x4f,
+        0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e, 0x16,
+        0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf, 0x51,
+        0xf5, 0x02, 0x21, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9,
+        0xca, 0xc2, 0xfc, 0x63, 0x25, 0x51, 0x02, 0x01, 0x01, 0xa1, 0x44, 0x03, 0x42, 0x00, 0x04,
+        0xda, 0x5d, 0x59, 0x27, 0xfc, 0x39, 0xcb, 0xa8, 0xa6, 0xcd, 0x57, 0x0b, 0xd5, 0xf6, 0x23,
+        0x46, 0x33, 0xe2, 0x6f, 0x9c, 0x56, 0x27, 0x72, 0xd6, 0xf2, 0x12, 0x08, 0x63, 0x15, 0x12,
+        0xe2, 0xdd, 0xe4, 0x7f, 0xc7, 0x0b, 0xdc, 0x90, 0x9e, 0xbd, 0xcb, 0x77, 0x28, 0x2a, 0x03,
+        0xb3, 0x74, 0x43, 0x81, 0xc6, 0x37, 0x86, 0xe5, 0x4a, 0x42, 0x80, 0x7c, 0x3d, 0x11, 0x43,
+        0x57, 0xd8, 0x1b, 0xe8,
+    ];
+    // A brainpoolP256r1 key with explicit parameters.
+    const BRAINPOOL_SEC1_EXPLICIT: &[u8] = &[
+        0x30, 0x82, 0x01, 0x51, 0x02, 0x01, 0x01, 0x04, 0x20, 0x8f, 0x5c, 0x74, 0xab, 0x51, 0x67,
+        0xb2, 0xe0, 0x86, 0x8a, 0x02, 0xf0, 0x8c, 0x0c, 0xdc, 0x96, 0xa6, 0xb2, 0xf5, 0x3a, 0x32,
+        0x64, 0x32, 0xb1, 0x11, 0x55, 0xfe, 0xf8, 0x25, 0x51, 0x8c, 0xdc, 0xa0, 0x81, 0xe3, 0x30,
+        0x81, 0xe0, 0x02, 0x01, 0x01, 0x30, 0x2c, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x01,
+        0x01, 0x02, 0x21, 0x00, 0xa9, 0xfb, 0x57, 0xdb, 0xa1, 0xee, 0xa9, 0xbc, 0x3e, 0x66, 0x0a,
+        0x90, 0x9d, 0x83, 0x8d, 0x72, 0x6e, 0x3b, 0xf6, 0x23, 0xd5, 0x26, 0x20, 0x28, 0x20, 0x13,
+        0x48, 0x1d, 0x1f, 0x6e, 0x53, 0x77, 0x30, 0x44, 0x04, 0x20, 0x7d, 0x5a, 0x09, 0x75, 0xfc,
+        0x2c, 0x30, 0x57, 0xee, 0xf6, 0x75, 0x30, 0x41, 0x7a, 0xff, 0xe7, 0xfb, 0x80, 0x55, 0xc1,
+        0x26, 0xdc, 0x5c, 0x6c, 0xe9, 0x4a, 0x4b, 0x44, 0xf3, 0x30, 0xb5, 0xd9, 0x04, 0x20, 0x26,
+        0xdc, 0x5c, 0x6c, 0xe9, 0x4a, 0x4b, 0x44, 0xf3, 0x30, 0xb5, 0xd9, 0xbb, 0xd7, 0x7c, 0xbf,
+        0x95, 0x84, 0x16, 0x29, 0x5c, 0xf7, 0xe1, 0xce, 0x6b, 0xcc, 0xdc, 0x18, 0xff, 0x8c, 0x07,
+        0xb6, 0x04, 0x41, 0x04, 0x8b, 0xd2, 0xae, 0xb9, 0xcb, 0x7e, 0x57, 0xcb, 0x2c, 0x4b, 0x48,
+        0x2f, 0xfc, 0x81, 0xb7, 0xaf, 0xb9, 0xde, 0x27, 0xe1, 0xe3, 0xbd, 0x23, 0xc2, 0x3a, 0x44,
+        0x53, 0xbd, 0x9a, 0xce, 0x32, 0x62, 0x54, 0x7e, 0xf8, 0x35, 0xc3, 0xda, 0xc4, 0xfd, 0x97,
+        0xf8, 0x46, 0x1a, 0x14, 0x61, 0x1d, 0xc9, 0xc2, 0x77, 0x45, 0x13, 0x2d, 0xed, 0x8e, 0x54,
+        0x5c, 0x1d, 0x54, 0xc7, 0x2f, 0x04, 0x69, 0x97, 0x02, 0x21, 0x00, 0xa9, 0xfb, 0x57, 0xdb,
+        0xa1, 0xee, 0xa9, 0xbc, 0x3e, 0x66, 0x0a, 0x90, 0x9d, 0x83, 0x8d, 0x71, 0x8c, 0x39, 0x7a,
+        0xa3, 0xb5, 0x61, 0xa6, 0xf7, 0x90, 0x1e, 0x0e, 0x82, 0x97, 0x48, 0x56, 0xa7, 0x02, 0x01,
+        0x01, 0xa1, 0x44, 0x03, 0x42, 0x00, 0x04, 0x06, 0xc0, 0xff, 0x93, 0xb4, 0x03, 0x87, 0xe4,
+        0x3d, 0xd8, 0x40, 0x71, 0xbc, 0xf5, 0x6d, 0x88, 0x11, 0x42, 0xd9, 0xbc, 0x83, 0xfc, 0xc6,
+        0xd1, 0x30, 0xc9, 0x43, 0x82, 0xcc, 0xfa, 0x9a, 0x43, 0x84, 0xcd, 0x05, 0x28, 0xde, 0xe2,
+        0x1d, 0xc5, 0xd1, 0xfa, 0x20, 0x4f, 0x3f, 0x25, 0x2b, 0x79, 0xa6, 0xa8, 0xa6, 0x19, 0x3c,
+        0xf0, 0x04, 0xc8, 0x8e, 0xa5, 0x82, 0xf0, 0x97, 0x73, 0x62, 0x8e,
+    ];
+
+    #[test]
+    fn test_ec_key_normalize_private_key() -> Result<(), Error> {
+        let named = ec_key_normalize_private_key(P256_SEC1_NAMED)?;
+        let explicit = ec_key_normalize_private_key(P256_SEC1_EXPLICIT)?;
+        assert_eq!(&named[..], &explicit[..]);
+        // Normalized keys are PKCS#8 and stay as they are.
+        assert_eq!(&ec_key_normalize_private_key(&named)?[..], &named[..]);
+
+        assert_eq!(
+            ec_key_normalize_private_key(BRAINPOOL_SEC1_EXPLICIT).map(|_| ()),
+            Err(Error::ECKEYUnsupportedCurve)
+        );
+        assert_eq!(
+            ec_key_normalize_private_key(b"not a key").map(|_| ()),
+            Err(Error::ECKEYParsePrivateKeyFailed)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_hmac_sha256() {
         let key = b"This is the key";
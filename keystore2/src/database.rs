@@ -41,16 +41,21 @@
 //! from the database module these functions take permission check
 //! callbacks.
 
+mod discard;
 mod perboot;
 pub(crate) mod utils;
 mod versioning;
+mod views;
 
 #[cfg(test)]
 pub mod tests;
 
+pub use perboot::PerbootScrubReport;
+
 use crate::gc::Gc;
 use crate::impl_metadata; // This is in database/utils.rs
 use crate::key_parameter::{KeyParameter, KeyParameterValue, Tag};
+use crate::key_rotation::previous_key_alias;
 use crate::ks_err;
 use crate::permission::KeyPermSet;
 use crate::utils::{get_current_time_in_milliseconds, watchdog as wd, AID_USER_OFFSET};
@@ -59,7 +64,8 @@ use crate::{
     super_key::SuperKeyType,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
+    Algorithm::Algorithm, HardwareAuthToken::HardwareAuthToken,
+    HardwareAuthenticatorType::HardwareAuthenticatorType, KeyOrigin::KeyOrigin,
     SecurityLevel::SecurityLevel,
 };
 use android_security_metrics::aidl::android::security::metrics::{
@@ -74,7 +80,7 @@ use std::{convert::TryFrom, convert::TryInto, ops::Deref, sync::LazyLock, time::
 use utils as db_utils;
 use utils::SqlField;
 
-use keystore2_crypto::ZVec;
+use keystore2_crypto::{hmac_sha256, ZVec};
 use log::error;
 #[cfg(not(test))]
 use rand::prelude::random;
@@ -88,7 +94,7 @@ use rusqlite::{
 };
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     path::Path,
     sync::{Arc, Condvar, Mutex},
     time::{Duration, SystemTime},
@@ -147,6 +153,37 @@ impl_metadata!(
         AttestationRawPubKey(Vec<u8>) with accessor attestation_raw_pub_key,
         /// SEC1 public key for ECDH encryption
         Sec1PublicKey(Vec<u8>) with accessor sec1_public_key,
+        /// Build fingerprint of the system that created the key.
+        CreationBuildFingerprint(String) with accessor creation_build_fingerprint,
+        /// OS patch level reported by KeyMint when the key was created.
+        CreationOsPatchLevel(i32) with accessor creation_os_patch_level,
+        /// Vendor patch level reported by KeyMint when the key was created.
+        CreationVendorPatchLevel(i32) with accessor creation_vendor_patch_level,
+        /// Boot patch level reported by KeyMint when the key was created.
+        CreationBootPatchLevel(i32) with accessor creation_boot_patch_level,
+        /// The kind of attestation key that was used to attest the key at creation.
+        CreationAttestationSource(AttestationSource) with accessor creation_attestation_source,
+        /// Version number of the KeyMint or Keymaster device that created the key. Versions
+        /// below 100 indicate a Keymaster device accessed through the km_compat translation layer.
+        CreationKmVersion(i32) with accessor creation_km_version,
+        /// Expiration date imposed on the key by the maximum key validity policy at creation.
+        MaxValidityExpirationDate(DateTime) with accessor max_validity_expiration_date,
+        /// Date at which KeyMint first reported the key as permanently invalidated.
+        InvalidationDate(DateTime) with accessor invalidation_date,
+        /// Date at which the idle integrity check first found the key blob to be rejected by
+        /// KeyMint.
+        IntegrityCheckFailureDate(DateTime) with accessor integrity_check_failure_date,
+        /// KeyOrigin of a key restored from a backup vault on the device it was backed up from.
+        RestoredOrigin(i32) with accessor restored_origin,
+        /// Number of operations that were started with the key since usage was recorded.
+        OperationCount(i64) with accessor operation_count,
+        /// Date at which an operation was last started with the key.
+        LastUsedDate(DateTime) with accessor last_used_date,
+        /// The key is deleted when the process that created it dies, see `session_keys`.
+        SessionBound(bool) with accessor session_bound,
+        /// Date until which a key that was replaced by a rotation is retained for its grants,
+        /// see `key_rotation`.
+        RetainedUntil(DateTime) with accessor retained_until,
         //  --- ADD NEW META DATA FIELDS HERE ---
         // For backwards compatibility add new entries only to
         // end of this list and above this comment.
@@ -304,7 +341,8 @@ impl FromSql for KeyType {
 }
 
 /// Uuid representation that can be stored in the database.
-/// Right now it can only be initialized from SecurityLevel.
+/// Right now it can only be initialized from SecurityLevel, optionally together with the
+/// name of a KeyMint instance of that security level.
 /// Once KeyMint provides a UUID type a corresponding From impl shall be added.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Uuid([u8; 16]);
@@ -323,6 +361,25 @@ impl From<SecurityLevel> for Uuid {
     }
 }
 
+impl Uuid {
+    /// Maximum length of a KeyMint instance name that can be encoded in a uuid.
+    const MAX_INSTANCE_NAME_LEN: usize = 12;
+
+    /// Returns the uuid of the KeyMint instance with the given name and security level.
+    /// The name is encoded in the leading bytes, so the default instance of a security level,
+    /// which has an empty name, keeps the uuid `Uuid::from(sec_level)`. Returns None if the
+    /// name is longer than 12 bytes.
+    pub fn for_keymint_instance(sec_level: SecurityLevel, name: &str) -> Option<Self> {
+        let name = name.as_bytes();
+        if name.len() > Self::MAX_INSTANCE_NAME_LEN {
+            return None;
+        }
+        let mut uuid = Self::from(sec_level);
+        uuid.0[..name.len()].copy_from_slice(name);
+        Some(uuid)
+    }
+}
+
 impl ToSql for Uuid {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
         self.0.to_sql()
@@ -500,6 +557,188 @@ impl FromSql for KeyLifeCycle {
     }
 }
 
+/// The number of milliseconds before the certificate expiration date of a key at which the key
+/// enters `KeyLifecycleState::ExpiringCert`.
+pub const EXPIRING_CERT_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+/// The lifecycle state of a key entry as seen by clients. It is maintained by the database from
+/// the state of the key entry and its metadata, so callers must not derive it themselves.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum KeyLifecycleState {
+    /// The key entry has been created but is not fully populated yet.
+    Provisioning,
+    /// The key is fully populated and usable by clients.
+    #[default]
+    Live,
+    /// The key is usable, but its certificate expires within `EXPIRING_CERT_WINDOW_MS`.
+    ExpiringCert,
+    /// KeyMint reported the key as permanently invalidated, e.g., because the biometric
+    /// enrollment it is bound to has changed. The key can no longer be used.
+    Invalidated,
+    /// The key was replaced by a rotation and is retained until `RetainedUntil`. It can only be
+    /// used through its grants.
+    Retained,
+    /// The key entry has been deleted and awaits garbage collection.
+    Tombstoned,
+}
+
+impl KeyLifecycleState {
+    /// Derives the lifecycle state of a key entry from the state column of the key entry and
+    /// its metadata at time `now`.
+    fn derive(state: KeyLifeCycle, metadata: &KeyMetaData, now: DateTime) -> Self {
+        match state {
+            KeyLifeCycle::Existing => Self::Provisioning,
+            KeyLifeCycle::Unreferenced => Self::Tombstoned,
+            KeyLifeCycle::Live if metadata.invalidation_date().is_some() => Self::Invalidated,
+            KeyLifeCycle::Live if metadata.retained_until().is_some_and(|until| *until <= now) => {
+                Self::Tombstoned
+            }
+            KeyLifeCycle::Live if metadata.retained_until().is_some() => Self::Retained,
+            KeyLifeCycle::Live => {
+                let cert_expiration = [
+                    metadata.attestation_expiration_date(),
+                    metadata.max_validity_expiration_date(),
+                ]
+                .into_iter()
+                .flatten()
+                .min();
+                match cert_expiration {
+                    Some(expiration)
+                        if expiration.to_millis_epoch().saturating_sub(now.to_millis_epoch())
+                            < EXPIRING_CERT_WINDOW_MS =>
+                    {
+                        Self::ExpiringCert
+                    }
+                    _ => Self::Live,
+                }
+            }
+        }
+    }
+}
+
+/// Describes the attestation key that was used to attest a key at creation time.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum AttestationSource {
+    /// No attestation was requested.
+    None,
+    /// The key was attested by the factory provisioned attestation key of the KeyMint device.
+    Factory,
+    /// The key was attested by a remotely provisioned attestation key.
+    Rkp,
+    /// The key was attested by an attestation key provided by the caller.
+    UserGenerated,
+}
+
+impl ToSql for AttestationSource {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        match self {
+            Self::None => Ok(ToSqlOutput::Owned(Value::Integer(0))),
+            Self::Factory => Ok(ToSqlOutput::Owned(Value::Integer(1))),
+            Self::Rkp => Ok(ToSqlOutput::Owned(Value::Integer(2))),
+            Self::UserGenerated => Ok(ToSqlOutput::Owned(Value::Integer(3))),
+        }
+    }
+}
+
+impl FromSql for AttestationSource {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        match i64::column_result(value)? {
+            0 => Ok(AttestationSource::None),
+            1 => Ok(AttestationSource::Factory),
+            2 => Ok(AttestationSource::Rkp),
+            3 => Ok(AttestationSource::UserGenerated),
+            v => Err(FromSqlError::OutOfRange(v)),
+        }
+    }
+}
+
+/// Structured record of the circumstances under which a key was created.
+/// Fields are None for keys created before provenance was recorded.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct KeyProvenance {
+    /// Build fingerprint of the system that created the key.
+    pub build_fingerprint: Option<String>,
+    /// OS patch level at creation.
+    pub os_patch_level: Option<i32>,
+    /// Vendor patch level at creation.
+    pub vendor_patch_level: Option<i32>,
+    /// Boot patch level at creation.
+    pub boot_patch_level: Option<i32>,
+    /// The kind of attestation key used at creation.
+    pub attestation_source: Option<AttestationSource>,
+    /// Version number of the KeyMint or Keymaster device that created the key.
+    pub km_version: Option<i32>,
+}
+
+impl KeyProvenance {
+    /// Returns true if the key was created by a Keymaster device that was accessed through
+    /// the km_compat translation layer. Returns None if this is unknown.
+    pub fn km_compat_translated(&self) -> Option<bool> {
+        self.km_version.map(|v| v < 100)
+    }
+
+    /// Adds the provenance information to the given key metadata.
+    pub fn add_to_metadata(&self, metadata: &mut KeyMetaData) {
+        if let Some(v) = &self.build_fingerprint {
+            metadata.add(KeyMetaEntry::CreationBuildFingerprint(v.clone()));
+        }
+        if let Some(v) = self.os_patch_level {
+            metadata.add(KeyMetaEntry::CreationOsPatchLevel(v));
+        }
+        if let Some(v) = self.vendor_patch_level {
+            metadata.add(KeyMetaEntry::CreationVendorPatchLevel(v));
+        }
+        if let Some(v) = self.boot_patch_level {
+            metadata.add(KeyMetaEntry::CreationBootPatchLevel(v));
+        }
+        if let Some(v) = self.attestation_source {
+            metadata.add(KeyMetaEntry::CreationAttestationSource(v));
+        }
+        if let Some(v) = self.km_version {
+            metadata.add(KeyMetaEntry::CreationKmVersion(v));
+        }
+    }
+}
+
+/// How often and when a key was used, as far as keystore recorded it.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct KeyUsageStats {
+    /// The number of operations that were started with the key.
+    pub operation_count: i64,
+    /// The date at which an operation was last started with the key, if any.
+    pub last_used: Option<DateTime>,
+}
+
+impl KeyUsageStats {
+    /// Adds the usage recorded in `other`.
+    pub fn merge(&mut self, other: &KeyUsageStats) {
+        self.operation_count += other.operation_count;
+        self.last_used = self.last_used.max(other.last_used);
+    }
+}
+
+impl KeyMetaData {
+    /// Returns the usage statistics recorded in this key metadata.
+    pub fn usage_stats(&self) -> KeyUsageStats {
+        KeyUsageStats {
+            operation_count: self.operation_count().copied().unwrap_or(0),
+            last_used: self.last_used_date().copied(),
+        }
+    }
+
+    /// Returns the provenance information recorded in this key metadata.
+    pub fn provenance(&self) -> KeyProvenance {
+        KeyProvenance {
+            build_fingerprint: self.creation_build_fingerprint().cloned(),
+            os_patch_level: self.creation_os_patch_level().copied(),
+            vendor_patch_level: self.creation_vendor_patch_level().copied(),
+            boot_patch_level: self.creation_boot_patch_level().copied(),
+            attestation_source: self.creation_attestation_source().copied(),
+            km_version: self.creation_km_version().copied(),
+        }
+    }
+}
+
 /// Keys have a KeyMint blob component and optional public certificate and
 /// certificate chain components.
 /// KeyEntryLoadBits is a bitmap that indicates to `KeystoreDB::load_key_entry`
@@ -668,6 +907,7 @@ pub struct KeyEntry {
     parameters: Vec<KeyParameter>,
     metadata: KeyMetaData,
     pure_cert: bool,
+    lifecycle_state: KeyLifecycleState,
 }
 
 impl KeyEntry {
@@ -707,11 +947,36 @@ impl KeyEntry {
     pub fn metadata(&self) -> &KeyMetaData {
         &self.metadata
     }
+    /// Extracts the key metadata of this key entry.
+    pub fn take_metadata(&mut self) -> KeyMetaData {
+        std::mem::take(&mut self.metadata)
+    }
     /// This returns true if the entry is a pure certificate entry with no
     /// private key component.
     pub fn pure_cert(&self) -> bool {
         self.pure_cert
     }
+    /// Returns the lifecycle state of the key entry at the time it was loaded.
+    pub fn lifecycle_state(&self) -> KeyLifecycleState {
+        self.lifecycle_state
+    }
+}
+
+/// A change to the grants of a key, see `KeystoreDB::update_subcomponents_and_grants`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GrantUpdate {
+    /// Grants `access_vector` to `grantee_uid`, replacing any existing grant.
+    Grant {
+        /// The uid of the grantee.
+        grantee_uid: u32,
+        /// The permissions granted.
+        access_vector: KeyPermSet,
+    },
+    /// Revokes the grant of `grantee_uid`, if any.
+    Ungrant {
+        /// The uid of the grantee.
+        grantee_uid: u32,
+    },
 }
 
 /// Indicates the sub component of a key entry for persistent storage.
@@ -724,6 +989,10 @@ impl SubComponentType {
     pub const CERT: SubComponentType = Self(1);
     /// Persistent identifier for a certificate chain blob.
     pub const CERT_CHAIN: SubComponentType = Self(2);
+    /// Persistent identifier for a reference to a certificate chain in the `certchain` table.
+    /// The blob holds the digest of the certificate chain. Certificate chains are stored this
+    /// way transparently, callers always use `CERT_CHAIN`.
+    pub const CERT_CHAIN_REF: SubComponentType = Self(3);
 }
 
 impl ToSql for SubComponentType {
@@ -766,12 +1035,34 @@ impl<T> DoGc<T> for Result<T> {
     }
 }
 
+/// The labels that callers attach to their key entries, by name. Keystore does not interpret
+/// them.
+pub type EntryMetadata = BTreeMap<String, Vec<u8>>;
+
+/// Selects keys by their characteristics, see `KeystoreDB::list_past_alias_filtered`. A key
+/// matches if it meets all conditions that are set.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct KeyEntryFilter {
+    /// The algorithm of the key.
+    pub algorithm: Option<Algorithm>,
+    /// The origin of the key.
+    pub origin: Option<KeyOrigin>,
+    /// Whether the key is bound to user authentication, i.e., has a user secure id.
+    pub auth_bound: Option<bool>,
+    /// The earliest creation date of the key, inclusive.
+    pub created_after: Option<DateTime>,
+    /// The latest creation date of the key, exclusive.
+    pub created_before: Option<DateTime>,
+}
+
 /// KeystoreDB wraps a connection to an SQLite database and tracks its
 /// ownership. It also implements all of Keystore 2.0's database functionality.
 pub struct KeystoreDB {
     conn: Connection,
     gc: Option<Arc<Gc>>,
     perboot: Arc<perboot::PerbootDB>,
+    // Whether free pages are released to the file system after key blobs were deleted.
+    discard_free_pages: bool,
 }
 
 /// Database representation of the monotonic time retrieved from the system call clock_gettime with
@@ -780,9 +1071,9 @@ pub struct KeystoreDB {
 pub struct BootTime(i64);
 
 impl BootTime {
-    /// Constructs a new BootTime
+    /// Constructs a new BootTime. Tests can advance the clock through IKeystoreTestHooks.
     pub fn now() -> Self {
-        Self(get_current_time_in_milliseconds())
+        Self(get_current_time_in_milliseconds() + crate::test_hooks::clock_offset_ms())
     }
 
     /// Returns the value of BootTime in milliseconds as i64
@@ -870,8 +1161,18 @@ pub struct SupersededBlob {
 
 impl KeystoreDB {
     const UNASSIGNED_KEY_ID: i64 = -1i64;
-    const CURRENT_DB_VERSION: u32 = 1;
-    const UPGRADERS: &'static [fn(&Transaction) -> Result<u32>] = &[Self::from_0_to_1];
+    const CURRENT_DB_VERSION: u32 = 3;
+    const UPGRADERS: &'static [fn(&Transaction) -> Result<u32>] =
+        &[Self::from_0_to_1, Self::from_1_to_2, Self::from_2_to_3];
+
+    /// Certificate chains of at least this size are stored only once in the `certchain` table
+    /// and referenced by their digest. Many keys attested by the same key share their
+    /// intermediate certificates. Smaller chains are stored inline, because a reference would
+    /// not save much.
+    const MIN_SHARED_CERT_CHAIN_SIZE: usize = 256;
+
+    /// Key used to compute the digest that identifies a shared certificate chain.
+    const CERT_CHAIN_DIGEST_KEY: &'static [u8] = b"keystore2 certificate chain";
 
     /// Name of the file that holds the cross-boot persistent database.
     pub const PERSISTENT_DB_FILENAME: &'static str = "persistent.sqlite";
@@ -887,7 +1188,12 @@ impl KeystoreDB {
         let persistent_path = Self::make_persistent_path(db_root)?;
         let conn = Self::make_connection(&persistent_path)?;
 
-        let mut db = Self { conn, gc, perboot: perboot::PERBOOT_DB.clone() };
+        let discard_free_pages = discard::enabled();
+        if discard_free_pages {
+            discard::setup(&conn).context(ks_err!("Failed to set up discarding of free pages."))?;
+        }
+
+        let mut db = Self { conn, gc, perboot: perboot::PERBOOT_DB.clone(), discard_free_pages };
         db.with_transaction(Immediate("TX_new"), |tx| {
             versioning::upgrade_database(tx, Self::CURRENT_DB_VERSION, Self::UPGRADERS)
                 .context(ks_err!("KeystoreDB::new: trying to upgrade database."))?;
@@ -896,6 +1202,25 @@ impl KeystoreDB {
         Ok(db)
     }
 
+    /// This will create a new read-only database connection to the persistent database in
+    /// the given directory. It neither upgrades nor initializes any tables, so the database
+    /// must have been opened with `KeystoreDB::new` before. When the database is in WAL
+    /// journal mode, read transactions on this connection operate on a snapshot and never
+    /// block writers on other connections. This makes it suitable for heavy read-only
+    /// queries such as listing entries or gathering storage statistics.
+    /// Like `KeystoreDB::new`, the connection must not be shared between threads.
+    pub fn new_read_only(db_root: &Path) -> Result<Self> {
+        let _wp = wd::watch("KeystoreDB::new_read_only");
+
+        let mut persistent_path = Self::make_persistent_path(db_root)?;
+        persistent_path.push_str("&mode=ro");
+        let conn = Self::make_connection(&persistent_path)?;
+        conn.execute("PRAGMA query_only = ON;", params![])
+            .context(ks_err!("Failed to make connection query only."))?;
+
+        Ok(Self { conn, gc: None, perboot: perboot::PERBOOT_DB.clone(), discard_free_pages: false })
+    }
+
     // This upgrade function deletes all MAX_BOOT_LEVEL keys, that were generated before
     // cryptographic binding to the boot level keys was implemented.
     fn from_0_to_1(tx: &Transaction) -> Result<u32> {
@@ -916,6 +1241,43 @@ impl KeystoreDB {
         Ok(1)
     }
 
+    // This upgrade function moves large certificate chains into the `certchain` table, so that
+    // identical certificate chains are stored only once.
+    fn from_1_to_2(tx: &Transaction) -> Result<u32> {
+        Self::init_cert_chain_table(tx).context(ks_err!())?;
+        let chains: Vec<(i64, Vec<u8>)> = {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT id, blob FROM persistent.blobentry
+                     WHERE subcomponent_type = ? AND LENGTH(blob) >= ?;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let rows = stmt
+                .query_map(
+                    params![SubComponentType::CERT_CHAIN, Self::MIN_SHARED_CERT_CHAIN_SIZE],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .context(ks_err!("Failed to query certificate chains."))?;
+            rows.collect::<Result<_, rusqlite::Error>>()
+                .context(ks_err!("Failed to extract certificate chains."))?
+        };
+        for (blob_id, chain) in chains {
+            let digest = Self::insert_cert_chain(tx, &chain).context(ks_err!())?;
+            tx.execute(
+                "UPDATE persistent.blobentry SET subcomponent_type = ?, blob = ? WHERE id = ?;",
+                params![SubComponentType::CERT_CHAIN_REF, digest, blob_id],
+            )
+            .context(ks_err!("Failed to replace certificate chain {}.", blob_id))?;
+        }
+        Ok(2)
+    }
+
+    // This upgrade function adds the diagnostic views, see `views`.
+    fn from_2_to_3(tx: &Transaction) -> Result<u32> {
+        views::recreate(tx).context(ks_err!())?;
+        Ok(3)
+    }
+
     fn init_tables(tx: &Transaction) -> Result<()> {
         tx.execute(
             "CREATE TABLE IF NOT EXISTS persistent.keyentry (
@@ -1023,6 +1385,29 @@ impl KeystoreDB {
         )
         .context("Failed to initialize \"grant\" table.")?;
 
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS persistent.entrymetadata (
+                    keyentryid INTEGER,
+                    name TEXT,
+                    value BLOB,
+                    UNIQUE (keyentryid, name));",
+            [],
+        )
+        .context("Failed to initialize \"entrymetadata\" table.")?;
+
+        Self::init_cert_chain_table(tx)?;
+        views::create(tx).context("Failed to create diagnostic views.")
+    }
+
+    fn init_cert_chain_table(tx: &Transaction) -> Result<()> {
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS persistent.certchain (
+                    id INTEGER PRIMARY KEY,
+                    digest BLOB UNIQUE,
+                    chain BLOB);",
+            [],
+        )
+        .context("Failed to initialize \"certchain\" table.")?;
         Ok(())
     }
 
@@ -1113,6 +1498,66 @@ impl KeystoreDB {
         )
     }
 
+    /// Estimates the storage that can be reclaimed by garbage collection and compaction.
+    /// `size` is the number of bytes held by key blobs waiting for the garbage collector plus
+    /// the free pages of the database; `unused_size` is the size of the free pages alone, which
+    /// only compaction can give back to the file system.
+    fn get_reclaimable_size(&mut self) -> Result<StorageStats> {
+        let (blob_bytes, free_bytes): (i64, i64) =
+            self.with_transaction(TransactionBehavior::Deferred, |tx| {
+                tx.query_row(
+                    "SELECT
+                         (SELECT COALESCE(SUM(LENGTH(blob)), 0) FROM persistent.blobentry
+                          WHERE subcomponent_type = ?
+                          AND (
+                              id NOT IN (
+                                  SELECT MAX(id) FROM persistent.blobentry
+                                  WHERE subcomponent_type = ?
+                                  GROUP BY keyentryid, subcomponent_type
+                              )
+                          OR keyentryid NOT IN (SELECT id FROM persistent.keyentry)
+                          OR keyentryid IN (SELECT id FROM persistent.keyentry WHERE state = ?)
+                          )),
+                         freelist_count * page_size
+                     FROM pragma_page_size('persistent'), persistent.pragma_freelist_count();",
+                    params![
+                        SubComponentType::KEY_BLOB,
+                        SubComponentType::KEY_BLOB,
+                        KeyLifeCycle::Unreferenced
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .context(ks_err!("get_storage_stat: Error computing reclaimable size."))
+                .no_gc()
+            })?;
+        let clamp = |v: i64| i32::try_from(v).unwrap_or(i32::MAX);
+        Ok(StorageStats {
+            storage_type: MetricsStorage::RECLAIMABLE,
+            size: clamp(blob_bytes + free_bytes),
+            unused_size: clamp(free_bytes),
+        })
+    }
+
+    /// Rebuilds the persistent database file to return free pages to the file system.
+    /// This is expensive and requires exclusive access to the database, so it should only be
+    /// used when storage is critically low.
+    pub fn compact(&mut self) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::compact");
+        loop {
+            match self.conn.execute("VACUUM persistent;", params![]).context(ks_err!()) {
+                Ok(_) => break Ok(()),
+                Err(e) => {
+                    if Self::is_locked_error(&e) {
+                        std::thread::sleep(DB_BUSY_RETRY_INTERVAL);
+                        continue;
+                    } else {
+                        return Err(e).context(ks_err!("Failed to vacuum persistent database."));
+                    }
+                }
+            }
+        }
+    }
+
     /// Fetches a storage statistics atom for a given storage type. For storage
     /// types that map to a table, information about the table's storage is
     /// returned. Requests for storage types that are not DB tables return None.
@@ -1166,6 +1611,7 @@ impl KeystoreDB {
             MetricsStorage::BLOB_METADATA_BLOB_ENTRY_ID_INDEX => {
                 self.get_table_size(storage_type, "persistent", "blobmetadata_blobentryid_index")
             }
+            MetricsStorage::RECLAIMABLE => self.get_reclaimable_size(),
             _ => Err(anyhow::Error::msg(format!("Unsupported storage type: {}", storage_type.0))),
         }
     }
@@ -1261,11 +1707,152 @@ impl KeystoreDB {
             )
             .context("Trying to purge superseded blobs.")?;
 
+            // Shared certificate chains are reference counted by the blob entries referring to
+            // them. Remove the ones that are no longer referenced.
+            tx.execute(
+                "DELETE FROM persistent.certchain
+                 WHERE digest NOT IN (
+                     SELECT blob FROM persistent.blobentry WHERE subcomponent_type = ?
+                 );",
+                params![SubComponentType::CERT_CHAIN_REF],
+            )
+            .context("Trying to purge unreferenced certificate chains.")?;
+
             Ok(vec![]).no_gc()
         })
         .context(ks_err!())
     }
 
+    /// Releases the free pages of the persistent database to the file system, so that it can
+    /// discard the blocks that held deleted key blobs. Returns the number of pages that were
+    /// released. Does nothing unless discarding deleted blobs is enabled, see the `discard`
+    /// module.
+    pub fn release_free_pages(&mut self) -> Result<u32> {
+        let _wp = wd::watch("KeystoreDB::release_free_pages");
+        if !self.discard_free_pages {
+            return Ok(0);
+        }
+        let released = self.with_transaction(Immediate("TX_release_free_pages"), |tx| {
+            discard::release_free_pages(tx).context("Trying to release free pages.").no_gc()
+        })?;
+        if released != 0 {
+            // In WAL mode, the database file is truncated when the log is checkpointed.
+            // Returns (busy, log frames, checkpointed frames). If the checkpoint is blocked by
+            // readers, the file is truncated by a later checkpoint.
+            self.conn
+                .query_row("PRAGMA persistent.wal_checkpoint(TRUNCATE);", params![], |_| Ok(()))
+                .context(ks_err!("Failed to checkpoint."))?;
+        }
+        Ok(released)
+    }
+
+    /// Returns the number of key blobs that are waiting to be processed by the garbage
+    /// collector. These are superseded key blobs and key blobs of unreferenced key entries.
+    pub fn count_superseded_blobs(&mut self) -> Result<usize> {
+        let _wp = wd::watch("KeystoreDB::count_superseded_blobs");
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            tx.query_row(
+                "SELECT COUNT(id) FROM persistent.blobentry
+                 WHERE subcomponent_type = ?
+                 AND (
+                     id NOT IN (
+                         SELECT MAX(id) FROM persistent.blobentry
+                         WHERE subcomponent_type = ?
+                         GROUP BY keyentryid, subcomponent_type
+                     )
+                 OR keyentryid NOT IN (SELECT id FROM persistent.keyentry)
+                 OR keyentryid IN (SELECT id FROM persistent.keyentry WHERE state = ?)
+                 );",
+                params![
+                    SubComponentType::KEY_BLOB,
+                    SubComponentType::KEY_BLOB,
+                    KeyLifeCycle::Unreferenced
+                ],
+                |row| row.get(0),
+            )
+            .context(ks_err!("Failed to count superseded blobs."))
+            .no_gc()
+        })
+    }
+
+    /// Returns the ids of up to `limit` live key entries whose current key blob is encrypted by the
+    /// super key with the given id, in ascending order.
+    pub fn get_key_ids_encrypted_by(
+        &mut self,
+        super_key_id: i64,
+        limit: usize,
+    ) -> Result<Vec<i64>> {
+        let _wp = wd::watch("KeystoreDB::get_key_ids_encrypted_by");
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT keyentryid FROM persistent.blobentry
+                     WHERE subcomponent_type = ?
+                     AND id IN (
+                         SELECT MAX(id) FROM persistent.blobentry
+                         WHERE subcomponent_type = ?
+                         GROUP BY keyentryid
+                     )
+                     AND id IN (
+                         SELECT blobentryid FROM persistent.blobmetadata
+                         WHERE tag = ? AND data = ?
+                     )
+                     AND keyentryid IN (SELECT id FROM persistent.keyentry WHERE state = ?)
+                     ORDER BY keyentryid ASC LIMIT ?;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let mut rows = stmt
+                .query(params![
+                    SubComponentType::KEY_BLOB,
+                    SubComponentType::KEY_BLOB,
+                    BlobMetaData::EncryptedBy,
+                    super_key_id,
+                    KeyLifeCycle::Live,
+                    limit as i64,
+                ])
+                .context(ks_err!("Failed to query."))?;
+            let mut key_ids: Vec<i64> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                key_ids.push(row.get(0).context("Failed to read key id.")?);
+                Ok(())
+            })
+            .context(ks_err!())?;
+            Ok(key_ids).no_gc()
+        })
+    }
+
+    /// Returns the number of live key entries whose current key blob is encrypted by the super key
+    /// with the given id.
+    pub fn count_keys_encrypted_by(&mut self, super_key_id: i64) -> Result<usize> {
+        let _wp = wd::watch("KeystoreDB::count_keys_encrypted_by");
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            tx.query_row(
+                "SELECT COUNT(keyentryid) FROM persistent.blobentry
+                 WHERE subcomponent_type = ?
+                 AND id IN (
+                     SELECT MAX(id) FROM persistent.blobentry
+                     WHERE subcomponent_type = ?
+                     GROUP BY keyentryid
+                 )
+                 AND id IN (
+                     SELECT blobentryid FROM persistent.blobmetadata
+                     WHERE tag = ? AND data = ?
+                 )
+                 AND keyentryid IN (SELECT id FROM persistent.keyentry WHERE state = ?);",
+                params![
+                    SubComponentType::KEY_BLOB,
+                    SubComponentType::KEY_BLOB,
+                    BlobMetaData::EncryptedBy,
+                    super_key_id,
+                    KeyLifeCycle::Live,
+                ],
+                |row| row.get(0),
+            )
+            .context(ks_err!("Failed to count keys."))
+            .no_gc()
+        })
+    }
+
     /// This maintenance function should be called only once before the database is used for the
     /// first time. It restores the invariant that `KeyLifeCycle::Existing` is a transient state.
     /// The function transitions all key entries from Existing to Unreferenced unconditionally and
@@ -1362,6 +1949,31 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
+    /// Replaces the key blobs of the given super keys in a single transaction, e.g., after they
+    /// were encrypted with a new password. The super keys keep their ids, which the blobs that they
+    /// encrypt refer to. The replaced blobs are deleted by the garbage collector.
+    pub fn replace_super_key_blobs(
+        &mut self,
+        blobs: &[(&KeyIdGuard, &[u8], &BlobMetaData)],
+    ) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::replace_super_key_blobs");
+
+        self.with_transaction(Immediate("TX_replace_super_key_blobs"), |tx| {
+            for (key_id, blob, blob_metadata) in blobs {
+                Self::set_blob_internal(
+                    tx,
+                    key_id.0,
+                    SubComponentType::KEY_BLOB,
+                    Some(blob),
+                    Some(blob_metadata),
+                )
+                .context(ks_err!("Failed to store key blob of super key {}.", key_id.0))?;
+            }
+            Ok(()).need_gc()
+        })
+        .context(ks_err!())
+    }
+
     /// Loads super key of a given user, if exists
     pub fn load_super_key(
         &mut self,
@@ -1530,6 +2142,20 @@ impl KeystoreDB {
     ) -> Result<()> {
         match (blob, sc_type) {
             (Some(blob), _) => {
+                let digest;
+                let (sc_type, blob) = if sc_type == SubComponentType::CERT_CHAIN {
+                    // A key has either an inline or a shared certificate chain. Remove the
+                    // previous one, so that the new one is not shadowed.
+                    Self::delete_cert_chain(tx, key_id)?;
+                    if blob.len() >= Self::MIN_SHARED_CERT_CHAIN_SIZE {
+                        digest = Self::insert_cert_chain(tx, blob)?;
+                        (SubComponentType::CERT_CHAIN_REF, digest.as_slice())
+                    } else {
+                        (sc_type, blob)
+                    }
+                } else {
+                    (sc_type, blob)
+                };
                 tx.execute(
                     "INSERT INTO persistent.blobentry
                      (subcomponent_type, keyentryid, blob) VALUES (?, ?, ?);",
@@ -1547,7 +2173,8 @@ impl KeystoreDB {
                         .context(ks_err!("Trying to store blob metadata."))?;
                 }
             }
-            (None, SubComponentType::CERT) | (None, SubComponentType::CERT_CHAIN) => {
+            (None, SubComponentType::CERT_CHAIN) => Self::delete_cert_chain(tx, key_id)?,
+            (None, SubComponentType::CERT) => {
                 tx.execute(
                     "DELETE FROM persistent.blobentry
                     WHERE subcomponent_type = ? AND keyentryid = ?;",
@@ -1563,6 +2190,31 @@ impl KeystoreDB {
         Ok(())
     }
 
+    /// Stores the given certificate chain in the `certchain` table unless an identical chain
+    /// is already stored there, and returns the digest that identifies it.
+    fn insert_cert_chain(tx: &Transaction, chain: &[u8]) -> Result<Vec<u8>> {
+        let digest = hmac_sha256(Self::CERT_CHAIN_DIGEST_KEY, chain)
+            .context(ks_err!("Failed to compute certificate chain digest."))?;
+        tx.execute(
+            "INSERT OR IGNORE INTO persistent.certchain (digest, chain) VALUES (?, ?);",
+            params![digest, chain],
+        )
+        .context(ks_err!("Failed to insert certificate chain."))?;
+        Ok(digest)
+    }
+
+    /// Removes the inline or shared certificate chain of the given key. Shared certificate
+    /// chains that are no longer referenced are removed by the garbage collector.
+    fn delete_cert_chain(tx: &Transaction, key_id: i64) -> Result<()> {
+        tx.execute(
+            "DELETE FROM persistent.blobentry
+            WHERE subcomponent_type IN (?, ?) AND keyentryid = ?;",
+            params![SubComponentType::CERT_CHAIN, SubComponentType::CERT_CHAIN_REF, key_id],
+        )
+        .context(ks_err!("Failed to delete certificate chain."))?;
+        Ok(())
+    }
+
     /// Inserts a collection of key parameters into the `persistent.keyparameter` table
     /// and associates them with the given `key_id`.
     #[cfg(test)]
@@ -1659,6 +2311,63 @@ impl KeystoreDB {
         Ok(updated != 0)
     }
 
+    /// Rebinds the live key with the given alias-domain-namespace tuple, if any, to
+    /// `<alias>.prev` and retains it until `until`. Its id, and with it its grants, stay the
+    /// same. A key that was retained under `<alias>.prev` before is unbound. Any other key bound
+    /// to `<alias>.prev` was created under that alias by the caller and is never replaced;
+    /// this fails with `ResponseCode::INVALID_ARGUMENT` instead.
+    /// Returns Ok(true) if a key was unbound as a hint to the garbage collector.
+    fn retain_previous_key(
+        tx: &Transaction,
+        alias: &str,
+        domain: &Domain,
+        namespace: &i64,
+        key_type: KeyType,
+        until: DateTime,
+    ) -> Result<bool> {
+        let find_live_key = |alias: &str| {
+            tx.query_row(
+                "SELECT id FROM persistent.keyentry
+                 WHERE alias = ? AND domain = ? AND namespace = ? AND key_type = ? AND state = ?;",
+                params![alias, domain.0 as u32, namespace, key_type, KeyLifeCycle::Live],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+        };
+        let Some(previous_id) = find_live_key(alias).context(ks_err!("Failed to query key."))?
+        else {
+            return Ok(false);
+        };
+        let previous_alias = previous_key_alias(alias);
+        let need_gc = match find_live_key(&previous_alias)
+            .context(ks_err!("Failed to query the retained key."))?
+        {
+            Some(retained_id) => {
+                let retained = KeyMetaData::load_from_db(retained_id, tx)
+                    .context(ks_err!("Failed to load metadata of {previous_alias:?}."))?
+                    .retained_until()
+                    .is_some();
+                if !retained {
+                    return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                        "{previous_alias:?} is bound to a key that was not retained."
+                    ));
+                }
+                Self::mark_unreferenced(tx, retained_id)
+                    .context(ks_err!("Failed to unbind the retained key."))?
+            }
+            None => false,
+        };
+        tx.execute(
+            "UPDATE persistent.keyentry SET alias = ? WHERE id = ?;",
+            params![previous_alias, previous_id],
+        )
+        .context(ks_err!("Failed to rebind the previous key."))?;
+        let mut metadata = KeyMetaData::new();
+        metadata.add(KeyMetaEntry::RetainedUntil(until));
+        metadata.store_in_db(previous_id, tx).context(ks_err!("Failed to store metadata."))?;
+        Ok(need_gc)
+    }
+
     /// Moves the key given by KeyIdGuard to the new location at `destination`. If the destination
     /// is already occupied by a key, this function fails with `ResponseCode::INVALID_ARGUMENT`.
     pub fn migrate_key_namespace(
@@ -1723,35 +2432,233 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
-    /// Store a new key in a single transaction.
-    /// The function creates a new key entry, populates the blob, key parameter, and metadata
-    /// fields, and rebinds the given alias to the new key.
-    /// The boolean returned is a hint for the garbage collector. If true, a key was replaced,
-    /// is now unreferenced and needs to be collected.
-    #[allow(clippy::too_many_arguments)]
-    pub fn store_new_key(
+    /// Changes the alias of the key identified by `key_id_guard` to `new_alias` in place.
+    /// The key keeps its id, domain, and namespace, so all grants, key parameters, and
+    /// metadata remain attached to it. The `check_permission` callback is called with the
+    /// descriptor of the new location before any change is made.
+    /// Fails with `ResponseCode::INVALID_ARGUMENT` if the new alias is already taken.
+    pub fn rename_key(
         &mut self,
-        key: &KeyDescriptor,
-        key_type: KeyType,
-        params: &[KeyParameter],
-        blob_info: &BlobInfo,
-        cert_info: &CertificateInfo,
-        metadata: &KeyMetaData,
-        km_uuid: &Uuid,
-    ) -> Result<KeyIdGuard> {
-        let _wp = wd::watch("KeystoreDB::store_new_key");
+        key_id_guard: KeyIdGuard,
+        new_alias: &str,
+        check_permission: impl Fn(&KeyDescriptor) -> Result<()>,
+    ) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::rename_key");
 
-        let (alias, domain, namespace) = match key {
-            KeyDescriptor { alias: Some(alias), domain: Domain::APP, nspace, blob: None }
-            | KeyDescriptor { alias: Some(alias), domain: Domain::SELINUX, nspace, blob: None } => {
-                (alias, key.domain, nspace)
-            }
-            _ => {
-                return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT))
-                    .context(ks_err!("Need alias and domain must be APP or SELINUX."));
-            }
-        };
-        self.with_transaction(Immediate("TX_store_new_key"), |tx| {
+        self.with_transaction(Immediate("TX_rename_key"), |tx| {
+            let (domain, namespace): (i32, i64) = tx
+                .query_row(
+                    "SELECT domain, namespace FROM persistent.keyentry
+                     WHERE id = ? AND state = ?;",
+                    params![key_id_guard.id(), KeyLifeCycle::Live],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .context("Failed to query source.")?
+                .ok_or(KsError::Rc(ResponseCode::KEY_NOT_FOUND))
+                .context("Source key not found.")?;
+
+            let destination = KeyDescriptor {
+                domain: Domain(domain),
+                nspace: namespace,
+                alias: Some(new_alias.to_string()),
+                blob: None,
+            };
+
+            // Security critical: Must return immediately on failure. Do not remove the '?';
+            check_permission(&destination).context("Trying to check permission.")?;
+
+            // Query the destination alias. If there is a key, the rename request fails.
+            if tx
+                .query_row(
+                    "SELECT id FROM persistent.keyentry
+                     WHERE alias = ? AND domain = ? AND namespace = ?;",
+                    params![new_alias, domain, namespace],
+                    |_| Ok(()),
+                )
+                .optional()
+                .context("Failed to query destination.")?
+                .is_some()
+            {
+                return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context("Target already exists.");
+            }
+
+            let updated = tx
+                .execute(
+                    "UPDATE persistent.keyentry SET alias = ? WHERE id = ?;",
+                    params![new_alias, key_id_guard.id()],
+                )
+                .context("Failed to update key entry.")?;
+
+            if updated != 1 {
+                return Err(KsError::sys())
+                    .context(format!("Update succeeded, but {} rows were updated.", updated));
+            }
+            Ok(()).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Replaces the public certificate and the certificate chain of the key identified by
+    /// `key_id_guard` and applies `grant_updates` in a single transaction, so that grantees
+    /// never see the new grants with the old certificates or vice versa. Like `set_blob`,
+    /// passing None removes the certificate or chain.
+    /// `check_permission` is called with the descriptor of the key, i.e., its domain,
+    /// namespace, and alias, for every update before anything is changed. Upon success, the
+    /// function returns the grant descriptors of the `GrantUpdate::Grant` updates in order.
+    pub fn update_subcomponents_and_grants(
+        &mut self,
+        key_id_guard: &KeyIdGuard,
+        public_cert: Option<&[u8]>,
+        certificate_chain: Option<&[u8]>,
+        grant_updates: &[GrantUpdate],
+        check_permission: impl Fn(&KeyDescriptor, &GrantUpdate) -> Result<()>,
+    ) -> Result<Vec<KeyDescriptor>> {
+        let _wp = wd::watch("KeystoreDB::update_subcomponents_and_grants");
+
+        self.with_transaction(Immediate("TX_update_subcomponents_and_grants"), |tx| {
+            let key_id = key_id_guard.id();
+            let key = tx
+                .query_row(
+                    "SELECT domain, namespace, alias FROM persistent.keyentry
+                     WHERE id = ? AND state = ?;",
+                    params![key_id, KeyLifeCycle::Live],
+                    |row| {
+                        Ok(KeyDescriptor {
+                            domain: Domain(row.get(0)?),
+                            nspace: row.get(1)?,
+                            alias: row.get(2)?,
+                            blob: None,
+                        })
+                    },
+                )
+                .optional()
+                .context(ks_err!("Failed to query key."))?
+                .ok_or(KsError::Rc(ResponseCode::KEY_NOT_FOUND))
+                .context(ks_err!("Key not found."))?;
+
+            // Security critical: Must return immediately on failure. Do not remove the '?';
+            for update in grant_updates {
+                check_permission(&key, update).context(ks_err!("check_permission failed."))?;
+            }
+
+            Self::set_blob_internal(tx, key_id, SubComponentType::CERT, public_cert, None)
+                .context(ks_err!("Failed to update cert subcomponent."))?;
+            Self::set_blob_internal(
+                tx,
+                key_id,
+                SubComponentType::CERT_CHAIN,
+                certificate_chain,
+                None,
+            )
+            .context(ks_err!("Failed to update cert chain subcomponent."))?;
+
+            let mut granted_keys = Vec::new();
+            for update in grant_updates {
+                match *update {
+                    GrantUpdate::Grant { grantee_uid, access_vector } => {
+                        let grant_id =
+                            Self::grant_internal(tx, key_id, grantee_uid, access_vector)?;
+                        granted_keys.push(KeyDescriptor {
+                            domain: Domain::GRANT,
+                            nspace: grant_id,
+                            alias: None,
+                            blob: None,
+                        });
+                    }
+                    GrantUpdate::Ungrant { grantee_uid } => {
+                        tx.execute(
+                            "DELETE FROM persistent.grant
+                            WHERE keyentryid = ? AND grantee = ?;",
+                            params![key_id, grantee_uid],
+                        )
+                        .context(ks_err!("Failed to delete grant."))?;
+                    }
+                }
+            }
+            Ok(granted_keys).need_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Store a new key in a single transaction.
+    /// The function creates a new key entry, populates the blob, key parameter, and metadata
+    /// fields, and rebinds the given alias to the new key.
+    /// The boolean returned is a hint for the garbage collector. If true, a key was replaced,
+    /// is now unreferenced and needs to be collected.
+    #[allow(clippy::too_many_arguments)]
+    pub fn store_new_key(
+        &mut self,
+        key: &KeyDescriptor,
+        key_type: KeyType,
+        params: &[KeyParameter],
+        blob_info: &BlobInfo,
+        cert_info: &CertificateInfo,
+        metadata: &KeyMetaData,
+        km_uuid: &Uuid,
+    ) -> Result<KeyIdGuard> {
+        let _wp = wd::watch("KeystoreDB::store_new_key");
+
+        self.store_new_key_internal(
+            key, key_type, params, blob_info, cert_info, metadata, km_uuid, None,
+        )
+    }
+
+    /// Like `store_new_key`, but the key that the alias was bound to before is not released.
+    /// It is rebound to `<alias>.prev` in the same transaction and retained until
+    /// `retain_previous_until`, see `key_rotation`. A key that was retained under that alias
+    /// before is unbound. Fails with `ResponseCode::INVALID_ARGUMENT` if a key that was not
+    /// retained by a rotation is bound to `<alias>.prev`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn store_rotated_key(
+        &mut self,
+        key: &KeyDescriptor,
+        key_type: KeyType,
+        params: &[KeyParameter],
+        blob_info: &BlobInfo,
+        cert_info: &CertificateInfo,
+        metadata: &KeyMetaData,
+        km_uuid: &Uuid,
+        retain_previous_until: DateTime,
+    ) -> Result<KeyIdGuard> {
+        let _wp = wd::watch("KeystoreDB::store_rotated_key");
+
+        self.store_new_key_internal(
+            key,
+            key_type,
+            params,
+            blob_info,
+            cert_info,
+            metadata,
+            km_uuid,
+            Some(retain_previous_until),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn store_new_key_internal(
+        &mut self,
+        key: &KeyDescriptor,
+        key_type: KeyType,
+        params: &[KeyParameter],
+        blob_info: &BlobInfo,
+        cert_info: &CertificateInfo,
+        metadata: &KeyMetaData,
+        km_uuid: &Uuid,
+        retain_previous_until: Option<DateTime>,
+    ) -> Result<KeyIdGuard> {
+        let (alias, domain, namespace) = match key {
+            KeyDescriptor { alias: Some(alias), domain: Domain::APP, nspace, blob: None }
+            | KeyDescriptor { alias: Some(alias), domain: Domain::SELINUX, nspace, blob: None } => {
+                (alias, key.domain, nspace)
+            }
+            _ => {
+                return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context(ks_err!("Need alias and domain must be APP or SELINUX."));
+            }
+        };
+        self.with_transaction(Immediate("TX_store_new_key"), |tx| {
             let key_id = Self::create_key_entry_internal(tx, &domain, namespace, key_type, km_uuid)
                 .context("Trying to create new key entry.")?;
             let BlobInfo { blob, metadata: blob_metadata, superseded_blob } = *blob_info;
@@ -1800,6 +2707,14 @@ impl KeystoreDB {
             Self::insert_keyparameter_internal(tx, &key_id, params)
                 .context("Trying to insert key parameters.")?;
             metadata.store_in_db(key_id.id(), tx).context("Trying to insert key metadata.")?;
+            let need_gc = match retain_previous_until {
+                Some(until) => {
+                    Self::retain_previous_key(tx, alias, &domain, namespace, key_type, until)
+                        .context("Trying to retain the previous key.")?
+                        || need_gc
+                }
+                None => need_gc,
+            };
             let need_gc = Self::rebind_alias(tx, &key_id, alias, &domain, namespace, key_type)
                 .context("Trying to rebind alias.")?
                 || need_gc;
@@ -2027,7 +2942,9 @@ impl KeystoreDB {
 
         let mut key_blob: Option<(i64, Vec<u8>)> = None;
         let mut cert_blob: Option<Vec<u8>> = None;
-        let mut cert_chain_blob: Option<Vec<u8>> = None;
+        // The id, subcomponent type, and blob of the most recent inline or shared certificate
+        // chain.
+        let mut cert_chain: Option<(i64, SubComponentType, Vec<u8>)> = None;
         let mut has_km_blob: bool = false;
         db_utils::with_rows_extract_all(&mut rows, |row| {
             let sub_type: SubComponentType =
@@ -2044,12 +2961,20 @@ impl KeystoreDB {
                     cert_blob =
                         Some(row.get(2).context("Failed to extract public certificate blob.")?);
                 }
-                (SubComponentType::CERT_CHAIN, true, _) => {
-                    cert_chain_blob =
-                        Some(row.get(2).context("Failed to extract certificate chain blob.")?);
+                (SubComponentType::CERT_CHAIN, true, _)
+                | (SubComponentType::CERT_CHAIN_REF, true, _) => {
+                    let id: i64 = row.get(0).context("Failed to extract certificate chain id.")?;
+                    if cert_chain.as_ref().is_none_or(|(prev_id, _, _)| id > *prev_id) {
+                        cert_chain = Some((
+                            id,
+                            sub_type,
+                            row.get(2).context("Failed to extract certificate chain blob.")?,
+                        ));
+                    }
                 }
                 (SubComponentType::CERT, _, _)
                 | (SubComponentType::CERT_CHAIN, _, _)
+                | (SubComponentType::CERT_CHAIN_REF, _, _)
                 | (SubComponentType::KEY_BLOB, _, _) => {}
                 _ => Err(KsError::sys()).context("Unknown subcomponent type.")?,
             }
@@ -2057,6 +2982,18 @@ impl KeystoreDB {
         })
         .context(ks_err!())?;
 
+        let cert_chain_blob = match cert_chain {
+            Some((_, SubComponentType::CERT_CHAIN_REF, digest)) => Some(
+                tx.query_row(
+                    "SELECT chain FROM persistent.certchain WHERE digest = ?;",
+                    params![digest],
+                    |row| row.get(0),
+                )
+                .context(ks_err!("Failed to load shared certificate chain."))?,
+            ),
+            cert_chain => cert_chain.map(|(_, _, blob)| blob),
+        };
+
         let blob_info = key_blob.map_or::<Result<_>, _>(Ok(None), |(blob_id, blob)| {
             Ok(Some((
                 blob,
@@ -2263,6 +3200,8 @@ impl KeystoreDB {
             .context("Trying to delete keyparameters.")?;
         tx.execute("DELETE FROM persistent.grant WHERE keyentryid = ?;", params![key_id])
             .context("Trying to delete grants.")?;
+        tx.execute("DELETE FROM persistent.entrymetadata WHERE keyentryid = ?;", params![key_id])
+            .context("Trying to delete entry metadata.")?;
         Ok(updated != 0)
     }
 
@@ -2294,16 +3233,84 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
-    fn get_key_km_uuid(tx: &Transaction, key_id: i64) -> Result<Uuid> {
+    /// Marks all live client keys in the given namespace whose alias starts with `prefix` as
+    /// unreferenced and returns their number. This happens in a single transaction: if
+    /// `check_permission` fails for any of the keys, none of them is unbound. The garbage
+    /// collector is notified once for all of them.
+    pub fn unbind_keys_by_alias_prefix(
+        &mut self,
+        domain: Domain,
+        namespace: i64,
+        prefix: &str,
+        check_permission: impl Fn(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> Result<usize> {
+        let _wp = wd::watch("KeystoreDB::unbind_keys_by_alias_prefix");
+
+        if !(domain == Domain::APP || domain == Domain::SELINUX) {
+            return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!());
+        }
+        self.with_transaction(Immediate("TX_unbind_keys_by_alias_prefix"), |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT id, alias FROM persistent.keyentry
+                     WHERE domain = ? AND namespace = ? AND key_type = ? AND state = ?
+                     AND substr(alias, 1, ?) = ?;",
+                )
+                .context("Failed to prepare the query to find the keys by alias prefix.")?;
+            let mut rows = stmt
+                .query(params![
+                    domain.0,
+                    namespace,
+                    KeyType::Client,
+                    KeyLifeCycle::Live,
+                    prefix.chars().count() as i64,
+                    prefix,
+                ])
+                .context("Failed to query the keys by alias prefix.")?;
+            let mut keys: Vec<(i64, String)> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                keys.push((
+                    row.get(0).context("Failed to read key id.")?,
+                    row.get(1).context("Failed to read alias.")?,
+                ));
+                Ok(())
+            })
+            .context("Failed to extract the keys by alias prefix.")?;
+
+            let mut notify_gc = false;
+            for (key_id, alias) in &keys {
+                // Perform access control. It is vital that we return here if the permission is
+                // denied. So do not touch that '?' at the end.
+                check_permission(
+                    &KeyDescriptor {
+                        domain,
+                        nspace: namespace,
+                        alias: Some(alias.clone()),
+                        blob: None,
+                    },
+                    None,
+                )
+                .context("While checking permission.")?;
+                notify_gc = Self::mark_unreferenced(tx, *key_id)
+                    .context("Trying to mark the key unreferenced.")?
+                    || notify_gc;
+            }
+            Ok(keys.len()).do_gc(notify_gc)
+        })
+        .context(ks_err!())
+    }
+
+    fn get_key_km_uuid_and_state(tx: &Transaction, key_id: i64) -> Result<(Uuid, KeyLifeCycle)> {
         tx.query_row(
-            "SELECT km_uuid FROM persistent.keyentry WHERE id = ?",
+            "SELECT km_uuid, state FROM persistent.keyentry WHERE id = ?",
             params![key_id],
-            |row| row.get(0),
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )
         .context(ks_err!())
     }
 
     /// Delete all artifacts belonging to the namespace given by the domain-namespace tuple.
+    /// For `Domain::APP`, this also deletes all grants to the UID given by the namespace.
     /// This leaves all of the blob entries orphaned for subsequent garbage collection.
     pub fn unbind_keys_for_namespace(&mut self, domain: Domain, namespace: i64) -> Result<()> {
         let _wp = wd::watch("KeystoreDB::unbind_keys_for_namespace");
@@ -2339,6 +3346,21 @@ impl KeystoreDB {
                 params![domain.0, namespace, KeyType::Client],
             )
             .context("Trying to delete grants.")?;
+            tx.execute(
+                "DELETE FROM persistent.entrymetadata
+                WHERE keyentryid IN (
+                    SELECT id FROM persistent.keyentry
+                    WHERE domain = ? AND namespace = ? AND key_type = ?
+                );",
+                params![domain.0, namespace, KeyType::Client],
+            )
+            .context("Trying to delete entry metadata.")?;
+            if domain == Domain::APP {
+                // The namespace is the UID of an app that is going away. Grants to it must not
+                // outlive it, or they would pass to the next app that is assigned the UID.
+                tx.execute("DELETE FROM persistent.grant WHERE grantee = ?;", params![namespace])
+                    .context("Trying to delete grants to the namespace.")?;
+            }
             tx.execute(
                 "DELETE FROM persistent.keyentry
                  WHERE domain = ? AND namespace = ? AND key_type = ?;",
@@ -2380,6 +3402,15 @@ impl KeystoreDB {
                 params![KeyLifeCycle::Unreferenced],
             )
             .context("Trying to delete grants.")?;
+            tx.execute(
+                "DELETE FROM persistent.entrymetadata
+            WHERE keyentryid IN (
+                SELECT id FROM persistent.keyentry
+                WHERE state = ?
+            );",
+                params![KeyLifeCycle::Unreferenced],
+            )
+            .context("Trying to delete entry metadata.")?;
             tx.execute(
                 "DELETE FROM persistent.keyentry
                 WHERE state = ?;",
@@ -2391,7 +3422,8 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
-    /// Deletes all keys for the given user, including both client keys and super keys.
+    /// Deletes all keys for the given user, including both client keys and super keys, and all
+    /// grants to the apps of the user.
     pub fn unbind_keys_for_user(&mut self, user_id: u32) -> Result<()> {
         let _wp = wd::watch("KeystoreDB::unbind_keys_for_user");
 
@@ -2444,6 +3476,15 @@ impl KeystoreDB {
                     .context("In unbind_keys_for_user.")?
                     || notify_gc;
             }
+            tx.execute(
+                &format!(
+                    "DELETE FROM persistent.grant
+                     WHERE cast ( (grantee/{aid_user_offset}) as int) = ?;",
+                    aid_user_offset = AID_USER_OFFSET
+                ),
+                params![user_id],
+            )
+            .context(ks_err!("Failed to delete grants to the apps of the user."))?;
             Ok(()).do_gc(notify_gc)
         })
         .context(ks_err!())
@@ -2526,8 +3567,10 @@ impl KeystoreDB {
         let parameters = Self::load_key_parameters(key_id, tx)
             .context("In load_key_components: Trying to load key parameters.")?;
 
-        let km_uuid = Self::get_key_km_uuid(tx, key_id)
+        let (km_uuid, state) = Self::get_key_km_uuid_and_state(tx, key_id)
             .context("In load_key_components: Trying to get KM uuid.")?;
+        let now = DateTime::now().context("In load_key_components: Trying to get time.")?;
+        let lifecycle_state = KeyLifecycleState::derive(state, &metadata, now);
 
         Ok(KeyEntry {
             id: key_id,
@@ -2538,6 +3581,7 @@ impl KeystoreDB {
             parameters,
             metadata,
             pure_cert: !has_km_blob,
+            lifecycle_state,
         })
     }
 
@@ -2553,9 +3597,25 @@ impl KeystoreDB {
         namespace: i64,
         key_type: KeyType,
         start_past_alias: Option<&str>,
+    ) -> Result<Vec<KeyDescriptor>> {
+        self.list_past_alias_limited(domain, namespace, key_type, start_past_alias, None)
+    }
+
+    /// Like `list_past_alias`, but returns at most `limit` KeyDescriptors if a limit is given.
+    /// The aliases are read in order from the index on domain, namespace and alias, so the cost of
+    /// a limited query does not grow with the number of keys in the namespace.
+    pub fn list_past_alias_limited(
+        &mut self,
+        domain: Domain,
+        namespace: i64,
+        key_type: KeyType,
+        start_past_alias: Option<&str>,
+        limit: Option<usize>,
     ) -> Result<Vec<KeyDescriptor>> {
         let _wp = wd::watch("KeystoreDB::list_past_alias");
 
+        // A negative limit means no limit to SQLite.
+        let limit = limit.map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX));
         let query = format!(
             "SELECT DISTINCT alias FROM persistent.keyentry
                      WHERE domain = ?
@@ -2564,7 +3624,8 @@ impl KeystoreDB {
                      AND state = ?
                      AND key_type = ?
                      {}
-                     ORDER BY alias ASC;",
+                     ORDER BY alias ASC
+                     LIMIT ?;",
             if start_past_alias.is_some() { " AND alias > ?" } else { "" }
         );
 
@@ -2578,11 +3639,12 @@ impl KeystoreDB {
                         namespace,
                         KeyLifeCycle::Live,
                         key_type,
-                        past_alias
+                        past_alias,
+                        limit
                     ])
                     .context(ks_err!("Failed to query."))?,
                 None => stmt
-                    .query(params![domain.0 as u32, namespace, KeyLifeCycle::Live, key_type,])
+                    .query(params![domain.0 as u32, namespace, KeyLifeCycle::Live, key_type, limit])
                     .context(ks_err!("Failed to query."))?,
             };
 
@@ -2628,6 +3690,174 @@ impl KeystoreDB {
         Ok(num_keys)
     }
 
+    /// Replaces the labels of the given key entry with `metadata`.
+    pub fn set_entry_metadata(
+        &mut self,
+        key_id: &KeyIdGuard,
+        metadata: &EntryMetadata,
+    ) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::set_entry_metadata");
+
+        self.with_transaction(Immediate("TX_set_entry_metadata"), |tx| {
+            tx.execute(
+                "DELETE FROM persistent.entrymetadata WHERE keyentryid = ?;",
+                params![key_id.0],
+            )
+            .context(ks_err!("Failed to delete entry metadata."))?;
+            let mut stmt = tx
+                .prepare(
+                    "INSERT INTO persistent.entrymetadata (keyentryid, name, value)
+                     VALUES (?, ?, ?);",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            for (name, value) in metadata {
+                stmt.insert(params![key_id.0, name, value])
+                    .with_context(|| ks_err!("Failed to insert label {:?}.", name))?;
+            }
+            Ok(()).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns the labels of the given key entry.
+    pub fn get_entry_metadata(&mut self, key_id: &KeyIdGuard) -> Result<EntryMetadata> {
+        let _wp = wd::watch("KeystoreDB::get_entry_metadata");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare("SELECT name, value FROM persistent.entrymetadata WHERE keyentryid = ?;")
+                .context(ks_err!("Failed to prepare statement."))?;
+            let mut rows = stmt.query(params![key_id.0]).context(ks_err!("Failed to query."))?;
+            let mut metadata = EntryMetadata::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                metadata.insert(
+                    row.get(0).context("Failed to read name.")?,
+                    row.get(1).context("Failed to read value.")?,
+                );
+                Ok(())
+            })
+            .context(ks_err!("Failed to extract rows."))?;
+            Ok(metadata).no_gc()
+        })
+    }
+
+    /// Like `list_past_alias`, but returns only the keys that match `filter`. The filter is
+    /// evaluated against the key parameters and the metadata of the keys, so that callers do not
+    /// have to load the characteristics of each key.
+    pub fn list_past_alias_filtered(
+        &mut self,
+        domain: Domain,
+        namespace: i64,
+        key_type: KeyType,
+        start_past_alias: Option<&str>,
+        filter: &KeyEntryFilter,
+    ) -> Result<Vec<KeyDescriptor>> {
+        let _wp = wd::watch("KeystoreDB::list_past_alias_filtered");
+
+        let mut conditions = String::new();
+        let mut args: Vec<Box<dyn ToSql>> = vec![
+            Box::new(domain.0 as u32),
+            Box::new(namespace),
+            Box::new(KeyLifeCycle::Live),
+            Box::new(key_type),
+        ];
+        if let Some(past_alias) = start_past_alias {
+            conditions.push_str(" AND alias > ?");
+            args.push(Box::new(past_alias.to_string()));
+        }
+        let parameters = [
+            filter.algorithm.map(KeyParameterValue::Algorithm),
+            filter.origin.map(KeyParameterValue::KeyOrigin),
+        ];
+        for value in parameters.into_iter().flatten() {
+            conditions.push_str(
+                " AND id IN (SELECT keyentryid FROM persistent.keyparameter
+                     WHERE tag = ? AND data = ?)",
+            );
+            args.push(Box::new(value.get_tag().0));
+            args.push(Box::new(value));
+        }
+        if let Some(auth_bound) = filter.auth_bound {
+            conditions.push_str(if auth_bound { " AND id IN" } else { " AND id NOT IN" });
+            conditions.push_str(" (SELECT keyentryid FROM persistent.keyparameter WHERE tag = ?)");
+            args.push(Box::new(Tag::USER_SECURE_ID.0));
+        }
+        for (op, date) in [(">=", filter.created_after), ("<", filter.created_before)] {
+            if let Some(date) = date {
+                conditions.push_str(&format!(
+                    " AND id IN (SELECT keyentryid FROM persistent.keymetadata
+                         WHERE tag = ? AND data {op} ?)"
+                ));
+                args.push(Box::new(KeyMetaData::CreationDate));
+                args.push(Box::new(date));
+            }
+        }
+        let query = format!(
+            "SELECT DISTINCT alias FROM persistent.keyentry
+                     WHERE domain = ?
+                     AND namespace = ?
+                     AND alias IS NOT NULL
+                     AND state = ?
+                     AND key_type = ?
+                     {conditions}
+                     ORDER BY alias ASC;"
+        );
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx.prepare(&query).context(ks_err!("Failed to prepare."))?;
+            let mut rows =
+                stmt.query(params_from_iter(args.iter())).context(ks_err!("Failed to query."))?;
+
+            let mut descriptors: Vec<KeyDescriptor> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                descriptors.push(KeyDescriptor {
+                    domain,
+                    nspace: namespace,
+                    alias: Some(row.get(0).context("Trying to extract alias.")?),
+                    blob: None,
+                });
+                Ok(())
+            })
+            .context(ks_err!("Failed to extract rows."))?;
+            Ok(descriptors).no_gc()
+        })
+    }
+
+    /// Returns the number of live client keys in the selected domain/namespace and the total
+    /// size in bytes of their blob entries, which includes superseded blobs that have not been
+    /// garbage collected yet. The key with the alias `except_alias`, if any, is not counted.
+    pub fn get_namespace_usage(
+        &mut self,
+        domain: Domain,
+        namespace: i64,
+        except_alias: Option<&str>,
+    ) -> Result<(usize, usize)> {
+        let _wp = wd::watch("KeystoreDB::get_namespace_usage");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            tx.query_row(
+                "SELECT COUNT(id), TOTAL((SELECT TOTAL(LENGTH(blob)) FROM persistent.blobentry
+                                          WHERE keyentryid = keyentry.id))
+                     FROM persistent.keyentry
+                     WHERE domain = ?
+                     AND namespace = ?
+                     AND alias IS NOT ?
+                     AND state = ?
+                     AND key_type = ?;",
+                params![
+                    domain.0 as u32,
+                    namespace,
+                    except_alias,
+                    KeyLifeCycle::Live,
+                    KeyType::Client
+                ],
+                |row| Ok((row.get(0)?, row.get::<_, f64>(1)? as usize)),
+            )
+            .context(ks_err!("Failed to get namespace usage."))
+            .no_gc()
+        })
+    }
+
     /// Adds a grant to the grant table.
     /// Like `load_key_entry` this function loads the access tuple before
     /// it uses the callback for a permission check. Upon success,
@@ -2665,40 +3895,50 @@ impl KeystoreDB {
             check_permission(&access_key_descriptor, &access_vector)
                 .context(ks_err!("check_permission failed"))?;
 
-            let grant_id = if let Some(grant_id) = tx
-                .query_row(
-                    "SELECT id FROM persistent.grant
-                WHERE keyentryid = ? AND grantee = ?;",
-                    params![key_id, grantee_uid],
-                    |row| row.get(0),
-                )
-                .optional()
-                .context(ks_err!("Failed get optional existing grant id."))?
-            {
-                tx.execute(
-                    "UPDATE persistent.grant
-                    SET access_vector = ?
-                    WHERE id = ?;",
-                    params![i32::from(access_vector), grant_id],
-                )
-                .context(ks_err!("Failed to update existing grant."))?;
-                grant_id
-            } else {
-                Self::insert_with_retry(|id| {
-                    tx.execute(
-                        "INSERT INTO persistent.grant (id, grantee, keyentryid, access_vector)
-                        VALUES (?, ?, ?, ?);",
-                        params![id, grantee_uid, key_id, i32::from(access_vector)],
-                    )
-                })
-                .context(ks_err!())?
-            };
+            let grant_id = Self::grant_internal(tx, key_id, grantee_uid, access_vector)?;
 
             Ok(KeyDescriptor { domain: Domain::GRANT, nspace: grant_id, alias: None, blob: None })
                 .no_gc()
         })
     }
 
+    /// Inserts or updates the grant of `key_id` to `grantee_uid` and returns the grant id.
+    fn grant_internal(
+        tx: &Transaction,
+        key_id: i64,
+        grantee_uid: u32,
+        access_vector: KeyPermSet,
+    ) -> Result<i64> {
+        if let Some(grant_id) = tx
+            .query_row(
+                "SELECT id FROM persistent.grant
+                WHERE keyentryid = ? AND grantee = ?;",
+                params![key_id, grantee_uid],
+                |row| row.get(0),
+            )
+            .optional()
+            .context(ks_err!("Failed get optional existing grant id."))?
+        {
+            tx.execute(
+                "UPDATE persistent.grant
+                    SET access_vector = ?
+                    WHERE id = ?;",
+                params![i32::from(access_vector), grant_id],
+            )
+            .context(ks_err!("Failed to update existing grant."))?;
+            Ok(grant_id)
+        } else {
+            Self::insert_with_retry(|id| {
+                tx.execute(
+                    "INSERT INTO persistent.grant (id, grantee, keyentryid, access_vector)
+                        VALUES (?, ?, ?, ?);",
+                    params![id, grantee_uid, key_id, i32::from(access_vector)],
+                )
+            })
+            .context(ks_err!())
+        }
+    }
+
     /// This function checks permissions like `grant` and `load_key_entry`
     /// before removing a grant from the grant table.
     pub fn ungrant(
@@ -2772,6 +4012,39 @@ impl KeystoreDB {
         self.perboot.find_auth_token_entry(p)
     }
 
+    /// Record the use of a caller provided nonce with the given key, remembering the last
+    /// `history` nonces per key until reboot. Returns false if the nonce was used recently.
+    pub fn insert_caller_nonce(&self, key_id: i64, nonce: &[u8], history: usize) -> bool {
+        self.perboot.insert_caller_nonce(key_id, nonce, history)
+    }
+
+    /// Checks the invariants of the per-boot database and repairs violations, see
+    /// `perboot_scrubber`. Returns the repairs made.
+    pub fn scrub_perboot(&mut self) -> Result<PerbootScrubReport> {
+        let _wp = wd::watch("KeystoreDB::scrub_perboot");
+
+        let key_ids = self.perboot.nonce_tracked_key_ids();
+        // Keys that start to be tracked after this query are not orphaned.
+        let orphaned: HashSet<i64> = self
+            .with_transaction(TransactionBehavior::Deferred, |tx| {
+                let mut stmt = tx
+                    .prepare("SELECT id FROM persistent.keyentry WHERE id = ? AND state = ?;")
+                    .context(ks_err!("Failed to prepare statement."))?;
+                let mut orphaned = HashSet::new();
+                for key_id in key_ids {
+                    if !stmt
+                        .exists(params![key_id, KeyLifeCycle::Live])
+                        .context(ks_err!("Failed to query key {key_id}."))?
+                    {
+                        orphaned.insert(key_id);
+                    }
+                }
+                Ok(orphaned).no_gc()
+            })
+            .context(ks_err!())?;
+        Ok(self.perboot.scrub(BootTime::now(), |key_id| orphaned.contains(&key_id)))
+    }
+
     /// Load descriptor of a key by key id
     pub fn load_key_descriptor(&mut self, key_id: i64) -> Result<Option<KeyDescriptor>> {
         let _wp = wd::watch("KeystoreDB::load_key_descriptor");
@@ -2796,6 +4069,361 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
+    /// Returns the grantees of the key with the given id and the permissions granted to them,
+    /// ordered by grantee.
+    pub fn load_grants(&mut self, key_id: i64) -> Result<Vec<(u32, KeyPermSet)>> {
+        let _wp = wd::watch("KeystoreDB::load_grants");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT grantee, access_vector FROM persistent.grant
+                    WHERE keyentryid = ? ORDER BY grantee;",
+                )
+                .context("Trying to prepare query.")?;
+            let grants = stmt
+                .query_map(params![key_id], |row| {
+                    Ok((row.get(0)?, KeyPermSet::from(row.get::<_, i32>(1)?)))
+                })
+                .context("Trying to query grants.")?
+                .collect::<rusqlite::Result<Vec<(u32, KeyPermSet)>>>()
+                .context("Trying to extract grants.")?;
+            Ok(grants).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns all grants of live client keys as tuples of the grant id, the descriptor of the
+    /// granted key, the grantee, and the granted permissions, ordered by grantee.
+    pub fn load_all_grants(&mut self) -> Result<Vec<(i64, KeyDescriptor, u32, KeyPermSet)>> {
+        let _wp = wd::watch("KeystoreDB::load_all_grants");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT g.id, k.domain, k.namespace, k.alias, g.grantee, g.access_vector
+                     FROM persistent.grant g
+                     JOIN persistent.keyentry k ON g.keyentryid = k.id
+                     WHERE k.state = ? AND k.key_type = ?
+                     ORDER BY g.grantee, g.id;",
+                )
+                .context("Trying to prepare query.")?;
+            let grants = stmt
+                .query_map(params![KeyLifeCycle::Live, KeyType::Client], |row| {
+                    Ok((
+                        row.get(0)?,
+                        KeyDescriptor {
+                            domain: Domain(row.get(1)?),
+                            nspace: row.get(2)?,
+                            alias: row.get(3)?,
+                            blob: None,
+                        },
+                        row.get(4)?,
+                        KeyPermSet::from(row.get::<_, i32>(5)?),
+                    ))
+                })
+                .context("Trying to query grants.")?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .context("Trying to extract grants.")?;
+            Ok(grants).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Records that KeyMint reported the key with the given id as permanently invalidated, which
+    /// moves it to `KeyLifecycleState::Invalidated`. The date of the first report is retained.
+    pub fn mark_key_invalidated(&mut self, key_id: i64) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::mark_key_invalidated");
+
+        let now = DateTime::now().context(ks_err!("Trying to get time."))?;
+        self.with_transaction(Immediate("TX_mark_key_invalidated"), |tx| {
+            tx.execute(
+                "INSERT OR IGNORE INTO persistent.keymetadata (keyentryid, tag, data)
+                    SELECT id, ?, ? FROM persistent.keyentry WHERE id = ? AND state = ?;",
+                params![KeyMetaData::InvalidationDate, now, key_id, KeyLifeCycle::Live],
+            )
+            .context("Trying to insert invalidation date.")
+            .no_gc()
+        })
+        .context(ks_err!())?;
+        Ok(())
+    }
+
+    /// Adds the given usage to the usage statistics of the keys with the given ids. Keys that
+    /// are no longer live are skipped.
+    pub fn record_key_usage(&mut self, usage: &[(i64, KeyUsageStats)]) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::record_key_usage");
+
+        self.with_transaction(Immediate("TX_record_key_usage"), |tx| {
+            let mut count_stmt = tx
+                .prepare(
+                    "INSERT INTO persistent.keymetadata (keyentryid, tag, data)
+                        SELECT id, ?, ? FROM persistent.keyentry WHERE id = ? AND state = ?
+                        ON CONFLICT (keyentryid, tag) DO UPDATE SET data = data + excluded.data;",
+                )
+                .context(ks_err!("Failed to prepare operation count statement."))?;
+            let mut date_stmt = tx
+                .prepare(
+                    "INSERT INTO persistent.keymetadata (keyentryid, tag, data)
+                        SELECT id, ?, ? FROM persistent.keyentry WHERE id = ? AND state = ?
+                        ON CONFLICT (keyentryid, tag)
+                            DO UPDATE SET data = max(data, excluded.data);",
+                )
+                .context(ks_err!("Failed to prepare last used date statement."))?;
+            for (key_id, stats) in usage {
+                count_stmt
+                    .execute(params![
+                        KeyMetaData::OperationCount,
+                        stats.operation_count,
+                        key_id,
+                        KeyLifeCycle::Live
+                    ])
+                    .context(ks_err!("Failed to add operation count of key {}.", key_id))?;
+                if let Some(last_used) = stats.last_used {
+                    date_stmt
+                        .execute(params![
+                            KeyMetaData::LastUsedDate,
+                            last_used,
+                            key_id,
+                            KeyLifeCycle::Live
+                        ])
+                        .context(ks_err!("Failed to set last used date of key {}.", key_id))?;
+                }
+            }
+            Ok(()).no_gc()
+        })
+    }
+
+    /// Unbinds the keys that were retained after a rotation and whose retention ended at `now`,
+    /// see `key_rotation`. Returns the number of keys unbound.
+    pub fn unbind_expired_retained_keys(&mut self, now: DateTime) -> Result<usize> {
+        let _wp = wd::watch("KeystoreDB::unbind_expired_retained_keys");
+
+        self.with_transaction(Immediate("TX_unbind_expired_retained_keys"), |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT keyentryid FROM persistent.keymetadata
+                        WHERE tag = ? AND data <= ?;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let key_ids = stmt
+                .query_map(params![KeyMetaData::RetainedUntil, now], |row| row.get(0))
+                .context(ks_err!("Failed to query."))?
+                .collect::<rusqlite::Result<Vec<i64>>>()
+                .context(ks_err!("Failed to extract rows."))?;
+            let mut notify_gc = false;
+            for key_id in &key_ids {
+                notify_gc = Self::mark_unreferenced(tx, *key_id)
+                    .context(ks_err!("Failed to unbind key {key_id}."))?
+                    || notify_gc;
+            }
+            Ok(key_ids.len()).do_gc(notify_gc)
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns the ids of all live keys that are bound to the process that created them.
+    pub fn get_session_bound_key_ids(&mut self) -> Result<Vec<i64>> {
+        let _wp = wd::watch("KeystoreDB::get_session_bound_key_ids");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT keyentryid FROM persistent.keymetadata
+                        WHERE tag = ? AND keyentryid IN (
+                            SELECT id FROM persistent.keyentry WHERE state = ?
+                        )
+                        ORDER BY keyentryid ASC;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let key_ids = stmt
+                .query_map(params![KeyMetaData::SessionBound, KeyLifeCycle::Live], |row| row.get(0))
+                .context(ks_err!("Failed to query."))?
+                .collect::<rusqlite::Result<Vec<i64>>>()
+                .context(ks_err!("Failed to extract rows."))?;
+            Ok(key_ids).no_gc()
+        })
+    }
+
+    /// Returns the ids, domains, namespaces and usage statistics of up to `limit` live client
+    /// keys, least recently used first. Keys that were never used come first.
+    pub fn list_least_recently_used_keys(
+        &mut self,
+        limit: usize,
+    ) -> Result<Vec<(i64, Domain, i64, KeyUsageStats)>> {
+        let _wp = wd::watch("KeystoreDB::list_least_recently_used_keys");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT k.id, k.domain, k.namespace, c.data, l.data
+                        FROM persistent.keyentry k
+                        LEFT JOIN persistent.keymetadata c
+                            ON c.keyentryid = k.id AND c.tag = ?
+                        LEFT JOIN persistent.keymetadata l
+                            ON l.keyentryid = k.id AND l.tag = ?
+                        WHERE k.state = ? AND k.key_type = ?
+                        ORDER BY l.data ASC, k.id ASC LIMIT ?;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let mut rows = stmt
+                .query(params![
+                    KeyMetaData::OperationCount,
+                    KeyMetaData::LastUsedDate,
+                    KeyLifeCycle::Live,
+                    KeyType::Client,
+                    limit as i64
+                ])
+                .context(ks_err!("Failed to query."))?;
+            let mut keys = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                let count: Option<i64> = row.get(3).context("Failed to read operation count.")?;
+                let last_used: Option<i64> =
+                    row.get(4).context("Failed to read last used date.")?;
+                keys.push((
+                    row.get(0).context("Failed to read key id.")?,
+                    Domain(row.get(1).context("Failed to read domain.")?),
+                    row.get(2).context("Failed to read namespace.")?,
+                    KeyUsageStats {
+                        operation_count: count.unwrap_or(0),
+                        last_used: last_used.map(DateTime::from_millis_epoch),
+                    },
+                ));
+                Ok(())
+            })
+            .context(ks_err!("Failed to extract rows."))?;
+            Ok(keys).no_gc()
+        })
+    }
+
+    /// Returns the ids of up to `limit` live client keys with ids greater than `after`, in
+    /// ascending order, that are candidates for the idle key blob integrity check: their current
+    /// key blob is not super-encrypted, so that it can be passed to KeyMint as is, and they have
+    /// not failed the check before.
+    pub fn get_key_ids_for_integrity_check(
+        &mut self,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<i64>> {
+        let _wp = wd::watch("KeystoreDB::get_key_ids_for_integrity_check");
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT keyentryid FROM persistent.blobentry
+                     WHERE subcomponent_type = ?
+                     AND id IN (
+                         SELECT MAX(id) FROM persistent.blobentry
+                         WHERE subcomponent_type = ?
+                         GROUP BY keyentryid
+                     )
+                     AND id NOT IN (
+                         SELECT blobentryid FROM persistent.blobmetadata WHERE tag = ?
+                     )
+                     AND keyentryid IN (
+                         SELECT id FROM persistent.keyentry
+                         WHERE id > ? AND state = ? AND key_type = ?
+                     )
+                     AND keyentryid NOT IN (
+                         SELECT keyentryid FROM persistent.keymetadata WHERE tag = ?
+                     )
+                     ORDER BY keyentryid ASC LIMIT ?;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let mut rows = stmt
+                .query(params![
+                    SubComponentType::KEY_BLOB,
+                    SubComponentType::KEY_BLOB,
+                    BlobMetaData::EncryptedBy,
+                    after,
+                    KeyLifeCycle::Live,
+                    KeyType::Client,
+                    KeyMetaData::IntegrityCheckFailureDate,
+                    limit as i64,
+                ])
+                .context(ks_err!("Failed to query."))?;
+            let mut key_ids: Vec<i64> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                key_ids.push(row.get(0).context("Failed to read key id.")?);
+                Ok(())
+            })
+            .context(ks_err!())?;
+            Ok(key_ids).no_gc()
+        })
+    }
+
+    /// Records that KeyMint rejected the key blob of the key with the given id during the idle
+    /// integrity check. The date of the first failure is retained.
+    pub fn mark_key_integrity_check_failed(&mut self, key_id: i64) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::mark_key_integrity_check_failed");
+
+        let now = DateTime::now().context(ks_err!("Trying to get time."))?;
+        self.with_transaction(Immediate("TX_mark_key_integrity_check_failed"), |tx| {
+            tx.execute(
+                "INSERT OR IGNORE INTO persistent.keymetadata (keyentryid, tag, data)
+                    VALUES (?, ?, ?);",
+                params![key_id, KeyMetaData::IntegrityCheckFailureDate, now],
+            )
+            .context("Trying to insert integrity check failure date.")
+            .no_gc()
+        })
+        .context(ks_err!())?;
+        Ok(())
+    }
+
+    /// Returns the descriptors of all live client keys with an alias together with their
+    /// ALGORITHM, KEY_SIZE, and EC_CURVE parameters, which determine the strength of a key.
+    pub fn load_key_strength_parameters(
+        &mut self,
+    ) -> Result<Vec<(KeyDescriptor, Vec<KeyParameter>)>> {
+        let _wp = wd::watch("KeystoreDB::load_key_strength_parameters");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT k.id, k.domain, k.namespace, k.alias, p.tag, p.data, p.security_level
+                     FROM persistent.keyentry k
+                     JOIN persistent.keyparameter p ON p.keyentryid = k.id
+                     WHERE k.state = ? AND k.key_type = ? AND k.alias IS NOT NULL
+                     AND p.tag IN (?, ?, ?)
+                     ORDER BY k.id;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let mut rows = stmt
+                .query(params![
+                    KeyLifeCycle::Live,
+                    KeyType::Client,
+                    Tag::ALGORITHM.0,
+                    Tag::KEY_SIZE.0,
+                    Tag::EC_CURVE.0
+                ])
+                .context(ks_err!("Failed to query."))?;
+            let mut keys: Vec<(i64, KeyDescriptor, Vec<KeyParameter>)> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                let key_id: i64 = row.get(0).context("Failed to read key id.")?;
+                let tag = Tag(row.get(4).context("Failed to read tag.")?);
+                let sec_level = SecurityLevel(row.get(6).context("Failed to read sec_level.")?);
+                let param = KeyParameter::new_from_sql(tag, &SqlField::new(5, row), sec_level)
+                    .context("Failed to read KeyParameter.")?;
+                match keys.last_mut() {
+                    Some((id, _, params)) if *id == key_id => params.push(param),
+                    _ => keys.push((
+                        key_id,
+                        KeyDescriptor {
+                            domain: Domain(row.get(1).context("Failed to read domain.")?),
+                            nspace: row.get(2).context("Failed to read namespace.")?,
+                            alias: row.get(3).context("Failed to read alias.")?,
+                            blob: None,
+                        },
+                        vec![param],
+                    )),
+                }
+                Ok(())
+            })
+            .context(ks_err!())?;
+            Ok(keys.into_iter().map(|(_, key, params)| (key, params)).collect()).no_gc()
+        })
+    }
+
     /// Returns a list of app UIDs that have keys authenticated by the given secure_user_id
     /// (for the given user_id).
     /// This is helpful for finding out which apps will have their keys invalidated when
@@ -0,0 +1,87 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module releases the database pages that held deleted key blobs to the file system.
+//! SQLite's `secure_delete` overwrites freed pages with zeros, but on flash storage the old
+//! content may linger in blocks that the file system has not discarded. In the incremental auto
+//! vacuum mode, SQLite can move the free pages to the end of the database file and truncate it,
+//! which releases their blocks to the file system. It discards them on the next fstrim, or
+//! immediately if it is mounted with the discard option.
+//!
+//! Releasing free pages is opt-in with the read-only property
+//! `ro.keystore.discard_deleted_blobs`, for devices with certification requirements about
+//! residual ciphertext on flash. All of it goes through SQLite and is subject to its locking, so
+//! the database file is never modified behind SQLite's back.
+
+use crate::ks_err;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, Transaction};
+use std::sync::LazyLock;
+
+const DISCARD_PROPERTY: &str = "ro.keystore.discard_deleted_blobs";
+
+/// The value of `PRAGMA auto_vacuum` for the incremental auto vacuum mode.
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+static ENABLED: LazyLock<bool> = LazyLock::new(|| {
+    rustutils::system_properties::read_bool(DISCARD_PROPERTY, false).unwrap_or_else(|e| {
+        log::error!("Failed to read {DISCARD_PROPERTY}: {e:?}");
+        false
+    })
+});
+
+/// Returns true if free pages are released on this device.
+pub fn enabled() -> bool {
+    *ENABLED
+}
+
+/// Enables secure delete and the incremental auto vacuum mode for the persistent database of
+/// `conn`. A database that was created in another auto vacuum mode is converted by a vacuum,
+/// which needs exclusive access to the database. If another connection is busy, the conversion
+/// is attempted again with the next connection, and until then no free pages are released.
+pub fn setup(conn: &Connection) -> Result<()> {
+    // Setting secure_delete returns the new value.
+    conn.query_row("PRAGMA persistent.secure_delete = ON;", params![], |_| Ok(()))
+        .context(ks_err!("Failed to enable secure delete."))?;
+    let auto_vacuum: i64 = conn
+        .query_row("PRAGMA persistent.auto_vacuum;", params![], |row| row.get(0))
+        .context(ks_err!("Failed to get auto vacuum mode."))?;
+    if auto_vacuum == AUTO_VACUUM_INCREMENTAL {
+        return Ok(());
+    }
+    conn.execute("PRAGMA persistent.auto_vacuum = INCREMENTAL;", params![])
+        .context(ks_err!("Failed to set auto vacuum mode."))?;
+    if let Err(e) = conn.execute("VACUUM persistent;", params![]) {
+        log::warn!("Failed to convert the database to incremental auto vacuum: {e:?}");
+    }
+    Ok(())
+}
+
+/// Moves all free pages of the persistent database to the end of the file and truncates them.
+/// Returns the number of pages that were released. Does nothing unless the database is in the
+/// incremental auto vacuum mode.
+pub fn release_free_pages(tx: &Transaction) -> Result<u32> {
+    let free_pages: u32 = tx
+        .query_row("PRAGMA persistent.freelist_count;", params![], |row| row.get(0))
+        .context(ks_err!("Failed to get free page count."))?;
+    if free_pages == 0 {
+        return Ok(0);
+    }
+    tx.execute("PRAGMA persistent.incremental_vacuum;", params![])
+        .context(ks_err!("Failed to vacuum free pages."))?;
+    let remaining: u32 = tx
+        .query_row("PRAGMA persistent.freelist_count;", params![], |row| row.get(0))
+        .context(ks_err!("Failed to get free page count."))?;
+    Ok(free_pages - remaining)
+}
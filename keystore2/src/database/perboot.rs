@@ -13,16 +13,50 @@
 // limitations under the License.
 
 //! This module implements a per-boot, shared, in-memory storage of auth tokens
-//! for the main Keystore 2.0 database module.
+//! and recently used caller provided nonces for the main Keystore 2.0 database module.
 
-use super::AuthTokenEntry;
+use super::{AuthTokenEntry, BootTime};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
 };
-use std::collections::HashSet;
+use arc_swap::ArcSwap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::sync::LazyLock;
-use std::sync::RwLock;
+use std::sync::Mutex;
+
+/// Upper bound for the number of keys whose caller provided nonces are tracked. When it is
+/// reached, the history of the least recently used key is dropped.
+const MAX_NONCE_TRACKED_KEYS: usize = 1024;
+
+/// Upper bound for the number of auth tokens. There is one token per user, authenticator id and
+/// authenticator type, so the table only grows beyond it if something went wrong. The scrubber
+/// then drops the oldest tokens.
+const MAX_AUTH_TOKENS: usize = 256;
+
+/// The repairs made by a scrub of the perboot database, see `PerbootDB::scrub`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PerbootScrubReport {
+    /// The number of auth tokens dropped because they were received after the time of the scrub.
+    pub future_auth_tokens: usize,
+    /// The number of auth tokens dropped because there were more than `MAX_AUTH_TOKENS`.
+    pub excess_auth_tokens: usize,
+    /// The number of nonce histories dropped because their key no longer exists.
+    pub orphaned_nonce_histories: usize,
+    /// The number of nonce histories dropped because more than `MAX_NONCE_TRACKED_KEYS` keys
+    /// were tracked.
+    pub excess_nonce_histories: usize,
+    /// True if the use counter of the caller nonces was behind the uses recorded in the
+    /// histories and was moved forward.
+    pub nonce_counter_repaired: bool,
+}
+
+impl PerbootScrubReport {
+    /// Returns true if the scrub found nothing to repair.
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
 
 #[derive(PartialEq, PartialOrd, Ord, Eq, Hash)]
 struct AuthTokenId {
@@ -61,13 +95,27 @@ impl PartialEq<AuthTokenEntryWrap> for AuthTokenEntryWrap {
 
 impl Eq for AuthTokenEntryWrap {}
 
-/// Per-boot state structure. Currently only used to track auth tokens.
+/// The recently used caller provided nonces of a key, oldest first.
+struct NonceHistory {
+    nonces: VecDeque<Vec<u8>>,
+    last_use: u64,
+}
+
+#[derive(Default)]
+struct CallerNonces {
+    keys: HashMap<i64, NonceHistory>,
+    uses: u64,
+}
+
+/// Per-boot state structure. Tracks auth tokens and caller provided nonces.
 #[derive(Default)]
 pub struct PerbootDB {
-    // We can use a .unwrap() discipline on this lock, because only panicking
-    // while holding a .write() lock will poison it. The only write usage is
-    // an insert call which inserts a pre-constructed pair.
-    auth_tokens: RwLock<HashSet<AuthTokenEntryWrap>>,
+    // Auth tokens are looked up on every begin() of an auth bound key, but only
+    // inserted when the user authenticates. So lookups read the current snapshot
+    // of the table without taking a lock, and inserts replace the snapshot with an
+    // updated copy.
+    auth_tokens: ArcSwap<HashSet<AuthTokenEntryWrap>>,
+    caller_nonces: Mutex<CallerNonces>,
 }
 
 /// The global instance of the perboot DB. Located here rather than in globals
@@ -82,7 +130,12 @@ impl PerbootDB {
     /// Add a new auth token + timestamp to the database, replacing any which
     /// match all of user_id, auth_id, and auth_type.
     pub fn insert_auth_token_entry(&self, entry: AuthTokenEntry) {
-        self.auth_tokens.write().unwrap().replace(AuthTokenEntryWrap(entry));
+        let entry = AuthTokenEntryWrap(entry);
+        self.auth_tokens.rcu(|auth_tokens| {
+            let mut auth_tokens = HashSet::clone(auth_tokens);
+            auth_tokens.replace(entry.clone());
+            auth_tokens
+        });
     }
     /// Locate an auth token entry which matches the predicate with the most
     /// recent update time.
@@ -90,18 +143,97 @@ impl PerbootDB {
         &self,
         p: P,
     ) -> Option<AuthTokenEntry> {
-        let reader = self.auth_tokens.read().unwrap();
-        let mut matches: Vec<_> = reader.iter().filter(|x| p(&x.0)).collect();
-        matches.sort_by_key(|x| x.0.time_received);
-        matches.last().map(|x| x.0.clone())
+        self.auth_tokens
+            .load()
+            .iter()
+            .filter(|x| p(&x.0))
+            .max_by_key(|x| x.0.time_received)
+            .map(|x| x.0.clone())
     }
     /// Return how many auth tokens are currently tracked.
     pub fn auth_tokens_len(&self) -> usize {
-        self.auth_tokens.read().unwrap().len()
+        self.auth_tokens.load().len()
+    }
+    /// Record the use of the caller provided `nonce` with the key `key_id`, remembering
+    /// the last `history` nonces of the key. Returns false, and records nothing, if
+    /// the nonce is among the remembered nonces of the key.
+    pub fn insert_caller_nonce(&self, key_id: i64, nonce: &[u8], history: usize) -> bool {
+        let mut caller_nonces = self.caller_nonces.lock().unwrap();
+        caller_nonces.uses += 1;
+        let last_use = caller_nonces.uses;
+        if !caller_nonces.keys.contains_key(&key_id)
+            && caller_nonces.keys.len() >= MAX_NONCE_TRACKED_KEYS
+        {
+            let lru = caller_nonces.keys.iter().min_by_key(|(_, h)| h.last_use).map(|(k, _)| *k);
+            if let Some(lru) = lru {
+                caller_nonces.keys.remove(&lru);
+            }
+        }
+        let entry = caller_nonces
+            .keys
+            .entry(key_id)
+            .or_insert_with(|| NonceHistory { nonces: VecDeque::new(), last_use });
+        entry.last_use = last_use;
+        if entry.nonces.iter().any(|n| n == nonce) {
+            return false;
+        }
+        if entry.nonces.len() >= history {
+            entry.nonces.pop_front();
+        }
+        entry.nonces.push_back(nonce.to_vec());
+        true
+    }
+    /// Returns the ids of the keys whose caller provided nonces are tracked.
+    pub fn nonce_tracked_key_ids(&self) -> Vec<i64> {
+        self.caller_nonces.lock().unwrap().keys.keys().copied().collect()
+    }
+    /// Checks the invariants of the database at time `now` and repairs violations by dropping
+    /// the offending entries. The nonce histories of the keys for which `is_orphaned` returns
+    /// true are dropped as well. Returns the repairs made.
+    pub fn scrub<F: Fn(i64) -> bool>(&self, now: BootTime, is_orphaned: F) -> PerbootScrubReport {
+        let mut report = PerbootScrubReport::default();
+
+        let needs_repair = {
+            let auth_tokens = self.auth_tokens.load();
+            auth_tokens.len() > MAX_AUTH_TOKENS
+                || auth_tokens.iter().any(|x| x.0.time_received > now)
+        };
+        if needs_repair {
+            self.auth_tokens.rcu(|auth_tokens| {
+                let mut kept: Vec<_> =
+                    auth_tokens.iter().filter(|x| x.0.time_received <= now).cloned().collect();
+                report.future_auth_tokens = auth_tokens.len() - kept.len();
+                kept.sort_by_key(|x| std::cmp::Reverse(x.0.time_received));
+                report.excess_auth_tokens = kept.len().saturating_sub(MAX_AUTH_TOKENS);
+                kept.truncate(MAX_AUTH_TOKENS);
+                kept.into_iter().collect::<HashSet<_>>()
+            });
+        }
+
+        let mut caller_nonces = self.caller_nonces.lock().unwrap();
+        let tracked = caller_nonces.keys.len();
+        caller_nonces.keys.retain(|key_id, _| !is_orphaned(*key_id));
+        report.orphaned_nonce_histories = tracked - caller_nonces.keys.len();
+        let excess = caller_nonces.keys.len().saturating_sub(MAX_NONCE_TRACKED_KEYS);
+        if excess > 0 {
+            let mut by_last_use: Vec<_> =
+                caller_nonces.keys.iter().map(|(key_id, h)| (h.last_use, *key_id)).collect();
+            by_last_use.sort();
+            for (_, key_id) in &by_last_use[..excess] {
+                caller_nonces.keys.remove(key_id);
+            }
+            report.excess_nonce_histories = excess;
+        }
+        let last_use = caller_nonces.keys.values().map(|h| h.last_use).max().unwrap_or(0);
+        if last_use > caller_nonces.uses {
+            caller_nonces.uses = last_use;
+            report.nonce_counter_repaired = true;
+        }
+        report
     }
     #[cfg(test)]
     /// For testing, return all auth tokens currently tracked.
     pub fn get_all_auth_token_entries(&self) -> Vec<AuthTokenEntry> {
-        self.auth_tokens.read().unwrap().iter().cloned().map(|x| x.0).collect()
+        self.auth_tokens.load().iter().cloned().map(|x| x.0).collect()
     }
 }
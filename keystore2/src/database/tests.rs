@@ -20,6 +20,7 @@ use crate::key_parameter::{
     KeyParameterValue, KeyPurpose, PaddingMode, SecurityLevel,
 };
 use crate::key_perm_set;
+use crate::key_rotation::check_retained_key_use;
 use crate::permission::{KeyPerm, KeyPermSet};
 use crate::super_key::{SuperKeyManager, USER_AFTER_FIRST_UNLOCK_SUPER_KEY, SuperEncryptionAlgorithm, SuperKeyType};
 use keystore2_test_utils::TempDir;
@@ -30,10 +31,10 @@ use android_hardware_security_keymint::aidl::android::hardware::security::keymin
 use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::{
     Timestamp::Timestamp,
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
 use std::fmt::Write;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, SystemTime};
@@ -48,7 +49,12 @@ pub fn new_test_db() -> Result<KeystoreDB> {
 fn new_test_db_at(path: &str) -> Result<KeystoreDB> {
     let conn = KeystoreDB::make_connection(path)?;
 
-    let mut db = KeystoreDB { conn, gc: None, perboot: Arc::new(perboot::PerbootDB::new()) };
+    let mut db = KeystoreDB {
+        conn,
+        gc: None,
+        perboot: Arc::new(perboot::PerbootDB::new()),
+        discard_free_pages: false,
+    };
     db.with_transaction(Immediate("TX_new_test_db"), |tx| {
         KeystoreDB::init_tables(tx).context("Failed to initialize tables.").no_gc()
     })?;
@@ -115,13 +121,14 @@ fn test_tables() -> Result<()> {
         .prepare("SELECT name from persistent.sqlite_master WHERE type='table' ORDER BY name;")?
         .query_map(params![], |row| row.get(0))?
         .collect::<rusqlite::Result<Vec<String>>>()?;
-    assert_eq!(tables.len(), 6);
+    assert_eq!(tables.len(), 7);
     assert_eq!(tables[0], "blobentry");
     assert_eq!(tables[1], "blobmetadata");
-    assert_eq!(tables[2], "grant");
-    assert_eq!(tables[3], "keyentry");
-    assert_eq!(tables[4], "keymetadata");
-    assert_eq!(tables[5], "keyparameter");
+    assert_eq!(tables[2], "certchain");
+    assert_eq!(tables[3], "grant");
+    assert_eq!(tables[4], "keyentry");
+    assert_eq!(tables[5], "keymetadata");
+    assert_eq!(tables[6], "keyparameter");
     Ok(())
 }
 
@@ -1075,6 +1082,401 @@ fn test_migrate_key_destination_occupied() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_rename_key_preserves_grants_and_metadata() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 1u32;
+    const GRANTEE_UID: u32 = 2u32;
+    static SOURCE_ALIAS: &str = "SOURCE_ALIAS";
+    static DESTINATION_ALIAS: &str = "DESTINATION_ALIAS";
+    let key_id_guard =
+        make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, SOURCE_ALIAS, None)
+            .context("test_rename_key_preserves_grants_and_metadata")?;
+    let key_id = key_id_guard.id();
+
+    let source_descriptor = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some(SOURCE_ALIAS.to_string()),
+        blob: None,
+    };
+    let destination_descriptor = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some(DESTINATION_ALIAS.to_string()),
+        blob: None,
+    };
+
+    let granted_key = db.grant(
+        &source_descriptor,
+        OWNER_UID,
+        GRANTEE_UID,
+        key_perm_set![KeyPerm::Use],
+        |_k, _av| Ok(()),
+    )?;
+
+    db.rename_key(key_id_guard, DESTINATION_ALIAS, |k| {
+        assert_eq!(Domain::APP, k.domain);
+        assert_eq!(OWNER_UID as i64, k.nspace);
+        assert_eq!(Some(DESTINATION_ALIAS), k.alias.as_deref());
+        Ok(())
+    })?;
+
+    let (_, key_entry) = db.load_key_entry(
+        &destination_descriptor,
+        KeyType::Client,
+        KeyEntryLoadBits::BOTH,
+        OWNER_UID,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(key_entry, make_test_key_entry_test_vector(key_id, None));
+
+    let (_, key_entry) = db.load_key_entry(
+        &granted_key,
+        KeyType::Client,
+        KeyEntryLoadBits::BOTH,
+        GRANTEE_UID,
+        |_k, av| {
+            assert!(av.unwrap().includes(KeyPerm::Use));
+            Ok(())
+        },
+    )?;
+    assert_eq!(key_entry, make_test_key_entry_test_vector(key_id, None));
+
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::KEY_NOT_FOUND)),
+        db.load_key_entry(
+            &source_descriptor,
+            KeyType::Client,
+            KeyEntryLoadBits::NONE,
+            OWNER_UID,
+            |_k, _av| Ok(()),
+        )
+        .unwrap_err()
+        .root_cause()
+        .downcast_ref::<KsError>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rename_key_destination_occupied() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 1u32;
+    static SOURCE_ALIAS: &str = "SOURCE_ALIAS";
+    static DESTINATION_ALIAS: &str = "DESTINATION_ALIAS";
+    let key_id_guard =
+        make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, SOURCE_ALIAS, None)
+            .context("test_rename_key_destination_occupied")?;
+    make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, DESTINATION_ALIAS, None)
+        .context("test_rename_key_destination_occupied")?;
+
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::INVALID_ARGUMENT)),
+        db.rename_key(key_id_guard, DESTINATION_ALIAS, |_k| Ok(()))
+            .unwrap_err()
+            .root_cause()
+            .downcast_ref::<KsError>()
+    );
+
+    Ok(())
+}
+
+/// `IKeystoreService::moveEntry` is served by `rename_key`. A move keeps the certificates, the
+/// grants, the key metadata, and the labels of the key, and the `rebind` permission is checked
+/// on the destination before anything changes.
+#[test]
+fn test_move_entry_keeps_certificates_grants_and_metadata() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 1u32;
+    const GRANTEE_UID: u32 = 2u32;
+    static SOURCE_ALIAS: &str = "SOURCE_ALIAS";
+    static DESTINATION_ALIAS: &str = "DESTINATION_ALIAS";
+    let key_id_guard =
+        make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, SOURCE_ALIAS, None)
+            .context("test_move_entry_keeps_certificates_grants_and_metadata")?;
+    let key_id = key_id_guard.id();
+    let labels: EntryMetadata = [("purpose".to_string(), b"signing".to_vec())].into();
+    db.set_entry_metadata(&key_id_guard, &labels)?;
+    let descriptor = |alias: &str| KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some(alias.to_string()),
+        blob: None,
+    };
+    let granted_key = db.grant(
+        &descriptor(SOURCE_ALIAS),
+        OWNER_UID,
+        GRANTEE_UID,
+        key_perm_set![KeyPerm::Use],
+        |_k, _av| Ok(()),
+    )?;
+    let load = |db: &mut KeystoreDB, key: &KeyDescriptor, uid| {
+        db.load_key_entry(key, KeyType::Client, KeyEntryLoadBits::BOTH, uid, |_k, _av| Ok(()))
+    };
+
+    // The Rebind check runs on the destination, and a denial leaves the key where it was.
+    let checked = RefCell::new(None);
+    let result = db.rename_key(key_id_guard, DESTINATION_ALIAS, |k| {
+        *checked.borrow_mut() = Some(k.clone());
+        Err(KsError::perm()).context("Rebind denied.")
+    });
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::PERMISSION_DENIED)),
+        result.unwrap_err().root_cause().downcast_ref::<KsError>()
+    );
+    assert_eq!(
+        Some(KeyDescriptor { nspace: OWNER_UID as i64, ..descriptor(DESTINATION_ALIAS) }),
+        checked.take()
+    );
+    let (key_id_guard, _) = load(&mut db, &descriptor(SOURCE_ALIAS), OWNER_UID)?;
+    assert_eq!(key_id, key_id_guard.id());
+
+    db.rename_key(key_id_guard, DESTINATION_ALIAS, |_k| Ok(()))?;
+
+    // The key, its certificates, and its metadata are found at the destination.
+    let (key_id_guard, key_entry) = load(&mut db, &descriptor(DESTINATION_ALIAS), OWNER_UID)?;
+    assert_eq!(key_entry, make_test_key_entry_test_vector(key_id, None));
+    assert_eq!(Some(TEST_CERT_BLOB), key_entry.cert.as_deref());
+    assert_eq!(Some(TEST_CERT_CHAIN_BLOB), key_entry.cert_chain.as_deref());
+    assert_eq!(labels, db.get_entry_metadata(&key_id_guard)?);
+    drop(key_id_guard);
+
+    // The grant still refers to the key, and the source alias is free.
+    let (_, key_entry) = load(&mut db, &granted_key, GRANTEE_UID)?;
+    assert_eq!(key_entry, make_test_key_entry_test_vector(key_id, None));
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::KEY_NOT_FOUND)),
+        load(&mut db, &descriptor(SOURCE_ALIAS), OWNER_UID)
+            .unwrap_err()
+            .root_cause()
+            .downcast_ref::<KsError>()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_update_subcomponents_and_grants() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 1u32;
+    const OLD_GRANTEE_UID: u32 = 2u32;
+    const NEW_GRANTEE_UID: u32 = 3u32;
+    static ALIAS: &str = "ALIAS";
+    static NEW_CERT: &[u8] = b"my new test cert";
+    static NEW_CERT_CHAIN: &[u8] = b"my new test cert_chain";
+    let key_id_guard = make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, ALIAS, None)
+        .context("test_update_subcomponents_and_grants")?;
+
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some(ALIAS.to_string()),
+        blob: None,
+    };
+    let old_grant =
+        db.grant(&key, OWNER_UID, OLD_GRANTEE_UID, key_perm_set![KeyPerm::Use], |_k, _av| Ok(()))?;
+    let updates = [
+        GrantUpdate::Ungrant { grantee_uid: OLD_GRANTEE_UID },
+        GrantUpdate::Grant {
+            grantee_uid: NEW_GRANTEE_UID,
+            access_vector: key_perm_set![KeyPerm::GetInfo],
+        },
+    ];
+
+    // A failed permission check leaves the key and its grants untouched.
+    let result = db.update_subcomponents_and_grants(
+        &key_id_guard,
+        Some(NEW_CERT),
+        Some(NEW_CERT_CHAIN),
+        &updates,
+        |_k, update| match update {
+            GrantUpdate::Grant { .. } => Err(KsError::perm()).context("Grant denied."),
+            GrantUpdate::Ungrant { .. } => Ok(()),
+        },
+    );
+    assert_eq!(Some(&KsError::perm()), result.unwrap_err().root_cause().downcast_ref::<KsError>());
+    // Loading the key takes its lock, so the guard must be released first.
+    drop(key_id_guard);
+    let (key_id_guard, mut key_entry) = db.load_key_entry(
+        &old_grant,
+        KeyType::Client,
+        KeyEntryLoadBits::PUBLIC,
+        OLD_GRANTEE_UID,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(key_entry.take_cert().as_deref(), Some(TEST_CERT_BLOB));
+    assert_eq!(key_entry.take_cert_chain().as_deref(), Some(TEST_CERT_CHAIN_BLOB));
+
+    let granted_keys = db.update_subcomponents_and_grants(
+        &key_id_guard,
+        Some(NEW_CERT),
+        Some(NEW_CERT_CHAIN),
+        &updates,
+        |k, _update| {
+            assert_eq!(Domain::APP, k.domain);
+            assert_eq!(OWNER_UID as i64, k.nspace);
+            assert_eq!(Some(ALIAS), k.alias.as_deref());
+            Ok(())
+        },
+    )?;
+    drop(key_id_guard);
+    assert_eq!(granted_keys.len(), 1);
+    assert_eq!(granted_keys[0].domain, Domain::GRANT);
+
+    let (_, mut key_entry) = db.load_key_entry(
+        &granted_keys[0],
+        KeyType::Client,
+        KeyEntryLoadBits::PUBLIC,
+        NEW_GRANTEE_UID,
+        |_k, av| {
+            assert_eq!(*av, Some(key_perm_set![KeyPerm::GetInfo]));
+            Ok(())
+        },
+    )?;
+    assert_eq!(key_entry.take_cert().as_deref(), Some(NEW_CERT));
+    assert_eq!(key_entry.take_cert_chain().as_deref(), Some(NEW_CERT_CHAIN));
+
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::KEY_NOT_FOUND)),
+        db.load_key_entry(
+            &old_grant,
+            KeyType::Client,
+            KeyEntryLoadBits::NONE,
+            OLD_GRANTEE_UID,
+            |_k, _av| Ok(()),
+        )
+        .unwrap_err()
+        .root_cause()
+        .downcast_ref::<KsError>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_load_grants() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 10100;
+    const WORK_PROFILE_UID: u32 = 10 * AID_USER_OFFSET + 10100;
+    const OTHER_APP_UID: u32 = 10101;
+    let key_id_guard = make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, "key", None)?;
+    assert_eq!(db.load_grants(key_id_guard.id())?, vec![]);
+
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    db.grant(&key, OWNER_UID, WORK_PROFILE_UID, key_perm_set![KeyPerm::Use], |_k, _av| Ok(()))?;
+    db.grant(&key, OWNER_UID, OTHER_APP_UID, key_perm_set![KeyPerm::GetInfo], |_k, _av| Ok(()))?;
+    assert_eq!(
+        db.load_grants(key_id_guard.id())?,
+        vec![
+            (OTHER_APP_UID, key_perm_set![KeyPerm::GetInfo]),
+            (WORK_PROFILE_UID, key_perm_set![KeyPerm::Use]),
+        ]
+    );
+
+    db.ungrant(&key, OWNER_UID, OTHER_APP_UID, |_k| Ok(()))?;
+    assert_eq!(
+        db.load_grants(key_id_guard.id())?,
+        vec![(WORK_PROFILE_UID, key_perm_set![KeyPerm::Use])]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_removing_grantee_deletes_grants() -> Result<()> {
+    let mut db = new_test_db()?;
+    const OWNER_UID: u32 = 10100;
+    const GRANTEE_UID: u32 = 10101;
+    const WORK_PROFILE_UID: u32 = 10 * AID_USER_OFFSET + 10102;
+    let key_id_guard = make_test_key_entry(&mut db, Domain::APP, OWNER_UID as i64, "key", None)?;
+    let key_id = key_id_guard.id();
+    drop(key_id_guard);
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let owner_key = KeyDescriptor { nspace: OWNER_UID as i64, ..key.clone() };
+    for grantee in [GRANTEE_UID, WORK_PROFILE_UID] {
+        db.grant(&key, OWNER_UID, grantee, key_perm_set![KeyPerm::Use], |_k, _av| Ok(()))?;
+    }
+    let grantees = |db: &mut KeystoreDB| -> Result<Vec<u32>> {
+        Ok(db.load_all_grants()?.into_iter().map(|(_, _, grantee, _)| grantee).collect())
+    };
+    assert_eq!(
+        db.load_all_grants()?.into_iter().map(|(_, k, _, _)| k).collect::<Vec<_>>(),
+        vec![owner_key.clone(), owner_key]
+    );
+
+    // Removing the grantee app deletes the grant to it but not the granted key.
+    db.unbind_keys_for_namespace(Domain::APP, GRANTEE_UID as i64)?;
+    assert_eq!(grantees(&mut db)?, vec![WORK_PROFILE_UID]);
+    assert_eq!(db.load_grants(key_id)?.len(), 1);
+
+    // Removing the Android user of the grantee deletes the grants to all of its apps.
+    db.unbind_keys_for_user(10)?;
+    assert_eq!(grantees(&mut db)?, vec![]);
+    assert_eq!(1, db.list_past_alias(Domain::APP, OWNER_UID as i64, KeyType::Client, None)?.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_key_provenance_round_trip() -> Result<()> {
+    let mut db = new_test_db()?;
+    let provenance = KeyProvenance {
+        build_fingerprint: Some("generic/device/device:15/BUILD/1:user/release-keys".to_string()),
+        os_patch_level: Some(202401),
+        vendor_patch_level: Some(20240105),
+        boot_patch_level: Some(20240105),
+        attestation_source: Some(AttestationSource::Rkp),
+        km_version: Some(41),
+    };
+    assert_eq!(Some(true), provenance.km_compat_translated());
+
+    let key_id = create_key_entry(&mut db, &Domain::APP, &1, KeyType::Client, &KEYSTORE_UUID)?;
+    let mut metadata = KeyMetaData::new();
+    metadata.add(KeyMetaEntry::CreationDate(DateTime::from_millis_epoch(123456789)));
+    provenance.add_to_metadata(&mut metadata);
+    db.insert_key_metadata(&key_id, &metadata)?;
+    rebind_alias(&mut db, &key_id, TEST_ALIAS, Domain::APP, 1)?;
+
+    let (_, key_entry) = db.load_key_entry(
+        &KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some(TEST_ALIAS.to_string()),
+            blob: None,
+        },
+        KeyType::Client,
+        KeyEntryLoadBits::NONE,
+        1,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(provenance, key_entry.metadata().provenance());
+
+    // Keys created without provenance information report an empty record.
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 1, "no_provenance", None)?;
+    let (_, key_entry) = db.load_key_entry(
+        &KeyDescriptor { domain: Domain::KEY_ID, nspace: key_id.id(), alias: None, blob: None },
+        KeyType::Client,
+        KeyEntryLoadBits::NONE,
+        1,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(KeyProvenance::default(), key_entry.metadata().provenance());
+    assert_eq!(None, key_entry.metadata().provenance().km_compat_translated());
+
+    Ok(())
+}
+
 #[test]
 fn test_upgrade_0_to_1() {
     const ALIAS1: &str = "test_upgrade_0_to_1_1";
@@ -1859,6 +2261,7 @@ fn make_test_key_entry_test_vector(key_id: i64, max_usage_count: Option<i32>) ->
         parameters: params,
         metadata,
         pure_cert: false,
+        lifecycle_state: KeyLifecycleState::Live,
     }
 }
 
@@ -1956,6 +2359,7 @@ fn make_bootlevel_test_key_entry_test_vector(key_id: i64, logical_only: bool) ->
         parameters: params,
         metadata,
         pure_cert: false,
+        lifecycle_state: KeyLifecycleState::Live,
     }
 }
 
@@ -2041,6 +2445,69 @@ fn test_unbind_keys_for_user() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_unbind_keys_by_alias_prefix() -> Result<()> {
+    let mut db = new_test_db()?;
+    for alias in ["contact_1", "contact_2", "contact", "other_contact_3"] {
+        make_test_key_entry(&mut db, Domain::APP, 1, alias, None)?;
+    }
+    make_test_key_entry(&mut db, Domain::APP, 2, "contact_4", None)?;
+    let aliases = |db: &mut KeystoreDB, namespace| -> Result<Vec<String>> {
+        Ok(db
+            .list_past_alias(Domain::APP, namespace, KeyType::Client, None)?
+            .into_iter()
+            .filter_map(|k| k.alias)
+            .collect())
+    };
+
+    // If the permission check fails for any key, no key is unbound.
+    let result = db.unbind_keys_by_alias_prefix(Domain::APP, 1, "contact_", |k, _| {
+        if k.alias.as_deref() == Some("contact_2") {
+            Err(KsError::perm()).context("Permission denied.")
+        } else {
+            Ok(())
+        }
+    });
+    assert!(result.is_err());
+    assert_eq!(4, aliases(&mut db, 1)?.len());
+
+    assert_eq!(2, db.unbind_keys_by_alias_prefix(Domain::APP, 1, "contact_", |_, _| Ok(()))?);
+    assert_eq!(vec!["contact", "other_contact_3"], aliases(&mut db, 1)?);
+    assert_eq!(vec!["contact_4"], aliases(&mut db, 2)?);
+    assert_eq!(0, db.unbind_keys_by_alias_prefix(Domain::APP, 1, "contact_", |_, _| Ok(()))?);
+    Ok(())
+}
+
+#[test]
+fn test_load_key_strength_parameters() -> Result<()> {
+    let mut db = new_test_db()?;
+    make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    make_test_key_entry(&mut db, Domain::SELINUX, 2, "key2", None)?;
+
+    let mut keys = db.load_key_strength_parameters()?;
+    keys.sort_by_key(|(k, _)| k.nspace);
+    assert_eq!(
+        vec![
+            (Domain::APP, 1, Some("key1".to_string())),
+            (Domain::SELINUX, 2, Some("key2".to_string()))
+        ],
+        keys.iter().map(|(k, _)| (k.domain, k.nspace, k.alias.clone())).collect::<Vec<_>>()
+    );
+    for (_, params) in &keys {
+        let mut tags: Vec<Tag> = params.iter().map(|p| p.get_tag()).collect();
+        tags.sort_by_key(|t| t.0);
+        tags.dedup();
+        assert_eq!(vec![Tag::ALGORITHM, Tag::EC_CURVE, Tag::KEY_SIZE], tags);
+    }
+
+    // Unbound keys are not reported.
+    db.unbind_keys_by_alias_prefix(Domain::APP, 1, "key", |_, _| Ok(()))?;
+    let keys = db.load_key_strength_parameters()?;
+    assert_eq!(1, keys.len());
+    assert_eq!(Domain::SELINUX, keys[0].0.domain);
+    Ok(())
+}
+
 #[test]
 fn test_unbind_keys_for_user_removes_superkeys() -> Result<()> {
     let mut db = new_test_db()?;
@@ -2415,18 +2882,137 @@ fn find_auth_token_entry_returns_latest() -> Result<()> {
     Ok(())
 }
 
-fn blob_count(db: &mut KeystoreDB, sc_type: SubComponentType) -> usize {
-    db.with_transaction(TransactionBehavior::Deferred, |tx| {
-        tx.query_row(
-            "SELECT COUNT(*) FROM persistent.blobentry
-                     WHERE subcomponent_type = ?;",
-            params![sc_type],
-            |row| row.get(0),
-        )
-        .context(ks_err!("Failed to count number of {sc_type:?} blobs"))
-        .no_gc()
-    })
-    .unwrap()
+#[test]
+fn test_scrub_perboot() -> Result<()> {
+    let mut db = new_test_db()?;
+    let token = |user_id: i64| HardwareAuthToken {
+        challenge: 0,
+        userId: user_id,
+        authenticatorId: 789,
+        authenticatorType: kmhw_authenticator_type::ANY,
+        timestamp: Timestamp { milliSeconds: 0 },
+        mac: b"mac".to_vec(),
+    };
+    db.insert_auth_token(&token(1));
+    let future = BootTime(BootTime::now().milliseconds() + 60_000);
+    db.perboot.insert_auth_token_entry(AuthTokenEntry::new(token(2), future));
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 1, TEST_ALIAS, None)?.id();
+    let orphaned_key_id = key_id.wrapping_add(1);
+    assert!(db.insert_caller_nonce(key_id, b"nonce", 4));
+    assert!(db.insert_caller_nonce(orphaned_key_id, b"nonce", 4));
+
+    assert_eq!(
+        PerbootScrubReport {
+            future_auth_tokens: 1,
+            orphaned_nonce_histories: 1,
+            ..Default::default()
+        },
+        db.scrub_perboot()?
+    );
+    assert_eq!(
+        vec![1],
+        get_auth_tokens(&db).iter().map(|e| e.auth_token.userId).collect::<Vec<_>>()
+    );
+    // The history of the existing key is kept.
+    assert!(!db.insert_caller_nonce(key_id, b"nonce", 4));
+    assert!(db.insert_caller_nonce(orphaned_key_id, b"nonce", 4));
+    assert_eq!(1, db.scrub_perboot()?.orphaned_nonce_histories);
+    assert!(db.scrub_perboot()?.is_clean());
+    Ok(())
+}
+
+// Contention benchmark for the auth token lookups in the begin() path. Runs lookups from a
+// growing number of threads while another thread keeps inserting tokens, and prints the lookup
+// throughput. Lookups do not take a lock, so the throughput should grow with the number of
+// threads. Use "--nocapture" to see the numbers.
+#[test]
+fn auth_token_lookup_contention() {
+    const DURATION: Duration = Duration::from_millis(200);
+    let perboot = Arc::new(perboot::PerbootDB::new());
+    let token = |user_id: i64| HardwareAuthToken {
+        challenge: 0,
+        userId: user_id,
+        authenticatorId: 789,
+        authenticatorType: kmhw_authenticator_type::ANY,
+        timestamp: Timestamp { milliSeconds: 0 },
+        mac: b"mac".to_vec(),
+    };
+    for user_id in 0..16 {
+        perboot.insert_auth_token_entry(AuthTokenEntry::new(token(user_id), BootTime::now()));
+    }
+
+    for readers in [1, 2, 4, 8] {
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (perboot, stop) = (perboot.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut inserts = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    let entry = AuthTokenEntry::new(token(inserts as i64 % 16), BootTime::now());
+                    perboot.insert_auth_token_entry(entry);
+                    inserts += 1;
+                    thread::sleep(Duration::from_micros(100));
+                }
+                inserts
+            })
+        };
+        let handles: Vec<_> = (0..readers)
+            .map(|i| {
+                let (perboot, stop) = (perboot.clone(), stop.clone());
+                thread::spawn(move || {
+                    let mut lookups = 0u64;
+                    while !stop.load(Ordering::Relaxed) {
+                        let user_id = (i + lookups) as i64 % 16;
+                        let found =
+                            perboot.find_auth_token_entry(|e| e.auth_token.userId == user_id);
+                        assert!(found.is_some(), "Token of user {user_id} went missing.");
+                        lookups += 1;
+                    }
+                    lookups
+                })
+            })
+            .collect();
+        thread::sleep(DURATION);
+        stop.store(true, Ordering::Relaxed);
+        let lookups: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        let inserts = writer.join().unwrap();
+        println!(
+            "{readers} reader(s): {} lookups/s with {} inserts/s",
+            lookups * 1000 / DURATION.as_millis() as u64,
+            inserts * 1000 / DURATION.as_millis() as u64
+        );
+        assert_eq!(perboot.auth_tokens_len(), 16);
+    }
+}
+
+#[test]
+fn test_insert_caller_nonce() -> Result<()> {
+    let db = new_test_db()?;
+    assert!(db.insert_caller_nonce(1, b"nonce1", 2));
+    assert!(db.insert_caller_nonce(1, b"nonce2", 2));
+    // Reuse is rejected, also with another history size, and nonces are tracked per key.
+    assert!(!db.insert_caller_nonce(1, b"nonce1", 2));
+    assert!(!db.insert_caller_nonce(1, b"nonce2", 3));
+    assert!(db.insert_caller_nonce(2, b"nonce1", 2));
+    // The oldest nonce is forgotten when the history is full.
+    assert!(db.insert_caller_nonce(1, b"nonce3", 2));
+    assert!(db.insert_caller_nonce(1, b"nonce1", 2));
+    assert!(!db.insert_caller_nonce(1, b"nonce3", 2));
+    Ok(())
+}
+
+fn blob_count(db: &mut KeystoreDB, sc_type: SubComponentType) -> usize {
+    db.with_transaction(TransactionBehavior::Deferred, |tx| {
+        tx.query_row(
+            "SELECT COUNT(*) FROM persistent.blobentry
+                     WHERE subcomponent_type = ?;",
+            params![sc_type],
+            |row| row.get(0),
+        )
+        .context(ks_err!("Failed to count number of {sc_type:?} blobs"))
+        .no_gc()
+    })
+    .unwrap()
 }
 
 #[test]
@@ -2490,6 +3076,285 @@ fn test_blobentry_gc() -> Result<()> {
     Ok(())
 }
 
+fn cert_chain_count(db: &mut KeystoreDB) -> usize {
+    db.with_transaction(TransactionBehavior::Deferred, |tx| {
+        tx.query_row("SELECT COUNT(*) FROM persistent.certchain;", [], |row| row.get(0))
+            .context(ks_err!("Failed to count number of shared certificate chains"))
+            .no_gc()
+    })
+    .unwrap()
+}
+
+fn load_cert_chain(db: &mut KeystoreDB, namespace: i64, alias: &str) -> Option<Vec<u8>> {
+    let (_, mut key_entry) = db
+        .load_key_entry(
+            &KeyDescriptor {
+                domain: Domain::APP,
+                nspace: namespace,
+                alias: Some(alias.to_string()),
+                blob: None,
+            },
+            KeyType::Client,
+            KeyEntryLoadBits::PUBLIC,
+            namespace as u32,
+            |_k, _av| Ok(()),
+        )
+        .unwrap();
+    key_entry.take_cert_chain()
+}
+
+#[test]
+fn test_shared_cert_chain() -> Result<()> {
+    let mut db = new_test_db()?;
+    let chain = vec![0x42; KeystoreDB::MIN_SHARED_CERT_CHAIN_SIZE];
+    let key_guard1 = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    let key_guard2 = make_test_key_entry(&mut db, Domain::APP, 2, "key2", None)?;
+    let _key_guard3 = make_test_key_entry(&mut db, Domain::APP, 3, "key3", None)?;
+
+    // Identical large chains are stored once and replace the inline chains.
+    db.set_blob(&key_guard1, SubComponentType::CERT_CHAIN, Some(&chain), None)?;
+    db.set_blob(&key_guard2, SubComponentType::CERT_CHAIN, Some(&chain), None)?;
+    assert_eq!(1, blob_count(&mut db, SubComponentType::CERT_CHAIN));
+    assert_eq!(2, blob_count(&mut db, SubComponentType::CERT_CHAIN_REF));
+    assert_eq!(1, cert_chain_count(&mut db));
+    assert_eq!(Some(chain.clone()), load_cert_chain(&mut db, 1, "key1"));
+    assert_eq!(Some(chain.clone()), load_cert_chain(&mut db, 2, "key2"));
+    assert_eq!(Some(TEST_CERT_CHAIN_BLOB.to_vec()), load_cert_chain(&mut db, 3, "key3"));
+
+    // Replacing a shared chain with an inline chain removes the reference.
+    db.set_blob(&key_guard1, SubComponentType::CERT_CHAIN, Some(TEST_CERT_CHAIN_BLOB), None)?;
+    assert_eq!(1, blob_count(&mut db, SubComponentType::CERT_CHAIN_REF));
+    assert_eq!(Some(TEST_CERT_CHAIN_BLOB.to_vec()), load_cert_chain(&mut db, 1, "key1"));
+
+    // The shared chain is garbage collected once the last key referring to it is gone.
+    db.set_blob(&key_guard2, SubComponentType::CERT_CHAIN, None, None)?;
+    assert_eq!(None, load_cert_chain(&mut db, 2, "key2"));
+    assert_eq!(1, cert_chain_count(&mut db));
+    assert!(db.handle_next_superseded_blobs(&[], 20)?.is_empty());
+    assert_eq!(0, cert_chain_count(&mut db));
+    Ok(())
+}
+
+#[test]
+fn test_upgrade_to_shared_cert_chains() -> Result<()> {
+    let mut db = new_test_db()?;
+    let chain = vec![0x42; KeystoreDB::MIN_SHARED_CERT_CHAIN_SIZE];
+    for (namespace, alias) in [(1, "key1"), (2, "key2")] {
+        let key_guard = make_test_key_entry(&mut db, Domain::APP, namespace, alias, None)?;
+        // Store the chain inline, as databases of version 1 did.
+        db.with_transaction(Immediate("TX_test"), |tx| {
+            tx.execute(
+                "INSERT INTO persistent.blobentry
+                 (subcomponent_type, keyentryid, blob) VALUES (?, ?, ?);",
+                params![SubComponentType::CERT_CHAIN, key_guard.id(), chain],
+            )
+            .context("Failed to insert inline chain.")
+            .no_gc()
+        })?;
+    }
+    let _key_guard3 = make_test_key_entry(&mut db, Domain::APP, 3, "key3", None)?;
+
+    db.with_transaction(Immediate("TX_test"), |tx| KeystoreDB::from_1_to_2(tx).no_gc())?;
+
+    assert_eq!(2, blob_count(&mut db, SubComponentType::CERT_CHAIN_REF));
+    assert_eq!(1, cert_chain_count(&mut db));
+    assert_eq!(Some(chain.clone()), load_cert_chain(&mut db, 1, "key1"));
+    assert_eq!(Some(chain), load_cert_chain(&mut db, 2, "key2"));
+    assert_eq!(Some(TEST_CERT_CHAIN_BLOB.to_vec()), load_cert_chain(&mut db, 3, "key3"));
+    Ok(())
+}
+
+#[test]
+fn test_diagnostic_views() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 10100, "key", None)?.0;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    db.grant(&key, 10100, 10101, key_perm_set![KeyPerm::Use], |_k, _av| Ok(()))?;
+
+    let keys: Vec<(i64, i32, i64, String, String, String, i64)> = db
+        .conn
+        .prepare(
+            "SELECT key_id, domain, namespace, alias, key_type, state, creation_date_ms
+             FROM persistent.diag_v1_keys;",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(
+        keys,
+        vec![(
+            key_id,
+            Domain::APP.0,
+            10100,
+            "key".to_string(),
+            "client".to_string(),
+            "live".to_string(),
+            123456789
+        )]
+    );
+
+    let parameter_count: usize = db.conn.query_row(
+        "SELECT COUNT(*) FROM persistent.diag_v1_key_parameters WHERE key_id = ?;",
+        params![key_id],
+        |row| row.get(0),
+    )?;
+    assert_eq!(parameter_count, make_test_params(None).len());
+
+    let grants: Vec<(u32, i64)> = db
+        .conn
+        .prepare("SELECT grantee_uid, key_id FROM persistent.diag_v1_grants;")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(grants, vec![(10101, key_id)]);
+
+    // The upgrade to version 3 adds the views to existing databases.
+    db.conn.execute("DROP VIEW persistent.diag_v1_keys;", [])?;
+    db.with_transaction(Immediate("TX_test"), |tx| KeystoreDB::from_2_to_3(tx).no_gc())?;
+    let version: i64 =
+        db.conn.query_row("SELECT version FROM persistent.diag_versions;", [], |row| row.get(0))?;
+    assert_eq!(version, 1);
+    let key_count: usize =
+        db.conn.query_row("SELECT COUNT(*) FROM persistent.diag_v1_keys;", [], |row| row.get(0))?;
+    assert_eq!(key_count, 1);
+    Ok(())
+}
+
+#[test]
+fn test_count_superseded_blobs() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_guard1 = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    let key_id2 = make_test_key_entry(&mut db, Domain::APP, 2, "key2", None)?.0;
+    let _key_id3 = make_test_key_entry(&mut db, Domain::APP, 3, "key3", None)?.0;
+    assert_eq!(0, db.count_superseded_blobs()?);
+
+    // Superseding the keyblob of key 1 leaves one blob for the garbage collector.
+    db.set_blob(&key_guard1, SubComponentType::KEY_BLOB, Some(&[1, 2, 3]), None)?;
+    assert_eq!(1, db.count_superseded_blobs()?);
+
+    // The keyblob of an unreferenced key counts even before the key entry is cleaned up.
+    db.with_transaction(Immediate("TX_delete_test_keys"), |tx| {
+        KeystoreDB::mark_unreferenced(tx, key_id2)?;
+        Ok(()).no_gc()
+    })
+    .unwrap();
+    assert_eq!(2, db.count_superseded_blobs()?);
+
+    // Handing out the blobs does not change the count, only deleting them does.
+    let superseded = db.handle_next_superseded_blobs(&[], 20)?;
+    assert_eq!(2, superseded.len());
+    assert_eq!(2, db.count_superseded_blobs()?);
+    let superseded_ids: Vec<i64> = superseded.iter().map(|v| v.blob_id).collect();
+    db.handle_next_superseded_blobs(&superseded_ids, 0)?;
+    assert_eq!(0, db.count_superseded_blobs()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_release_free_pages() -> Result<()> {
+    let temp_dir = TempDir::new("test_release_free_pages")?;
+    let mut db = KeystoreDB::new(temp_dir.path(), None)?;
+    // Blobs larger than a page are stored in overflow pages.
+    let key_guard = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    db.set_blob(&key_guard, SubComponentType::KEY_BLOB, Some(&[0xaa; 20000]), None)?;
+    db.set_blob(&key_guard, SubComponentType::KEY_BLOB, Some(&[0xbb; 100]), None)?;
+    make_test_key_entry(&mut db, Domain::APP, 2, "key2", None)?;
+
+    // Existing databases are converted to the incremental auto vacuum mode.
+    discard::setup(&db.conn)?;
+    db.discard_free_pages = true;
+    let auto_vacuum: i64 =
+        db.conn.query_row("PRAGMA persistent.auto_vacuum;", params![], |row| row.get(0))?;
+    assert_eq!(2, auto_vacuum);
+    let db_file = temp_dir.path().join(KeystoreDB::PERSISTENT_DB_FILENAME);
+    let file_size = || std::fs::metadata(&db_file).unwrap().len();
+
+    let superseded = db.handle_next_superseded_blobs(&[], 20)?;
+    let superseded_ids: Vec<i64> = superseded.iter().map(|v| v.blob_id).collect();
+    assert_eq!(2, superseded_ids.len());
+    db.handle_next_superseded_blobs(&superseded_ids, 0)?;
+    db.conn.query_row("PRAGMA persistent.wal_checkpoint(TRUNCATE);", params![], |_| Ok(()))?;
+    let size_before = file_size();
+
+    let released = db.release_free_pages()?;
+    assert!(released >= 4, "Expected overflow pages to be released: {released}");
+    assert_eq!(0, db.release_free_pages()?);
+    let free_pages: u32 =
+        db.conn.query_row("PRAGMA persistent.freelist_count;", params![], |row| row.get(0))?;
+    assert_eq!(0, free_pages);
+    assert!(file_size() < size_before, "The database file was not truncated.");
+
+    // The database is intact and the remaining keys are unaffected.
+    let integrity: String =
+        db.conn.query_row("PRAGMA persistent.integrity_check;", params![], |row| row.get(0))?;
+    assert_eq!("ok", integrity);
+    drop(key_guard);
+    let (key_guard, key_entry) = db.load_key_entry(
+        &KeyDescriptor {
+            domain: Domain::APP,
+            nspace: -1,
+            alias: Some("key1".to_string()),
+            blob: None,
+        },
+        KeyType::Client,
+        KeyEntryLoadBits::KM,
+        1,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(Some(&[0xbb; 100][..]), key_entry.key_blob_info().as_ref().map(|(b, _)| &b[..]));
+
+    // The database grows again as usual.
+    db.set_blob(&key_guard, SubComponentType::KEY_BLOB, Some(&[0xcc; 20000]), None)?;
+    let integrity: String =
+        db.conn.query_row("PRAGMA persistent.integrity_check;", params![], |row| row.get(0))?;
+    assert_eq!("ok", integrity);
+
+    Ok(())
+}
+
+#[test]
+fn test_reclaimable_size_and_compact() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_guard = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    let baseline = db.get_storage_stat(MetricsStorage::RECLAIMABLE)?;
+    assert!(baseline.size >= baseline.unused_size);
+
+    // A superseded keyblob adds its size to the reclaimable storage.
+    db.set_blob(&key_guard, SubComponentType::KEY_BLOB, Some(&[0; 1000]), None)?;
+    let stat = db.get_storage_stat(MetricsStorage::RECLAIMABLE)?;
+    assert_eq!(
+        stat.size - stat.unused_size,
+        baseline.size - baseline.unused_size + TEST_KEY_BLOB.len() as i32
+    );
+
+    // Once the garbage collector is done with it, only free pages are left for compaction,
+    // which gives them back.
+    let superseded = db.handle_next_superseded_blobs(&[], 20)?;
+    let superseded_ids: Vec<i64> = superseded.iter().map(|v| v.blob_id).collect();
+    db.handle_next_superseded_blobs(&superseded_ids, 0)?;
+    let stat = db.get_storage_stat(MetricsStorage::RECLAIMABLE)?;
+    assert_eq!(stat.size, stat.unused_size);
+
+    db.compact()?;
+    assert_eq!(0, db.get_storage_stat(MetricsStorage::RECLAIMABLE)?.size);
+
+    Ok(())
+}
+
 #[test]
 fn test_load_key_descriptor() -> Result<()> {
     let mut db = new_test_db()?;
@@ -2753,3 +3618,565 @@ fn test_list_keys_with_many_keys() -> Result<()> {
         }
     })
 }
+
+#[test]
+fn test_get_namespace_usage() -> Result<()> {
+    let mut db = new_test_db()?;
+    assert_eq!(db.get_namespace_usage(Domain::SELINUX, 100, None)?, (0, 0));
+
+    make_test_key_entry(&mut db, Domain::SELINUX, 100, "key1", None)?;
+    make_test_key_entry(&mut db, Domain::SELINUX, 100, "key2", None)?;
+    make_test_key_entry(&mut db, Domain::SELINUX, 101, "key1", None)?;
+    let key_size = TEST_KEY_BLOB.len() + TEST_CERT_BLOB.len() + TEST_CERT_CHAIN_BLOB.len();
+    assert_eq!(db.get_namespace_usage(Domain::SELINUX, 100, None)?, (2, 2 * key_size));
+    assert_eq!(db.get_namespace_usage(Domain::SELINUX, 100, Some("key1"))?, (1, key_size));
+    assert_eq!(db.get_namespace_usage(Domain::SELINUX, 100, Some("key3"))?, (2, 2 * key_size));
+    assert_eq!(db.get_namespace_usage(Domain::APP, 100, None)?, (0, 0));
+    Ok(())
+}
+
+#[test]
+fn test_list_past_alias_limited() -> Result<()> {
+    let mut db = new_test_db()?;
+    for alias in ["key_c", "key_a", "key_d", "key_b"] {
+        make_test_key_entry(&mut db, Domain::APP, 1, alias, None)?;
+    }
+    let mut list = |start_past_alias: Option<&str>, limit: Option<usize>| -> Result<Vec<String>> {
+        Ok(db
+            .list_past_alias_limited(Domain::APP, 1, KeyType::Client, start_past_alias, limit)?
+            .into_iter()
+            .map(|k| k.alias.unwrap())
+            .collect())
+    };
+    assert_eq!(list(None, None)?, vec!["key_a", "key_b", "key_c", "key_d"]);
+    assert_eq!(list(None, Some(2))?, vec!["key_a", "key_b"]);
+    assert_eq!(list(Some("key_b"), Some(1))?, vec!["key_c"]);
+    assert_eq!(list(Some("key_b"), Some(10))?, vec!["key_c", "key_d"]);
+    assert!(list(None, Some(0))?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_list_past_alias_filtered() -> Result<()> {
+    let mut db = new_test_db()?;
+    make_test_key_entry_with_sids(&mut db, Domain::APP, 1, "bound", None, &[42])?;
+    make_test_key_entry_with_sids(&mut db, Domain::APP, 1, "unbound", None, &[])?;
+    make_test_key_entry(&mut db, Domain::APP, 2, "other", None)?;
+
+    let mut list = |filter: KeyEntryFilter| -> Result<Vec<String>> {
+        Ok(db
+            .list_past_alias_filtered(Domain::APP, 1, KeyType::Client, None, &filter)?
+            .into_iter()
+            .map(|k| k.alias.unwrap())
+            .collect())
+    };
+    assert_eq!(list(KeyEntryFilter::default())?, vec!["bound", "unbound"]);
+    assert_eq!(
+        list(KeyEntryFilter { auth_bound: Some(true), ..Default::default() })?,
+        vec!["bound"]
+    );
+    assert_eq!(
+        list(KeyEntryFilter { auth_bound: Some(false), ..Default::default() })?,
+        vec!["unbound"]
+    );
+    let rsa = KeyEntryFilter {
+        algorithm: Some(Algorithm::RSA),
+        origin: Some(KeyOrigin::GENERATED),
+        ..Default::default()
+    };
+    assert_eq!(list(rsa)?, vec!["bound", "unbound"]);
+    let ec = KeyEntryFilter { algorithm: Some(Algorithm::EC), ..Default::default() };
+    assert!(list(ec)?.is_empty());
+    let imported = KeyEntryFilter { origin: Some(KeyOrigin::IMPORTED), ..Default::default() };
+    assert!(list(imported)?.is_empty());
+
+    // The test keys are created at 123456789.
+    let created = KeyEntryFilter {
+        created_after: Some(DateTime::from_millis_epoch(123456789)),
+        created_before: Some(DateTime::from_millis_epoch(123456790)),
+        ..Default::default()
+    };
+    assert_eq!(list(created)?, vec!["bound", "unbound"]);
+    let later = KeyEntryFilter {
+        created_after: Some(DateTime::from_millis_epoch(123456790)),
+        ..Default::default()
+    };
+    assert!(list(later)?.is_empty());
+    let earlier = KeyEntryFilter {
+        created_before: Some(DateTime::from_millis_epoch(123456789)),
+        ..Default::default()
+    };
+    assert!(list(earlier)?.is_empty());
+
+    let filter = KeyEntryFilter { auth_bound: Some(false), ..Default::default() };
+    assert_eq!(
+        db.list_past_alias_filtered(Domain::APP, 1, KeyType::Client, Some("bound"), &filter)?,
+        vec![KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some("unbound".to_string()),
+            blob: None
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_record_key_usage() -> Result<()> {
+    let mut db = new_test_db()?;
+    let used = make_test_key_entry(&mut db, Domain::APP, 1, "used", None)?.id();
+    let unused = make_test_key_entry(&mut db, Domain::APP, 1, "unused", None)?.id();
+    let deleted = make_test_key_entry(&mut db, Domain::APP, 1, "deleted", None)?.id();
+    db.unbind_key(
+        &KeyDescriptor { domain: Domain::KEY_ID, nspace: deleted, alias: None, blob: None },
+        KeyType::Client,
+        1,
+        |_, _| Ok(()),
+    )?;
+
+    let t1 = DateTime::from_millis_epoch(1000);
+    let t2 = DateTime::from_millis_epoch(2000);
+    let stats = |operation_count, last_used| KeyUsageStats { operation_count, last_used };
+    db.record_key_usage(&[(used, stats(2, Some(t2))), (deleted, stats(1, Some(t1)))])?;
+    // Batches add up, and an earlier date does not replace a later one.
+    db.record_key_usage(&[(used, stats(3, Some(t1)))])?;
+
+    let (_, entry) = db.load_key_entry(
+        &KeyDescriptor { domain: Domain::KEY_ID, nspace: used, alias: None, blob: None },
+        KeyType::Client,
+        KeyEntryLoadBits::NONE,
+        1,
+        |_, _| Ok(()),
+    )?;
+    assert_eq!(entry.metadata().usage_stats(), stats(5, Some(t2)));
+
+    // Keys that were never used come first, deleted keys are not listed.
+    assert_eq!(
+        db.list_least_recently_used_keys(10)?,
+        vec![(unused, Domain::APP, 1, stats(0, None)), (used, Domain::APP, 1, stats(5, Some(t2)))]
+    );
+    assert_eq!(db.list_least_recently_used_keys(1)?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_get_session_bound_key_ids() -> Result<()> {
+    let mut db = new_test_db()?;
+    let mut session_bound = KeyMetaData::new();
+    session_bound.add(KeyMetaEntry::SessionBound(true));
+    let bound = make_test_key_entry(&mut db, Domain::APP, 1, "bound", None)?;
+    db.insert_key_metadata(&bound, &session_bound)?;
+    make_test_key_entry(&mut db, Domain::APP, 1, "unbound", None)?;
+    let deleted = make_test_key_entry(&mut db, Domain::APP, 1, "deleted", None)?;
+    db.insert_key_metadata(&deleted, &session_bound)?;
+    db.unbind_key(
+        &KeyDescriptor { domain: Domain::KEY_ID, nspace: deleted.id(), alias: None, blob: None },
+        KeyType::Client,
+        1,
+        |_, _| Ok(()),
+    )?;
+
+    assert_eq!(db.get_session_bound_key_ids()?, vec![bound.id()]);
+    Ok(())
+}
+
+#[test]
+fn test_entry_metadata() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 1, "key", None)?;
+    let other_id = make_test_key_entry(&mut db, Domain::APP, 2, "other", None)?;
+    assert!(db.get_entry_metadata(&key_id)?.is_empty());
+
+    let metadata = EntryMetadata::from([
+        ("purpose".to_string(), b"signing".to_vec()),
+        ("rotation-generation".to_string(), vec![3]),
+    ]);
+    db.set_entry_metadata(&key_id, &metadata)?;
+    db.set_entry_metadata(&other_id, &metadata)?;
+    assert_eq!(db.get_entry_metadata(&key_id)?, metadata);
+
+    // Setting the labels replaces all of them.
+    let replaced = EntryMetadata::from([("rotation-generation".to_string(), vec![4])]);
+    db.set_entry_metadata(&key_id, &replaced)?;
+    assert_eq!(db.get_entry_metadata(&key_id)?, replaced);
+    assert_eq!(db.get_entry_metadata(&other_id)?, metadata);
+
+    // The labels are deleted with the key.
+    db.unbind_keys_for_namespace(Domain::APP, 1)?;
+    assert!(db.get_entry_metadata(&key_id)?.is_empty());
+    assert_eq!(db.get_entry_metadata(&other_id)?, metadata);
+    Ok(())
+}
+
+#[test]
+fn test_read_only_connection() -> Result<()> {
+    let temp_dir = TempDir::new("test_read_only_connection_")?;
+    let mut db = KeystoreDB::new(temp_dir.path(), None)?;
+    make_test_key_entry(&mut db, Domain::APP, 1, TEST_ALIAS, None)?;
+
+    let mut reader = KeystoreDB::new_read_only(temp_dir.path())?;
+    let keys = reader.list_past_alias(Domain::APP, 1, KeyType::Client, None)?;
+    assert_eq!(
+        keys,
+        vec![KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some(TEST_ALIAS.to_string()),
+            blob: None,
+        }]
+    );
+    assert_eq!(reader.count_keys(Domain::APP, 1, KeyType::Client)?, 1);
+
+    // Keys stored after the reader was opened must be visible, too.
+    make_test_key_entry(&mut db, Domain::APP, 1, "test_alias_2", None)?;
+    assert_eq!(reader.count_keys(Domain::APP, 1, KeyType::Client)?, 2);
+
+    // Writes must fail on the read-only connection.
+    assert!(make_test_key_entry(&mut reader, Domain::APP, 1, "test_alias_3", None).is_err());
+    assert_eq!(db.count_keys(Domain::APP, 1, KeyType::Client)?, 2);
+
+    Ok(())
+}
+
+/// Measures how long it takes to store `write_count` keys while another thread continuously
+/// lists all keys of a well populated namespace using either a read-only or a regular
+/// connection.
+fn measure_write_latency_while_listing(read_only: bool, write_count: usize) -> Result<Duration> {
+    let temp_dir = Arc::new(TempDir::new("write_latency_while_listing_")?);
+    let mut db = KeystoreDB::new(temp_dir.path(), None)?;
+    db_populate_keys(&mut db, 0, 10_000);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader_temp_dir = temp_dir.clone();
+    let reader_stop = stop.clone();
+    let reader = thread::spawn(move || {
+        let mut reader = if read_only {
+            KeystoreDB::new_read_only(reader_temp_dir.path())
+        } else {
+            KeystoreDB::new(reader_temp_dir.path(), None)
+        }
+        .expect("Failed to open database.");
+        while !reader_stop.load(Ordering::Relaxed) {
+            reader
+                .list_past_alias(Domain::APP, 10001, KeyType::Client, None)
+                .expect("Failed to list keys.");
+        }
+    });
+
+    let start = Instant::now();
+    for count in 0..write_count {
+        make_test_key_entry(&mut db, Domain::APP, 1, &format!("test_alias_{count}"), None)?;
+    }
+    let elapsed = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    reader.join().expect("Reader thread panicked.");
+    Ok(elapsed)
+}
+
+#[test]
+fn test_write_latency_while_listing_keys() -> Result<()> {
+    const WRITE_COUNT: usize = 200;
+    let read_only = measure_write_latency_while_listing(true, WRITE_COUNT)?;
+    let read_write = measure_write_latency_while_listing(false, WRITE_COUNT)?;
+    println!("\nConnection,writes,time_in_s");
+    println!("read_only, {WRITE_COUNT}, {}", read_only.as_secs_f64());
+    println!("read_write, {WRITE_COUNT}, {}", read_write.as_secs_f64());
+    Ok(())
+}
+
+#[test]
+fn test_uuid_for_keymint_instance() {
+    assert_eq!(
+        Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, ""),
+        Some(Uuid::from(SecurityLevel::STRONGBOX))
+    );
+    let esim = Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, "esim").unwrap();
+    assert_ne!(esim, Uuid::from(SecurityLevel::STRONGBOX));
+    assert_ne!(esim, Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, "ese").unwrap());
+    assert_ne!(
+        esim,
+        Uuid::for_keymint_instance(SecurityLevel::TRUSTED_ENVIRONMENT, "esim").unwrap()
+    );
+    assert!(Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, "twelve_bytes").is_some());
+    assert_eq!(Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, "thirteen_byte"), None);
+}
+
+fn load_lifecycle_state(db: &mut KeystoreDB, namespace: i64, alias: &str) -> KeyLifecycleState {
+    let (_, key_entry) = db
+        .load_key_entry(
+            &KeyDescriptor {
+                domain: Domain::APP,
+                nspace: namespace,
+                alias: Some(alias.to_string()),
+                blob: None,
+            },
+            KeyType::Client,
+            KeyEntryLoadBits::NONE,
+            namespace as u32,
+            |_k, _av| Ok(()),
+        )
+        .unwrap();
+    key_entry.lifecycle_state()
+}
+
+#[test]
+fn test_key_lifecycle_state() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_guard1 = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    let key_guard2 = make_test_key_entry(&mut db, Domain::APP, 2, "key2", None)?;
+    assert_eq!(KeyLifecycleState::Live, load_lifecycle_state(&mut db, 1, "key1"));
+
+    // A key whose certificate expires soon is still usable but flagged.
+    let expiration = DateTime::from_millis_epoch(
+        DateTime::now()?.to_millis_epoch() + EXPIRING_CERT_WINDOW_MS / 2,
+    );
+    let mut metadata = KeyMetaData::new();
+    metadata.add(KeyMetaEntry::MaxValidityExpirationDate(expiration));
+    db.insert_key_metadata(&key_guard2, &metadata)?;
+    assert_eq!(KeyLifecycleState::ExpiringCert, load_lifecycle_state(&mut db, 2, "key2"));
+
+    // Invalidation takes precedence and keeps the date of the first report.
+    db.mark_key_invalidated(key_guard1.id())?;
+    let (_, key_entry) = db.load_key_entry(
+        &KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some("key1".to_string()),
+            blob: None,
+        },
+        KeyType::Client,
+        KeyEntryLoadBits::NONE,
+        1,
+        |_k, _av| Ok(()),
+    )?;
+    let invalidation_date = key_entry.metadata().invalidation_date().copied();
+    assert!(invalidation_date.is_some());
+    db.mark_key_invalidated(key_guard1.id())?;
+    db.mark_key_invalidated(key_guard2.id())?;
+    assert_eq!(KeyLifecycleState::Invalidated, load_lifecycle_state(&mut db, 1, "key1"));
+    assert_eq!(KeyLifecycleState::Invalidated, load_lifecycle_state(&mut db, 2, "key2"));
+    let (_, key_entry) = db.load_key_entry(
+        &KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some("key1".to_string()),
+            blob: None,
+        },
+        KeyType::Client,
+        KeyEntryLoadBits::NONE,
+        1,
+        |_k, _av| Ok(()),
+    )?;
+    assert_eq!(invalidation_date.as_ref(), key_entry.metadata().invalidation_date());
+    Ok(())
+}
+
+#[test]
+fn test_derive_key_lifecycle_state() {
+    let now = DateTime::from_millis_epoch(1_000_000_000_000);
+    let metadata = KeyMetaData::new();
+    assert_eq!(
+        KeyLifecycleState::Provisioning,
+        KeyLifecycleState::derive(KeyLifeCycle::Existing, &metadata, now)
+    );
+    assert_eq!(
+        KeyLifecycleState::Tombstoned,
+        KeyLifecycleState::derive(KeyLifeCycle::Unreferenced, &metadata, now)
+    );
+    assert_eq!(
+        KeyLifecycleState::Live,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+
+    let mut metadata = KeyMetaData::new();
+    metadata.add(KeyMetaEntry::AttestationExpirationDate(DateTime::from_millis_epoch(
+        now.to_millis_epoch() + EXPIRING_CERT_WINDOW_MS,
+    )));
+    assert_eq!(
+        KeyLifecycleState::Live,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+    metadata.add(KeyMetaEntry::MaxValidityExpirationDate(now));
+    assert_eq!(
+        KeyLifecycleState::ExpiringCert,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+    metadata.add(KeyMetaEntry::InvalidationDate(now));
+    assert_eq!(
+        KeyLifecycleState::Invalidated,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+
+    let mut metadata = KeyMetaData::new();
+    metadata
+        .add(KeyMetaEntry::RetainedUntil(DateTime::from_millis_epoch(now.to_millis_epoch() + 1)));
+    assert_eq!(
+        KeyLifecycleState::Retained,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+    metadata.add(KeyMetaEntry::RetainedUntil(now));
+    assert_eq!(
+        KeyLifecycleState::Tombstoned,
+        KeyLifecycleState::derive(KeyLifeCycle::Live, &metadata, now)
+    );
+}
+
+fn store_rotated_test_key(
+    db: &mut KeystoreDB,
+    namespace: i64,
+    alias: &str,
+    until: DateTime,
+) -> Result<KeyIdGuard> {
+    let mut blob_metadata = BlobMetaData::new();
+    blob_metadata.add(BlobMetaEntry::KmUuid(KEYSTORE_UUID));
+    db.store_rotated_key(
+        &KeyDescriptor {
+            domain: Domain::APP,
+            nspace: namespace,
+            alias: Some(alias.to_string()),
+            blob: None,
+        },
+        KeyType::Client,
+        &make_test_params(None),
+        &BlobInfo::new(TEST_KEY_BLOB, &blob_metadata),
+        &CertificateInfo::new(None, None),
+        &KeyMetaData::new(),
+        &KEYSTORE_UUID,
+        until,
+    )
+}
+
+#[test]
+fn test_store_rotated_key() -> Result<()> {
+    const OWNER_UID: u32 = 1;
+    const GRANTEE_UID: u32 = 2;
+    let mut db = new_test_db()?;
+    let until = DateTime::from_millis_epoch(i64::MAX);
+    let key = |alias: &str| KeyDescriptor {
+        domain: Domain::APP,
+        nspace: OWNER_UID as i64,
+        alias: Some(alias.to_string()),
+        blob: None,
+    };
+    let load_id = |db: &mut KeystoreDB, key: &KeyDescriptor, uid| -> Result<i64> {
+        Ok(db
+            .load_key_entry(key, KeyType::Client, KeyEntryLoadBits::NONE, uid, |_, _| Ok(()))?
+            .0
+            .id())
+    };
+
+    // Rotating an alias that is not bound to a key does not retain anything.
+    let first_id = store_rotated_test_key(&mut db, OWNER_UID as i64, "key", until)?.id();
+    assert_eq!(KeyLifecycleState::Live, load_lifecycle_state(&mut db, 1, "key"));
+    assert_eq!(1, db.list_past_alias(Domain::APP, 1, KeyType::Client, None)?.len());
+    let granted_key =
+        db.grant(&key("key"), OWNER_UID, GRANTEE_UID, key_perm_set![KeyPerm::Use], |_k, _av| {
+            Ok(())
+        })?;
+
+    // The previous key keeps its id and grants under the retained alias.
+    let second_id = store_rotated_test_key(&mut db, OWNER_UID as i64, "key", until)?.id();
+    assert_eq!(second_id, load_id(&mut db, &key("key"), OWNER_UID)?);
+    assert_eq!(first_id, load_id(&mut db, &key("key.prev"), OWNER_UID)?);
+    assert_eq!(first_id, load_id(&mut db, &granted_key, GRANTEE_UID)?);
+    assert_eq!(KeyLifecycleState::Retained, load_lifecycle_state(&mut db, 1, "key.prev"));
+    assert_eq!(KeyLifecycleState::Live, load_lifecycle_state(&mut db, 1, "key"));
+
+    // Another rotation releases the key retained before.
+    let third_id = store_rotated_test_key(&mut db, OWNER_UID as i64, "key", until)?.id();
+    assert_eq!(third_id, load_id(&mut db, &key("key"), OWNER_UID)?);
+    assert_eq!(second_id, load_id(&mut db, &key("key.prev"), OWNER_UID)?);
+    assert!(load_id(&mut db, &granted_key, GRANTEE_UID).is_err());
+    assert_eq!(2, db.list_past_alias(Domain::APP, 1, KeyType::Client, None)?.len());
+
+    // The retained key is unbound once its retention ended.
+    assert_eq!(0, db.unbind_expired_retained_keys(DateTime::from_millis_epoch(0))?);
+    assert_eq!(1, db.unbind_expired_retained_keys(until)?);
+    assert_eq!(
+        vec![Some("key".to_string())],
+        db.list_past_alias(Domain::APP, 1, KeyType::Client, None)?
+            .into_iter()
+            .map(|k| k.alias)
+            .collect::<Vec<_>>()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_retained_key_use_requires_grant() -> Result<()> {
+    const OWNER_UID: u32 = 1;
+    const GRANTEE_UID: u32 = 2;
+    let mut db = new_test_db()?;
+    let until = DateTime::from_millis_epoch(i64::MAX);
+    let retained_id = store_rotated_test_key(&mut db, OWNER_UID as i64, "key", until)?.id();
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: OWNER_UID as i64,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let granted_key =
+        db.grant(&key, OWNER_UID, GRANTEE_UID, key_perm_set![KeyPerm::Use], |_k, _av| Ok(()))?;
+    store_rotated_test_key(&mut db, OWNER_UID as i64, "key", until)?;
+
+    // Loads the retained key like `create_operation` does and checks whether it may be used.
+    let check_use = |db: &mut KeystoreDB, key: &KeyDescriptor, uid| -> Result<()> {
+        let access_vector = Cell::new(None);
+        let (_, key_entry) =
+            db.load_key_entry(key, KeyType::Client, KeyEntryLoadBits::NONE, uid, |_, av| {
+                access_vector.set(av);
+                Ok(())
+            })?;
+        assert_eq!(KeyLifecycleState::Retained, key_entry.lifecycle_state());
+        check_retained_key_use(&access_vector.get())
+    };
+    let by_id = KeyDescriptor { domain: Domain::KEY_ID, nspace: retained_id, ..Default::default() };
+
+    // The grantee may use the retained key with the grant and with the key id that
+    // getKeyEntry returns to it.
+    check_use(&mut db, &granted_key, GRANTEE_UID)?;
+    check_use(&mut db, &by_id, GRANTEE_UID)?;
+
+    // The owner may not use it, neither by alias nor by key id.
+    let by_alias = KeyDescriptor { alias: Some("key.prev".to_string()), ..key };
+    for key in [&by_alias, &by_id] {
+        assert_eq!(
+            Some(&KsError::Rc(ResponseCode::PERMISSION_DENIED)),
+            check_use(&mut db, key, OWNER_UID).unwrap_err().root_cause().downcast_ref::<KsError>()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_store_rotated_key_keeps_unretained_previous_alias() -> Result<()> {
+    let mut db = new_test_db()?;
+    let until = DateTime::from_millis_epoch(i64::MAX);
+    let own_id = make_test_key_entry(&mut db, Domain::APP, 1, "key.prev", None)?.id();
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 1, "key", None)?.id();
+
+    // A key that the caller created under `<alias>.prev` is not replaced by a rotation.
+    let result = store_rotated_test_key(&mut db, 1, "key", until);
+    assert_eq!(
+        Some(&KsError::Rc(ResponseCode::INVALID_ARGUMENT)),
+        result.unwrap_err().root_cause().downcast_ref::<KsError>()
+    );
+    let load_id = |db: &mut KeystoreDB, alias: &str| -> Result<i64> {
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some(alias.to_string()),
+            blob: None,
+        };
+        Ok(db
+            .load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::NONE, 1, |_, _| Ok(()))?
+            .0
+            .id())
+    };
+    assert_eq!(own_id, load_id(&mut db, "key.prev")?);
+    assert_eq!(key_id, load_id(&mut db, "key")?);
+    assert_eq!(KeyLifecycleState::Live, load_lifecycle_state(&mut db, 1, "key.prev"));
+    assert_eq!(2, db.list_past_alias(Domain::APP, 1, KeyType::Client, None)?.len());
+    Ok(())
+}
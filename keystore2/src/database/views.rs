@@ -0,0 +1,103 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module defines the diagnostic views of the persistent database. They give platform
+//! diagnostics tools read-only access to the key database through stable column names, so that
+//! the tools do not depend on the layout of the tables, which changes across releases.
+//!
+//! The views are versioned. The views of version N are named `diag_vN_<name>`, and the view
+//! `diag_versions` lists the versions the database provides. The columns of a released version
+//! never change:
+//!  * An upgrade of the database that changes a table read by the views must call `recreate`, so
+//!    that the views keep their columns on top of the new layout.
+//!  * A change that cannot be expressed in the columns of an existing version adds the views of a
+//!    new version next to the old ones.
+//!
+//! Version 1 has the following views:
+//!  * `diag_v1_keys(key_id, domain, namespace, alias, key_type, state, creation_date_ms)`, where
+//!    `key_type` is "client" or "super" and `state` is "existing", "live" or "unreferenced".
+//!  * `diag_v1_key_parameters(key_id, tag, value, security_level)`.
+//!  * `diag_v1_grants(grant_id, grantee_uid, key_id, access_vector)`.
+//!
+//! The views never expose key blobs or other key material. Tools should read them through a
+//! read-only connection in WAL journal mode, which operates on a snapshot and never blocks
+//! keystore, see `KeystoreDB::new_read_only`.
+
+use super::KeyMetaData;
+use crate::ks_err;
+use anyhow::{Context, Result};
+use rusqlite::Transaction;
+
+/// The names of all views, for `recreate`.
+const VIEWS: &[&str] =
+    &["diag_versions", "diag_v1_keys", "diag_v1_key_parameters", "diag_v1_grants"];
+
+/// Creates the views that do not exist yet. The tables must exist.
+pub(super) fn create(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE VIEW IF NOT EXISTS persistent.diag_versions AS
+             SELECT 1 AS version;",
+        [],
+    )
+    .context(ks_err!("Failed to create view diag_versions."))?;
+
+    tx.execute(
+        &format!(
+            "CREATE VIEW IF NOT EXISTS persistent.diag_v1_keys AS
+                 SELECT
+                     id AS key_id,
+                     domain,
+                     namespace,
+                     CAST(alias AS TEXT) AS alias,
+                     CASE key_type WHEN 0 THEN 'client' WHEN 1 THEN 'super' END AS key_type,
+                     CASE state
+                         WHEN 0 THEN 'existing'
+                         WHEN 1 THEN 'live'
+                         WHEN 2 THEN 'unreferenced'
+                     END AS state,
+                     (SELECT data FROM keymetadata
+                      WHERE keyentryid = keyentry.id AND tag = {}) AS creation_date_ms
+                 FROM keyentry;",
+            KeyMetaData::CreationDate
+        ),
+        [],
+    )
+    .context(ks_err!("Failed to create view diag_v1_keys."))?;
+
+    tx.execute(
+        "CREATE VIEW IF NOT EXISTS persistent.diag_v1_key_parameters AS
+             SELECT keyentryid AS key_id, tag, data AS value, security_level
+             FROM keyparameter;",
+        [],
+    )
+    .context(ks_err!("Failed to create view diag_v1_key_parameters."))?;
+
+    tx.execute(
+        "CREATE VIEW IF NOT EXISTS persistent.diag_v1_grants AS
+             SELECT id AS grant_id, grantee AS grantee_uid, keyentryid AS key_id, access_vector
+             FROM grant;",
+        [],
+    )
+    .context(ks_err!("Failed to create view diag_v1_grants."))?;
+    Ok(())
+}
+
+/// Drops and creates all views, so that they reflect the current layout of the tables.
+pub(super) fn recreate(tx: &Transaction) -> Result<()> {
+    for view in VIEWS {
+        tx.execute(&format!("DROP VIEW IF EXISTS persistent.{view};"), [])
+            .context(ks_err!("Failed to drop view {}.", view))?;
+    }
+    create(tx).context(ks_err!())
+}
@@ -0,0 +1,234 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements deferred connections to security levels. StrongBox implementations
+//! are often slow to come up and rarely used early during boot, so devices may set
+//! `keystore.strongbox.lazy_connect` to true to let keystore2 connect to StrongBox on first
+//! use instead of during startup. Init can set `keystore.strongbox.warm_up` to true to hint
+//! that StrongBox will be needed soon, which makes keystore2 connect in the background.
+//!
+//! If the HAL is not ready when it is first needed, keystore2 waits for it for a bounded time
+//! instead of failing right away. The wait is only granted once, so that a device with a broken
+//! StrongBox does not stall every request.
+//!
+//! Keys on additional StrongBox instances have uuids of their own, which
+//! `KeystoreService::get_i_sec_level_by_uuid` maps to StrongBox. They are served by the same
+//! deferred connection, whose security level object routes them to their instance.
+
+use crate::error::{anyhow_error_to_kind, ErrorKind};
+use crate::globals::is_keymint_declared;
+use crate::id_rotation::IdRotationState;
+use crate::ks_err;
+use crate::security_level::KeystoreSecurityLevel;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_hardware_security_keymint::binder::Strong;
+use android_system_keystore2::aidl::android::system::keystore2::IKeystoreSecurityLevel::IKeystoreSecurityLevel;
+use anyhow::{Context, Result};
+use rustutils::system_properties::{self, PropertyWatcher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// If true, the connection to StrongBox is deferred until first use.
+const LAZY_STRONGBOX_PROPERTY: &str = "keystore.strongbox.lazy_connect";
+
+/// Set to true by init to hint that StrongBox will be used soon.
+const STRONGBOX_WARM_UP_PROPERTY: &str = "keystore.strongbox.warm_up";
+
+/// How long to wait for a HAL that is not ready yet when it is first needed.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait between connection attempts.
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns true if the connection to StrongBox should be deferred. This is only possible for
+/// declared KeyMint instances, because the presence of a Keymaster device behind km_compat is
+/// only known after connecting to it.
+pub fn defer_strongbox() -> bool {
+    system_properties::read_bool(LAZY_STRONGBOX_PROPERTY, false).unwrap_or(false)
+        && is_keymint_declared(&SecurityLevel::STRONGBOX)
+}
+
+/// Connects to a security level.
+type Connector<T> = Box<dyn Fn() -> Result<T> + Send + Sync>;
+
+/// A security level that is connected on first use. The type of the connection is a parameter,
+/// so that tests can substitute the connector.
+pub struct DeferredSecurityLevel<T = Strong<dyn IKeystoreSecurityLevel>> {
+    security_level: SecurityLevel,
+    connect: Connector<T>,
+    connection: Mutex<Option<T>>,
+    waited: AtomicBool,
+}
+
+impl DeferredSecurityLevel {
+    /// Creates a deferred connection to `security_level` and starts watching for the warm-up
+    /// hint.
+    pub fn new(security_level: SecurityLevel, id_rotation_state: IdRotationState) -> Arc<Self> {
+        let result = Arc::new(Self::with_connector(
+            security_level,
+            Box::new(move || {
+                KeystoreSecurityLevel::new_native_binder(security_level, id_rotation_state.clone())
+                    .map(|(dev, _, _)| dev)
+            }),
+        ));
+        let deferred = result.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = deferred.warm_up_on_hint() {
+                log::error!("Failed to warm up {:?}: {e:?}", deferred.security_level);
+            }
+        });
+        result
+    }
+
+    /// Waits for the warm-up hint and connects. Blocks, so must be run in its own thread.
+    fn warm_up_on_hint(&self) -> Result<()> {
+        PropertyWatcher::new(STRONGBOX_WARM_UP_PROPERTY)
+            .context(ks_err!("Failed to watch {}.", STRONGBOX_WARM_UP_PROPERTY))?
+            .wait_for_value("true", None)
+            .context(ks_err!("Failed to wait for {}.", STRONGBOX_WARM_UP_PROPERTY))?;
+        log::info!("Received warm-up hint for {:?}.", self.security_level);
+        self.get().map(|_| ())
+    }
+}
+
+impl<T: Clone> DeferredSecurityLevel<T> {
+    fn with_connector(security_level: SecurityLevel, connect: Connector<T>) -> Self {
+        Self {
+            security_level,
+            connect,
+            connection: Mutex::new(None),
+            waited: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the security level, connecting to it if this has not happened yet.
+    pub fn get(&self) -> Result<T> {
+        let mut connection = self.connection.lock().unwrap();
+        if let Some(dev) = connection.as_ref() {
+            return Ok(dev.clone());
+        }
+        let deadline = if self.waited.swap(true, Ordering::Relaxed) {
+            Instant::now()
+        } else {
+            Instant::now() + CONNECT_TIMEOUT
+        };
+        let dev = loop {
+            match (self.connect)() {
+                Ok(dev) => break dev,
+                // Only wait for errors that indicate that the HAL is not up yet.
+                Err(e)
+                    if Instant::now() < deadline
+                        && matches!(
+                            anyhow_error_to_kind(&e),
+                            ErrorKind::HalDead | ErrorKind::Retryable
+                        ) =>
+                {
+                    log::warn!("{:?} is not ready yet: {e:?}", self.security_level);
+                    std::thread::sleep(CONNECT_RETRY_INTERVAL);
+                }
+                Err(e) => {
+                    return Err(e)
+                        .context(ks_err!("Failed to connect to {:?}.", self.security_level))
+                }
+            }
+        };
+        log::info!("Connected to deferred security level {:?}.", self.security_level);
+        *connection = Some(dev.clone());
+        Ok(dev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, ErrorCode, ResponseCode};
+    use std::sync::atomic::AtomicUsize;
+
+    /// Returns a deferred connection whose connector fails with the errors in `failures` before
+    /// it succeeds, and the number of connection attempts.
+    fn new_deferred(failures: Vec<Error>) -> (DeferredSecurityLevel<usize>, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let failures = Mutex::new(failures.into_iter());
+        let deferred = DeferredSecurityLevel::with_connector(
+            SecurityLevel::STRONGBOX,
+            Box::new(move || {
+                let attempt = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                match failures.lock().unwrap().next() {
+                    Some(e) => Err(e.into()),
+                    None => Ok(attempt),
+                }
+            }),
+        );
+        (deferred, attempts)
+    }
+
+    fn error_of(e: &anyhow::Error) -> Option<&Error> {
+        e.root_cause().downcast_ref::<Error>()
+    }
+
+    #[test]
+    fn test_connection_is_cached() {
+        let (deferred, attempts) = new_deferred(vec![]);
+        assert_eq!(deferred.get().unwrap(), 1);
+        assert_eq!(deferred.get().unwrap(), 1);
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_retries_while_hal_is_not_ready() {
+        let (deferred, attempts) = new_deferred(vec![
+            Error::Km(ErrorCode::SECURE_HW_COMMUNICATION_FAILED),
+            Error::Rc(ResponseCode::BACKEND_BUSY),
+            Error::Km(ErrorCode::SECURE_HW_COMMUNICATION_FAILED),
+        ]);
+        assert_eq!(deferred.get().unwrap(), 4);
+        assert_eq!(attempts.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_no_retry_for_other_errors() {
+        let (deferred, attempts) =
+            new_deferred(vec![Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)]);
+        let e = deferred.get().unwrap_err();
+        assert_eq!(error_of(&e), Some(&Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        // The next attempt connects.
+        assert_eq!(deferred.get().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_wait_is_bounded_and_granted_once() {
+        let (deferred, attempts) = new_deferred(
+            std::iter::repeat_with(|| Error::Rc(ResponseCode::BACKEND_BUSY)).take(1000).collect(),
+        );
+
+        let start = Instant::now();
+        let e = deferred.get().unwrap_err();
+        let elapsed = start.elapsed();
+        assert_eq!(error_of(&e), Some(&Error::Rc(ResponseCode::BACKEND_BUSY)));
+        assert!(elapsed >= CONNECT_TIMEOUT, "Gave up after {elapsed:?}.");
+        assert!(elapsed < CONNECT_TIMEOUT + Duration::from_secs(2), "Waited for {elapsed:?}.");
+        let waiting_attempts = attempts.load(Ordering::Relaxed);
+        assert!(waiting_attempts > 1);
+
+        // The second request does not wait.
+        let start = Instant::now();
+        assert!(deferred.get().is_err());
+        assert!(start.elapsed() < CONNECT_RETRY_INTERVAL);
+        assert_eq!(attempts.load(Ordering::Relaxed), waiting_attempts + 1);
+    }
+}
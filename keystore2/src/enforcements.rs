@@ -16,8 +16,11 @@
 // TODO: more description to follow.
 use crate::ks_err;
 use crate::error::{map_binder_status, Error, ErrorCode};
-use crate::globals::{get_timestamp_service, ASYNC_TASK, DB, ENFORCEMENTS};
+use crate::globals::{get_timestamp_service, ASYNC_TASK, DB, ENFORCEMENTS, KEY_ENTRY_CACHE};
+use crate::import_limits::read_usize_property;
 use crate::key_parameter::{KeyParameter, KeyParameterValue};
+use crate::storage_tier::StorageTier;
+use crate::utils::USER_SYSTEM;
 use crate::{authorization::Error as AuthzError, super_key::SuperEncryptionType};
 use crate::{
     database::{AuthTokenEntry, BootTime},
@@ -26,7 +29,8 @@ use crate::{
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, ErrorCode::ErrorCode as Ec, HardwareAuthToken::HardwareAuthToken,
     HardwareAuthenticatorType::HardwareAuthenticatorType,
-    KeyParameter::KeyParameter as KmKeyParameter, KeyPurpose::KeyPurpose, Tag::Tag,
+    KeyParameter::KeyParameter as KmKeyParameter,
+    KeyParameterValue::KeyParameterValue as KmKeyParameterValue, KeyPurpose::KeyPurpose, Tag::Tag,
 };
 use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::{
     TimeStampToken::TimeStampToken,
@@ -41,11 +45,21 @@ use std::{
     collections::{HashMap, HashSet},
     sync::{
         mpsc::{channel, Receiver, Sender, TryRecvError},
-        Arc, Mutex, Weak,
+        Arc, LazyLock, Mutex, Weak,
     },
     time::SystemTime,
 };
 
+/// The number of caller provided nonces that are remembered per key to reject nonce reuse for
+/// encryption. It is configured with the read-only property `ro.keystore.caller_nonce_history`.
+/// The check is off by default, because existing apps may reuse a caller provided IV on
+/// purpose, e.g., for deterministic encryption in CBC mode, and would break. Devices that
+/// enable it reject an ENCRYPT operation of a key with CALLER_NONCE with `INVALID_NONCE` if its
+/// nonce is among the last `ro.keystore.caller_nonce_history` nonces used with that key. The
+/// history lives in the per-boot database and covers a bounded number of keys.
+static CALLER_NONCE_HISTORY: LazyLock<usize> =
+    LazyLock::new(|| read_usize_property("ro.keystore.caller_nonce_history", 0));
+
 #[derive(Debug)]
 enum AuthRequestState {
     /// An outstanding per operation authorization request.
@@ -297,12 +311,14 @@ impl AuthInfo {
         if let Some(key_id) = self.key_usage_limited {
             // On the last successful use, the key gets deleted. In this case we
             // have to notify the garbage collector.
-            DB.with(|db| {
+            let result = DB.with(|db| {
                 db.borrow_mut()
                     .check_and_update_key_usage_count(key_id)
                     .context("Trying to update key usage count.")
-            })
-            .context(ks_err!())?;
+            });
+            // The remaining usage count is part of the key's authorizations.
+            KEY_ENTRY_CACHE.invalidate_all();
+            result.context(ks_err!())?;
         }
         Ok(())
     }
@@ -355,9 +371,16 @@ pub struct Enforcements {
     /// The enforcement module will try to get a confirmation token from this channel whenever
     /// an operation that requires confirmation finishes.
     confirmation_token_receiver: Arc<Mutex<Option<Receiver<Vec<u8>>>>>,
+    /// True if the system user is headless. See `is_device_locked`.
+    headless_system_user_mode: bool,
 }
 
 impl Enforcements {
+    /// Creates the enforcements for a device whose system user may be headless.
+    pub fn new(headless_system_user_mode: bool) -> Self {
+        Self { headless_system_user_mode, ..Default::default() }
+    }
+
     /// Install the confirmation token receiver. The enforcement module will try to get a
     /// confirmation token from this channel whenever an operation that requires confirmation
     /// finishes.
@@ -606,6 +629,31 @@ impl Enforcements {
         Ok((hat, AuthInfo { state, key_usage_limited, confirmation_token_receiver }))
     }
 
+    /// Records the caller provided nonce of an encryption operation with the key `key_id`.
+    /// Returns false if the nonce was used recently with the key, because nonce reuse destroys
+    /// the security of, e.g., GCM. The caller must abort the operation in that case. This must
+    /// be called after `authorize_create`, which rejects nonces for keys without CALLER_NONCE.
+    pub fn insert_caller_nonce(
+        &self,
+        purpose: KeyPurpose,
+        key_id: i64,
+        op_params: &[KmKeyParameter],
+    ) -> bool {
+        if purpose != KeyPurpose::ENCRYPT || *CALLER_NONCE_HISTORY == 0 {
+            return true;
+        }
+        let nonce = op_params.iter().find_map(|kp| match &kp.value {
+            KmKeyParameterValue::Blob(nonce) if kp.tag == Tag::NONCE => Some(nonce),
+            _ => None,
+        });
+        match nonce {
+            Some(nonce) => {
+                DB.with(|db| db.borrow().insert_caller_nonce(key_id, nonce, *CALLER_NONCE_HISTORY))
+            }
+            None => true,
+        }
+    }
+
     fn find_auth_token<F>(p: F) -> Option<AuthTokenEntry>
     where
         F: Fn(&AuthTokenEntry) -> bool,
@@ -631,11 +679,17 @@ impl Enforcements {
     }
 
     /// Check if the device is locked for the given user. If there's no entry yet for the user,
-    /// we assume that the device is locked
+    /// we assume that the device is locked.
+    ///
+    /// A headless system user never has a lock screen and is never unlocked itself. Instead, the
+    /// device is unlocked for the system user while it is unlocked for any other user.
     fn is_device_locked(&self, user_id: i32) -> bool {
         // unwrap here because there's no way this mutex guard can be poisoned and
         // because there's no way to recover, even if it is poisoned.
         let set = self.device_unlocked_set.lock().unwrap();
+        if self.headless_system_user_mode && user_id == USER_SYSTEM as i32 {
+            return set.is_empty();
+        }
         !set.contains(&user_id)
     }
 
@@ -667,23 +721,33 @@ impl Enforcements {
         self.op_auth_map.add_receiver(challenge, recv);
     }
 
-    /// Given the set of key parameters and flags, check if super encryption is required.
+    /// Given the set of key parameters and flags, and the storage tier of the key's namespace,
+    /// check if super encryption is required. Keys in sensitive namespaces are always bound to
+    /// the unlocked device state, unless they are bound to a boot level.
     pub fn super_encryption_required(
         domain: &Domain,
         key_parameters: &[KeyParameter],
         flags: Option<i32>,
+        storage_tier: StorageTier,
     ) -> SuperEncryptionType {
-        if let Some(flags) = flags {
-            if (flags & KEY_FLAG_AUTH_BOUND_WITHOUT_CRYPTOGRAPHIC_LSKF_BINDING) != 0 {
-                return SuperEncryptionType::None;
-            }
-        }
         // Each answer has a priority, numerically largest priority wins.
         struct Candidate {
             priority: u32,
             enc_type: SuperEncryptionType,
         }
-        let mut result = Candidate { priority: 0, enc_type: SuperEncryptionType::None };
+        let mut result = match storage_tier {
+            StorageTier::Sensitive => {
+                Candidate { priority: 2, enc_type: SuperEncryptionType::UnlockedDeviceRequired }
+            }
+            StorageTier::Normal => {
+                if let Some(flags) = flags {
+                    if (flags & KEY_FLAG_AUTH_BOUND_WITHOUT_CRYPTOGRAPHIC_LSKF_BINDING) != 0 {
+                        return SuperEncryptionType::None;
+                    }
+                }
+                Candidate { priority: 0, enc_type: SuperEncryptionType::None }
+            }
+        };
         for kp in key_parameters {
             let t = match kp.key_parameter_value() {
                 KeyParameterValue::MaxBootLevel(level) => {
@@ -778,3 +842,85 @@ impl Enforcements {
 }
 
 // TODO: Add tests to enforcement module (b/175578618).
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+
+    const SECONDARY_USER_ID: i32 = 10;
+
+    #[test]
+    fn test_device_locked_for_headless_system_user() {
+        let enforcements = Enforcements::new(true);
+        assert!(enforcements.is_device_locked(USER_SYSTEM as i32));
+
+        enforcements.set_device_locked(SECONDARY_USER_ID, false);
+        assert!(!enforcements.is_device_locked(USER_SYSTEM as i32));
+        assert!(!enforcements.is_device_locked(SECONDARY_USER_ID));
+
+        enforcements.set_device_locked(SECONDARY_USER_ID, true);
+        assert!(enforcements.is_device_locked(USER_SYSTEM as i32));
+    }
+
+    #[test]
+    fn test_device_locked_for_system_user() {
+        let enforcements = Enforcements::new(false);
+        enforcements.set_device_locked(SECONDARY_USER_ID, false);
+        assert!(enforcements.is_device_locked(USER_SYSTEM as i32));
+
+        enforcements.set_device_locked(USER_SYSTEM as i32, false);
+        assert!(!enforcements.is_device_locked(USER_SYSTEM as i32));
+    }
+
+    #[test]
+    fn test_super_encryption_for_storage_tiers() {
+        let params = [KeyParameter::new(
+            KeyParameterValue::UserSecureID(42),
+            SecurityLevel::TRUSTED_ENVIRONMENT,
+        )];
+        let flags = Some(KEY_FLAG_AUTH_BOUND_WITHOUT_CRYPTOGRAPHIC_LSKF_BINDING);
+        assert!(matches!(
+            Enforcements::super_encryption_required(
+                &Domain::SELINUX,
+                &params,
+                None,
+                StorageTier::Normal
+            ),
+            SuperEncryptionType::None
+        ));
+        assert!(matches!(
+            Enforcements::super_encryption_required(
+                &Domain::APP,
+                &params,
+                flags,
+                StorageTier::Normal
+            ),
+            SuperEncryptionType::None
+        ));
+        assert!(matches!(
+            Enforcements::super_encryption_required(
+                &Domain::SELINUX,
+                &params,
+                flags,
+                StorageTier::Sensitive
+            ),
+            SuperEncryptionType::UnlockedDeviceRequired
+        ));
+
+        // Boot level binding takes precedence.
+        let params = [KeyParameter::new(
+            KeyParameterValue::MaxBootLevel(30),
+            SecurityLevel::TRUSTED_ENVIRONMENT,
+        )];
+        assert!(matches!(
+            Enforcements::super_encryption_required(
+                &Domain::SELINUX,
+                &params,
+                None,
+                StorageTier::Sensitive
+            ),
+            SuperEncryptionType::BootLevel(30)
+        ));
+    }
+}
@@ -21,6 +21,9 @@
 //!
 //! `SerializedError` is used send error codes on the wire.
 //!
+//! `ErrorKind` classifies errors for callers inside Keystore that need to decide how to react to
+//! an error, e.g., whether to retry, without matching on individual error codes.
+//!
 //! `into_[logged_]binder` is a convenience method used to convert `anyhow::Error` into
 //! `SerializedError` wire type.
 //!
@@ -59,6 +62,19 @@ pub enum Error {
     BinderTransaction(StatusCode),
 }
 
+/// Coarse classification of errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A resource was busy. The request may succeed if it is retried later.
+    Retryable,
+    /// The key does not exist or can no longer be used.
+    KeyGone,
+    /// The HAL or another service that Keystore depends on died or is not reachable.
+    HalDead,
+    /// Any other error.
+    Other,
+}
+
 impl Error {
     /// Short hand for `Error::Rc(ResponseCode::SYSTEM_ERROR)`
     pub fn sys() -> Self {
@@ -69,6 +85,28 @@ impl Error {
     pub fn perm() -> Self {
         Error::Rc(ResponseCode::PERMISSION_DENIED)
     }
+
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Rc(
+                ResponseCode::BACKEND_BUSY
+                | ResponseCode::OPERATION_BUSY
+                | ResponseCode::OUT_OF_KEYS_TRANSIENT_ERROR,
+            )
+            | Error::Km(ErrorCode::TOO_MANY_OPERATIONS | ErrorCode::CONCURRENT_ACCESS_CONFLICT) => {
+                ErrorKind::Retryable
+            }
+            Error::Rc(ResponseCode::KEY_NOT_FOUND | ResponseCode::KEY_PERMANENTLY_INVALIDATED)
+            | Error::Km(ErrorCode::KEY_PERMANENTLY_INVALIDATED) => ErrorKind::KeyGone,
+            Error::Km(ErrorCode::SECURE_HW_COMMUNICATION_FAILED)
+            | Error::Binder(ExceptionCode::TRANSACTION_FAILED, _)
+            | Error::BinderTransaction(StatusCode::DEAD_OBJECT | StatusCode::NAME_NOT_FOUND) => {
+                ErrorKind::HalDead
+            }
+            _ => ErrorKind::Other,
+        }
+    }
 }
 
 impl From<RkpdError> for Error {
@@ -222,6 +260,22 @@ pub fn error_to_serialized_error(e: &Error) -> SerializedError {
     }
 }
 
+/// Returns the kind of the root cause of an anyhow::Error. Besides Keystore errors, this
+/// classifies busy and locked database errors as retryable.
+pub fn anyhow_error_to_kind(e: &anyhow::Error) -> ErrorKind {
+    let root_cause = e.root_cause();
+    match root_cause.downcast_ref::<Error>() {
+        Some(e) => e.kind(),
+        None => match root_cause.downcast_ref::<rusqlite::ffi::Error>() {
+            Some(rusqlite::ffi::Error {
+                code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                ..
+            }) => ErrorKind::Retryable,
+            _ => ErrorKind::Other,
+        },
+    }
+}
+
 /// Returns a SerializedError given a reference to anyhow::Error.
 pub fn anyhow_error_to_serialized_error(e: &anyhow::Error) -> SerializedError {
     let root_cause = e.root_cause();
@@ -216,3 +216,34 @@ fn rkpd_error_is_in_sync_with_response_code() {
         assert_eq!(e, Error::Rc(expected_response_code));
     }
 }
+
+#[test]
+fn error_kind_test() {
+    let kinds = [
+        (Error::Rc(ResponseCode::BACKEND_BUSY), ErrorKind::Retryable),
+        (Error::Km(ErrorCode::TOO_MANY_OPERATIONS), ErrorKind::Retryable),
+        (Error::Rc(ResponseCode::KEY_NOT_FOUND), ErrorKind::KeyGone),
+        (Error::Km(ErrorCode::KEY_PERMANENTLY_INVALIDATED), ErrorKind::KeyGone),
+        (Error::BinderTransaction(StatusCode::DEAD_OBJECT), ErrorKind::HalDead),
+        (Error::Binder(ExceptionCode::TRANSACTION_FAILED, 0), ErrorKind::HalDead),
+        (Error::Rc(ResponseCode::PERMISSION_DENIED), ErrorKind::Other),
+        (Error::Km(ErrorCode::INVALID_ARGUMENT), ErrorKind::Other),
+        (Error::Binder(ExceptionCode::SERVICE_SPECIFIC, 1), ErrorKind::Other),
+    ];
+    for (error, expected_kind) in kinds {
+        assert_eq!(error.kind(), expected_kind, "{error:?}");
+    }
+
+    assert_eq!(
+        anyhow_error_to_kind(&nested_rc(ResponseCode::KEY_NOT_FOUND).unwrap_err()),
+        ErrorKind::KeyGone
+    );
+    assert_eq!(
+        anyhow_error_to_kind(&nested_ec(ErrorCode::TOO_MANY_OPERATIONS).unwrap_err()),
+        ErrorKind::Retryable
+    );
+    let busy = anyhow!(rusqlite::ffi::Error::new(5 /* SQLITE_BUSY */)).context("db busy");
+    assert_eq!(anyhow_error_to_kind(&busy), ErrorKind::Retryable);
+    assert_eq!(anyhow_error_to_kind(&nested_selinux_perm().unwrap_err()), ErrorKind::Other);
+    assert_eq!(anyhow_error_to_kind(&nested_other_error().unwrap_err()), ErrorKind::Other);
+}
@@ -0,0 +1,273 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements key namespaces scoped to eSIM profiles. Telephony maps the ICCID of
+//! each eSIM profile to the namespace that holds the carrier keys of the profile, and tells
+//! keystore when a profile is activated or deactivated. While a profile is inactive, the keys in
+//! its namespace cannot be used to start operations, so that the carrier keys of one profile
+//! are not usable while another profile is in service. All other access, e.g., to the public
+//! key entry, is unaffected.
+//!
+//! The mapping is configured through IKeystoreMaintenance and does not persist across restarts
+//! of keystore, so it must be re-applied by telephony. A profile is inactive until it is
+//! activated.
+
+use crate::error::Error;
+use crate::globals::DB;
+use crate::ks_err;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+/// The eSIM profile namespaces of this keystore instance.
+pub static ESIM_PROFILES: LazyLock<EsimProfiles> = LazyLock::new(Default::default);
+
+/// ICCIDs have at most 22 digits, and at least 18 in practice.
+const ICCID_LENGTHS: std::ops::RangeInclusive<usize> = 18..=22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EsimProfile {
+    domain: Domain,
+    nspace: i64,
+    active: bool,
+}
+
+/// The state of an eSIM profile as shown in dumps. The ICCID is redacted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EsimProfileState {
+    /// The last four digits of the ICCID.
+    pub iccid_suffix: String,
+    /// The domain of the namespace of the profile.
+    pub domain: Domain,
+    /// The namespace of the profile.
+    pub nspace: i64,
+    /// Whether the profile is active.
+    pub active: bool,
+}
+
+/// Holds the namespace and activation state of the registered eSIM profiles by ICCID.
+#[derive(Debug, Default)]
+pub struct EsimProfiles {
+    profiles: RwLock<HashMap<String, EsimProfile>>,
+}
+
+fn check_iccid(iccid: &str) -> Result<()> {
+    if !ICCID_LENGTHS.contains(&iccid.len()) || !iccid.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Malformed ICCID of length {}.", iccid.len()));
+    }
+    Ok(())
+}
+
+fn iccid_suffix(iccid: &str) -> &str {
+    &iccid[iccid.len().saturating_sub(4)..]
+}
+
+impl EsimProfiles {
+    /// Maps the eSIM profile `iccid` to the namespace `nspace` in `domain`, which must be
+    /// Domain::APP or Domain::SELINUX. A namespace can belong to one profile only. Registering
+    /// a profile again replaces its namespace. The profile is inactive until it is activated.
+    pub fn register(&self, iccid: &str, domain: Domain, nspace: i64) -> Result<()> {
+        check_iccid(iccid).context(ks_err!())?;
+        if !matches!(domain, Domain::APP | Domain::SELINUX) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Unsupported domain {:?}.", domain));
+        }
+        let mut profiles = self.profiles.write().unwrap();
+        if profiles.iter().any(|(i, p)| i != iccid && p.domain == domain && p.nspace == nspace) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Namespace {:?}/{} already belongs to another eSIM profile.",
+                domain,
+                nspace
+            ));
+        }
+        log::info!(
+            "eSIM profile ...{} mapped to namespace {:?}/{}.",
+            iccid_suffix(iccid),
+            domain,
+            nspace
+        );
+        profiles.insert(iccid.to_string(), EsimProfile { domain, nspace, active: false });
+        Ok(())
+    }
+
+    /// Removes the eSIM profile `iccid`. The keys in its namespace are no longer locked when
+    /// the profile is inactive. Removing a profile that is not registered has no effect.
+    pub fn unregister(&self, iccid: &str) {
+        if self.profiles.write().unwrap().remove(iccid).is_some() {
+            log::info!("eSIM profile ...{} removed.", iccid_suffix(iccid));
+        }
+    }
+
+    /// Activates or deactivates the eSIM profile `iccid`. Fails with
+    /// `ResponseCode::INVALID_ARGUMENT` if the profile is not registered.
+    pub fn set_active(&self, iccid: &str, active: bool) -> Result<()> {
+        let mut profiles = self.profiles.write().unwrap();
+        let profile = profiles
+            .get_mut(iccid)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("eSIM profile ...{} is not registered.", iccid_suffix(iccid)))?;
+        profile.active = active;
+        log::info!(
+            "eSIM profile ...{} {}.",
+            iccid_suffix(iccid),
+            if active { "activated" } else { "deactivated" }
+        );
+        Ok(())
+    }
+
+    /// Returns true if the namespace `nspace` in `domain` belongs to an inactive eSIM profile.
+    fn is_locked(&self, domain: Domain, nspace: i64) -> bool {
+        self.profiles
+            .read()
+            .unwrap()
+            .values()
+            .any(|p| !p.active && p.domain == domain && p.nspace == nspace)
+    }
+
+    fn any_locked(&self) -> bool {
+        self.profiles.read().unwrap().values().any(|p| !p.active)
+    }
+
+    /// Fails with `ResponseCode::LOCKED` if the key with the id `key_id` belongs to the
+    /// namespace of an inactive eSIM profile. The owner of the key is looked up by id, so that
+    /// grants of the key are locked as well.
+    pub fn check_key_unlocked(&self, key_id: i64) -> Result<()> {
+        if !self.any_locked() {
+            return Ok(());
+        }
+        let key = DB
+            .with(|db| db.borrow_mut().load_key_descriptor(key_id))
+            .context(ks_err!("Trying to load key descriptor."))?;
+        match key {
+            Some(key) if self.is_locked(key.domain, key.nspace) => {
+                Err(Error::Rc(ResponseCode::LOCKED))
+                    .context(ks_err!("Key {} belongs to an inactive eSIM profile.", key_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the state of all registered eSIM profiles, ordered by namespace.
+    pub fn profiles(&self) -> Vec<EsimProfileState> {
+        let mut result: Vec<EsimProfileState> = self
+            .profiles
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(iccid, p)| EsimProfileState {
+                iccid_suffix: iccid_suffix(iccid).to_string(),
+                domain: p.domain,
+                nspace: p.nspace,
+                active: p.active,
+            })
+            .collect();
+        result.sort_by_key(|p| (p.domain.0, p.nspace));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICCID_1: &str = "8901260123456789012";
+    const ICCID_2: &str = "8901260123456789029";
+
+    fn response_code(result: Result<()>) -> Option<ResponseCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Rc(rc)) => Some(*rc),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let profiles = EsimProfiles::default();
+        assert_eq!(
+            response_code(profiles.register("8901", Domain::APP, 10100)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+        assert_eq!(
+            response_code(profiles.register("890126012345678901F", Domain::APP, 10100)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+        assert_eq!(
+            response_code(profiles.register(ICCID_1, Domain::GRANT, 1)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+        profiles.register(ICCID_1, Domain::APP, 10100).unwrap();
+        // A namespace cannot be shared by two profiles.
+        assert_eq!(
+            response_code(profiles.register(ICCID_2, Domain::APP, 10100)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+        profiles.register(ICCID_2, Domain::SELINUX, 10100).unwrap();
+        // Registering again moves the profile to the new namespace.
+        profiles.register(ICCID_1, Domain::APP, 10101).unwrap();
+
+        assert_eq!(
+            profiles.profiles(),
+            vec![
+                EsimProfileState {
+                    iccid_suffix: "9012".to_string(),
+                    domain: Domain::APP,
+                    nspace: 10101,
+                    active: false,
+                },
+                EsimProfileState {
+                    iccid_suffix: "9029".to_string(),
+                    domain: Domain::SELINUX,
+                    nspace: 10100,
+                    active: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_switch() {
+        let profiles = EsimProfiles::default();
+        assert!(!profiles.any_locked());
+        assert_eq!(
+            response_code(profiles.set_active(ICCID_1, true)),
+            Some(ResponseCode::INVALID_ARGUMENT)
+        );
+
+        profiles.register(ICCID_1, Domain::APP, 10100).unwrap();
+        profiles.register(ICCID_2, Domain::APP, 10101).unwrap();
+        // Profiles are locked until they are activated.
+        assert!(profiles.is_locked(Domain::APP, 10100));
+        assert!(profiles.is_locked(Domain::APP, 10101));
+        assert!(!profiles.is_locked(Domain::APP, 10102));
+        assert!(!profiles.is_locked(Domain::SELINUX, 10100));
+
+        profiles.set_active(ICCID_1, true).unwrap();
+        assert!(!profiles.is_locked(Domain::APP, 10100));
+        assert!(profiles.is_locked(Domain::APP, 10101));
+
+        // Switch to the other profile.
+        profiles.set_active(ICCID_1, false).unwrap();
+        profiles.set_active(ICCID_2, true).unwrap();
+        assert!(profiles.is_locked(Domain::APP, 10100));
+        assert!(!profiles.is_locked(Domain::APP, 10101));
+
+        profiles.unregister(ICCID_1);
+        assert!(!profiles.is_locked(Domain::APP, 10100));
+        assert!(!profiles.any_locked());
+    }
+}
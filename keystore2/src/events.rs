@@ -0,0 +1,256 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module streams redacted keystore events to platform observers, such as device health
+//! services, that register an IKeystoreEventListener through IKeystoreMaintenance. Events
+//! carry the type, time, caller, and security level, but never an alias or key material.
+//!
+//! Events are delivered asynchronously and in order. Events that are published while a
+//! delivery is pending are batched into the same call. Nothing is recorded while no listener
+//! is registered.
+
+use crate::async_task::AsyncTask;
+use crate::calling_identity;
+use crate::database::DateTime;
+use crate::error::Error;
+use crate::ks_err;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_security_maintenance::aidl::android::security::maintenance::{
+    IKeystoreEventListener::IKeystoreEventListener, KeystoreEvent::KeystoreEvent,
+    KeystoreEventType::KeystoreEventType,
+};
+use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
+use anyhow::{Context, Result};
+use binder::{DeathRecipient, IBinder, Interface, SpIBinder, Strong};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Maximum number of listeners that can be registered at the same time.
+const MAX_LISTENERS: usize = 16;
+
+/// Maximum number of events that wait for delivery. Further events are dropped until the
+/// pending events were delivered.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// The event hub of keystore.
+pub static EVENTS: LazyLock<Arc<EventHub>> = LazyLock::new(Default::default);
+
+/// Publishes a key event of type `event_type` caused by the calling app, if the request that
+/// caused it succeeded.
+pub fn publish_key_event(event_type: KeystoreEventType, sl: SecurityLevel, success: bool) {
+    if success {
+        EVENTS.publish(event_type, calling_identity::get_calling_uid() as i32, sl);
+    }
+}
+
+struct Listener {
+    listener: Strong<dyn IKeystoreEventListener>,
+    // Keeps the death notification registered for as long as the listener is.
+    _death_recipient: Option<DeathRecipient>,
+}
+
+/// Keeps track of event listeners and delivers events to them.
+#[derive(Default)]
+pub struct EventHub {
+    listeners: Mutex<Vec<Listener>>,
+    pending: Mutex<Vec<KeystoreEvent>>,
+    hal_death_recipients: Mutex<Vec<DeathRecipient>>,
+    delivery: AsyncTask,
+}
+
+impl EventHub {
+    /// Registers `listener`. It is unregistered automatically when its process dies.
+    pub fn register(self: &Arc<Self>, listener: &Strong<dyn IKeystoreEventListener>) -> Result<()> {
+        let mut listeners = self.listeners.lock().unwrap();
+        let binder = listener.as_binder();
+        if listeners.iter().any(|l| l.listener.as_binder() == binder) {
+            return Ok(());
+        }
+        if listeners.len() >= MAX_LISTENERS {
+            return Err(Error::Rc(ResponseCode::BACKEND_BUSY))
+                .context(ks_err!("Already {} listeners registered.", listeners.len()));
+        }
+        let hub = Arc::downgrade(self);
+        let death_recipient = self.link_to_death(binder, move || {
+            if let Some(hub) = hub.upgrade() {
+                hub.remove_dead_listeners();
+            }
+        });
+        listeners.push(Listener { listener: listener.clone(), _death_recipient: death_recipient });
+        Ok(())
+    }
+
+    /// Unregisters `listener` if it is registered.
+    pub fn unregister(&self, listener: &Strong<dyn IKeystoreEventListener>) {
+        let binder = listener.as_binder();
+        self.listeners.lock().unwrap().retain(|l| l.listener.as_binder() != binder);
+    }
+
+    fn remove_dead_listeners(&self) {
+        self.listeners.lock().unwrap().retain(|l| {
+            let alive = l.listener.as_binder().is_binder_alive();
+            if !alive {
+                log::info!("Removing dead keystore event listener.");
+            }
+            alive
+        });
+    }
+
+    /// Registers `on_death` to be called when `binder` dies. Returns the death recipient, which
+    /// must be kept alive, or None if this is not possible, e.g., because `binder` is local.
+    fn link_to_death<F>(&self, mut binder: SpIBinder, on_death: F) -> Option<DeathRecipient>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let mut death_recipient = DeathRecipient::new(on_death);
+        match binder.link_to_death(&mut death_recipient) {
+            Ok(()) => Some(death_recipient),
+            Err(e) => {
+                log::warn!("Cannot watch for death of binder: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Publishes an event of type `event_type` caused by `uid`.
+    pub fn publish(self: &Arc<Self>, event_type: KeystoreEventType, uid: i32, sl: SecurityLevel) {
+        if self.listeners.lock().unwrap().is_empty() {
+            return;
+        }
+        let event = KeystoreEvent {
+            r#type: event_type,
+            timestampMillis: DateTime::now().map_or(0, DateTime::to_millis_epoch),
+            uid,
+            securityLevel: sl,
+        };
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= MAX_PENDING_EVENTS {
+            log::warn!("Dropping keystore event {event:?}: too many events pending.");
+            return;
+        }
+        let schedule = pending.is_empty();
+        pending.push(event);
+        drop(pending);
+        if schedule {
+            let hub = self.clone();
+            self.delivery.queue_lo(move |_| hub.deliver());
+        }
+    }
+
+    fn deliver(&self) {
+        let events = std::mem::take(&mut *self.pending.lock().unwrap());
+        let listeners: Vec<Strong<dyn IKeystoreEventListener>> =
+            self.listeners.lock().unwrap().iter().map(|l| l.listener.clone()).collect();
+        for listener in listeners {
+            if let Err(e) = listener.onEvents(&events) {
+                log::warn!("Failed to deliver {} keystore events: {e:?}", events.len());
+            }
+        }
+    }
+
+    /// Publishes a KeystoreEventType::HAL_DIED event when the KeyMint instance behind `binder`
+    /// dies.
+    pub fn watch_hal(self: &Arc<Self>, security_level: SecurityLevel, binder: SpIBinder) {
+        let hub = Arc::downgrade(self);
+        let death_recipient = self.link_to_death(binder, move || {
+            log::error!("KeyMint instance of {security_level:?} died.");
+            if let Some(hub) = hub.upgrade() {
+                hub.publish(KeystoreEventType::HAL_DIED, -1, security_level);
+            }
+        });
+        if let Some(death_recipient) = death_recipient {
+            self.hal_death_recipients.lock().unwrap().push(death_recipient);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_security_maintenance::aidl::android::security::maintenance::IKeystoreEventListener::BnKeystoreEventListener;
+    use binder::BinderFeatures;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    struct TestListener {
+        sender: Mutex<mpsc::Sender<Vec<KeystoreEvent>>>,
+    }
+
+    impl Interface for TestListener {}
+
+    impl IKeystoreEventListener for TestListener {
+        fn onEvents(&self, events: &[KeystoreEvent]) -> binder::Result<()> {
+            let _ = self.sender.lock().unwrap().send(events.to_vec());
+            Ok(())
+        }
+    }
+
+    fn new_listener() -> (Strong<dyn IKeystoreEventListener>, mpsc::Receiver<Vec<KeystoreEvent>>) {
+        let (sender, receiver) = mpsc::channel();
+        let listener = BnKeystoreEventListener::new_binder(
+            TestListener { sender: Mutex::new(sender) },
+            BinderFeatures::default(),
+        );
+        (listener, receiver)
+    }
+
+    fn received(receiver: &mpsc::Receiver<Vec<KeystoreEvent>>) -> Vec<(KeystoreEventType, i32)> {
+        let mut result = vec![];
+        while let Ok(events) = receiver.recv_timeout(Duration::from_millis(200)) {
+            result.extend(events.iter().map(|e| (e.r#type, e.uid)));
+        }
+        result
+    }
+
+    #[test]
+    fn test_publish() {
+        let hub: Arc<EventHub> = Default::default();
+        // Nothing is recorded without listeners.
+        hub.publish(KeystoreEventType::KEY_CREATED, 10001, SecurityLevel::TRUSTED_ENVIRONMENT);
+
+        let (listener, receiver) = new_listener();
+        hub.register(&listener).unwrap();
+        // Registering twice has no effect.
+        hub.register(&listener).unwrap();
+        hub.publish(KeystoreEventType::KEY_CREATED, 10001, SecurityLevel::TRUSTED_ENVIRONMENT);
+        hub.publish(KeystoreEventType::KEY_DELETED, 10001, SecurityLevel::KEYSTORE);
+        hub.publish(KeystoreEventType::QUOTA_EXCEEDED, 10002, SecurityLevel::KEYSTORE);
+        assert_eq!(
+            received(&receiver),
+            vec![
+                (KeystoreEventType::KEY_CREATED, 10001),
+                (KeystoreEventType::KEY_DELETED, 10001),
+                (KeystoreEventType::QUOTA_EXCEEDED, 10002),
+            ]
+        );
+
+        hub.unregister(&listener);
+        hub.publish(KeystoreEventType::KEY_CREATED, 10001, SecurityLevel::TRUSTED_ENVIRONMENT);
+        assert_eq!(received(&receiver), vec![]);
+    }
+
+    #[test]
+    fn test_max_listeners() {
+        let hub: Arc<EventHub> = Default::default();
+        let listeners: Vec<_> = (0..MAX_LISTENERS).map(|_| new_listener()).collect();
+        for (listener, _) in &listeners {
+            hub.register(listener).unwrap();
+        }
+        let (listener, _receiver) = new_listener();
+        let e = hub.register(&listener).unwrap_err();
+        assert_eq!(e.root_cause().downcast_ref(), Some(&Error::Rc(ResponseCode::BACKEND_BUSY)));
+
+        hub.unregister(&listeners[0].0);
+        hub.register(&listener).unwrap();
+    }
+}
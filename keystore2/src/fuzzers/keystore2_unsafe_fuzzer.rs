@@ -16,6 +16,10 @@
 
 #![no_main]
 
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor,
+};
+use keystore2::key_descriptor_validation::{check_key_descriptor, DescriptorUse};
 use keystore2::{legacy_blob::LegacyBlobLoader, utils::ui_opts_2_compat};
 use keystore2_aaid::get_aaid;
 use keystore2_apc_compat::ApcHal;
@@ -23,7 +27,8 @@ use keystore2_crypto::{
     aes_gcm_decrypt, aes_gcm_encrypt, ec_key_generate_key, ec_key_get0_public_key,
     ec_key_marshal_private_key, ec_key_parse_private_key, ec_point_oct_to_point,
     ec_point_point_to_oct, ecdh_compute_key, generate_random_data, hkdf_expand, hkdf_extract,
-    hmac_sha256, parse_subject_from_certificate, Password, ZVec,
+    hmac_sha256, parse_subject_from_certificate, parse_subject_public_key_info_from_certificate,
+    Password, ZVec,
 };
 use keystore2_hal_names::get_hidl_instances;
 use keystore2_selinux::{check_access, getpidcon, setcon, Backend, Context, KeystoreKeyBackend};
@@ -120,6 +125,13 @@ enum FuzzCommand<'a> {
     SetCon {
         set_target: &'a [u8],
     },
+    ValidateKeyDescriptor {
+        domain: i32,
+        nspace: i64,
+        alias: Option<String>,
+        blob: Option<&'a [u8]>,
+        create: bool,
+    },
 }
 
 fuzz_target!(|commands: Vec<FuzzCommand>| {
@@ -176,6 +188,7 @@ fuzz_target!(|commands: Vec<FuzzCommand>| {
             }
             FuzzCommand::ParseSubjectFromCertificate { parse_buf } => {
                 let _res = parse_subject_from_certificate(parse_buf);
+                let _res = parse_subject_public_key_info_from_certificate(parse_buf);
             }
             FuzzCommand::GetHidlInstances {
                 hidl_package,
@@ -232,6 +245,16 @@ fuzz_target!(|commands: Vec<FuzzCommand>| {
             FuzzCommand::SetCon { set_target } => {
                 let _res = setcon(&CString::new(get_valid_cstring_data(set_target)).unwrap());
             }
+            FuzzCommand::ValidateKeyDescriptor { domain, nspace, alias, blob, create } => {
+                let key = KeyDescriptor {
+                    domain: Domain(domain),
+                    nspace,
+                    alias,
+                    blob: blob.map(|b| b.to_vec()),
+                };
+                let usage = if create { DescriptorUse::Create } else { DescriptorUse::Lookup };
+                let _res = check_key_descriptor(&key, usage);
+            }
         }
     }
 });
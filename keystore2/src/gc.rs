@@ -13,21 +13,24 @@
 // limitations under the License.
 
 //! This module implements the key garbage collector.
-//! The key garbage collector has one public function `notify_gc()`. This will create
+//! The key garbage collector has the public function `notify_gc()`. This will create
 //! a thread on demand which will query the database for unreferenced key entries,
 //! optionally dispose of sensitive key material appropriately, and then delete
-//! the key entry from the database.
+//! the key entry from the database. `run_now()` performs a garbage collection pass
+//! synchronously and reports its progress.
 
 use crate::ks_err;
 use crate::{
     async_task,
     database::{KeystoreDB, SupersededBlob, Uuid},
     super_key::SuperKeyManager,
+    thermal::THERMAL_THROTTLING,
 };
 use anyhow::{Context, Result};
 use async_task::AsyncTask;
 use std::sync::{
     atomic::{AtomicU8, Ordering},
+    mpsc::channel,
     Arc, RwLock,
 };
 
@@ -36,6 +39,15 @@ pub struct Gc {
     notified: Arc<AtomicU8>,
 }
 
+/// Progress report of a synchronous garbage collection pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcPassResult {
+    /// Number of key blobs that were processed during the pass.
+    pub blobs_processed: usize,
+    /// Number of key blobs still waiting for garbage collection after the pass.
+    pub blobs_remaining: usize,
+}
+
 impl Gc {
     /// Creates a garbage collector using the given async_task.
     /// The garbage collector needs a function to invalidate key blobs, a database connection,
@@ -62,6 +74,7 @@ impl Gc {
             shelf.get_or_put_with(|| GcInternal {
                 deleted_blob_ids: vec![],
                 superseded_blobs: vec![],
+                blobs_processed: 0,
                 invalidate_key,
                 db,
                 async_task: weak_at,
@@ -80,11 +93,34 @@ impl Gc {
             self.async_task.queue_lo(|shelf| shelf.get_downcast_mut::<GcInternal>().unwrap().step())
         }
     }
+
+    /// Runs a garbage collection pass processing up to `max_blobs` key blobs on the garbage
+    /// collector's worker thread and blocks until it has completed. The pass is queued with
+    /// high priority. If blobs remain after the pass, the regular low priority garbage
+    /// collection is notified to take care of them.
+    pub fn run_now(&self, max_blobs: usize) -> Result<GcPassResult> {
+        let (sender, receiver) = channel();
+        self.async_task.queue_hi(move |shelf| {
+            let result = shelf.get_downcast_mut::<GcInternal>().unwrap().run_pass(max_blobs);
+            // The receiver only goes away if the caller gave up waiting.
+            let _ = sender.send(result);
+        });
+        let result = receiver
+            .recv()
+            .context(ks_err!("Garbage collector went away."))?
+            .context(ks_err!("Garbage collection pass failed."))?;
+        if result.blobs_remaining != 0 {
+            self.notify_gc();
+        }
+        Ok(result)
+    }
 }
 
 struct GcInternal {
     deleted_blob_ids: Vec<i64>,
     superseded_blobs: Vec<SupersededBlob>,
+    // Running count of the blobs taken up for processing.
+    blobs_processed: usize,
     invalidate_key: Box<dyn Fn(&Uuid, &[u8]) -> Result<()> + Send + 'static>,
     db: KeystoreDB,
     async_task: std::sync::Weak<AsyncTask>,
@@ -114,6 +150,7 @@ impl GcInternal {
             // removed from the database regardless of whether the following
             // succeeds or not.
             self.deleted_blob_ids.push(blob_id);
+            self.blobs_processed += 1;
 
             // If the key has a km_uuid we try to get the corresponding device
             // and delete the key, unwrapping if necessary and possible.
@@ -132,12 +169,59 @@ impl GcInternal {
         Ok(())
     }
 
+    /// Releases the database pages that held deleted key blobs to the file system, if enabled.
+    fn release_free_pages(&mut self) {
+        match self.db.release_free_pages() {
+            Ok(0) => {}
+            Ok(pages) => log::info!("Released {pages} free database page(s)."),
+            Err(e) => log::error!("Failed to release free database pages: {:?}", e),
+        }
+    }
+
+    /// Processes up to `max_blobs` blobs in one go. Afterwards, the blobs that were handled are
+    /// removed from the database, and the number of blobs left is counted.
+    fn run_pass(&mut self, max_blobs: usize) -> Result<GcPassResult> {
+        let start = self.blobs_processed;
+        while self.blobs_processed - start < max_blobs {
+            let before = self.blobs_processed;
+            if let Err(e) = self.process_one_key() {
+                log::error!("Error trying to delete blob entry. {:?}", e);
+            }
+            if self.blobs_processed == before {
+                // No more blobs to process.
+                break;
+            }
+        }
+
+        // Remove processed blobs from the database. Passing a maximum of 0 does not take up
+        // further blobs.
+        let deleted_blob_ids = std::mem::take(&mut self.deleted_blob_ids);
+        self.db
+            .handle_next_superseded_blobs(&deleted_blob_ids, 0)
+            .context(ks_err!("Trying to remove processed blobs."))?;
+
+        self.release_free_pages();
+
+        let blobs_remaining =
+            self.db.count_superseded_blobs().context(ks_err!("Trying to count blobs."))?;
+        Ok(GcPassResult { blobs_processed: self.blobs_processed - start, blobs_remaining })
+    }
+
     /// Processes one key and then schedules another attempt until it runs out of blobs to delete.
+    /// Stops while background work is deferred due to thermal throttling. The thermal
+    /// throttling state notifies the garbage collector when the deferral ends.
     fn step(&mut self) {
         self.notified.store(0, Ordering::Relaxed);
+        if THERMAL_THROTTLING.defer_background_work() {
+            return;
+        }
         if let Err(e) = self.process_one_key() {
             log::error!("Error trying to delete blob entry. {:?}", e);
         }
+        if self.deleted_blob_ids.is_empty() {
+            // All deletions are committed, so the pages that held the deleted blobs are free.
+            self.release_free_pages();
+        }
         // Schedule the next step. This gives high priority requests a chance to interleave.
         if !self.deleted_blob_ids.is_empty() {
             if let Some(at) = self.async_task.upgrade() {
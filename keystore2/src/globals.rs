@@ -17,12 +17,17 @@
 //! to talk to.
 
 use crate::async_task::AsyncTask;
-use crate::gc::Gc;
+use crate::boot_profile::BOOT_PROFILE;
+use crate::events::EVENTS;
+use crate::gc::{Gc, GcPassResult};
+use crate::import_limits::ImportLimiter;
+use crate::key_entry_cache::KeyEntryCache;
 use crate::km_compat::{BacklevelKeyMintWrapper, KeyMintV1};
 use crate::ks_err;
 use crate::legacy_blob::LegacyBlobLoader;
 use crate::legacy_importer::LegacyImporter;
 use crate::super_key::SuperKeyManager;
+use crate::test_hooks;
 use crate::utils::{retry_get_interface, watchdog as wd};
 use crate::{
     database::KeystoreDB,
@@ -44,9 +49,10 @@ use android_hardware_security_secureclock::aidl::android::hardware::security::se
 };
 use android_security_compat::aidl::android::security::compat::IKeystoreCompatService::IKeystoreCompatService;
 use anyhow::{Context, Result};
-use binder::FromIBinder;
 use binder::{get_declared_instances, is_declared};
+use binder::{FromIBinder, Interface};
 use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::Instant;
 use std::{cell::RefCell, sync::Once};
 use std::{collections::HashMap, path::Path, path::PathBuf};
 
@@ -63,6 +69,7 @@ static DB_INIT: Once = Once::new();
 pub fn create_thread_local_db() -> KeystoreDB {
     let db_path = DB_PATH.read().expect("Could not get the database directory");
 
+    let started = Instant::now();
     let result = KeystoreDB::new(&db_path, Some(GC.clone()));
     let mut db = match result {
         Ok(db) => db,
@@ -82,16 +89,40 @@ pub fn create_thread_local_db() -> KeystoreDB {
                 "Cleaned up {n} failed entries, indicating keystore crash on key generation"
             );
         }
+        // The first connection creates or upgrades the schema.
+        BOOT_PROFILE.record("db_open", started);
     });
     db
 }
 
+/// Open a read-only connection to the Keystore 2.0 database. This is called during the
+/// initialization of the thread local DB_READER field. It should never be called directly.
+/// The read-write connection of this thread is touched first, so that the database schema
+/// is guaranteed to be up to date before the read-only connection is opened.
+pub fn create_thread_local_reader_db() -> KeystoreDB {
+    DB.with(|_| ());
+    let db_path = DB_PATH.read().expect("Could not get the database directory");
+
+    match KeystoreDB::new_read_only(&db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            log::error!("Failed to open read-only Keystore database at {db_path:?}: {e:?}");
+            panic!("Failed to open read-only database for Keystore, cannot continue: {e:?}")
+        }
+    }
+}
+
 thread_local! {
     /// Database connections are not thread safe, but connecting to the
     /// same database multiple times is safe as long as each connection is
     /// used by only one thread. So we store one database connection per
     /// thread in this thread local key.
     pub static DB: RefCell<KeystoreDB> = RefCell::new(create_thread_local_db());
+
+    /// A read-only database connection per thread. Heavy read-only queries, like listing
+    /// entries and gathering storage statistics, use this connection so that they do not
+    /// contend with the write path on `DB`.
+    pub static DB_READER: RefCell<KeystoreDB> = RefCell::new(create_thread_local_reader_db());
 }
 
 struct DevicesMap<T: FromIBinder + ?Sized> {
@@ -113,6 +144,11 @@ impl<T: FromIBinder + ?Sized> DevicesMap<T> {
             .map(|(dev, hw_info)| ((*dev).clone(), (*hw_info).clone(), *uuid))
     }
 
+    /// Additional instances of a security level are only looked up by uuid.
+    fn insert_instance(&mut self, uuid: Uuid, dev: Strong<T>, hw_info: KeyMintHardwareInfo) {
+        self.devices_by_uuid.insert(uuid, (dev, hw_info));
+    }
+
     fn devices(&self) -> Vec<Strong<T>> {
         self.devices_by_uuid.values().map(|(dev, _)| dev.clone()).collect()
     }
@@ -138,11 +174,19 @@ impl<T: FromIBinder + ?Sized> Default for DevicesMap<T> {
     }
 }
 
+/// Returns true if the system user is headless, i.e., it runs in the background and never has a
+/// lock screen, while people use the device as secondary users. This is the case on, e.g.,
+/// Android Automotive devices.
+pub fn is_headless_system_user_mode() -> bool {
+    rustutils::system_properties::read_bool("ro.fw.mu.headless_system_user", false).unwrap_or(false)
+}
+
 /// The path where keystore stores all its keys.
 pub static DB_PATH: LazyLock<RwLock<PathBuf>> =
     LazyLock::new(|| RwLock::new(Path::new("/data/misc/keystore").to_path_buf()));
 /// Runtime database of unwrapped super keys.
-pub static SUPER_KEY: LazyLock<Arc<RwLock<SuperKeyManager>>> = LazyLock::new(Default::default);
+pub static SUPER_KEY: LazyLock<Arc<RwLock<SuperKeyManager>>> =
+    LazyLock::new(|| Arc::new(RwLock::new(SuperKeyManager::new(is_headless_system_user_mode()))));
 /// Map of KeyMint devices.
 static KEY_MINT_DEVICES: LazyLock<Mutex<DevicesMap<dyn IKeyMintDevice>>> =
     LazyLock::new(Default::default);
@@ -152,7 +196,8 @@ static TIME_STAMP_DEVICE: Mutex<Option<Strong<dyn ISecureClock>>> = Mutex::new(N
 /// priorities.
 pub static ASYNC_TASK: LazyLock<Arc<AsyncTask>> = LazyLock::new(Default::default);
 /// Singleton for enforcements.
-pub static ENFORCEMENTS: LazyLock<Enforcements> = LazyLock::new(Default::default);
+pub static ENFORCEMENTS: LazyLock<Enforcements> =
+    LazyLock::new(|| Enforcements::new(is_headless_system_user_mode()));
 /// LegacyBlobLoader is initialized and exists globally.
 /// The same directory used by the database is used by the LegacyBlobLoader as well.
 pub static LEGACY_BLOB_LOADER: LazyLock<Arc<LegacyBlobLoader>> = LazyLock::new(|| {
@@ -163,6 +208,10 @@ pub static LEGACY_BLOB_LOADER: LazyLock<Arc<LegacyBlobLoader>> = LazyLock::new(|
 /// Legacy migrator. Atomically migrates legacy blobs to the database.
 pub static LEGACY_IMPORTER: LazyLock<Arc<LegacyImporter>> =
     LazyLock::new(|| Arc::new(LegacyImporter::new(Arc::new(Default::default()))));
+/// Enforces the per-UID limits on key and certificate imports.
+pub static IMPORT_LIMITER: LazyLock<ImportLimiter> = LazyLock::new(Default::default);
+/// Caches recent `getKeyEntry` responses.
+pub static KEY_ENTRY_CACHE: LazyLock<KeyEntryCache> = LazyLock::new(Default::default);
 /// Background thread which handles logging via statsd and logd
 pub static LOGS_HANDLER: LazyLock<Arc<AsyncTask>> = LazyLock::new(Default::default);
 
@@ -185,11 +234,25 @@ static GC: LazyLock<Arc<Gc>> = LazyLock::new(|| {
     }))
 });
 
+/// Runs a garbage collection pass processing up to `max_blobs` key blobs and blocks until
+/// it has completed. See `Gc::run_now`.
+pub fn run_gc_now(max_blobs: usize) -> Result<GcPassResult> {
+    GC.run_now(max_blobs)
+}
+
+/// Wakes up the background garbage collector.
+pub fn notify_gc() {
+    GC.notify_gc()
+}
+
 /// Determine the service name for a KeyMint device of the given security level
 /// gotten by binder service from the device and determining what services
 /// are available.
 fn keymint_service_name(security_level: &SecurityLevel) -> Result<Option<String>> {
     let keymint_descriptor: &str = <BpKeyMintDevice as IKeyMintDevice>::get_descriptor();
+    if test_hooks::is_keymaster_forced() {
+        return Ok(None);
+    }
     let keymint_instances = get_declared_instances(keymint_descriptor).unwrap();
 
     let service_name = match *security_level {
@@ -218,16 +281,47 @@ fn keymint_service_name(security_level: &SecurityLevel) -> Result<Option<String>
     Ok(service_name)
 }
 
+/// Returns true if a KeyMint instance is declared for the given security level.
+pub fn is_keymint_declared(security_level: &SecurityLevel) -> bool {
+    matches!(keymint_service_name(security_level), Ok(Some(_)))
+}
+
+/// Prefix of the declared KeyMint instances that provide additional StrongBox instances, e.g.,
+/// `strongbox_esim` on devices with both an embedded secure element and an eSIM secure element.
+const STRONGBOX_INSTANCE_PREFIX: &str = "strongbox_";
+
+/// Returns the names of the additional StrongBox instances declared by the device.
+pub fn get_additional_strongbox_instances() -> Vec<String> {
+    let keymint_descriptor: &str = <BpKeyMintDevice as IKeyMintDevice>::get_descriptor();
+    get_declared_instances(keymint_descriptor)
+        .unwrap_or_else(|e| {
+            log::error!("Failed to get declared KeyMint instances: {e:?}");
+            vec![]
+        })
+        .into_iter()
+        .filter(|instance| {
+            instance.strip_prefix(STRONGBOX_INSTANCE_PREFIX).is_some_and(|name| !name.is_empty())
+        })
+        .collect()
+}
+
 /// Make a new connection to a KeyMint device of the given security level.
-/// If no native KeyMint device can be found this function also brings
+/// If `instance` is given, this connects to the declared KeyMint instance of that name.
+/// Otherwise, if no native KeyMint device can be found this function also brings
 /// up the compatibility service and attempts to connect to the legacy wrapper.
 fn connect_keymint(
     security_level: &SecurityLevel,
+    instance: Option<&str>,
 ) -> Result<(Strong<dyn IKeyMintDevice>, KeyMintHardwareInfo)> {
     // Show the keymint interface that is registered in the binder
     // service and use the security level to get the service name.
-    let service_name = keymint_service_name(security_level)
-        .context(ks_err!("Get service name from binder service"))?;
+    let service_name = match instance {
+        Some(instance) => {
+            Some(format!("{}/{}", <BpKeyMintDevice as IKeyMintDevice>::get_descriptor(), instance))
+        }
+        None => keymint_service_name(security_level)
+            .context(ks_err!("Get service name from binder service"))?,
+    };
 
     let (keymint, hal_version) = if let Some(service_name) = service_name {
         let km: Strong<dyn IKeyMintDevice> =
@@ -251,19 +345,33 @@ fn connect_keymint(
         let keystore_compat_service: Strong<dyn IKeystoreCompatService> =
             map_binder_status_code(binder::get_interface("android.security.compat"))
                 .context(ks_err!("Trying to connect to compat service."))?;
-        (
-            map_binder_status(keystore_compat_service.getKeyMintDevice(*security_level))
-                .map_err(|e| match e {
+        let get_device = |security_level| {
+            map_binder_status(keystore_compat_service.getKeyMintDevice(security_level)).map_err(
+                |e| match e {
                     Error::BinderTransaction(StatusCode::NAME_NOT_FOUND) => {
                         Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)
                     }
                     e => e,
-                })
-                .context(ks_err!(
-                    "Trying to get Legacy wrapper. Attempt to get keystore \
-                    compat service for security level {:?}",
-                    *security_level
-                ))?,
+                },
+            )
+        };
+        let keymint = match get_device(*security_level) {
+            // When emulating a Keymaster-only device without Keymaster hardware, use the
+            // software KeyMint device of km_compat in place of the TEE.
+            Err(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
+                if test_hooks::is_keymaster_forced()
+                    && *security_level == SecurityLevel::TRUSTED_ENVIRONMENT =>
+            {
+                get_device(SecurityLevel::SOFTWARE)
+            }
+            result => result,
+        };
+        (
+            keymint.context(ks_err!(
+                "Trying to get Legacy wrapper. Attempt to get keystore \
+                compat service for security level {:?}",
+                *security_level
+            ))?,
             None,
         )
     };
@@ -338,6 +446,16 @@ fn connect_keymint(
         hw_info.versionNumber = hal_version;
     }
 
+    // Additional instances must implement the security level they are declared for.
+    if instance.is_some() && hw_info.securityLevel != *security_level {
+        return Err(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)).context(ks_err!(
+            "KeyMint instance {:?} has security level {:?}, expected {:?}.",
+            instance,
+            hw_info.securityLevel,
+            security_level
+        ));
+    }
+
     Ok((keymint, hw_info))
 }
 
@@ -352,13 +470,42 @@ pub fn get_keymint_device(
         Ok((dev, hw_info, uuid))
     } else {
         let (dev, hw_info) =
-            connect_keymint(security_level).context(ks_err!("Cannot connect to Keymint"))?;
+            connect_keymint(security_level, None).context(ks_err!("Cannot connect to Keymint"))?;
+        EVENTS.watch_hal(*security_level, dev.as_binder());
         devices_map.insert(*security_level, dev, hw_info);
         // Unwrap must succeed because we just inserted it.
         Ok(devices_map.dev_by_sec_level(security_level).unwrap())
     }
 }
 
+/// Returns the uuid of the additional StrongBox instance with the given name.
+pub fn strongbox_instance_uuid(instance: &str) -> Option<Uuid> {
+    instance
+        .strip_prefix(STRONGBOX_INSTANCE_PREFIX)
+        .and_then(|name| Uuid::for_keymint_instance(SecurityLevel::STRONGBOX, name))
+}
+
+/// Get the additional StrongBox instance with the given name either from our cache or
+/// by making a new connection. Returns the device, the hardware info and the uuid.
+pub fn get_strongbox_instance(
+    instance: &str,
+) -> Result<(Strong<dyn IKeyMintDevice>, KeyMintHardwareInfo, Uuid)> {
+    let uuid = strongbox_instance_uuid(instance)
+        .ok_or(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
+        .context(ks_err!("Invalid StrongBox instance name {:?}.", instance))?;
+    let mut devices_map = KEY_MINT_DEVICES.lock().unwrap();
+    if let Some((dev, hw_info, uuid)) = devices_map.dev_by_uuid(&uuid) {
+        Ok((dev, hw_info, uuid))
+    } else {
+        let (dev, hw_info) = connect_keymint(&SecurityLevel::STRONGBOX, Some(instance))
+            .context(ks_err!("Cannot connect to Keymint instance {:?}", instance))?;
+        EVENTS.watch_hal(SecurityLevel::STRONGBOX, dev.as_binder());
+        devices_map.insert_instance(uuid, dev, hw_info);
+        // Unwrap must succeed because we just inserted it.
+        Ok(devices_map.dev_by_uuid(&uuid).unwrap())
+    }
+}
+
 /// Get a keymint device for the given uuid. This will only access the cache, but will not
 /// attempt to establish a new connection. It is assumed that the cache is already populated
 /// when this is called. This is a fair assumption, because service.rs iterates through all
@@ -0,0 +1,501 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module translates asymmetric private keys that are imported as a JSON Web Key
+//! (RFC 7517, 7518 and 8037) or as a COSE_Key (RFC 9052, 9053 and 8230) into PKCS#8, which is
+//! the only asymmetric import format of KeyMint. EC keys on P-256, P-384 and P-521, RSA keys
+//! with all CRT parameters, and Ed25519 and X25519 keys are supported.
+//!
+//! The encoding is told apart by the first byte of the key material: a PKCS#8 PrivateKeyInfo
+//! is a DER SEQUENCE (0x30), a JWK is a JSON object (`{`), and a COSE_Key is a CBOR map (major
+//! type 5). The PKCS#8 encoding is kept in `ZVec`s. The parsed input is not, but the caller
+//! holds the same material in its request anyway.
+
+use crate::error::Error;
+use crate::ks_err;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::ErrorCode::ErrorCode;
+use anyhow::{Context, Result};
+use keystore2_crypto::ZVec;
+use serde_cbor::Value as CborValue;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+/// The `[1] publicKey` field of an ECPrivateKey.
+const TAG_EC_PUBLIC_KEY: u8 = 0xa1;
+
+/// The DER encoding of an ASN.1 NULL.
+const DER_NULL: &[u8] = &[0x05, 0x00];
+
+/// 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// A named curve of EC keys.
+#[derive(Debug)]
+struct EcCurve {
+    oid: &'static [u8],
+    jwk_name: &'static str,
+    cose_id: i128,
+    /// The size of the private key and of each coordinate in bytes.
+    size: usize,
+}
+
+const EC_CURVES: &[EcCurve] = &[
+    // 1.2.840.10045.3.1.7
+    EcCurve {
+        oid: &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+        jwk_name: "P-256",
+        cose_id: 1,
+        size: 32,
+    },
+    // 1.3.132.0.34
+    EcCurve { oid: &[0x2b, 0x81, 0x04, 0x00, 0x22], jwk_name: "P-384", cose_id: 2, size: 48 },
+    // 1.3.132.0.35
+    EcCurve { oid: &[0x2b, 0x81, 0x04, 0x00, 0x23], jwk_name: "P-521", cose_id: 3, size: 66 },
+];
+
+/// A curve of octet key pairs, whose algorithm is identified by the curve alone.
+#[derive(Debug)]
+struct OkpCurve {
+    oid: &'static [u8],
+    jwk_name: &'static str,
+    cose_id: i128,
+}
+
+const OKP_CURVES: &[OkpCurve] = &[
+    // 1.3.101.110
+    OkpCurve { oid: &[0x2b, 0x65, 0x6e], jwk_name: "X25519", cose_id: 4 },
+    // 1.3.101.112
+    OkpCurve { oid: &[0x2b, 0x65, 0x70], jwk_name: "Ed25519", cose_id: 6 },
+];
+
+/// The JWK members of an RSA private key, in the order of the fields of an RSAPrivateKey.
+const JWK_RSA_MEMBERS: [&str; 8] = ["n", "e", "d", "p", "q", "dp", "dq", "qi"];
+
+/// COSE_Key labels, see the COSE Key Common Parameters and COSE Key Type Parameters registries.
+const COSE_LABEL_KTY: i128 = 1;
+const COSE_LABEL_CRV: i128 = -1;
+const COSE_LABEL_X: i128 = -2;
+const COSE_LABEL_Y: i128 = -3;
+const COSE_LABEL_D: i128 = -4;
+/// The labels of an RSA private key, from n to qInv, in the order of the fields of an
+/// RSAPrivateKey.
+const COSE_RSA_LABELS: [i128; 8] = [-1, -2, -3, -4, -5, -6, -7, -8];
+
+const COSE_KTY_OKP: i128 = 1;
+const COSE_KTY_EC2: i128 = 2;
+const COSE_KTY_RSA: i128 = 3;
+
+/// A private key decoded from a JWK or a COSE_Key.
+#[derive(Debug)]
+enum PrivateKey {
+    Ec {
+        curve: &'static EcCurve,
+        d: ZVec,
+        public: Option<(ZVec, ZVec)>,
+    },
+    /// n, e, d, p, q, dP, dQ, and qInv.
+    Rsa([ZVec; 8]),
+    Okp {
+        curve: &'static OkpCurve,
+        d: ZVec,
+    },
+}
+
+fn invalid() -> Error {
+    Error::Km(ErrorCode::INVALID_ARGUMENT)
+}
+
+/// Translates `key_data` into a PKCS#8 PrivateKeyInfo if it is a JWK or a COSE_Key. Returns
+/// None if it is neither, in which case it is imported as is.
+pub fn translate_to_pkcs8(key_data: &[u8]) -> Result<Option<ZVec>> {
+    let key = match key_data.first() {
+        Some(b'{') => parse_jwk(key_data).context(ks_err!("Invalid JWK."))?,
+        Some(0xa0..=0xbf) => parse_cose_key(key_data).context(ks_err!("Invalid COSE_Key."))?,
+        _ => return Ok(None),
+    };
+    to_pkcs8(&key).map(Some).context(ks_err!())
+}
+
+fn parse_jwk(key_data: &[u8]) -> Result<PrivateKey> {
+    let jwk: serde_json::Map<String, JsonValue> =
+        serde_json::from_slice(key_data).map_err(|_| invalid()).context(ks_err!())?;
+    let string = |name: &str| {
+        jwk.get(name)
+            .and_then(JsonValue::as_str)
+            .ok_or_else(invalid)
+            .context(ks_err!("No member {:?}.", name))
+    };
+    let bytes = |name: &str| {
+        string(name).and_then(|s| base64url_decode(s).context(ks_err!("Member {:?}.", name)))
+    };
+    match string("kty")? {
+        "EC" => {
+            let crv = string("crv")?;
+            let curve = EC_CURVES
+                .iter()
+                .find(|c| c.jwk_name == crv)
+                .ok_or(Error::Km(ErrorCode::UNSUPPORTED_EC_CURVE))
+                .context(ks_err!("Unsupported curve {:?}.", crv))?;
+            Ok(PrivateKey::Ec { curve, d: bytes("d")?, public: Some((bytes("x")?, bytes("y")?)) })
+        }
+        "RSA" => {
+            let mut components = Vec::with_capacity(JWK_RSA_MEMBERS.len());
+            for name in JWK_RSA_MEMBERS {
+                components.push(bytes(name)?);
+            }
+            Ok(PrivateKey::Rsa(components.try_into().unwrap()))
+        }
+        "OKP" => {
+            let crv = string("crv")?;
+            let curve = OKP_CURVES
+                .iter()
+                .find(|c| c.jwk_name == crv)
+                .ok_or(Error::Km(ErrorCode::UNSUPPORTED_ALGORITHM))
+                .context(ks_err!("Unsupported curve {:?}.", crv))?;
+            Ok(PrivateKey::Okp { curve, d: bytes("d")? })
+        }
+        kty => Err(Error::Km(ErrorCode::UNSUPPORTED_ALGORITHM))
+            .context(ks_err!("Unsupported key type {:?}.", kty)),
+    }
+}
+
+fn parse_cose_key(key_data: &[u8]) -> Result<PrivateKey> {
+    let map: BTreeMap<CborValue, CborValue> =
+        serde_cbor::from_slice(key_data).map_err(|_| invalid()).context(ks_err!())?;
+    let get = |label: i128| map.get(&CborValue::Integer(label));
+    let integer = |label: i128| match get(label) {
+        Some(CborValue::Integer(i)) => Ok(*i),
+        _ => Err(invalid()).context(ks_err!("No integer with label {}.", label)),
+    };
+    let bytes = |label: i128| match get(label) {
+        Some(CborValue::Bytes(b)) => ZVec::try_from(b.as_slice()).context(ks_err!()),
+        _ => Err(invalid()).context(ks_err!("No byte string with label {}.", label)),
+    };
+    match integer(COSE_LABEL_KTY)? {
+        COSE_KTY_EC2 => {
+            let crv = integer(COSE_LABEL_CRV)?;
+            let curve = EC_CURVES
+                .iter()
+                .find(|c| c.cose_id == crv)
+                .ok_or(Error::Km(ErrorCode::UNSUPPORTED_EC_CURVE))
+                .context(ks_err!("Unsupported curve {}.", crv))?;
+            // The public key is optional in a private COSE_Key, and y may be given as a sign
+            // bit only. ECPrivateKey does not need the public key, so it is only passed along if
+            // it is given in full.
+            let public = match (get(COSE_LABEL_X), get(COSE_LABEL_Y)) {
+                (Some(_), Some(CborValue::Bytes(_))) => {
+                    Some((bytes(COSE_LABEL_X)?, bytes(COSE_LABEL_Y)?))
+                }
+                _ => None,
+            };
+            Ok(PrivateKey::Ec { curve, d: bytes(COSE_LABEL_D)?, public })
+        }
+        COSE_KTY_RSA => {
+            let mut components = Vec::with_capacity(COSE_RSA_LABELS.len());
+            for label in COSE_RSA_LABELS {
+                components.push(bytes(label)?);
+            }
+            Ok(PrivateKey::Rsa(components.try_into().unwrap()))
+        }
+        COSE_KTY_OKP => {
+            let crv = integer(COSE_LABEL_CRV)?;
+            let curve = OKP_CURVES
+                .iter()
+                .find(|c| c.cose_id == crv)
+                .ok_or(Error::Km(ErrorCode::UNSUPPORTED_ALGORITHM))
+                .context(ks_err!("Unsupported curve {}.", crv))?;
+            Ok(PrivateKey::Okp { curve, d: bytes(COSE_LABEL_D)? })
+        }
+        kty => Err(Error::Km(ErrorCode::UNSUPPORTED_ALGORITHM))
+            .context(ks_err!("Unsupported key type {}.", kty)),
+    }
+}
+
+/// Encodes `key` as a PKCS#8 PrivateKeyInfo (RFC 5208, 5915, 8017 and 8410).
+fn to_pkcs8(key: &PrivateKey) -> Result<ZVec> {
+    let (algorithm, private_key) = match key {
+        PrivateKey::Ec { curve, d, public } => {
+            let d = left_pad(d, curve.size).context(ks_err!("Private key."))?;
+            let mut ec_fields = vec![der(TAG_INTEGER, &[&[1]])?, der(TAG_OCTET_STRING, &[&d])?];
+            if let Some((x, y)) = public {
+                let x = left_pad(x, curve.size).context(ks_err!("Coordinate x."))?;
+                let y = left_pad(y, curve.size).context(ks_err!("Coordinate y."))?;
+                // An uncompressed point, in a bit string without unused bits.
+                let point = der(TAG_BIT_STRING, &[&[0, 0x04], &x, &y])?;
+                ec_fields.push(der(TAG_EC_PUBLIC_KEY, &[&point])?);
+            }
+            (
+                der(
+                    TAG_SEQUENCE,
+                    &[&der(TAG_OID, &[OID_EC_PUBLIC_KEY])?, &der(TAG_OID, &[curve.oid])?],
+                )?,
+                der(TAG_SEQUENCE, &ec_fields.iter().map(|f| &f[..]).collect::<Vec<_>>())?,
+            )
+        }
+        PrivateKey::Rsa(components) => {
+            let mut rsa_fields = vec![der(TAG_INTEGER, &[&[0]])?];
+            for component in components {
+                rsa_fields.push(der_unsigned_integer(component)?);
+            }
+            (
+                der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_RSA_ENCRYPTION])?, DER_NULL])?,
+                der(TAG_SEQUENCE, &rsa_fields.iter().map(|f| &f[..]).collect::<Vec<_>>())?,
+            )
+        }
+        PrivateKey::Okp { curve, d } => {
+            (der(TAG_SEQUENCE, &[&der(TAG_OID, &[curve.oid])?])?, der(TAG_OCTET_STRING, &[d])?)
+        }
+    };
+    der(
+        TAG_SEQUENCE,
+        &[&der(TAG_INTEGER, &[&[0]])?, &algorithm, &der(TAG_OCTET_STRING, &[&private_key])?],
+    )
+}
+
+/// Returns the DER element with the tag `tag` and the concatenation of `parts` as contents.
+fn der(tag: u8, parts: &[&[u8]]) -> Result<ZVec> {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    let len_bytes = len.to_be_bytes();
+    let significant =
+        &len_bytes[len_bytes.iter().position(|b| *b != 0).unwrap_or(len_bytes.len())..];
+    let header_len = if len < 0x80 { 2 } else { 2 + significant.len() };
+    let mut element = ZVec::new(header_len + len).context(ks_err!())?;
+    element[0] = tag;
+    if len < 0x80 {
+        element[1] = len as u8;
+    } else {
+        element[1] = 0x80 | significant.len() as u8;
+        element[2..header_len].copy_from_slice(significant);
+    }
+    let mut offset = header_len;
+    for part in parts {
+        element[offset..offset + part.len()].copy_from_slice(part);
+        offset += part.len();
+    }
+    Ok(element)
+}
+
+/// Returns the DER integer of the unsigned big-endian integer `value`.
+fn der_unsigned_integer(value: &[u8]) -> Result<ZVec> {
+    let value = &value[value.iter().position(|b| *b != 0).unwrap_or(value.len())..];
+    match value.first() {
+        None => der(TAG_INTEGER, &[&[0]]),
+        // A leading zero keeps the integer positive.
+        Some(b) if b & 0x80 != 0 => der(TAG_INTEGER, &[&[0], value]),
+        Some(_) => der(TAG_INTEGER, &[value]),
+    }
+}
+
+/// Returns `value` with leading zeros added or removed to make it `size` bytes long.
+fn left_pad(value: &[u8], size: usize) -> Result<ZVec> {
+    let value = &value[value.iter().position(|b| *b != 0).unwrap_or(value.len())..];
+    if value.len() > size {
+        return Err(invalid()).context(ks_err!("Expected at most {} bytes.", size));
+    }
+    let mut padded = ZVec::new(size).context(ks_err!())?;
+    padded[size - value.len()..].copy_from_slice(value);
+    Ok(padded)
+}
+
+/// Decodes unpadded base64url (RFC 4648), as used by JWK.
+fn base64url_decode(encoded: &str) -> Result<ZVec> {
+    if encoded.len() % 4 == 1 {
+        return Err(invalid()).context(ks_err!("Invalid base64url length."));
+    }
+    let mut decoded = ZVec::new(encoded.len() * 3 / 4).context(ks_err!())?;
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut len = 0;
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return Err(invalid()).context(ks_err!("Invalid base64url character.")),
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded[len] = (bits >> bit_count) as u8;
+            len += 1;
+        }
+    }
+    decoded.reduce_len(len);
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A P-256 private key, as PKCS#8 with the public key.
+    const EC_PKCS8: &str = concat!(
+        "308187020100301306072a8648ce3d020106082a8648ce3d030107046d306b020101042034777b879207486d",
+        "9970d29a764de22f892767c6f70fdeae399d826a4b93a471a144034200048e6f0d39cdcdcd3f1baeb0b18bc6",
+        "9b00e1f0f9e5433319fdf2b2e660e5ed27c37a2bbc06f7bc6fa1aae1a3fb3cc22496e41fccf1a69edcacb29b",
+        "d5f2523e6e39"
+    );
+
+    /// The key of `EC_PKCS8` without the public key.
+    const EC_PKCS8_PRIVATE_ONLY: &str = concat!(
+        "3041020100301306072a8648ce3d020106082a8648ce3d03010704273025020101042034777b879207486d99",
+        "70d29a764de22f892767c6f70fdeae399d826a4b93a471"
+    );
+
+    const EC_D: &str = "34777b879207486d9970d29a764de22f892767c6f70fdeae399d826a4b93a471";
+    const EC_X: &str = "8e6f0d39cdcdcd3f1baeb0b18bc69b00e1f0f9e5433319fdf2b2e660e5ed27c3";
+    const EC_Y: &str = "7a2bbc06f7bc6fa1aae1a3fb3cc22496e41fccf1a69edcacb29bd5f2523e6e39";
+
+    const ED25519_PKCS8: &str = concat!(
+        "302e020100300506032b6570042204206c45fe71dc22f3fac784a28fe2ad6812a2315a2643d4b90e510f83d5",
+        "e9b0ebdc"
+    );
+
+    /// An RSA private key with small made-up components, which shows how they are encoded.
+    const RSA_PKCS8: &str = concat!(
+        "3032020100300d06092a864886f70d0101010500041e301c0201000201010202008002017f02010202010302",
+        "0104020105020106"
+    );
+
+    fn km_error(result: Result<Option<ZVec>>) -> Option<ErrorCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Km(ec)) => Some(*ec),
+            _ => None,
+        }
+    }
+
+    fn translate(key_data: &[u8]) -> Vec<u8> {
+        translate_to_pkcs8(key_data).unwrap().unwrap().to_vec()
+    }
+
+    fn cose_key(entries: Vec<(i128, CborValue)>) -> Vec<u8> {
+        let map = entries.into_iter().map(|(label, v)| (CborValue::Integer(label), v)).collect();
+        serde_cbor::to_vec(&CborValue::Map(map)).unwrap()
+    }
+
+    fn cose_bytes(hex_value: &str) -> CborValue {
+        CborValue::Bytes(hex::decode(hex_value).unwrap())
+    }
+
+    #[test]
+    fn test_jwk() {
+        let jwk = r#"{"kty":"EC","crv":"P-256",
+            "d":"NHd7h5IHSG2ZcNKadk3iL4knZ8b3D96uOZ2CakuTpHE",
+            "x":"jm8NOc3NzT8brrCxi8abAOHw-eVDMxn98rLmYOXtJ8M",
+            "y":"eiu8Bve8b6Gq4aP7PMIkluQfzPGmntysspvV8lI-bjk"}"#;
+        assert_eq!(translate(jwk.as_bytes()), hex::decode(EC_PKCS8).unwrap());
+
+        let jwk = r#"{"kty":"OKP","crv":"Ed25519","d":"bEX-cdwi8_rHhKKP4q1oEqIxWiZD1LkOUQ-D1emw69w",
+            "x":"ignored"}"#;
+        assert_eq!(translate(jwk.as_bytes()), hex::decode(ED25519_PKCS8).unwrap());
+
+        let jwk = r#"{"kty":"RSA","n":"AQ","e":"gA","d":"AH8","p":"Ag","q":"Aw","dp":"BA",
+            "dq":"BQ","qi":"Bg"}"#;
+        assert_eq!(translate(jwk.as_bytes()), hex::decode(RSA_PKCS8).unwrap());
+    }
+
+    #[test]
+    fn test_cose_key() {
+        let key = cose_key(vec![
+            (COSE_LABEL_KTY, CborValue::Integer(COSE_KTY_EC2)),
+            (COSE_LABEL_CRV, CborValue::Integer(1)),
+            (COSE_LABEL_X, cose_bytes(EC_X)),
+            (COSE_LABEL_Y, cose_bytes(EC_Y)),
+            (COSE_LABEL_D, cose_bytes(EC_D)),
+        ]);
+        assert_eq!(translate(&key), hex::decode(EC_PKCS8).unwrap());
+
+        // A compressed public key is left out.
+        let key = cose_key(vec![
+            (COSE_LABEL_KTY, CborValue::Integer(COSE_KTY_EC2)),
+            (COSE_LABEL_CRV, CborValue::Integer(1)),
+            (COSE_LABEL_X, cose_bytes(EC_X)),
+            (COSE_LABEL_Y, CborValue::Bool(true)),
+            (COSE_LABEL_D, cose_bytes(EC_D)),
+        ]);
+        assert_eq!(translate(&key), hex::decode(EC_PKCS8_PRIVATE_ONLY).unwrap());
+
+        let key = cose_key(vec![
+            (COSE_LABEL_KTY, CborValue::Integer(COSE_KTY_OKP)),
+            (COSE_LABEL_CRV, CborValue::Integer(6)),
+            (
+                COSE_LABEL_D,
+                cose_bytes("6c45fe71dc22f3fac784a28fe2ad6812a2315a2643d4b90e510f83d5e9b0ebdc"),
+            ),
+        ]);
+        assert_eq!(translate(&key), hex::decode(ED25519_PKCS8).unwrap());
+
+        let mut entries = vec![(COSE_LABEL_KTY, CborValue::Integer(COSE_KTY_RSA))];
+        for (label, value) in
+            COSE_RSA_LABELS.iter().zip(["01", "80", "007f", "02", "03", "04", "05", "06"])
+        {
+            entries.push((*label, cose_bytes(value)));
+        }
+        assert_eq!(translate(&cose_key(entries)), hex::decode(RSA_PKCS8).unwrap());
+    }
+
+    #[test]
+    fn test_translate_errors() {
+        // PKCS#8 and anything else is imported as is.
+        assert!(translate_to_pkcs8(&hex::decode(EC_PKCS8).unwrap()).unwrap().is_none());
+        assert!(translate_to_pkcs8(&[]).unwrap().is_none());
+
+        let invalid = Some(ErrorCode::INVALID_ARGUMENT);
+        assert_eq!(km_error(translate_to_pkcs8(b"{")), invalid);
+        assert_eq!(km_error(translate_to_pkcs8(br#"{"kty":"EC","crv":"P-256"}"#)), invalid);
+        assert_eq!(
+            km_error(translate_to_pkcs8(br#"{"kty":"OKP","crv":"Ed25519","d":"not base64!"}"#)),
+            invalid
+        );
+        assert_eq!(
+            km_error(translate_to_pkcs8(br#"{"kty":"EC","crv":"secp256k1","d":"AQ"}"#)),
+            Some(ErrorCode::UNSUPPORTED_EC_CURVE)
+        );
+        assert_eq!(
+            km_error(translate_to_pkcs8(br#"{"kty":"oct","k":"AQ"}"#)),
+            Some(ErrorCode::UNSUPPORTED_ALGORITHM)
+        );
+        // The private key must fit the curve.
+        let key = cose_key(vec![
+            (COSE_LABEL_KTY, CborValue::Integer(COSE_KTY_EC2)),
+            (COSE_LABEL_CRV, CborValue::Integer(1)),
+            (COSE_LABEL_D, CborValue::Bytes(vec![1; 33])),
+        ]);
+        assert_eq!(km_error(translate_to_pkcs8(&key)), invalid);
+    }
+
+    #[test]
+    fn test_der() {
+        assert_eq!(&der(TAG_OCTET_STRING, &[]).unwrap()[..], &[0x04, 0x00]);
+        let long = der(TAG_OCTET_STRING, &[&[0xab; 200], &[0xcd; 100]]).unwrap();
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(long.len(), 304);
+        assert_eq!(&der_unsigned_integer(&[0, 0, 0x80]).unwrap()[..], &[0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(&der_unsigned_integer(&[0]).unwrap()[..], &[0x02, 0x01, 0x00]);
+        assert_eq!(&base64url_decode("").unwrap()[..], b"");
+        assert_eq!(&base64url_decode("Zm9vYg").unwrap()[..], b"foob");
+        assert_eq!(&base64url_decode("-_8").unwrap()[..], &[0xfb, 0xff]);
+        assert!(base64url_decode("Zm9vY").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements per-UID limits on imports of keys and certificates. Without them,
+//! a malicious or buggy app could fill the database with an unbounded number of imported
+//! entries. The limits are configured with system properties:
+//!  * `keystore.import.max_data_size`: The maximum number of bytes per import call.
+//!  * `keystore.import.max_per_minute`: The maximum number of import calls per UID per minute.
+//!  * `keystore.import.exempt_privileged`: If true (the default), imports into
+//!    `Domain::SELINUX` and imports by UIDs outside of the app range are not limited.
+//!
+//! A limit of 0 disables the respective check.
+
+use crate::error::Error;
+use crate::events::EVENTS;
+use crate::ks_err;
+use crate::utils::AID_USER_OFFSET;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_security_maintenance::aidl::android::security::maintenance::KeystoreEventType::KeystoreEventType;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_DATA_SIZE_PROPERTY: &str = "keystore.import.max_data_size";
+const MAX_PER_MINUTE_PROPERTY: &str = "keystore.import.max_per_minute";
+const EXEMPT_PRIVILEGED_PROPERTY: &str = "keystore.import.exempt_privileged";
+
+const DEFAULT_MAX_DATA_SIZE: usize = 128 * 1024;
+const DEFAULT_MAX_PER_MINUTE: usize = 120;
+
+/// The first app id. UIDs with a smaller app id belong to the system.
+const AID_APP_START: u32 = 10000;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Configuration of the import limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportLimitConfig {
+    /// Maximum number of bytes per import call, 0 for no limit.
+    pub max_data_size: usize,
+    /// Maximum number of import calls per UID in a minute, 0 for no limit.
+    pub max_per_minute: usize,
+    /// Whether privileged callers are exempt from the limits.
+    pub exempt_privileged: bool,
+}
+
+impl Default for ImportLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_data_size: DEFAULT_MAX_DATA_SIZE,
+            max_per_minute: DEFAULT_MAX_PER_MINUTE,
+            exempt_privileged: true,
+        }
+    }
+}
+
+impl ImportLimitConfig {
+    /// Reads the configuration from system properties, using the defaults for properties that
+    /// are not set or cannot be parsed.
+    pub fn from_system_properties() -> Self {
+        let default = Self::default();
+        Self {
+            max_data_size: read_usize_property(MAX_DATA_SIZE_PROPERTY, default.max_data_size),
+            max_per_minute: read_usize_property(MAX_PER_MINUTE_PROPERTY, default.max_per_minute),
+            exempt_privileged: rustutils::system_properties::read_bool(
+                EXEMPT_PRIVILEGED_PROPERTY,
+                default.exempt_privileged,
+            )
+            .unwrap_or(default.exempt_privileged),
+        }
+    }
+}
+
+/// Reads a numeric system property, returning `default_value` if it is not set or cannot be
+/// parsed.
+pub fn read_usize_property(name: &str, default_value: usize) -> usize {
+    match rustutils::system_properties::read(name) {
+        Ok(Some(value)) => value.parse::<usize>().unwrap_or_else(|e| {
+            log::error!("Failed to parse {}={:?}: {:?}", name, value, e);
+            default_value
+        }),
+        Ok(None) => default_value,
+        Err(e) => {
+            log::error!("Failed to read {}: {:?}", name, e);
+            default_value
+        }
+    }
+}
+
+/// Returns true if a caller is privileged with respect to the limits of this module, i.e., if
+/// it stores into `Domain::SELINUX` or its UID is outside of the app range.
+pub fn is_privileged(caller_uid: u32, domain: Domain) -> bool {
+    domain == Domain::SELINUX || caller_uid % AID_USER_OFFSET < AID_APP_START
+}
+
+/// Tracks recent imports per UID and enforces the import limits.
+#[derive(Default)]
+pub struct ImportLimiter {
+    recent_imports: Mutex<HashMap<u32, VecDeque<Instant>>>,
+}
+
+impl ImportLimiter {
+    /// Checks an import of `data_size` bytes by `caller_uid` into `domain` against the limits
+    /// configured by system properties. If the import is allowed, it counts against the
+    /// caller's rate limit.
+    pub fn check_import(&self, caller_uid: u32, domain: Domain, data_size: usize) -> Result<()> {
+        let result = self.check_import_with(
+            &ImportLimitConfig::from_system_properties(),
+            Instant::now(),
+            caller_uid,
+            domain,
+            data_size,
+        );
+        if result.is_err() {
+            EVENTS.publish(
+                KeystoreEventType::QUOTA_EXCEEDED,
+                caller_uid as i32,
+                SecurityLevel::KEYSTORE,
+            );
+        }
+        result
+    }
+
+    fn check_import_with(
+        &self,
+        config: &ImportLimitConfig,
+        now: Instant,
+        caller_uid: u32,
+        domain: Domain,
+        data_size: usize,
+    ) -> Result<()> {
+        if config.exempt_privileged && is_privileged(caller_uid, domain) {
+            return Ok(());
+        }
+
+        if config.max_data_size != 0 && data_size > config.max_data_size {
+            return Err(Error::Rc(ResponseCode::TOO_MUCH_DATA)).context(ks_err!(
+                "Import of {} bytes exceeds the limit of {} bytes.",
+                data_size,
+                config.max_data_size
+            ));
+        }
+
+        if config.max_per_minute != 0 {
+            let mut recent_imports = self.recent_imports.lock().unwrap();
+            // Forget about callers who have not imported anything within the window, so that
+            // the map does not grow without bounds.
+            recent_imports.retain(|_, imports| {
+                while imports.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+                    imports.pop_front();
+                }
+                !imports.is_empty()
+            });
+            let imports = recent_imports.entry(caller_uid).or_default();
+            if imports.len() >= config.max_per_minute {
+                return Err(Error::Rc(ResponseCode::BACKEND_BUSY)).context(ks_err!(
+                    "UID {} exceeded the limit of {} imports per minute.",
+                    caller_uid,
+                    config.max_per_minute
+                ));
+            }
+            imports.push_back(now);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_UID: u32 = 10100;
+
+    fn response_code(result: Result<()>) -> Option<ResponseCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Rc(rc)) => Some(*rc),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_data_size_limit() {
+        let limiter = ImportLimiter::default();
+        let config = ImportLimitConfig { max_data_size: 10, ..Default::default() };
+        let now = Instant::now();
+        assert!(limiter.check_import_with(&config, now, APP_UID, Domain::APP, 10).is_ok());
+        assert_eq!(
+            response_code(limiter.check_import_with(&config, now, APP_UID, Domain::APP, 11)),
+            Some(ResponseCode::TOO_MUCH_DATA)
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = ImportLimiter::default();
+        let config = ImportLimitConfig { max_per_minute: 2, ..Default::default() };
+        let now = Instant::now();
+        assert!(limiter.check_import_with(&config, now, APP_UID, Domain::APP, 1).is_ok());
+        assert!(limiter.check_import_with(&config, now, APP_UID, Domain::APP, 1).is_ok());
+        assert_eq!(
+            response_code(limiter.check_import_with(&config, now, APP_UID, Domain::APP, 1)),
+            Some(ResponseCode::BACKEND_BUSY)
+        );
+        // Other UIDs are not affected.
+        assert!(limiter.check_import_with(&config, now, APP_UID + 1, Domain::APP, 1).is_ok());
+        // After the window has passed, the caller may import again.
+        let later = now + RATE_WINDOW;
+        assert!(limiter.check_import_with(&config, later, APP_UID, Domain::APP, 1).is_ok());
+    }
+
+    #[test]
+    fn test_privileged_exemption() {
+        let limiter = ImportLimiter::default();
+        let config =
+            ImportLimitConfig { max_data_size: 10, max_per_minute: 1, ..Default::default() };
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_import_with(&config, now, APP_UID, Domain::SELINUX, 11).is_ok());
+            assert!(limiter.check_import_with(&config, now, 1000, Domain::APP, 11).is_ok());
+        }
+
+        let config = ImportLimitConfig { exempt_privileged: false, ..config };
+        assert_eq!(
+            response_code(limiter.check_import_with(&config, now, 1000, Domain::SELINUX, 11)),
+            Some(ResponseCode::TOO_MUCH_DATA)
+        );
+    }
+}
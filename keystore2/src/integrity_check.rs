@@ -0,0 +1,217 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the idle key blob integrity check. Corrupted key blobs are otherwise
+//! only discovered when the key is used, which is often a user visible moment such as an
+//! unlock or a payment. When the background worker becomes idle, the check samples a few key
+//! entries and asks the KeyMint instance that owns each of them to parse the key blob with
+//! `getKeyCharacteristics`, which neither changes the blob nor uses the key.
+//!
+//! A key blob that KeyMint rejects with `INVALID_KEY_BLOB` is marked with an
+//! `IntegrityCheckFailureDate` in the key metadata and is not checked again. Every check that
+//! reached a verdict is recorded in a KEY_BLOB_INTEGRITY_CHECK_STATS atom. Super-encrypted key
+//! blobs are not checked, because they cannot be passed to KeyMint without the super key.
+//!
+//! Keys are sampled in the order of their random ids, so that successive passes cover all keys
+//! over time. The check is configured with system properties:
+//!  * `keystore.integrity_check.keys_per_pass`: The number of keys checked per pass. 0 disables
+//!    the check.
+//!  * `keystore.integrity_check.interval_secs`: The minimum time between two passes. The first
+//!    pass happens one interval after startup.
+//!
+//! KeyMint cannot parse a key blob that is bound to an application id or application data
+//! without them. Keystore does not know these values, so such keys are reported as failed.
+
+use crate::database::{KeyEntryLoadBits, KeyType, KeystoreDB};
+use crate::error::{map_km_error, Error};
+use crate::globals::{get_keymint_dev_by_uuid, ASYNC_TASK, DB};
+use crate::import_limits::read_usize_property;
+use crate::ks_err;
+use crate::metrics_store::log_key_blob_integrity_check_stats;
+use crate::thermal::THERMAL_THROTTLING;
+use crate::utils::{watchdog as wd, AID_KEYSTORE};
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    ErrorCode::ErrorCode, SecurityLevel::SecurityLevel,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor,
+};
+use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
+
+const KEYS_PER_PASS_PROPERTY: &str = "keystore.integrity_check.keys_per_pass";
+const INTERVAL_SECS_PROPERTY: &str = "keystore.integrity_check.interval_secs";
+
+const DEFAULT_KEYS_PER_PASS: usize = 4;
+const DEFAULT_INTERVAL_SECS: usize = 6 * 60 * 60;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    /// KeyMint parsed the key blob.
+    Valid,
+    /// KeyMint rejected the key blob as invalid.
+    Invalid,
+    /// The check failed for a reason that says nothing about the key blob.
+    Inconclusive,
+}
+
+struct IntegrityCheckState {
+    last_pass: Option<Instant>,
+    cursor: i64,
+}
+
+impl Default for IntegrityCheckState {
+    fn default() -> Self {
+        Self { last_pass: None, cursor: i64::MIN }
+    }
+}
+
+/// Registers the integrity check as an idle callback.
+pub fn register_integrity_check() {
+    ASYNC_TASK.add_idle(|shelf| {
+        let keys_per_pass = read_usize_property(KEYS_PER_PASS_PROPERTY, DEFAULT_KEYS_PER_PASS);
+        if keys_per_pass == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(read_usize_property(
+            INTERVAL_SECS_PROPERTY,
+            DEFAULT_INTERVAL_SECS,
+        ) as u64);
+        let state = shelf.get_mut::<IntegrityCheckState>();
+        let now = Instant::now();
+        match state.last_pass {
+            None => {
+                state.last_pass = Some(now);
+                return;
+            }
+            Some(last) if now.duration_since(last) < interval => return,
+            Some(_) => {}
+        }
+        if THERMAL_THROTTLING.defer_background_work() {
+            return;
+        }
+        state.last_pass = Some(now);
+        let cursor = state.cursor;
+        match DB.with(|db| run_pass(&mut db.borrow_mut(), cursor, keys_per_pass, check_key)) {
+            Ok(cursor) => state.cursor = cursor,
+            Err(e) => log::error!("Key blob integrity check failed: {e:?}"),
+        }
+    });
+}
+
+/// Checks up to `limit` keys with ids greater than `cursor` using `check`. Marks the keys whose
+/// blobs are invalid and returns the cursor for the next pass, which starts over with the
+/// lowest key id once all keys were checked.
+fn run_pass<F>(db: &mut KeystoreDB, cursor: i64, limit: usize, check: F) -> Result<i64>
+where
+    F: Fn(&mut KeystoreDB, i64) -> Result<(Verdict, SecurityLevel)>,
+{
+    let key_ids = db
+        .get_key_ids_for_integrity_check(cursor, limit)
+        .context(ks_err!("Failed to get key ids."))?;
+    for key_id in &key_ids {
+        let (verdict, security_level) = match check(db, *key_id) {
+            Ok(result) => result,
+            Err(e) => {
+                log::warn!("Failed to check key blob of key {key_id}: {e:?}");
+                continue;
+            }
+        };
+        match verdict {
+            Verdict::Valid => log_key_blob_integrity_check_stats(true, security_level),
+            Verdict::Invalid => {
+                log::error!("KeyMint {security_level:?} rejected the key blob of key {key_id}.");
+                log_key_blob_integrity_check_stats(false, security_level);
+                db.mark_key_integrity_check_failed(*key_id)
+                    .context(ks_err!("Failed to mark key {key_id}."))?;
+            }
+            Verdict::Inconclusive => {}
+        }
+    }
+    Ok(match key_ids.last() {
+        Some(last) if key_ids.len() == limit => *last,
+        _ => i64::MIN,
+    })
+}
+
+/// Asks the KeyMint instance that owns the key with the given id to parse its key blob.
+fn check_key(db: &mut KeystoreDB, key_id: i64) -> Result<(Verdict, SecurityLevel)> {
+    let key = KeyDescriptor { domain: Domain::KEY_ID, nspace: key_id, alias: None, blob: None };
+    let (_key_id_guard, mut key_entry) = db
+        .load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::KM, AID_KEYSTORE, |_, _| Ok(()))
+        .context(ks_err!("Failed to load key entry."))?;
+    let (blob, blob_metadata) = key_entry
+        .take_key_blob_info()
+        .ok_or_else(Error::sys)
+        .context(ks_err!("Key entry has no key blob."))?;
+    let km_uuid = blob_metadata
+        .km_uuid()
+        .copied()
+        .ok_or_else(Error::sys)
+        .context(ks_err!("Key blob has no KeyMint uuid."))?;
+    let (km_dev, hw_info) =
+        get_keymint_dev_by_uuid(&km_uuid).context(ks_err!("Failed to get KeyMint device."))?;
+    let result = map_km_error({
+        let _wp = wd::watch("integrity_check: calling IKeyMintDevice::getKeyCharacteristics");
+        km_dev.getKeyCharacteristics(&blob, &[], &[])
+    });
+    let verdict = match result {
+        // A blob that needs an upgrade was parsed; it is upgraded on next use.
+        Ok(_) | Err(Error::Km(ErrorCode::KEY_REQUIRES_UPGRADE)) => Verdict::Valid,
+        Err(Error::Km(ErrorCode::INVALID_KEY_BLOB)) => Verdict::Invalid,
+        Err(e) => {
+            log::warn!("Integrity check of key {key_id} was inconclusive: {e:?}");
+            Verdict::Inconclusive
+        }
+    };
+    Ok((verdict, hw_info.securityLevel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{make_test_key_entry, new_test_db};
+    use crate::database::{BlobMetaData, BlobMetaEntry, SubComponentType, KEYSTORE_UUID};
+
+    fn make_plain_key(db: &mut KeystoreDB, alias: &str) -> Result<i64> {
+        let key_id = make_test_key_entry(db, Domain::APP, 1, alias, None)?;
+        let mut blob_metadata = BlobMetaData::new();
+        blob_metadata.add(BlobMetaEntry::KmUuid(KEYSTORE_UUID));
+        db.set_blob(&key_id, SubComponentType::KEY_BLOB, Some(&b"blob"[..]), Some(&blob_metadata))?;
+        Ok(key_id.id())
+    }
+
+    #[test]
+    fn test_run_pass() -> Result<()> {
+        let mut db = new_test_db()?;
+        let mut key_ids = vec![];
+        for alias in ["key1", "key2", "key3"] {
+            key_ids.push(make_plain_key(&mut db, alias)?);
+        }
+        key_ids.sort();
+        let invalid = key_ids[1];
+        let check = |_: &mut KeystoreDB, key_id: i64| {
+            let verdict = if key_id == invalid { Verdict::Invalid } else { Verdict::Valid };
+            Ok((verdict, SecurityLevel::TRUSTED_ENVIRONMENT))
+        };
+
+        let cursor = run_pass(&mut db, i64::MIN, 2, check)?;
+        assert_eq!(cursor, key_ids[1]);
+        // The invalid key is not checked again.
+        assert_eq!(db.get_key_ids_for_integrity_check(i64::MIN, 10)?, vec![key_ids[0], key_ids[2]]);
+        // The pass that reaches the end of the keys starts over.
+        assert_eq!(run_pass(&mut db, cursor, 2, check)?, i64::MIN);
+        Ok(())
+    }
+}
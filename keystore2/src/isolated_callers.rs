@@ -0,0 +1,217 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the policy for callers that run in isolated processes, i.e., with an
+//! isolated UID or an app zygote isolated UID. Isolated UIDs are recycled as soon as their
+//! process dies, so keys that an isolated process stores under its UID would otherwise be
+//! visible to the next, unrelated process that gets the UID.
+//!
+//! The read-only property `ro.keystore.isolated_process_policy` selects the policy:
+//!  * `reject` (default): All calls of isolated processes to IKeystoreService fail with
+//!    `ResponseCode::PERMISSION_DENIED`, before any other check, so that they fail in the same
+//!    way regardless of the key or the domain.
+//!  * `ephemeral`: An isolated process can use the `Domain::APP` namespace of its UID, but the
+//!    namespace dies with the process: the keys of the UID are deleted when a different process
+//!    than the last one calls with the UID, and when a process with an isolated UID is found to
+//!    be dead. Keystore tracks the processes in memory, so the keys are also deleted after
+//!    keystore restarts. Isolated processes cannot grant their keys to others.
+//!
+//! With either policy the SELinux policy still applies, e.g., `isolated_app` cannot find the
+//! keystore service unless the device's policy allows it.
+
+use crate::error::Error;
+use crate::globals::{DB, KEY_ENTRY_CACHE};
+use crate::ks_err;
+use crate::utils::AID_USER_OFFSET;
+use android_system_keystore2::aidl::android::system::keystore2::Domain::Domain;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+const POLICY_PROPERTY: &str = "ro.keystore.isolated_process_policy";
+
+/// First app id of the app zygote isolated UID range, see AID_APP_ZYGOTE_START in
+/// system/core/libcutils/include/private/android_filesystem_config.h.
+const AID_APP_ZYGOTE_START: u32 = 90000;
+
+/// Last app id of the isolated UID range, see AID_ISOLATED_END in
+/// system/core/libcutils/include/private/android_filesystem_config.h.
+const AID_ISOLATED_END: u32 = 99999;
+
+/// The isolated callers of this keystore instance.
+pub static ISOLATED_CALLERS: LazyLock<IsolatedCallers> = LazyLock::new(|| {
+    let policy = match rustutils::system_properties::read(POLICY_PROPERTY) {
+        Ok(value) => parse_policy(value.as_deref()),
+        Err(e) => {
+            log::error!("Failed to read {POLICY_PROPERTY}: {e:?}");
+            IsolatedProcessPolicy::Reject
+        }
+    };
+    IsolatedCallers::new(policy)
+});
+
+/// How keystore treats callers in isolated processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolatedProcessPolicy {
+    /// Isolated processes cannot use keystore.
+    Reject,
+    /// Isolated processes have a namespace that dies with the process.
+    Ephemeral,
+}
+
+fn parse_policy(value: Option<&str>) -> IsolatedProcessPolicy {
+    match value {
+        None | Some("") | Some("reject") => IsolatedProcessPolicy::Reject,
+        Some("ephemeral") => IsolatedProcessPolicy::Ephemeral,
+        Some(value) => {
+            log::error!("Invalid {POLICY_PROPERTY} {value:?}, rejecting isolated processes.");
+            IsolatedProcessPolicy::Reject
+        }
+    }
+}
+
+/// Returns true if `uid` belongs to an isolated process of any user.
+pub fn is_isolated_uid(uid: u32) -> bool {
+    (AID_APP_ZYGOTE_START..=AID_ISOLATED_END).contains(&(uid % AID_USER_OFFSET))
+}
+
+/// Returns false if the process `pid` no longer exists.
+fn is_alive(pid: i32) -> bool {
+    // SAFETY: Signal 0 only checks whether the process exists, it is not delivered.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+/// Applies the isolated process policy and tracks the processes of isolated UIDs.
+pub struct IsolatedCallers {
+    policy: IsolatedProcessPolicy,
+    /// The last process that called with each isolated UID.
+    pids: Mutex<HashMap<u32, i32>>,
+}
+
+impl IsolatedCallers {
+    fn new(policy: IsolatedProcessPolicy) -> Self {
+        Self { policy, pids: Default::default() }
+    }
+
+    /// Returns the policy for isolated processes.
+    pub fn policy(&self) -> IsolatedProcessPolicy {
+        self.policy
+    }
+
+    /// Checks a call from the process `caller_pid` with the UID `caller_uid`. Fails with
+    /// `ResponseCode::PERMISSION_DENIED` if the caller is an isolated process and isolated
+    /// processes are rejected. With the ephemeral policy, deletes the keys of the namespaces
+    /// whose process died before the call is served.
+    pub fn check_caller(&self, caller_uid: u32, caller_pid: i32) -> Result<()> {
+        if !is_isolated_uid(caller_uid) {
+            return Ok(());
+        }
+        match self.policy {
+            IsolatedProcessPolicy::Reject => Err(Error::perm())
+                .context(ks_err!("Isolated process with UID {} cannot use keystore.", caller_uid)),
+            IsolatedProcessPolicy::Ephemeral => {
+                for uid in self.register(caller_uid, caller_pid, is_alive) {
+                    log::info!("Deleting the keys of isolated UID {uid}, its process is gone.");
+                    let nspace = uid as i64;
+                    let result = DB
+                        .with(|db| db.borrow_mut().unbind_keys_for_namespace(Domain::APP, nspace));
+                    KEY_ENTRY_CACHE.invalidate_namespace(Domain::APP, nspace);
+                    result.context(ks_err!("Trying to delete the keys of UID {}.", uid))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Fails with `ResponseCode::PERMISSION_DENIED` if `caller_uid` is an isolated process,
+    /// whose keys must not outlive it in other namespaces.
+    pub fn check_grant(&self, caller_uid: u32) -> Result<()> {
+        if is_isolated_uid(caller_uid) {
+            return Err(Error::perm())
+                .context(ks_err!("Isolated process with UID {} cannot grant keys.", caller_uid));
+        }
+        Ok(())
+    }
+
+    /// Records that `pid` called with the isolated UID `uid`. Returns the UIDs whose namespace
+    /// must be cleared: `uid` if its last process was a different one, or if it called for the
+    /// first time since keystore started, and the UIDs whose last process died. Dead processes
+    /// are only looked for when a new process shows up, which is when UIDs get recycled.
+    fn register(&self, uid: u32, pid: i32, is_alive: impl Fn(i32) -> bool) -> Vec<u32> {
+        let mut pids = self.pids.lock().unwrap();
+        if pids.insert(uid, pid) == Some(pid) {
+            return Vec::new();
+        }
+        let mut dead: Vec<u32> = pids
+            .iter()
+            .filter(|(other_uid, pid)| **other_uid != uid && !is_alive(**pid))
+            .map(|(other_uid, _)| *other_uid)
+            .collect();
+        for other_uid in &dead {
+            pids.remove(other_uid);
+        }
+        dead.push(uid);
+        dead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(parse_policy(None), IsolatedProcessPolicy::Reject);
+        assert_eq!(parse_policy(Some("reject")), IsolatedProcessPolicy::Reject);
+        assert_eq!(parse_policy(Some("ephemeral")), IsolatedProcessPolicy::Ephemeral);
+        assert_eq!(parse_policy(Some("allow")), IsolatedProcessPolicy::Reject);
+    }
+
+    #[test]
+    fn test_is_isolated_uid() {
+        assert!(!is_isolated_uid(10001));
+        assert!(!is_isolated_uid(89999));
+        assert!(is_isolated_uid(90000));
+        assert!(is_isolated_uid(99123));
+        assert!(is_isolated_uid(10 * AID_USER_OFFSET + 99999));
+        assert!(!is_isolated_uid(10 * AID_USER_OFFSET + 10001));
+    }
+
+    #[test]
+    fn test_register() {
+        let callers = IsolatedCallers::new(IsolatedProcessPolicy::Ephemeral);
+        // The first call of a UID clears keys that a previous keystore instance left behind.
+        assert_eq!(callers.register(99000, 100, |_| true), vec![99000]);
+        assert!(callers.register(99000, 100, |_| true).is_empty());
+        assert_eq!(callers.register(99001, 200, |_| true), vec![99001]);
+        // A new process with a recycled UID gets an empty namespace.
+        assert_eq!(callers.register(99000, 300, |_| true), vec![99000]);
+        // A new process sweeps the namespaces of dead processes.
+        let mut cleared = callers.register(99002, 400, |pid| pid != 200);
+        cleared.sort();
+        assert_eq!(cleared, vec![99001, 99002]);
+        assert_eq!(callers.register(99001, 500, |_| true), vec![99001]);
+    }
+
+    #[test]
+    fn test_check_caller_reject() {
+        let callers = IsolatedCallers::new(IsolatedProcessPolicy::Reject);
+        callers.check_caller(10001, 100).unwrap();
+        let err = callers.check_caller(99000, 100).unwrap_err();
+        assert_eq!(err.root_cause().downcast_ref::<Error>(), Some(&Error::perm()));
+        assert!(callers.check_grant(99000).is_err());
+        callers.check_grant(10001).unwrap();
+    }
+}
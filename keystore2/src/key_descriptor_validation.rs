@@ -0,0 +1,238 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the validation of the `KeyDescriptor`s that clients pass to keystore2.
+//! It keeps the rules in one place, so that every entry point rejects the same malformed
+//! descriptors with the same error, before they reach the permission checks or the database:
+//!  * The domain must be one of the values defined by `Domain`.
+//!  * `Domain::APP` and `Domain::SELINUX` keys must have an alias, and so must new keys in any
+//!    domain but `Domain::BLOB`.
+//!  * Aliases must not be longer than `MAX_ALIAS_LEN` bytes or contain NUL characters.
+//!  * `Domain::SELINUX` namespaces must not be negative.
+//!  * When looking up a key, `Domain::BLOB` requires a non-empty blob, and the other domains must
+//!    not carry a blob.
+//!
+//! Whether `Domain::GRANT` and `Domain::KEY_ID` may be used is up to each entry point.
+
+use crate::error::Error;
+use crate::ks_err;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::ErrorCode::ErrorCode;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+
+/// The maximum length of an alias in bytes.
+pub const MAX_ALIAS_LEN: usize = 1024;
+
+/// Describes how the request that carries a key descriptor uses it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorUse {
+    /// The descriptor names the location of a new key, as in `generateKey`, `importKey` and
+    /// `importWrappedKey`. The blob field is not checked because `importWrappedKey` uses it for
+    /// the wrapped key material.
+    Create,
+    /// The descriptor names an existing key.
+    Lookup,
+}
+
+/// Checks `key` against the rules described in the module documentation.
+///
+/// A missing alias on creation fails with `ErrorCode::INVALID_ARGUMENT`, as it always has for
+/// `generateKey` and `importKey`; all other violations fail with
+/// `ResponseCode::INVALID_ARGUMENT`.
+pub fn check_key_descriptor(key: &KeyDescriptor, usage: DescriptorUse) -> Result<()> {
+    let invalid = || Error::Rc(ResponseCode::INVALID_ARGUMENT);
+
+    match key.domain {
+        Domain::APP | Domain::SELINUX | Domain::GRANT | Domain::KEY_ID | Domain::BLOB => {}
+        domain => {
+            return Err(invalid()).context(ks_err!("Unknown domain {:?}.", domain));
+        }
+    }
+
+    let alias_required = match usage {
+        DescriptorUse::Create => key.domain != Domain::BLOB,
+        DescriptorUse::Lookup => matches!(key.domain, Domain::APP | Domain::SELINUX),
+    };
+    if alias_required && key.alias.is_none() {
+        return Err(match usage {
+            DescriptorUse::Create => Error::Km(ErrorCode::INVALID_ARGUMENT),
+            DescriptorUse::Lookup => invalid(),
+        })
+        .context(ks_err!("Alias must be specified for {:?}.", key.domain));
+    }
+    if let Some(alias) = &key.alias {
+        check_alias(alias).context(ks_err!())?;
+    }
+
+    if key.domain == Domain::SELINUX && key.nspace < 0 {
+        return Err(invalid()).context(ks_err!("Negative SELinux namespace {}.", key.nspace));
+    }
+
+    if usage == DescriptorUse::Lookup {
+        match (key.domain, &key.blob) {
+            (Domain::BLOB, Some(blob)) if !blob.is_empty() => {}
+            (Domain::BLOB, _) => {
+                return Err(invalid()).context(ks_err!("Domain::BLOB requires a key blob."));
+            }
+            (domain, Some(_)) => {
+                return Err(invalid()).context(ks_err!("Unexpected key blob for {:?}.", domain));
+            }
+            (_, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `alias` is within the length limit and free of NUL characters, which would be
+/// truncated by C string based consumers such as the legacy blob file names.
+pub fn check_alias(alias: &str) -> Result<()> {
+    if alias.len() > MAX_ALIAS_LEN {
+        return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+            "Alias is {} bytes long, the limit is {}.",
+            alias.len(),
+            MAX_ALIAS_LEN
+        ));
+    }
+    if alias.contains('\0') {
+        return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Alias contains a NUL character."));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: Result<()>) -> Option<Error> {
+        match result.err()?.root_cause().downcast_ref::<Error>() {
+            Some(Error::Rc(rc)) => Some(Error::Rc(*rc)),
+            Some(Error::Km(km)) => Some(Error::Km(*km)),
+            _ => None,
+        }
+    }
+
+    const INVALID_RC: Option<Error> = Some(Error::Rc(ResponseCode::INVALID_ARGUMENT));
+    const INVALID_KM: Option<Error> = Some(Error::Km(ErrorCode::INVALID_ARGUMENT));
+
+    fn descriptor(
+        domain: Domain,
+        nspace: i64,
+        alias: Option<&str>,
+        blob: Option<&[u8]>,
+    ) -> KeyDescriptor {
+        KeyDescriptor {
+            domain,
+            nspace,
+            alias: alias.map(str::to_string),
+            blob: blob.map(<[u8]>::to_vec),
+        }
+    }
+
+    #[test]
+    fn test_valid_descriptors() {
+        let valid = [
+            (descriptor(Domain::APP, -1, Some("key"), None), DescriptorUse::Create),
+            (descriptor(Domain::APP, 10001, Some("key"), None), DescriptorUse::Lookup),
+            (descriptor(Domain::SELINUX, 100, Some("key"), None), DescriptorUse::Create),
+            (descriptor(Domain::SELINUX, 0, Some("key"), None), DescriptorUse::Lookup),
+            (descriptor(Domain::GRANT, -42, None, None), DescriptorUse::Lookup),
+            (descriptor(Domain::KEY_ID, 42, None, None), DescriptorUse::Lookup),
+            (descriptor(Domain::BLOB, 1, None, None), DescriptorUse::Create),
+            (descriptor(Domain::BLOB, 1, Some("ignored"), None), DescriptorUse::Create),
+            (descriptor(Domain::BLOB, 1, None, Some(b"blob")), DescriptorUse::Lookup),
+            // importWrappedKey passes the wrapped key material as blob.
+            (descriptor(Domain::APP, -1, Some("key"), Some(b"wrapped")), DescriptorUse::Create),
+            (descriptor(Domain::APP, -1, Some(""), None), DescriptorUse::Create),
+            (
+                descriptor(Domain::APP, -1, Some(&"a".repeat(MAX_ALIAS_LEN)), None),
+                DescriptorUse::Create,
+            ),
+        ];
+        for (key, usage) in valid {
+            assert!(check_key_descriptor(&key, usage).is_ok(), "{key:?} {usage:?}");
+        }
+    }
+
+    #[test]
+    fn test_missing_alias() {
+        for domain in [Domain::APP, Domain::SELINUX] {
+            let key = descriptor(domain, 0, None, None);
+            assert_eq!(error(check_key_descriptor(&key, DescriptorUse::Create)), INVALID_KM);
+            assert_eq!(error(check_key_descriptor(&key, DescriptorUse::Lookup)), INVALID_RC);
+        }
+        for domain in [Domain::GRANT, Domain::KEY_ID] {
+            let key = descriptor(domain, 0, None, None);
+            assert_eq!(error(check_key_descriptor(&key, DescriptorUse::Create)), INVALID_KM);
+            assert!(check_key_descriptor(&key, DescriptorUse::Lookup).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_blob_misuse() {
+        let lookup = |key: KeyDescriptor| error(check_key_descriptor(&key, DescriptorUse::Lookup));
+        assert_eq!(lookup(descriptor(Domain::BLOB, 1, None, None)), INVALID_RC);
+        assert_eq!(lookup(descriptor(Domain::BLOB, 1, None, Some(b""))), INVALID_RC);
+        assert_eq!(lookup(descriptor(Domain::APP, -1, Some("key"), Some(b"blob"))), INVALID_RC);
+        assert_eq!(lookup(descriptor(Domain::SELINUX, 1, Some("key"), Some(b"blob"))), INVALID_RC);
+        assert_eq!(lookup(descriptor(Domain::GRANT, 1, None, Some(b"blob"))), INVALID_RC);
+        assert_eq!(lookup(descriptor(Domain::KEY_ID, 1, None, Some(b"blob"))), INVALID_RC);
+    }
+
+    #[test]
+    fn test_alias_limits() {
+        assert!(check_alias(&"a".repeat(MAX_ALIAS_LEN)).is_ok());
+        assert_eq!(error(check_alias(&"a".repeat(MAX_ALIAS_LEN + 1))), INVALID_RC);
+        // The limit is in bytes, not characters.
+        assert_eq!(error(check_alias(&"\u{e9}".repeat(MAX_ALIAS_LEN / 2 + 1))), INVALID_RC);
+        assert_eq!(error(check_alias("key\0")), INVALID_RC);
+        assert!(check_alias("key with spaces/and:punctuation_\u{1f511}").is_ok());
+    }
+
+    /// Hand-written extreme values of the fields that the `ValidateKeyDescriptor` command of
+    /// keystore2_unsafe_fuzzer varies, with their expected outcome. None of them is a fuzzer
+    /// finding. Inputs that the fuzzer finds to misbehave should be added here.
+    #[test]
+    fn test_extreme_field_values() {
+        let huge_alias = "x".repeat(MAX_ALIAS_LEN * 4);
+        let corpus: &[(i32, i64, Option<&str>, Option<&[u8]>, DescriptorUse, Option<Error>)] = &[
+            (-1, 0, Some("key"), None, DescriptorUse::Lookup, INVALID_RC),
+            (5, 0, Some("key"), None, DescriptorUse::Create, INVALID_RC),
+            (i32::MAX, 0, None, None, DescriptorUse::Lookup, INVALID_RC),
+            (i32::MIN, i64::MIN, None, Some(b"\0"), DescriptorUse::Create, INVALID_RC),
+            (Domain::SELINUX.0, i64::MIN, Some("key"), None, DescriptorUse::Create, INVALID_RC),
+            (Domain::SELINUX.0, i64::MAX, Some("key"), None, DescriptorUse::Lookup, None),
+            (Domain::APP.0, i64::MIN, Some("key"), None, DescriptorUse::Lookup, None),
+            (Domain::APP.0, 0, Some("\0"), None, DescriptorUse::Create, INVALID_RC),
+            (Domain::APP.0, 0, Some(&huge_alias), None, DescriptorUse::Lookup, INVALID_RC),
+            (Domain::GRANT.0, i64::MIN, Some("\0"), None, DescriptorUse::Lookup, INVALID_RC),
+            (
+                Domain::KEY_ID.0,
+                i64::MAX,
+                Some(&huge_alias),
+                None,
+                DescriptorUse::Lookup,
+                INVALID_RC,
+            ),
+            (Domain::BLOB.0, i64::MIN, None, Some(b"\0"), DescriptorUse::Lookup, None),
+            (Domain::BLOB.0, 0, Some("\0"), Some(b"blob"), DescriptorUse::Lookup, INVALID_RC),
+        ];
+        for (domain, nspace, alias, blob, usage, expected) in corpus {
+            let key = descriptor(Domain(*domain), *nspace, *alias, *blob);
+            assert_eq!(&error(check_key_descriptor(&key, *usage)), expected, "{key:?} {usage:?}");
+        }
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module produces redacted diagnostic bundles for single keys, so that bug reports can
+//! include the complete state of a key as seen by Keystore. A bundle is a CBOR map with text
+//! keys holding the identity of the key, its authorizations, metadata and provenance, its
+//! public certificate and certificate chain, and the retained key use audit records of the key.
+//!
+//! A bundle never contains key material: the key blob is not loaded, and the authorizations
+//! `APPLICATION_ID` and `APPLICATION_DATA` are removed should they ever be recorded.
+//!
+//! This module also compares the authorizations that keystore stored for a key with those that
+//! KeyMint currently reports for its key blob, to diagnose vendor HAL upgrades that change the
+//! enforcement of existing keys.
+
+use crate::audit_log::{KeyUseRecord, KEY_USE_AUDIT};
+use crate::database::{KeyEntry, KeyEntryLoadBits, KeyLifecycleState, KeyMetaData, KeyType, Uuid};
+use crate::error::{map_km_error, Error};
+use crate::globals::{get_keymint_dev_by_uuid, DB, SUPER_KEY};
+use crate::key_parameter::{KeyParameter, Tag};
+use crate::ks_err;
+use crate::utils::{key_characteristics_to_internal, watchdog as wd};
+use android_security_maintenance::aidl::android::security::maintenance::KeyCharacteristicsDiff::KeyCharacteristicsDiff;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// Version of the bundle format. Must be incremented when existing fields change meaning.
+const BUNDLE_VERSION: i128 = 1;
+
+/// The state of a key that goes into a diagnostic bundle.
+#[derive(Debug, Default)]
+struct KeyState {
+    key: KeyDescriptor,
+    key_id: i64,
+    km_uuid: Uuid,
+    lifecycle_state: KeyLifecycleState,
+    parameters: Vec<KeyParameter>,
+    metadata: KeyMetaData,
+    cert: Option<Vec<u8>>,
+    cert_chain: Option<Vec<u8>>,
+    audit_records: Vec<KeyUseRecord>,
+}
+
+/// Loads the key entry of the given key and returns its key id, the entry and the descriptor by
+/// alias, if the key has one. For `Domain::APP`, `key.nspace` is the UID of the owner of the key
+/// rather than that of the caller. The caller must have been authorized to inspect all keys.
+fn load_key_entry(
+    key: &KeyDescriptor,
+    load_bits: KeyEntryLoadBits,
+) -> Result<(i64, KeyEntry, KeyDescriptor)> {
+    let owner_uid = match key.domain {
+        Domain::APP => u32::try_from(key.nspace)
+            .map_err(|_| Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Invalid UID {}.", key.nspace))?,
+        Domain::SELINUX | Domain::KEY_ID => 0,
+        _ => {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Unsupported domain {:?}.", key.domain));
+        }
+    };
+    DB.with::<_, Result<(i64, KeyEntry, KeyDescriptor)>>(|db| {
+        let mut db = db.borrow_mut();
+        let (key_id_guard, key_entry) = db.load_key_entry(
+            key,
+            KeyType::Client,
+            load_bits,
+            owner_uid,
+            // Access to all keys was granted with the permission to get diagnostics.
+            |_k, _av| Ok(()),
+        )?;
+        let key_id = key_id_guard.id();
+        let key = db.load_key_descriptor(key_id)?.unwrap_or_else(|| key.clone());
+        Ok((key_id, key_entry, key))
+    })
+    .context(ks_err!("Failed to load key entry."))
+}
+
+impl KeyState {
+    /// Loads the state of the given key, see `load_key_entry`.
+    fn load(key: &KeyDescriptor) -> Result<Self> {
+        let (key_id, mut key_entry, key) =
+            load_key_entry(key, KeyEntryLoadBits::PUBLIC).context(ks_err!())?;
+        Ok(Self {
+            key,
+            key_id,
+            km_uuid: *key_entry.km_uuid(),
+            lifecycle_state: key_entry.lifecycle_state(),
+            metadata: key_entry.take_metadata(),
+            cert: key_entry.take_cert(),
+            cert_chain: key_entry.take_cert_chain(),
+            parameters: key_entry.into_key_parameters(),
+            audit_records: KEY_USE_AUDIT
+                .records()
+                .into_iter()
+                .filter(|r| r.key_id == key_id)
+                .collect(),
+        })
+    }
+
+    /// Encodes the state as a CBOR diagnostic bundle.
+    fn to_bundle(&self) -> Result<Vec<u8>> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let optional_bytes = |b: &Option<Vec<u8>>| b.clone().map_or(Value::Null, Value::Bytes);
+        let optional_int = |i: Option<i64>| i.map_or(Value::Null, |i| Value::Integer(i.into()));
+
+        let parameters: Vec<&KeyParameter> = self
+            .parameters
+            .iter()
+            .filter(|p| !matches!(p.get_tag(), Tag::APPLICATION_ID | Tag::APPLICATION_DATA))
+            .collect();
+
+        let provenance = self.metadata.provenance();
+        let metadata = BTreeMap::from([
+            (
+                text("creation_date_ms"),
+                optional_int(self.metadata.creation_date().map(|d| d.to_millis_epoch())),
+            ),
+            (
+                text("max_validity_expiration_ms"),
+                optional_int(
+                    self.metadata.max_validity_expiration_date().map(|d| d.to_millis_epoch()),
+                ),
+            ),
+            (
+                text("integrity_check_failure_ms"),
+                optional_int(
+                    self.metadata.integrity_check_failure_date().map(|d| d.to_millis_epoch()),
+                ),
+            ),
+            (
+                text("build_fingerprint"),
+                provenance.build_fingerprint.map_or(Value::Null, Value::Text),
+            ),
+            (text("os_patch_level"), optional_int(provenance.os_patch_level.map(i64::from))),
+            (
+                text("vendor_patch_level"),
+                optional_int(provenance.vendor_patch_level.map(i64::from)),
+            ),
+            (text("boot_patch_level"), optional_int(provenance.boot_patch_level.map(i64::from))),
+            (
+                text("attestation_source"),
+                provenance.attestation_source.map_or(Value::Null, |s| text(&format!("{s:?}"))),
+            ),
+            (text("km_version"), optional_int(provenance.km_version.map(i64::from))),
+        ]);
+
+        let audit_records = self
+            .audit_records
+            .iter()
+            .map(|r| {
+                Value::Map(BTreeMap::from([
+                    (text("time_ms"), Value::Integer(r.time_ms.into())),
+                    (text("caller_uid"), Value::Integer(r.caller_uid.into())),
+                    (text("purpose"), Value::Integer(r.purpose.0.into())),
+                    (text("outcome"), text(&format!("{:?}", r.outcome))),
+                ]))
+            })
+            .collect();
+
+        let bundle = BTreeMap::from([
+            (text("version"), Value::Integer(BUNDLE_VERSION)),
+            (text("key_id"), Value::Integer(self.key_id.into())),
+            (text("domain"), Value::Integer(self.key.domain.0.into())),
+            (text("namespace"), Value::Integer(self.key.nspace.into())),
+            (text("alias"), self.key.alias.as_deref().map_or(Value::Null, text)),
+            (text("km_uuid"), Value::Bytes(self.km_uuid.to_vec())),
+            (text("lifecycle_state"), text(&format!("{:?}", self.lifecycle_state))),
+            (
+                text("authorizations"),
+                serde_cbor::value::to_value(&parameters)
+                    .context(ks_err!("Failed to encode authorizations."))?,
+            ),
+            (text("metadata"), Value::Map(metadata)),
+            (text("certificate"), optional_bytes(&self.cert)),
+            (text("certificate_chain"), optional_bytes(&self.cert_chain)),
+            (text("audit_records"), Value::Array(audit_records)),
+        ]);
+        serde_cbor::to_vec(&Value::Map(bundle)).context(ks_err!("Failed to encode bundle."))
+    }
+}
+
+/// Returns the redacted diagnostic bundle of the given key. For `Domain::APP`, `key.nspace` is
+/// the UID of the owner of the key. The caller must have been authorized to inspect all keys.
+pub fn get_key_diagnostic_bundle(key: &KeyDescriptor) -> Result<Vec<u8>> {
+    KeyState::load(key).context(ks_err!())?.to_bundle()
+}
+
+/// Returns the authorizations that are in `stored` but not in `reported`, and those that are in
+/// `reported` but not in `stored`. Each authorization is matched at most once, so that repeated
+/// authorizations, e.g., purposes, are compared by their number. Authorizations that keystore
+/// adds itself are not compared, because KeyMint does not know them.
+fn diff_parameters(
+    mut stored: Vec<KeyParameter>,
+    mut reported: Vec<KeyParameter>,
+) -> (Vec<KeyParameter>, Vec<KeyParameter>) {
+    stored.retain(|p| p.get_tag() != Tag::USER_ID);
+    stored.sort();
+    reported.sort();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut stored, mut reported) =
+        (stored.into_iter().peekable(), reported.into_iter().peekable());
+    loop {
+        match (stored.peek(), reported.peek()) {
+            (None, None) => break,
+            (Some(s), Some(r)) if s == r => {
+                stored.next();
+                reported.next();
+            }
+            (Some(s), Some(r)) if s < r => removed.extend(stored.next()),
+            (Some(_), Some(_)) | (None, Some(_)) => added.extend(reported.next()),
+            (Some(_), None) => removed.extend(stored.next()),
+        }
+    }
+    (removed, added)
+}
+
+/// Compares the authorizations that keystore stored for the given key with those that KeyMint
+/// reports for its key blob. For `Domain::APP`, `key.nspace` is the UID of the owner of the key.
+/// The caller must have been authorized to inspect all keys.
+pub fn get_key_characteristics_diff(key: &KeyDescriptor) -> Result<KeyCharacteristicsDiff> {
+    let (key_id, mut key_entry, _) =
+        load_key_entry(key, KeyEntryLoadBits::KM).context(ks_err!())?;
+    let (blob, blob_metadata) = key_entry
+        .take_key_blob_info()
+        .ok_or(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+        .context(ks_err!("Key {} has no key blob.", key_id))?;
+    let km_uuid = blob_metadata
+        .km_uuid()
+        .copied()
+        .ok_or_else(Error::sys)
+        .context(ks_err!("Key blob has no KeyMint uuid."))?;
+    let (km_dev, _) =
+        get_keymint_dev_by_uuid(&km_uuid).context(ks_err!("Failed to get KeyMint device."))?;
+    let key_blob = SUPER_KEY
+        .read()
+        .unwrap()
+        .unwrap_key_if_required(&blob_metadata, &blob)
+        .context(ks_err!("Failed to unwrap key blob."))?;
+    let reported = map_km_error({
+        let _wp = wd::watch("key_diagnostics: calling IKeyMintDevice::getKeyCharacteristics");
+        km_dev.getKeyCharacteristics(&key_blob, &[], &[])
+    })
+    .context(ks_err!("KeyMint failed to report the characteristics of key {}.", key_id))?;
+
+    let (removed, added) =
+        diff_parameters(key_entry.into_key_parameters(), key_characteristics_to_internal(reported));
+    if !removed.is_empty() || !added.is_empty() {
+        log::warn!("Key {key_id}: KeyMint no longer reports {removed:?}, but reports {added:?}.");
+    }
+    Ok(KeyCharacteristicsDiff {
+        removed: removed.into_iter().map(|p| p.into_authorization()).collect(),
+        added: added.into_iter().map(|p| p.into_authorization()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{DateTime, KeyMetaEntry};
+    use crate::key_parameter::{KeyParameterValue, SecurityLevel};
+    use crate::operation::Outcome;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::KeyPurpose::KeyPurpose;
+
+    fn field<'a>(map: &'a Value, name: &str) -> &'a Value {
+        match map {
+            Value::Map(m) => m.get(&Value::Text(name.to_string())).unwrap(),
+            v => panic!("Not a map: {v:?}"),
+        }
+    }
+
+    #[test]
+    fn test_diff_parameters() {
+        let tee = |v| KeyParameter::new(v, SecurityLevel::TRUSTED_ENVIRONMENT);
+        let sign = KeyParameterValue::KeyPurpose(KeyPurpose::SIGN);
+        let verify = KeyParameterValue::KeyPurpose(KeyPurpose::VERIFY);
+        let stored = vec![
+            tee(KeyParameterValue::KeySize(256)),
+            tee(sign.clone()),
+            tee(verify.clone()),
+            tee(KeyParameterValue::UserSecureID(42)),
+            KeyParameter::new(KeyParameterValue::UserID(10), SecurityLevel::SOFTWARE),
+        ];
+
+        // The order of the authorizations does not matter.
+        let reported = vec![
+            tee(KeyParameterValue::UserSecureID(42)),
+            tee(verify.clone()),
+            tee(sign.clone()),
+            tee(KeyParameterValue::KeySize(256)),
+        ];
+        assert_eq!(diff_parameters(stored.clone(), reported), (vec![], vec![]));
+
+        // The user secure id is now enforced by keystore, and the key can be used to sign twice.
+        let reported = vec![
+            tee(KeyParameterValue::KeySize(256)),
+            tee(sign.clone()),
+            tee(sign.clone()),
+            tee(verify),
+            KeyParameter::new(KeyParameterValue::UserSecureID(42), SecurityLevel::KEYSTORE),
+        ];
+        assert_eq!(
+            diff_parameters(stored, reported),
+            (
+                vec![tee(KeyParameterValue::UserSecureID(42))],
+                vec![
+                    tee(sign),
+                    KeyParameter::new(KeyParameterValue::UserSecureID(42), SecurityLevel::KEYSTORE)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_to_bundle() -> Result<()> {
+        let mut metadata = KeyMetaData::new();
+        metadata.add(KeyMetaEntry::CreationDate(DateTime::from_millis_epoch(1000)));
+        let state = KeyState {
+            key: KeyDescriptor {
+                domain: Domain::APP,
+                nspace: 10100,
+                alias: Some("key".to_string()),
+                blob: None,
+            },
+            key_id: 42,
+            parameters: vec![
+                KeyParameter::new(
+                    KeyParameterValue::KeySize(256),
+                    SecurityLevel::TRUSTED_ENVIRONMENT,
+                ),
+                KeyParameter::new(
+                    KeyParameterValue::ApplicationID(b"secret".to_vec()),
+                    SecurityLevel::TRUSTED_ENVIRONMENT,
+                ),
+            ],
+            metadata,
+            cert: Some(b"cert".to_vec()),
+            audit_records: vec![KeyUseRecord {
+                time_ms: 5,
+                caller_uid: 10100,
+                key_id: 42,
+                purpose: KeyPurpose::SIGN,
+                outcome: Outcome::Success,
+            }],
+            ..Default::default()
+        };
+
+        let bundle: Value = serde_cbor::from_slice(&state.to_bundle()?)?;
+        assert_eq!(field(&bundle, "version"), &Value::Integer(BUNDLE_VERSION));
+        assert_eq!(field(&bundle, "key_id"), &Value::Integer(42));
+        assert_eq!(field(&bundle, "alias"), &Value::Text("key".to_string()));
+        assert_eq!(field(&bundle, "lifecycle_state"), &Value::Text("Live".to_string()));
+        assert_eq!(field(&bundle, "certificate"), &Value::Bytes(b"cert".to_vec()));
+        assert_eq!(field(&bundle, "certificate_chain"), &Value::Null);
+        assert_eq!(field(field(&bundle, "metadata"), "creation_date_ms"), &Value::Integer(1000));
+        match field(&bundle, "authorizations") {
+            Value::Array(a) => assert_eq!(a.len(), 1),
+            v => panic!("Unexpected authorizations: {v:?}"),
+        }
+        match field(&bundle, "audit_records") {
+            Value::Array(a) => assert_eq!(field(&a[0], "caller_uid"), &Value::Integer(10100)),
+            v => panic!("Unexpected audit records: {v:?}"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements a short lived cache of `IKeystoreService::getKeyEntry` responses.
+//! Apps tend to look up the same keys several times in a row while starting up, and each
+//! lookup costs a database transaction and several SELinux checks.
+//!
+//! Responses are cached per calling UID and SID, so a cached response is only ever returned
+//! to a caller that passed the permission checks for it. Every mutation of a namespace
+//! invalidates the entries of that namespace. A key looked up by grant or key id may belong to
+//! any namespace, so such entries are invalidated by every mutation. Mutations that only change
+//! key metadata that is not part of the response, e.g., blob upgrades, do not invalidate.
+//!
+//! The time to live is configured with the system property `keystore.key_entry_cache.ttl_ms`.
+//! A value of 0 disables the cache.
+
+use crate::import_limits::read_usize_property;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, KeyEntryResponse::KeyEntryResponse,
+};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const TTL_PROPERTY: &str = "keystore.key_entry_cache.ttl_ms";
+
+const DEFAULT_TTL_MS: usize = 1000;
+
+/// The maximum number of cached responses. Bursts of lookups are short, so a full cache mostly
+/// holds expired entries.
+const MAX_ENTRIES: usize = 256;
+
+/// Identifies a lookup: the caller and the key descriptor it passed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LookupKey {
+    caller_uid: u32,
+    caller_sid: Vec<u8>,
+    domain: i32,
+    nspace: i64,
+    alias: Option<String>,
+}
+
+impl LookupKey {
+    /// Returns the cache key for a lookup of `key` by the given caller, or None if lookups of
+    /// this kind are not cached.
+    pub fn new(caller_uid: u32, caller_sid: Option<&CStr>, key: &KeyDescriptor) -> Option<Self> {
+        let nspace = match key.domain {
+            Domain::APP => caller_uid as i64,
+            Domain::SELINUX | Domain::GRANT | Domain::KEY_ID => key.nspace,
+            _ => return None,
+        };
+        Some(Self {
+            caller_uid,
+            caller_sid: caller_sid?.to_bytes().to_vec(),
+            domain: key.domain.0,
+            nspace,
+            alias: key.alias.clone(),
+        })
+    }
+
+    /// Returns the namespace the key was looked up in, or None if the lookup was indirect.
+    fn namespace(&self) -> Option<(i32, i64)> {
+        match Domain(self.domain) {
+            Domain::APP | Domain::SELINUX => Some((self.domain, self.nspace)),
+            _ => None,
+        }
+    }
+}
+
+struct CachedResponse {
+    inserted: Instant,
+    response: KeyEntryResponse,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Incremented by every invalidation. Responses loaded before an invalidation may be stale
+    /// and are not inserted.
+    generation: u64,
+    entries: HashMap<LookupKey, CachedResponse>,
+}
+
+/// Caches `getKeyEntry` responses for a short time.
+#[derive(Default)]
+pub struct KeyEntryCache {
+    state: Mutex<CacheState>,
+}
+
+impl KeyEntryCache {
+    fn ttl() -> Duration {
+        Duration::from_millis(read_usize_property(TTL_PROPERTY, DEFAULT_TTL_MS) as u64)
+    }
+
+    /// Returns the cached response to the lookup `key`, if any.
+    pub fn get(&self, key: &LookupKey) -> Option<KeyEntryResponse> {
+        self.get_with(Self::ttl(), Instant::now(), key)
+    }
+
+    fn get_with(&self, ttl: Duration, now: Instant, key: &LookupKey) -> Option<KeyEntryResponse> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(key)
+            .filter(|cached| now.duration_since(cached.inserted) < ttl)
+            .map(|cached| cached.response.clone())
+    }
+
+    /// Returns the current generation of the cache. It must be taken before loading the
+    /// response that is passed to `insert`.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// Caches `response` for the lookup `key`, unless the cache was invalidated since
+    /// `generation` was taken.
+    pub fn insert(&self, key: LookupKey, generation: u64, response: KeyEntryResponse) {
+        self.insert_with(Self::ttl(), Instant::now(), key, generation, response)
+    }
+
+    fn insert_with(
+        &self,
+        ttl: Duration,
+        now: Instant,
+        key: LookupKey,
+        generation: u64,
+        response: KeyEntryResponse,
+    ) {
+        if ttl.is_zero() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        if state.entries.len() >= MAX_ENTRIES {
+            state.entries.retain(|_, cached| now.duration_since(cached.inserted) < ttl);
+            if state.entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        state.entries.insert(key, CachedResponse { inserted: now, response });
+    }
+
+    /// Invalidates the cached responses that may be affected by a mutation of the namespace
+    /// `nspace` in `domain`.
+    pub fn invalidate_namespace(&self, domain: Domain, nspace: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.entries.retain(|key, _| key.namespace().is_some_and(|ns| ns != (domain.0, nspace)));
+    }
+
+    /// Invalidates the cached responses that may be affected by a mutation of the key `key`
+    /// on behalf of `caller_uid`.
+    pub fn invalidate_key(&self, key: &KeyDescriptor, caller_uid: u32) {
+        match key.domain {
+            Domain::APP => self.invalidate_namespace(Domain::APP, caller_uid as i64),
+            Domain::SELINUX => self.invalidate_namespace(Domain::SELINUX, key.nspace),
+            _ => self.invalidate_all(),
+        }
+    }
+
+    /// Invalidates all cached responses.
+    pub fn invalidate_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(1);
+    const APP_UID: u32 = 10100;
+
+    fn sid() -> &'static CStr {
+        CStr::from_bytes_with_nul(b"u:r:untrusted_app:s0\0").unwrap()
+    }
+
+    fn lookup(domain: Domain, nspace: i64, alias: &str) -> LookupKey {
+        let key = KeyDescriptor { domain, nspace, alias: Some(alias.to_string()), blob: None };
+        LookupKey::new(APP_UID, Some(sid()), &key).unwrap()
+    }
+
+    fn response(nspace: i64) -> KeyEntryResponse {
+        let mut response = KeyEntryResponse::default();
+        response.metadata.key.nspace = nspace;
+        response
+    }
+
+    fn cached(cache: &KeyEntryCache, now: Instant, key: &LookupKey) -> Option<i64> {
+        cache.get_with(TTL, now, key).map(|r| r.metadata.key.nspace)
+    }
+
+    #[test]
+    fn test_lookup_key() {
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 42,
+            alias: Some("foo".to_string()),
+            blob: None,
+        };
+        // The namespace of Domain::APP lookups is always the calling UID.
+        assert_eq!(LookupKey::new(APP_UID, Some(sid()), &key), Some(lookup(Domain::APP, 0, "foo")));
+        assert_eq!(LookupKey::new(APP_UID, None, &key), None);
+        let blob = KeyDescriptor { domain: Domain::BLOB, ..Default::default() };
+        assert_eq!(LookupKey::new(APP_UID, Some(sid()), &blob), None);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = KeyEntryCache::default();
+        let now = Instant::now();
+        let key = lookup(Domain::APP, 0, "foo");
+        cache.insert_with(TTL, now, key.clone(), cache.generation(), response(1));
+        assert_eq!(cached(&cache, now, &key), Some(1));
+        assert_eq!(cached(&cache, now + TTL / 2, &key), Some(1));
+        assert_eq!(cached(&cache, now + TTL, &key), None);
+        assert_eq!(cached(&cache, now, &lookup(Domain::APP, 0, "bar")), None);
+
+        // A TTL of 0 disables the cache.
+        let key = lookup(Domain::APP, 0, "bar");
+        cache.insert_with(Duration::ZERO, now, key.clone(), cache.generation(), response(2));
+        assert_eq!(cached(&cache, now, &key), None);
+    }
+
+    #[test]
+    fn test_invalidate_namespace() {
+        let cache = KeyEntryCache::default();
+        let now = Instant::now();
+        let app = lookup(Domain::APP, 0, "foo");
+        let selinux = lookup(Domain::SELINUX, 102, "foo");
+        let grant = lookup(Domain::GRANT, 7, "foo");
+        for (i, key) in [&app, &selinux, &grant].into_iter().enumerate() {
+            cache.insert_with(TTL, now, key.clone(), cache.generation(), response(i as i64));
+        }
+
+        // Indirect lookups are invalidated by mutations of any namespace.
+        cache.invalidate_namespace(Domain::SELINUX, 103);
+        assert_eq!(cached(&cache, now, &app), Some(0));
+        assert_eq!(cached(&cache, now, &selinux), Some(1));
+        assert_eq!(cached(&cache, now, &grant), None);
+
+        cache.invalidate_key(
+            &KeyDescriptor { domain: Domain::APP, nspace: 0, alias: None, blob: None },
+            APP_UID,
+        );
+        assert_eq!(cached(&cache, now, &app), None);
+        assert_eq!(cached(&cache, now, &selinux), Some(1));
+
+        cache.invalidate_key(&KeyDescriptor { domain: Domain::KEY_ID, ..Default::default() }, 0);
+        assert_eq!(cached(&cache, now, &selinux), None);
+    }
+
+    #[test]
+    fn test_stale_insert() {
+        let cache = KeyEntryCache::default();
+        let now = Instant::now();
+        let key = lookup(Domain::APP, 0, "foo");
+        let generation = cache.generation();
+        // The namespace is mutated while the response is being loaded.
+        cache.invalidate_namespace(Domain::SELINUX, 102);
+        cache.insert_with(TTL, now, key.clone(), generation, response(1));
+        assert_eq!(cached(&cache, now, &key), None);
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let cache = KeyEntryCache::default();
+        let now = Instant::now();
+        for i in 0..MAX_ENTRIES {
+            let key = lookup(Domain::APP, 0, &i.to_string());
+            cache.insert_with(TTL, now, key, cache.generation(), response(i as i64));
+        }
+        let key = lookup(Domain::APP, 0, "one too many");
+        cache.insert_with(TTL, now, key.clone(), cache.generation(), response(1));
+        assert_eq!(cached(&cache, now, &key), None);
+
+        // Expired entries make room for new ones.
+        cache.insert_with(TTL, now + TTL, key.clone(), cache.generation(), response(1));
+        assert_eq!(cached(&cache, now + TTL, &key), Some(1));
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the IKeystoreKeyLiveness AIDL interface, which lets callers re-prove
+//! the possession of an attested key without replacing it, see
+//! `KeystoreSecurityLevel::attest_key_liveness`.
+
+use crate::calling_identity;
+use crate::cpu_accounting;
+use crate::error::{into_logged_binder, map_km_error, Error, ErrorCode};
+use crate::isolated_callers::ISOLATED_CALLERS;
+use crate::ks_err;
+use crate::security_level::native_security_level;
+use crate::utils::watchdog as wd;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_security_keyliveness::aidl::android::security::keyliveness::{
+    IKeystoreKeyLiveness::{BnKeystoreKeyLiveness, IKeystoreKeyLiveness},
+    KeyLivenessAttestation::KeyLivenessAttestation,
+};
+use android_security_keyliveness::binder::{
+    BinderFeatures, Interface, Result as BinderResult, Strong,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    IKeystoreService::IKeystoreService, KeyDescriptor::KeyDescriptor,
+};
+use anyhow::{Context, Result};
+
+/// Implementation of the IKeystoreKeyLiveness service.
+pub struct KeyLivenessService {
+    service: Strong<dyn IKeystoreService>,
+}
+
+impl KeyLivenessService {
+    /// Create a new instance of the key liveness service. The given keystore service is used to
+    /// connect to security levels that are connected lazily.
+    pub fn new_native_binder(
+        service: Strong<dyn IKeystoreService>,
+    ) -> Result<Strong<dyn IKeystoreKeyLiveness>> {
+        Ok(BnKeystoreKeyLiveness::new_binder(
+            Self { service },
+            BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
+        ))
+    }
+
+    fn attest_key_liveness(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        challenge: &[u8],
+    ) -> Result<KeyLivenessAttestation> {
+        ISOLATED_CALLERS
+            .check_caller(calling_identity::get_calling_uid(), calling_identity::get_calling_pid())
+            .context(ks_err!())?;
+        // Getting the security level connects to it if it is connected lazily, e.g., StrongBox.
+        map_km_error(self.service.getSecurityLevel(security_level))
+            .context(ks_err!("Failed to get security level {security_level:?}."))?;
+        native_security_level(security_level)
+            .ok_or(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
+            .context(ks_err!("No such security level."))?
+            .attest_key_liveness(key, challenge)
+    }
+}
+
+impl Interface for KeyLivenessService {}
+
+impl IKeystoreKeyLiveness for KeyLivenessService {
+    fn attestKeyLiveness(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        challenge: &[u8],
+    ) -> BinderResult<KeyLivenessAttestation> {
+        let _wp = wd::watch("IKeystoreKeyLiveness::attestKeyLiveness");
+        let _cpu = cpu_accounting::account("IKeystoreKeyLiveness::attestKeyLiveness");
+        self.attest_key_liveness(security_level, key, challenge).map_err(into_logged_binder)
+    }
+}
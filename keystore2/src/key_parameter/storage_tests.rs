@@ -124,6 +124,23 @@ fn test_to_sql_enum_i32() -> Result<()> {
     Ok(())
 }
 
+/// Test storing a KeyParameter with an algorithm that is not known to Keystore, e.g., one that
+/// was added to KeyMint later, in the database
+#[test]
+fn test_to_sql_unknown_algorithm() -> Result<()> {
+    let db = init_db()?;
+    let kp = KeyParameter::new(
+        KeyParameterValue::Algorithm(Algorithm(1000)),
+        SecurityLevel::TRUSTED_ENVIRONMENT,
+    );
+    store_keyparameter(&db, 1, &kp)?;
+    let key_param = query_from_keyparameter(&db)?;
+    assert_eq!(kp.get_tag(), key_param.get_tag());
+    assert_eq!(kp.key_parameter_value(), key_param.key_parameter_value());
+    assert_eq!(kp.security_level(), key_param.security_level());
+    Ok(())
+}
+
 /// Test storing a KeyParameter (with key parameter value which is of i32) in the database
 #[test]
 fn test_to_sql_i32() -> Result<()> {
@@ -0,0 +1,135 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements key rotation, i.e., replacing the key bound to an alias with a newly
+//! generated key without a window in which the alias is bound to neither or to a half-stored
+//! key. The new key is bound to the alias in the same transaction that releases the previous
+//! key, see `KeystoreSecurityLevel::rotate_key`.
+//!
+//! Clients that were granted the previous key may not have switched to the new key yet, so the
+//! rotation can retain the previous key for a grace period. It is rebound to `<alias>.prev`,
+//! which replaces any key retained there by an earlier rotation, and it keeps its id and hence
+//! its grants. A retained key can only be used through its grants, and it is deleted when the
+//! grace period ends. Keys whose grace period ended cannot be used anymore even before the
+//! idle sweep deletes them.
+
+use crate::database::DateTime;
+use crate::error::Error;
+use crate::globals::{ASYNC_TASK, DB, KEY_ENTRY_CACHE};
+use crate::ks_err;
+use crate::permission::{KeyPerm, KeyPermSet};
+use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
+use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
+
+/// The suffix of the alias under which the previous key of a rotation is retained.
+pub const PREVIOUS_KEY_ALIAS_SUFFIX: &str = ".prev";
+
+/// The minimum time between two sweeps for retained keys whose grace period ended.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Returns the alias under which the previous key of a rotation of `alias` is retained.
+pub fn previous_key_alias(alias: &str) -> String {
+    format!("{alias}{PREVIOUS_KEY_ALIAS_SUFFIX}")
+}
+
+/// Returns the date until which the previous key of a rotation at `now` is retained, or None if
+/// `grace_period_millis` is 0 and the previous key is released right away.
+pub fn retain_previous_until(now: DateTime, grace_period_millis: i64) -> Result<Option<DateTime>> {
+    match grace_period_millis {
+        0 => Ok(None),
+        millis if millis < 0 => Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Negative grace period {millis}.")),
+        millis => now
+            .to_millis_epoch()
+            .checked_add(millis)
+            .map(|until| Some(DateTime::from_millis_epoch(until)))
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Grace period {millis} out of range.")),
+    }
+}
+
+/// Checks that a retained key may be used by a caller whose access to the key was established
+/// with `access_vector` by the access check of `KeystoreDB::load_key_entry`. The access vector
+/// is only set if the caller has a grant for the key, whether the caller addressed it with
+/// `Domain::GRANT` or `Domain::KEY_ID`. The owner of the key has no access vector, so it cannot
+/// use the retained key through any domain.
+pub fn check_retained_key_use(access_vector: &Option<KeyPermSet>) -> Result<()> {
+    match access_vector {
+        Some(access_vector) if access_vector.includes(KeyPerm::Use) => Ok(()),
+        _ => Err(Error::Rc(ResponseCode::PERMISSION_DENIED))
+            .context(ks_err!("Retained keys can only be used through grants.")),
+    }
+}
+
+struct RetentionSweepState {
+    last_sweep: Instant,
+}
+
+impl Default for RetentionSweepState {
+    fn default() -> Self {
+        Self { last_sweep: Instant::now() }
+    }
+}
+
+/// Registers the sweep that deletes the retained keys whose grace period ended as an idle
+/// callback.
+pub fn register_retention_sweep() {
+    ASYNC_TASK.add_idle(|shelf| {
+        let state = shelf.get_mut::<RetentionSweepState>();
+        let now = Instant::now();
+        if now.duration_since(state.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        state.last_sweep = now;
+        let result = DateTime::now()
+            .context(ks_err!("Failed to get the time."))
+            .and_then(|now| DB.with(|db| db.borrow_mut().unbind_expired_retained_keys(now)));
+        match result {
+            Ok(0) => {}
+            Ok(count) => {
+                log::info!("Deleted {count} retained keys whose grace period ended.");
+                KEY_ENTRY_CACHE.invalidate_all();
+            }
+            Err(e) => log::error!("Failed to delete retained keys: {e:?}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retain_previous_until() {
+        let now = DateTime::from_millis_epoch(1_000);
+        assert_eq!(retain_previous_until(now, 0).unwrap(), None);
+        assert_eq!(
+            retain_previous_until(now, 500).unwrap(),
+            Some(DateTime::from_millis_epoch(1_500))
+        );
+        for millis in [-1, i64::MAX] {
+            let error = retain_previous_until(now, millis).unwrap_err();
+            assert_eq!(
+                error.root_cause().downcast_ref::<Error>(),
+                Some(&Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            );
+        }
+    }
+
+    #[test]
+    fn test_previous_key_alias() {
+        assert_eq!(previous_key_alias("signing_key"), "signing_key.prev");
+    }
+}
@@ -0,0 +1,334 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the minimum key strength policy, which supports the deprecation of
+//! weak keys on a platform timeline. When enforced, new RSA, EC, and AES keys that are weaker
+//! than the configured minimums are rejected, and imported keys are checked again against the
+//! key characteristics reported by KeyMint, because the key size of imported key material need
+//! not be specified. Existing keys are not affected, but `list_weak_keys` reports them so that
+//! their owners can be identified before the policy is enforced. The policy is configured with
+//! system properties:
+//!  * `keystore.key_strength.enforce`: If true, weak keys are rejected. Defaults to false.
+//!  * `keystore.key_strength.min_rsa_bits`: The minimum size of RSA keys. Defaults to 2048.
+//!  * `keystore.key_strength.min_ec_bits`: The minimum size of EC keys. Defaults to 256, i.e.,
+//!    P-256. Curve 25519 counts as 256 bits.
+//!  * `keystore.key_strength.min_aes_bits`: The minimum size of AES keys. Defaults to 128.
+//!  * `keystore.key_strength.exempt_privileged`: If true (the default), keys in
+//!    `Domain::SELINUX` and keys of UIDs outside of the app range are exempt.
+//!
+//! A minimum of 0 disables the check for the respective algorithm.
+
+use crate::error::Error;
+use crate::globals::DB;
+use crate::import_limits::{is_privileged, read_usize_property};
+use crate::ks_err;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, EcCurve::EcCurve, ErrorCode::ErrorCode, KeyParameter::KeyParameter,
+    KeyParameterValue::KeyParameterValue, Tag::Tag,
+};
+use android_security_maintenance::aidl::android::security::maintenance::WeakKeyInfo::WeakKeyInfo;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor,
+};
+use anyhow::{Context, Result};
+
+const ENFORCE_PROPERTY: &str = "keystore.key_strength.enforce";
+const MIN_RSA_BITS_PROPERTY: &str = "keystore.key_strength.min_rsa_bits";
+const MIN_EC_BITS_PROPERTY: &str = "keystore.key_strength.min_ec_bits";
+const MIN_AES_BITS_PROPERTY: &str = "keystore.key_strength.min_aes_bits";
+const EXEMPT_PRIVILEGED_PROPERTY: &str = "keystore.key_strength.exempt_privileged";
+
+const DEFAULT_MIN_RSA_BITS: usize = 2048;
+const DEFAULT_MIN_EC_BITS: usize = 256;
+const DEFAULT_MIN_AES_BITS: usize = 128;
+
+/// Configuration of the minimum key strength policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStrengthConfig {
+    /// Whether new keys below the minimum strength are rejected.
+    pub enforce: bool,
+    /// Minimum size of RSA keys in bits, 0 for no minimum.
+    pub min_rsa_bits: usize,
+    /// Minimum size of EC keys in bits, 0 for no minimum.
+    pub min_ec_bits: usize,
+    /// Minimum size of AES keys in bits, 0 for no minimum.
+    pub min_aes_bits: usize,
+    /// Whether keys of privileged owners are exempt from the policy.
+    pub exempt_privileged: bool,
+}
+
+impl Default for KeyStrengthConfig {
+    fn default() -> Self {
+        Self {
+            enforce: false,
+            min_rsa_bits: DEFAULT_MIN_RSA_BITS,
+            min_ec_bits: DEFAULT_MIN_EC_BITS,
+            min_aes_bits: DEFAULT_MIN_AES_BITS,
+            exempt_privileged: true,
+        }
+    }
+}
+
+impl KeyStrengthConfig {
+    /// Reads the configuration from system properties, using the defaults for properties that
+    /// are not set or cannot be parsed.
+    pub fn from_system_properties() -> Self {
+        let default = Self::default();
+        let read_bool = |name, default_value| {
+            rustutils::system_properties::read_bool(name, default_value).unwrap_or(default_value)
+        };
+        Self {
+            enforce: read_bool(ENFORCE_PROPERTY, default.enforce),
+            min_rsa_bits: read_usize_property(MIN_RSA_BITS_PROPERTY, default.min_rsa_bits),
+            min_ec_bits: read_usize_property(MIN_EC_BITS_PROPERTY, default.min_ec_bits),
+            min_aes_bits: read_usize_property(MIN_AES_BITS_PROPERTY, default.min_aes_bits),
+            exempt_privileged: read_bool(EXEMPT_PRIVILEGED_PROPERTY, default.exempt_privileged),
+        }
+    }
+
+    /// Returns the minimum size in bits of keys of the given algorithm, 0 if there is none.
+    fn min_bits(&self, algorithm: Algorithm) -> usize {
+        match algorithm {
+            Algorithm::RSA => self.min_rsa_bits,
+            Algorithm::EC => self.min_ec_bits,
+            Algorithm::AES => self.min_aes_bits,
+            _ => 0,
+        }
+    }
+
+    /// Returns the algorithm, the size, and the minimum size of a key with the given parameters
+    /// if it is below the minimum strength.
+    fn weakness(&self, params: &[KeyParameter]) -> Option<(Algorithm, i32, usize)> {
+        let (algorithm, bits) = key_strength(params)?;
+        let min_bits = self.min_bits(algorithm);
+        match usize::try_from(bits) {
+            Ok(b) if b >= min_bits => None,
+            _ => Some((algorithm, bits, min_bits)),
+        }
+    }
+}
+
+/// Returns the size in bits of keys on the given curve.
+fn ec_curve_bits(curve: EcCurve) -> Option<i32> {
+    match curve {
+        EcCurve::P_224 => Some(224),
+        EcCurve::P_256 | EcCurve::CURVE_25519 => Some(256),
+        EcCurve::P_384 => Some(384),
+        EcCurve::P_521 => Some(521),
+        _ => None,
+    }
+}
+
+/// Returns the algorithm and the size in bits of a key with the given parameters, if the policy
+/// covers the algorithm and the parameters determine the size. The curve of an EC key takes
+/// precedence over its key size.
+fn key_strength(params: &[KeyParameter]) -> Option<(Algorithm, i32)> {
+    let algorithm = params.iter().find_map(|p| match p.value {
+        KeyParameterValue::Algorithm(a) if p.tag == Tag::ALGORITHM => Some(a),
+        _ => None,
+    })?;
+    let key_size = params.iter().find_map(|p| match p.value {
+        KeyParameterValue::Integer(bits) if p.tag == Tag::KEY_SIZE => Some(bits),
+        _ => None,
+    });
+    let curve_bits = params.iter().find_map(|p| match p.value {
+        KeyParameterValue::EcCurve(curve) if p.tag == Tag::EC_CURVE => ec_curve_bits(curve),
+        _ => None,
+    });
+    match algorithm {
+        Algorithm::RSA | Algorithm::AES => key_size.map(|bits| (algorithm, bits)),
+        Algorithm::EC => curve_bits.or(key_size).map(|bits| (algorithm, bits)),
+        _ => None,
+    }
+}
+
+/// Checks the parameters of a new key that `caller_uid` creates in `domain` against the
+/// policy configured by system properties.
+pub fn check_key_strength(caller_uid: u32, domain: Domain, params: &[KeyParameter]) -> Result<()> {
+    check_key_strength_with(
+        &KeyStrengthConfig::from_system_properties(),
+        caller_uid,
+        domain,
+        params,
+    )
+}
+
+fn check_key_strength_with(
+    config: &KeyStrengthConfig,
+    caller_uid: u32,
+    domain: Domain,
+    params: &[KeyParameter],
+) -> Result<()> {
+    if !config.enforce || (config.exempt_privileged && is_privileged(caller_uid, domain)) {
+        return Ok(());
+    }
+    match config.weakness(params) {
+        None => Ok(()),
+        Some((algorithm, bits, min_bits)) => {
+            let error_code = if params.iter().any(|p| p.tag == Tag::EC_CURVE) {
+                ErrorCode::UNSUPPORTED_EC_CURVE
+            } else {
+                ErrorCode::UNSUPPORTED_KEY_SIZE
+            };
+            Err(Error::Km(error_code)).context(ks_err!(
+                "{:?} key of {} bits is below the minimum of {} bits required by policy.",
+                algorithm,
+                bits,
+                min_bits
+            ))
+        }
+    }
+}
+
+/// Returns all existing keys that are below the minimum strength configured by system
+/// properties, regardless of whether the policy is enforced.
+pub fn list_weak_keys() -> Result<Vec<WeakKeyInfo>> {
+    let keys = DB
+        .with(|db| db.borrow_mut().load_key_strength_parameters())
+        .context(ks_err!("Failed to load key parameters."))?
+        .into_iter()
+        .map(|(key, params)| {
+            (key, params.into_iter().map(|p| p.key_parameter_value().clone().into()).collect())
+        })
+        .collect();
+    Ok(list_weak_keys_with(&KeyStrengthConfig::from_system_properties(), keys))
+}
+
+fn list_weak_keys_with(
+    config: &KeyStrengthConfig,
+    keys: Vec<(KeyDescriptor, Vec<KeyParameter>)>,
+) -> Vec<WeakKeyInfo> {
+    keys.into_iter()
+        .filter(|(key, _)| {
+            // The namespace of an app key is the UID of its owner.
+            let owner_uid = u32::try_from(key.nspace).unwrap_or(0);
+            !(config.exempt_privileged && is_privileged(owner_uid, key.domain))
+        })
+        .filter_map(|(key, params)| {
+            let (algorithm, bits, min_bits) = config.weakness(&params)?;
+            Some(WeakKeyInfo {
+                key,
+                algorithm,
+                keySizeBits: bits,
+                minimumKeySizeBits: min_bits.try_into().unwrap_or(i32::MAX),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_UID: u32 = 10100;
+
+    fn algorithm(algorithm: Algorithm) -> KeyParameter {
+        KeyParameter { tag: Tag::ALGORITHM, value: KeyParameterValue::Algorithm(algorithm) }
+    }
+
+    fn key_size(bits: i32) -> KeyParameter {
+        KeyParameter { tag: Tag::KEY_SIZE, value: KeyParameterValue::Integer(bits) }
+    }
+
+    fn ec_curve(curve: EcCurve) -> KeyParameter {
+        KeyParameter { tag: Tag::EC_CURVE, value: KeyParameterValue::EcCurve(curve) }
+    }
+
+    fn km_error(result: Result<()>) -> Option<ErrorCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Km(ec)) => Some(*ec),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_key_strength() {
+        assert_eq!(
+            key_strength(&[algorithm(Algorithm::RSA), key_size(1024)]),
+            Some((Algorithm::RSA, 1024))
+        );
+        assert_eq!(
+            key_strength(&[algorithm(Algorithm::EC), key_size(521), ec_curve(EcCurve::P_224)]),
+            Some((Algorithm::EC, 224))
+        );
+        assert_eq!(
+            key_strength(&[algorithm(Algorithm::EC), key_size(384)]),
+            Some((Algorithm::EC, 384))
+        );
+        assert_eq!(key_strength(&[algorithm(Algorithm::RSA)]), None);
+        assert_eq!(key_strength(&[algorithm(Algorithm::HMAC), key_size(64)]), None);
+        assert_eq!(key_strength(&[key_size(64)]), None);
+    }
+
+    #[test]
+    fn test_check_key_strength() {
+        let config = KeyStrengthConfig { enforce: true, ..Default::default() };
+        let check = |params: &[KeyParameter]| {
+            check_key_strength_with(&config, APP_UID, Domain::APP, params)
+        };
+        assert!(check(&[algorithm(Algorithm::RSA), key_size(2048)]).is_ok());
+        assert!(check(&[algorithm(Algorithm::EC), ec_curve(EcCurve::CURVE_25519)]).is_ok());
+        assert!(check(&[algorithm(Algorithm::AES), key_size(128)]).is_ok());
+        assert_eq!(
+            km_error(check(&[algorithm(Algorithm::RSA), key_size(1024)])),
+            Some(ErrorCode::UNSUPPORTED_KEY_SIZE)
+        );
+        assert_eq!(
+            km_error(check(&[algorithm(Algorithm::EC), ec_curve(EcCurve::P_224)])),
+            Some(ErrorCode::UNSUPPORTED_EC_CURVE)
+        );
+        assert_eq!(
+            km_error(check(&[algorithm(Algorithm::AES), key_size(64)])),
+            Some(ErrorCode::UNSUPPORTED_KEY_SIZE)
+        );
+
+        let weak = [algorithm(Algorithm::RSA), key_size(1024)];
+        assert!(check_key_strength_with(&config, APP_UID, Domain::SELINUX, &weak).is_ok());
+        assert!(check_key_strength_with(&config, 1000, Domain::APP, &weak).is_ok());
+        let no_minimum = KeyStrengthConfig { min_rsa_bits: 0, ..config };
+        assert!(check_key_strength_with(&no_minimum, APP_UID, Domain::APP, &weak).is_ok());
+        let not_enforced = KeyStrengthConfig { enforce: false, ..config };
+        assert!(check_key_strength_with(&not_enforced, APP_UID, Domain::APP, &weak).is_ok());
+    }
+
+    #[test]
+    fn test_list_weak_keys() {
+        let key = |domain, nspace: u32, alias: &str| KeyDescriptor {
+            domain,
+            nspace: nspace.into(),
+            alias: Some(alias.to_string()),
+            blob: None,
+        };
+        let weak = vec![algorithm(Algorithm::RSA), key_size(1024)];
+        let keys = vec![
+            (key(Domain::APP, APP_UID, "weak"), weak.clone()),
+            (key(Domain::APP, APP_UID, "strong"), vec![algorithm(Algorithm::RSA), key_size(4096)]),
+            (key(Domain::APP, 1000, "system"), weak.clone()),
+            (key(Domain::SELINUX, 102, "selinux"), weak.clone()),
+        ];
+
+        let config = KeyStrengthConfig::default();
+        assert_eq!(
+            list_weak_keys_with(&config, keys.clone()),
+            vec![WeakKeyInfo {
+                key: key(Domain::APP, APP_UID, "weak"),
+                algorithm: Algorithm::RSA,
+                keySizeBits: 1024,
+                minimumKeySizeBits: 2048,
+            }]
+        );
+        let config = KeyStrengthConfig { exempt_privileged: false, ..config };
+        assert_eq!(list_weak_keys_with(&config, keys).len(), 3);
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the registry of key generation templates. A template is a named set
+//! of key parameters, e.g., `webauthn-p256`, that is defined by device policy. Apps generate a
+//! key from a template by referring to it as `template:<name>` instead of listing the
+//! parameters themselves, so that all keys of a kind get the same parameters across the fleet,
+//! and the policy owner can change the parameters without changing the apps.
+//!
+//! The parameters of the template take precedence: the parameters given by the app are added
+//! to them, and must not use any tag that the template uses.
+//!
+//! Templates are registered through IKeystoreMaintenance and do not persist across restarts of
+//! keystore, so they must be re-registered by the policy owner.
+
+use crate::error::Error;
+use crate::ks_err;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    KeyParameter::KeyParameter, Tag::Tag,
+};
+use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+/// The key generation templates of this keystore instance.
+pub static KEY_TEMPLATES: LazyLock<KeyTemplates> = LazyLock::new(Default::default);
+
+/// The prefix of references to templates.
+pub const TEMPLATE_PREFIX: &str = "template:";
+
+/// The maximum number of templates.
+const MAX_TEMPLATES: usize = 64;
+
+/// The tags that keystore adds to the parameters of new keys, which templates must not set.
+const RESERVED_TAGS: &[Tag] = &[
+    Tag::CREATION_DATETIME,
+    Tag::ATTESTATION_APPLICATION_ID,
+    Tag::ATTESTATION_CHALLENGE,
+    Tag::RESET_SINCE_ID_ROTATION,
+];
+
+/// Holds the key generation templates by name.
+#[derive(Debug, Default)]
+pub struct KeyTemplates {
+    templates: RwLock<HashMap<String, Vec<KeyParameter>>>,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-_.".contains(&b))
+}
+
+impl KeyTemplates {
+    /// Registers the template `name` with the key parameters `params`, replacing a template of
+    /// the same name. Names consist of at most 64 lower case letters, digits, '-', '_' and '.'.
+    /// The parameters must include the algorithm and must not include tags that keystore adds
+    /// or that are specific to a single key.
+    pub fn register(&self, name: &str, params: &[KeyParameter]) -> Result<()> {
+        if !is_valid_name(name) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Invalid template name {:?}.", name));
+        }
+        if !params.iter().any(|kp| kp.tag == Tag::ALGORITHM) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Template {:?} has no algorithm.", name));
+        }
+        if let Some(kp) = params.iter().find(|kp| RESERVED_TAGS.contains(&kp.tag)) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Template {:?} must not set {:?}.",
+                name,
+                kp.tag
+            ));
+        }
+        let mut templates = self.templates.write().unwrap();
+        if templates.len() >= MAX_TEMPLATES && !templates.contains_key(name) {
+            return Err(Error::Rc(ResponseCode::TOO_MUCH_DATA))
+                .context(ks_err!("Too many templates to register {:?}.", name));
+        }
+        log::info!("Registered key template {name:?}: {params:?}");
+        templates.insert(name.to_string(), params.to_vec());
+        Ok(())
+    }
+
+    /// Removes the template `name`. Returns false if there is no such template.
+    pub fn unregister(&self, name: &str) -> bool {
+        log::info!("Unregistering key template {name:?}.");
+        self.templates.write().unwrap().remove(name).is_some()
+    }
+
+    /// Expands the template reference `reference`, i.e., `template:<name>`, into the parameters
+    /// of the template followed by `params`.
+    pub fn expand(&self, reference: &str, params: &[KeyParameter]) -> Result<Vec<KeyParameter>> {
+        let name = reference
+            .strip_prefix(TEMPLATE_PREFIX)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Invalid template reference {:?}.", reference))?;
+        let templates = self.templates.read().unwrap();
+        let template = templates
+            .get(name)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("No template {:?}.", name))?;
+        if let Some(kp) = params.iter().find(|kp| template.iter().any(|t| t.tag == kp.tag)) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Template {:?} already sets {:?}.",
+                name,
+                kp.tag
+            ));
+        }
+        Ok(template.iter().chain(params).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+        Algorithm::Algorithm, EcCurve::EcCurve, KeyParameterValue::KeyParameterValue,
+        KeyPurpose::KeyPurpose,
+    };
+
+    fn param(tag: Tag, value: KeyParameterValue) -> KeyParameter {
+        KeyParameter { tag, value }
+    }
+
+    fn rc(result: Result<impl std::fmt::Debug>) -> Option<ResponseCode> {
+        match result.unwrap_err().root_cause().downcast_ref::<Error>() {
+            Some(Error::Rc(rc)) => Some(*rc),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let templates = KeyTemplates::default();
+        let ec = param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC));
+        let invalid = Some(ResponseCode::INVALID_ARGUMENT);
+        assert_eq!(rc(templates.register("", &[ec.clone()])), invalid);
+        assert_eq!(rc(templates.register("WebAuthn", &[ec.clone()])), invalid);
+        assert_eq!(rc(templates.register("webauthn", &[])), invalid);
+        let challenge = param(Tag::ATTESTATION_CHALLENGE, KeyParameterValue::Blob(vec![1]));
+        assert_eq!(rc(templates.register("webauthn", &[ec.clone(), challenge])), invalid);
+
+        for i in 0..MAX_TEMPLATES {
+            templates.register(&format!("template-{i}"), &[ec.clone()]).unwrap();
+        }
+        assert_eq!(
+            rc(templates.register("webauthn-p256", &[ec.clone()])),
+            Some(ResponseCode::TOO_MUCH_DATA)
+        );
+        // Replacing a template is always possible.
+        templates.register("template-0", &[ec.clone()]).unwrap();
+        assert!(templates.unregister("template-0"));
+        assert!(!templates.unregister("template-0"));
+        templates.register("webauthn-p256", &[ec]).unwrap();
+    }
+
+    #[test]
+    fn test_expand() {
+        let templates = KeyTemplates::default();
+        let template = vec![
+            param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC)),
+            param(Tag::EC_CURVE, KeyParameterValue::EcCurve(EcCurve::P_256)),
+            param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::SIGN)),
+        ];
+        templates.register("webauthn-p256", &template).unwrap();
+
+        let challenge = param(Tag::ATTESTATION_CHALLENGE, KeyParameterValue::Blob(vec![1]));
+        let mut expected = template.clone();
+        expected.push(challenge.clone());
+        assert_eq!(
+            templates.expand("template:webauthn-p256", &[challenge.clone()]).unwrap(),
+            expected
+        );
+
+        let invalid = Some(ResponseCode::INVALID_ARGUMENT);
+        assert_eq!(rc(templates.expand("webauthn-p256", &[])), invalid);
+        assert_eq!(rc(templates.expand("template:webauthn-p384", &[])), invalid);
+        // Apps cannot override the parameters of the template.
+        let purpose = param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::AGREE_KEY));
+        assert_eq!(rc(templates.expand("template:webauthn-p256", &[purpose])), invalid);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module records per-key usage statistics, i.e., the number of operations started with a
+//! key and the time of the last one, so that stale keys can be found. The statistics are kept in
+//! the key metadata in the database.
+//!
+//! Writing to the database for every operation would put a transaction on the hot path, so uses
+//! are collected in memory and written in batches by the async task: when `FLUSH_THRESHOLD` keys
+//! have pending uses, or the oldest pending use is `FLUSH_INTERVAL` old. The pending uses of at
+//! most one batch are lost if keystore dies.
+
+use crate::database::{DateTime, KeyUsageStats};
+use crate::globals::{ASYNC_TASK, DB};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of keys with pending uses from which on the uses are written to the database.
+const FLUSH_THRESHOLD: usize = 64;
+
+/// The age of the oldest pending use from which on the uses are written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The key usage statistics of this keystore instance.
+pub static KEY_USAGE: LazyLock<KeyUsage> = LazyLock::new(Default::default);
+
+#[derive(Debug, Default)]
+struct Pending {
+    usage: HashMap<i64, KeyUsageStats>,
+    /// The time of the oldest pending use.
+    since: Option<Instant>,
+    /// True if a flush is queued on the async task.
+    flush_queued: bool,
+}
+
+/// Collects the uses of keys until they are written to the database.
+#[derive(Debug, Default)]
+pub struct KeyUsage {
+    pending: Mutex<Pending>,
+}
+
+impl KeyUsage {
+    /// Records that an operation was started with the key `key_id` at `now`.
+    pub fn record_use(&self, key_id: i64, now: DateTime) {
+        if self.add(key_id, now, Instant::now()) {
+            ASYNC_TASK.queue_lo(|_| KEY_USAGE.flush());
+        }
+    }
+
+    /// Adds a use of `key_id` to the pending uses. Returns true if the caller must queue a flush.
+    fn add(&self, key_id: i64, now: DateTime, instant: Instant) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending
+            .usage
+            .entry(key_id)
+            .or_default()
+            .merge(&KeyUsageStats { operation_count: 1, last_used: Some(now) });
+        let since = *pending.since.get_or_insert(instant);
+        let due = pending.usage.len() >= FLUSH_THRESHOLD
+            || instant.saturating_duration_since(since) >= FLUSH_INTERVAL;
+        if due && !pending.flush_queued {
+            pending.flush_queued = true;
+            return true;
+        }
+        false
+    }
+
+    /// Removes and returns the pending uses.
+    fn take(&self) -> Vec<(i64, KeyUsageStats)> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.since = None;
+        pending.flush_queued = false;
+        std::mem::take(&mut pending.usage).into_iter().collect()
+    }
+
+    /// Returns the uses of `key_id` that are not written to the database yet.
+    pub fn pending_usage(&self, key_id: i64) -> KeyUsageStats {
+        self.pending.lock().unwrap().usage.get(&key_id).copied().unwrap_or_default()
+    }
+
+    /// Writes the pending uses to the database.
+    pub fn flush(&self) {
+        let usage = self.take();
+        if usage.is_empty() {
+            return;
+        }
+        if let Err(e) = DB.with(|db| db.borrow_mut().record_key_usage(&usage)) {
+            log::error!("Failed to record the usage of {} keys: {e:?}", usage.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_take() {
+        let usage = KeyUsage::default();
+        let start = Instant::now();
+        let t1 = DateTime::from_millis_epoch(1000);
+        let t2 = DateTime::from_millis_epoch(2000);
+        assert!(!usage.add(1, t2, start));
+        assert!(!usage.add(1, t1, start));
+        assert_eq!(
+            usage.pending_usage(1),
+            KeyUsageStats { operation_count: 2, last_used: Some(t2) }
+        );
+        assert_eq!(usage.pending_usage(2), KeyUsageStats::default());
+
+        // The batch is due once the oldest use is old enough, and is only queued once.
+        assert!(usage.add(2, t2, start + FLUSH_INTERVAL));
+        assert!(!usage.add(3, t2, start + FLUSH_INTERVAL));
+        let mut taken = usage.take();
+        taken.sort_by_key(|(key_id, _)| *key_id);
+        assert_eq!(taken.iter().map(|(key_id, _)| *key_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(usage.pending_usage(1), KeyUsageStats::default());
+
+        // The batch is also due once enough keys have pending uses.
+        for key_id in 0..FLUSH_THRESHOLD as i64 - 1 {
+            assert!(!usage.add(key_id, t1, start));
+        }
+        assert!(usage.add(FLUSH_THRESHOLD as i64, t1, start));
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module states the rules for the visibility of keys across Android users and
+//! implements the query for the effective visibility of a key.
+//!
+//! A UID encodes both an Android user and an app. Work, clone, and private profiles are Android
+//! users of their own, so the same app has a different UID in each profile. The rules are:
+//!
+//!  * A `Domain::APP` key is visible to the UID that owns it, through `Domain::APP` and
+//!    `Domain::KEY_ID`, see `permission::is_app_key_owner`. It is not visible to the same app in
+//!    any other Android user or profile.
+//!  * Any other UID, in the same or in another Android user, sees a `Domain::APP` key only if
+//!    the key was granted to it, and only through `Domain::GRANT` with the granted permissions.
+//!  * A `Domain::SELINUX` key is visible to all callers that have permissions on its namespace
+//!    according to the SELinux policy. The Android user of the caller plays no role. Grants
+//!    work as for `Domain::APP` keys.
+
+use crate::database::{KeyEntryLoadBits, KeyType};
+use crate::error::Error;
+use crate::globals::DB;
+use crate::ks_err;
+use crate::permission::KeyPermSet;
+use crate::utils::{uid_to_android_user, AID_APP_START, AID_USER_OFFSET};
+use android_security_maintenance::aidl::android::security::maintenance::{
+    KeyGrantee::KeyGrantee, KeyVisibility::KeyVisibility, StaleGrant::StaleGrant,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+/// Returns the Android user that owns the key with the descriptor `key`, as stored in the
+/// database, or None if the key is not a `Domain::APP` key.
+fn owner_user(key: &KeyDescriptor) -> Result<Option<u32>> {
+    match key.domain {
+        Domain::APP => Ok(Some(uid_to_android_user(
+            u32::try_from(key.nspace)
+                .map_err(|_| Error::sys())
+                .context(ks_err!("Invalid owner UID {}.", key.nspace))?,
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the grantee `uid` with the permissions `access_vector` of a key owned by
+/// `owner_user`.
+fn grantee(uid: u32, access_vector: KeyPermSet, owner_user: Option<u32>) -> Result<KeyGrantee> {
+    let user_id = uid_to_android_user(uid);
+    Ok(KeyGrantee {
+        uid: i32::try_from(uid).context(ks_err!("Invalid grantee UID {uid}."))?,
+        userId: user_id as i32,
+        accessVector: i32::from(access_vector),
+        crossUser: owner_user.is_some_and(|owner| owner != user_id),
+    })
+}
+
+/// Returns the visibility of the key with the descriptor `key`, as stored in the database,
+/// that was granted to `grants`.
+fn visibility(key: KeyDescriptor, grants: &[(u32, KeyPermSet)]) -> Result<KeyVisibility> {
+    let owner_user = owner_user(&key)?;
+    let grantees = grants
+        .iter()
+        .map(|(uid, access_vector)| grantee(*uid, *access_vector, owner_user))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(KeyVisibility { key, ownerUserId: owner_user.map_or(-1, |u| u as i32), grantees })
+}
+
+/// Returns true if `uid` is in the application UID range of an Android user.
+fn is_app_uid(uid: u32) -> bool {
+    uid % AID_USER_OFFSET >= AID_APP_START
+}
+
+/// Returns the grants in `grants` to application UIDs that are not in `installed_uids`.
+fn stale_grants(
+    grants: Vec<(i64, KeyDescriptor, u32, KeyPermSet)>,
+    installed_uids: &HashSet<u32>,
+) -> Result<Vec<StaleGrant>> {
+    grants
+        .into_iter()
+        .filter(|(_, _, uid, _)| is_app_uid(*uid) && !installed_uids.contains(uid))
+        .map(|(grant_id, key, uid, access_vector)| {
+            let grantee = grantee(uid, access_vector, owner_user(&key)?)?;
+            Ok(StaleGrant { grantId: grant_id, key, grantee })
+        })
+        .collect()
+}
+
+/// Returns all grants to application UIDs that are not in `installed_uids`. Such grants should
+/// have been deleted when the grantee was removed. The caller must have been authorized to
+/// inspect all keys.
+pub fn list_stale_grants(installed_uids: &[i32]) -> Result<Vec<StaleGrant>> {
+    let installed_uids = installed_uids
+        .iter()
+        .map(|uid| {
+            u32::try_from(*uid)
+                .map_err(|_| Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Invalid UID {uid}."))
+        })
+        .collect::<Result<HashSet<u32>>>()?;
+    let grants = DB
+        .with(|db| db.borrow_mut().load_all_grants())
+        .context(ks_err!("Failed to load grants."))?;
+    stale_grants(grants, &installed_uids)
+}
+
+/// Returns the visibility of the given key. For `Domain::APP`, `key.nspace` is the UID of the
+/// owner of the key rather than that of the caller. The caller must have been authorized to
+/// inspect all keys.
+pub fn get_key_visibility(key: &KeyDescriptor) -> Result<KeyVisibility> {
+    let owner_uid = match key.domain {
+        Domain::APP => u32::try_from(key.nspace)
+            .map_err(|_| Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Invalid UID {}.", key.nspace))?,
+        Domain::SELINUX | Domain::KEY_ID => 0,
+        _ => {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Unsupported domain {:?}.", key.domain));
+        }
+    };
+    let (key, grants) = DB
+        .with::<_, Result<(KeyDescriptor, Vec<(u32, KeyPermSet)>)>>(|db| {
+            let mut db = db.borrow_mut();
+            let (key_id_guard, _) = db.load_key_entry(
+                key,
+                KeyType::Client,
+                KeyEntryLoadBits::NONE,
+                owner_uid,
+                // Access to all keys was granted with the permission to query the visibility.
+                |_k, _av| Ok(()),
+            )?;
+            let key_id = key_id_guard.id();
+            let key = db
+                .load_key_descriptor(key_id)?
+                .ok_or(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+                .context(ks_err!("Key {key_id} went away."))?;
+            Ok((key, db.load_grants(key_id)?))
+        })
+        .context(ks_err!("Failed to load key entry."))?;
+    visibility(key, &grants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_perm_set;
+    use crate::permission::KeyPerm;
+
+    const APP_ID: u32 = 10100;
+    const WORK_PROFILE: u32 = 10;
+
+    #[test]
+    fn test_visibility() -> Result<()> {
+        let uid = |user: u32| user * AID_USER_OFFSET + APP_ID;
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: uid(WORK_PROFILE) as i64,
+            alias: Some("key".to_string()),
+            blob: None,
+        };
+        let grants = [
+            (uid(WORK_PROFILE) + 1, key_perm_set![KeyPerm::Use]),
+            (uid(0), key_perm_set![KeyPerm::GetInfo, KeyPerm::Use]),
+        ];
+        let v = visibility(key.clone(), &grants)?;
+        assert_eq!(v.key, key);
+        assert_eq!(v.ownerUserId, WORK_PROFILE as i32);
+        assert_eq!(
+            v.grantees,
+            vec![
+                KeyGrantee {
+                    uid: (uid(WORK_PROFILE) + 1) as i32,
+                    userId: WORK_PROFILE as i32,
+                    accessVector: i32::from(key_perm_set![KeyPerm::Use]),
+                    crossUser: false,
+                },
+                KeyGrantee {
+                    uid: uid(0) as i32,
+                    userId: 0,
+                    accessVector: i32::from(key_perm_set![KeyPerm::GetInfo, KeyPerm::Use]),
+                    crossUser: true,
+                },
+            ]
+        );
+
+        // SELinux keys belong to no Android user, so no grant crosses users.
+        let key = KeyDescriptor { domain: Domain::SELINUX, nspace: 102, ..key };
+        let v = visibility(key, &grants)?;
+        assert_eq!(v.ownerUserId, -1);
+        assert!(v.grantees.iter().all(|g| !g.crossUser));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_grants() -> Result<()> {
+        const SYSTEM_UID: u32 = 1000;
+        let uid = |user: u32| user * AID_USER_OFFSET + APP_ID;
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: uid(0) as i64,
+            alias: Some("key".to_string()),
+            blob: None,
+        };
+        let grants = vec![
+            (1, key.clone(), SYSTEM_UID, key_perm_set![KeyPerm::Use]),
+            (2, key.clone(), uid(0) + 1, key_perm_set![KeyPerm::Use]),
+            (3, key.clone(), uid(WORK_PROFILE), key_perm_set![KeyPerm::GetInfo]),
+        ];
+
+        // Every grantee is installed.
+        let installed = HashSet::from([uid(0), uid(0) + 1, uid(WORK_PROFILE)]);
+        assert_eq!(stale_grants(grants.clone(), &installed)?, vec![]);
+
+        // The app in the work profile was removed. Grants to system UIDs are never stale.
+        let installed = HashSet::from([uid(0), uid(0) + 1]);
+        assert_eq!(
+            stale_grants(grants, &installed)?,
+            vec![StaleGrant {
+                grantId: 3,
+                key,
+                grantee: KeyGrantee {
+                    uid: uid(WORK_PROFILE) as i32,
+                    userId: WORK_PROFILE as i32,
+                    accessVector: i32::from(key_perm_set![KeyPerm::GetInfo]),
+                    crossUser: true,
+                },
+            }]
+        );
+        Ok(())
+    }
+}
@@ -14,21 +14,33 @@
 
 //! This crate implements the Keystore 2.0 service entry point.
 
+use keystore2::async_service::KeystoreAsyncService;
+use keystore2::boot_profile::BOOT_PROFILE;
 use keystore2::entropy;
 use keystore2::globals::ENFORCEMENTS;
+use keystore2::integrity_check;
+use keystore2::key_liveness::KeyLivenessService;
+use keystore2::key_rotation;
+use keystore2::log_levels::{ModuleLogFilter, MODULE_LOG_LEVELS};
 use keystore2::maintenance::Maintenance;
 use keystore2::metrics::Metrics;
 use keystore2::metrics_store;
+use keystore2::perboot_scrubber;
 use keystore2::service::KeystoreService;
+use keystore2::session_keys::{self, SESSION_KEYS};
+use keystore2::test_hooks;
+use keystore2::thermal::THERMAL_THROTTLING;
 use keystore2::{apc::ApcManager, shared_secret_negotiation};
 use keystore2::{authorization::AuthorizationManager, id_rotation::IdRotationState};
 use legacykeystore::LegacyKeystore;
 use log::{error, info};
 use rusqlite::trace as sqlite_trace;
-use std::{os::raw::c_int, panic, path::Path, sync::mpsc::channel};
+use std::{os::raw::c_int, panic, path::Path, sync::mpsc::channel, sync::LazyLock};
 
 static KS2_SERVICE_NAME: &str = "android.system.keystore2.IKeystoreService/default";
+static ASYNC_SERVICE_NAME: &str = "android.security.keystoreasync";
 static APC_SERVICE_NAME: &str = "android.security.apc";
+static KEY_LIVENESS_SERVICE_NAME: &str = "android.security.keyliveness";
 static AUTHORIZATION_SERVICE_NAME: &str = "android.security.authorization";
 static METRICS_SERVICE_NAME: &str = "android.security.metrics";
 static USER_MANAGER_SERVICE_NAME: &str = "android.security.maintenance";
@@ -36,11 +48,15 @@ static LEGACY_KEYSTORE_SERVICE_NAME: &str = "android.security.legacykeystore";
 
 /// Keystore 2.0 takes one argument which is a path indicating its designated working directory.
 fn main() {
-    // Initialize android logging.
-    android_logger::init_once(
+    // Start the boot profile clock before anything else.
+    LazyLock::force(&BOOT_PROFILE);
+
+    // Initialize android logging. The levels are filtered per module, so the android logger
+    // itself must accept all records.
+    let android_logger = android_logger::AndroidLogger::new(
         android_logger::Config::default()
             .with_tag("keystore2")
-            .with_max_level(log::LevelFilter::Debug)
+            .with_max_level(log::LevelFilter::Trace)
             .with_log_buffer(android_logger::LogId::System)
             .format(|buf, record| {
                 writeln!(
@@ -52,6 +68,10 @@ fn main() {
                 )
             }),
     );
+    log::set_boxed_logger(Box::new(ModuleLogFilter::new(android_logger)))
+        .expect("Failed to install logger.");
+    log::set_max_level(log::LevelFilter::Trace);
+    MODULE_LOG_LEVELS.watch();
     // Redirect panic messages to logcat.
     panic::set_hook(Box::new(|panic_info| {
         error!("{}", panic_info);
@@ -94,6 +114,12 @@ fn main() {
     ENFORCEMENTS.install_confirmation_token_receiver(confirmation_token_receiver);
 
     entropy::register_feeder();
+    integrity_check::register_integrity_check();
+    key_rotation::register_retention_sweep();
+    perboot_scrubber::register_perboot_scrubber();
+    session_keys::delete_orphaned_session_keys();
+    SESSION_KEYS.watch();
+    THERMAL_THROTTLING.watch();
     shared_secret_negotiation::perform_shared_secret_negotiation();
 
     info!("Starting thread pool now.");
@@ -106,6 +132,22 @@ fn main() {
         panic!("Failed to register service {} because of {:?}.", KS2_SERVICE_NAME, e);
     });
 
+    let key_liveness_service = KeyLivenessService::new_native_binder(ks_service.clone())
+        .unwrap_or_else(|e| {
+            panic!("Failed to create service {} because of {:?}.", KEY_LIVENESS_SERVICE_NAME, e);
+        });
+    binder::add_service(KEY_LIVENESS_SERVICE_NAME, key_liveness_service.as_binder())
+        .unwrap_or_else(|e| {
+            panic!("Failed to register service {} because of {:?}.", KEY_LIVENESS_SERVICE_NAME, e);
+        });
+
+    let async_service = KeystoreAsyncService::new_native_binder(ks_service).unwrap_or_else(|e| {
+        panic!("Failed to create service {} because of {:?}.", ASYNC_SERVICE_NAME, e);
+    });
+    binder::add_service(ASYNC_SERVICE_NAME, async_service.as_binder()).unwrap_or_else(|e| {
+        panic!("Failed to register service {} because of {:?}.", ASYNC_SERVICE_NAME, e);
+    });
+
     let apc_service =
         ApcManager::new_native_binder(confirmation_token_sender).unwrap_or_else(|e| {
             panic!("Failed to create service {} because of {:?}.", APC_SERVICE_NAME, e);
@@ -151,7 +193,10 @@ fn main() {
         },
     );
 
+    test_hooks::register_service();
+
     info!("Successfully registered Keystore 2.0 service.");
+    BOOT_PROFILE.record("service_registration", BOOT_PROFILE.created());
 
     info!("Joining thread pool now.");
     binder::ProcessState::join_thread_pool();
@@ -400,6 +400,29 @@ convertSharedSecretParametersToLegacy(const std::vector<SharedSecretParameters>&
     return ssps;
 }
 
+// Keymaster 4 and the software KeyMint only implement the algorithms below. Key parameters
+// requesting any other algorithm, e.g., one of the post-quantum algorithms added in later KeyMint
+// versions, cannot be converted to legacy parameters and are rejected up front.
+static bool hasUnsupportedAlgorithm(const std::vector<KeyParameter>& keyParams) {
+    for (const auto& keyParam : keyParams) {
+        if (keyParam.tag != Tag::ALGORITHM ||
+            keyParam.value.getTag() != KeyParameterValue::Tag::algorithm) {
+            continue;
+        }
+        switch (keyParam.value.get<KeyParameterValue::Tag::algorithm>()) {
+        case Algorithm::RSA:
+        case Algorithm::EC:
+        case Algorithm::AES:
+        case Algorithm::TRIPLE_DES:
+        case Algorithm::HMAC:
+            break;
+        default:
+            return true;
+        }
+    }
+    return false;
+}
+
 void OperationSlotManager::setNumFreeSlots(uint8_t numFreeSlots) {
     std::lock_guard<std::mutex> lock(mNumFreeSlotsMutex);
     mNumFreeSlots = numFreeSlots;
@@ -461,6 +484,10 @@ ScopedAStatus KeyMintDevice::addRngEntropy(const std::vector<uint8_t>& in_data)
 ScopedAStatus KeyMintDevice::generateKey(const std::vector<KeyParameter>& inKeyParams,
                                          const std::optional<AttestationKey>& in_attestationKey,
                                          KeyCreationResult* out_creationResult) {
+    if (hasUnsupportedAlgorithm(inKeyParams)) {
+        LOG(ERROR) << __func__ << ": Algorithm not supported by Keymaster 4.";
+        return convertErrorCode(KMV1::ErrorCode::UNSUPPORTED_ALGORITHM);
+    }
 
     // Since KeyMaster doesn't support ECDH, route all key creation requests to
     // soft-KeyMint if and only an ECDH key is requested.
@@ -531,6 +558,10 @@ ScopedAStatus KeyMintDevice::importKey(const std::vector<KeyParameter>& inKeyPar
                                        const std::vector<uint8_t>& in_inKeyData,
                                        const std::optional<AttestationKey>& in_attestationKey,
                                        KeyCreationResult* out_creationResult) {
+    if (hasUnsupportedAlgorithm(inKeyParams)) {
+        LOG(ERROR) << __func__ << ": Algorithm not supported by Keymaster 4.";
+        return convertErrorCode(KMV1::ErrorCode::UNSUPPORTED_ALGORITHM);
+    }
     // Since KeyMaster doesn't support ECDH, route all ECDH key import requests to
     // soft-KeyMint.
     //
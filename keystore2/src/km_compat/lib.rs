@@ -172,6 +172,29 @@ mod tests {
         assert_eq!(creation_result.certificateChain.len(), 0);
     }
 
+    #[test]
+    fn test_unsupported_algorithm() {
+        let legacy = get_device_or_skip_test!();
+        // An algorithm value that Keymaster 4 does not know, standing in for the algorithms added
+        // in later KeyMint versions.
+        let kps = [KeyParameter {
+            tag: Tag::ALGORITHM,
+            value: KeyParameterValue::Algorithm(Algorithm(1000)),
+        }];
+        let result = legacy.generateKey(&kps, None /* attest_key */);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().service_specific_error(),
+            ErrorCode::UNSUPPORTED_ALGORITHM.0
+        );
+        let result = legacy.importKey(&kps, KeyFormat::PKCS8, &[0; 16], None /* attest_key */);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().service_specific_error(),
+            ErrorCode::UNSUPPORTED_ALGORITHM.0
+        );
+    }
+
     #[test]
     fn test_import_wrapped_key() {
         let legacy = get_device_or_skip_test!();
@@ -14,6 +14,7 @@
 
 //! This module acts as a bridge between the legacy key database and the keystore2 database.
 
+use crate::boot_profile::BOOT_PROFILE;
 use crate::database::{
     BlobInfo, BlobMetaData, BlobMetaEntry, CertificateInfo, DateTime, EncryptedBy, KeyMetaData,
     KeyMetaEntry, KeyType, KeystoreDB, Uuid, KEYSTORE_UUID,
@@ -39,6 +40,7 @@ use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// Represents LegacyImporter.
 pub struct LegacyImporter {
@@ -154,11 +156,14 @@ impl LegacyImporter {
                     let mut initializer = self.initializer.lock().unwrap();
 
                     if let Some(initializer) = initializer.take() {
+                        let started = Instant::now();
                         let (db, sec_level_to_km_uuid, legacy_loader) = (initializer)();
 
-                        if legacy_loader.is_empty().context(
+                        let is_empty = legacy_loader.is_empty().context(
                             "In check_state: Trying to check if the legacy database is empty.",
-                        )? {
+                        )?;
+                        BOOT_PROFILE.record("legacy_import_check", started);
+                        if is_empty {
                             self.state.store(Self::STATE_EMPTY, Ordering::Relaxed);
                             return Ok(Self::STATE_EMPTY);
                         }
@@ -16,9 +16,11 @@
 #![recursion_limit = "256"]
 
 pub mod apc;
+pub mod async_service;
 pub mod async_task;
 pub mod authorization;
 pub mod boot_level_keys;
+pub mod boot_profile;
 pub mod database;
 pub mod ec_crypto;
 pub mod enforcements;
@@ -26,28 +28,61 @@ pub mod entropy;
 pub mod error;
 pub mod globals;
 pub mod id_rotation;
+pub mod integrity_check;
+pub mod key_descriptor_validation;
+pub mod key_liveness;
 /// Internal Representation of Key Parameter and convenience functions.
 pub mod key_parameter;
+pub mod key_rotation;
 pub mod legacy_blob;
 pub mod legacy_importer;
+pub mod log_levels;
 pub mod maintenance;
 pub mod metrics;
+pub mod metrics_exporter;
 pub mod metrics_store;
 pub mod operation;
+pub mod perboot_scrubber;
 pub mod permission;
 pub mod raw_device;
 pub mod remote_provisioning;
 pub mod security_level;
 pub mod service;
+pub mod session_keys;
 pub mod shared_secret_negotiation;
+pub mod test_hooks;
+pub mod thermal;
 pub mod utils;
 
 mod attestation_key_utils;
 mod audit_log;
+mod backup_vault;
+mod calling_identity;
+mod cert_chain_limits;
+mod cpu_accounting;
+mod deferred_security_level;
+mod esim_profiles;
+mod events;
 mod gc;
+mod import_formats;
+mod import_limits;
+mod isolated_callers;
+mod key_diagnostics;
+mod key_entry_cache;
+mod key_strength;
+mod key_templates;
+mod key_usage;
+mod key_visibility;
 mod km_compat;
+mod namespace_quotas;
+mod provisioning_info;
+mod public_key_export;
+mod slo_monitor;
+mod storage_tier;
 mod super_key;
 mod sw_keyblob;
+mod validity_policy;
 mod watchdog_helper;
+mod worker_pool;
 
 use message_macro::source_location_msg as ks_err;
@@ -0,0 +1,193 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements per-module log levels, so that the verbosity of a single subsystem
+//! can be raised for debugging in the field without flooding logcat with all of keystore2.
+//! The levels are configured with the system property `keystore.log_levels`, which holds a
+//! comma separated list of `<module>=<level>` entries, e.g., `gc=verbose,database=warn`. The
+//! property is watched, so changes take effect immediately.
+//!
+//! The modules are `database`, `enforcements`, `gc`, and `rkp`. The levels are `off`, `error`,
+//! `warn`, `info`, `debug`, and `verbose`. Modules that are not listed log at the default level
+//! `debug`, as does all code outside of these modules.
+
+use crate::ks_err;
+use anyhow::{Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use rustutils::system_properties::PropertyWatcher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::LazyLock;
+
+/// The property holding the per-module log levels.
+const LOG_LEVELS_PROPERTY: &str = "keystore.log_levels";
+
+/// The level of modules without a configured level and of code outside of the modules.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// The configurable modules and the log targets, i.e., module paths, that belong to them.
+const MODULES: &[(&str, &[&str])] = &[
+    ("database", &["keystore2::database"]),
+    ("enforcements", &["keystore2::enforcements"]),
+    ("gc", &["keystore2::gc"]),
+    ("rkp", &["keystore2::remote_provisioning", "rkpd_client"]),
+];
+
+/// The level filters indexed by their numeric value.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// The log levels of this keystore2 process.
+pub static MODULE_LOG_LEVELS: LazyLock<ModuleLogLevels> = LazyLock::new(ModuleLogLevels::new);
+
+/// Holds the current log level of every configurable module.
+#[derive(Debug)]
+pub struct ModuleLogLevels {
+    levels: [AtomicUsize; MODULES.len()],
+}
+
+impl ModuleLogLevels {
+    fn new() -> Self {
+        Self { levels: std::array::from_fn(|_| AtomicUsize::new(DEFAULT_LEVEL as usize)) }
+    }
+
+    /// Returns the level of the module that `target` belongs to.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        MODULES
+            .iter()
+            .position(|(_, prefixes)| {
+                prefixes.iter().any(|prefix| {
+                    target
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+                })
+            })
+            .map_or(DEFAULT_LEVEL, |i| LEVELS[self.levels[i].load(Ordering::Relaxed)])
+    }
+
+    /// Applies the configuration `spec`. Modules not listed in `spec` are reset to the default
+    /// level. Invalid entries are logged and ignored.
+    pub fn apply(&self, spec: &str) {
+        let mut levels = [DEFAULT_LEVEL; MODULES.len()];
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(module, level)| {
+                let module = MODULES.iter().position(|(name, _)| *name == module.trim())?;
+                Some((module, parse_level(level.trim())?))
+            });
+            match parsed {
+                Some((module, level)) => levels[module] = level,
+                None => log::error!("Invalid entry {entry:?} in {LOG_LEVELS_PROPERTY}."),
+            }
+        }
+        for ((level, (name, _)), new_level) in self.levels.iter().zip(MODULES).zip(levels) {
+            if level.swap(new_level as usize, Ordering::Relaxed) != new_level as usize {
+                log::info!("Log level of {name} is now {new_level}.");
+            }
+        }
+    }
+
+    /// Starts a thread that applies the configuration whenever the property changes.
+    pub fn watch(&'static self) {
+        std::thread::spawn(move || {
+            if let Err(e) = self.watch_property() {
+                log::error!("Failed to watch {LOG_LEVELS_PROPERTY}: {e:?}");
+            }
+        });
+    }
+
+    /// Blocks waiting for property changes, so must be run in its own thread.
+    fn watch_property(&self) -> Result<()> {
+        let mut w = PropertyWatcher::new(LOG_LEVELS_PROPERTY)
+            .context(ks_err!("PropertyWatcher::new failed"))?;
+        loop {
+            match w.read(|_n, v| Ok(v.to_string())) {
+                Ok(spec) => self.apply(&spec),
+                // The property does not exist until it is set for the first time.
+                Err(_) => self.apply(""),
+            }
+            w.wait(None).context(ks_err!("wait for property failed"))?;
+        }
+    }
+}
+
+fn parse_level(level: &str) -> Option<LevelFilter> {
+    match level {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "verbose" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// A logger that drops records below the level of the module that logged them and forwards
+/// all other records to `inner`. `inner` must accept records of all levels, and the maximum
+/// level of the `log` crate must be `LevelFilter::Trace`.
+pub struct ModuleLogFilter<L> {
+    inner: L,
+}
+
+impl<L: Log> ModuleLogFilter<L> {
+    /// Creates a new filter in front of `inner`.
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+impl<L: Log> Log for ModuleLogFilter<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= MODULE_LOG_LEVELS.level_for(metadata.target())
+            && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_for() {
+        let levels = ModuleLogLevels::new();
+        assert_eq!(levels.level_for("keystore2::gc"), DEFAULT_LEVEL);
+
+        levels.apply("gc=verbose, rkp = warn,database=bogus,unknown=info,enforcements");
+        assert_eq!(levels.level_for("keystore2::gc"), LevelFilter::Trace);
+        assert_eq!(levels.level_for("keystore2::remote_provisioning"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("rkpd_client"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("keystore2::database::versioning"), DEFAULT_LEVEL);
+        assert_eq!(levels.level_for("keystore2::gcx"), DEFAULT_LEVEL);
+        assert_eq!(levels.level_for("keystore2::service"), DEFAULT_LEVEL);
+
+        levels.apply("database=off");
+        assert_eq!(levels.level_for("keystore2::database::versioning"), LevelFilter::Off);
+        assert_eq!(levels.level_for("keystore2::gc"), DEFAULT_LEVEL);
+    }
+}
@@ -14,32 +14,60 @@
 
 //! This module implements IKeystoreMaintenance AIDL interface.
 
+use crate::audit_log::{KeyUseAuditConfig, KEY_USE_AUDIT};
+use crate::backup_vault;
+use crate::boot_profile::BOOT_PROFILE;
+use crate::calling_identity;
 use crate::database::{KeyEntryLoadBits, KeyType};
 use crate::error::into_logged_binder;
 use crate::error::map_km_error;
 use crate::error::Error;
+use crate::esim_profiles::ESIM_PROFILES;
+use crate::events::EVENTS;
 use crate::globals::get_keymint_device;
-use crate::globals::{DB, LEGACY_IMPORTER, SUPER_KEY};
+use crate::globals::{notify_gc, run_gc_now, DB, KEY_ENTRY_CACHE, LEGACY_IMPORTER, SUPER_KEY};
+use crate::key_diagnostics;
+use crate::key_strength;
+use crate::key_templates::KEY_TEMPLATES;
+use crate::key_usage::KEY_USAGE;
+use crate::key_visibility;
 use crate::ks_err;
+use crate::operation::list_operations;
 use crate::permission::{KeyPerm, KeystorePerm};
+use crate::provisioning_info;
 use crate::super_key::SuperKeyManager;
+use crate::thermal::THERMAL_THROTTLING;
 use crate::utils::{
-    check_dump_permission, check_get_app_uids_affected_by_sid_permissions, check_key_permission,
+    check_device_attestation_permissions, check_dump_permission,
+    check_get_app_uids_affected_by_sid_permissions, check_key_permission,
     check_keystore_permission, uid_to_android_user, watchdog as wd,
 };
+use crate::validity_policy::KEY_VALIDITY_POLICY;
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    ErrorCode::ErrorCode, IKeyMintDevice::IKeyMintDevice, SecurityLevel::SecurityLevel,
+    ErrorCode::ErrorCode, IKeyMintDevice::IKeyMintDevice, KeyParameter::KeyParameter,
+    SecurityLevel::SecurityLevel,
 };
-use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::{
-    BnKeystoreMaintenance, IKeystoreMaintenance,
+use android_security_maintenance::aidl::android::security::maintenance::{
+    BackupVaultEntry::BackupVaultEntry,
+    GarbageCollectionResult::GarbageCollectionResult,
+    IKeystoreEventListener::IKeystoreEventListener,
+    IKeystoreMaintenance::{BnKeystoreMaintenance, IKeystoreMaintenance},
+    KeyBlobReencryptionResult::KeyBlobReencryptionResult,
+    KeyCharacteristicsDiff::KeyCharacteristicsDiff,
+    KeyVisibility::KeyVisibility,
+    OperationInfo::OperationInfo,
+    ProvisioningInfo::ProvisioningInfo,
+    StaleGrant::StaleGrant,
+    WeakKeyInfo::WeakKeyInfo,
 };
 use android_security_maintenance::binder::{
-    BinderFeatures, Interface, Result as BinderResult, Strong, ThreadState,
+    BinderFeatures, Interface, Result as BinderResult, Strong,
 };
 use android_security_metrics::aidl::android::security::metrics::{
-    KeystoreAtomPayload::KeystoreAtomPayload::StorageStats
+    KeystoreAtomPayload::KeystoreAtomPayload::StorageStats, Storage::Storage as MetricsStorage,
 };
 use android_system_keystore2::aidl::android::system::keystore2::KeyDescriptor::KeyDescriptor;
+use android_system_keystore2::aidl::android::system::keystore2::KeyMetadata::KeyMetadata;
 use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
 use anyhow::{Context, Result};
 use keystore2_crypto::Password;
@@ -47,6 +75,13 @@ use keystore2_crypto::Password;
 /// Reexport Domain for the benefit of DeleteListener
 pub use android_system_keystore2::aidl::android::system::keystore2::Domain::Domain;
 
+/// Upper bound for the number of key blobs disposed of synchronously when storage is
+/// critically low. Any remaining blobs are left to the background garbage collector.
+const LOW_STORAGE_GC_MAX_BLOBS: usize = 500;
+
+/// The number of least recently used keys that the dump lists.
+const MAX_DUMPED_KEY_USAGE: usize = 50;
+
 /// The Maintenance module takes a delete listener argument which observes user and namespace
 /// deletion events.
 pub trait DeleteListener {
@@ -77,14 +112,15 @@ impl Maintenance {
         // is very important.
         check_keystore_permission(KeystorePerm::ChangeUser).context(ks_err!())?;
 
-        DB.with(|db| {
+        let result = DB.with(|db| {
             SUPER_KEY.write().unwrap().remove_user(
                 &mut db.borrow_mut(),
                 &LEGACY_IMPORTER,
                 user_id as u32,
             )
-        })
-        .context(ks_err!("Trying to delete keys from db."))?;
+        });
+        KEY_ENTRY_CACHE.invalidate_all();
+        result.context(ks_err!("Trying to delete keys from db."))?;
         self.delete_listener
             .delete_user(user_id as u32)
             .context(ks_err!("While invoking the delete listener."))
@@ -121,8 +157,31 @@ impl Maintenance {
             .bulk_delete_user(user_id as u32, true)
             .context(ks_err!("Failed to delete legacy keys."))?;
 
-        DB.with(|db| db.borrow_mut().unbind_auth_bound_keys_for_user(user_id as u32))
-            .context(ks_err!("Failed to delete auth-bound keys."))
+        let result = DB.with(|db| db.borrow_mut().unbind_auth_bound_keys_for_user(user_id as u32));
+        KEY_ENTRY_CACHE.invalidate_all();
+        result.context(ks_err!("Failed to delete auth-bound keys."))
+    }
+
+    // Re-binds the user's super keys to a new password, keeping the keys.
+    fn on_user_password_migrated(
+        user_id: i32,
+        old_password: Password,
+        new_password: Password,
+    ) -> Result<()> {
+        // Permission check. Must return on error. Do not touch the '?'.
+        check_keystore_permission(KeystorePerm::ChangePassword).context(ks_err!())?;
+
+        let mut skm = SUPER_KEY.write().unwrap();
+        DB.with(|db| {
+            skm.migrate_user_password(
+                &mut db.borrow_mut(),
+                &LEGACY_IMPORTER,
+                user_id as u32,
+                &old_password,
+                &new_password,
+            )
+        })
+        .context(ks_err!("Failed to migrate the password of the user's super keys."))
     }
 
     fn clear_namespace(&self, domain: Domain, nspace: i64) -> Result<()> {
@@ -132,8 +191,9 @@ impl Maintenance {
         LEGACY_IMPORTER
             .bulk_delete_uid(domain, nspace)
             .context(ks_err!("Trying to delete legacy keys."))?;
-        DB.with(|db| db.borrow_mut().unbind_keys_for_namespace(domain, nspace))
-            .context(ks_err!("Trying to delete keys from db."))?;
+        let result = DB.with(|db| db.borrow_mut().unbind_keys_for_namespace(domain, nspace));
+        KEY_ENTRY_CACHE.invalidate_namespace(domain, nspace);
+        result.context(ks_err!("Trying to delete keys from db."))?;
         self.delete_listener
             .delete_namespace(domain, nspace)
             .context(ks_err!("While invoking the delete listener."))
@@ -201,7 +261,7 @@ impl Maintenance {
     }
 
     fn migrate_key_namespace(source: &KeyDescriptor, destination: &KeyDescriptor) -> Result<()> {
-        let calling_uid = ThreadState::get_calling_uid();
+        let calling_uid = calling_identity::get_calling_uid();
 
         match source.domain {
             Domain::SELINUX | Domain::KEY_ID | Domain::APP => (),
@@ -223,7 +283,7 @@ impl Maintenance {
 
         let super_key = SUPER_KEY.read().unwrap().get_after_first_unlock_key_by_user_id(user_id);
 
-        DB.with(|db| {
+        let result = DB.with(|db| {
             let (key_id_guard, _) = LEGACY_IMPORTER
                 .with_try_import(source, calling_uid, super_key, || {
                     db.borrow_mut().load_key_entry(
@@ -244,7 +304,10 @@ impl Maintenance {
                     check_key_permission(KeyPerm::Rebind, k, &None)
                 })
             }
-        })
+        });
+        // The source may have been looked up by key id, so its namespace is not known here.
+        KEY_ENTRY_CACHE.invalidate_all();
+        result
     }
 
     fn delete_all_keys() -> Result<()> {
@@ -268,6 +331,262 @@ impl Maintenance {
             .context(ks_err!("Failed to get app UIDs affected by SID"))
     }
 
+    fn run_garbage_collection(max_blobs: i32) -> Result<GarbageCollectionResult> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::RunGc).context(ks_err!("Checking permission"))?;
+
+        let max_blobs = usize::try_from(max_blobs)
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("max_blobs must be positive."))?;
+
+        let result = run_gc_now(max_blobs).context(ks_err!("Garbage collection pass failed."))?;
+        log::info!(
+            "Garbage collection pass processed {} blob(s), {} remaining.",
+            result.blobs_processed,
+            result.blobs_remaining
+        );
+        Ok(GarbageCollectionResult {
+            blobsProcessed: result.blobs_processed.try_into().unwrap_or(i32::MAX),
+            blobsRemaining: result.blobs_remaining.try_into().unwrap_or(i32::MAX),
+        })
+    }
+
+    fn on_low_storage(critical: bool) -> Result<i64> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::RunGc).context(ks_err!("Checking permission"))?;
+
+        if !critical {
+            notify_gc();
+            return Ok(0);
+        }
+
+        let db_size = || {
+            DB.with(|db| db.borrow_mut().get_storage_stat(MetricsStorage::DATABASE))
+                .map(|stat| stat.size)
+                .context(ks_err!("Failed to get database size."))
+        };
+        let size_before = db_size()?;
+        let result = run_gc_now(LOW_STORAGE_GC_MAX_BLOBS)
+            .context(ks_err!("Garbage collection pass failed."))?;
+        DB.with(|db| db.borrow_mut().compact()).context(ks_err!("Failed to compact database."))?;
+        let freed = (size_before - db_size()?).max(0);
+        log::info!(
+            "Low storage: processed {} blob(s), {} remaining, freed {} bytes.",
+            result.blobs_processed,
+            result.blobs_remaining,
+            freed
+        );
+        Ok(freed.into())
+    }
+
+    fn reencrypt_key_blobs(user_id: i32, max_blobs: i32) -> Result<KeyBlobReencryptionResult> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ChangeUser)
+            .context(ks_err!("Checking permission"))?;
+
+        let user_id = u32::try_from(user_id)
+            .ok()
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("user_id must not be negative."))?;
+        let max_blobs = usize::try_from(max_blobs)
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("max_blobs must be positive."))?;
+
+        let result = DB
+            .with(|db| {
+                SUPER_KEY.read().unwrap().reencrypt_superseded_blobs(
+                    &mut db.borrow_mut(),
+                    user_id,
+                    max_blobs,
+                )
+            })
+            .context(ks_err!("Re-encryption pass failed."))?;
+        log::info!(
+            "Re-encryption pass for user {} re-encrypted {} blob(s), {} failed, {} remaining.",
+            user_id,
+            result.blobs_reencrypted,
+            result.blobs_failed,
+            result.blobs_remaining
+        );
+        Ok(KeyBlobReencryptionResult {
+            blobsReencrypted: result.blobs_reencrypted.try_into().unwrap_or(i32::MAX),
+            blobsFailed: result.blobs_failed.try_into().unwrap_or(i32::MAX),
+            blobsRemaining: result.blobs_remaining.try_into().unwrap_or(i32::MAX),
+        })
+    }
+
+    fn set_key_use_audit_namespaces(user_ids: &[i32], selinux_namespaces: &[i64]) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ConfigureAudit)
+            .context(ks_err!("Checking permission"))?;
+
+        let user_ids = user_ids
+            .iter()
+            .map(|user_id| u32::try_from(*user_id).ok())
+            .collect::<Option<_>>()
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("User ids must not be negative."))?;
+        KEY_USE_AUDIT.set_config(KeyUseAuditConfig {
+            user_ids,
+            selinux_namespaces: selinux_namespaces.iter().copied().collect(),
+        });
+        Ok(())
+    }
+
+    fn set_max_key_validity(domain: Domain, nspace: i64, max_validity_millis: i64) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ConfigureKeyPolicy)
+            .context(ks_err!("Checking permission"))?;
+
+        if max_validity_millis < 0 {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Maximum validity must not be negative."));
+        }
+        let max_validity_ms = (max_validity_millis != 0).then_some(max_validity_millis);
+        match domain {
+            Domain::APP => {
+                let user_id = u32::try_from(nspace)
+                    .map_err(|_| Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context(ks_err!("Invalid user id {}.", nspace))?;
+                KEY_VALIDITY_POLICY.set_user_max_validity(user_id, max_validity_ms);
+            }
+            Domain::SELINUX => {
+                KEY_VALIDITY_POLICY.set_selinux_max_validity(nspace, max_validity_ms)
+            }
+            _ => {
+                return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context(ks_err!("Unsupported domain {:?}.", domain));
+            }
+        }
+        Ok(())
+    }
+
+    fn register_esim_profile(iccid: &str, domain: Domain, nspace: i64) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ManageEsimProfiles)
+            .context(ks_err!("Checking permission"))?;
+
+        ESIM_PROFILES.register(iccid, domain, nspace)
+    }
+
+    fn unregister_esim_profile(iccid: &str) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ManageEsimProfiles)
+            .context(ks_err!("Checking permission"))?;
+
+        ESIM_PROFILES.unregister(iccid);
+        Ok(())
+    }
+
+    fn set_esim_profile_active(iccid: &str, active: bool) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ManageEsimProfiles)
+            .context(ks_err!("Checking permission"))?;
+
+        ESIM_PROFILES.set_active(iccid, active)
+    }
+
+    fn import_from_backup_vault(
+        recovery_key: &KeyDescriptor,
+        entries: &[BackupVaultEntry],
+    ) -> Result<Vec<KeyMetadata>> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::RestoreBackup)
+            .context(ks_err!("Checking permission"))?;
+
+        backup_vault::import_from_backup_vault(recovery_key, entries)
+    }
+
+    fn register_key_template(name: &str, params: &[KeyParameter]) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ConfigureKeyPolicy)
+            .context(ks_err!("Checking permission"))?;
+
+        KEY_TEMPLATES.register(name, params)
+    }
+
+    fn unregister_key_template(name: &str) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ConfigureKeyPolicy)
+            .context(ks_err!("Checking permission"))?;
+
+        if !KEY_TEMPLATES.unregister(name) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("No template {:?}.", name));
+        }
+        Ok(())
+    }
+
+    fn get_key_diagnostic_bundle(key: &KeyDescriptor) -> Result<Vec<u8>> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        key_diagnostics::get_key_diagnostic_bundle(key)
+    }
+
+    fn get_key_characteristics_diff(key: &KeyDescriptor) -> Result<KeyCharacteristicsDiff> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        key_diagnostics::get_key_characteristics_diff(key)
+    }
+
+    fn get_key_visibility(key: &KeyDescriptor) -> Result<KeyVisibility> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        key_visibility::get_key_visibility(key)
+    }
+
+    fn get_provisioning_info(telephony_ids: &[KeyParameter]) -> Result<Vec<ProvisioningInfo>> {
+        // Security critical permission check. This statement must return on fail.
+        check_device_attestation_permissions().context(ks_err!("Checking permission"))?;
+
+        provisioning_info::get_provisioning_info(telephony_ids)
+    }
+
+    fn list_operations() -> Result<Vec<OperationInfo>> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        Ok(list_operations())
+    }
+
+    fn list_weak_keys() -> Result<Vec<WeakKeyInfo>> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        key_strength::list_weak_keys()
+    }
+
+    fn list_stale_grants(installed_uids: &[i32]) -> Result<Vec<StaleGrant>> {
+        // Security critical permission check. This statement must return on fail.
+        check_dump_permission().context(ks_err!("Checking permission"))?;
+
+        key_visibility::list_stale_grants(installed_uids)
+    }
+
+    fn register_event_listener(listener: &Strong<dyn IKeystoreEventListener>) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ObserveEvents)
+            .context(ks_err!("Checking permission"))?;
+
+        EVENTS.register(listener)
+    }
+
+    fn unregister_event_listener(listener: &Strong<dyn IKeystoreEventListener>) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::ObserveEvents)
+            .context(ks_err!("Checking permission"))?;
+
+        EVENTS.unregister(listener);
+        Ok(())
+    }
+
     fn dump_state(&self, f: &mut dyn std::io::Write) -> std::io::Result<()> {
         writeln!(f, "keystore2 running")?;
         writeln!(f)?;
@@ -315,6 +634,107 @@ impl Maintenance {
         write!(f, "{:?}", *crate::metrics_store::METRICS_STORE)?;
         writeln!(f)?;
 
+        // Display the boot profile.
+        writeln!(f, "Boot profile:")?;
+        for stage in BOOT_PROFILE.stages() {
+            writeln!(
+                f,
+                "  {:<40}: start {:>8} ms, duration {:>8} ms",
+                stage.name,
+                stage.start.as_millis(),
+                stage.duration.as_millis()
+            )?;
+        }
+        writeln!(f)?;
+
+        // Display the in-flight operations.
+        let operations = list_operations();
+        writeln!(f, "Operations ({} in flight):", operations.len())?;
+        for op in operations {
+            writeln!(
+                f,
+                "  uid {:>6} {:?} {:?}: age {:>8} ms, idle {:>8} ms, malus {}{}{}",
+                op.ownerUid,
+                op.securityLevel,
+                op.purpose,
+                op.ageMillis,
+                op.idleMillis,
+                op.pruningMalus,
+                if op.forced { ", forced" } else { "" },
+                match op.pruneExemptMillis {
+                    0 => String::new(),
+                    millis => format!(", prune exempt for {millis} ms"),
+                }
+            )?;
+        }
+        writeln!(f)?;
+
+        // Display the thermal throttling state.
+        let thermal = THERMAL_THROTTLING.status();
+        writeln!(f, "Thermal throttling:")?;
+        writeln!(f, "  Deferral enabled:         {}", thermal.enabled)?;
+        writeln!(f, "  Worst severity:           {:?}", thermal.severity)?;
+        writeln!(f, "  Background work deferred: {}", thermal.deferred)?;
+        writeln!(f, "  Deferred tasks:           {}", thermal.deferred_tasks)?;
+        writeln!(f)?;
+
+        // Display the eSIM profile namespaces. ICCIDs are redacted.
+        let profiles = ESIM_PROFILES.profiles();
+        if !profiles.is_empty() {
+            writeln!(f, "eSIM profiles:")?;
+            for p in profiles {
+                writeln!(
+                    f,
+                    "  ...{} {:?}/{}: {}",
+                    p.iccid_suffix,
+                    p.domain,
+                    p.nspace,
+                    if p.active { "active" } else { "inactive" }
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        // Display retained key use audit records.
+        let records = KEY_USE_AUDIT.records();
+        if !records.is_empty() {
+            writeln!(f, "Key use audit records:")?;
+            for r in records {
+                writeln!(
+                    f,
+                    "  {:>12} uid={} key_id={} purpose={:?} outcome={:?}",
+                    r.time_ms, r.caller_uid, r.key_id, r.purpose, r.outcome
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        // Display the least recently used keys, which are candidates for cleanup.
+        KEY_USAGE.flush();
+        match DB.with(|db| db.borrow_mut().list_least_recently_used_keys(MAX_DUMPED_KEY_USAGE)) {
+            Ok(keys) => {
+                writeln!(f, "Least recently used keys:")?;
+                for (key_id, domain, nspace, stats) in keys {
+                    writeln!(
+                        f,
+                        "  key_id={} {:?}/{}: operations {:>8}, last used {}",
+                        key_id,
+                        domain,
+                        nspace,
+                        stats.operation_count,
+                        match stats.last_used {
+                            Some(date) => format!("{} ms", date.to_millis_epoch()),
+                            None => "never".to_string(),
+                        }
+                    )?;
+                }
+            }
+            Err(e) => {
+                writeln!(f, "Failed to retrieve key usage: {e:?}")?;
+            }
+        }
+        writeln!(f)?;
+
         // Reminder: any additional information added to the `dump_state()` output needs to be
         // careful not to include confidential information (e.g. key material).
 
@@ -377,6 +797,18 @@ impl IKeystoreMaintenance for Maintenance {
         Self::on_user_lskf_removed(user_id).map_err(into_logged_binder)
     }
 
+    fn onUserPasswordMigrated(
+        &self,
+        user_id: i32,
+        old_password: &[u8],
+        new_password: &[u8],
+    ) -> BinderResult<()> {
+        log::info!("onUserPasswordMigrated(user={user_id})");
+        let _wp = wd::watch("IKeystoreMaintenance::onUserPasswordMigrated");
+        Self::on_user_password_migrated(user_id, old_password.into(), new_password.into())
+            .map_err(into_logged_binder)
+    }
+
     fn clearNamespace(&self, domain: Domain, nspace: i64) -> BinderResult<()> {
         log::info!("clearNamespace({domain:?}, nspace={nspace})");
         let _wp = wd::watch("IKeystoreMaintenance::clearNamespace");
@@ -414,4 +846,163 @@ impl IKeystoreMaintenance for Maintenance {
         let _wp = wd::watch("IKeystoreMaintenance::getAppUidsAffectedBySid");
         Self::get_app_uids_affected_by_sid(user_id, secure_user_id).map_err(into_logged_binder)
     }
+
+    fn runGarbageCollection(&self, max_blobs: i32) -> BinderResult<GarbageCollectionResult> {
+        log::info!("runGarbageCollection(max_blobs={max_blobs})");
+        let _wp = wd::watch("IKeystoreMaintenance::runGarbageCollection");
+        Self::run_garbage_collection(max_blobs).map_err(into_logged_binder)
+    }
+
+    fn onLowStorage(&self, critical: bool) -> BinderResult<i64> {
+        log::info!("onLowStorage(critical={critical})");
+        let _wp = wd::watch("IKeystoreMaintenance::onLowStorage");
+        Self::on_low_storage(critical).map_err(into_logged_binder)
+    }
+
+    fn reencryptKeyBlobs(
+        &self,
+        user_id: i32,
+        max_blobs: i32,
+    ) -> BinderResult<KeyBlobReencryptionResult> {
+        log::info!("reencryptKeyBlobs(user_id={user_id}, max_blobs={max_blobs})");
+        let _wp = wd::watch("IKeystoreMaintenance::reencryptKeyBlobs");
+        Self::reencrypt_key_blobs(user_id, max_blobs).map_err(into_logged_binder)
+    }
+
+    fn setKeyUseAuditNamespaces(
+        &self,
+        user_ids: &[i32],
+        selinux_namespaces: &[i64],
+    ) -> BinderResult<()> {
+        log::info!(
+            "setKeyUseAuditNamespaces(users={user_ids:?}, namespaces={selinux_namespaces:?})"
+        );
+        let _wp = wd::watch("IKeystoreMaintenance::setKeyUseAuditNamespaces");
+        Self::set_key_use_audit_namespaces(user_ids, selinux_namespaces).map_err(into_logged_binder)
+    }
+
+    fn setMaxKeyValidity(
+        &self,
+        domain: Domain,
+        nspace: i64,
+        max_validity_millis: i64,
+    ) -> BinderResult<()> {
+        log::info!("setMaxKeyValidity({domain:?}, nspace={nspace}, millis={max_validity_millis})");
+        let _wp = wd::watch("IKeystoreMaintenance::setMaxKeyValidity");
+        Self::set_max_key_validity(domain, nspace, max_validity_millis).map_err(into_logged_binder)
+    }
+
+    fn getKeyDiagnosticBundle(&self, key: &KeyDescriptor) -> BinderResult<Vec<u8>> {
+        log::info!("getKeyDiagnosticBundle(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyDiagnosticBundle");
+        Self::get_key_diagnostic_bundle(key).map_err(into_logged_binder)
+    }
+
+    fn getKeyVisibility(&self, key: &KeyDescriptor) -> BinderResult<KeyVisibility> {
+        log::info!("getKeyVisibility(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyVisibility");
+        Self::get_key_visibility(key).map_err(into_logged_binder)
+    }
+
+    fn listOperations(&self) -> BinderResult<Vec<OperationInfo>> {
+        log::info!("listOperations()");
+        let _wp = wd::watch("IKeystoreMaintenance::listOperations");
+        Self::list_operations().map_err(into_logged_binder)
+    }
+
+    fn listWeakKeys(&self) -> BinderResult<Vec<WeakKeyInfo>> {
+        log::info!("listWeakKeys()");
+        let _wp = wd::watch("IKeystoreMaintenance::listWeakKeys");
+        Self::list_weak_keys().map_err(into_logged_binder)
+    }
+
+    fn getProvisioningInfo(
+        &self,
+        telephony_ids: &[KeyParameter],
+    ) -> BinderResult<Vec<ProvisioningInfo>> {
+        // Do not log the telephony IDs.
+        log::info!("getProvisioningInfo(telephony_ids.len()={})", telephony_ids.len());
+        let _wp = wd::watch_millis("IKeystoreMaintenance::getProvisioningInfo", 10000);
+        Self::get_provisioning_info(telephony_ids).map_err(into_logged_binder)
+    }
+
+    fn registerEventListener(
+        &self,
+        listener: &Strong<dyn IKeystoreEventListener>,
+    ) -> BinderResult<()> {
+        log::info!("registerEventListener()");
+        let _wp = wd::watch("IKeystoreMaintenance::registerEventListener");
+        Self::register_event_listener(listener).map_err(into_logged_binder)
+    }
+
+    fn unregisterEventListener(
+        &self,
+        listener: &Strong<dyn IKeystoreEventListener>,
+    ) -> BinderResult<()> {
+        log::info!("unregisterEventListener()");
+        let _wp = wd::watch("IKeystoreMaintenance::unregisterEventListener");
+        Self::unregister_event_listener(listener).map_err(into_logged_binder)
+    }
+
+    fn listStaleGrants(&self, installed_uids: &[i32]) -> BinderResult<Vec<StaleGrant>> {
+        log::info!("listStaleGrants(installed_uids.len()={})", installed_uids.len());
+        let _wp = wd::watch("IKeystoreMaintenance::listStaleGrants");
+        Self::list_stale_grants(installed_uids).map_err(into_logged_binder)
+    }
+
+    // Do not log the ICCIDs. The eSIM profile module logs their last digits.
+    fn registerEsimProfile(&self, iccid: &str, domain: Domain, nspace: i64) -> BinderResult<()> {
+        log::info!("registerEsimProfile({domain:?}, nspace={nspace})");
+        let _wp = wd::watch("IKeystoreMaintenance::registerEsimProfile");
+        Self::register_esim_profile(iccid, domain, nspace).map_err(into_logged_binder)
+    }
+
+    fn unregisterEsimProfile(&self, iccid: &str) -> BinderResult<()> {
+        log::info!("unregisterEsimProfile()");
+        let _wp = wd::watch("IKeystoreMaintenance::unregisterEsimProfile");
+        Self::unregister_esim_profile(iccid).map_err(into_logged_binder)
+    }
+
+    fn onEsimProfileActivated(&self, iccid: &str) -> BinderResult<()> {
+        log::info!("onEsimProfileActivated()");
+        let _wp = wd::watch("IKeystoreMaintenance::onEsimProfileActivated");
+        Self::set_esim_profile_active(iccid, true).map_err(into_logged_binder)
+    }
+
+    fn onEsimProfileDeactivated(&self, iccid: &str) -> BinderResult<()> {
+        log::info!("onEsimProfileDeactivated()");
+        let _wp = wd::watch("IKeystoreMaintenance::onEsimProfileDeactivated");
+        Self::set_esim_profile_active(iccid, false).map_err(into_logged_binder)
+    }
+
+    fn importFromBackupVault(
+        &self,
+        recovery_key: &KeyDescriptor,
+        entries: &[BackupVaultEntry],
+    ) -> BinderResult<Vec<KeyMetadata>> {
+        log::info!("importFromBackupVault(entries={})", entries.len());
+        let _wp = wd::watch("IKeystoreMaintenance::importFromBackupVault");
+        Self::import_from_backup_vault(recovery_key, entries).map_err(into_logged_binder)
+    }
+
+    fn registerKeyTemplate(&self, name: &str, params: &[KeyParameter]) -> BinderResult<()> {
+        log::info!("registerKeyTemplate({name:?}, params={})", params.len());
+        let _wp = wd::watch("IKeystoreMaintenance::registerKeyTemplate");
+        Self::register_key_template(name, params).map_err(into_logged_binder)
+    }
+
+    fn unregisterKeyTemplate(&self, name: &str) -> BinderResult<()> {
+        log::info!("unregisterKeyTemplate({name:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::unregisterKeyTemplate");
+        Self::unregister_key_template(name).map_err(into_logged_binder)
+    }
+
+    fn getKeyCharacteristicsDiff(
+        &self,
+        key: &KeyDescriptor,
+    ) -> BinderResult<KeyCharacteristicsDiff> {
+        log::info!("getKeyCharacteristicsDiff(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyCharacteristicsDiff");
+        Self::get_key_characteristics_diff(key).map_err(into_logged_binder)
+    }
 }
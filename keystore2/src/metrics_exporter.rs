@@ -0,0 +1,246 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module decides where keystore's metrics go. Every atom that keystore logs is handed to
+//! the exporter selected by the system property `debug.keystore.metrics_exporter` at startup:
+//!
+//! * `statsd`, the default, aggregates the atoms in the metrics store, from which the statsd
+//!   proxy pulls them.
+//! * `file` appends every atom as one JSON line to `metrics.jsonl` in the database directory,
+//!   so that labs without a statsd pipeline can still collect keystore telemetry, e.g., during
+//!   stress tests. The file is rotated once it grows beyond `MAX_FILE_SIZE`, keeping one
+//!   previous file. It is meant for debugging only and is not aggregated or rate limited.
+//!
+//! Pulled atoms, e.g., storage and CPU time statistics, are computed on demand and are only
+//! available through statsd.
+
+use crate::database::DateTime;
+use crate::globals::DB_PATH;
+use crate::ks_err;
+use crate::metrics_store::{Summary, METRICS_STORE};
+use android_security_metrics::aidl::android::security::metrics::{
+    AtomID::AtomID, KeystoreAtomPayload::KeystoreAtomPayload,
+};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+/// The system property that selects the exporter. It can be set from the shell with
+/// `setprop debug.keystore.metrics_exporter file` and takes effect when keystore restarts.
+const EXPORTER_PROPERTY: &str = "debug.keystore.metrics_exporter";
+
+/// The name of the file of the file exporter in the database directory.
+const FILE_NAME: &str = "metrics.jsonl";
+
+/// The size from which on the file exporter starts a new file, in bytes.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The exporter of this keystore instance.
+pub static METRICS_EXPORTER: LazyLock<Box<dyn MetricsExporter>> = LazyLock::new(new_exporter);
+
+/// A destination of keystore's metrics.
+pub trait MetricsExporter: Send + Sync {
+    /// Exports one occurrence of the atom `atom_id`.
+    fn export(&self, atom_id: AtomID, payload: KeystoreAtomPayload);
+}
+
+/// Exports atoms to statsd by way of the metrics store.
+#[derive(Debug, Default)]
+pub struct StatsdExporter;
+
+impl MetricsExporter for StatsdExporter {
+    fn export(&self, atom_id: AtomID, payload: KeystoreAtomPayload) {
+        METRICS_STORE.insert_atom(atom_id, payload);
+    }
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    size: u64,
+}
+
+/// Appends atoms as JSON lines to a local file.
+#[derive(Debug)]
+pub struct FileExporter {
+    path: PathBuf,
+    max_size: u64,
+    file: Mutex<Option<OpenFile>>,
+}
+
+impl FileExporter {
+    /// Returns an exporter that appends to the file `path`, which is rotated once it grows beyond
+    /// `max_size` bytes.
+    pub fn new(path: &Path, max_size: u64) -> Result<Self> {
+        let file = Self::open(path).context(ks_err!("Failed to open {path:?}."))?;
+        Ok(Self { path: path.to_path_buf(), max_size, file: Mutex::new(Some(file)) })
+    }
+
+    fn open(path: &Path) -> Result<OpenFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(OpenFile { file, size })
+    }
+
+    /// Returns the path of the previous file, which the current file is renamed to on rotation.
+    fn previous_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".1");
+        path.into()
+    }
+
+    /// Renames the current file to the previous file, if there is one, and opens a new current
+    /// file.
+    fn rotate(&self) -> Result<OpenFile> {
+        match std::fs::rename(&self.path, self.previous_path()) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).context(ks_err!("Failed to rename {:?}.", self.path));
+            }
+            _ => {}
+        }
+        Self::open(&self.path).context(ks_err!("Failed to open {:?}.", self.path))
+    }
+
+    fn write_line(&self, line: &str) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let current = match file.take() {
+            Some(current)
+                if current.size == 0 || current.size + line.len() as u64 <= self.max_size =>
+            {
+                current
+            }
+            // If the rotation fails, the atom is dropped and the next one tries again.
+            _ => self.rotate()?,
+        };
+        let current = file.insert(current);
+        current.file.write_all(line.as_bytes()).context(ks_err!("Failed to write."))?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Returns the JSON line of one occurrence of the atom `atom_id` at `time`.
+fn to_json_line(time: DateTime, atom_id: AtomID, payload: &KeystoreAtomPayload) -> String {
+    let value = serde_json::json!({
+        "time_ms": time.to_millis_epoch(),
+        "atom_id": atom_id.0,
+        "atom": atom_id.show().trim_end(),
+        "summary": payload.show(),
+        "payload": format!("{payload:?}"),
+    });
+    format!("{value}\n")
+}
+
+impl MetricsExporter for FileExporter {
+    fn export(&self, atom_id: AtomID, payload: KeystoreAtomPayload) {
+        let time = DateTime::now().unwrap_or(DateTime::from_millis_epoch(0));
+        if let Err(e) = self.write_line(&to_json_line(time, atom_id, &payload)) {
+            log::error!("Failed to export atom {}: {e:?}", atom_id.show().trim_end());
+        }
+    }
+}
+
+/// Returns the exporter selected by `EXPORTER_PROPERTY`. Falls back to statsd if the file
+/// exporter cannot be opened or the property is not understood.
+fn new_exporter() -> Box<dyn MetricsExporter> {
+    match rustutils::system_properties::read(EXPORTER_PROPERTY) {
+        Ok(None) => Box::new(StatsdExporter),
+        Ok(Some(name)) if name == "statsd" => Box::new(StatsdExporter),
+        Ok(Some(name)) if name == "file" => {
+            let path =
+                DB_PATH.read().expect("Could not get the database directory").join(FILE_NAME);
+            match FileExporter::new(&path, MAX_FILE_SIZE) {
+                Ok(exporter) => {
+                    log::info!("Exporting metrics to {path:?}.");
+                    Box::new(exporter)
+                }
+                Err(e) => {
+                    log::error!("Falling back to statsd: {e:?}");
+                    Box::new(StatsdExporter)
+                }
+            }
+        }
+        Ok(Some(name)) => {
+            log::error!("Unknown metrics exporter {name:?}, falling back to statsd.");
+            Box::new(StatsdExporter)
+        }
+        Err(e) => {
+            log::error!("Failed to read {EXPORTER_PROPERTY}: {e:?}");
+            Box::new(StatsdExporter)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_security_metrics::aidl::android::security::metrics::SloBreachStats::SloBreachStats;
+    use keystore2_test_utils::TempDir;
+
+    fn slo_breach(api: &str) -> KeystoreAtomPayload {
+        KeystoreAtomPayload::SloBreachStats(SloBreachStats {
+            api: api.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line = to_json_line(
+            DateTime::from_millis_epoch(1234),
+            AtomID::SLO_BREACH_STATS,
+            &slo_breach("begin"),
+        );
+        assert!(line.ends_with('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["time_ms"], 1234);
+        assert_eq!(value["atom_id"], AtomID::SLO_BREACH_STATS.0);
+        assert_eq!(value["atom"], AtomID::SLO_BREACH_STATS.show().trim_end());
+        assert_eq!(value["summary"], slo_breach("begin").show());
+    }
+
+    #[test]
+    fn test_file_exporter_rotation() {
+        let temp_dir = TempDir::new("metrics_exporter_test").unwrap();
+        let path = temp_dir.path().join(FILE_NAME);
+        let line_len = to_json_line(
+            DateTime::now().unwrap(),
+            AtomID::SLO_BREACH_STATS,
+            &slo_breach("a"),
+        )
+        .len() as u64;
+        // Room for two lines but not for three.
+        let exporter = FileExporter::new(&path, 2 * line_len + line_len / 2).unwrap();
+        let read_lines = |path: &Path| -> Vec<serde_json::Value> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        };
+
+        exporter.export(AtomID::SLO_BREACH_STATS, slo_breach("a"));
+        exporter.export(AtomID::SLO_BREACH_STATS, slo_breach("b"));
+        assert_eq!(read_lines(&path).len(), 2);
+        assert!(!exporter.previous_path().exists());
+
+        exporter.export(AtomID::SLO_BREACH_STATS, slo_breach("c"));
+        let current = read_lines(&path);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["summary"], slo_breach("c").show());
+        assert_eq!(read_lines(&exporter.previous_path()).len(), 2);
+    }
+}
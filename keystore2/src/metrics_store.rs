@@ -16,11 +16,16 @@
 //! 1. Processes the data about keystore events asynchronously, and
 //!    stores them in an in-memory store.
 //! 2. Returns the collected metrics when requested by the statsd proxy.
+//!
+//! The logged atoms reach the store through the statsd exporter of the `metrics_exporter`
+//! module, unless a different exporter is selected.
 
+use crate::cpu_accounting::CPU_ACCOUNTING;
 use crate::error::anyhow_error_to_serialized_error;
-use crate::globals::DB;
-use crate::key_parameter::KeyParameterValue as KsKeyParamValue;
+use crate::globals::DB_READER;
+use crate::key_parameter::{KeyParameter as KsKeyParameter, KeyParameterValue as KsKeyParamValue};
 use crate::ks_err;
+use crate::metrics_exporter::METRICS_EXPORTER;
 use crate::operation::Outcome;
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
@@ -29,23 +34,28 @@ use android_hardware_security_keymint::aidl::android::hardware::security::keymin
     SecurityLevel::SecurityLevel,
 };
 use android_security_metrics::aidl::android::security::metrics::{
-    Algorithm::Algorithm as MetricsAlgorithm, AtomID::AtomID, CrashStats::CrashStats,
-    EcCurve::EcCurve as MetricsEcCurve,
+    Algorithm::Algorithm as MetricsAlgorithm, AtomID::AtomID, CallCpuStats::CallCpuStats,
+    CrashStats::CrashStats, EcCurve::EcCurve as MetricsEcCurve,
     HardwareAuthenticatorType::HardwareAuthenticatorType as MetricsHardwareAuthenticatorType,
+    KeyBlobIntegrityCheckStats::KeyBlobIntegrityCheckStats,
+    KeyBlobReencryptionStats::KeyBlobReencryptionStats,
     KeyCreationWithAuthInfo::KeyCreationWithAuthInfo,
     KeyCreationWithGeneralInfo::KeyCreationWithGeneralInfo,
     KeyCreationWithPurposeAndModesInfo::KeyCreationWithPurposeAndModesInfo,
     KeyOperationWithGeneralInfo::KeyOperationWithGeneralInfo,
+    KeyOperationWithKeyCharacteristicsInfo::KeyOperationWithKeyCharacteristicsInfo,
     KeyOperationWithPurposeAndModesInfo::KeyOperationWithPurposeAndModesInfo,
     KeyOrigin::KeyOrigin as MetricsKeyOrigin, Keystore2AtomWithOverflow::Keystore2AtomWithOverflow,
     KeystoreAtom::KeystoreAtom, KeystoreAtomPayload::KeystoreAtomPayload,
-    Outcome::Outcome as MetricsOutcome, Purpose::Purpose as MetricsPurpose,
-    RkpError::RkpError as MetricsRkpError, RkpErrorStats::RkpErrorStats,
-    SecurityLevel::SecurityLevel as MetricsSecurityLevel, Storage::Storage as MetricsStorage,
+    NamespaceQuotaStats::NamespaceQuotaStats, Outcome::Outcome as MetricsOutcome,
+    Purpose::Purpose as MetricsPurpose, RkpError::RkpError as MetricsRkpError,
+    RkpErrorStats::RkpErrorStats, SecurityLevel::SecurityLevel as MetricsSecurityLevel,
+    SloBreachStats::SloBreachStats, Storage::Storage as MetricsStorage,
 };
 use anyhow::{anyhow, Context, Result};
 use std::collections::HashMap;
 use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
 // Note: Crash events are recorded at keystore restarts, based on the assumption that keystore only
 // gets restarted after a crash, during a boot cycle.
@@ -95,7 +105,7 @@ impl MetricsStore {
     /// limit for a single atom is set to 250. If the number of atom objects created for a
     /// particular atom exceeds this limit, an overflow atom object is created to track the ID of
     /// such atoms.
-    const SINGLE_ATOM_STORE_MAX_SIZE: usize = 250;
+    pub(crate) const SINGLE_ATOM_STORE_MAX_SIZE: usize = 250;
 
     /// Return a vector of atom objects with the given atom ID, if one exists in the metrics_store.
     /// If any atom object does not exist in the metrics_store for the given atom ID, return an
@@ -120,6 +130,11 @@ impl MetricsStore {
             };
         }
 
+        // CPU time accounting keeps its own totals, because they are sums rather than counts.
+        if AtomID::CALL_CPU_STATS == atom_id {
+            return Ok(CPU_ACCOUNTING.pull());
+        }
+
         // It is safe to call unwrap here since the lock can not be poisoned based on its usage
         // in this module and the lock is not acquired in the same thread before.
         let metrics_store_guard = self.metrics_store.lock().unwrap();
@@ -132,7 +147,7 @@ impl MetricsStore {
     }
 
     /// Insert an atom object to the metrics_store indexed by the atom ID.
-    fn insert_atom(&self, atom_id: AtomID, atom: KeystoreAtomPayload) {
+    pub(crate) fn insert_atom(&self, atom_id: AtomID, atom: KeystoreAtomPayload) {
         // It is ok to unwrap here since the mutex cannot be poisoned according to the way it is
         // used in this module. And the lock is not acquired by this thread before.
         let mut metrics_store_guard = self.metrics_store.lock().unwrap();
@@ -159,7 +174,7 @@ impl MetricsStore {
     }
 }
 
-/// Log key creation events to be sent to statsd.
+/// Log key creation events to be sent to the metrics exporter.
 pub fn log_key_creation_event_stats<U>(
     sec_level: SecurityLevel,
     key_params: &[KeyParameter],
@@ -171,10 +186,9 @@ pub fn log_key_creation_event_stats<U>(
         key_creation_with_purpose_and_modes_info,
     ) = process_key_creation_event_stats(sec_level, key_params, result);
 
-    METRICS_STORE
-        .insert_atom(AtomID::KEY_CREATION_WITH_GENERAL_INFO, key_creation_with_general_info);
-    METRICS_STORE.insert_atom(AtomID::KEY_CREATION_WITH_AUTH_INFO, key_creation_with_auth_info);
-    METRICS_STORE.insert_atom(
+    METRICS_EXPORTER.export(AtomID::KEY_CREATION_WITH_GENERAL_INFO, key_creation_with_general_info);
+    METRICS_EXPORTER.export(AtomID::KEY_CREATION_WITH_AUTH_INFO, key_creation_with_auth_info);
+    METRICS_EXPORTER.export(
         AtomID::KEY_CREATION_WITH_PURPOSE_AND_MODES_INFO,
         key_creation_with_purpose_and_modes_info,
     );
@@ -226,14 +240,7 @@ fn process_key_creation_event_stats<U>(
     for key_param in key_params.iter().map(KsKeyParamValue::from) {
         match key_param {
             KsKeyParamValue::Algorithm(a) => {
-                let algorithm = match a {
-                    Algorithm::RSA => MetricsAlgorithm::RSA,
-                    Algorithm::EC => MetricsAlgorithm::EC,
-                    Algorithm::AES => MetricsAlgorithm::AES,
-                    Algorithm::TRIPLE_DES => MetricsAlgorithm::TRIPLE_DES,
-                    Algorithm::HMAC => MetricsAlgorithm::HMAC,
-                    _ => MetricsAlgorithm::ALGORITHM_UNSPECIFIED,
-                };
+                let algorithm = process_algorithm(a);
                 key_creation_with_general_info.algorithm = algorithm;
                 key_creation_with_purpose_and_modes_info.algorithm = algorithm;
             }
@@ -293,14 +300,7 @@ fn process_key_creation_event_stats<U>(
                 );
             }
             KsKeyParamValue::EcCurve(e) => {
-                key_creation_with_general_info.ec_curve = match e {
-                    EcCurve::P_224 => MetricsEcCurve::P_224,
-                    EcCurve::P_256 => MetricsEcCurve::P_256,
-                    EcCurve::P_384 => MetricsEcCurve::P_384,
-                    EcCurve::P_521 => MetricsEcCurve::P_521,
-                    EcCurve::CURVE_25519 => MetricsEcCurve::CURVE_25519,
-                    _ => MetricsEcCurve::EC_CURVE_UNSPECIFIED,
-                }
+                key_creation_with_general_info.ec_curve = process_ec_curve(e);
             }
             KsKeyParamValue::AttestationChallenge(_) => {
                 key_creation_with_general_info.attestation_requested = true;
@@ -322,13 +322,15 @@ fn process_key_creation_event_stats<U>(
     )
 }
 
-/// Log key operation events to be sent to statsd.
+/// Log key operation events to be sent to the metrics exporter.
 pub fn log_key_operation_event_stats(
     sec_level: SecurityLevel,
     key_purpose: KeyPurpose,
     op_params: &[KeyParameter],
+    key_params: &[KsKeyParameter],
     op_outcome: &Outcome,
     key_upgraded: bool,
+    keymint_duration: Duration,
 ) {
     let (key_operation_with_general_info, key_operation_with_purpose_and_modes_info) =
         process_key_operation_event_stats(
@@ -338,12 +340,21 @@ pub fn log_key_operation_event_stats(
             op_outcome,
             key_upgraded,
         );
-    METRICS_STORE
-        .insert_atom(AtomID::KEY_OPERATION_WITH_GENERAL_INFO, key_operation_with_general_info);
-    METRICS_STORE.insert_atom(
+    METRICS_EXPORTER
+        .export(AtomID::KEY_OPERATION_WITH_GENERAL_INFO, key_operation_with_general_info);
+    METRICS_EXPORTER.export(
         AtomID::KEY_OPERATION_WITH_PURPOSE_AND_MODES_INFO,
         key_operation_with_purpose_and_modes_info,
     );
+    METRICS_EXPORTER.export(
+        AtomID::KEY_OPERATION_WITH_KEY_CHARACTERISTICS_INFO,
+        process_key_operation_key_characteristics_stats(
+            sec_level,
+            key_params,
+            op_outcome,
+            keymint_duration,
+        ),
+    );
 }
 
 // Process the statistics related to key operations and return the two atom objects related to key
@@ -384,16 +395,10 @@ fn process_key_operation_event_stats(
         _ => MetricsPurpose::KEY_PURPOSE_UNSPECIFIED,
     };
 
-    key_operation_with_general_info.outcome = match op_outcome {
-        Outcome::Unknown | Outcome::Dropped => MetricsOutcome::DROPPED,
-        Outcome::Success => MetricsOutcome::SUCCESS,
-        Outcome::Abort => MetricsOutcome::ABORT,
-        Outcome::Pruned => MetricsOutcome::PRUNED,
-        Outcome::ErrorCode(e) => {
-            key_operation_with_general_info.error_code = e.0;
-            MetricsOutcome::ERROR
-        }
-    };
+    key_operation_with_general_info.outcome = process_outcome(op_outcome);
+    if let Outcome::ErrorCode(e) = op_outcome {
+        key_operation_with_general_info.error_code = e.0;
+    }
 
     for key_param in op_params.iter().map(KsKeyParamValue::from) {
         match key_param {
@@ -427,6 +432,102 @@ fn process_key_operation_event_stats(
     )
 }
 
+// Process the statistics related to key operations that are dimensioned by the characteristics
+// of the key used, and return the KeyOperationWithKeyCharacteristicsInfo atom object.
+fn process_key_operation_key_characteristics_stats(
+    sec_level: SecurityLevel,
+    key_params: &[KsKeyParameter],
+    op_outcome: &Outcome,
+    keymint_duration: Duration,
+) -> KeystoreAtomPayload {
+    let mut key_operation_with_key_characteristics_info = KeyOperationWithKeyCharacteristicsInfo {
+        algorithm: MetricsAlgorithm::ALGORITHM_UNSPECIFIED,
+        key_size: -1,
+        ec_curve: MetricsEcCurve::EC_CURVE_UNSPECIFIED,
+        security_level: process_security_level(sec_level),
+        outcome: process_outcome(op_outcome),
+        log2_keymint_duration_millis: log2_duration_millis(keymint_duration),
+        // Default for bool is false (for auth_bound field).
+        ..Default::default()
+    };
+
+    for key_param in key_params.iter().map(KsKeyParameter::key_parameter_value) {
+        match key_param {
+            KsKeyParamValue::Algorithm(a) => {
+                key_operation_with_key_characteristics_info.algorithm = process_algorithm(*a);
+            }
+            KsKeyParamValue::KeySize(s) => {
+                key_operation_with_key_characteristics_info.key_size = bucket_key_size(*s);
+            }
+            KsKeyParamValue::EcCurve(e) => {
+                key_operation_with_key_characteristics_info.ec_curve = process_ec_curve(*e);
+            }
+            KsKeyParamValue::UserSecureID(_) => {
+                key_operation_with_key_characteristics_info.auth_bound = true;
+            }
+            _ => {}
+        }
+    }
+    if key_operation_with_key_characteristics_info.algorithm == MetricsAlgorithm::EC {
+        // Do not record key sizes if Algorithm = EC, in order to reduce cardinality.
+        key_operation_with_key_characteristics_info.key_size = -1;
+    }
+
+    KeystoreAtomPayload::KeyOperationWithKeyCharacteristicsInfo(
+        key_operation_with_key_characteristics_info,
+    )
+}
+
+/// Commonly used key sizes. Key sizes are rounded up to the next value in this list before
+/// they are recorded in operation atoms, in order to reduce cardinality.
+const KEY_SIZE_BUCKETS: &[i32] = &[64, 128, 192, 256, 512, 1024, 2048, 3072, 4096, 8192];
+
+fn bucket_key_size(key_size: i32) -> i32 {
+    KEY_SIZE_BUCKETS.iter().find(|&&bucket| key_size <= bucket).copied().unwrap_or(-1)
+}
+
+/// The largest value recorded for the logarithm of an operation duration. Durations of more
+/// than about 35 minutes are recorded as this value.
+const LOG2_DURATION_MILLIS_MAX: i32 = 22;
+
+fn log2_duration_millis(duration: Duration) -> i32 {
+    let millis = duration.as_millis();
+    let bit_length = (u128::BITS - millis.leading_zeros()) as i32;
+    bit_length.min(LOG2_DURATION_MILLIS_MAX)
+}
+
+fn process_outcome(op_outcome: &Outcome) -> MetricsOutcome {
+    match op_outcome {
+        Outcome::Unknown | Outcome::Dropped => MetricsOutcome::DROPPED,
+        Outcome::Success => MetricsOutcome::SUCCESS,
+        Outcome::Abort => MetricsOutcome::ABORT,
+        Outcome::Pruned => MetricsOutcome::PRUNED,
+        Outcome::ErrorCode(_) => MetricsOutcome::ERROR,
+    }
+}
+
+fn process_algorithm(algorithm: Algorithm) -> MetricsAlgorithm {
+    match algorithm {
+        Algorithm::RSA => MetricsAlgorithm::RSA,
+        Algorithm::EC => MetricsAlgorithm::EC,
+        Algorithm::AES => MetricsAlgorithm::AES,
+        Algorithm::TRIPLE_DES => MetricsAlgorithm::TRIPLE_DES,
+        Algorithm::HMAC => MetricsAlgorithm::HMAC,
+        _ => MetricsAlgorithm::ALGORITHM_UNSPECIFIED,
+    }
+}
+
+fn process_ec_curve(ec_curve: EcCurve) -> MetricsEcCurve {
+    match ec_curve {
+        EcCurve::P_224 => MetricsEcCurve::P_224,
+        EcCurve::P_256 => MetricsEcCurve::P_256,
+        EcCurve::P_384 => MetricsEcCurve::P_384,
+        EcCurve::P_521 => MetricsEcCurve::P_521,
+        EcCurve::CURVE_25519 => MetricsEcCurve::CURVE_25519,
+        _ => MetricsEcCurve::EC_CURVE_UNSPECIFIED,
+    }
+}
+
 fn process_security_level(sec_level: SecurityLevel) -> MetricsSecurityLevel {
     match sec_level {
         SecurityLevel::SOFTWARE => MetricsSecurityLevel::SECURITY_LEVEL_SOFTWARE,
@@ -549,7 +650,7 @@ pub(crate) fn pull_storage_stats() -> Result<Vec<KeystoreAtom>> {
             }
         };
     };
-    DB.with(|db| {
+    DB_READER.with(|db| {
         let mut db = db.borrow_mut();
         append(db.get_storage_stat(MetricsStorage::DATABASE));
         append(db.get_storage_stat(MetricsStorage::KEY_ENTRY));
@@ -565,6 +666,7 @@ pub(crate) fn pull_storage_stats() -> Result<Vec<KeystoreAtom>> {
         append(db.get_storage_stat(MetricsStorage::AUTH_TOKEN));
         append(db.get_storage_stat(MetricsStorage::BLOB_METADATA));
         append(db.get_storage_stat(MetricsStorage::BLOB_METADATA_BLOB_ENTRY_ID_INDEX));
+        append(db.get_storage_stat(MetricsStorage::RECLAIMABLE));
     });
     Ok(atom_vec)
 }
@@ -575,7 +677,47 @@ pub fn log_rkp_error_stats(rkp_error: MetricsRkpError, sec_level: &SecurityLevel
         rkpError: rkp_error,
         security_level: process_security_level(*sec_level),
     });
-    METRICS_STORE.insert_atom(AtomID::RKP_ERROR_STATS, rkp_error_stats);
+    METRICS_EXPORTER.export(AtomID::RKP_ERROR_STATS, rkp_error_stats);
+}
+
+/// Log the outcome of re-encrypting a super-encrypted key blob with the current super key.
+pub fn log_key_blob_reencryption_stats(success: bool) {
+    let outcome = if success { MetricsOutcome::SUCCESS } else { MetricsOutcome::ERROR };
+    let key_blob_reencryption_stats =
+        KeystoreAtomPayload::KeyBlobReencryptionStats(KeyBlobReencryptionStats { outcome });
+    METRICS_EXPORTER.export(AtomID::KEY_BLOB_REENCRYPTION_STATS, key_blob_reencryption_stats);
+}
+
+/// Log the outcome of checking a key blob with KeyMint during the idle integrity check.
+pub fn log_key_blob_integrity_check_stats(valid: bool, sec_level: SecurityLevel) {
+    let outcome = if valid { MetricsOutcome::SUCCESS } else { MetricsOutcome::ERROR };
+    let key_blob_integrity_check_stats =
+        KeystoreAtomPayload::KeyBlobIntegrityCheckStats(KeyBlobIntegrityCheckStats {
+            outcome,
+            security_level: process_security_level(sec_level),
+        });
+    METRICS_EXPORTER.export(AtomID::KEY_BLOB_INTEGRITY_CHECK_STATS, key_blob_integrity_check_stats);
+}
+
+/// Log that the p99 latency of the KeyMint call `api` on `sec_level` breached its service level
+/// objective.
+pub fn log_slo_breach_stats(api: &str, sec_level: SecurityLevel) {
+    let slo_breach_stats = KeystoreAtomPayload::SloBreachStats(SloBreachStats {
+        api: api.to_string(),
+        security_level: process_security_level(sec_level),
+    });
+    METRICS_EXPORTER.export(AtomID::SLO_BREACH_STATS, slo_breach_stats);
+}
+
+/// Log that a key was generated or imported into the SELinux namespace `namespace` while it was
+/// at (`rejected`) or close to its `quota`.
+pub fn log_namespace_quota_stats(namespace: i64, quota: &str, rejected: bool) {
+    let namespace_quota_stats = KeystoreAtomPayload::NamespaceQuotaStats(NamespaceQuotaStats {
+        namespace,
+        quota: quota.to_string(),
+        rejected,
+    });
+    METRICS_EXPORTER.export(AtomID::NAMESPACE_QUOTA_STATS, namespace_quota_stats);
 }
 
 /// This function tries to read and update the system property: keystore.crash_count.
@@ -701,7 +843,7 @@ enum KeyPurposeBitPosition {
 /// The various metrics-related types are not defined in this crate, so the orphan
 /// trait rule means that `std::fmt::Debug` cannot be implemented for them.
 /// Instead, create our own local trait that generates a debug string for a type.
-trait Summary {
+pub(crate) trait Summary {
     fn show(&self) -> String;
 }
 
@@ -736,6 +878,12 @@ impl_summary_enum!(AtomID, 14,
     KEY_OPERATION_WITH_GENERAL_INFO => "KEYOP_GENERAL",
     RKP_ERROR_STATS => "RKP_ERR",
     CRASH_STATS => "CRASH",
+    KEY_OPERATION_WITH_KEY_CHARACTERISTICS_INFO => "KEYOP_KEY",
+    KEY_BLOB_REENCRYPTION_STATS => "REENCRYPT",
+    KEY_BLOB_INTEGRITY_CHECK_STATS => "INTEGRITY",
+    CALL_CPU_STATS => "CALL_CPU",
+    SLO_BREACH_STATS => "SLO_BREACH",
+    NAMESPACE_QUOTA_STATS => "NS_QUOTA",
 );
 
 impl_summary_enum!(MetricsStorage, 28,
@@ -963,12 +1111,42 @@ impl Summary for KeystoreAtomPayload {
                     show_blockmode(v.block_mode_bitmap)
                 )
             }
+            KeystoreAtomPayload::KeyOperationWithKeyCharacteristicsInfo(v) => {
+                format!(
+                    "{} ksz={:>4} crv={} auth? {} sec={} {} log2(ms)={:2}",
+                    v.algorithm.show(),
+                    v.key_size,
+                    v.ec_curve.show(),
+                    if v.auth_bound { "Y" } else { "N" },
+                    v.security_level.show(),
+                    v.outcome.show(),
+                    v.log2_keymint_duration_millis
+                )
+            }
             KeystoreAtomPayload::RkpErrorStats(v) => {
                 format!("{} sec={}", v.rkpError.show(), v.security_level.show())
             }
             KeystoreAtomPayload::CrashStats(v) => {
                 format!("count={}", v.count_of_crash_events)
             }
+            KeystoreAtomPayload::KeyBlobReencryptionStats(v) => {
+                format!("outcome={}", v.outcome.show())
+            }
+            KeystoreAtomPayload::KeyBlobIntegrityCheckStats(v) => {
+                format!("outcome={} sec={}", v.outcome.show(), v.security_level.show())
+            }
+            KeystoreAtomPayload::CallCpuStats(v) => {
+                format!(
+                    "{} uid={} calls={} user={}us sys={}us",
+                    v.api, v.uid, v.call_count, v.user_time_micros, v.system_time_micros
+                )
+            }
+            KeystoreAtomPayload::SloBreachStats(v) => {
+                format!("{} sec={}", v.api, v.security_level.show())
+            }
+            KeystoreAtomPayload::NamespaceQuotaStats(v) => {
+                format!("ns={} quota={} rejected={}", v.namespace, v.quota, v.rejected)
+            }
             KeystoreAtomPayload::Keystore2AtomWithOverflow(v) => {
                 format!("atom={}", v.atom_id.show())
             }
@@ -1002,4 +1180,57 @@ mod tests {
         modes |= 0x300;
         assert_eq!(show_blockmode(modes), "-T-E(full:0x000003aa)");
     }
+
+    #[test]
+    fn test_bucket_key_size() {
+        assert_eq!(bucket_key_size(128), 128);
+        assert_eq!(bucket_key_size(168), 192);
+        assert_eq!(bucket_key_size(2048), 2048);
+        assert_eq!(bucket_key_size(2049), 3072);
+        assert_eq!(bucket_key_size(8192), 8192);
+        assert_eq!(bucket_key_size(16384), -1);
+    }
+
+    #[test]
+    fn test_log2_duration_millis() {
+        assert_eq!(log2_duration_millis(Duration::from_micros(999)), 0);
+        assert_eq!(log2_duration_millis(Duration::from_millis(1)), 1);
+        assert_eq!(log2_duration_millis(Duration::from_millis(3)), 2);
+        assert_eq!(log2_duration_millis(Duration::from_millis(4)), 3);
+        assert_eq!(log2_duration_millis(Duration::from_secs(1)), 10);
+        assert_eq!(log2_duration_millis(Duration::from_secs(24 * 60 * 60)), 22);
+    }
+
+    #[test]
+    fn test_key_characteristics_stats() {
+        let key_params = vec![
+            KsKeyParameter::new(
+                KsKeyParamValue::Algorithm(Algorithm::EC),
+                SecurityLevel::STRONGBOX,
+            ),
+            KsKeyParameter::new(KsKeyParamValue::KeySize(521), SecurityLevel::STRONGBOX),
+            KsKeyParameter::new(KsKeyParamValue::EcCurve(EcCurve::P_521), SecurityLevel::STRONGBOX),
+            KsKeyParameter::new(KsKeyParamValue::UserSecureID(42), SecurityLevel::STRONGBOX),
+        ];
+        let atom = process_key_operation_key_characteristics_stats(
+            SecurityLevel::STRONGBOX,
+            &key_params,
+            &Outcome::Success,
+            Duration::from_millis(300),
+        );
+        assert_eq!(
+            atom,
+            KeystoreAtomPayload::KeyOperationWithKeyCharacteristicsInfo(
+                KeyOperationWithKeyCharacteristicsInfo {
+                    algorithm: MetricsAlgorithm::EC,
+                    key_size: -1,
+                    ec_curve: MetricsEcCurve::P_521,
+                    auth_bound: true,
+                    security_level: MetricsSecurityLevel::SECURITY_LEVEL_STRONGBOX,
+                    outcome: MetricsOutcome::SUCCESS,
+                    log2_keymint_duration_millis: 9,
+                }
+            )
+        );
+    }
 }
@@ -0,0 +1,257 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements quotas for SELinux namespaces, which protect the shared database from
+//! a runaway system component. A namespace can be limited in the number of its keys and in the
+//! total size of their blobs. Generating or importing a key into a namespace fails with
+//! `ResponseCode::TOO_MUCH_DATA` if the namespace is at one of its quotas. Replacing a key of
+//! the namespace does not count the replaced key, but the new key may exceed the byte quota by
+//! its own size.
+//!
+//! Quotas are reserved along with the SELinux namespace itself, in the
+//! `keystore2_namespace_quotas` file of the partition that defines the namespace in its
+//! keystore2_key_contexts. Each line of the file assigns quotas to a namespace:
+//!
+//! ```text
+//! # <namespace> [max_keys=<count>] [max_bytes=<bytes>]
+//! 102 max_keys=1000 max_bytes=4194304
+//! ```
+//!
+//! A quota that is not given, or is 0, is not limited. Namespaces that no file mentions have no
+//! quotas. The files are read once, when keystore starts.
+//!
+//! A NAMESPACE_QUOTA_STATS atom is logged when a key is rejected, and when a key is accepted
+//! into a namespace that uses at least `PRESSURE_PERCENT` percent of one of its quotas.
+
+use crate::error::Error;
+use crate::events::EVENTS;
+use crate::globals::DB;
+use crate::ks_err;
+use crate::metrics_store::log_namespace_quota_stats;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_security_maintenance::aidl::android::security::maintenance::KeystoreEventType::KeystoreEventType;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// The quota files, one per partition, like the keystore2_key_contexts files.
+const QUOTA_FILES: &[&str] = &[
+    "/system/etc/selinux/plat_keystore2_namespace_quotas",
+    "/system_ext/etc/selinux/system_ext_keystore2_namespace_quotas",
+    "/product/etc/selinux/product_keystore2_namespace_quotas",
+    "/vendor/etc/selinux/vendor_keystore2_namespace_quotas",
+];
+
+/// The usage of a quota, in percent, from which on accepted keys are logged.
+const PRESSURE_PERCENT: usize = 80;
+
+/// The quotas of the SELinux namespaces.
+static NAMESPACE_QUOTAS: LazyLock<HashMap<i64, NamespaceQuota>> = LazyLock::new(|| {
+    let mut quotas = HashMap::new();
+    for file in QUOTA_FILES {
+        match std::fs::read_to_string(file) {
+            Ok(content) => parse_quotas(file, &content, &mut quotas),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to read {file}: {e:?}"),
+        }
+    }
+    quotas
+});
+
+/// The quotas of a namespace. 0 means no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NamespaceQuota {
+    /// The maximum number of keys.
+    pub max_keys: usize,
+    /// The maximum total size of the key blobs, certificates and certificate chains in bytes.
+    pub max_bytes: usize,
+}
+
+/// The quotas that a namespace can be at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuotaKind {
+    Keys,
+    Bytes,
+}
+
+impl QuotaKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Keys => "keys",
+            Self::Bytes => "bytes",
+        }
+    }
+}
+
+/// The outcome of a quota check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    /// The key is accepted.
+    Accept,
+    /// The key is accepted, but the namespace is close to the quota.
+    Pressure(QuotaKind),
+    /// The key is rejected because the namespace is at the quota.
+    Reject(QuotaKind),
+}
+
+impl NamespaceQuota {
+    /// Checks a new key against the quotas of a namespace with `keys` keys of `bytes` bytes.
+    fn check(&self, keys: usize, bytes: usize) -> Verdict {
+        let usage =
+            [(QuotaKind::Keys, keys, self.max_keys), (QuotaKind::Bytes, bytes, self.max_bytes)];
+        let limited = usage.iter().filter(|(_, _, max)| *max != 0);
+        if let Some((kind, _, _)) = limited.clone().find(|(_, used, max)| used >= max) {
+            return Verdict::Reject(*kind);
+        }
+        match limited
+            .clone()
+            .find(|(_, used, max)| used.saturating_mul(100) >= max.saturating_mul(PRESSURE_PERCENT))
+        {
+            Some((kind, _, _)) => Verdict::Pressure(*kind),
+            None => Verdict::Accept,
+        }
+    }
+}
+
+/// Parses the quota file `file` with the content `content` into `quotas`. Invalid lines are
+/// logged and ignored. The first line for a namespace wins.
+fn parse_quotas(file: &str, content: &str, quotas: &mut HashMap<i64, NamespaceQuota>) {
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_line(line) {
+            Some((nspace, quota)) => {
+                if quotas.contains_key(&nspace) {
+                    log::error!(
+                        "{file}:{}: Ignoring duplicate quotas of namespace {nspace}.",
+                        i + 1
+                    );
+                } else {
+                    quotas.insert(nspace, quota);
+                }
+            }
+            None => log::error!("{file}:{}: Ignoring invalid line {line:?}.", i + 1),
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<(i64, NamespaceQuota)> {
+    let mut fields = line.split_whitespace();
+    let nspace = fields.next()?.parse().ok()?;
+    let mut quota = NamespaceQuota::default();
+    for field in fields {
+        let (name, value) = field.split_once('=')?;
+        let value = value.parse().ok()?;
+        match name {
+            "max_keys" => quota.max_keys = value,
+            "max_bytes" => quota.max_bytes = value,
+            _ => return None,
+        }
+    }
+    Some((nspace, quota))
+}
+
+/// Checks whether a key may be generated or imported into the given namespace with the alias
+/// `alias`. An existing key with the alias is not counted, because the new key replaces it.
+/// Only `Domain::SELINUX` namespaces have quotas.
+pub fn check_namespace_quota(
+    caller_uid: u32,
+    domain: Domain,
+    nspace: i64,
+    alias: Option<&str>,
+) -> Result<()> {
+    if domain != Domain::SELINUX {
+        return Ok(());
+    }
+    let Some(quota) = NAMESPACE_QUOTAS.get(&nspace) else {
+        return Ok(());
+    };
+    let (keys, bytes) = DB
+        .with(|db| db.borrow_mut().get_namespace_usage(domain, nspace, alias))
+        .context(ks_err!())?;
+    match quota.check(keys, bytes) {
+        Verdict::Accept => Ok(()),
+        Verdict::Pressure(kind) => {
+            log_namespace_quota_stats(nspace, kind.name(), false);
+            Ok(())
+        }
+        Verdict::Reject(kind) => {
+            log_namespace_quota_stats(nspace, kind.name(), true);
+            EVENTS.publish(
+                KeystoreEventType::QUOTA_EXCEEDED,
+                caller_uid as i32,
+                SecurityLevel::KEYSTORE,
+            );
+            Err(Error::Rc(ResponseCode::TOO_MUCH_DATA)).context(ks_err!(
+                "Namespace {} is at its quota of {:?} with {} keys of {} bytes.",
+                nspace,
+                quota,
+                keys,
+                bytes
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quotas() {
+        let mut quotas = HashMap::new();
+        parse_quotas(
+            "test",
+            "# Quotas.\n\
+             102 max_keys=10 max_bytes=4096\n\
+             \n\
+             103 max_bytes=100 # Only bytes.\n\
+             104\n\
+             102 max_keys=20\n\
+             105 max_keys=abc\n\
+             106 max_certs=1\n\
+             x max_keys=1\n",
+            &mut quotas,
+        );
+        assert_eq!(
+            quotas,
+            HashMap::from([
+                (102, NamespaceQuota { max_keys: 10, max_bytes: 4096 }),
+                (103, NamespaceQuota { max_keys: 0, max_bytes: 100 }),
+                (104, NamespaceQuota::default()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let quota = NamespaceQuota { max_keys: 10, max_bytes: 1000 };
+        assert_eq!(quota.check(0, 0), Verdict::Accept);
+        assert_eq!(quota.check(7, 799), Verdict::Accept);
+        assert_eq!(quota.check(8, 0), Verdict::Pressure(QuotaKind::Keys));
+        assert_eq!(quota.check(0, 800), Verdict::Pressure(QuotaKind::Bytes));
+        assert_eq!(quota.check(10, 0), Verdict::Reject(QuotaKind::Keys));
+        assert_eq!(quota.check(9, 1000), Verdict::Reject(QuotaKind::Bytes));
+
+        let unlimited = NamespaceQuota::default();
+        assert_eq!(unlimited.check(usize::MAX, usize::MAX), Verdict::Accept);
+        let only_keys = NamespaceQuota { max_keys: 1, max_bytes: 0 };
+        assert_eq!(only_keys.check(0, usize::MAX), Verdict::Accept);
+    }
+}
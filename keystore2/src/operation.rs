@@ -60,6 +60,11 @@
 //! exits. This should be the cue for the client to destroy its binder.
 //! At that point the operation gets dropped.
 //!
+//! Privileged callers can exempt an operation from pruning for a few seconds, e.g., for a
+//! payment transaction, where a pruned operation means a failed tap. Exemptions expire
+//! automatically and only few of them are granted at a time, see
+//! `OperationDb::grant_prune_exemption`.
+//!
 //! ## Architecture
 //! The `IKeystoreOperation` trait is implemented by `KeystoreOperation`.
 //! This acts as a proxy object holding a strong reference to actual operation
@@ -125,27 +130,43 @@
 //! the operation is either being touched, which changes its pruning resistance,
 //! or it transitions to its end-of-life, which means we may get a free slot.
 //! Either way, we have to revaluate the pruning scores.
+//!
+//! ## Operation Budget
+//! Callers can query their `OperationBudget` to schedule batch work for times when the
+//! backend is not saturated. The number of operation slots of a KeyMint backend is not
+//! discoverable, so the operation database estimates it from the number of running
+//! operations observed when the backend last reported `ErrorCode::TOO_MANY_OPERATIONS`.
+
+mod pruning;
 
+use crate::audit_log::{log_key_use, AuditedKey};
+use crate::cpu_accounting;
 use crate::enforcements::AuthInfo;
 use crate::error::{
     error_to_serialized_error, into_binder, into_logged_binder, map_km_error, Error, ErrorCode,
     ResponseCode, SerializedError,
 };
+use crate::key_parameter::KeyParameter as KsKeyParameter;
 use crate::ks_err;
 use crate::metrics_store::log_key_operation_event_stats;
+use crate::slo_monitor::{self, SloApi};
+use crate::test_hooks;
 use crate::utils::watchdog as wd;
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     IKeyMintOperation::IKeyMintOperation, KeyParameter::KeyParameter, KeyPurpose::KeyPurpose,
     SecurityLevel::SecurityLevel,
 };
 use android_hardware_security_keymint::binder::{BinderFeatures, Strong};
+use android_security_maintenance::aidl::android::security::maintenance::OperationInfo::OperationInfo;
 use android_system_keystore2::aidl::android::system::keystore2::{
     IKeystoreOperation::BnKeystoreOperation, IKeystoreOperation::IKeystoreOperation,
 };
 use anyhow::{anyhow, Context, Result};
+use pruning::{Candidate, PruningInfo};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, MutexGuard, Weak},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    sync::{Arc, LazyLock, Mutex, MutexGuard, Weak},
     time::Duration,
     time::Instant,
 };
@@ -176,12 +197,16 @@ pub struct Operation {
     // The index of this operation in the OperationDb.
     index: usize,
     km_op: Strong<dyn IKeyMintOperation>,
+    created: Instant,
     last_usage: Mutex<Instant>,
     outcome: Mutex<Outcome>,
     owner: u32, // Uid of the operation's owner.
     auth_info: Mutex<AuthInfo>,
     forced: bool,
+    prune_exemption: Option<PruneExemption>,
     logging_info: LoggingInfo,
+    // Accumulated time spent in calls to the KeyMint operation.
+    keymint_duration: Mutex<Duration>,
 }
 
 /// Keeps track of the information required for logging operations.
@@ -190,7 +215,9 @@ pub struct LoggingInfo {
     sec_level: SecurityLevel,
     purpose: KeyPurpose,
     op_params: Vec<KeyParameter>,
+    key_params: Vec<KsKeyParameter>,
     key_upgraded: bool,
+    audited_key: Option<AuditedKey>,
 }
 
 impl LoggingInfo {
@@ -199,17 +226,17 @@ impl LoggingInfo {
         sec_level: SecurityLevel,
         purpose: KeyPurpose,
         op_params: Vec<KeyParameter>,
+        key_params: Vec<KsKeyParameter>,
         key_upgraded: bool,
     ) -> LoggingInfo {
-        Self { sec_level, purpose, op_params, key_upgraded }
+        Self { sec_level, purpose, op_params, key_params, key_upgraded, audited_key: None }
     }
-}
 
-struct PruningInfo {
-    last_usage: Instant,
-    owner: u32,
-    index: usize,
-    forced: bool,
+    /// Marks the operation's key as audited, so that the outcome of the operation is recorded
+    /// in the audit log.
+    pub fn with_audited_key(self, audited_key: Option<AuditedKey>) -> Self {
+        Self { audited_key, ..self }
+    }
 }
 
 // We don't except more than 32KiB of data in `update`, `updateAad`, and `finish`.
@@ -223,17 +250,21 @@ impl Operation {
         owner: u32,
         auth_info: AuthInfo,
         forced: bool,
+        prune_exemption: Option<PruneExemption>,
         logging_info: LoggingInfo,
     ) -> Self {
         Self {
             index,
             km_op,
+            created: Instant::now(),
             last_usage: Mutex::new(Instant::now()),
             outcome: Mutex::new(Outcome::Unknown),
             owner,
             auth_info: Mutex::new(auth_info),
             forced,
+            prune_exemption,
             logging_info,
+            keymint_duration: Mutex::new(Duration::ZERO),
         }
     }
 
@@ -260,6 +291,33 @@ impl Operation {
             owner: self.owner,
             index: self.index,
             forced: self.forced,
+            prune_exempt: self.prune_exemption.as_ref().is_some_and(|e| e.remaining().is_some()),
+        })
+    }
+
+    /// Returns redacted information about the operation at time `now`, or None if it was
+    /// finalized. `siblings` is the number of running operations of the owner, including this
+    /// one.
+    fn info(&self, siblings: usize, now: Instant) -> Option<OperationInfo> {
+        let p_info = self.get_pruning_info()?;
+        let idle = now.saturating_duration_since(p_info.last_usage);
+        let millis = |d: Duration| i64::try_from(d.as_millis()).unwrap_or(i64::MAX);
+        Some(OperationInfo {
+            ownerUid: self.owner as i32,
+            securityLevel: self.logging_info.sec_level,
+            purpose: self.logging_info.purpose,
+            ageMillis: millis(now.saturating_duration_since(self.created)),
+            idleMillis: millis(idle),
+            forced: self.forced,
+            pruningMalus: match self.forced || p_info.prune_exempt {
+                true => 0,
+                false => i64::try_from(pruning::malus(siblings as u64, idle)).unwrap_or(i64::MAX),
+            },
+            pruneExemptMillis: self
+                .prune_exemption
+                .as_ref()
+                .and_then(|e| e.remaining())
+                .map_or(0, millis),
         })
     }
 
@@ -348,6 +406,17 @@ impl Operation {
         *self.last_usage.lock().expect("In touch.") = Instant::now();
     }
 
+    // Calls `f` and adds the time it took to the time spent in KeyMint.
+    fn time_keymint_call<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        // Expect safety:
+        // `keymint_duration` is locked only for primitive single line statements.
+        // There is no chance to panic and poison the mutex.
+        *self.keymint_duration.lock().expect("In time_keymint_call.") += start.elapsed();
+        result
+    }
+
     /// Implementation of `IKeystoreOperation::updateAad`.
     /// Refer to the AIDL spec at system/hardware/interfaces/keystore2 for details.
     fn update_aad(&self, aad_input: &[u8]) -> Result<()> {
@@ -364,7 +433,9 @@ impl Operation {
 
         self.update_outcome(&mut outcome, {
             let _wp = wd::watch("Operation::update_aad: calling IKeyMintOperation::updateAad");
-            map_km_error(self.km_op.updateAad(aad_input, hat.as_ref(), tst.as_ref()))
+            self.time_keymint_call(|| {
+                map_km_error(self.km_op.updateAad(aad_input, hat.as_ref(), tst.as_ref()))
+            })
         })
         .context(ks_err!("Update failed."))?;
 
@@ -388,7 +459,11 @@ impl Operation {
         let output = self
             .update_outcome(&mut outcome, {
                 let _wp = wd::watch("Operation::update: calling IKeyMintOperation::update");
-                map_km_error(self.km_op.update(input, hat.as_ref(), tst.as_ref()))
+                self.time_keymint_call(|| {
+                    map_km_error(test_hooks::hal_call("IKeyMintOperation::update", || {
+                        self.km_op.update(input, hat.as_ref(), tst.as_ref())
+                    }))
+                })
             })
             .context(ks_err!("Update failed."))?;
 
@@ -417,14 +492,19 @@ impl Operation {
 
         let output = self
             .update_outcome(&mut outcome, {
+                let _slo = slo_monitor::time(SloApi::Finish, self.logging_info.sec_level);
                 let _wp = wd::watch("Operation::finish: calling IKeyMintOperation::finish");
-                map_km_error(self.km_op.finish(
-                    input,
-                    signature,
-                    hat.as_ref(),
-                    tst.as_ref(),
-                    confirmation_token.as_deref(),
-                ))
+                self.time_keymint_call(|| {
+                    map_km_error(test_hooks::hal_call("IKeyMintOperation::finish", || {
+                        self.km_op.finish(
+                            input,
+                            signature,
+                            hat.as_ref(),
+                            tst.as_ref(),
+                            confirmation_token.as_deref(),
+                        )
+                    }))
+                })
             })
             .context(ks_err!("Finish failed."))?;
 
@@ -461,9 +541,18 @@ impl Drop for Operation {
             self.logging_info.sec_level,
             self.logging_info.purpose,
             &(self.logging_info.op_params),
+            &(self.logging_info.key_params),
             &guard,
             self.logging_info.key_upgraded,
+            *self.keymint_duration.lock().expect("In drop."),
         );
+        if let Some(audited_key) = &self.logging_info.audited_key {
+            let outcome = match *guard {
+                Outcome::Unknown => Outcome::Dropped,
+                outcome => outcome,
+            };
+            log_key_use(audited_key, self.owner, self.logging_info.purpose, outcome);
+        }
         if let Outcome::Unknown = *guard {
             drop(guard);
             // If the operation was still active we call abort, setting
@@ -475,19 +564,112 @@ impl Drop for Operation {
     }
 }
 
+/// The number of operation slots assumed for a backend until it first reports
+/// `ErrorCode::TOO_MANY_OPERATIONS`.
+const ASSUMED_OPERATION_CAPACITY: usize = 16;
+
+/// A caller's view of the operation capacity of a security level. All values are estimates,
+/// because operations of other callers may start or end at any time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperationBudget {
+    /// Number of operations of the caller that are currently running.
+    pub in_flight: i32,
+    /// The caller's fair share of the operation slots, i.e., the estimated capacity divided
+    /// among the caller and all other callers with running operations. Callers running more
+    /// operations than this are the first to have their operations pruned.
+    pub quota: i32,
+    /// Estimated utilization of the operation slots of the backend in percent.
+    pub global_pressure: i32,
+}
+
+/// The longest time for which an operation can be exempt from pruning.
+pub const MAX_PRUNE_EXEMPTION: Duration = Duration::from_secs(10);
+
+/// The maximum number of unexpired pruning exemptions of an OperationDb.
+const MAX_PRUNE_EXEMPT_OPERATIONS: usize = 2;
+
+/// The unexpired pruning exemptions of an OperationDb.
+#[derive(Debug, Default)]
+struct PruneExemptions {
+    next_id: u64,
+    // Maps the id of an exemption to the uid of its owner and its expiry.
+    exemptions: HashMap<u64, (u32, Instant)>,
+}
+
+/// An exemption of an operation from pruning, granted by `OperationDb::grant_prune_exemption`.
+/// It is returned to the OperationDb when it is dropped with its operation.
+#[derive(Debug)]
+pub struct PruneExemption {
+    id: u64,
+    until: Instant,
+    exemptions: Arc<Mutex<PruneExemptions>>,
+}
+
+impl PruneExemption {
+    /// Returns the time until the exemption expires, or None if it expired.
+    fn remaining(&self) -> Option<Duration> {
+        self.until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    }
+}
+
+impl Drop for PruneExemption {
+    fn drop(&mut self) {
+        self.exemptions.lock().expect("In PruneExemption::drop.").exemptions.remove(&self.id);
+    }
+}
+
+/// The operation databases whose operations are listed by `list_operations`.
+static REGISTERED_OPERATION_DBS: LazyLock<Mutex<Vec<Weak<OperationDb>>>> =
+    LazyLock::new(Default::default);
+
+/// Returns redacted information about the running operations of all registered operation
+/// databases, most recently used first.
+pub fn list_operations() -> Vec<OperationInfo> {
+    let dbs: Vec<Arc<OperationDb>> = {
+        let mut dbs = REGISTERED_OPERATION_DBS.lock().unwrap();
+        dbs.retain(|db| db.strong_count() != 0);
+        dbs.iter().filter_map(|db| db.upgrade()).collect()
+    };
+    let now = Instant::now();
+    let mut infos: Vec<OperationInfo> = dbs.iter().flat_map(|db| db.operation_infos(now)).collect();
+    infos.sort_by_key(|info| info.idleMillis);
+    infos
+}
+
 /// The OperationDb holds weak references to all ongoing operations.
 /// Its main purpose is to facilitate operation pruning.
+///
+/// The `operations` lock is never held across a call into KeyMint, so that a slow backend
+/// cannot stall the creation of operations and budget queries while it aborts an operation.
+/// Dropping the last reference to an operation aborts it, so references upgraded under the
+/// lock are released only after the lock.
 #[derive(Debug, Default)]
 pub struct OperationDb {
     // TODO replace Vec with WeakTable when the weak_table crate becomes
     // available.
     operations: Mutex<Vec<Weak<Operation>>>,
+    // Number of running operations when the backend last reported that it ran out of
+    // operation slots, or 0 if it never did.
+    observed_capacity: AtomicUsize,
+    // Serializes pruning, so that concurrent callers do not evict more than one operation
+    // for the same free slot.
+    pruning: Mutex<()>,
+    // Number of completed calls to `prune`.
+    prune_generation: AtomicU64,
+    prune_exemptions: Arc<Mutex<PruneExemptions>>,
 }
 
 impl OperationDb {
     /// Creates a new OperationDb.
     pub fn new() -> Self {
-        Self { operations: Mutex::new(Vec::new()) }
+        Self::default()
+    }
+
+    /// Creates a new OperationDb whose operations are listed by `list_operations`.
+    pub fn new_registered() -> Arc<Self> {
+        let db = Arc::new(Self::new());
+        REGISTERED_OPERATION_DBS.lock().unwrap().push(Arc::downgrade(&db));
+        db
     }
 
     /// Creates a new operation.
@@ -499,6 +681,7 @@ impl OperationDb {
         owner: u32,
         auth_info: AuthInfo,
         forced: bool,
+        prune_exemption: Option<PruneExemption>,
         logging_info: LoggingInfo,
     ) -> Arc<Operation> {
         // We use unwrap because we don't allow code that can panic while locked.
@@ -507,9 +690,11 @@ impl OperationDb {
         let mut index: usize = 0;
         // First we iterate through the operation slots to try and find an unused
         // slot. If we don't find one, we append the new entry instead.
+        // Do not upgrade the weak references here. If the upgraded reference was the last one,
+        // dropping it would abort the operation while the lock is held.
         match (*operations).iter_mut().find(|s| {
             index += 1;
-            s.upgrade().is_none()
+            s.strong_count() == 0
         }) {
             Some(free_slot) => {
                 let new_op = Arc::new(Operation::new(
@@ -518,6 +703,7 @@ impl OperationDb {
                     owner,
                     auth_info,
                     forced,
+                    prune_exemption,
                     logging_info,
                 ));
                 *free_slot = Arc::downgrade(&new_op);
@@ -530,6 +716,7 @@ impl OperationDb {
                     owner,
                     auth_info,
                     forced,
+                    prune_exemption,
                     logging_info,
                 ));
                 operations.push(Arc::downgrade(&new_op));
@@ -538,10 +725,83 @@ impl OperationDb {
         }
     }
 
+    /// Grants `owner` an exemption from pruning for `duration`, at most `MAX_PRUNE_EXEMPTION`,
+    /// for an operation that it is about to create. An owner can hold only one unexpired
+    /// exemption, and the OperationDb grants at most `MAX_PRUNE_EXEMPT_OPERATIONS` at a time,
+    /// so that exempt operations cannot take over the operation slots. An exemption counts
+    /// until it expires or its operation is dropped, whichever comes first.
+    pub fn grant_prune_exemption(
+        &self,
+        owner: u32,
+        duration: Duration,
+    ) -> Result<PruneExemption, Error> {
+        if duration.is_zero() || duration > MAX_PRUNE_EXEMPTION {
+            return Err(Error::Km(ErrorCode::INVALID_ARGUMENT));
+        }
+        let now = Instant::now();
+        let mut table = self.prune_exemptions.lock().expect("In grant_prune_exemption.");
+        table.exemptions.retain(|_, (_, until)| now < *until);
+        if table.exemptions.len() >= MAX_PRUNE_EXEMPT_OPERATIONS
+            || table.exemptions.values().any(|(o, _)| *o == owner)
+        {
+            return Err(Error::Rc(ResponseCode::BACKEND_BUSY));
+        }
+        let id = table.next_id;
+        table.next_id += 1;
+        let until = now + duration;
+        table.exemptions.insert(id, (owner, until));
+        Ok(PruneExemption { id, until, exemptions: self.prune_exemptions.clone() })
+    }
+
     fn get(&self, index: usize) -> Option<Arc<Operation>> {
         self.operations.lock().expect("In OperationDb::get.").get(index).and_then(|op| op.upgrade())
     }
 
+    /// Returns all running operations. The caller must drop the returned references after the
+    /// lock was released, which is why they are collected rather than visited under the lock.
+    fn live_operations(&self) -> Vec<Arc<Operation>> {
+        self.operations
+            .lock()
+            .expect("In OperationDb::live_operations.")
+            .iter()
+            .filter_map(|op| op.upgrade())
+            .collect()
+    }
+
+    /// Returns redacted information about the running operations at time `now`.
+    fn operation_infos(&self, now: Instant) -> Vec<OperationInfo> {
+        let operations = self.live_operations();
+        let mut siblings: HashMap<u32, usize> = HashMap::new();
+        let running: Vec<&Arc<Operation>> =
+            operations.iter().filter(|op| op.get_pruning_info().is_some()).collect();
+        running.iter().for_each(|op| *siblings.entry(op.owner).or_insert(0) += 1);
+        running.iter().filter_map(|op| op.info(siblings[&op.owner], now)).collect()
+    }
+
+    /// Returns the operation budget of `caller`.
+    pub fn get_budget(&self, caller: u32) -> OperationBudget {
+        // Maps the uid of the owner to the number of running operations of that owner.
+        let mut owners: HashMap<u32, usize> = HashMap::new();
+        self.live_operations()
+            .iter()
+            .filter_map(|op| op.get_pruning_info())
+            .for_each(|p_info| *owners.entry(p_info.owner).or_insert(0) += 1);
+
+        let capacity = match self.observed_capacity.load(Ordering::Relaxed) {
+            0 => ASSUMED_OPERATION_CAPACITY,
+            c => c,
+        };
+        let running: usize = owners.values().sum();
+        let in_flight = owners.get(&caller).copied().unwrap_or(0);
+        let other_owners = owners.keys().filter(|owner| **owner != caller).count();
+        let to_i32 = |v: usize| i32::try_from(v).unwrap_or(i32::MAX);
+        OperationBudget {
+            in_flight: to_i32(in_flight),
+            quota: to_i32((capacity / (other_owners + 1)).max(1)),
+            global_pressure: to_i32((running * 100 / capacity).min(100)),
+        }
+    }
+
     /// Attempts to prune an operation.
     ///
     /// This function is used during operation creation, i.e., by
@@ -614,107 +874,43 @@ impl OperationDb {
     /// ## Update
     /// We also allow callers to cannibalize their own sibling operations if no other
     /// slot can be found. In this case the least recently used sibling is pruned.
+    ///
+    /// ## Concurrency
+    /// Pruning is serialized. A caller that had to wait for another caller to finish pruning
+    /// returns `Ok(())` without pruning, because a slot was just freed up on its behalf. If
+    /// another caller snatched up that slot, the next attempt to create an operation fails
+    /// with `ErrorCode::TOO_MANY_OPERATIONS` and the caller prunes again. This way, callers
+    /// that run out of slots at the same time do not evict one operation each.
     pub fn prune(&self, caller: u32, forced: bool) -> Result<(), Error> {
+        let generation = self.prune_generation.load(Ordering::Acquire);
+        let _pruning = self.pruning.lock().expect("In OperationDb::prune: Trying to lock pruning.");
+        if self.prune_generation.load(Ordering::Acquire) != generation {
+            return Ok(());
+        }
+        let result = self.prune_one(caller, forced);
+        if result.is_ok() {
+            self.prune_generation.fetch_add(1, Ordering::Release);
+        }
+        result
+    }
+
+    /// Selects a pruning candidate with `pruning::select_candidate` and prunes it. Must only be called
+    /// with the `pruning` lock held.
+    fn prune_one(&self, caller: u32, forced: bool) -> Result<(), Error> {
         loop {
-            // Maps the uid of the owner to the number of operations that owner has
-            // (running_siblings). More operations per owner lowers the pruning
-            // resistance of the operations of that owner. Whereas the number of
-            // ongoing operations of the caller lowers the pruning power of the caller.
-            let mut owners: HashMap<u32, u64> = HashMap::new();
-            let mut pruning_info: Vec<PruningInfo> = Vec::new();
-
-            let now = Instant::now();
-            self.operations
-                .lock()
-                .expect("In OperationDb::prune: Trying to lock self.operations.")
-                .iter()
-                .for_each(|op| {
-                    if let Some(op) = op.upgrade() {
-                        if let Some(p_info) = op.get_pruning_info() {
-                            let owner = p_info.owner;
-                            pruning_info.push(p_info);
-                            // Count operations per owner.
-                            *owners.entry(owner).or_insert(0) += 1;
-                        }
-                    }
-                });
-
-            // If the operation is forced, the caller has a malus of 0.
-            let caller_malus = if forced { 0 } else { 1u64 + *owners.entry(caller).or_default() };
-
-            // We iterate through all operations computing the malus and finding
-            // the candidate with the highest malus which must also be higher
-            // than the caller_malus.
-            struct CandidateInfo {
-                index: usize,
-                malus: u64,
-                last_usage: Instant,
-                age: Duration,
-            }
-            let mut oldest_caller_op: Option<CandidateInfo> = None;
-            let candidate = pruning_info.iter().fold(
-                None,
-                |acc: Option<CandidateInfo>, &PruningInfo { last_usage, owner, index, forced }| {
-                    // Compute the age of the current operation.
-                    let age = now
-                        .checked_duration_since(last_usage)
-                        .unwrap_or_else(|| Duration::new(0, 0));
-
-                    // Find the least recently used sibling as an alternative pruning candidate.
-                    if owner == caller {
-                        if let Some(CandidateInfo { age: a, .. }) = oldest_caller_op {
-                            if age > a {
-                                oldest_caller_op =
-                                    Some(CandidateInfo { index, malus: 0, last_usage, age });
-                            }
-                        } else {
-                            oldest_caller_op =
-                                Some(CandidateInfo { index, malus: 0, last_usage, age });
-                        }
-                    }
+            let running: Vec<PruningInfo> =
+                self.live_operations().iter().filter_map(|op| op.get_pruning_info()).collect();
 
-                    // Compute the malus of the current operation.
-                    let malus = if forced {
-                        // Forced operations have a malus of 0. And cannot even be pruned
-                        // by other forced operations.
-                        0
-                    } else {
-                        // Expect safety: Every owner in pruning_info was counted in
-                        // the owners map. So this unwrap cannot panic.
-                        *owners.get(&owner).expect(
-                            "This is odd. We should have counted every owner in pruning_info.",
-                        ) + ((age.as_secs() + 1) as f64).log(6.0).floor() as u64
-                    };
-
-                    // Now check if the current operation is a viable/better candidate
-                    // the one currently stored in the accumulator.
-                    match acc {
-                        // First we have to find any operation that is prunable by the caller.
-                        None => {
-                            if caller_malus < malus {
-                                Some(CandidateInfo { index, malus, last_usage, age })
-                            } else {
-                                None
-                            }
-                        }
-                        // If we have found one we look for the operation with the worst score.
-                        // If there is a tie, the older operation is considered weaker.
-                        Some(CandidateInfo { index: i, malus: m, last_usage: l, age: a }) => {
-                            if malus > m || (malus == m && age > a) {
-                                Some(CandidateInfo { index, malus, last_usage, age })
-                            } else {
-                                Some(CandidateInfo { index: i, malus: m, last_usage: l, age: a })
-                            }
-                        }
-                    }
-                },
-            );
+            // We only get here if the backend ran out of operation slots, so the number of
+            // running operations is our best estimate of its capacity.
+            if !running.is_empty() {
+                self.observed_capacity.store(running.len(), Ordering::Relaxed);
+            }
 
-            // If we did not find a suitable candidate we may cannibalize our oldest sibling.
-            let candidate = candidate.or(oldest_caller_op);
+            let candidate = pruning::select_candidate(caller, forced, Instant::now(), &running);
 
             match candidate {
-                Some(CandidateInfo { index, malus: _, last_usage, age: _ }) => {
+                Some(Candidate { index, last_usage }) => {
                     match self.get(index) {
                         Some(op) => {
                             match op.prune(last_usage) {
@@ -823,6 +1019,7 @@ impl binder::Interface for KeystoreOperation {}
 impl IKeystoreOperation for KeystoreOperation {
     fn updateAad(&self, aad_input: &[u8]) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreOperation::updateAad");
+        let _cpu = cpu_accounting::account("IKeystoreOperation::updateAad");
         self.with_locked_operation(
             |op| op.update_aad(aad_input).context(ks_err!("KeystoreOperation::updateAad")),
             false,
@@ -832,6 +1029,7 @@ impl IKeystoreOperation for KeystoreOperation {
 
     fn update(&self, input: &[u8]) -> binder::Result<Option<Vec<u8>>> {
         let _wp = wd::watch("IKeystoreOperation::update");
+        let _cpu = cpu_accounting::account("IKeystoreOperation::update");
         self.with_locked_operation(
             |op| op.update(input).context(ks_err!("KeystoreOperation::update")),
             false,
@@ -844,6 +1042,7 @@ impl IKeystoreOperation for KeystoreOperation {
         signature: Option<&[u8]>,
     ) -> binder::Result<Option<Vec<u8>>> {
         let _wp = wd::watch("IKeystoreOperation::finish");
+        let _cpu = cpu_accounting::account("IKeystoreOperation::finish");
         self.with_locked_operation(
             |op| op.finish(input, signature).context(ks_err!("KeystoreOperation::finish")),
             true,
@@ -853,6 +1052,7 @@ impl IKeystoreOperation for KeystoreOperation {
 
     fn abort(&self) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreOperation::abort");
+        let _cpu = cpu_accounting::account("IKeystoreOperation::abort");
         let result = self.with_locked_operation(
             |op| op.abort(Outcome::Abort).context(ks_err!("KeystoreOperation::abort")),
             true,
@@ -869,3 +1069,255 @@ impl IKeystoreOperation for KeystoreOperation {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::globals::ENFORCEMENTS;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+        HardwareAuthToken::HardwareAuthToken, IKeyMintOperation::BnKeyMintOperation,
+    };
+    use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::TimeStampToken::TimeStampToken;
+    use std::sync::{mpsc, Barrier};
+
+    /// A KeyMint operation that takes `abort_delay` to abort and reports the start of every
+    /// abort on `abort_started`.
+    struct MockOperation {
+        abort_delay: Duration,
+        abort_started: Mutex<mpsc::Sender<()>>,
+    }
+
+    impl binder::Interface for MockOperation {}
+
+    impl IKeyMintOperation for MockOperation {
+        fn updateAad(
+            &self,
+            _input: &[u8],
+            _auth_token: Option<&HardwareAuthToken>,
+            _timestamp_token: Option<&TimeStampToken>,
+        ) -> binder::Result<()> {
+            Ok(())
+        }
+
+        fn update(
+            &self,
+            input: &[u8],
+            _auth_token: Option<&HardwareAuthToken>,
+            _timestamp_token: Option<&TimeStampToken>,
+        ) -> binder::Result<Vec<u8>> {
+            Ok(input.to_vec())
+        }
+
+        fn finish(
+            &self,
+            _input: Option<&[u8]>,
+            _signature: Option<&[u8]>,
+            _auth_token: Option<&HardwareAuthToken>,
+            _timestamp_token: Option<&TimeStampToken>,
+            _confirmation_token: Option<&[u8]>,
+        ) -> binder::Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn abort(&self) -> binder::Result<()> {
+            // The receiver may be gone by the time the operation is dropped.
+            let _ = self.abort_started.lock().unwrap().send(());
+            std::thread::sleep(self.abort_delay);
+            Ok(())
+        }
+    }
+
+    fn create_operation(
+        db: &OperationDb,
+        owner: u32,
+        abort_delay: Duration,
+        abort_started: mpsc::Sender<()>,
+    ) -> Arc<Operation> {
+        let km_op = BnKeyMintOperation::new_binder(
+            MockOperation { abort_delay, abort_started: Mutex::new(abort_started) },
+            BinderFeatures::default(),
+        );
+        let (_, auth_info) =
+            ENFORCEMENTS.authorize_create(KeyPurpose::SIGN, None, &[], false).unwrap();
+        db.create_operation(
+            km_op,
+            owner,
+            auth_info,
+            false,
+            None,
+            LoggingInfo::new(
+                SecurityLevel::TRUSTED_ENVIRONMENT,
+                KeyPurpose::SIGN,
+                vec![],
+                vec![],
+                false,
+            ),
+        )
+    }
+
+    fn is_pruned(op: &Operation) -> bool {
+        *op.outcome.lock().unwrap() == Outcome::Pruned
+    }
+
+    #[test]
+    fn test_slow_abort_does_not_block_admission() {
+        const SLOW: Duration = Duration::from_millis(500);
+        let db = Arc::new(OperationDb::new());
+        let (tx, rx) = mpsc::channel();
+        let slow_op = create_operation(&db, 1, SLOW, tx.clone());
+
+        // The caller has no other choice than to cannibalize its own operation.
+        let pruner = {
+            let db = db.clone();
+            std::thread::spawn(move || db.prune(1, false))
+        };
+        rx.recv().unwrap();
+
+        // While the backend is busy aborting, other callers are admitted without delay.
+        let start = Instant::now();
+        let op = create_operation(&db, 2, Duration::ZERO, tx);
+        let budget = db.get_budget(2);
+        let latency = start.elapsed();
+
+        assert!(latency < SLOW / 2, "Admission took {latency:?} while pruning.");
+        assert_eq!(budget.in_flight, 1);
+        assert_eq!(pruner.join().unwrap(), Ok(()));
+        assert!(is_pruned(&slow_op));
+        assert!(!is_pruned(&op));
+    }
+
+    #[test]
+    fn test_concurrent_pruners_evict_one_operation() {
+        const PRUNERS: usize = 4;
+        let db = Arc::new(OperationDb::new());
+        let (tx, rx) = mpsc::channel();
+        let ops: Vec<_> = (0..PRUNERS)
+            .map(|_| create_operation(&db, 1, Duration::from_millis(300), tx.clone()))
+            .collect();
+
+        let barrier = Arc::new(Barrier::new(PRUNERS));
+        let pruners: Vec<_> = (0..PRUNERS)
+            .map(|i| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    db.prune(2 + i as u32, false)
+                })
+            })
+            .collect();
+        for pruner in pruners {
+            assert_eq!(pruner.join().unwrap(), Ok(()));
+        }
+
+        // All pruners ran out of slots at the same time, so freeing up one slot is enough.
+        assert_eq!(ops.iter().filter(|op| is_pruned(op)).count(), 1);
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_get_budget() {
+        let db = OperationDb::new();
+        let (tx, _rx) = mpsc::channel();
+        let budget = |in_flight, quota, global_pressure| OperationBudget {
+            in_flight,
+            quota,
+            global_pressure,
+        };
+
+        // Until the backend runs out of slots, its capacity is assumed.
+        assert_eq!(db.get_budget(1001), budget(0, ASSUMED_OPERATION_CAPACITY as i32, 0));
+        let mut ops: Vec<_> = [1001, 1001, 1001, 1001, 1002, 1002]
+            .into_iter()
+            .map(|owner| create_operation(&db, owner, Duration::ZERO, tx.clone()))
+            .collect();
+        // The capacity is split among the caller and the other owners of running operations.
+        assert_eq!(db.get_budget(1001), budget(4, 8, 37));
+        assert_eq!(db.get_budget(1002), budget(2, 8, 37));
+        assert_eq!(db.get_budget(1003), budget(0, 5, 37));
+
+        // Pruning means that the backend ran out of slots with six running operations.
+        assert_eq!(db.prune(1003, false), Ok(()));
+        assert_eq!(ops.iter().filter(|op| is_pruned(op)).count(), 1);
+        assert_eq!(db.get_budget(1001), budget(3, 3, 83));
+        assert_eq!(db.get_budget(1003), budget(0, 2, 83));
+
+        // The pressure is capped if more operations run than the backend was observed to hold.
+        ops.extend((0..3).map(|_| create_operation(&db, 1002, Duration::ZERO, tx.clone())));
+        assert_eq!(db.get_budget(1002), budget(5, 3, 100));
+        // Every caller has a quota of at least one operation.
+        ops.extend(
+            (1004..1010).map(|owner| create_operation(&db, owner, Duration::ZERO, tx.clone())),
+        );
+        assert_eq!(db.get_budget(1003).quota, 1);
+    }
+
+    #[test]
+    fn test_list_operations() {
+        let db = OperationDb::new_registered();
+        let (tx, _rx) = mpsc::channel();
+        let ops: Vec<_> = [1001, 1001, 1002]
+            .into_iter()
+            .map(|owner| create_operation(&db, owner, Duration::ZERO, tx.clone()))
+            .collect();
+        let now = Instant::now() + Duration::from_secs(6);
+
+        let infos = db.operation_infos(now);
+        assert_eq!(infos.len(), 3);
+        for info in &infos {
+            assert_eq!(info.securityLevel, SecurityLevel::TRUSTED_ENVIRONMENT);
+            assert_eq!(info.purpose, KeyPurpose::SIGN);
+            assert!(info.ageMillis >= 6000);
+            assert!(!info.forced);
+        }
+        // Siblings and 6 seconds of idle time add up.
+        let malus = |owner: i32| infos.iter().find(|i| i.ownerUid == owner).unwrap().pruningMalus;
+        assert_eq!(malus(1001), 3);
+        assert_eq!(malus(1002), 2);
+
+        // Operations of registered databases are listed globally until they are finalized.
+        assert_eq!(list_operations().iter().filter(|i| i.ownerUid == 1002).count(), 1);
+        *ops[2].outcome.lock().unwrap() = Outcome::Success;
+        assert_eq!(db.operation_infos(now).len(), 2);
+        assert!(list_operations().iter().all(|i| i.ownerUid != 1002));
+    }
+
+    #[test]
+    fn test_grant_prune_exemption() {
+        let db = OperationDb::new();
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            db.grant_prune_exemption(1001, Duration::ZERO).unwrap_err(),
+            Error::Km(ErrorCode::INVALID_ARGUMENT)
+        );
+        assert_eq!(
+            db.grant_prune_exemption(1001, MAX_PRUNE_EXEMPTION + second).unwrap_err(),
+            Error::Km(ErrorCode::INVALID_ARGUMENT)
+        );
+
+        let first = db.grant_prune_exemption(1001, second).unwrap();
+        assert!(first.remaining().is_some());
+        // One exemption per owner.
+        assert_eq!(
+            db.grant_prune_exemption(1001, second).unwrap_err(),
+            Error::Rc(ResponseCode::BACKEND_BUSY)
+        );
+        let _second = db.grant_prune_exemption(1002, second).unwrap();
+        // At most MAX_PRUNE_EXEMPT_OPERATIONS at a time.
+        assert_eq!(
+            db.grant_prune_exemption(1003, second).unwrap_err(),
+            Error::Rc(ResponseCode::BACKEND_BUSY)
+        );
+        // Dropping an exemption returns it.
+        drop(first);
+        let _third = db.grant_prune_exemption(1003, second).unwrap();
+
+        // Expired exemptions no longer count.
+        let db = OperationDb::new();
+        let short = Duration::from_millis(10);
+        let expired = [1001, 1002].map(|owner| db.grant_prune_exemption(owner, short).unwrap());
+        std::thread::sleep(short);
+        assert!(expired.iter().all(|e| e.remaining().is_none()));
+        let _fresh = db.grant_prune_exemption(1001, second).unwrap();
+    }
+}
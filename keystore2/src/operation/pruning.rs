@@ -0,0 +1,302 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the operation pruning policy described in `OperationDb::prune`.
+//! The policy is a pure function of the running operations and the current time, so that
+//! changes to it can be evaluated with simulated workloads instead of on a device.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What the pruning policy knows about a running operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PruningInfo {
+    /// The time the operation was last used.
+    pub last_usage: Instant,
+    /// Uid of the operation's owner.
+    pub owner: u32,
+    /// The index of the operation in the OperationDb.
+    pub index: usize,
+    /// Whether the operation is forced, which makes it immune to pruning.
+    pub forced: bool,
+    /// Whether the operation has an unexpired pruning exemption, which makes it immune to
+    /// pruning, even by its owner.
+    pub prune_exempt: bool,
+}
+
+/// The operation selected for pruning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    /// The index of the operation in the OperationDb.
+    pub index: usize,
+    /// The last usage of the operation when it was selected. The operation must not be
+    /// pruned if it was used since.
+    pub last_usage: Instant,
+}
+
+/// Returns the malus of a running operation that is not forced. `siblings` is the number of
+/// running operations of its owner, including itself, and `age` is the time since it was last
+/// used. Operations with a higher malus are pruned first.
+pub fn malus(siblings: u64, age: Duration) -> u64 {
+    siblings + ((age.as_secs() + 1) as f64).log(6.0).floor() as u64
+}
+
+/// Selects the operation that `caller` may prune at time `now` in order to start a new
+/// operation, or returns None if there is no such operation in `running`.
+pub fn select_candidate(
+    caller: u32,
+    forced: bool,
+    now: Instant,
+    running: &[PruningInfo],
+) -> Option<Candidate> {
+    // Maps the uid of the owner to the number of operations that owner has
+    // (running_siblings). More operations per owner lowers the pruning
+    // resistance of the operations of that owner. Whereas the number of
+    // ongoing operations of the caller lowers the pruning power of the caller.
+    let mut owners: HashMap<u32, u64> = HashMap::new();
+    for p_info in running {
+        *owners.entry(p_info.owner).or_insert(0) += 1;
+    }
+
+    // If the operation is forced, the caller has a malus of 0.
+    let caller_malus = if forced { 0 } else { 1u64 + *owners.entry(caller).or_default() };
+
+    // We iterate through all operations computing the malus and finding
+    // the candidate with the highest malus which must also be higher
+    // than the caller_malus.
+    struct CandidateInfo {
+        index: usize,
+        malus: u64,
+        last_usage: Instant,
+        age: Duration,
+    }
+    let mut oldest_caller_op: Option<CandidateInfo> = None;
+    let candidate = running.iter().filter(|p_info| !p_info.prune_exempt).fold(
+        None,
+        |acc: Option<CandidateInfo>, &PruningInfo { last_usage, owner, index, forced, .. }| {
+            // Compute the age of the current operation.
+            let age = now.checked_duration_since(last_usage).unwrap_or_else(|| Duration::new(0, 0));
+
+            // Find the least recently used sibling as an alternative pruning candidate.
+            if owner == caller {
+                if let Some(CandidateInfo { age: a, .. }) = oldest_caller_op {
+                    if age > a {
+                        oldest_caller_op = Some(CandidateInfo { index, malus: 0, last_usage, age });
+                    }
+                } else {
+                    oldest_caller_op = Some(CandidateInfo { index, malus: 0, last_usage, age });
+                }
+            }
+
+            // Compute the malus of the current operation.
+            let malus = if forced {
+                // Forced operations have a malus of 0. And cannot even be pruned
+                // by other forced operations.
+                0
+            } else {
+                // Expect safety: Every owner in running was counted in
+                // the owners map. So this unwrap cannot panic.
+                malus(
+                    *owners
+                        .get(&owner)
+                        .expect("This is odd. We should have counted every owner in running."),
+                    age,
+                )
+            };
+
+            // Now check if the current operation is a viable/better candidate
+            // the one currently stored in the accumulator.
+            match acc {
+                // First we have to find any operation that is prunable by the caller.
+                None => {
+                    if caller_malus < malus {
+                        Some(CandidateInfo { index, malus, last_usage, age })
+                    } else {
+                        None
+                    }
+                }
+                // If we have found one we look for the operation with the worst score.
+                // If there is a tie, the older operation is considered weaker.
+                Some(CandidateInfo { index: i, malus: m, last_usage: l, age: a }) => {
+                    if malus > m || (malus == m && age > a) {
+                        Some(CandidateInfo { index, malus, last_usage, age })
+                    } else {
+                        Some(CandidateInfo { index: i, malus: m, last_usage: l, age: a })
+                    }
+                }
+            }
+        },
+    );
+
+    // If we did not find a suitable candidate we may cannibalize our oldest sibling.
+    candidate
+        .or(oldest_caller_op)
+        .map(|CandidateInfo { index, last_usage, .. }| Candidate { index, last_usage })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore2_test_utils::operation_workload::{
+        Arrival, SyntheticOperation, WorkloadGenerator,
+    };
+
+    const CAPACITY: usize = 16;
+
+    /// Replays synthetic workloads against the pruning policy with a fixed number of operation
+    /// slots.
+    struct Simulation {
+        start: Instant,
+        slots: Vec<Option<SyntheticOperation>>,
+        rejected: HashMap<u32, usize>,
+        pruned: HashMap<u32, usize>,
+    }
+
+    impl Simulation {
+        fn new(running: Vec<SyntheticOperation>) -> Self {
+            let mut slots: Vec<_> = running.into_iter().map(Some).collect();
+            assert!(slots.len() <= CAPACITY);
+            slots.resize(CAPACITY, None);
+            Self { start: Instant::now(), slots, rejected: HashMap::new(), pruned: HashMap::new() }
+        }
+
+        fn admit(&mut self, arrival: &Arrival) {
+            let op = SyntheticOperation {
+                owner: arrival.owner,
+                last_usage: arrival.at,
+                forced: arrival.forced,
+            };
+            if let Some(free) = self.slots.iter_mut().find(|s| s.is_none()) {
+                *free = Some(op);
+                return;
+            }
+            let running: Vec<PruningInfo> = self
+                .slots
+                .iter()
+                .enumerate()
+                .filter_map(|(index, s)| s.as_ref().map(|s| (index, s)))
+                .map(|(index, s)| PruningInfo {
+                    last_usage: self.start + s.last_usage,
+                    owner: s.owner,
+                    index,
+                    forced: s.forced,
+                    prune_exempt: false,
+                })
+                .collect();
+            match select_candidate(arrival.owner, arrival.forced, self.start + arrival.at, &running)
+            {
+                Some(Candidate { index, .. }) => {
+                    let victim = self.slots[index].replace(op).unwrap();
+                    *self.pruned.entry(victim.owner).or_insert(0) += 1;
+                }
+                None => *self.rejected.entry(arrival.owner).or_insert(0) += 1,
+            }
+        }
+
+        fn run(&mut self, arrivals: &[Arrival]) {
+            arrivals.iter().for_each(|arrival| self.admit(arrival));
+        }
+    }
+
+    fn info(index: usize, owner: u32, last_usage: Instant) -> PruningInfo {
+        PruningInfo { last_usage, owner, index, forced: false, prune_exempt: false }
+    }
+
+    #[test]
+    fn test_young_single_operations_are_safe() {
+        let now = Instant::now() + Duration::from_secs(100);
+        let young = now - Duration::from_secs(4);
+        let aging = now - Duration::from_secs(10);
+
+        let running = [info(0, 1, young), info(1, 2, young)];
+        assert_eq!(select_candidate(3, false, now, &running), None);
+        // A caller may still cannibalize its own operation.
+        assert_eq!(
+            select_candidate(1, false, now, &running),
+            Some(Candidate { index: 0, last_usage: young })
+        );
+
+        let running = [info(0, 1, young), info(1, 2, aging)];
+        assert_eq!(
+            select_candidate(3, false, now, &running),
+            Some(Candidate { index: 1, last_usage: aging })
+        );
+    }
+
+    #[test]
+    fn test_forced_operations_are_never_pruned() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let old = now - Duration::from_secs(500);
+        let running = [
+            PruningInfo { forced: true, ..info(0, 1, old) },
+            PruningInfo { forced: true, ..info(1, 1, old) },
+        ];
+        assert_eq!(select_candidate(2, true, now, &running), None);
+        assert_eq!(select_candidate(2, false, now, &running), None);
+    }
+
+    #[test]
+    fn test_prune_exempt_operations_are_never_pruned() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let old = now - Duration::from_secs(500);
+        let running = [
+            PruningInfo { prune_exempt: true, ..info(0, 1, old) },
+            PruningInfo { prune_exempt: true, ..info(1, 1, old) },
+        ];
+        assert_eq!(select_candidate(2, true, now, &running), None);
+        // Not even the owner may cannibalize an exempt operation.
+        assert_eq!(select_candidate(1, false, now, &running), None);
+
+        let running = [PruningInfo { prune_exempt: true, ..info(0, 1, old) }, info(1, 1, now)];
+        assert_eq!(
+            select_candidate(1, false, now, &running),
+            Some(Candidate { index: 1, last_usage: now })
+        );
+    }
+
+    #[test]
+    fn test_flooding_client_cannot_starve_well_behaved_clients() {
+        const FLOODER: u32 = 10000;
+        let well_behaved: Vec<u32> = (10001..10005).collect();
+        let now = Duration::from_secs(60);
+        let mut generator = WorkloadGenerator::new(0x6b657973);
+
+        let count = CAPACITY - well_behaved.len();
+        let mut running = generator.running(FLOODER, count, Duration::ZERO..now, now);
+        for owner in &well_behaved {
+            running.extend(generator.running(
+                *owner,
+                1,
+                Duration::ZERO..Duration::from_secs(1),
+                now,
+            ));
+        }
+        let mut simulation = Simulation::new(running);
+
+        // The flooder keeps asking for more while new clients show up. All requests must be
+        // served, but never at the expense of the well behaved clients, whose operations stay
+        // younger than 5s during the simulation.
+        let mut owners = vec![FLOODER; 4];
+        owners.extend(20000..20004);
+        let gaps = Duration::ZERO..Duration::from_millis(10);
+        simulation.run(&generator.arrivals(&owners, 200, gaps, now));
+
+        assert_eq!(simulation.rejected, HashMap::new());
+        assert!(simulation.pruned[&FLOODER] > 0);
+        for owner in &well_behaved {
+            assert_eq!(simulation.pruned.get(owner), None);
+            assert!(simulation.slots.iter().flatten().any(|op| op.owner == *owner));
+        }
+    }
+}
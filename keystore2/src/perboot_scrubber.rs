@@ -0,0 +1,61 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the background scrubber of the per-boot database, which holds the
+//! auth tokens and the recently used caller provided nonces in memory. The per-boot database
+//! lives until reboot, so an inconsistency in it used to persist until then and surface as
+//! confusing failures, e.g., of auth bound operations. When the background worker becomes idle,
+//! and at most once per `SCRUB_INTERVAL`, the scrubber checks these invariants:
+//!  * No auth token was received after the time of the check, which would let it pass timeout
+//!    checks for longer than it should.
+//!  * The auth token table stays within its bounds.
+//!  * Nonce histories belong to existing keys.
+//!  * The nonce histories stay within their bounds, and the use counter that orders them is
+//!    ahead of all recorded uses.
+//!
+//! Violations are repaired by dropping the offending entries, which at worst makes a user
+//! authenticate again, and are logged.
+
+use crate::globals::{ASYNC_TASK, DB};
+use std::time::{Duration, Instant};
+
+/// The minimum time between two scrubs.
+const SCRUB_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+struct PerbootScrubState {
+    last_scrub: Instant,
+}
+
+impl Default for PerbootScrubState {
+    fn default() -> Self {
+        Self { last_scrub: Instant::now() }
+    }
+}
+
+/// Registers the scrubber as an idle callback.
+pub fn register_perboot_scrubber() {
+    ASYNC_TASK.add_idle(|shelf| {
+        let state = shelf.get_mut::<PerbootScrubState>();
+        let now = Instant::now();
+        if now.duration_since(state.last_scrub) < SCRUB_INTERVAL {
+            return;
+        }
+        state.last_scrub = now;
+        match DB.with(|db| db.borrow_mut().scrub_perboot()) {
+            Ok(report) if report.is_clean() => {}
+            Ok(report) => log::warn!("Repaired the per-boot database: {report:?}"),
+            Err(e) => log::error!("Failed to scrub the per-boot database: {e:?}"),
+        }
+    });
+}
@@ -67,7 +67,8 @@ implement_class!(
         /// Checked when the caller tries to use a unique id.
         #[selinux(name = gen_unique_id)]
         GenUniqueId = KeyPermission::GEN_UNIQUE_ID.0,
-        /// Checked when the caller tries to load a key.
+        /// Checked when the caller tries to load a key. Loading the certificates of a key
+        /// requires this permission.
         #[selinux(name = get_info)]
         GetInfo = KeyPermission::GET_INFO.0,
         /// Checked when the caller attempts to grant a key to another uid.
@@ -87,7 +88,8 @@ implement_class!(
         /// Checked when the caller attempts to update public key artifacts.
         #[selinux(name = update)]
         Update = KeyPermission::UPDATE.0,
-        /// Checked when the caller attempts to use a private or public key.
+        /// Checked when the caller attempts to use a private or public key. Callers with this
+        /// permission but without `GetInfo` can load the key without its certificates.
         #[selinux(name = use)]
         Use = KeyPermission::USE.0,
         /// Does nothing, and is not checked. For use of device identifiers,
@@ -150,6 +152,36 @@ implement_class!(
         /// Checked on IKeystoreAuthorization::getLastAuthTime() is called.
         #[selinux(name = get_last_auth_time)]
         GetLastAuthTime,
+        /// Checked when IKeystoreMaintenance::runGarbageCollection or onLowStorage is called.
+        #[selinux(name = run_gc)]
+        RunGc,
+        /// Checked when IKeystoreMaintenance::setKeyUseAuditNamespaces is called.
+        #[selinux(name = configure_audit)]
+        ConfigureAudit,
+        /// Checked when a key is generated on an additional KeyMint instance of a security level.
+        #[selinux(name = select_keymint_instance)]
+        SelectKeyMintInstance,
+        /// Checked when IKeystoreMaintenance::setMaxKeyValidity, registerKeyTemplate or
+        /// unregisterKeyTemplate is called.
+        #[selinux(name = configure_key_policy)]
+        ConfigureKeyPolicy,
+        /// Checked when IKeystoreMaintenance::registerEventListener or unregisterEventListener
+        /// is called.
+        #[selinux(name = observe_events)]
+        ObserveEvents,
+        /// Checked when IKeystoreMaintenance::registerEsimProfile, unregisterEsimProfile,
+        /// onEsimProfileActivated, or onEsimProfileDeactivated is called.
+        #[selinux(name = manage_esim_profiles)]
+        ManageEsimProfiles,
+        /// Checked when any method of IKeystoreTestHooks is called.
+        #[selinux(name = test_hooks)]
+        TestHooks,
+        /// Checked when IKeystoreMaintenance::importFromBackupVault is called.
+        #[selinux(name = restore_backup)]
+        RestoreBackup,
+        /// Checked when a pruning exempt operation is created.
+        #[selinux(name = prune_exempt_operation)]
+        PruneExemptOperation,
     }
 );
 
@@ -310,6 +342,13 @@ pub fn check_grant_permission(
     Ok(())
 }
 
+/// Returns true if `caller_uid` owns the `Domain::APP` key with the namespace `nspace`. This is
+/// the only way to access a `Domain::APP` key other than by grant. The same app in another
+/// Android user or profile has a different UID and does not own the key.
+pub fn is_app_key_owner(caller_uid: u32, nspace: i64) -> bool {
+    caller_uid as i64 == nspace
+}
+
 /// Uses `selinux::check_permission` to check if the given caller context `caller_cxt`
 /// has the permissions indicated by `perm` for the target domain indicated by the key
 /// descriptor `key` in the security class `keystore2_key`.
@@ -352,7 +391,7 @@ pub fn check_key_permission(
     let target_context = match key.domain {
         // apps get the default keystore context
         Domain::APP => {
-            if caller_uid as i64 != key.nspace {
+            if !is_app_key_owner(caller_uid, key.nspace) {
                 return Err(selinux::Error::perm())
                     .context("Trying to access key without ownership.");
             }
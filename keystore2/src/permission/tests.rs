@@ -244,6 +244,38 @@ fn check_key_permission_domain_app() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn check_key_permission_isolated_app() -> Result<()> {
+    use crate::isolated_callers::{IsolatedProcessPolicy, ISOLATED_CALLERS};
+    let isolated_app = Context::new("u:r:isolated_app:s0:c512,c768")?;
+    let uid = 99000;
+    let key = KeyDescriptor { domain: Domain::APP, nspace: uid as i64, alias: None, blob: None };
+
+    // Isolated processes can never grant their keys, whatever the SELinux policy says.
+    assert!(ISOLATED_CALLERS.check_grant(uid).is_err());
+    assert_perm_failed!(check_key_permission(uid, &isolated_app, KeyPerm::ManageBlob, &key, &None));
+    match ISOLATED_CALLERS.policy() {
+        // With the ephemeral policy, the SELinux policy must let isolated processes use the keys
+        // of their namespace.
+        IsolatedProcessPolicy::Ephemeral => {
+            assert!(check_key_permission(uid, &isolated_app, KeyPerm::Use, &key, &None).is_ok());
+            assert!(check_key_permission(uid, &isolated_app, KeyPerm::Rebind, &key, &None).is_ok());
+        }
+        // Otherwise, the SELinux policy denies them access to keys, in case they get past the
+        // check of the isolated process policy.
+        IsolatedProcessPolicy::Reject => {
+            assert_perm_failed!(check_key_permission(
+                uid,
+                &isolated_app,
+                KeyPerm::Rebind,
+                &key,
+                &None
+            ));
+        }
+    }
+    Ok(())
+}
+
 #[test]
 fn check_key_permission_domain_selinux() -> Result<()> {
     let (sctx, namespace, is_su) = check_context()?;
@@ -432,3 +464,14 @@ fn key_perm_set_include_no_overlap_test() {
     assert!(!v1.includes(v2));
     assert!(!v2.includes(v1));
 }
+#[test]
+fn app_key_owner_is_exact_uid_test() {
+    use crate::utils::AID_USER_OFFSET;
+    const APP_ID: u32 = 10100;
+    let owner = APP_ID as i64;
+    assert!(is_app_key_owner(APP_ID, owner));
+    assert!(!is_app_key_owner(APP_ID + 1, owner));
+    // The same app in a work or clone profile is a different UID.
+    assert!(!is_app_key_owner(10 * AID_USER_OFFSET + APP_ID, owner));
+    assert!(!is_app_key_owner(11 * AID_USER_OFFSET + APP_ID, owner));
+}
@@ -0,0 +1,382 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module determines the attestation provisioning state of the KeyMint instances: which
+//! attestation IDs KeyMint attests to, whether factory attestation keys are present, and whether
+//! the device is enrolled for remote key provisioning.
+//!
+//! KeyMint has no interface to query the provisioned attestation IDs, so Keystore generates a
+//! throwaway attested key for each ID with the value that the ID is provisioned from. KeyMint
+//! refuses with `ErrorCode::CANNOT_ATTEST_IDS` if the ID is not provisioned or has another
+//! value. Attestation IDs and factory keys do not change while the device is running, so the
+//! outcome of these probes is cached until reboot.
+
+use crate::error::{map_km_error, wrapped_rkpd_error_to_ks_error, Error};
+use crate::globals::{get_keymint_device, get_remotely_provisioned_component_name};
+use crate::ks_err;
+use crate::remote_provisioning::{get_rkpd_attestation_key, RemProvState};
+use crate::utils::{AID_KEYSTORE, UNDEFINED_NOT_AFTER};
+use crate::watchdog_helper::watchdog as wd;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, AttestationKey::AttestationKey, Digest::Digest, EcCurve::EcCurve,
+    ErrorCode::ErrorCode, IKeyMintDevice::IKeyMintDevice, KeyParameter::KeyParameter,
+    KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
+    Tag::Tag,
+};
+use android_security_maintenance::aidl::android::security::maintenance::{
+    AttestationIdInfo::AttestationIdInfo, ProvisioningInfo::ProvisioningInfo,
+    ProvisioningState::ProvisioningState,
+};
+use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
+use anyhow::{Context, Result};
+use binder::Strong;
+use keystore2_crypto::parse_subject_from_certificate;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// The attestation IDs in the order in which they are reported, with the system properties that
+/// their values are provisioned from. The first property that is set provides the value. The
+/// telephony IDs cannot be read by Keystore and must be passed by the caller.
+const ATTESTATION_IDS: &[(Tag, &[&str])] = &[
+    (
+        Tag::ATTESTATION_ID_BRAND,
+        &["ro.product.brand_for_attestation", "ro.product.vendor.brand", "ro.product.brand"],
+    ),
+    (
+        Tag::ATTESTATION_ID_DEVICE,
+        &["ro.product.device_for_attestation", "ro.product.vendor.device", "ro.product.device"],
+    ),
+    (
+        Tag::ATTESTATION_ID_PRODUCT,
+        &["ro.product.name_for_attestation", "ro.product.vendor.name", "ro.product.name"],
+    ),
+    (Tag::ATTESTATION_ID_SERIAL, &["ro.serialno"]),
+    (Tag::ATTESTATION_ID_IMEI, &[]),
+    (Tag::ATTESTATION_ID_SECOND_IMEI, &[]),
+    (Tag::ATTESTATION_ID_MEID, &[]),
+    (
+        Tag::ATTESTATION_ID_MANUFACTURER,
+        &[
+            "ro.product.manufacturer_for_attestation",
+            "ro.product.vendor.manufacturer",
+            "ro.product.manufacturer",
+        ],
+    ),
+    (
+        Tag::ATTESTATION_ID_MODEL,
+        &["ro.product.model_for_attestation", "ro.product.vendor.model", "ro.product.model"],
+    ),
+];
+
+/// DER encoding of an attestation application ID without packages and signatures. KeyMint
+/// requires one for every attestation, but the probe keys do not belong to any app.
+const EMPTY_ATTESTATION_APPLICATION_ID: &[u8] = &[0x30, 0x04, 0x31, 0x00, 0x31, 0x00];
+
+/// Outcomes of probes that do not change until reboot. Unknown outcomes are not cached.
+#[derive(Default)]
+struct ProbeCache {
+    factory_keys: HashMap<SecurityLevel, ProvisioningState>,
+    ids: HashMap<(SecurityLevel, Tag, Vec<u8>), ProvisioningState>,
+}
+
+static PROBE_CACHE: LazyLock<Mutex<ProbeCache>> = LazyLock::new(Default::default);
+
+fn read_id_property(names: &[&str]) -> Option<Vec<u8>> {
+    names.iter().find_map(|name| match rustutils::system_properties::read(name) {
+        Ok(Some(value)) if !value.is_empty() => Some(value.into_bytes()),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Failed to read {name}: {e:?}");
+            None
+        }
+    })
+}
+
+/// Returns the value of each attestation ID, if known. The values of the telephony IDs are
+/// taken from `telephony_ids`, all others are read with `read_property`.
+fn id_values(
+    telephony_ids: &[KeyParameter],
+    read_property: impl Fn(&[&str]) -> Option<Vec<u8>>,
+) -> Result<Vec<(Tag, Option<Vec<u8>>)>> {
+    for kp in telephony_ids {
+        let is_telephony_id = matches!(
+            kp.tag,
+            Tag::ATTESTATION_ID_IMEI | Tag::ATTESTATION_ID_SECOND_IMEI | Tag::ATTESTATION_ID_MEID
+        );
+        if !is_telephony_id || !matches!(kp.value, KeyParameterValue::Blob(_)) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Not a telephony ID: {:?}.", kp.tag));
+        }
+    }
+    Ok(ATTESTATION_IDS
+        .iter()
+        .map(|(tag, properties)| {
+            let value = if properties.is_empty() {
+                telephony_ids.iter().find(|kp| kp.tag == *tag).and_then(|kp| match &kp.value {
+                    KeyParameterValue::Blob(value) => Some(value.clone()),
+                    _ => None,
+                })
+            } else {
+                read_property(properties)
+            };
+            (*tag, value)
+        })
+        .collect())
+}
+
+/// Maps the outcome of a probe to a provisioning state. `not_provisioned` is the error code
+/// with which KeyMint reports that the probed item is not provisioned.
+fn probe_state<T>(result: &Result<T, Error>, not_provisioned: ErrorCode) -> ProvisioningState {
+    match result {
+        Ok(_) => ProvisioningState::PROVISIONED,
+        Err(Error::Km(e)) if *e == not_provisioned => ProvisioningState::NOT_PROVISIONED,
+        Err(e) => {
+            log::warn!("Probe failed: {e:?}");
+            ProvisioningState::UNKNOWN
+        }
+    }
+}
+
+struct Prober {
+    sec_level: SecurityLevel,
+    km_dev: Strong<dyn IKeyMintDevice>,
+}
+
+impl Prober {
+    /// Generates an attested key with the additional parameter `extra` and deletes it again.
+    fn generate(
+        &self,
+        extra: Option<KeyParameter>,
+        attest_key: Option<&AttestationKey>,
+    ) -> Result<(), Error> {
+        let mut params = vec![
+            KeyParameter {
+                tag: Tag::ALGORITHM,
+                value: KeyParameterValue::Algorithm(Algorithm::EC),
+            },
+            KeyParameter { tag: Tag::EC_CURVE, value: KeyParameterValue::EcCurve(EcCurve::P_256) },
+            KeyParameter {
+                tag: Tag::PURPOSE,
+                value: KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+            },
+            KeyParameter { tag: Tag::DIGEST, value: KeyParameterValue::Digest(Digest::SHA_2_256) },
+            KeyParameter { tag: Tag::NO_AUTH_REQUIRED, value: KeyParameterValue::BoolValue(true) },
+            KeyParameter {
+                tag: Tag::ATTESTATION_CHALLENGE,
+                value: KeyParameterValue::Blob(b"provisioning info".to_vec()),
+            },
+            KeyParameter {
+                tag: Tag::ATTESTATION_APPLICATION_ID,
+                value: KeyParameterValue::Blob(EMPTY_ATTESTATION_APPLICATION_ID.to_vec()),
+            },
+            KeyParameter {
+                tag: Tag::CERTIFICATE_NOT_BEFORE,
+                value: KeyParameterValue::DateTime(0),
+            },
+            KeyParameter {
+                tag: Tag::CERTIFICATE_NOT_AFTER,
+                value: KeyParameterValue::DateTime(UNDEFINED_NOT_AFTER),
+            },
+        ];
+        params.extend(extra);
+        let creation_result = map_km_error({
+            let _wp = wd::watch_millis(
+                "provisioning_info::Prober::generate: calling IKeyMintDevice::generateKey",
+                5000, // Generate can take a little longer.
+            );
+            self.km_dev.generateKey(&params, attest_key)
+        })?;
+        if let Err(e) = map_km_error(self.km_dev.deleteKey(&creation_result.keyBlob)) {
+            log::warn!("Failed to delete probe key on {:?}: {e:?}", self.sec_level);
+        }
+        Ok(())
+    }
+
+    fn factory_keys(&self) -> ProvisioningState {
+        if let Some(state) = PROBE_CACHE.lock().unwrap().factory_keys.get(&self.sec_level) {
+            return *state;
+        }
+        let state =
+            probe_state(&self.generate(None, None), ErrorCode::ATTESTATION_KEYS_NOT_PROVISIONED);
+        if state != ProvisioningState::UNKNOWN {
+            PROBE_CACHE.lock().unwrap().factory_keys.insert(self.sec_level, state);
+        }
+        state
+    }
+
+    /// Probes the attestation ID `tag` with `value`. `attest_key` is None if there are no
+    /// attestation keys, and Some(None) if the factory keys are to be used.
+    fn attestation_id(
+        &self,
+        tag: Tag,
+        value: Option<&Vec<u8>>,
+        attest_key: Option<Option<&AttestationKey>>,
+    ) -> ProvisioningState {
+        let Some(value) = value else { return ProvisioningState::UNKNOWN };
+        let cache_key = (self.sec_level, tag, value.clone());
+        if let Some(state) = PROBE_CACHE.lock().unwrap().ids.get(&cache_key) {
+            return *state;
+        }
+        // Without attestation keys, KeyMint cannot attest to anything.
+        let Some(attest_key) = attest_key else { return ProvisioningState::UNKNOWN };
+        let extra = KeyParameter { tag, value: KeyParameterValue::Blob(value.clone()) };
+        let state =
+            probe_state(&self.generate(Some(extra), attest_key), ErrorCode::CANNOT_ATTEST_IDS);
+        if state != ProvisioningState::UNKNOWN {
+            PROBE_CACHE.lock().unwrap().ids.insert(cache_key, state);
+        }
+        state
+    }
+}
+
+/// Returns the remotely provisioned attestation key of Keystore itself and the corresponding
+/// provisioning state.
+fn remotely_provisioned_key(
+    sec_level: SecurityLevel,
+) -> (ProvisioningState, Option<AttestationKey>) {
+    let rkpd_key = match get_rkpd_attestation_key(&sec_level, AID_KEYSTORE) {
+        Ok(rkpd_key) => rkpd_key,
+        Err(e) => {
+            log::warn!("Failed to get remotely provisioned key for {sec_level:?}: {e:?}");
+            return match wrapped_rkpd_error_to_ks_error(&e) {
+                Error::Rc(ResponseCode::OUT_OF_KEYS_PERMANENT_ERROR) => {
+                    (ProvisioningState::NOT_PROVISIONED, None)
+                }
+                _ => (ProvisioningState::UNKNOWN, None),
+            };
+        }
+    };
+    match parse_subject_from_certificate(&rkpd_key.encodedCertChain) {
+        Ok(issuer_subject_name) => (
+            ProvisioningState::PROVISIONED,
+            Some(AttestationKey {
+                keyBlob: rkpd_key.keyBlob,
+                attestKeyParams: vec![],
+                // Batch certificate is at the beginning of the certificate chain.
+                issuerSubjectName: issuer_subject_name,
+            }),
+        ),
+        Err(e) => {
+            log::error!("Failed to parse subject of remotely provisioned key: {e:?}");
+            (ProvisioningState::PROVISIONED, None)
+        }
+    }
+}
+
+fn provisioning_info(
+    sec_level: SecurityLevel,
+    km_dev: Strong<dyn IKeyMintDevice>,
+    id_values: &[(Tag, Option<Vec<u8>>)],
+) -> ProvisioningInfo {
+    let prober = Prober { sec_level, km_dev };
+    let rkp_supported = get_remotely_provisioned_component_name(&sec_level).is_ok();
+    let (remotely_provisioned_keys, rkpd_key) = if rkp_supported {
+        remotely_provisioned_key(sec_level)
+    } else {
+        (ProvisioningState::NOT_PROVISIONED, None)
+    };
+    let factory_keys = prober.factory_keys();
+    // Prefer factory keys, because remotely provisioned keys are certified for a limited time.
+    let attest_key = match (factory_keys, &rkpd_key) {
+        (ProvisioningState::PROVISIONED, _) => Some(None),
+        (_, Some(rkpd_key)) => Some(Some(rkpd_key)),
+        _ => None,
+    };
+    let attestation_ids = id_values
+        .iter()
+        .map(|(tag, value)| AttestationIdInfo {
+            tag: *tag,
+            state: prober.attestation_id(*tag, value.as_ref(), attest_key),
+        })
+        .collect();
+    ProvisioningInfo {
+        securityLevel: sec_level,
+        attestationIds: attestation_ids,
+        factoryAttestationKeys: factory_keys,
+        rkpSupported: rkp_supported,
+        rkpOnly: RemProvState::new(sec_level).is_rkp_only(),
+        remotelyProvisionedKeys: remotely_provisioned_keys,
+    }
+}
+
+/// Returns the attestation provisioning state of each KeyMint instance. The values of the
+/// telephony IDs are taken from `telephony_ids`. The caller must have been authorized to learn
+/// the device identifiers.
+pub fn get_provisioning_info(telephony_ids: &[KeyParameter]) -> Result<Vec<ProvisioningInfo>> {
+    let id_values = id_values(telephony_ids, read_id_property)?;
+    Ok([SecurityLevel::TRUSTED_ENVIRONMENT, SecurityLevel::STRONGBOX]
+        .into_iter()
+        .filter_map(|sec_level| {
+            let (km_dev, _, _) = get_keymint_device(&sec_level).ok()?;
+            Some(provisioning_info(sec_level, km_dev, &id_values))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(tag: Tag, value: &[u8]) -> KeyParameter {
+        KeyParameter { tag, value: KeyParameterValue::Blob(value.to_vec()) }
+    }
+
+    #[test]
+    fn test_id_values() -> Result<()> {
+        let read_property = |names: &[&str]| match names[0] {
+            "ro.product.brand_for_attestation" => Some(b"brand".to_vec()),
+            "ro.serialno" => Some(b"serial".to_vec()),
+            _ => None,
+        };
+        let values = id_values(&[blob(Tag::ATTESTATION_ID_SECOND_IMEI, b"imei2")], read_property)?;
+        assert_eq!(values.len(), ATTESTATION_IDS.len());
+        let value = |tag: Tag| values.iter().find(|(t, _)| *t == tag).unwrap().1.clone();
+        assert_eq!(value(Tag::ATTESTATION_ID_BRAND), Some(b"brand".to_vec()));
+        assert_eq!(value(Tag::ATTESTATION_ID_SERIAL), Some(b"serial".to_vec()));
+        assert_eq!(value(Tag::ATTESTATION_ID_SECOND_IMEI), Some(b"imei2".to_vec()));
+        assert_eq!(value(Tag::ATTESTATION_ID_IMEI), None);
+        assert_eq!(value(Tag::ATTESTATION_ID_MODEL), None);
+
+        // Only telephony IDs may be passed by the caller.
+        for kp in [
+            blob(Tag::ATTESTATION_ID_BRAND, b"brand"),
+            KeyParameter { tag: Tag::ATTESTATION_ID_IMEI, value: KeyParameterValue::Integer(1) },
+        ] {
+            let e = id_values(&[kp], read_property).unwrap_err();
+            assert_eq!(
+                e.root_cause().downcast_ref::<Error>(),
+                Some(&Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_state() {
+        let not_provisioned = ErrorCode::CANNOT_ATTEST_IDS;
+        assert_eq!(probe_state(&Ok(()), not_provisioned), ProvisioningState::PROVISIONED);
+        assert_eq!(
+            probe_state::<()>(&Err(Error::Km(not_provisioned)), not_provisioned),
+            ProvisioningState::NOT_PROVISIONED
+        );
+        assert_eq!(
+            probe_state::<()>(&Err(Error::Km(ErrorCode::INVALID_TAG)), not_provisioned),
+            ProvisioningState::UNKNOWN
+        );
+        assert_eq!(
+            probe_state::<()>(&Err(Error::Rc(ResponseCode::SYSTEM_ERROR)), not_provisioned),
+            ProvisioningState::UNKNOWN
+        );
+    }
+}
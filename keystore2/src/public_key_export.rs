@@ -0,0 +1,322 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module exports the public key of a key entry in the encodings that callers commonly
+//! need, so that they do not have to parse the X.509 certificate of the key themselves:
+//!  * `DER`: The DER-encoded SubjectPublicKeyInfo.
+//!  * `PEM`: The SubjectPublicKeyInfo as a PEM `PUBLIC KEY` block.
+//!  * `JWK`: A JSON Web Key (RFC 7517) of an EC, RSA, Ed25519 or X25519 key. The members are
+//!    the required members of RFC 7638 in lexicographic order, so the JWK is also the input of
+//!    its thumbprint.
+//!
+//! The SubjectPublicKeyInfo is taken from the certificate of the key entry with BoringSSL. The
+//! JWK is derived from the SubjectPublicKeyInfo here, which only needs to read a few fields.
+
+use crate::error::Error;
+use crate::ks_err;
+use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
+use anyhow::{Context, Result};
+use keystore2_crypto::parse_subject_public_key_info_from_certificate;
+
+/// The encoding of an exported public key. Mirrors `PublicKeyFormat` of IKeystoreService, which
+/// is defined outside this tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKeyFormat {
+    /// DER-encoded SubjectPublicKeyInfo.
+    Der = 0,
+    /// PEM-encoded SubjectPublicKeyInfo.
+    Pem = 1,
+    /// JSON Web Key.
+    Jwk = 2,
+}
+
+impl TryFrom<i32> for PublicKeyFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(format: i32) -> Result<Self> {
+        match format {
+            0 => Ok(Self::Der),
+            1 => Ok(Self::Pem),
+            2 => Ok(Self::Jwk),
+            _ => Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Unknown public key format {}.", format)),
+        }
+    }
+}
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 1.3.101.110
+const OID_X25519: &[u8] = &[0x2b, 0x65, 0x6e];
+/// 1.3.101.112
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// The named curves of EC keys: their OID, JWK name, and field size in bytes.
+const EC_CURVES: &[(&[u8], &str, usize)] = &[
+    // 1.2.840.10045.3.1.7
+    (&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07], "P-256", 32),
+    // 1.3.132.0.34
+    (&[0x2b, 0x81, 0x04, 0x00, 0x22], "P-384", 48),
+    // 1.3.132.0.35
+    (&[0x2b, 0x81, 0x04, 0x00, 0x23], "P-521", 66),
+];
+
+/// Returns the public key in the certificate `cert` in the encoding `format`.
+pub fn export_public_key(cert: &[u8], format: PublicKeyFormat) -> Result<Vec<u8>> {
+    let spki = parse_subject_public_key_info_from_certificate(cert)
+        .context(ks_err!("Failed to extract the public key from the certificate."))?;
+    match format {
+        PublicKeyFormat::Der => Ok(spki),
+        PublicKeyFormat::Pem => Ok(to_pem(&spki).into_bytes()),
+        PublicKeyFormat::Jwk => to_jwk(&spki).map(String::into_bytes),
+    }
+}
+
+fn to_pem(spki: &[u8]) -> String {
+    let encoded = base64(spki, BASE64_ALPHABET, true);
+    let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+    // The base64 alphabet is ASCII, so the chunks are valid UTF-8.
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str("-----END PUBLIC KEY-----\n");
+    pem
+}
+
+fn to_jwk(spki: &[u8]) -> Result<String> {
+    let (algorithm, key) = parse_spki(spki).context(ks_err!("Malformed public key."))?;
+    let mut algorithm_fields = algorithm;
+    let oid = read_element(&mut algorithm_fields, TAG_OID).context(ks_err!())?;
+    let b64url = |data: &[u8]| base64(data, BASE64URL_ALPHABET, false);
+    match oid {
+        OID_EC_PUBLIC_KEY => {
+            let curve = read_element(&mut algorithm_fields, TAG_OID).context(ks_err!())?;
+            let (_, name, size) = EC_CURVES
+                .iter()
+                .find(|(oid, _, _)| *oid == curve)
+                .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("EC curve {:02x?} has no JWK name.", curve))?;
+            // Only uncompressed points are used in certificates.
+            if key.len() != 1 + 2 * size || key[0] != 0x04 {
+                return Err(Error::sys()).context(ks_err!("Malformed EC point."));
+            }
+            let (x, y) = key[1..].split_at(*size);
+            Ok(format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                name,
+                b64url(x),
+                b64url(y)
+            ))
+        }
+        OID_RSA_ENCRYPTION => {
+            let mut input = key;
+            let mut rsa_key = read_element(&mut input, TAG_SEQUENCE).context(ks_err!())?;
+            let n = read_unsigned_integer(&mut rsa_key).context(ks_err!())?;
+            let e = read_unsigned_integer(&mut rsa_key).context(ks_err!())?;
+            Ok(format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, b64url(e), b64url(n)))
+        }
+        OID_ED25519 | OID_X25519 => {
+            let name = if oid == OID_ED25519 { "Ed25519" } else { "X25519" };
+            Ok(format!(r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#, name, b64url(key)))
+        }
+        _ => Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Key algorithm {:02x?} has no JWK encoding.", oid)),
+    }
+}
+
+/// Splits a SubjectPublicKeyInfo into the contents of its AlgorithmIdentifier and the contents
+/// of its public key bit string.
+fn parse_spki(spki: &[u8]) -> Result<(&[u8], &[u8])> {
+    let mut input = spki;
+    let mut spki = read_element(&mut input, TAG_SEQUENCE).context(ks_err!())?;
+    let algorithm = read_element(&mut spki, TAG_SEQUENCE).context(ks_err!())?;
+    let key = read_element(&mut spki, TAG_BIT_STRING).context(ks_err!())?;
+    // The first byte of a bit string is the number of unused bits, which keys don't have.
+    match key.split_first() {
+        Some((0, key)) => Ok((algorithm, key)),
+        _ => Err(Error::sys()).context(ks_err!("Malformed public key bit string.")),
+    }
+}
+
+/// Reads a DER element with the tag `tag` from the front of `input`, and returns its contents.
+fn read_element<'a>(input: &mut &'a [u8], tag: u8) -> Result<&'a [u8]> {
+    let data = *input;
+    let (&actual_tag, rest) = data.split_first().ok_or_else(Error::sys).context(ks_err!())?;
+    if actual_tag != tag {
+        return Err(Error::sys()).context(ks_err!(
+            "Expected tag {:#04x}, found {:#04x}.",
+            tag,
+            actual_tag
+        ));
+    }
+    let (&first, rest) = rest.split_first().ok_or_else(Error::sys).context(ks_err!())?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        // Long form: the low bits give the number of length bytes.
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err(Error::sys()).context(ks_err!("Invalid length."));
+        }
+        let (len, rest) = rest.split_at(count);
+        (len.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize), rest)
+    };
+    if rest.len() < len {
+        return Err(Error::sys()).context(ks_err!("Element exceeds its input."));
+    }
+    let (contents, rest) = rest.split_at(len);
+    *input = rest;
+    Ok(contents)
+}
+
+/// Reads a non-negative DER integer from the front of `input`, and returns its big-endian
+/// magnitude without leading zeros.
+fn read_unsigned_integer<'a>(input: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let mut value = read_element(input, TAG_INTEGER).context(ks_err!())?;
+    if value.first().is_none_or(|b| b & 0x80 != 0) {
+        return Err(Error::sys()).context(ks_err!("Expected a non-negative integer."));
+    }
+    while value.len() > 1 && value[0] == 0 {
+        value = &value[1..];
+    }
+    Ok(value)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encodes `data` in base64 (RFC 4648) with the given alphabet, and with or without padding.
+fn base64(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits =
+            chunk.iter().enumerate().fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+        // A chunk of n bytes is encoded in n + 1 characters.
+        for i in 0..=chunk.len() {
+            encoded.push(alphabet[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        if pad {
+            for _ in chunk.len()..3 {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed certificate of a P-256 key.
+    const EC_CERT: &str = concat!(
+        "3082010a3081b1a003020102020101300a06082a8648ce3d040302300f310d300b06035504030c0474657374",
+        "301e170d3236303130313030303030305a170d3436303130313030303030305a300f310d300b060355040300",
+        "0c04746573743059301306072a8648ce3d020106082a8648ce3d03010703420004ffdef25146798c9b7423c8",
+        "5900857b05d65738a064ad068de99b7c29d8808eaac3b0fcacbc66ec4363daedc12115726ad2bd6673288531",
+        "087aaae2bee129516d300a06082a8648ce3d04030203480030450220715fb815fbad764f7615e5a37725b4d5",
+        "b8a8203b209b6eecdaa508242f75d80c022100b8bc48fcaf31f7ec611e739c6ab79d9c2204d56d939685c57f",
+        "4df67b1a8d6a46"
+    );
+
+    /// The SubjectPublicKeyInfo of `EC_CERT`.
+    const EC_SPKI: &str = concat!(
+        "3059301306072a8648ce3d020106082a8648ce3d03010703420004ffdef25146798c9b7423c85900857b05d6",
+        "5738a064ad068de99b7c29d8808eaac3b0fcacbc66ec4363daedc12115726ad2bd6673288531087aaae2bee1",
+        "29516d"
+    );
+
+    const RSA_SPKI: &str = concat!(
+        "30819f300d06092a864886f70d010101050003818d0030818902818100e661318a043a5d975452fe79ff3821",
+        "dc4e7370fd5e038ff07018c1754c6ffbd54d15d6b42ac79702fce4c9bd5e93721b9820fbc09988e106892c3c",
+        "db0f6fd734c5e1aca6a346ccdddbd17018eaa0b4a778bda387b9011747f4413053708858f8b79eceb43933ea",
+        "ce42dbb4896d8d5668824fed04c521a6dd70934a57d4cf04c30203010001"
+    );
+
+    const ED25519_SPKI: &str =
+        "302a300506032b65700321009b0c86001a5ddc700e89521e2174016474cce5fd27a1352a64c2ce45fd6846ed";
+
+    #[test]
+    fn test_base64() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (b"", "", ""),
+            (b"f", "Zg==", "Zg"),
+            (b"fo", "Zm8=", "Zm8"),
+            (b"foo", "Zm9v", "Zm9v"),
+            (b"foob", "Zm9vYg==", "Zm9vYg"),
+            (&[0xfb, 0xff], "+/8=", "-_8"),
+        ];
+        for (data, b64, b64url) in cases {
+            assert_eq!(base64(data, BASE64_ALPHABET, true), *b64);
+            assert_eq!(base64(data, BASE64URL_ALPHABET, false), *b64url);
+        }
+    }
+
+    #[test]
+    fn test_export_public_key() {
+        let cert = hex::decode(EC_CERT).unwrap();
+        let spki = hex::decode(EC_SPKI).unwrap();
+        assert_eq!(export_public_key(&cert, PublicKeyFormat::Der).unwrap(), spki);
+        assert_eq!(
+            String::from_utf8(export_public_key(&cert, PublicKeyFormat::Pem).unwrap()).unwrap(),
+            concat!(
+                "-----BEGIN PUBLIC KEY-----\n",
+                "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE/97yUUZ5jJt0I8hZAIV7BdZXOKBk\n",
+                "rQaN6Zt8KdiAjqrDsPysvGbsQ2Pa7cEhFXJq0r1mcyiFMQh6quK+4SlRbQ==\n",
+                "-----END PUBLIC KEY-----\n"
+            )
+        );
+        assert_eq!(
+            String::from_utf8(export_public_key(&cert, PublicKeyFormat::Jwk).unwrap()).unwrap(),
+            concat!(
+                r#"{"crv":"P-256","kty":"EC","#,
+                r#""x":"_97yUUZ5jJt0I8hZAIV7BdZXOKBkrQaN6Zt8KdiAjqo","#,
+                r#""y":"w7D8rLxm7ENj2u3BIRVyatK9ZnMohTEIeqrivuEpUW0"}"#
+            )
+        );
+        assert!(export_public_key(&spki, PublicKeyFormat::Der).is_err());
+    }
+
+    #[test]
+    fn test_to_jwk() {
+        assert_eq!(
+            to_jwk(&hex::decode(RSA_SPKI).unwrap()).unwrap(),
+            concat!(
+                r#"{"e":"AQAB","kty":"RSA","n":"5mExigQ6XZdUUv55_zgh3E5zcP1eA4_wcBjBdUxv-9VNFda0K"#,
+                r#"seXAvzkyb1ek3IbmCD7wJmI4QaJLDzbD2_XNMXhrKajRszd29FwGOqgtKd4vaOHuQEXR_RBMFNwiFj"#,
+                r#"4t57OtDkz6s5C27SJbY1WaIJP7QTFIabdcJNKV9TPBMM"}"#
+            )
+        );
+        assert_eq!(
+            to_jwk(&hex::decode(ED25519_SPKI).unwrap()).unwrap(),
+            r#"{"crv":"Ed25519","kty":"OKP","x":"mwyGABpd3HAOiVIeIXQBZHTM5f0noTUqZMLORf1oRu0"}"#
+        );
+
+        // A truncated key is rejected.
+        let spki = hex::decode(EC_SPKI).unwrap();
+        assert!(to_jwk(&spki[..spki.len() - 1]).is_err());
+        assert_eq!(PublicKeyFormat::try_from(2).unwrap(), PublicKeyFormat::Jwk);
+        assert!(PublicKeyFormat::try_from(3).is_err());
+    }
+}
@@ -20,9 +20,8 @@
 //! DB.
 
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    Algorithm::Algorithm, AttestationKey::AttestationKey, Certificate::Certificate,
-    KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel,
-    Tag::Tag,
+    AttestationKey::AttestationKey, Certificate::Certificate, ErrorCode::ErrorCode,
+    KeyParameter::KeyParameter, SecurityLevel::SecurityLevel, Tag::Tag,
 };
 use android_security_rkp_aidl::aidl::android::security::rkp::RemotelyProvisionedKey::RemotelyProvisionedKey;
 use android_system_keystore2::aidl::android::system::keystore2::{
@@ -31,16 +30,18 @@ use android_system_keystore2::aidl::android::system::keystore2::{
 use anyhow::{Context, Result};
 use keystore2_crypto::parse_subject_from_certificate;
 
-use crate::error::wrapped_rkpd_error_to_ks_error;
+use crate::error::{wrapped_rkpd_error_to_ks_error, Error};
 use crate::globals::get_remotely_provisioned_component_name;
 use crate::ks_err;
 use crate::metrics_store::log_rkp_error_stats;
+use crate::test_hooks;
+use crate::utils::is_asymmetric_key;
 use crate::watchdog_helper::watchdog as wd;
 use android_security_metrics::aidl::android::security::metrics::RkpError::RkpError as MetricsRkpError;
 
 /// Contains helper functions to check if remote provisioning is enabled on the system and, if so,
 /// to assign and retrieve attestation keys and certificate chains.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct RemProvState {
     security_level: SecurityLevel,
 }
@@ -51,7 +52,11 @@ impl RemProvState {
         Self { security_level }
     }
 
-    fn is_rkp_only(&self) -> bool {
+    /// Returns true if attestation on this security level must use remotely provisioned keys.
+    pub fn is_rkp_only(&self) -> bool {
+        if self.is_rkp_only_simulated() {
+            return true;
+        }
         let default_value = false;
 
         let property_name = match self.security_level {
@@ -64,19 +69,27 @@ impl RemProvState {
             .unwrap_or(default_value)
     }
 
-    fn is_asymmetric_key(&self, params: &[KeyParameter]) -> bool {
-        params.iter().any(|kp| {
-            matches!(
-                kp,
-                KeyParameter {
-                    tag: Tag::ALGORITHM,
-                    value: KeyParameterValue::Algorithm(Algorithm::RSA)
-                } | KeyParameter {
-                    tag: Tag::ALGORITHM,
-                    value: KeyParameterValue::Algorithm(Algorithm::EC)
-                }
-            )
-        })
+    /// Returns true if a test asked keystore2 through IKeystoreTestHooks to behave as if this
+    /// security level were RKP-only: RKPD failures are not papered over by falling back to the
+    /// factory provisioned attestation key, and requests that would be attested by the factory key
+    /// fail with ATTESTATION_KEYS_NOT_PROVISIONED. This gives the RKP-only code paths coverage on
+    /// devices that still have factory certificates.
+    pub fn is_rkp_only_simulated(&self) -> bool {
+        matches!(self.security_level, SecurityLevel::STRONGBOX | SecurityLevel::TRUSTED_ENVIRONMENT)
+            && test_hooks::is_rkp_only_simulated()
+    }
+
+    /// Called before a key gets attested by the factory provisioned attestation key. Fails the
+    /// way an RKP-only KeyMint device would if RKP-only mode is simulated. Device unique
+    /// attestation does not use the batch key and is not affected.
+    pub fn check_factory_attestation(&self, params: &[KeyParameter]) -> Result<()> {
+        if self.is_rkp_only_simulated()
+            && !params.iter().any(|kp| kp.tag == Tag::DEVICE_UNIQUE_ATTESTATION)
+        {
+            return Err(Error::Km(ErrorCode::ATTESTATION_KEYS_NOT_PROVISIONED))
+                .context(ks_err!("Factory attestation is disabled while simulating RKP-only."));
+        }
+        Ok(())
     }
 
     /// Fetches attestation key and corresponding certificates from RKPD.
@@ -86,7 +99,7 @@ impl RemProvState {
         caller_uid: u32,
         params: &[KeyParameter],
     ) -> Result<Option<(AttestationKey, Certificate)>> {
-        if !self.is_asymmetric_key(params) || key.domain != Domain::APP {
+        if !is_asymmetric_key(params) || key.domain != Domain::APP {
             Ok(None)
         } else {
             match get_rkpd_attestation_key(&self.security_level, caller_uid) {
@@ -119,7 +132,8 @@ impl RemProvState {
     }
 }
 
-fn get_rkpd_attestation_key(
+/// Fetches the remotely provisioned attestation key assigned to `caller_uid` from RKPD.
+pub fn get_rkpd_attestation_key(
     security_level: &SecurityLevel,
     caller_uid: u32,
 ) -> Result<RemotelyProvisionedKey> {
@@ -16,46 +16,72 @@
 
 use crate::attestation_key_utils::{get_attest_key_info, AttestationKeyInfo};
 use crate::audit_log::{
-    log_key_deleted, log_key_generated, log_key_imported, log_key_integrity_violation,
+    audited_key, log_key_deleted, log_key_generated, log_key_imported, log_key_integrity_violation,
+    log_key_use,
 };
+use crate::calling_identity;
+use crate::cpu_accounting;
 use crate::database::{BlobInfo, CertificateInfo, KeyIdGuard};
 use crate::error::{
-    self, into_logged_binder, map_km_error, wrapped_rkpd_error_to_ks_error, Error, ErrorCode,
+    self, anyhow_error_to_serialized_error, into_logged_binder, map_km_error,
+    wrapped_rkpd_error_to_ks_error, Error, ErrorCode,
 };
+use crate::esim_profiles::ESIM_PROFILES;
+use crate::events::publish_key_event;
 use crate::globals::{
-    get_remotely_provisioned_component_name, DB, ENFORCEMENTS, LEGACY_IMPORTER, SUPER_KEY,
+    get_additional_strongbox_instances, get_remotely_provisioned_component_name,
+    get_strongbox_instance, DB, ENFORCEMENTS, IMPORT_LIMITER, KEY_ENTRY_CACHE, LEGACY_IMPORTER,
+    SUPER_KEY,
 };
+use crate::import_formats::translate_to_pkcs8;
+use crate::key_descriptor_validation::{check_key_descriptor, DescriptorUse};
 use crate::key_parameter::KeyParameter as KsKeyParam;
 use crate::key_parameter::KeyParameterValue as KsKeyParamValue;
+use crate::key_rotation::{check_retained_key_use, previous_key_alias, retain_previous_until};
+use crate::key_strength::check_key_strength;
+use crate::key_templates::KEY_TEMPLATES;
+use crate::key_usage::KEY_USAGE;
 use crate::ks_err;
 use crate::metrics_store::log_key_creation_event_stats;
+use crate::namespace_quotas::check_namespace_quota;
 use crate::remote_provisioning::RemProvState;
+use crate::session_keys::{SessionKeyOwner, KEY_FLAG_SESSION_BOUND, SESSION_KEYS};
+use crate::slo_monitor::{self, SloApi};
+use crate::storage_tier::storage_tier;
 use crate::super_key::{KeyBlob, SuperKeyManager};
+use crate::test_hooks;
 use crate::utils::{
-    check_device_attestation_permissions, check_key_permission,
-    check_unique_id_attestation_permissions, is_device_id_attestation_tag,
-    key_characteristics_to_internal, log_security_safe_params, uid_to_android_user, watchdog as wd,
-    UNDEFINED_NOT_AFTER,
+    canonicalize_key_parameters, check_device_attestation_permissions, check_key_permission,
+    check_keystore_permission, check_unique_id_attestation_permissions, is_asymmetric_key,
+    is_device_id_attestation_tag, key_characteristics_to_internal, log_security_safe_params,
+    uid_to_android_user, watchdog as wd, UNDEFINED_NOT_AFTER,
 };
+use crate::validity_policy::KEY_VALIDITY_POLICY;
 use crate::{
     database::{
-        BlobMetaData, BlobMetaEntry, DateTime, KeyEntry, KeyEntryLoadBits, KeyMetaData,
-        KeyMetaEntry, KeyType, SubComponentType, Uuid,
+        AttestationSource, BlobMetaData, BlobMetaEntry, DateTime, KeyEntry, KeyEntryLoadBits,
+        KeyLifecycleState, KeyMetaData, KeyMetaEntry, KeyProvenance, KeyType, SubComponentType,
+        Uuid,
     },
     operation::KeystoreOperation,
     operation::LoggingInfo,
+    operation::OperationBudget,
     operation::OperationDb,
-    permission::KeyPerm,
+    operation::Outcome,
+    permission::{KeyPerm, KeystorePerm},
 };
 use crate::{globals::get_keymint_device, id_rotation::IdRotationState};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    Algorithm::Algorithm, AttestationKey::AttestationKey,
+    Algorithm::Algorithm, AttestationKey::AttestationKey, Digest::Digest,
     HardwareAuthenticatorType::HardwareAuthenticatorType, IKeyMintDevice::IKeyMintDevice,
     KeyCreationResult::KeyCreationResult, KeyFormat::KeyFormat,
     KeyMintHardwareInfo::KeyMintHardwareInfo, KeyParameter::KeyParameter,
-    KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel, Tag::Tag,
+    KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, PaddingMode::PaddingMode,
+    SecurityLevel::SecurityLevel, Tag::Tag,
 };
-use android_hardware_security_keymint::binder::{BinderFeatures, Strong, ThreadState};
+use android_hardware_security_keymint::binder::{BinderFeatures, Strong};
+use android_security_keyliveness::aidl::android::security::keyliveness::KeyLivenessAttestation::KeyLivenessAttestation;
+use android_security_maintenance::aidl::android::security::maintenance::KeystoreEventType::KeystoreEventType;
 use android_system_keystore2::aidl::android::system::keystore2::{
     AuthenticatorSpec::AuthenticatorSpec, CreateOperationResponse::CreateOperationResponse,
     Domain::Domain, EphemeralStorageKeyResponse::EphemeralStorageKeyResponse,
@@ -64,48 +90,277 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     KeyMetadata::KeyMetadata, KeyParameters::KeyParameters, ResponseCode::ResponseCode,
 };
 use anyhow::{anyhow, Context, Result};
+use keystore2_crypto::{ec_key_normalize_private_key, Error as CryptoError, ZVec};
 use rkpd_client::store_rkpd_attestation_key;
+use serde_cbor::Value;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::time::SystemTime;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
 /// Implementation of the IKeystoreSecurityLevel Interface.
+#[derive(Clone)]
 pub struct KeystoreSecurityLevel {
     security_level: SecurityLevel,
     keymint: Strong<dyn IKeyMintDevice>,
     hw_info: KeyMintHardwareInfo,
     km_uuid: Uuid,
-    operation_db: OperationDb,
+    operation_db: Arc<OperationDb>,
     rem_prov_state: RemProvState,
     id_rotation_state: IdRotationState,
+    // Additional KeyMint instances of this security level by instance name, e.g., the
+    // StrongBox in an eSIM on devices with two secure elements.
+    instances: HashMap<String, KeystoreSecurityLevel>,
 }
 
 // Blob of 32 zeroes used as empty masking key.
 static ZERO_BLOB_32: &[u8] = &[0; 32];
 
+// Identifies the format of the statements created by `attest_key_liveness`.
+const KEY_LIVENESS_STATEMENT_VERSION: &str = "android.keystore2.key_liveness.v1";
+
+/// The security levels of this keystore instance, for the services that need more than the
+/// IKeystoreSecurityLevel interface.
+static NATIVE_SECURITY_LEVELS: LazyLock<
+    RwLock<HashMap<SecurityLevel, Arc<KeystoreSecurityLevel>>>,
+> = LazyLock::new(Default::default);
+
+/// Returns the security level object of `security_level` if keystore is connected to it.
+pub fn native_security_level(security_level: SecurityLevel) -> Option<Arc<KeystoreSecurityLevel>> {
+    NATIVE_SECURITY_LEVELS.read().unwrap().get(&security_level).cloned()
+}
+
+/// The maximum number of keys of a `KeystoreSecurityLevel::generate_keys` batch.
+const MAX_KEY_GENERATION_BATCH: usize = 64;
+
+/// The maximum number of concurrent KeyMint calls of all key generation batches, not counting
+/// the binder threads that serve the batches.
+const MAX_CONCURRENT_KEY_GENERATIONS: usize = 4;
+
+/// The helper threads that key generation batches share, so that concurrent batches do not
+/// multiply the load on KeyMint.
+static KEY_GENERATION_HELPERS: ThreadBudget = ThreadBudget::new(MAX_CONCURRENT_KEY_GENERATIONS);
+
+/// One key of a `KeystoreSecurityLevel::generate_keys` batch, with the arguments of
+/// `IKeystoreSecurityLevel::generateKey`.
+#[derive(Debug, Clone)]
+pub struct KeyGenerationRequest {
+    /// The descriptor of the new key.
+    pub key: KeyDescriptor,
+    /// The key that attests the new key, if any.
+    pub attestation_key: Option<KeyDescriptor>,
+    /// The parameters of the new key.
+    pub params: Vec<KeyParameter>,
+    /// The flags of the new key, see `IKeystoreSecurityLevel::generateKey`.
+    pub flags: i32,
+    /// Additional entropy, see `IKeystoreSecurityLevel::generateKey`.
+    pub entropy: Vec<u8>,
+}
+
+/// A key generation request that passed all checks, with the parameters that are passed to
+/// KeyMint.
+struct PreparedKeyGeneration {
+    key: KeyDescriptor,
+    caller_uid: u32,
+    attestation_key_info: Option<AttestationKeyInfo>,
+    params: Vec<KeyParameter>,
+    max_validity_expiration: Option<DateTime>,
+    attestation_source: AttestationSource,
+}
+
+/// How a key is created, for `KeystoreSecurityLevel::log_key_creation`.
+#[derive(Clone, Copy)]
+enum KeyCreation {
+    Generated,
+    Imported,
+}
+
+/// A number of threads that callers of `map_concurrently` share.
+struct ThreadBudget {
+    available: Mutex<usize>,
+}
+
+impl ThreadBudget {
+    const fn new(threads: usize) -> Self {
+        Self { available: Mutex::new(threads) }
+    }
+
+    /// Takes up to `wanted` threads from the budget without waiting. They are returned when the
+    /// grant is dropped.
+    fn take(&self, wanted: usize) -> ThreadGrant<'_> {
+        let mut available = self.available.lock().unwrap();
+        let threads = wanted.min(*available);
+        *available -= threads;
+        ThreadGrant { budget: self, threads }
+    }
+}
+
+/// Threads taken from a `ThreadBudget`.
+struct ThreadGrant<'a> {
+    budget: &'a ThreadBudget,
+    threads: usize,
+}
+
+impl Drop for ThreadGrant<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.threads;
+    }
+}
+
+/// Maps `items` with `f` on the calling thread and up to `helpers` additional threads and
+/// returns the results in the order of the items.
+fn map_concurrently<T: Send, R: Send>(
+    items: Vec<T>,
+    helpers: usize,
+    f: impl Fn(T) -> R + Sync,
+) -> Vec<R> {
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let work = || {
+        let mut results = Vec::new();
+        loop {
+            let next = queue.lock().unwrap().next();
+            let Some((i, item)) = next else { break };
+            results.push((i, f(item)));
+        }
+        results
+    };
+    let mut results: Vec<(usize, R)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..helpers).map(|_| s.spawn(&work)).collect();
+        let mut results = work();
+        results
+            .extend(workers.into_iter().flat_map(|w| w.join().expect("Worker thread panicked.")));
+        results
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Returns true if the tag is part of the authorization policy of a key, i.e., if it restricts
+/// how, when, or by whom the key may be used.
+fn is_auth_policy_tag(tag: Tag) -> bool {
+    matches!(
+        tag,
+        Tag::PURPOSE
+            | Tag::USER_SECURE_ID
+            | Tag::NO_AUTH_REQUIRED
+            | Tag::USER_AUTH_TYPE
+            | Tag::AUTH_TIMEOUT
+            | Tag::ALLOW_WHILE_ON_BODY
+            | Tag::TRUSTED_USER_PRESENCE_REQUIRED
+            | Tag::TRUSTED_CONFIRMATION_REQUIRED
+            | Tag::UNLOCKED_DEVICE_REQUIRED
+            | Tag::ACTIVE_DATETIME
+            | Tag::ORIGINATION_EXPIRE_DATETIME
+            | Tag::USAGE_EXPIRE_DATETIME
+            | Tag::USAGE_COUNT_LIMIT
+            | Tag::MAX_USES_PER_BOOT
+            | Tag::MIN_SECONDS_BETWEEN_OPS
+    )
+}
+
+/// The system property that makes `create_operation` echo the effective operation parameters
+/// in the response, so that developers can see what was negotiated with KeyMint. It can be set
+/// from the shell with `setprop debug.keystore.echo_operation_parameters true`.
+const ECHO_OPERATION_PARAMETERS_PROPERTY: &str = "debug.keystore.echo_operation_parameters";
+
+fn echo_operation_parameters() -> bool {
+    rustutils::system_properties::read_bool(ECHO_OPERATION_PARAMETERS_PROPERTY, false)
+        .unwrap_or(false)
+}
+
+/// Returns the parameters that an operation was begun with: the purpose, the parameters passed
+/// to KeyMint, and the parameters returned by KeyMint, e.g., a generated nonce. A returned
+/// parameter replaces a passed parameter with the same tag. The purpose, which callers pass as
+/// a parameter as well, is listed only once.
+fn effective_operation_parameters(
+    purpose: KeyPurpose,
+    passed: &[KeyParameter],
+    returned: &[KeyParameter],
+) -> Vec<KeyParameter> {
+    std::iter::once(KeyParameter {
+        tag: Tag::PURPOSE,
+        value: KeyParameterValue::KeyPurpose(purpose),
+    })
+    .chain(
+        passed
+            .iter()
+            .filter(|p| p.tag != Tag::PURPOSE && !returned.iter().any(|r| r.tag == p.tag))
+            .cloned(),
+    )
+    .chain(returned.iter().filter(|p| p.tag != Tag::PURPOSE).cloned())
+    .collect()
+}
+
 impl KeystoreSecurityLevel {
     /// Creates a new security level instance wrapped in a
     /// BnKeystoreSecurityLevel proxy object. It also enables
     /// `BinderFeatures::set_requesting_sid` on the new interface, because
     /// we need it for checking keystore permissions.
+    /// Besides the uuid of the KeyMint instance of the security level, this returns the uuids
+    /// of the additional KeyMint instances that are served by the same object.
     pub fn new_native_binder(
         security_level: SecurityLevel,
         id_rotation_state: IdRotationState,
-    ) -> Result<(Strong<dyn IKeystoreSecurityLevel>, Uuid)> {
+    ) -> Result<(Strong<dyn IKeystoreSecurityLevel>, Uuid, Vec<Uuid>)> {
         let (dev, hw_info, km_uuid) = get_keymint_device(&security_level)
             .context(ks_err!("KeystoreSecurityLevel::new_native_binder."))?;
+        let mut instances = HashMap::new();
+        if security_level == SecurityLevel::STRONGBOX {
+            for instance in get_additional_strongbox_instances() {
+                match get_strongbox_instance(&instance) {
+                    Ok((dev, hw_info, km_uuid)) => {
+                        log::info!("Found additional StrongBox instance {instance}.");
+                        let instance_sec_level = Self::new(
+                            security_level,
+                            dev,
+                            hw_info,
+                            km_uuid,
+                            id_rotation_state.clone(),
+                            HashMap::new(),
+                        );
+                        instances.insert(instance, instance_sec_level);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to connect to StrongBox instance {instance}: {e:?}")
+                    }
+                }
+            }
+        }
+        let instance_uuids = instances.values().map(|instance| instance.km_uuid).collect();
+        let sec_level =
+            Self::new(security_level, dev, hw_info, km_uuid, id_rotation_state, instances);
+        // The copy shares the KeyMint connections and the operation database with the binder.
+        NATIVE_SECURITY_LEVELS.write().unwrap().insert(security_level, Arc::new(sec_level.clone()));
         let result = BnKeystoreSecurityLevel::new_binder(
-            Self {
-                security_level,
-                keymint: dev,
-                hw_info,
-                km_uuid,
-                operation_db: OperationDb::new(),
-                rem_prov_state: RemProvState::new(security_level),
-                id_rotation_state,
-            },
+            sec_level,
             BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
         );
-        Ok((result, km_uuid))
+        Ok((result, km_uuid, instance_uuids))
+    }
+
+    fn new(
+        security_level: SecurityLevel,
+        keymint: Strong<dyn IKeyMintDevice>,
+        hw_info: KeyMintHardwareInfo,
+        km_uuid: Uuid,
+        id_rotation_state: IdRotationState,
+        instances: HashMap<String, KeystoreSecurityLevel>,
+    ) -> Self {
+        Self {
+            security_level,
+            keymint,
+            hw_info,
+            km_uuid,
+            operation_db: OperationDb::new_registered(),
+            rem_prov_state: RemProvState::new(security_level),
+            id_rotation_state,
+            instances,
+        }
+    }
+
+    fn instance_by_uuid(&self, km_uuid: &Uuid) -> Option<&KeystoreSecurityLevel> {
+        self.instances.values().find(|instance| instance.km_uuid == *km_uuid)
     }
 
     fn watch_millis(&self, id: &'static str, millis: u64) -> Option<wd::WatchPoint> {
@@ -118,12 +373,46 @@ impl KeystoreSecurityLevel {
         wd::watch_millis_with(id, wd::DEFAULT_TIMEOUT_MS, sec_level)
     }
 
+    /// Collects the provenance information for a newly created key with the given
+    /// key characteristics.
+    fn key_provenance(
+        &self,
+        key_parameters: &[KsKeyParam],
+        attestation_source: AttestationSource,
+    ) -> KeyProvenance {
+        let mut provenance = KeyProvenance {
+            build_fingerprint: rustutils::system_properties::read("ro.build.fingerprint")
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to read build fingerprint: {e:?}");
+                    None
+                }),
+            attestation_source: Some(attestation_source),
+            km_version: Some(self.hw_info.versionNumber),
+            ..Default::default()
+        };
+        for param in key_parameters {
+            match param.key_parameter_value() {
+                KsKeyParamValue::OSPatchLevel(v) => provenance.os_patch_level = Some(*v),
+                KsKeyParamValue::VendorPatchLevel(v) => provenance.vendor_patch_level = Some(*v),
+                KsKeyParamValue::BootPatchLevel(v) => provenance.boot_patch_level = Some(*v),
+                _ => {}
+            }
+        }
+        provenance
+    }
+
+    /// Stores a newly created key. If `retain_previous_until` is given, the key that the alias
+    /// was bound to before is retained until then, see `key_rotation`.
+    #[allow(clippy::too_many_arguments)]
     fn store_new_key(
         &self,
         key: KeyDescriptor,
         creation_result: KeyCreationResult,
         user_id: u32,
         flags: Option<i32>,
+        attestation_source: AttestationSource,
+        max_validity_expiration: Option<DateTime>,
+        retain_previous_until: Option<DateTime>,
     ) -> Result<KeyMetadata> {
         let KeyCreationResult {
             keyBlob: key_blob,
@@ -157,6 +446,18 @@ impl KeystoreSecurityLevel {
 
         let creation_date = DateTime::now().context(ks_err!("Trying to make creation time."))?;
 
+        // Session-bound keys are stored by keystore, so that it can delete them.
+        let session_owner = match flags {
+            Some(flags) if flags & KEY_FLAG_SESSION_BOUND != 0 => {
+                if key.domain == Domain::BLOB {
+                    return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                        .context(ks_err!("Domain::BLOB keys cannot be session-bound."));
+                }
+                Some(SessionKeyOwner::of_caller().context(ks_err!())?)
+            }
+            _ => None,
+        };
+
         let key = match key.domain {
             Domain::BLOB => KeyDescriptor {
                 domain: Domain::BLOB,
@@ -176,6 +477,7 @@ impl KeystoreSecurityLevel {
                             &(key.domain),
                             &key_parameters,
                             flags,
+                            storage_tier(key.domain, key.nspace),
                             user_id,
                             &key_blob,
                         )
@@ -183,19 +485,43 @@ impl KeystoreSecurityLevel {
 
                     let mut key_metadata = KeyMetaData::new();
                     key_metadata.add(KeyMetaEntry::CreationDate(creation_date));
+                    self.key_provenance(&key_parameters, attestation_source)
+                        .add_to_metadata(&mut key_metadata);
+                    if let Some(expiration) = max_validity_expiration {
+                        key_metadata.add(KeyMetaEntry::MaxValidityExpirationDate(expiration));
+                    }
+                    if session_owner.is_some() {
+                        key_metadata.add(KeyMetaEntry::SessionBound(true));
+                    }
                     blob_metadata.add(BlobMetaEntry::KmUuid(self.km_uuid));
 
-                    let key_id = db
-                        .store_new_key(
+                    let blob_info = BlobInfo::new(&key_blob, &blob_metadata);
+                    let key_id = match retain_previous_until {
+                        Some(until) => db.store_rotated_key(
                             &key,
                             KeyType::Client,
                             &key_parameters,
-                            &BlobInfo::new(&key_blob, &blob_metadata),
+                            &blob_info,
                             &cert_info,
                             &key_metadata,
                             &self.km_uuid,
-                        )
-                        .context(ks_err!())?;
+                            until,
+                        ),
+                        None => db.store_new_key(
+                            &key,
+                            KeyType::Client,
+                            &key_parameters,
+                            &blob_info,
+                            &cert_info,
+                            &key_metadata,
+                            &self.km_uuid,
+                        ),
+                    }
+                    .context(ks_err!())?;
+                    KEY_ENTRY_CACHE.invalidate_namespace(key.domain, key.nspace);
+                    if let Some(owner) = session_owner {
+                        SESSION_KEYS.register(key_id.id(), owner);
+                    }
                     Ok(KeyDescriptor {
                         domain: Domain::KEY_ID,
                         nspace: key_id.id(),
@@ -220,8 +546,10 @@ impl KeystoreSecurityLevel {
         key: &KeyDescriptor,
         operation_parameters: &[KeyParameter],
         forced: bool,
+        prune_exemption: Option<Duration>,
     ) -> Result<CreateOperationResponse> {
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
         // We use `scoping_blob` to extend the life cycle of the blob loaded from the database,
         // so that we can use it by reference like the blob provided by the key descriptor.
         // Otherwise, we would have to clone the blob from the key descriptor.
@@ -254,6 +582,9 @@ impl KeystoreSecurityLevel {
                     .read()
                     .unwrap()
                     .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+                // The access vector of the access check, which tells if the key is used through
+                // a grant, whatever the domain of `key`.
+                let access_vector = Cell::new(None);
                 let (key_id_guard, mut key_entry) = DB
                     .with::<_, Result<(KeyIdGuard, KeyEntry)>>(|db| {
                         LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
@@ -267,6 +598,7 @@ impl KeystoreSecurityLevel {
                                     if forced {
                                         check_key_permission(KeyPerm::ReqForcedOp, k, &av)?;
                                     }
+                                    access_vector.set(av);
                                     Ok(())
                                 },
                             )
@@ -274,6 +606,22 @@ impl KeystoreSecurityLevel {
                     })
                     .context(ks_err!("Failed to load key blob."))?;
 
+                match key_entry.lifecycle_state() {
+                    KeyLifecycleState::Invalidated => {
+                        return Err(Error::Km(ErrorCode::KEY_PERMANENTLY_INVALIDATED))
+                            .context(ks_err!("Key was permanently invalidated."));
+                    }
+                    KeyLifecycleState::Retained => {
+                        check_retained_key_use(&access_vector.get()).context(ks_err!())?;
+                    }
+                    KeyLifecycleState::Tombstoned => {
+                        return Err(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+                            .context(ks_err!("The grace period of the retained key ended."));
+                    }
+                    _ => {}
+                }
+                ESIM_PROFILES.check_key_unlocked(key_id_guard.id()).context(ks_err!())?;
+
                 let (blob, blob_metadata) =
                     key_entry.take_key_blob_info().ok_or_else(Error::sys).context(ks_err!(
                         "Successfully loaded key entry, \
@@ -290,6 +638,23 @@ impl KeystoreSecurityLevel {
             }
         };
 
+        // Keys that live on an additional KeyMint instance of this security level must be used
+        // with that instance. It loads the key again, so the key id lock must be released.
+        if let Some(instance) = blob_metadata.km_uuid().and_then(|uuid| self.instance_by_uuid(uuid))
+        {
+            drop(key_id_guard);
+            return instance.create_operation(key, operation_parameters, forced, prune_exemption);
+        }
+
+        let prune_exemption = prune_exemption
+            .map(|duration| self.operation_db.grant_prune_exemption(caller_uid, duration))
+            .transpose()
+            .context(ks_err!("Failed to grant a pruning exemption."))?;
+
+        let operation_parameters = canonicalize_key_parameters(operation_parameters)
+            .context(ks_err!("Invalid operation parameters."))?;
+        let operation_parameters = operation_parameters.as_slice();
+
         let purpose = operation_parameters.iter().find(|p| p.tag == Tag::PURPOSE).map_or(
             Err(Error::Km(ErrorCode::INVALID_ARGUMENT))
                 .context(ks_err!("No operation purpose specified.")),
@@ -321,6 +686,8 @@ impl KeystoreSecurityLevel {
             .unwrap_key_if_required(&blob_metadata, km_blob)
             .context(ks_err!("Failed to handle super encryption."))?;
 
+        let audited_key = key_properties.as_ref().and_then(|(key_id, _)| audited_key(*key_id));
+
         let (begin_result, upgraded_blob) = self
             .upgrade_keyblob_if_required_with(
                 key_id_guard,
@@ -329,18 +696,22 @@ impl KeystoreSecurityLevel {
                 operation_parameters,
                 |blob| loop {
                     match map_km_error({
+                        let _slo = slo_monitor::time(SloApi::Begin, self.security_level);
                         let _wp = self.watch(
                             "KeystoreSecurityLevel::create_operation: calling IKeyMintDevice::begin",
                         );
-                        self.keymint.begin(
-                            purpose,
-                            blob,
-                            operation_parameters,
-                            immediate_hat.as_ref(),
-                        )
+                        test_hooks::hal_call("IKeyMintDevice::begin", || {
+                            self.keymint.begin(
+                                purpose,
+                                blob,
+                                operation_parameters,
+                                immediate_hat.as_ref(),
+                            )
+                        })
                     }) {
                         Err(Error::Km(ErrorCode::TOO_MANY_OPERATIONS)) => {
-                            self.operation_db.prune(caller_uid, forced)?;
+                            self.operation_db
+                                .prune(caller_uid, forced || prune_exemption.is_some())?;
                             continue;
                         }
                         v @ Err(Error::Km(ErrorCode::INVALID_KEY_BLOB)) => {
@@ -355,15 +726,54 @@ impl KeystoreSecurityLevel {
                             }
                             return v;
                         }
+                        v @ Err(Error::Km(ErrorCode::KEY_PERMANENTLY_INVALIDATED)) => {
+                            if let Some((key_id, _)) = key_properties {
+                                if let Err(e) =
+                                    DB.with(|db| db.borrow_mut().mark_key_invalidated(key_id))
+                                {
+                                    log::error!("Failed to mark key as invalidated: {e:?}");
+                                }
+                            }
+                            return v;
+                        }
                         v => return v,
                     }
                 },
             )
+            .inspect_err(|e| {
+                if let Some(audited_key) = &audited_key {
+                    let outcome = Outcome::ErrorCode(anyhow_error_to_serialized_error(e));
+                    log_key_use(audited_key, caller_uid, purpose, outcome);
+                }
+            })
             .context(ks_err!("Failed to begin operation."))?;
 
+        // The nonce is recorded only once KeyMint accepted it, so that callers can retry with the
+        // same nonce if begin fails, e.g., because the user must authenticate first.
+        if let (Some((key_id, _)), Some(km_op)) = (&key_properties, &begin_result.operation) {
+            if !ENFORCEMENTS.insert_caller_nonce(purpose, *key_id, operation_parameters) {
+                if let Err(e) = map_km_error(km_op.abort()) {
+                    log::warn!("Failed to abort operation with reused nonce: {e:?}");
+                }
+                return Err(Error::Km(ErrorCode::INVALID_NONCE))
+                    .context(ks_err!("NONCE was used recently with this key."));
+            }
+        }
+
+        if let (Some((key_id, _)), Ok(now)) = (&key_properties, DateTime::now()) {
+            KEY_USAGE.record_use(*key_id, now);
+        }
+
         let operation_challenge = auth_info.finalize_create_authorization(begin_result.challenge);
 
+        let parameters = if echo_operation_parameters() {
+            effective_operation_parameters(purpose, operation_parameters, &begin_result.params)
+        } else {
+            begin_result.params
+        };
+
         let op_params: Vec<KeyParameter> = operation_parameters.to_vec();
+        let key_params = key_properties.map(|(_, key_params)| key_params).unwrap_or_default();
 
         let operation = match begin_result.operation {
             Some(km_op) => self.operation_db.create_operation(
@@ -371,7 +781,15 @@ impl KeystoreSecurityLevel {
                 caller_uid,
                 auth_info,
                 forced,
-                LoggingInfo::new(self.security_level, purpose, op_params, upgraded_blob.is_some()),
+                prune_exemption,
+                LoggingInfo::new(
+                    self.security_level,
+                    purpose,
+                    op_params,
+                    key_params,
+                    upgraded_blob.is_some(),
+                )
+                .with_audited_key(audited_key),
             ),
             None => {
                 return Err(Error::sys()).context(ks_err!(
@@ -390,9 +808,9 @@ impl KeystoreSecurityLevel {
         Ok(CreateOperationResponse {
             iOperation: Some(op_binder),
             operationChallenge: operation_challenge,
-            parameters: match begin_result.params.len() {
+            parameters: match parameters.len() {
                 0 => None,
-                _ => Some(KeyParameters { keyParameter: begin_result.params }),
+                _ => Some(KeyParameters { keyParameter: parameters }),
             },
             // An upgraded blob should only be returned if the caller has permission
             // to use Domain::BLOB keys. If we got to this point, we already checked
@@ -406,9 +824,12 @@ impl KeystoreSecurityLevel {
         uid: u32,
         params: &[KeyParameter],
         key: &KeyDescriptor,
-    ) -> Result<Vec<KeyParameter>> {
+    ) -> Result<(Vec<KeyParameter>, Option<DateTime>)> {
         let mut result = params.to_vec();
 
+        // Reject keys below the minimum strength if device policy enforces it.
+        check_key_strength(uid, key.domain, params).context(ks_err!())?;
+
         // Prevent callers from specifying the CREATION_DATETIME tag.
         if params.iter().any(|kp| kp.tag == Tag::CREATION_DATETIME) {
             return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
@@ -421,24 +842,24 @@ impl KeystoreSecurityLevel {
         // quering the clock multiple times.
         let creation_datetime = SystemTime::now();
 
+        let creation_millis: i64 = creation_datetime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context(ks_err!(
+                "KeystoreSecurityLevel::add_required_parameters: \
+                    Failed to get epoch time."
+            ))?
+            .as_millis()
+            .try_into()
+            .context(ks_err!(
+                "KeystoreSecurityLevel::add_required_parameters: \
+                    Failed to convert epoch time."
+            ))?;
+
         // Add CREATION_DATETIME only if the backend version Keymint V1 (100) or newer.
         if self.hw_info.versionNumber >= 100 {
             result.push(KeyParameter {
                 tag: Tag::CREATION_DATETIME,
-                value: KeyParameterValue::DateTime(
-                    creation_datetime
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .context(ks_err!(
-                            "KeystoreSecurityLevel::add_required_parameters: \
-                                Failed to get epoch time."
-                        ))?
-                        .as_millis()
-                        .try_into()
-                        .context(ks_err!(
-                            "KeystoreSecurityLevel::add_required_parameters: \
-                                Failed to convert epoch time."
-                        ))?,
-                ),
+                value: KeyParameterValue::DateTime(creation_millis),
             });
         }
 
@@ -493,25 +914,29 @@ impl KeystoreSecurityLevel {
 
         // If we are generating/importing an asymmetric key, we need to make sure
         // that NOT_BEFORE and NOT_AFTER are present.
-        match params.iter().find(|kp| kp.tag == Tag::ALGORITHM) {
-            Some(KeyParameter { tag: _, value: KeyParameterValue::Algorithm(Algorithm::RSA) })
-            | Some(KeyParameter { tag: _, value: KeyParameterValue::Algorithm(Algorithm::EC) }) => {
-                if !params.iter().any(|kp| kp.tag == Tag::CERTIFICATE_NOT_BEFORE) {
-                    result.push(KeyParameter {
-                        tag: Tag::CERTIFICATE_NOT_BEFORE,
-                        value: KeyParameterValue::DateTime(0),
-                    })
-                }
-                if !params.iter().any(|kp| kp.tag == Tag::CERTIFICATE_NOT_AFTER) {
-                    result.push(KeyParameter {
-                        tag: Tag::CERTIFICATE_NOT_AFTER,
-                        value: KeyParameterValue::DateTime(UNDEFINED_NOT_AFTER),
-                    })
-                }
+        if is_asymmetric_key(params) {
+            if !params.iter().any(|kp| kp.tag == Tag::CERTIFICATE_NOT_BEFORE) {
+                result.push(KeyParameter {
+                    tag: Tag::CERTIFICATE_NOT_BEFORE,
+                    value: KeyParameterValue::DateTime(0),
+                })
+            }
+            if !params.iter().any(|kp| kp.tag == Tag::CERTIFICATE_NOT_AFTER) {
+                result.push(KeyParameter {
+                    tag: Tag::CERTIFICATE_NOT_AFTER,
+                    value: KeyParameterValue::DateTime(UNDEFINED_NOT_AFTER),
+                })
             }
-            _ => {}
         }
-        Ok(result)
+
+        // Clamp the validity of the key if device policy imposes a maximum validity on new keys
+        // in its namespace.
+        let max_validity_expiration =
+            KEY_VALIDITY_POLICY.apply(key.domain, key.nspace, creation_millis, &mut result);
+
+        let result =
+            canonicalize_key_parameters(&result).context(ks_err!("Invalid key parameters."))?;
+        Ok((result, max_validity_expiration))
     }
 
     fn generate_key(
@@ -522,11 +947,40 @@ impl KeystoreSecurityLevel {
         flags: i32,
         _entropy: &[u8],
     ) -> Result<KeyMetadata> {
-        if key.domain != Domain::BLOB && key.alias.is_none() {
-            return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
-                .context(ks_err!("Alias must be specified"));
-        }
-        let caller_uid = ThreadState::get_calling_uid();
+        let PreparedKeyGeneration {
+            key,
+            caller_uid,
+            attestation_key_info,
+            params,
+            max_validity_expiration,
+            attestation_source,
+        } = self.prepare_key_generation(key, attest_key_descriptor, params).context(ks_err!())?;
+        let creation_result =
+            self.generate_on_keymint(&key, attestation_key_info, &params).context(ks_err!())?;
+
+        let user_id = uid_to_android_user(caller_uid);
+        self.store_new_key(
+            key,
+            creation_result,
+            user_id,
+            Some(flags),
+            attestation_source,
+            max_validity_expiration,
+            None,
+        )
+        .context(ks_err!())
+    }
+
+    /// Checks a key generation request and completes its parameters. Holds the lock of the
+    /// attestation key, if one is given, until the result is dropped.
+    fn prepare_key_generation(
+        &self,
+        key: &KeyDescriptor,
+        attest_key_descriptor: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+    ) -> Result<PreparedKeyGeneration> {
+        check_key_descriptor(key, DescriptorUse::Create).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
 
         let key = match key.domain {
             Domain::APP => KeyDescriptor {
@@ -542,6 +996,9 @@ impl KeystoreSecurityLevel {
         // Must return on error for security reasons.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!())?;
 
+        check_namespace_quota(caller_uid, key.domain, key.nspace, key.alias.as_deref())
+            .context(ks_err!("Namespace quota exceeded."))?;
+
         let attestation_key_info = match (key.domain, attest_key_descriptor) {
             (Domain::BLOB, _) => None,
             _ => DB
@@ -557,11 +1014,40 @@ impl KeystoreSecurityLevel {
                 })
                 .context(ks_err!("Trying to get an attestation key"))?,
         };
-        let params = self
+        let (params, max_validity_expiration) = self
             .add_required_parameters(caller_uid, params, &key)
             .context(ks_err!("Trying to get aaid."))?;
 
-        let creation_result = match attestation_key_info {
+        let attestation_source = match &attestation_key_info {
+            Some(AttestationKeyInfo::UserGenerated { .. }) => AttestationSource::UserGenerated,
+            Some(AttestationKeyInfo::RkpdProvisioned { .. }) => AttestationSource::Rkp,
+            None => Self::attestation_source_without_attest_key(&params),
+        };
+        if attestation_source == AttestationSource::Factory {
+            self.rem_prov_state
+                .check_factory_attestation(&params)
+                .context(ks_err!("Trying to attest with the factory key."))?;
+        }
+
+        Ok(PreparedKeyGeneration {
+            key,
+            caller_uid,
+            attestation_key_info,
+            params,
+            max_validity_expiration,
+            attestation_source,
+        })
+    }
+
+    /// Generates a key on KeyMint. Does not use the identity of the caller, so that it can run on
+    /// any thread.
+    fn generate_on_keymint(
+        &self,
+        key: &KeyDescriptor,
+        attestation_key_info: Option<AttestationKeyInfo>,
+        params: &[KeyParameter],
+    ) -> Result<KeyCreationResult> {
+        match attestation_key_info {
             Some(AttestationKeyInfo::UserGenerated {
                 key_id_guard,
                 blob,
@@ -572,7 +1058,7 @@ impl KeystoreSecurityLevel {
                     Some(key_id_guard),
                     &KeyBlob::Ref(&blob),
                     blob_metadata.km_uuid().copied(),
-                    &params,
+                    params,
                     |blob| {
                         let attest_key = Some(AttestationKey {
                             keyBlob: blob.to_vec(),
@@ -587,14 +1073,16 @@ impl KeystoreSecurityLevel {
                                 ),
                                 5000, // Generate can take a little longer.
                             );
-                            self.keymint.generateKey(&params, attest_key.as_ref())
+                            test_hooks::hal_call("IKeyMintDevice::generateKey", || {
+                                self.keymint.generateKey(params, attest_key.as_ref())
+                            })
                         })
                     },
                 )
                 .context(ks_err!(
                     "While generating with a user-generated \
                       attestation key, params: {:?}.",
-                    log_security_safe_params(&params)
+                    log_security_safe_params(params)
                 ))
                 .map(|(result, _)| result),
             Some(AttestationKeyInfo::RkpdProvisioned { attestation_key, attestation_certs }) => {
@@ -612,14 +1100,16 @@ impl KeystoreSecurityLevel {
                             attestKeyParams: vec![],
                             issuerSubjectName: attestation_key.issuerSubjectName.clone(),
                         });
-                        self.keymint.generateKey(&params, dynamic_attest_key.as_ref())
+                        test_hooks::hal_call("IKeyMintDevice::generateKey", || {
+                            self.keymint.generateKey(params, dynamic_attest_key.as_ref())
+                        })
                     })
                 })
                 .context(ks_err!(
                     "While generating Key {:?} with remote \
                     provisioned attestation key and params: {:?}.",
                     key.alias,
-                    log_security_safe_params(&params)
+                    log_security_safe_params(params)
                 ))
                 .map(|(mut result, _)| {
                     result.certificateChain.push(attestation_certs);
@@ -634,18 +1124,26 @@ impl KeystoreSecurityLevel {
                     ),
                     5000, // Generate can take a little longer.
                 );
-                self.keymint.generateKey(&params, None)
+                test_hooks::hal_call("IKeyMintDevice::generateKey", || {
+                    self.keymint.generateKey(params, None)
+                })
             })
             .context(ks_err!(
                 "While generating without a provided \
                  attestation key and params: {:?}.",
-                log_security_safe_params(&params)
+                log_security_safe_params(params)
             )),
         }
-        .context(ks_err!())?;
+    }
 
-        let user_id = uid_to_android_user(caller_uid);
-        self.store_new_key(key, creation_result, user_id, Some(flags)).context(ks_err!())
+    // Without an attestation key, KeyMint uses its factory provisioned attestation key if
+    // an attestation challenge is present.
+    fn attestation_source_without_attest_key(params: &[KeyParameter]) -> AttestationSource {
+        if params.iter().any(|kp| kp.tag == Tag::ATTESTATION_CHALLENGE) {
+            AttestationSource::Factory
+        } else {
+            AttestationSource::None
+        }
     }
 
     fn import_key(
@@ -656,11 +1154,8 @@ impl KeystoreSecurityLevel {
         flags: i32,
         key_data: &[u8],
     ) -> Result<KeyMetadata> {
-        if key.domain != Domain::BLOB && key.alias.is_none() {
-            return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
-                .context(ks_err!("Alias must be specified"));
-        }
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Create).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
 
         let key = match key.domain {
             Domain::APP => KeyDescriptor {
@@ -675,7 +1170,17 @@ impl KeystoreSecurityLevel {
         // import_key requires the rebind permission.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!("In import_key."))?;
 
-        let params = self
+        check_namespace_quota(caller_uid, key.domain, key.nspace, key.alias.as_deref())
+            .context(ks_err!("Namespace quota exceeded."))?;
+
+        // Keys returned as blobs do not take up space in the database.
+        if key.domain != Domain::BLOB {
+            IMPORT_LIMITER
+                .check_import(caller_uid, key.domain, key_data.len())
+                .context(ks_err!("Import limit exceeded."))?;
+        }
+
+        let (params, max_validity_expiration) = self
             .add_required_parameters(caller_uid, params, &key)
             .context(ks_err!("Trying to get aaid."))?;
 
@@ -688,23 +1193,85 @@ impl KeystoreSecurityLevel {
                 KeyParameterValue::Algorithm(Algorithm::AES)
                 | KeyParameterValue::Algorithm(Algorithm::HMAC)
                 | KeyParameterValue::Algorithm(Algorithm::TRIPLE_DES) => Ok(KeyFormat::RAW),
-                KeyParameterValue::Algorithm(Algorithm::RSA)
-                | KeyParameterValue::Algorithm(Algorithm::EC) => Ok(KeyFormat::PKCS8),
+                // RSA, EC, and any public key algorithm that Keystore does not know yet. See
+                // `is_asymmetric_algorithm`.
+                KeyParameterValue::Algorithm(_) => Ok(KeyFormat::PKCS8),
                 v => Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
                     .context(ks_err!("Unknown Algorithm {:?}.", v)),
             })
             .context(ks_err!())?;
 
+        // Asymmetric keys may also be given as JWK or COSE_Key, which KeyMint does not take.
+        let translated_key_data = if format == KeyFormat::PKCS8 {
+            translate_to_pkcs8(key_data).context(ks_err!())?
+        } else {
+            None
+        };
+        let key_data = translated_key_data.as_deref().unwrap_or(key_data);
+
+        let normalized_key_data = if params.iter().any(|p| {
+            p.tag == Tag::ALGORITHM && p.value == KeyParameterValue::Algorithm(Algorithm::EC)
+        }) {
+            Self::normalize_ec_private_key(key_data).context(ks_err!())?
+        } else {
+            None
+        };
+        let key_data = normalized_key_data.as_deref().unwrap_or(key_data);
+
         let km_dev = &self.keymint;
         let creation_result = map_km_error({
             let _wp =
                 self.watch("KeystoreSecurityLevel::import_key: calling IKeyMintDevice::importKey.");
-            km_dev.importKey(&params, format, key_data, None /* attestKey */)
+            test_hooks::hal_call("IKeyMintDevice::importKey", || {
+                km_dev.importKey(&params, format, key_data, None /* attestKey */)
+            })
         })
         .context(ks_err!("Trying to call importKey"))?;
 
+        // The size of imported key material need not be specified, so check the strength of
+        // the key as reported by KeyMint.
+        check_key_strength(caller_uid, key.domain, &Self::authorizations(&creation_result))
+            .context(ks_err!("Imported key."))?;
+
         let user_id = uid_to_android_user(caller_uid);
-        self.store_new_key(key, creation_result, user_id, Some(flags)).context(ks_err!())
+        let attestation_source = Self::attestation_source_without_attest_key(&params);
+        self.store_new_key(
+            key,
+            creation_result,
+            user_id,
+            Some(flags),
+            attestation_source,
+            max_validity_expiration,
+            None,
+        )
+        .context(ks_err!())
+    }
+
+    /// Returns the authorizations of a new key at all security levels.
+    fn authorizations(creation_result: &KeyCreationResult) -> Vec<KeyParameter> {
+        creation_result
+            .keyCharacteristics
+            .iter()
+            .flat_map(|kc| kc.authorizations.iter().cloned())
+            .collect()
+    }
+
+    /// KeyMint implementations differ in which encodings of EC private keys they accept. This
+    /// converts SEC1 keys and keys with explicit curve parameters to PKCS#8 with a named curve,
+    /// which all implementations accept. Returns None if `key_data` cannot be parsed, in which
+    /// case it is left to KeyMint to reject it.
+    fn normalize_ec_private_key(key_data: &[u8]) -> Result<Option<ZVec>> {
+        match ec_key_normalize_private_key(key_data) {
+            Ok(normalized) => Ok(Some(normalized)),
+            Err(CryptoError::ECKEYUnsupportedCurve) => Err(error::Error::Km(
+                ErrorCode::UNSUPPORTED_EC_CURVE,
+            ))
+            .context(ks_err!("Explicit EC curve parameters do not match a supported named curve.")),
+            Err(e) => {
+                log::warn!("Passing unparseable EC key material to KeyMint: {e:?}");
+                Ok(None)
+            }
+        }
     }
 
     fn import_wrapped_key(
@@ -715,6 +1282,7 @@ impl KeystoreSecurityLevel {
         params: &[KeyParameter],
         authenticators: &[AuthenticatorSpec],
     ) -> Result<KeyMetadata> {
+        check_key_descriptor(key, DescriptorUse::Create).context(ks_err!())?;
         let wrapped_data: &[u8] = match key {
             KeyDescriptor { domain: Domain::APP, blob: Some(ref blob), alias: Some(_), .. }
             | KeyDescriptor {
@@ -732,8 +1300,10 @@ impl KeystoreSecurityLevel {
             return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
                 .context(ks_err!("Import wrapped key not supported for self managed blobs."));
         }
+        check_key_descriptor(wrapping_key, DescriptorUse::Lookup)
+            .context(ks_err!("Invalid wrapping key."))?;
 
-        let caller_uid = ThreadState::get_calling_uid();
+        let caller_uid = calling_identity::get_calling_uid();
         let user_id = uid_to_android_user(caller_uid);
 
         let key = match key.domain {
@@ -755,6 +1325,12 @@ impl KeystoreSecurityLevel {
         // Import_wrapped_key requires the rebind permission for the new key.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!())?;
 
+        IMPORT_LIMITER
+            .check_import(caller_uid, key.domain, wrapped_data.len())
+            .context(ks_err!("Import limit exceeded."))?;
+        check_namespace_quota(caller_uid, key.domain, key.nspace, key.alias.as_deref())
+            .context(ks_err!("Namespace quota exceeded."))?;
+
         let super_key = SUPER_KEY.read().unwrap().get_after_first_unlock_key_by_user_id(user_id);
 
         let (wrapping_key_id_guard, mut wrapping_key_entry) = DB
@@ -827,11 +1403,14 @@ impl KeystoreSecurityLevel {
             )
             .context(ks_err!())?;
 
-        self.store_new_key(key, creation_result, user_id, None)
+        check_key_strength(caller_uid, key.domain, &Self::authorizations(&creation_result))
+            .context(ks_err!("Imported wrapped key."))?;
+
+        self.store_new_key(key, creation_result, user_id, None, AttestationSource::None, None, None)
             .context(ks_err!("Trying to store the new key."))
     }
 
-    fn store_upgraded_keyblob(
+    pub(crate) fn store_upgraded_keyblob(
         key_id_guard: KeyIdGuard,
         km_uuid: Option<Uuid>,
         key_blob: &KeyBlob,
@@ -869,12 +1448,22 @@ impl KeystoreSecurityLevel {
     where
         F: Fn(&[u8]) -> Result<T, Error>,
     {
+        // A forced upgrade makes the first attempt fail as if KeyMint required the upgrade.
+        let forced_upgrade = Cell::new(
+            key_id_guard.as_ref().is_some_and(|k| test_hooks::take_forced_upgrade(k.id())),
+        );
         let (v, upgraded_blob) = crate::utils::upgrade_keyblob_if_required_with(
             &*self.keymint,
             self.hw_info.versionNumber,
             key_blob,
             params,
-            f,
+            |blob| {
+                if forced_upgrade.replace(false) {
+                    Err(Error::Km(ErrorCode::KEY_REQUIRES_UPGRADE))
+                } else {
+                    f(blob)
+                }
+            },
             |upgraded_blob| {
                 if key_id_guard.is_some() {
                     // Unwrap cannot panic, because the is_some was true.
@@ -985,6 +1574,498 @@ impl KeystoreSecurityLevel {
         }
     }
 
+    /// Returns the caller's view of the operation capacity of this security level, so that
+    /// callers can schedule batch work for times when the backend is not saturated.
+    /// This backs `IKeystoreSecurityLevel::getOperationBudget`.
+    pub fn get_operation_budget(&self) -> OperationBudget {
+        self.operation_db.get_budget(calling_identity::get_calling_uid())
+    }
+
+    /// Records the attempt of `caller_uid` to create the key `key` with the parameters `params`
+    /// in the metrics, the audit log, and the key events.
+    fn log_key_creation<U>(
+        &self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+        params: &[KeyParameter],
+        creation: KeyCreation,
+        result: &Result<U>,
+    ) {
+        log_key_creation_event_stats(self.security_level, params, result);
+        match creation {
+            KeyCreation::Generated => log_key_generated(key, caller_uid, result.is_ok()),
+            KeyCreation::Imported => log_key_imported(key, caller_uid, result.is_ok()),
+        }
+        publish_key_event(KeystoreEventType::KEY_CREATED, self.security_level, result.is_ok());
+    }
+
+    /// Generates a key on the additional KeyMint instance `instance` of this security level,
+    /// e.g., `strongbox_esim`. The key records the instance that it lives on, so that operations
+    /// with the key are routed to that instance. Choosing an instance is reserved to privileged
+    /// callers; all other callers get the default instance of the security level.
+    pub fn generate_key_on_instance(
+        &self,
+        instance: &str,
+        key: &KeyDescriptor,
+        attest_key_descriptor: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        flags: i32,
+        entropy: &[u8],
+    ) -> Result<KeyMetadata> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::SelectKeyMintInstance)
+            .context(ks_err!("Checking permission"))?;
+
+        let instance_sec_level = self
+            .instances
+            .get(instance)
+            .ok_or(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
+            .context(ks_err!(
+                "No KeyMint instance {:?} for {:?}.",
+                instance,
+                self.security_level
+            ))?;
+        let result =
+            instance_sec_level.generate_key(key, attest_key_descriptor, params, flags, entropy);
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            params,
+            KeyCreation::Generated,
+            &result,
+        );
+        result
+    }
+
+    /// Creates an operation that cannot be pruned for `exemption`, at most
+    /// `MAX_PRUNE_EXEMPTION`, for payment flows, where a pruned operation means a failed tap.
+    /// While the exemption lasts, the operation is not pruned, not even by forced operations or
+    /// its owner. Creating the operation may prune other operations like a forced operation
+    /// does. Exemptions are reserved to privileged callers and strictly limited, see
+    /// `OperationDb::grant_prune_exemption`. This backs the pruning exemption flag of
+    /// `IKeystoreSecurityLevel::createOperation`.
+    pub fn create_prune_exempt_operation(
+        &self,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        exemption: Duration,
+    ) -> Result<CreateOperationResponse> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::PruneExemptOperation)
+            .context(ks_err!("Checking permission"))?;
+        self.create_operation(key, operation_parameters, false, Some(exemption))
+    }
+
+    /// Generates a batch of keys, so that callers needing many keys, e.g., at provisioning time,
+    /// don't pay a binder round trip per key. Each request is checked and stored like a call of
+    /// `generateKey` by the caller, but the keys are generated by KeyMint concurrently, on the
+    /// binder thread and on the helper threads that are available from `KEY_GENERATION_HELPERS`.
+    /// Requests with an attestation key are generated one after the
+    /// other, because their preparation holds the lock of the attestation key. Returns a result
+    /// per request, in the order of the requests; a failed request does not fail the others.
+    /// This backs `IKeystoreSecurityLevel::generateKeys`.
+    pub fn generate_keys(
+        &self,
+        requests: &[KeyGenerationRequest],
+    ) -> Result<Vec<Result<KeyMetadata>>> {
+        if requests.len() > MAX_KEY_GENERATION_BATCH {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Batch of {} keys exceeds the maximum of {}.",
+                requests.len(),
+                MAX_KEY_GENERATION_BATCH
+            ));
+        }
+        let caller_uid = calling_identity::get_calling_uid();
+
+        // The checks use the identity of the caller, so they run on the binder thread.
+        let prepared: Vec<_> = requests
+            .iter()
+            .map(|request| {
+                request
+                    .attestation_key
+                    .is_none()
+                    .then(|| self.prepare_key_generation(&request.key, None, &request.params))
+            })
+            .collect();
+        let helpers = KEY_GENERATION_HELPERS.take(requests.len().saturating_sub(1));
+        let created = map_concurrently(prepared, helpers.threads, |prepared| {
+            prepared.map(|prepared| {
+                prepared.and_then(|mut prepared| {
+                    let attestation_key_info = prepared.attestation_key_info.take();
+                    let creation_result = self.generate_on_keymint(
+                        &prepared.key,
+                        attestation_key_info,
+                        &prepared.params,
+                    )?;
+                    Ok((prepared, creation_result))
+                })
+            })
+        });
+
+        Ok(requests
+            .iter()
+            .zip(created)
+            .map(|(request, created)| {
+                let result = match created {
+                    None => self.generate_key(
+                        &request.key,
+                        request.attestation_key.as_ref(),
+                        &request.params,
+                        request.flags,
+                        &request.entropy,
+                    ),
+                    Some(created) => created.and_then(|(prepared, creation_result)| {
+                        self.store_prepared_key(prepared, creation_result, request.flags)
+                    }),
+                };
+                self.log_key_creation(
+                    &request.key,
+                    caller_uid,
+                    &request.params,
+                    KeyCreation::Generated,
+                    &result,
+                );
+                result
+            })
+            .collect())
+    }
+
+    fn store_prepared_key(
+        &self,
+        prepared: PreparedKeyGeneration,
+        creation_result: KeyCreationResult,
+        flags: i32,
+    ) -> Result<KeyMetadata> {
+        // The quota check of the preparation did not count the other keys of the batch.
+        let PreparedKeyGeneration { key, caller_uid, .. } = &prepared;
+        check_namespace_quota(*caller_uid, key.domain, key.nspace, key.alias.as_deref())
+            .context(ks_err!("Namespace quota exceeded."))?;
+        self.store_new_key(
+            prepared.key,
+            creation_result,
+            uid_to_android_user(prepared.caller_uid),
+            Some(flags),
+            prepared.attestation_source,
+            prepared.max_validity_expiration,
+            None,
+        )
+        .context(ks_err!())
+    }
+
+    /// Generates a key from the key generation template `template`, i.e., `template:<name>`,
+    /// see `key_templates`. The key gets the parameters of the template followed by `params`.
+    /// This backs template references in `IKeystoreSecurityLevel::generateKey`.
+    pub fn generate_key_from_template(
+        &self,
+        template: &str,
+        key: &KeyDescriptor,
+        attest_key_descriptor: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        flags: i32,
+        entropy: &[u8],
+    ) -> Result<KeyMetadata> {
+        let params = KEY_TEMPLATES.expand(template, params).context(ks_err!())?;
+        let result = self.generate_key(key, attest_key_descriptor, &params, flags, entropy);
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            &params,
+            KeyCreation::Generated,
+            &result,
+        );
+        result
+    }
+
+    /// Replaces the existing key `key` with a newly generated key, see `key_rotation`. The alias
+    /// is bound to the new key in the same transaction that releases the previous key. If
+    /// `grace_period_millis` is positive, the previous key is retained under `<alias>.prev` for
+    /// that long and can only be used through its grants. Fails with `KEY_NOT_FOUND` if there is
+    /// no key to rotate. This backs `IKeystoreSecurityLevel::rotateKey`.
+    pub fn rotate_key(
+        &self,
+        key: &KeyDescriptor,
+        attest_key_descriptor: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        flags: i32,
+        entropy: &[u8],
+        grace_period_millis: i64,
+    ) -> Result<KeyMetadata> {
+        let result = self.rotate_key_internal(
+            key,
+            attest_key_descriptor,
+            params,
+            flags,
+            entropy,
+            grace_period_millis,
+        );
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            params,
+            KeyCreation::Generated,
+            &result,
+        );
+        result
+    }
+
+    fn rotate_key_internal(
+        &self,
+        key: &KeyDescriptor,
+        attest_key_descriptor: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        flags: i32,
+        _entropy: &[u8],
+        grace_period_millis: i64,
+    ) -> Result<KeyMetadata> {
+        if !matches!(key.domain, Domain::APP | Domain::SELINUX) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Cannot rotate keys in {:?}.", key.domain));
+        }
+        let now = DateTime::now().context(ks_err!("Trying to get the time."))?;
+        let retain_previous_until =
+            retain_previous_until(now, grace_period_millis).context(ks_err!())?;
+        let prepared =
+            self.prepare_key_generation(key, attest_key_descriptor, params).context(ks_err!())?;
+
+        // `prepare_key_generation` checked that the descriptor has an alias.
+        let alias = prepared.key.alias.as_deref().ok_or_else(Error::sys).context(ks_err!())?;
+        if retain_previous_until.is_some() {
+            let previous =
+                KeyDescriptor { alias: Some(previous_key_alias(alias)), ..prepared.key.clone() };
+            check_key_descriptor(&previous, DescriptorUse::Create)
+                .context(ks_err!("Cannot retain the previous key."))?;
+        }
+        let exists = DB
+            .with(|db| {
+                db.borrow_mut().key_exists(
+                    prepared.key.domain,
+                    prepared.key.nspace,
+                    alias,
+                    KeyType::Client,
+                )
+            })
+            .context(ks_err!())?;
+        if !exists {
+            return Err(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+                .context(ks_err!("No key to rotate."));
+        }
+
+        let PreparedKeyGeneration {
+            key,
+            caller_uid,
+            attestation_key_info,
+            params,
+            max_validity_expiration,
+            attestation_source,
+        } = prepared;
+        let creation_result =
+            self.generate_on_keymint(&key, attestation_key_info, &params).context(ks_err!())?;
+        self.store_new_key(
+            key,
+            creation_result,
+            uid_to_android_user(caller_uid),
+            Some(flags),
+            attestation_source,
+            max_validity_expiration,
+            retain_previous_until,
+        )
+        .context(ks_err!())
+    }
+
+    /// Produces a fresh signed statement that the given key still exists in the KeyMint instance
+    /// of this security level, after checking that its authorization policy as enforced by
+    /// KeyMint is the same as when the key was created. This allows relying parties to re-check
+    /// possession of a key without having to replace it.
+    ///
+    /// The statement is signed with the key itself, so on its own it only proves that the caller
+    /// can use the key. It includes the attestation certificate of the key, which KeyMint signed
+    /// when the key was created. The claims that the key is hardware-bound and about its
+    /// authorization policy are taken from that certificate, so keys without an attestation
+    /// certificate chain are not supported. The key must be an RSA or EC signing key that the
+    /// caller can use. Keys bound to an application ID or application data are not supported.
+    /// This backs `IKeystoreKeyLiveness::attestKeyLiveness`.
+    pub fn attest_key_liveness(
+        &self,
+        key: &KeyDescriptor,
+        challenge: &[u8],
+    ) -> Result<KeyLivenessAttestation> {
+        if key.domain == Domain::BLOB {
+            return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
+                .context(ks_err!("Key must not be of Domain::BLOB."));
+        }
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+        let (key_id_guard, mut key_entry) = DB
+            .with::<_, Result<(KeyIdGuard, KeyEntry)>>(|db| {
+                LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::BOTH,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::Use, k, &av),
+                    )
+                })
+            })
+            .context(ks_err!("Failed to load key blob."))?;
+        let (blob, blob_metadata) = key_entry
+            .take_key_blob_info()
+            .ok_or_else(Error::sys)
+            .context(ks_err!("Successfully loaded key entry, but KM blob was missing."))?;
+
+        // Keys that live on an additional KeyMint instance of this security level must be
+        // checked by that instance. It loads the key again, so the key id lock must be released.
+        if let Some(instance) = blob_metadata.km_uuid().and_then(|uuid| self.instance_by_uuid(uuid))
+        {
+            drop(key_id_guard);
+            return instance.attest_key_liveness(key, challenge);
+        }
+
+        let (certificate, certificate_chain) =
+            match (key_entry.take_cert(), key_entry.take_cert_chain()) {
+                (Some(cert), Some(chain)) if !chain.is_empty() => (cert, chain),
+                _ => {
+                    return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                        .context(ks_err!("Key has no attestation certificate chain."));
+                }
+            };
+
+        let km_blob = SUPER_KEY
+            .read()
+            .unwrap()
+            .unwrap_key_if_required(&blob_metadata, &blob)
+            .context(ks_err!("Failed to handle super encryption."))?;
+
+        // Asking KeyMint for the characteristics proves that the key blob is still accepted by
+        // this KeyMint instance.
+        let (key_characteristics, _) = self
+            .upgrade_keyblob_if_required_with(
+                Some(key_id_guard),
+                &km_blob,
+                blob_metadata.km_uuid().copied(),
+                &[],
+                |blob| {
+                    map_km_error({
+                        let _wp = self.watch(concat!(
+                            "KeystoreSecurityLevel::attest_key_liveness: ",
+                            "calling IKeyMintDevice::getKeyCharacteristics"
+                        ));
+                        self.keymint.getKeyCharacteristics(blob, &[], &[])
+                    })
+                },
+            )
+            .context(ks_err!("Failed to get key characteristics."))?;
+
+        let enforced_by_keymint = |params: Vec<KsKeyParam>| {
+            let mut params: Vec<KsKeyParam> =
+                params.into_iter().filter(|p| *p.security_level() == self.security_level).collect();
+            params.sort();
+            params
+        };
+        let auth_policy = |params: &[KsKeyParam]| {
+            params.iter().filter(|p| is_auth_policy_tag(p.get_tag())).cloned().collect::<Vec<_>>()
+        };
+        let authorizations =
+            enforced_by_keymint(key_characteristics_to_internal(key_characteristics));
+        let recorded_authorizations = enforced_by_keymint(key_entry.into_key_parameters());
+        if auth_policy(&authorizations) != auth_policy(&recorded_authorizations) {
+            return Err(Error::Km(ErrorCode::INVALID_KEY_BLOB))
+                .context(ks_err!("Authorization policy of the key changed."));
+        }
+
+        let statement = Self::key_liveness_statement(
+            challenge,
+            DateTime::now().context(ks_err!())?,
+            &certificate,
+        )?;
+
+        let signing_params = Self::key_liveness_signing_params(&authorizations)?;
+        let operation = self
+            .create_operation(key, &signing_params, false, None)
+            .context(ks_err!("Failed to begin signing operation."))?
+            .iOperation
+            .ok_or_else(Error::sys)
+            .context(ks_err!("No operation returned."))?;
+        let signature = map_km_error(operation.finish(Some(&statement), None))
+            .context(ks_err!("Failed to sign statement."))?
+            .ok_or_else(Error::sys)
+            .context(ks_err!("Signing operation returned no signature."))?;
+
+        Ok(KeyLivenessAttestation {
+            statement,
+            signature,
+            certificate,
+            certificateChain: certificate_chain,
+        })
+    }
+
+    /// Encodes the liveness statement for the key with the attestation certificate
+    /// `certificate`.
+    fn key_liveness_statement(
+        challenge: &[u8],
+        now: DateTime,
+        certificate: &[u8],
+    ) -> Result<Vec<u8>> {
+        serde_cbor::to_vec(&Value::Array(vec![
+            Value::Text(KEY_LIVENESS_STATEMENT_VERSION.to_string()),
+            Value::Bytes(challenge.to_vec()),
+            Value::Integer(now.to_millis_epoch().into()),
+            Value::Bytes(certificate.to_vec()),
+        ]))
+        .context(ks_err!("Failed to encode statement."))
+    }
+
+    /// Selects the operation parameters for signing a liveness statement with a key that has the
+    /// given authorizations.
+    fn key_liveness_signing_params(authorizations: &[KsKeyParam]) -> Result<Vec<KeyParameter>> {
+        let authorizes = |value: KsKeyParamValue| {
+            authorizations.iter().any(|p| *p.key_parameter_value() == value)
+        };
+
+        if !authorizes(KsKeyParamValue::KeyPurpose(KeyPurpose::SIGN)) {
+            return Err(Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+                .context(ks_err!("Key cannot be used for signing."));
+        }
+        let digest = [Digest::SHA_2_256, Digest::NONE]
+            .into_iter()
+            .find(|d| authorizes(KsKeyParamValue::Digest(*d)))
+            .ok_or(Error::Km(ErrorCode::INCOMPATIBLE_DIGEST))
+            .context(ks_err!("Key supports neither SHA-256 nor raw signing."))?;
+        let mut params = vec![
+            KeyParameter {
+                tag: Tag::PURPOSE,
+                value: KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+            },
+            KeyParameter { tag: Tag::DIGEST, value: KeyParameterValue::Digest(digest) },
+        ];
+
+        if authorizes(KsKeyParamValue::Algorithm(Algorithm::RSA)) {
+            // PSS cannot be used without a digest.
+            let paddings: &[PaddingMode] = if digest == Digest::NONE {
+                &[PaddingMode::RSA_PKCS1_1_5_SIGN]
+            } else {
+                &[PaddingMode::RSA_PSS, PaddingMode::RSA_PKCS1_1_5_SIGN]
+            };
+            let padding = paddings
+                .iter()
+                .find(|p| authorizes(KsKeyParamValue::PaddingMode(**p)))
+                .ok_or(Error::Km(ErrorCode::INCOMPATIBLE_PADDING_MODE))
+                .context(ks_err!("Key supports no usable signature padding."))?;
+            params.push(KeyParameter {
+                tag: Tag::PADDING,
+                value: KeyParameterValue::PaddingMode(*padding),
+            });
+        } else if !authorizes(KsKeyParamValue::Algorithm(Algorithm::EC)) {
+            return Err(Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+                .context(ks_err!("Only RSA and EC keys can sign liveness statements."));
+        }
+        Ok(params)
+    }
+
     fn delete_key(&self, key: &KeyDescriptor) -> Result<()> {
         if key.domain != Domain::BLOB {
             return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
@@ -1019,7 +2100,8 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
         forced: bool,
     ) -> binder::Result<CreateOperationResponse> {
         let _wp = self.watch("IKeystoreSecurityLevel::createOperation");
-        self.create_operation(key, operation_parameters, forced).map_err(into_logged_binder)
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::createOperation");
+        self.create_operation(key, operation_parameters, forced, None).map_err(into_logged_binder)
     }
     fn generateKey(
         &self,
@@ -1032,9 +2114,15 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
         // Duration is set to 5 seconds, because generateKey - especially for RSA keys, takes more
         // time than other operations
         let _wp = self.watch_millis("IKeystoreSecurityLevel::generateKey", 5000);
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::generateKey");
         let result = self.generate_key(key, attestation_key, params, flags, entropy);
-        log_key_creation_event_stats(self.security_level, params, &result);
-        log_key_generated(key, ThreadState::get_calling_uid(), result.is_ok());
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            params,
+            KeyCreation::Generated,
+            &result,
+        );
         result.map_err(into_logged_binder)
     }
     fn importKey(
@@ -1046,9 +2134,15 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
         key_data: &[u8],
     ) -> binder::Result<KeyMetadata> {
         let _wp = self.watch("IKeystoreSecurityLevel::importKey");
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::importKey");
         let result = self.import_key(key, attestation_key, params, flags, key_data);
-        log_key_creation_event_stats(self.security_level, params, &result);
-        log_key_imported(key, ThreadState::get_calling_uid(), result.is_ok());
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            params,
+            KeyCreation::Imported,
+            &result,
+        );
         result.map_err(into_logged_binder)
     }
     fn importWrappedKey(
@@ -1060,10 +2154,16 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
         authenticators: &[AuthenticatorSpec],
     ) -> binder::Result<KeyMetadata> {
         let _wp = self.watch("IKeystoreSecurityLevel::importWrappedKey");
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::importWrappedKey");
         let result =
             self.import_wrapped_key(key, wrapping_key, masking_key, params, authenticators);
-        log_key_creation_event_stats(self.security_level, params, &result);
-        log_key_imported(key, ThreadState::get_calling_uid(), result.is_ok());
+        self.log_key_creation(
+            key,
+            calling_identity::get_calling_uid(),
+            params,
+            KeyCreation::Imported,
+            &result,
+        );
         result.map_err(into_logged_binder)
     }
     fn convertStorageKeyToEphemeral(
@@ -1071,12 +2171,15 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
         storage_key: &KeyDescriptor,
     ) -> binder::Result<EphemeralStorageKeyResponse> {
         let _wp = self.watch("IKeystoreSecurityLevel::convertStorageKeyToEphemeral");
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::convertStorageKeyToEphemeral");
         self.convert_storage_key_to_ephemeral(storage_key).map_err(into_logged_binder)
     }
     fn deleteKey(&self, key: &KeyDescriptor) -> binder::Result<()> {
         let _wp = self.watch("IKeystoreSecurityLevel::deleteKey");
+        let _cpu = cpu_accounting::account("IKeystoreSecurityLevel::deleteKey");
         let result = self.delete_key(key);
-        log_key_deleted(key, ThreadState::get_calling_uid(), result.is_ok());
+        log_key_deleted(key, calling_identity::get_calling_uid(), result.is_ok());
+        publish_key_event(KeystoreEventType::KEY_DELETED, self.security_level, result.is_ok());
         result.map_err(into_logged_binder)
     }
 }
@@ -1088,8 +2191,8 @@ mod tests {
     use crate::globals::get_keymint_device;
     use crate::utils::upgrade_keyblob_if_required_with;
     use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-        Algorithm::Algorithm, AttestationKey::AttestationKey, KeyParameter::KeyParameter,
-        KeyParameterValue::KeyParameterValue, Tag::Tag,
+        Algorithm::Algorithm, AttestationKey::AttestationKey, BlockMode::BlockMode,
+        KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, Tag::Tag,
     };
     use keystore2_crypto::parse_subject_from_certificate;
     use rkpd_client::get_rkpd_attestation_key;
@@ -1159,4 +2262,165 @@ mod tests {
             println!("RKPD key was NOT upgraded.");
         }
     }
+
+    #[test]
+    fn test_key_liveness_statement() {
+        let statement = KeystoreSecurityLevel::key_liveness_statement(
+            b"challenge",
+            DateTime::from_millis_epoch(1234),
+            b"certificate",
+        )
+        .unwrap();
+        assert_eq!(
+            serde_cbor::from_slice::<Value>(&statement).unwrap(),
+            Value::Array(vec![
+                Value::Text(KEY_LIVENESS_STATEMENT_VERSION.to_string()),
+                Value::Bytes(b"challenge".to_vec()),
+                Value::Integer(1234),
+                Value::Bytes(b"certificate".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_key_liveness_signing_params() {
+        let tee = SecurityLevel::TRUSTED_ENVIRONMENT;
+        let auths = |values: Vec<KsKeyParamValue>| {
+            values.into_iter().map(|v| KsKeyParam::new(v, tee)).collect::<Vec<_>>()
+        };
+        let param = |tag, value| KeyParameter { tag, value };
+        let sign = param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::SIGN));
+
+        let ec = auths(vec![
+            KsKeyParamValue::Algorithm(Algorithm::EC),
+            KsKeyParamValue::KeyPurpose(KeyPurpose::SIGN),
+            KsKeyParamValue::Digest(Digest::SHA_2_256),
+        ]);
+        assert_eq!(
+            KeystoreSecurityLevel::key_liveness_signing_params(&ec).unwrap(),
+            vec![sign.clone(), param(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_256))]
+        );
+
+        // PSS is preferred, but cannot be used for raw RSA signing.
+        let rsa = auths(vec![
+            KsKeyParamValue::Algorithm(Algorithm::RSA),
+            KsKeyParamValue::KeyPurpose(KeyPurpose::SIGN),
+            KsKeyParamValue::Digest(Digest::NONE),
+            KsKeyParamValue::PaddingMode(PaddingMode::RSA_PSS),
+            KsKeyParamValue::PaddingMode(PaddingMode::RSA_PKCS1_1_5_SIGN),
+        ]);
+        assert_eq!(
+            KeystoreSecurityLevel::key_liveness_signing_params(&rsa).unwrap(),
+            vec![
+                sign,
+                param(Tag::DIGEST, KeyParameterValue::Digest(Digest::NONE)),
+                param(
+                    Tag::PADDING,
+                    KeyParameterValue::PaddingMode(PaddingMode::RSA_PKCS1_1_5_SIGN)
+                ),
+            ]
+        );
+
+        let encrypt_only = auths(vec![
+            KsKeyParamValue::Algorithm(Algorithm::RSA),
+            KsKeyParamValue::KeyPurpose(KeyPurpose::DECRYPT),
+            KsKeyParamValue::Digest(Digest::SHA_2_256),
+        ]);
+        assert_eq!(
+            KeystoreSecurityLevel::key_liveness_signing_params(&encrypt_only)
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>(),
+            Some(&Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+        );
+    }
+
+    #[test]
+    fn test_effective_operation_parameters() {
+        let param = |tag, value| KeyParameter { tag, value };
+        let block_mode = param(Tag::BLOCK_MODE, KeyParameterValue::BlockMode(BlockMode::GCM));
+        let mac_length = param(Tag::MAC_LENGTH, KeyParameterValue::Integer(128));
+        let nonce = |n: &[u8]| param(Tag::NONCE, KeyParameterValue::Blob(n.to_vec()));
+
+        assert_eq!(
+            effective_operation_parameters(
+                KeyPurpose::ENCRYPT,
+                &[block_mode.clone(), mac_length.clone()],
+                &[nonce(b"generated")]
+            ),
+            vec![
+                param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT)),
+                block_mode.clone(),
+                mac_length.clone(),
+                nonce(b"generated"),
+            ]
+        );
+
+        // What KeyMint returns is what it uses.
+        assert_eq!(
+            effective_operation_parameters(
+                KeyPurpose::DECRYPT,
+                &[nonce(b"passed"), block_mode.clone()],
+                &[nonce(b"returned")]
+            ),
+            vec![
+                param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::DECRYPT)),
+                block_mode.clone(),
+                nonce(b"returned"),
+            ]
+        );
+
+        // The purpose that the caller passed is not repeated.
+        let purpose = |p| param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(p));
+        assert_eq!(
+            effective_operation_parameters(
+                KeyPurpose::SIGN,
+                &[purpose(KeyPurpose::SIGN), mac_length.clone()],
+                &[]
+            ),
+            vec![purpose(KeyPurpose::SIGN), mac_length]
+        );
+        assert_eq!(
+            effective_operation_parameters(
+                KeyPurpose::ENCRYPT,
+                &[block_mode.clone(), purpose(KeyPurpose::ENCRYPT)],
+                &[purpose(KeyPurpose::ENCRYPT), nonce(b"generated")]
+            ),
+            vec![purpose(KeyPurpose::ENCRYPT), block_mode, nonce(b"generated")]
+        );
+    }
+
+    #[test]
+    fn test_map_concurrently() {
+        let items: Vec<u64> = (0..100).collect();
+        let results = map_concurrently(items, 4, |i| {
+            // Let later items finish first.
+            std::thread::sleep(Duration::from_micros(100 - i));
+            i * 2
+        });
+        assert_eq!(results, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(map_concurrently(Vec::<u64>::new(), 0, |i| i).is_empty());
+        // Without helpers, the calling thread does all the work.
+        let caller = std::thread::current().id();
+        let results = map_concurrently(vec![1, 2, 3], 0, |i| {
+            assert_eq!(std::thread::current().id(), caller);
+            i
+        });
+        assert_eq!(results, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_thread_budget() {
+        let budget = ThreadBudget::new(4);
+        let first = budget.take(3);
+        assert_eq!(first.threads, 3);
+        // The budget is shared, and taking threads does not wait for others to return theirs.
+        let second = budget.take(3);
+        assert_eq!(second.threads, 1);
+        assert_eq!(budget.take(1).threads, 0);
+        drop(first);
+        assert_eq!(budget.take(4).threads, 3);
+        drop(second);
+        assert_eq!(budget.take(4).threads, 4);
+    }
 }
@@ -15,23 +15,43 @@
 //! This crate implement the core Keystore 2.0 service API as defined by the Keystore 2.0
 //! AIDL spec.
 
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::audit_log::log_key_deleted;
+use crate::boot_profile::BOOT_PROFILE;
+use crate::calling_identity;
+use crate::cert_chain_limits::check_cert_chain;
+use crate::cpu_accounting;
+use crate::deferred_security_level::{defer_strongbox, DeferredSecurityLevel};
+use crate::events::publish_key_event;
+use crate::isolated_callers::ISOLATED_CALLERS;
+use crate::key_descriptor_validation::{check_alias, check_key_descriptor, DescriptorUse};
+use crate::key_entry_cache::LookupKey;
+use crate::key_usage::KEY_USAGE;
 use crate::ks_err;
 use crate::permission::{KeyPerm, KeystorePerm};
+use crate::public_key_export::{export_public_key, PublicKeyFormat};
 use crate::security_level::KeystoreSecurityLevel;
 use crate::utils::{
     check_grant_permission, check_key_permission, check_keystore_permission, count_key_entries,
-    key_parameters_to_authorizations, list_key_entries, uid_to_android_user, watchdog as wd,
+    key_parameters_to_authorizations, list_key_entries, list_key_entries_filtered,
+    list_key_entries_page, uid_to_android_user, watchdog as wd, KeyEntryPage,
 };
 use crate::{
     database::Uuid,
-    globals::{create_thread_local_db, DB, LEGACY_BLOB_LOADER, LEGACY_IMPORTER, SUPER_KEY},
+    globals::{
+        create_thread_local_db, get_additional_strongbox_instances, strongbox_instance_uuid, DB,
+        DB_READER, IMPORT_LIMITER, KEY_ENTRY_CACHE, LEGACY_BLOB_LOADER, LEGACY_IMPORTER, SUPER_KEY,
+    },
 };
 use crate::{database::KEYSTORE_UUID, permission};
 use crate::{
-    database::{KeyEntryLoadBits, KeyType, SubComponentType},
+    database::{
+        EntryMetadata, GrantUpdate, KeyEntryFilter, KeyEntryLoadBits, KeyProvenance, KeyType,
+        KeyUsageStats, SubComponentType,
+    },
     error::ResponseCode,
 };
 use crate::{
@@ -39,7 +59,8 @@ use crate::{
     id_rotation::IdRotationState,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
-use android_hardware_security_keymint::binder::{BinderFeatures, Strong, ThreadState};
+use android_hardware_security_keymint::binder::{BinderFeatures, Strong};
+use android_security_maintenance::aidl::android::security::maintenance::KeystoreEventType::KeystoreEventType;
 use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, IKeystoreSecurityLevel::IKeystoreSecurityLevel,
     IKeystoreService::BnKeystoreService, IKeystoreService::IKeystoreService,
@@ -49,11 +70,22 @@ use anyhow::{Context, Result};
 use error::Error;
 use keystore2_selinux as selinux;
 
+/// The maximum number of labels of a key entry, see `KeystoreService::set_entry_metadata`.
+const MAX_ENTRY_METADATA_LABELS: usize = 16;
+
+/// The maximum size of the name of a label in bytes.
+const MAX_ENTRY_METADATA_NAME_SIZE: usize = 64;
+
+/// The maximum size of the value of a label in bytes.
+const MAX_ENTRY_METADATA_VALUE_SIZE: usize = 256;
+
 /// Implementation of the IKeystoreService.
 #[derive(Default)]
 pub struct KeystoreService {
     i_sec_level_by_uuid: HashMap<Uuid, Strong<dyn IKeystoreSecurityLevel>>,
     uuid_by_sec_level: HashMap<SecurityLevel, Uuid>,
+    sec_level_by_instance_uuid: HashMap<Uuid, SecurityLevel>,
+    deferred_strongbox: Option<Arc<DeferredSecurityLevel>>,
 }
 
 impl KeystoreService {
@@ -62,10 +94,12 @@ impl KeystoreService {
         id_rotation_state: IdRotationState,
     ) -> Result<Strong<dyn IKeystoreService>> {
         let mut result: Self = Default::default();
-        let (dev, uuid) = match KeystoreSecurityLevel::new_native_binder(
-            SecurityLevel::TRUSTED_ENVIRONMENT,
-            id_rotation_state.clone(),
-        ) {
+        let (dev, uuid, _) = match BOOT_PROFILE.time("connect_tee", || {
+            KeystoreSecurityLevel::new_native_binder(
+                SecurityLevel::TRUSTED_ENVIRONMENT,
+                id_rotation_state.clone(),
+            )
+        }) {
             Ok(v) => v,
             Err(e) => {
                 log::error!("Failed to construct mandatory security level TEE: {e:?}");
@@ -77,10 +111,35 @@ impl KeystoreService {
         result.i_sec_level_by_uuid.insert(uuid, dev);
         result.uuid_by_sec_level.insert(SecurityLevel::TRUSTED_ENVIRONMENT, uuid);
 
-        // Strongbox is optional, so we ignore errors and turn the result into an Option.
-        if let Ok((dev, uuid)) =
-            KeystoreSecurityLevel::new_native_binder(SecurityLevel::STRONGBOX, id_rotation_state)
+        // Strongbox is optional, so we ignore errors when connecting to it eagerly.
+        if defer_strongbox() {
+            // The uuids of StrongBox and its additional instances are derived from their
+            // names, so they are known without connecting.
+            let uuid = SecurityLevel::STRONGBOX.into();
+            result.uuid_by_sec_level.insert(SecurityLevel::STRONGBOX, uuid);
+            for instance in get_additional_strongbox_instances() {
+                if let Some(instance_uuid) = strongbox_instance_uuid(&instance) {
+                    result
+                        .sec_level_by_instance_uuid
+                        .insert(instance_uuid, SecurityLevel::STRONGBOX);
+                }
+            }
+            result.deferred_strongbox =
+                Some(DeferredSecurityLevel::new(SecurityLevel::STRONGBOX, id_rotation_state));
+        } else if let Ok((dev, uuid, instance_uuids)) =
+            BOOT_PROFILE.time("connect_strongbox", || {
+                KeystoreSecurityLevel::new_native_binder(
+                    SecurityLevel::STRONGBOX,
+                    id_rotation_state,
+                )
+            })
         {
+            // Keys on additional StrongBox instances are served by the same security level
+            // object, which routes them to their instance.
+            for instance_uuid in instance_uuids {
+                result.i_sec_level_by_uuid.insert(instance_uuid, dev.clone());
+                result.sec_level_by_instance_uuid.insert(instance_uuid, SecurityLevel::STRONGBOX);
+            }
             result.i_sec_level_by_uuid.insert(uuid, dev);
             result.uuid_by_sec_level.insert(SecurityLevel::STRONGBOX, uuid);
         }
@@ -103,12 +162,19 @@ impl KeystoreService {
             .iter()
             .find(|(_, v)| **v == *uuid)
             .map(|(s, _)| *s)
+            .or_else(|| self.sec_level_by_instance_uuid.get(uuid).copied())
             .unwrap_or(SecurityLevel::SOFTWARE)
     }
 
     fn get_i_sec_level_by_uuid(&self, uuid: &Uuid) -> Result<Strong<dyn IKeystoreSecurityLevel>> {
         if let Some(dev) = self.i_sec_level_by_uuid.get(uuid) {
             Ok(dev.clone())
+        } else if let Some(deferred) = self
+            .deferred_strongbox
+            .as_ref()
+            .filter(|_| self.uuid_to_sec_level(uuid) == SecurityLevel::STRONGBOX)
+        {
+            deferred.get().context(ks_err!("Trying to connect to StrongBox."))
         } else {
             Err(error::Error::sys()).context(ks_err!("KeyMint instance for key not found."))
         }
@@ -124,6 +190,10 @@ impl KeystoreService {
             .and_then(|uuid| self.i_sec_level_by_uuid.get(uuid))
         {
             Ok(dev.clone())
+        } else if let Some(deferred) =
+            self.deferred_strongbox.as_ref().filter(|_| sec_level == SecurityLevel::STRONGBOX)
+        {
+            deferred.get().context(ks_err!("Trying to connect to StrongBox."))
         } else {
             Err(error::Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
                 .context(ks_err!("No such security level."))
@@ -131,13 +201,35 @@ impl KeystoreService {
     }
 
     fn get_key_entry(&self, key: &KeyDescriptor) -> Result<KeyEntryResponse> {
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        let lookup_key =
+            calling_identity::with_calling_sid(|sid| LookupKey::new(caller_uid, sid, key));
+        if let Some(response) = lookup_key.as_ref().and_then(|k| KEY_ENTRY_CACHE.get(k)) {
+            return Ok(response);
+        }
+
+        let generation = KEY_ENTRY_CACHE.generation();
+        let response = self.load_key_entry_response(key, caller_uid)?;
+        if let Some(lookup_key) = lookup_key {
+            KEY_ENTRY_CACHE.insert(lookup_key, generation, response.clone());
+        }
+        Ok(response)
+    }
 
+    fn load_key_entry_response(
+        &self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+    ) -> Result<KeyEntryResponse> {
         let super_key = SUPER_KEY
             .read()
             .unwrap()
             .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
 
+        // Callers who may use the key but not inspect it get the key entry without its
+        // certificates, so that grants can separate verifiers from users of a key.
+        let certificates_withheld = Cell::new(false);
         let (key_id_guard, mut key_entry) = DB
             .with(|db| {
                 LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
@@ -146,11 +238,26 @@ impl KeystoreService {
                         KeyType::Client,
                         KeyEntryLoadBits::PUBLIC,
                         caller_uid,
-                        |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                        |k, av| {
+                            let get_info = check_key_permission(KeyPerm::GetInfo, k, &av);
+                            let withheld = get_info.is_err()
+                                && check_key_permission(KeyPerm::Use, k, &av).is_ok();
+                            certificates_withheld.set(withheld);
+                            if withheld {
+                                Ok(())
+                            } else {
+                                get_info
+                            }
+                        },
                     )
                 })
             })
             .context(ks_err!("while trying to load key info."))?;
+        let (certificate, certificate_chain) = if certificates_withheld.get() {
+            (None, None)
+        } else {
+            (key_entry.take_cert(), key_entry.take_cert_chain())
+        };
 
         let i_sec_level = if !key_entry.pure_cert() {
             Some(
@@ -170,8 +277,8 @@ impl KeystoreService {
                     ..Default::default()
                 },
                 keySecurityLevel: self.uuid_to_sec_level(key_entry.km_uuid()),
-                certificate: key_entry.take_cert(),
-                certificateChain: key_entry.take_cert_chain(),
+                certificate,
+                certificateChain: certificate_chain,
                 modificationTimeMs: key_entry
                     .metadata()
                     .creation_date()
@@ -183,19 +290,129 @@ impl KeystoreService {
         })
     }
 
+    /// Returns the provenance information recorded when the key identified by `key` was
+    /// created. The caller needs the `get_info` permission on the key.
+    /// This backs the provenance field of `KeyMetadata`.
+    pub fn get_key_provenance(&self, key: &KeyDescriptor) -> Result<KeyProvenance> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let (_, key_entry) = DB
+            .with(|db| {
+                LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                    )
+                })
+            })
+            .context(ks_err!("while trying to load key info."))?;
+
+        Ok(key_entry.metadata().provenance())
+    }
+
+    /// Returns the number of operations that were started with the key identified by `key`, and
+    /// the date of the last one, including uses that are not yet written to the database. The
+    /// caller needs the `get_info` permission on the key.
+    /// This backs `IKeystoreService::getKeyUsageStats`.
+    pub fn get_key_usage_stats(&self, key: &KeyDescriptor) -> Result<KeyUsageStats> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let (key_id_guard, key_entry) = DB
+            .with(|db| {
+                LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                    )
+                })
+            })
+            .context(ks_err!("while trying to load key info."))?;
+
+        let mut stats = key_entry.metadata().usage_stats();
+        stats.merge(&KEY_USAGE.pending_usage(key_id_guard.id()));
+        Ok(stats)
+    }
+
+    /// Returns the public key of the key identified by `key` in the encoding `format`, taken
+    /// from the certificate of the key. The caller needs the `get_info` permission on the key.
+    /// Fails with `ResponseCode::INVALID_ARGUMENT` if the key has no certificate, e.g., because
+    /// it is a symmetric key.
+    /// This backs `IKeystoreService::getPublicKey`.
+    pub fn get_public_key(&self, key: &KeyDescriptor, format: PublicKeyFormat) -> Result<Vec<u8>> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let (_, mut key_entry) = DB
+            .with(|db| {
+                LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::PUBLIC,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                    )
+                })
+            })
+            .context(ks_err!("while trying to load key info."))?;
+
+        let cert = key_entry
+            .take_cert()
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("The key has no certificate."))?;
+        export_public_key(&cert, format).context(ks_err!())
+    }
+
     fn update_subcomponent(
         &self,
         key: &KeyDescriptor,
         public_cert: Option<&[u8]>,
         certificate_chain: Option<&[u8]>,
     ) -> Result<()> {
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        // Storing certificates is subject to the same limits as importing keys. Clearing them is
+        // not.
+        if public_cert.is_some() || certificate_chain.is_some() {
+            let data_size =
+                public_cert.map_or(0, |c| c.len()) + certificate_chain.map_or(0, |c| c.len());
+            IMPORT_LIMITER
+                .check_import(caller_uid, key.domain, data_size)
+                .context(ks_err!("Import limit exceeded."))?;
+        }
+        if let Some(cert_chain) = certificate_chain {
+            check_cert_chain(caller_uid, key.domain, cert_chain)
+                .context(ks_err!("Invalid certificate chain."))?;
+        }
         let super_key = SUPER_KEY
             .read()
             .unwrap()
             .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
 
-        DB.with::<_, Result<()>>(|db| {
+        let result = DB.with::<_, Result<()>>(|db| {
             let entry = match LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
                 db.borrow_mut().load_key_entry(
                     key,
@@ -235,7 +452,7 @@ impl KeystoreService {
             let key = match (key.domain, &key.alias) {
                 (Domain::APP, Some(ref alias)) => KeyDescriptor {
                     domain: Domain::APP,
-                    nspace: ThreadState::get_calling_uid() as i64,
+                    nspace: calling_identity::get_calling_uid() as i64,
                     alias: Some(alias.clone()),
                     blob: None,
                 },
@@ -258,6 +475,143 @@ impl KeystoreService {
             )
             .context(ks_err!("Failed to insert new certificate."))?;
             Ok(())
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!())
+    }
+
+    /// Replaces the public certificate and the certificate chain of an existing key and
+    /// applies `grant_updates` atomically, e.g., to grant a verifier access to the key together
+    /// with the certificate it is supposed to verify. The caller needs the `update` permission
+    /// on the key, the `grant` permission for every update, and every permission it grants.
+    /// Isolated processes cannot grant, see `IsolatedCallers::check_grant`.
+    /// Returns the grant descriptors of the `GrantUpdate::Grant` updates in order.
+    /// This backs `IKeystoreService::updateSubcomponentAndGrants`.
+    pub fn update_subcomponent_and_grants(
+        &self,
+        key: &KeyDescriptor,
+        public_cert: Option<&[u8]>,
+        certificate_chain: Option<&[u8]>,
+        grant_updates: &[GrantUpdate],
+    ) -> Result<Vec<KeyDescriptor>> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        if grant_updates.iter().any(|update| matches!(update, GrantUpdate::Grant { .. })) {
+            ISOLATED_CALLERS.check_grant(caller_uid).context(ks_err!())?;
+        }
+        if public_cert.is_some() || certificate_chain.is_some() {
+            let data_size =
+                public_cert.map_or(0, |c| c.len()) + certificate_chain.map_or(0, |c| c.len());
+            IMPORT_LIMITER
+                .check_import(caller_uid, key.domain, data_size)
+                .context(ks_err!("Import limit exceeded."))?;
+        }
+        if let Some(cert_chain) = certificate_chain {
+            check_cert_chain(caller_uid, key.domain, cert_chain)
+                .context(ks_err!("Invalid certificate chain."))?;
+        }
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let result = DB.with(|db| {
+            let (key_id_guard, _) = LEGACY_IMPORTER
+                .with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::Update, k, &av),
+                    )
+                })
+                .context(ks_err!("Failed to load key entry."))?;
+
+            db.borrow_mut().update_subcomponents_and_grants(
+                &key_id_guard,
+                public_cert,
+                certificate_chain,
+                grant_updates,
+                |k, update| match update {
+                    GrantUpdate::Grant { access_vector, .. } => {
+                        check_grant_permission(*access_vector, k)
+                    }
+                    GrantUpdate::Ungrant { .. } => check_key_permission(KeyPerm::Grant, k, &None),
+                },
+            )
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!("KeystoreService::update_subcomponent_and_grants."))
+    }
+
+    /// Replaces the labels of a key with `metadata`, e.g., "rotation-generation" or "purpose".
+    /// Labels are small opaque values that callers attach to their keys instead of encoding them
+    /// in the alias. A key has at most 16 labels, with non-empty names of at most 64 bytes and
+    /// values of at most 256 bytes. The caller needs the `update` permission on the key, like
+    /// for `updateSubcomponent`. The labels are deleted with the key.
+    /// This backs `IKeystoreService::setEntryMetadata`.
+    pub fn set_entry_metadata(&self, key: &KeyDescriptor, metadata: &EntryMetadata) -> Result<()> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        if metadata.len() > MAX_ENTRY_METADATA_LABELS {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Too many labels: {}.", metadata.len()));
+        }
+        if let Some((name, _)) = metadata.iter().find(|(name, value)| {
+            name.is_empty()
+                || name.len() > MAX_ENTRY_METADATA_NAME_SIZE
+                || value.len() > MAX_ENTRY_METADATA_VALUE_SIZE
+        }) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Invalid label {:?}.", name));
+        }
+        let caller_uid = calling_identity::get_calling_uid();
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        DB.with(|db| {
+            let (key_id_guard, _) = LEGACY_IMPORTER
+                .with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::Update, k, &av),
+                    )
+                })
+                .context(ks_err!("Failed to load key entry."))?;
+            db.borrow_mut().set_entry_metadata(&key_id_guard, metadata)
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns the labels of a key, see `set_entry_metadata`. The caller needs the `get_info`
+    /// permission on the key, like for `getKeyEntry`.
+    /// This backs `IKeystoreService::getEntryMetadata`.
+    pub fn get_entry_metadata(&self, key: &KeyDescriptor) -> Result<EntryMetadata> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        DB.with(|db| {
+            let (key_id_guard, _) = LEGACY_IMPORTER
+                .with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                    )
+                })
+                .context(ks_err!("Failed to load key entry."))?;
+            db.borrow_mut().get_entry_metadata(&key_id_guard)
         })
         .context(ks_err!())
     }
@@ -270,7 +624,7 @@ impl KeystoreService {
         let mut k = match domain {
             Domain::APP => KeyDescriptor {
                 domain,
-                nspace: ThreadState::get_calling_uid() as u64 as i64,
+                nspace: calling_identity::get_calling_uid() as u64 as i64,
                 ..Default::default()
             },
             Domain::SELINUX => KeyDescriptor { domain, nspace: namespace, ..Default::default() },
@@ -305,13 +659,13 @@ impl KeystoreService {
     fn list_entries(&self, domain: Domain, namespace: i64) -> Result<Vec<KeyDescriptor>> {
         let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
 
-        DB.with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, None))
+        DB_READER.with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, None))
     }
 
     fn count_num_entries(&self, domain: Domain, namespace: i64) -> Result<i32> {
         let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
 
-        DB.with(|db| count_key_entries(&mut db.borrow_mut(), k.domain, k.nspace))
+        DB_READER.with(|db| count_key_entries(&mut db.borrow_mut(), k.domain, k.nspace))
     }
 
     fn list_entries_batched(
@@ -321,41 +675,201 @@ impl KeystoreService {
         start_past_alias: Option<&str>,
     ) -> Result<Vec<KeyDescriptor>> {
         let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
-        DB.with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, start_past_alias))
+        DB_READER
+            .with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, start_past_alias))
+    }
+
+    /// Lists the keys of a namespace page by page. Unlike `listEntriesBatched`, which continues
+    /// after the last alias of the previous batch, the next page is selected by the opaque
+    /// continuation token of the previous page. The last page has no continuation token.
+    /// This backs `IKeystoreService::listEntriesPaged`.
+    pub fn list_entries_paged(
+        &self,
+        domain: Domain,
+        namespace: i64,
+        continuation_token: Option<&str>,
+    ) -> Result<KeyEntryPage> {
+        let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
+        DB_READER.with(|db| {
+            list_key_entries_page(&mut db.borrow_mut(), k.domain, k.nspace, continuation_token)
+        })
+    }
+
+    /// Like `listEntriesBatched`, but lists only the keys that match `filter`, e.g., all auth
+    /// bound EC keys created after a given date. The filter is evaluated by keystore, so that
+    /// callers do not have to load the characteristics of every key. Legacy keys are only
+    /// listed once they have been imported into the database.
+    /// This backs `IKeystoreService::listEntriesFiltered`.
+    pub fn list_entries_filtered(
+        &self,
+        domain: Domain,
+        namespace: i64,
+        start_past_alias: Option<&str>,
+        filter: &KeyEntryFilter,
+    ) -> Result<Vec<KeyDescriptor>> {
+        let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
+        DB_READER.with(|db| {
+            list_key_entries_filtered(
+                &mut db.borrow_mut(),
+                k.domain,
+                k.nspace,
+                start_past_alias,
+                filter,
+            )
+        })
     }
 
     fn delete_key(&self, key: &KeyDescriptor) -> Result<()> {
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
         let super_key = SUPER_KEY
             .read()
             .unwrap()
             .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
 
-        DB.with(|db| {
+        let result = DB.with(|db| {
             LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
                 db.borrow_mut().unbind_key(key, KeyType::Client, caller_uid, |k, av| {
                     check_key_permission(KeyPerm::Delete, k, &av)
                         .context(ks_err!("During delete_key."))
                 })
             })
-        })
-        .context(ks_err!("Trying to unbind the key."))?;
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!("Trying to unbind the key."))?;
         Ok(())
     }
 
+    /// Deletes all keys in the caller's namespace whose alias starts with `prefix` and returns
+    /// their number. If domain is Domain::APP, the namespace is always the calling UID. Unlike
+    /// a sequence of `deleteKey` calls, this is transactional, so a caller that gets killed
+    /// midway does not leave part of a key family behind, and the garbage collector is
+    /// notified once. The caller needs the `delete` permission on every affected key.
+    /// This backs `IKeystoreService::deleteKeysByPrefix`.
+    pub fn delete_keys_by_prefix(
+        &self,
+        domain: Domain,
+        namespace: i64,
+        prefix: &str,
+    ) -> Result<i32> {
+        // The prefix is checked like an alias, along with the domain and namespace.
+        let prefix_key = KeyDescriptor {
+            domain,
+            nspace: namespace,
+            alias: Some(prefix.to_string()),
+            blob: None,
+        };
+        check_key_descriptor(&prefix_key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        let nspace = match domain {
+            Domain::APP => caller_uid as i64,
+            Domain::SELINUX => namespace,
+            _ => {
+                return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context(ks_err!("Domain must be one of APP or SELINUX."));
+            }
+        };
+        if prefix.is_empty() {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Prefix must not be empty."));
+        }
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let result = DB.with(|db| {
+            // Import matching legacy keys first, so that the deletion below covers them.
+            let legacy_keys = LEGACY_IMPORTER
+                .list_uid(domain, nspace)
+                .context(ks_err!("Trying to list legacy keys."))?;
+            for key in legacy_keys
+                .iter()
+                .filter(|k| k.alias.as_deref().is_some_and(|a| a.starts_with(prefix)))
+            {
+                LEGACY_IMPORTER
+                    .with_try_import(key, caller_uid, super_key.clone(), || {
+                        db.borrow_mut().load_key_entry(
+                            key,
+                            KeyType::Client,
+                            KeyEntryLoadBits::NONE,
+                            caller_uid,
+                            |k, av| check_key_permission(KeyPerm::Delete, k, &av),
+                        )
+                    })
+                    .context(ks_err!("Trying to import legacy key."))?;
+            }
+
+            db.borrow_mut().unbind_keys_by_alias_prefix(domain, nspace, prefix, |k, av| {
+                check_key_permission(KeyPerm::Delete, k, &av)
+            })
+        });
+        KEY_ENTRY_CACHE.invalidate_namespace(domain, nspace);
+        result.context(ks_err!("Trying to unbind the keys.")).map(|count| count as i32)
+    }
+
+    /// Atomically changes the alias of the key identified by `key` to `new_alias`. The key
+    /// stays in its domain and namespace and keeps its grants and metadata. The caller needs
+    /// the `delete` permission on the source and the `rebind` permission on the destination.
+    /// This backs `IKeystoreService::renameKey`. `IKeystoreService::moveEntry` is the same
+    /// operation, so its binder method calls this one as well.
+    pub fn rename_key(&self, key: &KeyDescriptor, new_alias: &str) -> Result<()> {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        check_alias(new_alias).context(ks_err!())?;
+        match key.domain {
+            Domain::APP | Domain::SELINUX => (),
+            _ => {
+                return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                    .context(ks_err!("Source domain must be one of APP or SELINUX."));
+            }
+        };
+        if new_alias.is_empty() {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("New alias must not be empty."));
+        }
+
+        let caller_uid = calling_identity::get_calling_uid();
+        let super_key = SUPER_KEY
+            .read()
+            .unwrap()
+            .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
+
+        let result = DB.with(|db| {
+            let (key_id_guard, _) = LEGACY_IMPORTER
+                .with_try_import(key, caller_uid, super_key, || {
+                    db.borrow_mut().load_key_entry(
+                        key,
+                        KeyType::Client,
+                        KeyEntryLoadBits::NONE,
+                        caller_uid,
+                        |k, av| check_key_permission(KeyPerm::Delete, k, &av),
+                    )
+                })
+                .context(ks_err!("Failed to load key entry."))?;
+
+            db.borrow_mut().rename_key(key_id_guard, new_alias, |k| {
+                check_key_permission(KeyPerm::Rebind, k, &None)
+            })
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!("KeystoreService::rename_key."))
+    }
+
     fn grant(
         &self,
         key: &KeyDescriptor,
         grantee_uid: i32,
         access_vector: permission::KeyPermSet,
     ) -> Result<KeyDescriptor> {
-        let caller_uid = ThreadState::get_calling_uid();
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        ISOLATED_CALLERS.check_grant(caller_uid).context(ks_err!())?;
         let super_key = SUPER_KEY
             .read()
             .unwrap()
             .get_after_first_unlock_key_by_user_id(uid_to_android_user(caller_uid));
 
-        DB.with(|db| {
+        let result = DB.with(|db| {
             LEGACY_IMPORTER.with_try_import(key, caller_uid, super_key, || {
                 db.borrow_mut().grant(
                     key,
@@ -365,22 +879,33 @@ impl KeystoreService {
                     |k, av| check_grant_permission(*av, k).context("During grant."),
                 )
             })
-        })
-        .context(ks_err!("KeystoreService::grant."))
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!("KeystoreService::grant."))
     }
 
     fn ungrant(&self, key: &KeyDescriptor, grantee_uid: i32) -> Result<()> {
-        DB.with(|db| {
-            db.borrow_mut().ungrant(key, ThreadState::get_calling_uid(), grantee_uid as u32, |k| {
+        check_key_descriptor(key, DescriptorUse::Lookup).context(ks_err!())?;
+        let caller_uid = calling_identity::get_calling_uid();
+        let result = DB.with(|db| {
+            db.borrow_mut().ungrant(key, caller_uid, grantee_uid as u32, |k| {
                 check_key_permission(KeyPerm::Grant, k, &None)
             })
-        })
-        .context(ks_err!("KeystoreService::ungrant."))
+        });
+        KEY_ENTRY_CACHE.invalidate_key(key, caller_uid);
+        result.context(ks_err!("KeystoreService::ungrant."))
     }
 }
 
 impl binder::Interface for KeystoreService {}
 
+/// Applies the isolated process policy to the caller, see `isolated_callers`.
+fn check_isolated_caller() -> binder::Result<()> {
+    ISOLATED_CALLERS
+        .check_caller(calling_identity::get_calling_uid(), calling_identity::get_calling_pid())
+        .map_err(into_logged_binder)
+}
+
 // Implementation of IKeystoreService. See AIDL spec at
 // system/security/keystore2/binder/android/security/keystore2/IKeystoreService.aidl
 impl IKeystoreService for KeystoreService {
@@ -389,10 +914,14 @@ impl IKeystoreService for KeystoreService {
         security_level: SecurityLevel,
     ) -> binder::Result<Strong<dyn IKeystoreSecurityLevel>> {
         let _wp = wd::watch_millis_with("IKeystoreService::getSecurityLevel", 500, security_level);
+        let _cpu = cpu_accounting::account("IKeystoreService::getSecurityLevel");
+        check_isolated_caller()?;
         self.get_security_level(security_level).map_err(into_logged_binder)
     }
     fn getKeyEntry(&self, key: &KeyDescriptor) -> binder::Result<KeyEntryResponse> {
         let _wp = wd::watch("IKeystoreService::get_key_entry");
+        let _cpu = cpu_accounting::account("IKeystoreService::getKeyEntry");
+        check_isolated_caller()?;
         self.get_key_entry(key).map_err(into_logged_binder)
     }
     fn updateSubcomponent(
@@ -402,16 +931,23 @@ impl IKeystoreService for KeystoreService {
         certificate_chain: Option<&[u8]>,
     ) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreService::updateSubcomponent");
+        let _cpu = cpu_accounting::account("IKeystoreService::updateSubcomponent");
+        check_isolated_caller()?;
         self.update_subcomponent(key, public_cert, certificate_chain).map_err(into_logged_binder)
     }
     fn listEntries(&self, domain: Domain, namespace: i64) -> binder::Result<Vec<KeyDescriptor>> {
         let _wp = wd::watch("IKeystoreService::listEntries");
+        let _cpu = cpu_accounting::account("IKeystoreService::listEntries");
+        check_isolated_caller()?;
         self.list_entries(domain, namespace).map_err(into_logged_binder)
     }
     fn deleteKey(&self, key: &KeyDescriptor) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreService::deleteKey");
+        let _cpu = cpu_accounting::account("IKeystoreService::deleteKey");
+        check_isolated_caller()?;
         let result = self.delete_key(key);
-        log_key_deleted(key, ThreadState::get_calling_uid(), result.is_ok());
+        log_key_deleted(key, calling_identity::get_calling_uid(), result.is_ok());
+        publish_key_event(KeystoreEventType::KEY_DELETED, SecurityLevel::KEYSTORE, result.is_ok());
         result.map_err(into_logged_binder)
     }
     fn grant(
@@ -421,10 +957,14 @@ impl IKeystoreService for KeystoreService {
         access_vector: i32,
     ) -> binder::Result<KeyDescriptor> {
         let _wp = wd::watch("IKeystoreService::grant");
+        let _cpu = cpu_accounting::account("IKeystoreService::grant");
+        check_isolated_caller()?;
         self.grant(key, grantee_uid, access_vector.into()).map_err(into_logged_binder)
     }
     fn ungrant(&self, key: &KeyDescriptor, grantee_uid: i32) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreService::ungrant");
+        let _cpu = cpu_accounting::account("IKeystoreService::ungrant");
+        check_isolated_caller()?;
         self.ungrant(key, grantee_uid).map_err(into_logged_binder)
     }
     fn listEntriesBatched(
@@ -434,11 +974,103 @@ impl IKeystoreService for KeystoreService {
         start_past_alias: Option<&str>,
     ) -> binder::Result<Vec<KeyDescriptor>> {
         let _wp = wd::watch("IKeystoreService::listEntriesBatched");
+        let _cpu = cpu_accounting::account("IKeystoreService::listEntriesBatched");
+        check_isolated_caller()?;
         self.list_entries_batched(domain, namespace, start_past_alias).map_err(into_logged_binder)
     }
 
     fn getNumberOfEntries(&self, domain: Domain, namespace: i64) -> binder::Result<i32> {
         let _wp = wd::watch("IKeystoreService::getNumberOfEntries");
+        let _cpu = cpu_accounting::account("IKeystoreService::getNumberOfEntries");
+        check_isolated_caller()?;
         self.count_num_entries(domain, namespace).map_err(into_logged_binder)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calling_identity::CallingIdentity;
+    use crate::key_descriptor_validation::MAX_ALIAS_LEN;
+    use crate::key_perm_set;
+
+    fn assert_invalid<T: std::fmt::Debug>(result: Result<T>) {
+        assert_eq!(
+            Some(&Error::Rc(ResponseCode::INVALID_ARGUMENT)),
+            result.unwrap_err().root_cause().downcast_ref::<Error>()
+        );
+    }
+
+    fn key(domain: Domain, nspace: i64, alias: Option<&str>) -> KeyDescriptor {
+        KeyDescriptor { domain, nspace, alias: alias.map(str::to_string), blob: None }
+    }
+
+    // The descriptors are validated before the caller is identified or the database is touched,
+    // so these tests do not need a running keystore.
+
+    #[test]
+    fn test_get_key_provenance_validates_descriptor() {
+        let service = KeystoreService::default();
+        assert_invalid(service.get_key_provenance(&key(Domain(42), 0, Some("key"))));
+        assert_invalid(service.get_key_provenance(&key(Domain::APP, 0, None)));
+    }
+
+    #[test]
+    fn test_get_key_usage_stats_validates_descriptor() {
+        let service = KeystoreService::default();
+        assert_invalid(service.get_key_usage_stats(&key(Domain::SELINUX, -1, Some("key"))));
+        assert_invalid(service.get_key_usage_stats(&key(Domain::APP, 0, Some("key\0"))));
+    }
+
+    #[test]
+    fn test_update_subcomponent_and_grants_validates_descriptor() {
+        let service = KeystoreService::default();
+        let long_alias = "a".repeat(MAX_ALIAS_LEN + 1);
+        assert_invalid(service.update_subcomponent_and_grants(
+            &key(Domain::APP, 0, Some(&long_alias)),
+            Some(b"cert"),
+            None,
+            &[],
+        ));
+        let with_blob =
+            KeyDescriptor { blob: Some(b"blob".to_vec()), ..key(Domain::KEY_ID, 1, None) };
+        assert_invalid(service.update_subcomponent_and_grants(&with_blob, None, None, &[]));
+    }
+
+    #[test]
+    fn test_update_subcomponent_and_grants_rejects_isolated_grantor() {
+        let service = KeystoreService::default();
+        let key = key(Domain::APP, 0, Some("key"));
+        let grant =
+            GrantUpdate::Grant { grantee_uid: 10001, access_vector: key_perm_set![KeyPerm::Use] };
+        let result = CallingIdentity::new(99000, 42, None)
+            .run(|| service.update_subcomponent_and_grants(&key, None, None, &[grant]));
+        assert_eq!(
+            Some(&Error::Rc(ResponseCode::PERMISSION_DENIED)),
+            result.unwrap_err().root_cause().downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn test_delete_keys_by_prefix_validates_prefix() {
+        let service = KeystoreService::default();
+        assert_invalid(service.delete_keys_by_prefix(Domain::APP, 0, "key\0"));
+        assert_invalid(service.delete_keys_by_prefix(
+            Domain::APP,
+            0,
+            &"a".repeat(MAX_ALIAS_LEN + 1),
+        ));
+        assert_invalid(service.delete_keys_by_prefix(Domain::SELINUX, -1, "key"));
+    }
+
+    #[test]
+    fn test_rename_key_validates_descriptor_and_new_alias() {
+        let service = KeystoreService::default();
+        let source = key(Domain::APP, 0, Some("key"));
+        assert_invalid(service.rename_key(&source, &"a".repeat(MAX_ALIAS_LEN + 1)));
+        assert_invalid(service.rename_key(&source, "new\0alias"));
+        assert_invalid(service.rename_key(&key(Domain::APP, 0, None), "new_alias"));
+        let with_blob = KeyDescriptor { blob: Some(b"blob".to_vec()), ..source };
+        assert_invalid(service.rename_key(&with_blob, "new_alias"));
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements session-bound keys, which are deleted when the process that created
+//! them dies, e.g., per-session encryption keys that must not outlive the process even if it
+//! crashes. A key is session-bound if it is generated or imported with
+//! `KEY_FLAG_SESSION_BOUND`.
+//!
+//! Keystore holds a pidfd of the creating process and a thread waits for the processes to
+//! exit. The keys of a dead process are unbound like deleted keys, and the garbage collector
+//! deletes them from KeyMint. Keys are tracked in memory, so the session-bound keys of the
+//! previous keystore instance are deleted when keystore starts: their processes can no longer
+//! be told apart from new processes with the same pid.
+
+use crate::calling_identity;
+use crate::database::KeyType;
+use crate::error::Error;
+use crate::globals::{DB, KEY_ENTRY_CACHE};
+use crate::ks_err;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use anyhow::{Context, Result};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Condvar, LazyLock, Mutex};
+
+/// Binds the new key to the calling process. Mirrors
+/// `IKeystoreSecurityLevel::KEY_FLAG_SESSION_BOUND`, which is defined outside this tree.
+pub const KEY_FLAG_SESSION_BOUND: i32 = 0x2;
+
+/// How long the watcher waits for processes to exit before it looks for new ones, in
+/// milliseconds.
+const POLL_TIMEOUT_MS: i32 = 1000;
+
+/// The session-bound keys of this keystore instance.
+pub static SESSION_KEYS: LazyLock<SessionKeys> = LazyLock::new(Default::default);
+
+/// A process that owns session-bound keys.
+#[derive(Debug)]
+pub struct SessionKeyOwner {
+    pid: i32,
+    pidfd: OwnedFd,
+}
+
+impl SessionKeyOwner {
+    /// Returns the owner of a new session-bound key, i.e., the calling process.
+    pub fn of_caller() -> Result<Self> {
+        let pid = calling_identity::get_calling_pid();
+        // Oneway calls do not report the calling process.
+        if pid <= 0 {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("No calling process to bind the key to."));
+        }
+        Self::of_pid(pid)
+    }
+
+    fn of_pid(pid: i32) -> Result<Self> {
+        // SAFETY: pidfd_open takes no pointers.
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(Error::sys()).context(ks_err!(
+                "pidfd_open({}) failed: {:?}",
+                pid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: The file descriptor was just opened and is owned by nothing else.
+        let pidfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        Ok(Self { pid, pidfd })
+    }
+
+    /// Returns true if the process exited. A pidfd becomes readable when its process exits.
+    fn is_dead(&self) -> bool {
+        let mut pollfd =
+            libc::pollfd { fd: self.pidfd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: The pointer is valid because it comes from a reference, and poll doesn't
+        // retain it beyond the call.
+        let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        ready > 0
+    }
+}
+
+/// The session-bound keys of a live process.
+#[derive(Debug)]
+struct Session {
+    owner: SessionKeyOwner,
+    key_ids: Vec<i64>,
+}
+
+/// Tracks the session-bound keys by the process that created them.
+#[derive(Debug, Default)]
+pub struct SessionKeys {
+    sessions: Mutex<Vec<Session>>,
+    registered: Condvar,
+}
+
+impl SessionKeys {
+    /// Binds the key `key_id` to `owner`. Keys of the same process share its pidfd.
+    pub fn register(&self, key_id: i64, owner: SessionKeyOwner) {
+        let mut sessions = self.sessions.lock().unwrap();
+        // A session of a process with the same pid that is still alive belongs to the same
+        // process. A dead one is about to be collected by the watcher.
+        match sessions.iter_mut().find(|s| s.owner.pid == owner.pid && !s.owner.is_dead()) {
+            Some(session) => session.key_ids.push(key_id),
+            None => sessions.push(Session { owner, key_ids: vec![key_id] }),
+        }
+        self.registered.notify_one();
+    }
+
+    /// Starts the thread that deletes the keys of processes when they exit.
+    pub fn watch(&'static self) {
+        std::thread::spawn(move || loop {
+            let key_ids = self.wait_for_exits();
+            delete_keys(&key_ids);
+        });
+    }
+
+    /// Waits until at least one process with session-bound keys exited, and returns their keys.
+    fn wait_for_exits(&self) -> Vec<i64> {
+        loop {
+            let mut pollfds: Vec<libc::pollfd> = {
+                let mut sessions = self.sessions.lock().unwrap();
+                while sessions.is_empty() {
+                    sessions = self.registered.wait(sessions).unwrap();
+                }
+                sessions
+                    .iter()
+                    .map(|s| libc::pollfd {
+                        fd: s.owner.pidfd.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    })
+                    .collect()
+            };
+            // The pidfds stay open while polling, because only this thread removes sessions.
+            // SAFETY: The pointer and length come from a valid vector, and poll doesn't retain
+            // them beyond the call.
+            let ready = unsafe {
+                libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, POLL_TIMEOUT_MS)
+            };
+            if ready <= 0 {
+                continue;
+            }
+            let exited = self.take_exited();
+            if !exited.is_empty() {
+                return exited;
+            }
+        }
+    }
+
+    /// Removes the sessions of the processes that exited, and returns their keys.
+    fn take_exited(&self) -> Vec<i64> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut key_ids = Vec::new();
+        sessions.retain_mut(|s| {
+            if s.owner.is_dead() {
+                log::info!("Process {} exited, deleting its session-bound keys.", s.owner.pid);
+                key_ids.append(&mut s.key_ids);
+                false
+            } else {
+                true
+            }
+        });
+        key_ids
+    }
+}
+
+/// Unbinds the given keys. Keys that were already deleted or replaced are skipped.
+fn delete_keys(key_ids: &[i64]) {
+    for key_id in key_ids {
+        let key =
+            KeyDescriptor { domain: Domain::KEY_ID, nspace: *key_id, alias: None, blob: None };
+        // The keys are deleted on behalf of their owner, so no permission is checked.
+        let result =
+            DB.with(|db| db.borrow_mut().unbind_key(&key, KeyType::Client, 0, |_, _| Ok(())));
+        if let Err(e) = result {
+            if e.root_cause().downcast_ref() != Some(&Error::Rc(ResponseCode::KEY_NOT_FOUND)) {
+                log::error!("Failed to delete session-bound key {key_id}: {e:?}");
+            }
+        }
+    }
+    KEY_ENTRY_CACHE.invalidate_all();
+}
+
+/// Deletes the session-bound keys that a previous keystore instance left behind. Must be called
+/// before keystore accepts calls.
+pub fn delete_orphaned_session_keys() {
+    match DB.with(|db| db.borrow_mut().get_session_bound_key_ids()) {
+        Ok(key_ids) if key_ids.is_empty() => {}
+        Ok(key_ids) => {
+            log::info!("Deleting {} session-bound keys of the previous instance.", key_ids.len());
+            delete_keys(&key_ids);
+        }
+        Err(e) => log::error!("Failed to look up session-bound keys: {e:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let keys = SessionKeys::default();
+        let pid = std::process::id() as i32;
+        keys.register(1, SessionKeyOwner::of_pid(pid).unwrap());
+        keys.register(2, SessionKeyOwner::of_pid(pid).unwrap());
+        // Keys of the same live process share a session.
+        {
+            let sessions = keys.sessions.lock().unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].key_ids, vec![1, 2]);
+            assert!(!sessions[0].owner.is_dead());
+        }
+        assert!(keys.take_exited().is_empty());
+    }
+
+    #[test]
+    fn test_exited_process() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let owner = SessionKeyOwner::of_pid(child.id() as i32).unwrap();
+        child.wait().unwrap();
+        assert!(owner.is_dead());
+
+        let keys = SessionKeys::default();
+        keys.register(3, owner);
+        keys.register(4, SessionKeyOwner::of_pid(std::process::id() as i32).unwrap());
+        assert_eq!(keys.take_exited(), vec![3]);
+        assert_eq!(keys.sessions.lock().unwrap().len(), 1);
+    }
+}
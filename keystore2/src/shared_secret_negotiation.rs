@@ -14,6 +14,7 @@
 
 //! This module implements the shared secret negotiation.
 
+use crate::boot_profile::BOOT_PROFILE;
 use crate::error::{map_binder_status, map_binder_status_code, Error};
 use crate::globals::get_keymint_device;
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
@@ -27,7 +28,7 @@ use anyhow::Result;
 use binder::get_declared_instances;
 use keystore2_hal_names::get_hidl_instances;
 use std::fmt::{self, Display, Formatter};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// This function initiates the shared secret negotiation. It starts a thread and then returns
 /// immediately. The thread gets hal names from the android ServiceManager. It then attempts
@@ -41,11 +42,13 @@ use std::time::Duration;
 /// An error during the second phase or a checksum mismatch leads to a panic.
 pub fn perform_shared_secret_negotiation() {
     std::thread::spawn(|| {
+        let started = Instant::now();
         let participants = list_participants()
             .expect("In perform_shared_secret_negotiation: Trying to list participants.");
         let connected = connect_participants(participants);
         negotiate_shared_secret(connected);
         log::info!("Shared secret negotiation concluded successfully.");
+        BOOT_PROFILE.record("shared_secret_negotiation", started);
 
         // Once shared secret negotiation is done, the StrongBox and TEE have a common key that
         // can be used to authenticate a possible RootOfTrust transfer.
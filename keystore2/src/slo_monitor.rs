@@ -0,0 +1,321 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module monitors the latency of KeyMint calls against service level objectives (SLOs),
+//! so that secure elements whose performance degrades over time get noticed, e.g., on dogfood
+//! builds. It tracks the p99 latency of IKeyMintDevice::begin and IKeyMintOperation::finish per
+//! security level in consecutive windows of `WINDOW`. When the p99 latency exceeds its
+//! threshold in `keystore.slo.breach_windows` consecutive windows, the monitor logs a warning
+//! and an SLO_BREACH_STATS atom. This repeats every as many windows for as long as the breach
+//! lasts.
+//!
+//! The thresholds are configured with system properties of the form
+//! `keystore.slo.<api>.<security level>.p99_ms`, e.g., `keystore.slo.begin.strongbox.p99_ms`.
+//! A threshold of 0 disables monitoring of the API on the security level.
+
+use crate::import_limits::read_usize_property;
+use crate::metrics_store::log_slo_breach_stats;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const BREACH_WINDOWS_PROPERTY: &str = "keystore.slo.breach_windows";
+
+const DEFAULT_BREACH_WINDOWS: usize = 5;
+
+/// The length of a window.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Windows with fewer samples do not have a meaningful p99 latency. They neither breach the SLO
+/// nor end a breach.
+const MIN_SAMPLES: usize = 20;
+
+/// The maximum number of samples kept per window. Later samples of the window are dropped.
+const MAX_SAMPLES: usize = 1000;
+
+/// The SLO monitor of this keystore instance.
+pub static SLO_MONITOR: LazyLock<SloMonitor> = LazyLock::new(Default::default);
+
+/// The KeyMint calls that are monitored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SloApi {
+    /// IKeyMintDevice::begin.
+    Begin,
+    /// IKeyMintOperation::finish.
+    Finish,
+}
+
+impl SloApi {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Begin => "IKeyMintDevice::begin",
+            Self::Finish => "IKeyMintOperation::finish",
+        }
+    }
+
+    /// Returns the threshold of the p99 latency of this call on `sec_level`, or None if it is not
+    /// monitored.
+    fn threshold(self, sec_level: SecurityLevel) -> Option<Duration> {
+        let (level, default_ms) = match sec_level {
+            SecurityLevel::TRUSTED_ENVIRONMENT => ("tee", 200),
+            SecurityLevel::STRONGBOX => ("strongbox", 2000),
+            _ => return None,
+        };
+        let api = match self {
+            Self::Begin => "begin",
+            Self::Finish => "finish",
+        };
+        let property = format!("keystore.slo.{api}.{level}.p99_ms");
+        match read_usize_property(&property, default_ms) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        }
+    }
+}
+
+/// A report of an SLO breach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SloBreach {
+    api: SloApi,
+    sec_level: SecurityLevel,
+    p99: Duration,
+    threshold: Duration,
+    windows: u32,
+}
+
+/// The latencies of the current window of one call on one security level, and the number of
+/// consecutive windows before it that breached the SLO.
+struct Tracker {
+    window_start: Instant,
+    samples: Vec<Duration>,
+    breached_windows: u32,
+}
+
+impl Tracker {
+    fn new(now: Instant) -> Self {
+        Self { window_start: now, samples: Vec::new(), breached_windows: 0 }
+    }
+
+    /// Returns the p99 latency of the current window, or None if it has too few samples.
+    fn p99(&mut self) -> Option<Duration> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        self.samples.sort_unstable();
+        Some(self.samples[(self.samples.len() * 99).div_ceil(100) - 1])
+    }
+
+    /// Ends the current window and starts a new one at `now`. Returns the p99 latency of the
+    /// window and the number of consecutive breached windows up to it if it breached the
+    /// `threshold`.
+    fn close_window(
+        &mut self,
+        now: Instant,
+        threshold: Option<Duration>,
+    ) -> Option<(Duration, u32)> {
+        let p99 = self.p99();
+        self.window_start = now;
+        self.samples.clear();
+        let (Some(p99), Some(threshold)) = (p99, threshold) else {
+            return None;
+        };
+        if p99 <= threshold {
+            self.breached_windows = 0;
+            return None;
+        }
+        self.breached_windows += 1;
+        Some((p99, self.breached_windows))
+    }
+}
+
+/// Tracks the latencies of KeyMint calls per call and security level.
+#[derive(Default)]
+pub struct SloMonitor {
+    trackers: Mutex<HashMap<(SloApi, SecurityLevel), Tracker>>,
+}
+
+impl SloMonitor {
+    /// Records a call of `api` on `sec_level` that took `latency`.
+    pub fn record(&self, api: SloApi, sec_level: SecurityLevel, latency: Duration) {
+        let breach_windows = read_usize_property(BREACH_WINDOWS_PROPERTY, DEFAULT_BREACH_WINDOWS);
+        let breach =
+            self.record_with(api, sec_level, latency, Instant::now(), breach_windows, || {
+                api.threshold(sec_level)
+            });
+        if let Some(b) = breach {
+            log::warn!(
+                "SLO breach: api={} security_level={:?} p99_ms={} threshold_ms={} windows={}",
+                b.api.name(),
+                b.sec_level,
+                b.p99.as_millis(),
+                b.threshold.as_millis(),
+                b.windows
+            );
+            log_slo_breach_stats(b.api.name(), b.sec_level);
+        }
+    }
+
+    fn record_with(
+        &self,
+        api: SloApi,
+        sec_level: SecurityLevel,
+        latency: Duration,
+        now: Instant,
+        breach_windows: usize,
+        threshold: impl FnOnce() -> Option<Duration>,
+    ) -> Option<SloBreach> {
+        let mut trackers = self.trackers.lock().unwrap();
+        let tracker = trackers.entry((api, sec_level)).or_insert_with(|| Tracker::new(now));
+        let mut breach = None;
+        if now.duration_since(tracker.window_start) >= WINDOW {
+            let threshold = threshold();
+            if let Some((p99, windows)) = tracker.close_window(now, threshold) {
+                if breach_windows > 0 && windows as usize % breach_windows == 0 {
+                    breach = Some(SloBreach {
+                        api,
+                        sec_level,
+                        p99,
+                        threshold: threshold.unwrap(),
+                        windows,
+                    });
+                }
+            }
+        }
+        if tracker.samples.len() < MAX_SAMPLES {
+            tracker.samples.push(latency);
+        }
+        breach
+    }
+}
+
+/// Records the latency of a KeyMint call when dropped.
+pub struct SloTimer {
+    api: SloApi,
+    sec_level: SecurityLevel,
+    start: Instant,
+}
+
+impl Drop for SloTimer {
+    fn drop(&mut self) {
+        SLO_MONITOR.record(self.api, self.sec_level, self.start.elapsed());
+    }
+}
+
+/// Starts timing a call of `api` on `sec_level`, which is recorded when the returned timer is
+/// dropped.
+pub fn time(api: SloApi, sec_level: SecurityLevel) -> SloTimer {
+    SloTimer { api, sec_level, start: Instant::now() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEE: SecurityLevel = SecurityLevel::TRUSTED_ENVIRONMENT;
+    const THRESHOLD: Duration = Duration::from_millis(100);
+
+    /// Records `count` calls of `latency` into the window starting at `start`, and returns the
+    /// breach reported by the first of them, which closes the previous window.
+    fn fill_window(
+        monitor: &SloMonitor,
+        start: Instant,
+        latency: Duration,
+        count: usize,
+    ) -> Option<SloBreach> {
+        let mut breaches = (0..count).map(|_| {
+            monitor.record_with(SloApi::Begin, TEE, latency, start, 3, || Some(THRESHOLD))
+        });
+        let first = breaches.next().flatten();
+        assert_eq!(breaches.flatten().next(), None);
+        first
+    }
+
+    #[test]
+    fn test_p99() {
+        let mut tracker = Tracker::new(Instant::now());
+        tracker.samples = (1..=MIN_SAMPLES as u64 - 1).map(Duration::from_millis).collect();
+        assert_eq!(tracker.p99(), None);
+        tracker.samples = (1..=200).rev().map(Duration::from_millis).collect();
+        assert_eq!(tracker.p99(), Some(Duration::from_millis(198)));
+        tracker.samples = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(tracker.p99(), Some(Duration::from_millis(99)));
+    }
+
+    #[test]
+    fn test_breach_after_consecutive_windows() {
+        let monitor = SloMonitor::default();
+        let start = Instant::now();
+        let slow = THRESHOLD * 2;
+        let window = |i: u32| start + WINDOW * i;
+
+        assert_eq!(fill_window(&monitor, window(0), slow, MIN_SAMPLES), None);
+        assert_eq!(fill_window(&monitor, window(1), slow, MIN_SAMPLES), None);
+        assert_eq!(fill_window(&monitor, window(2), slow, MIN_SAMPLES), None);
+        // The third slow window is closed.
+        let breach = fill_window(&monitor, window(3), slow, MIN_SAMPLES).unwrap();
+        assert_eq!(
+            breach,
+            SloBreach {
+                api: SloApi::Begin,
+                sec_level: TEE,
+                p99: slow,
+                threshold: THRESHOLD,
+                windows: 3
+            }
+        );
+        // The breach is reported again after three more windows.
+        assert_eq!(fill_window(&monitor, window(4), slow, MIN_SAMPLES), None);
+        assert_eq!(fill_window(&monitor, window(5), slow, MIN_SAMPLES), None);
+        assert_eq!(fill_window(&monitor, window(6), slow, MIN_SAMPLES).unwrap().windows, 6);
+    }
+
+    #[test]
+    fn test_fast_window_ends_breach() {
+        let monitor = SloMonitor::default();
+        let start = Instant::now();
+        let slow = THRESHOLD * 2;
+        let window = |i: u32| start + WINDOW * i;
+
+        fill_window(&monitor, window(0), slow, MIN_SAMPLES);
+        fill_window(&monitor, window(1), slow, MIN_SAMPLES);
+        // A window within the SLO ends the breach.
+        fill_window(&monitor, window(2), THRESHOLD, MIN_SAMPLES);
+        fill_window(&monitor, window(3), slow, MIN_SAMPLES);
+        // A window with too few samples neither breaches nor ends a breach.
+        assert_eq!(fill_window(&monitor, window(4), slow, MIN_SAMPLES - 1), None);
+        assert_eq!(fill_window(&monitor, window(5), slow, MIN_SAMPLES), None);
+        assert_eq!(fill_window(&monitor, window(6), slow, MIN_SAMPLES), None);
+        assert!(fill_window(&monitor, window(7), slow, MIN_SAMPLES).is_some());
+    }
+
+    #[test]
+    fn test_disabled_threshold() {
+        let monitor = SloMonitor::default();
+        let start = Instant::now();
+        for i in 0..10 {
+            for _ in 0..MIN_SAMPLES {
+                let breach = monitor.record_with(
+                    SloApi::Finish,
+                    TEE,
+                    Duration::from_secs(10),
+                    start + WINDOW * i,
+                    1,
+                    || None,
+                );
+                assert_eq!(breach, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module assigns storage tiers to key namespaces. Keys in a sensitive namespace are
+//! superencrypted with the UnlockedDeviceRequired super key of the owning user, even if they
+//! are not bound to the unlocked device state by their authorizations. Their key blobs can then
+//! only be read from the database while the user's device is unlocked, which gives system
+//! components a stronger at-rest protection without binding each key to user authentication.
+//!
+//! Sensitive namespaces are reserved along with the SELinux namespace itself: the read-only
+//! property `ro.keystore.sensitive_namespaces` holds a comma separated list of SELinux
+//! namespaces, as defined in keystore2_key_contexts, that are sensitive. All other namespaces,
+//! including all app namespaces, are normal.
+//!
+//! The tier is applied when a key is stored. Removing a namespace from the list does not
+//! change how existing keys are stored.
+
+use android_system_keystore2::aidl::android::system::keystore2::Domain::Domain;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+const SENSITIVE_NAMESPACES_PROPERTY: &str = "ro.keystore.sensitive_namespaces";
+
+/// The SELinux namespaces that are sensitive.
+static SENSITIVE_NAMESPACES: LazyLock<HashSet<i64>> =
+    LazyLock::new(|| match rustutils::system_properties::read(SENSITIVE_NAMESPACES_PROPERTY) {
+        Ok(Some(value)) => parse_namespaces(&value),
+        Ok(None) => HashSet::new(),
+        Err(e) => {
+            log::error!("Failed to read {SENSITIVE_NAMESPACES_PROPERTY}: {e:?}");
+            HashSet::new()
+        }
+    });
+
+/// The storage tier of a key namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageTier {
+    /// Keys are superencrypted as required by their authorizations.
+    Normal,
+    /// Keys are additionally bound to the unlocked device state at rest.
+    Sensitive,
+}
+
+/// Returns the storage tier of the given namespace.
+pub fn storage_tier(domain: Domain, nspace: i64) -> StorageTier {
+    if domain == Domain::SELINUX && SENSITIVE_NAMESPACES.contains(&nspace) {
+        StorageTier::Sensitive
+    } else {
+        StorageTier::Normal
+    }
+}
+
+/// Parses a comma separated list of namespaces. Invalid entries are logged and ignored.
+fn parse_namespaces(value: &str) -> HashSet<i64> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match s.parse() {
+            Ok(nspace) => Some(nspace),
+            Err(e) => {
+                log::error!("Invalid namespace {s:?} in {SENSITIVE_NAMESPACES_PROPERTY}: {e:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_namespaces() {
+        assert_eq!(parse_namespaces(""), HashSet::new());
+        assert_eq!(parse_namespaces("102, 103,,abc,-5"), HashSet::from([102, 103, -5]));
+    }
+}
@@ -19,6 +19,7 @@ use crate::{
     database::EncryptedBy,
     database::KeyEntry,
     database::KeyType,
+    database::SubComponentType,
     database::{KeyEntryLoadBits, KeyIdGuard, KeyMetaData, KeyMetaEntry, KeystoreDB},
     ec_crypto::ECDHPrivateKey,
     enforcements::Enforcements,
@@ -27,8 +28,10 @@ use crate::{
     key_parameter::{KeyParameter, KeyParameterValue},
     ks_err,
     legacy_importer::LegacyImporter,
+    metrics_store::log_key_blob_reencryption_stats,
     raw_device::KeyMintDevice,
-    utils::{watchdog as wd, AesGcm, AID_KEYSTORE},
+    storage_tier::StorageTier,
+    utils::{watchdog as wd, AesGcm, AID_KEYSTORE, USER_SYSTEM},
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, BlockMode::BlockMode, HardwareAuthToken::HardwareAuthToken,
@@ -249,6 +252,17 @@ struct BiometricUnlock {
     private: LockedKey,
 }
 
+/// Result of a key blob re-encryption pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReencryptionPassResult {
+    /// Number of key blobs that were re-encrypted during the pass.
+    pub blobs_reencrypted: usize,
+    /// Number of key blobs that could not be re-encrypted during the pass.
+    pub blobs_failed: usize,
+    /// Number of key blobs that still need to be re-encrypted after the pass.
+    pub blobs_remaining: usize,
+}
+
 #[derive(Default)]
 struct UserSuperKeys {
     /// The AfterFirstUnlock super key is used for synthetic password binding of authentication
@@ -289,9 +303,17 @@ impl SkmState {
 #[derive(Default)]
 pub struct SuperKeyManager {
     data: SkmState,
+    /// True if the system user is headless. See `unlock_user` and
+    /// `lock_unlocked_device_required_keys`.
+    headless_system_user_mode: bool,
 }
 
 impl SuperKeyManager {
+    /// Creates a super key manager for a device whose system user may be headless.
+    pub fn new(headless_system_user_mode: bool) -> Self {
+        Self { headless_system_user_mode, ..Default::default() }
+    }
+
     pub fn set_up_boot_level_cache(skm: &Arc<RwLock<Self>>, db: &mut KeystoreDB) -> Result<()> {
         let mut skm_guard = skm.write().unwrap();
         if skm_guard.data.boot_level_key_cache.is_some() {
@@ -667,10 +689,11 @@ impl SuperKeyManager {
         domain: &Domain,
         key_parameters: &[KeyParameter],
         flags: Option<i32>,
+        storage_tier: StorageTier,
         user_id: UserId,
         key_blob: &[u8],
     ) -> Result<(Vec<u8>, BlobMetaData)> {
-        match Enforcements::super_encryption_required(domain, key_parameters, flags) {
+        match Enforcements::super_encryption_required(domain, key_parameters, flags, storage_tier) {
             SuperEncryptionType::None => Ok((key_blob.to_vec(), BlobMetaData::new())),
             SuperEncryptionType::AfterFirstUnlock => {
                 // Encrypt the given key blob with the user's AfterFirstUnlock super key. If the
@@ -739,6 +762,100 @@ impl SuperKeyManager {
         }
     }
 
+    /// Re-encrypts up to `max_blobs` key blobs of the given user that are encrypted with a
+    /// superseded super key, i.e., a super key whose blobs get re-encrypted with another super key
+    /// on first use. This allows key blobs in an outdated format to be migrated without waiting for
+    /// them to be used. Progress is kept in the database, so an interrupted pass resumes where it
+    /// left off. Blobs that fail to re-encrypt are counted in `blobs_failed` and are retried by the
+    /// next pass. Returns `ResponseCode::LOCKED` if the user's UnlockedDeviceRequired super keys
+    /// are not in memory.
+    pub fn reencrypt_superseded_blobs(
+        &self,
+        db: &mut KeystoreDB,
+        user_id: UserId,
+        max_blobs: usize,
+    ) -> Result<ReencryptionPassResult> {
+        let user_keys = self
+            .data
+            .user_keys
+            .get(&user_id)
+            .filter(|k| k.unlocked_device_required_symmetric.is_some())
+            .ok_or(Error::Rc(ResponseCode::LOCKED))
+            .context(ks_err!("The user's super keys are not in memory."))?;
+        let superseded_key_ids: Vec<i64> = [
+            &user_keys.after_first_unlock,
+            &user_keys.unlocked_device_required_symmetric,
+            &user_keys.unlocked_device_required_private,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|k| k.reencrypt_with.is_some())
+        .filter_map(|k| match k.id {
+            SuperKeyIdentifier::DatabaseId(id) => Some(id),
+            SuperKeyIdentifier::BootLevel(_) => None,
+        })
+        .collect();
+
+        let mut result = ReencryptionPassResult::default();
+        for super_key_id in superseded_key_ids {
+            let limit = max_blobs.saturating_sub(result.blobs_reencrypted + result.blobs_failed);
+            let key_ids = db
+                .get_key_ids_encrypted_by(super_key_id, limit)
+                .context(ks_err!("Failed to get key ids."))?;
+            for key_id in key_ids {
+                let success = match self.reencrypt_key_blob(db, key_id) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::error!("Failed to re-encrypt key blob of key {key_id}: {e:?}");
+                        false
+                    }
+                };
+                log_key_blob_reencryption_stats(success);
+                if success {
+                    result.blobs_reencrypted += 1;
+                } else {
+                    result.blobs_failed += 1;
+                }
+            }
+            result.blobs_remaining += db
+                .count_keys_encrypted_by(super_key_id)
+                .context(ks_err!("Failed to count keys."))?;
+        }
+        Ok(result)
+    }
+
+    fn reencrypt_key_blob(&self, db: &mut KeystoreDB, key_id: i64) -> Result<()> {
+        let key = KeyDescriptor { domain: Domain::KEY_ID, nspace: key_id, alias: None, blob: None };
+        let (key_id_guard, mut key_entry) =
+            db.load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::KM, AID_KEYSTORE, |_, _| {
+                Ok(())
+            })
+            .context(ks_err!("Failed to load key entry."))?;
+        let (blob, blob_metadata) = key_entry
+            .take_key_blob_info()
+            .ok_or_else(Error::sys)
+            .context(ks_err!("Key entry has no key blob."))?;
+        let key_blob = self
+            .unwrap_key_if_required(&blob_metadata, &blob)
+            .context(ks_err!("Failed to unwrap."))?;
+        let (new_blob, Some(mut new_blob_metadata)) =
+            Self::reencrypt_if_required(&key_blob, &key_blob)
+                .context(ks_err!("Failed to re-encrypt."))?
+        else {
+            return Err(Error::sys()).context(ks_err!("Key blob is not super-encrypted."));
+        };
+        if let Some(km_uuid) = blob_metadata.km_uuid() {
+            new_blob_metadata.add(BlobMetaEntry::KmUuid(*km_uuid));
+        }
+        db.set_blob(
+            &key_id_guard,
+            SubComponentType::KEY_BLOB,
+            Some(&new_blob),
+            Some(&new_blob_metadata),
+        )
+        .context(ks_err!("Failed to store re-encrypted key blob."))
+    }
+
     fn create_super_key(
         &mut self,
         db: &mut KeystoreDB,
@@ -935,8 +1052,10 @@ impl SuperKeyManager {
                 // wipe the keys (unless a weak unlock method is enabled).  So just log the error.
             }
         }
-        // Wipe the plaintext copy of the keys, unless a weak unlock method is enabled.
-        if !weak_unlock_enabled {
+        // Wipe the plaintext copy of the keys, unless a weak unlock method is enabled. A headless
+        // system user is never unlocked with a password, so its keys could never be unlocked
+        // again. Enforcements treat its keys as locked while the device is locked for all users.
+        if !weak_unlock_enabled && !(self.headless_system_user_mode && user_id == USER_SYSTEM) {
             entry.unlocked_device_required_symmetric = None;
             entry.unlocked_device_required_private = None;
         }
@@ -1139,6 +1258,10 @@ impl SuperKeyManager {
     /// If the user state is AfterFirstUnlock:
     /// - Unlock the user's UnlockedDeviceRequired super keys only
     ///
+    /// If the user state is Uninitialized and the system user is headless:
+    /// - Initialize a secondary user with the given password. On such devices, secondary users
+    ///   may be created and brought to the foreground before their super keys were created.
+    ///
     pub fn unlock_user(
         &mut self,
         db: &mut KeystoreDB,
@@ -1151,6 +1274,13 @@ impl SuperKeyManager {
             UserState::AfterFirstUnlock(_) => {
                 self.unlock_unlocked_device_required_keys(db, user_id, password)
             }
+            UserState::Uninitialized
+                if self.headless_system_user_mode && user_id != USER_SYSTEM =>
+            {
+                log::info!("Initializing user {user_id} at first unlock.");
+                self.initialize_user(db, legacy_importer, user_id, password, false)
+                    .context(ks_err!("Failed to initialize user at first unlock."))
+            }
             UserState::Uninitialized => {
                 Err(Error::sys()).context(ks_err!("Tried to unlock an uninitialized user!"))
             }
@@ -1180,6 +1310,68 @@ impl SuperKeyManager {
             }
         }
     }
+
+    /// Re-binds the given user's password protected super keys from `old_password` to
+    /// `new_password`. This is called when the secret that LockSettingsService derives for the
+    /// user changes while the user's keys must survive, e.g., when the Gatekeeper password handle
+    /// of the user moves to another weaver slot or to a replaced security chip.
+    ///
+    /// All super keys are decrypted with the old password before any of them is re-encrypted, and
+    /// they are stored in a single transaction. So if the old password is wrong or the migration
+    /// fails, all super keys stay bound to the old password. The super keys themselves do not
+    /// change, so neither the keys that they encrypt nor the cached super keys are affected.
+    pub fn migrate_user_password(
+        &mut self,
+        db: &mut KeystoreDB,
+        legacy_importer: &LegacyImporter,
+        user_id: UserId,
+        old_password: &Password,
+        new_password: &Password,
+    ) -> Result<()> {
+        log::info!("migrate_user_password(user={user_id})");
+        let after_first_unlock = legacy_importer
+            .with_try_import_super_key(user_id, old_password, || {
+                db.load_super_key(&USER_AFTER_FIRST_UNLOCK_SUPER_KEY, user_id)
+            })
+            .context(ks_err!("Failed to load AfterFirstUnlock super key."))?
+            .ok_or(Error::Rc(ResponseCode::UNINITIALIZED))
+            .context(ks_err!("User {} does not have a super key.", user_id))?;
+        let mut super_keys = vec![(&USER_AFTER_FIRST_UNLOCK_SUPER_KEY, after_first_unlock)];
+        // The UnlockedDeviceRequired super keys do not exist before the first unlock.
+        for key_type in [
+            &USER_UNLOCKED_DEVICE_REQUIRED_SYMMETRIC_SUPER_KEY,
+            &USER_UNLOCKED_DEVICE_REQUIRED_P521_SUPER_KEY,
+        ] {
+            if let Some(entry) = db
+                .load_super_key(key_type, user_id)
+                .with_context(|| ks_err!("Failed to load {}.", key_type.name))?
+            {
+                super_keys.push((key_type, entry));
+            }
+        }
+
+        let mut blobs = Vec::new();
+        for (key_type, (key_id_guard, entry)) in super_keys {
+            let super_key = Self::extract_super_key_from_key_entry(
+                key_type.algorithm,
+                entry,
+                old_password,
+                None,
+            )
+            .with_context(|| {
+                ks_err!("Failed to decrypt {} with the old password.", key_type.name)
+            })?;
+            let (blob, blob_metadata) =
+                Self::encrypt_with_password(&super_key.key, new_password)
+                    .with_context(|| ks_err!("Failed to encrypt {}.", key_type.name))?;
+            blobs.push((key_id_guard, blob, blob_metadata));
+        }
+        let blobs: Vec<_> = blobs
+            .iter()
+            .map(|(key_id_guard, blob, blob_metadata)| (key_id_guard, &blob[..], blob_metadata))
+            .collect();
+        db.replace_super_key_blobs(&blobs).context(ks_err!("Failed to store super keys."))
+    }
 }
 
 /// This enum represents different states of the user's life cycle in the device.
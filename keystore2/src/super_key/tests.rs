@@ -170,6 +170,61 @@ fn test_unlock_wrong_password() {
     );
 }
 
+#[test]
+fn test_migrate_user_password() {
+    let pw: Password = generate_password_blob();
+    let new_pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_test(&pw);
+    let super_key =
+        skm.read().unwrap().get_after_first_unlock_key_by_user_id_internal(USER_ID).unwrap();
+
+    // The migration fails without a change if the old password is wrong.
+    assert!(skm
+        .write()
+        .unwrap()
+        .migrate_user_password(&mut keystore_db, &legacy_importer, USER_ID, &new_pw, &new_pw)
+        .is_err());
+    assert!(skm
+        .write()
+        .unwrap()
+        .migrate_user_password(&mut keystore_db, &legacy_importer, USER_ID, &pw, &new_pw)
+        .is_ok());
+
+    skm.write().unwrap().data.user_keys.clear();
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, USER_ID, &pw)
+        .is_err());
+    assert_locked(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        USER_ID,
+        "The user was unlocked with the old password!",
+    );
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, USER_ID, &new_pw)
+        .is_ok());
+    // The super key itself did not change, so keys that it encrypted remain usable.
+    let migrated_key =
+        skm.read().unwrap().get_after_first_unlock_key_by_user_id_internal(USER_ID).unwrap();
+    assert_eq!(*migrated_key.key, *super_key.key);
+    assert!(matches!(
+        (migrated_key.id, super_key.id),
+        (SuperKeyIdentifier::DatabaseId(a), SuperKeyIdentifier::DatabaseId(b)) if a == b
+    ));
+
+    // An uninitialized user cannot be migrated.
+    assert!(skm
+        .write()
+        .unwrap()
+        .migrate_user_password(&mut keystore_db, &legacy_importer, USER_ID + 1, &pw, &new_pw)
+        .is_err());
+}
+
 #[test]
 fn test_unlock_user_idempotent() {
     let pw: Password = generate_password_blob();
@@ -285,3 +340,161 @@ fn test_remove_unlocked_user() {
 fn test_remove_locked_user() {
     test_user_removal(true);
 }
+
+#[test]
+fn test_reencrypt_superseded_blobs() {
+    const KEY_BLOB: &[u8] = b"ECDH encrypted key blob";
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, _legacy_importer) = setup_test(&pw);
+
+    // Encrypt a key blob as if it had been created while the device was locked.
+    let key_id = {
+        let key_id_guard = make_test_key_entry(
+            &mut keystore_db,
+            Domain::APP,
+            USER_ID.into(),
+            TEST_KEY_ALIAS,
+            None,
+        )
+        .unwrap();
+        let (blob, blob_metadata) = SuperKeyManager::encrypt_with_hybrid_super_key(
+            KEY_BLOB,
+            None,
+            &USER_UNLOCKED_DEVICE_REQUIRED_P521_SUPER_KEY,
+            &mut keystore_db,
+            USER_ID,
+        )
+        .unwrap();
+        assert!(blob_metadata.public_key().is_some());
+        keystore_db
+            .set_blob(&key_id_guard, SubComponentType::KEY_BLOB, Some(&blob), Some(&blob_metadata))
+            .unwrap();
+        key_id_guard.id()
+    };
+
+    let result = skm.read().unwrap().reencrypt_superseded_blobs(&mut keystore_db, USER_ID, 10);
+    assert_eq!(
+        result.unwrap(),
+        ReencryptionPassResult { blobs_reencrypted: 1, blobs_failed: 0, blobs_remaining: 0 }
+    );
+
+    let key = KeyDescriptor { domain: Domain::KEY_ID, nspace: key_id, alias: None, blob: None };
+    let (_, mut key_entry) = keystore_db
+        .load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::KM, AID_KEYSTORE, |_, _| Ok(()))
+        .unwrap();
+    let (blob, blob_metadata) = key_entry.take_key_blob_info().unwrap();
+    assert!(blob_metadata.public_key().is_none());
+    assert!(blob_metadata.km_uuid().is_some());
+    let key_blob = skm.read().unwrap().unwrap_key_if_required(&blob_metadata, &blob).unwrap();
+    assert_eq!(KEY_BLOB, &key_blob[..]);
+
+    // Nothing is left to do for a second pass.
+    let result = skm.read().unwrap().reencrypt_superseded_blobs(&mut keystore_db, USER_ID, 10);
+    assert_eq!(result.unwrap(), ReencryptionPassResult::default());
+
+    // The pass requires the UnlockedDeviceRequired super keys.
+    skm.write().unwrap().data.user_keys.clear();
+    let result = skm.read().unwrap().reencrypt_superseded_blobs(&mut keystore_db, USER_ID, 10);
+    assert_eq!(
+        Some(&Error::Rc(ResponseCode::LOCKED)),
+        result.unwrap_err().root_cause().downcast_ref::<Error>()
+    );
+}
+
+const SECONDARY_USER_ID: u32 = 10;
+
+fn setup_headless_test(
+    pw: &Password,
+) -> (Arc<RwLock<SuperKeyManager>>, KeystoreDB, LegacyImporter) {
+    let mut keystore_db = new_test_db().unwrap();
+    let mut legacy_importer = LegacyImporter::new(Arc::new(Default::default()));
+    legacy_importer.set_empty();
+    let skm = Arc::new(RwLock::new(SuperKeyManager::new(true)));
+    assert!(skm
+        .write()
+        .unwrap()
+        .initialize_user(&mut keystore_db, &legacy_importer, USER_SYSTEM, pw, false)
+        .is_ok());
+    (skm, keystore_db, legacy_importer)
+}
+
+#[test]
+fn test_headless_unlock_initializes_secondary_user() {
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_headless_test(&pw);
+    assert_uninitialized(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        SECONDARY_USER_ID,
+        "The secondary user was initialized with the system user!",
+    );
+
+    let secondary_pw: Password = generate_password_blob();
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, SECONDARY_USER_ID, &secondary_pw)
+        .is_ok());
+    assert_unlocked(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        SECONDARY_USER_ID,
+        "The secondary user was not initialized at first unlock!",
+    );
+
+    // The super keys were created with the password of the first unlock.
+    skm.write().unwrap().data.user_keys.remove(&SECONDARY_USER_ID);
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, SECONDARY_USER_ID, &pw)
+        .is_err());
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, SECONDARY_USER_ID, &secondary_pw)
+        .is_ok());
+}
+
+#[test]
+fn test_unlock_uninitialized_user_without_headless_system_user() {
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_test(&pw);
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, SECONDARY_USER_ID, &pw)
+        .is_err());
+    assert_uninitialized(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        SECONDARY_USER_ID,
+        "An uninitialized user was initialized at unlock!",
+    );
+}
+
+#[test]
+fn test_headless_system_user_keeps_unlocked_device_required_keys() {
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_headless_test(&pw);
+    assert!(skm
+        .write()
+        .unwrap()
+        .unlock_user(&mut keystore_db, &legacy_importer, SECONDARY_USER_ID, &pw)
+        .is_ok());
+
+    let mut skm = skm.write().unwrap();
+    for user_id in [USER_SYSTEM, SECONDARY_USER_ID] {
+        skm.lock_unlocked_device_required_keys(&mut keystore_db, user_id, &[], false);
+    }
+    let has_plaintext_keys = |skm: &SuperKeyManager, user_id: u32| {
+        let entry = &skm.data.user_keys[&user_id];
+        entry.unlocked_device_required_symmetric.is_some()
+            && entry.unlocked_device_required_private.is_some()
+    };
+    assert!(has_plaintext_keys(&skm, USER_SYSTEM));
+    assert!(!has_plaintext_keys(&skm, SECONDARY_USER_ID));
+}
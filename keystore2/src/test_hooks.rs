@@ -0,0 +1,398 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module holds all hooks that let tests change the behavior of keystore2, and implements
+//! the IKeystoreTestHooks AIDL interface that configures them. Keeping them in one place keeps
+//! the test backdoors of keystore2 auditable.
+//!
+//! The hooks are only compiled in with the `test_hooks` cfg, which Android.bp sets on
+//! debuggable builds only. The interface additionally requires the keystore2 permission
+//! `test_hooks`. Without the cfg, the functions of this module do nothing, and the service is
+//! not registered.
+//!
+//! The hooks are:
+//!  * HAL latency: keystore2 sleeps when it sets a watch point whose id mentions a HAL method.
+//!    By convention, the watch points guarding HAL calls are named `...: calling <method>`, so
+//!    the latency counts against the watch point of the HAL call.
+//!  * HAL errors: the HAL calls wrapped in `hal_call` fail with an injected KeyMint error.
+//!  * Clock: the boot time clock, which determines the age of auth tokens, can be advanced.
+//!  * RKP-only simulation: see `RemProvState::is_rkp_only_simulated`.
+//!  * Forced key upgrades: the next use of a key behaves as if KeyMint required an upgrade of
+//!    its blob, see `take_forced_upgrade`.
+//!  * Keymaster emulation: see `is_keymaster_forced`. It must be in effect before keystore2
+//!    connects to its devices, so it is configured with a system property instead of the
+//!    interface.
+
+#[cfg(test_hooks)]
+use crate::error::{into_logged_binder, Error, ErrorCode, ResponseCode};
+#[cfg(test_hooks)]
+use crate::ks_err;
+#[cfg(test_hooks)]
+use crate::permission::KeystorePerm;
+#[cfg(test_hooks)]
+use crate::utils::{check_keystore_permission, watchdog as wd};
+#[cfg(test_hooks)]
+use android_security_testhooks::aidl::android::security::testhooks::IKeystoreTestHooks::{
+    BnKeystoreTestHooks, IKeystoreTestHooks,
+};
+#[cfg(test_hooks)]
+use anyhow::{Context, Result};
+use binder::Result as BinderResult;
+#[cfg(test_hooks)]
+use binder::{BinderFeatures, Interface, Status, Strong};
+#[cfg(test_hooks)]
+use std::collections::{HashMap, HashSet};
+#[cfg(test_hooks)]
+use std::sync::{LazyLock, RwLock};
+#[cfg(test_hooks)]
+use std::time::Duration;
+
+/// The name under which the IKeystoreTestHooks service is registered.
+#[cfg(test_hooks)]
+const TEST_HOOKS_SERVICE_NAME: &str = "android.security.testhooks";
+
+#[cfg(test_hooks)]
+#[derive(Debug, Default)]
+struct Hooks {
+    hal_latency: HashMap<String, Duration>,
+    /// The injected error and the number of calls it is injected into, by HAL method.
+    hal_errors: HashMap<String, (ErrorCode, u32)>,
+    clock_offset_ms: i64,
+    rkp_only_simulated: bool,
+    /// The ids of the keys whose blobs are upgraded the next time they are used.
+    forced_upgrades: HashSet<i64>,
+}
+
+#[cfg(test_hooks)]
+static HOOKS: LazyLock<RwLock<Hooks>> = LazyLock::new(Default::default);
+
+#[cfg(test_hooks)]
+impl Hooks {
+    /// Returns the latency injected into the watch point `id`. If several methods match, the
+    /// longest one wins.
+    fn latency_for(&self, id: &str) -> Option<Duration> {
+        self.hal_latency
+            .iter()
+            .filter(|(method, _)| id.contains(method.as_str()))
+            .max_by_key(|(method, _)| method.len())
+            .map(|(_, latency)| *latency)
+    }
+
+    /// Consumes one injected error of the HAL method `method`, if any.
+    fn take_error(&mut self, method: &str) -> Option<ErrorCode> {
+        let (error, count) = self.hal_errors.get_mut(method)?;
+        let error = *error;
+        *count -= 1;
+        if *count == 0 {
+            self.hal_errors.remove(method);
+        }
+        Some(error)
+    }
+}
+
+/// Sleeps for the latency injected into the watch point `id`, if any.
+#[cfg(test_hooks)]
+pub fn inject_hal_latency(id: &str) {
+    let latency = HOOKS.read().unwrap().latency_for(id);
+    if let Some(latency) = latency {
+        log::warn!("Injecting {latency:?} of latency into \"{id}\".");
+        std::thread::sleep(latency);
+    }
+}
+
+/// Sleeps for the latency injected into the watch point `id`, if any.
+#[cfg(not(test_hooks))]
+pub fn inject_hal_latency(_id: &str) {}
+
+/// Makes the call of the HAL method `method` with `call`, unless an error was injected into
+/// the method, in which case the call fails with that error without reaching the HAL.
+#[cfg(test_hooks)]
+pub fn hal_call<T>(method: &str, call: impl FnOnce() -> BinderResult<T>) -> BinderResult<T> {
+    let error = HOOKS.write().unwrap().take_error(method);
+    match error {
+        Some(error) => {
+            log::warn!("Injecting {error:?} into a call of {method}.");
+            Err(Status::new_service_specific_error(error.0, None))
+        }
+        None => call(),
+    }
+}
+
+/// Makes the call of the HAL method `method` with `call`, unless an error was injected into
+/// the method, in which case the call fails with that error without reaching the HAL.
+#[cfg(not(test_hooks))]
+pub fn hal_call<T>(_method: &str, call: impl FnOnce() -> BinderResult<T>) -> BinderResult<T> {
+    call()
+}
+
+/// Returns the number of milliseconds by which the boot time clock was advanced.
+#[cfg(test_hooks)]
+pub fn clock_offset_ms() -> i64 {
+    HOOKS.read().unwrap().clock_offset_ms
+}
+
+/// Returns the number of milliseconds by which the boot time clock was advanced.
+#[cfg(not(test_hooks))]
+pub fn clock_offset_ms() -> i64 {
+    0
+}
+
+/// Returns true if keystore2 was asked to behave as if the TEE and StrongBox were RKP-only.
+#[cfg(test_hooks)]
+pub fn is_rkp_only_simulated() -> bool {
+    HOOKS.read().unwrap().rkp_only_simulated
+}
+
+/// Returns true if keystore2 was asked to behave as if the TEE and StrongBox were RKP-only.
+#[cfg(not(test_hooks))]
+pub fn is_rkp_only_simulated() -> bool {
+    false
+}
+
+/// Returns true if an upgrade of the blob of the key `key_id` was forced, and consumes the
+/// forced upgrade.
+#[cfg(test_hooks)]
+pub fn take_forced_upgrade(key_id: i64) -> bool {
+    let forced = HOOKS.write().unwrap().forced_upgrades.remove(&key_id);
+    if forced {
+        log::warn!("Forcing an upgrade of the blob of key {key_id}.");
+    }
+    forced
+}
+
+/// Returns true if an upgrade of the blob of the key `key_id` was forced, and consumes the
+/// forced upgrade.
+#[cfg(not(test_hooks))]
+pub fn take_forced_upgrade(_key_id: i64) -> bool {
+    false
+}
+
+/// Setting this system property to true makes keystore2 treat the device as Keymaster-only: all
+/// security levels are connected through km_compat, even if KeyMint devices are declared. This
+/// keeps the compatibility layer under test on devices without Keymaster hardware, where the
+/// software KeyMint device of km_compat stands in for the TEE. The property is read once when
+/// keystore2 connects to its devices, so keystore2 must be restarted for a change to take effect.
+#[cfg(test_hooks)]
+const FORCE_KEYMASTER_PROPERTY: &str = "keystore.test.force_keymaster";
+
+#[cfg(test_hooks)]
+static FORCE_KEYMASTER: LazyLock<bool> = LazyLock::new(|| {
+    let forced =
+        rustutils::system_properties::read_bool(FORCE_KEYMASTER_PROPERTY, false).unwrap_or(false);
+    if forced {
+        log::warn!("Emulating a Keymaster-only device as requested by {FORCE_KEYMASTER_PROPERTY}.");
+    }
+    forced
+});
+
+/// Returns true if keystore2 was asked to treat the device as Keymaster-only, see
+/// `FORCE_KEYMASTER_PROPERTY`.
+#[cfg(test_hooks)]
+pub fn is_keymaster_forced() -> bool {
+    *FORCE_KEYMASTER
+}
+
+/// Returns true if keystore2 was asked to treat the device as Keymaster-only.
+#[cfg(not(test_hooks))]
+pub fn is_keymaster_forced() -> bool {
+    false
+}
+
+/// Registers the IKeystoreTestHooks service. Does nothing on builds without test hooks.
+pub fn register_service() {
+    #[cfg(test_hooks)]
+    {
+        log::warn!("Registering {TEST_HOOKS_SERVICE_NAME}. This must not happen on user builds.");
+        let service = TestHooksService::new_native_binder().unwrap_or_else(|e| {
+            panic!("Failed to create service {} because of {:?}.", TEST_HOOKS_SERVICE_NAME, e);
+        });
+        binder::add_service(TEST_HOOKS_SERVICE_NAME, service.as_binder()).unwrap_or_else(|e| {
+            panic!("Failed to register service {} because of {:?}.", TEST_HOOKS_SERVICE_NAME, e);
+        });
+    }
+}
+
+/// Implementation of the IKeystoreTestHooks service.
+#[cfg(test_hooks)]
+struct TestHooksService;
+
+#[cfg(test_hooks)]
+impl TestHooksService {
+    fn new_native_binder() -> Result<Strong<dyn IKeystoreTestHooks>> {
+        Ok(BnKeystoreTestHooks::new_binder(
+            Self,
+            BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
+        ))
+    }
+
+    fn set_hal_latency(method: &str, millis: i32) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        if method.is_empty() || millis < 0 {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Malformed HAL latency."));
+        }
+        let mut hooks = HOOKS.write().unwrap();
+        if millis == 0 {
+            hooks.hal_latency.remove(method);
+        } else {
+            hooks.hal_latency.insert(method.to_string(), Duration::from_millis(millis as u64));
+        }
+        Ok(())
+    }
+
+    fn inject_hal_error(method: &str, error_code: i32, count: i32) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        if method.is_empty() || error_code >= 0 || count < 0 {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("Malformed HAL error."));
+        }
+        let mut hooks = HOOKS.write().unwrap();
+        if count == 0 {
+            hooks.hal_errors.remove(method);
+        } else {
+            hooks.hal_errors.insert(method.to_string(), (ErrorCode(error_code), count as u32));
+        }
+        Ok(())
+    }
+
+    fn advance_clock(millis: i64) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        if millis < 0 {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+                .context(ks_err!("The clock cannot be turned back."));
+        }
+        let mut hooks = HOOKS.write().unwrap();
+        hooks.clock_offset_ms = hooks
+            .clock_offset_ms
+            .checked_add(millis)
+            .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!("Clock offset overflow."))?;
+        log::warn!("Boot time clock advanced by {} ms in total.", hooks.clock_offset_ms);
+        Ok(())
+    }
+
+    fn set_rkp_only_simulated(simulated: bool) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        HOOKS.write().unwrap().rkp_only_simulated = simulated;
+        log::warn!("RKP-only simulation {}.", if simulated { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    fn reset() -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        *HOOKS.write().unwrap() = Default::default();
+        Ok(())
+    }
+
+    fn force_key_upgrade(key_id: i64) -> Result<()> {
+        // Security critical permission check. This statement must return on fail.
+        check_keystore_permission(KeystorePerm::TestHooks).context(ks_err!())?;
+        HOOKS.write().unwrap().forced_upgrades.insert(key_id);
+        Ok(())
+    }
+}
+
+#[cfg(test_hooks)]
+impl Interface for TestHooksService {}
+
+#[cfg(test_hooks)]
+impl IKeystoreTestHooks for TestHooksService {
+    fn setHalLatency(&self, method: &str, millis: i32) -> BinderResult<()> {
+        log::info!("setHalLatency({method:?}, {millis})");
+        let _wp = wd::watch("IKeystoreTestHooks::setHalLatency");
+        Self::set_hal_latency(method, millis).map_err(into_logged_binder)
+    }
+
+    fn injectHalError(&self, method: &str, error_code: i32, count: i32) -> BinderResult<()> {
+        log::info!("injectHalError({method:?}, {error_code}, {count})");
+        let _wp = wd::watch("IKeystoreTestHooks::injectHalError");
+        Self::inject_hal_error(method, error_code, count).map_err(into_logged_binder)
+    }
+
+    fn advanceClock(&self, millis: i64) -> BinderResult<()> {
+        log::info!("advanceClock({millis})");
+        let _wp = wd::watch("IKeystoreTestHooks::advanceClock");
+        Self::advance_clock(millis).map_err(into_logged_binder)
+    }
+
+    fn setRkpOnlySimulated(&self, simulated: bool) -> BinderResult<()> {
+        log::info!("setRkpOnlySimulated({simulated})");
+        let _wp = wd::watch("IKeystoreTestHooks::setRkpOnlySimulated");
+        Self::set_rkp_only_simulated(simulated).map_err(into_logged_binder)
+    }
+
+    fn reset(&self) -> BinderResult<()> {
+        log::info!("reset()");
+        let _wp = wd::watch("IKeystoreTestHooks::reset");
+        Self::reset().map_err(into_logged_binder)
+    }
+
+    fn forceKeyUpgrade(&self, key_id: i64) -> BinderResult<()> {
+        log::info!("forceKeyUpgrade({key_id})");
+        let _wp = wd::watch("IKeystoreTestHooks::forceKeyUpgrade");
+        Self::force_key_upgrade(key_id).map_err(into_logged_binder)
+    }
+}
+
+#[cfg(all(test, test_hooks))]
+mod tests {
+    use super::*;
+
+    const BEGIN: &str = "KeyMintDevice::use_key_in_one_step: calling IKeyMintDevice::begin";
+    const FINISH: &str = "Operation::finish: calling IKeyMintOperation::finish";
+
+    fn hooks_with_latency(latencies: &[(&str, u64)]) -> Hooks {
+        Hooks {
+            hal_latency: latencies
+                .iter()
+                .map(|(method, millis)| (method.to_string(), Duration::from_millis(*millis)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_latency_for() {
+        let hooks = hooks_with_latency(&[
+            ("IKeyMintDevice::begin", 600),
+            ("IKeyMintOperation::finish", 200),
+        ]);
+        assert_eq!(hooks.latency_for(BEGIN), Some(Duration::from_millis(600)));
+        assert_eq!(hooks.latency_for(FINISH), Some(Duration::from_millis(200)));
+        assert_eq!(hooks.latency_for("IKeystoreService::getKeyEntry"), None);
+        assert_eq!(Hooks::default().latency_for(BEGIN), None);
+    }
+
+    #[test]
+    fn test_latency_for_longest_match() {
+        let hooks = hooks_with_latency(&[("begin", 5), ("IKeyMintDevice::begin", 600)]);
+        assert_eq!(hooks.latency_for(BEGIN), Some(Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn test_take_error() {
+        let mut hooks = Hooks::default();
+        hooks.hal_errors.insert("IKeyMintDevice::begin".to_string(), (ErrorCode::UNKNOWN_ERROR, 2));
+        assert_eq!(hooks.take_error("IKeyMintOperation::finish"), None);
+        assert_eq!(hooks.take_error("IKeyMintDevice::begin"), Some(ErrorCode::UNKNOWN_ERROR));
+        assert_eq!(hooks.take_error("IKeyMintDevice::begin"), Some(ErrorCode::UNKNOWN_ERROR));
+        assert_eq!(hooks.take_error("IKeyMintDevice::begin"), None);
+        assert!(hooks.hal_errors.is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module tracks the thermal throttling state of the device, so that keystore2 can defer
+//! low priority background work, i.e., the key garbage collector, while the device is severely
+//! throttled. Deleting superseded keys involves the KeyMint backend, and keeping that load off
+//! the thermal budget leaves it to the work that a user is waiting for.
+//!
+//! Deferral is opt-in with the read-only property `ro.keystore.thermal_deferral`. When it is
+//! set, keystore2 registers a callback with the thermal HAL and defers background work while
+//! any temperature sensor reports a throttling severity of `SEVERE` or worse. Deferred work
+//! resumes as soon as all sensors are below that severity again. Work that a client asked for
+//! explicitly, e.g., a synchronous garbage collection pass, is never deferred.
+
+use crate::ks_err;
+use android_hardware_thermal::aidl::android::hardware::thermal::{
+    IThermal::IThermal, IThermalChangedCallback::BnThermalChangedCallback,
+    IThermalChangedCallback::IThermalChangedCallback, Temperature::Temperature,
+    ThrottlingSeverity::ThrottlingSeverity,
+};
+use anyhow::{Context, Result};
+use binder::{BinderFeatures, Interface, Strong};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+const THERMAL_DEFERRAL_PROPERTY: &str = "ro.keystore.thermal_deferral";
+
+const THERMAL_SERVICE_NAME: &str = "android.hardware.thermal.IThermal/default";
+
+/// The lowest severity at which background work is deferred.
+const DEFERRAL_SEVERITY: ThrottlingSeverity = ThrottlingSeverity::SEVERE;
+
+/// The thermal throttling state of this keystore2 process.
+pub static THERMAL_THROTTLING: LazyLock<ThermalThrottling> = LazyLock::new(|| {
+    ThermalThrottling::new(
+        rustutils::system_properties::read_bool(THERMAL_DEFERRAL_PROPERTY, false).unwrap_or_else(
+            |e| {
+                log::error!("Failed to read {THERMAL_DEFERRAL_PROPERTY}: {e:?}");
+                false
+            },
+        ),
+    )
+});
+
+/// A snapshot of the thermal throttling state for dumpsys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThermalStatus {
+    /// Whether deferral is enabled.
+    pub enabled: bool,
+    /// The worst severity reported by any sensor.
+    pub severity: ThrottlingSeverity,
+    /// Whether background work is currently deferred.
+    pub deferred: bool,
+    /// The number of background tasks that were deferred since keystore2 started.
+    pub deferred_tasks: u64,
+}
+
+/// Holds the latest throttling severity of every temperature sensor.
+#[derive(Debug)]
+pub struct ThermalThrottling {
+    enabled: bool,
+    sensors: Mutex<HashMap<String, ThrottlingSeverity>>,
+    deferred: AtomicBool,
+    deferred_tasks: AtomicU64,
+}
+
+impl ThermalThrottling {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            sensors: Default::default(),
+            deferred: AtomicBool::new(false),
+            deferred_tasks: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if background work should be deferred, and counts the deferred task.
+    /// Callers must call this right before doing a unit of background work and skip it if the
+    /// result is true.
+    pub fn defer_background_work(&self) -> bool {
+        let deferred = self.enabled && self.deferred.load(Ordering::Relaxed);
+        if deferred {
+            self.deferred_tasks.fetch_add(1, Ordering::Relaxed);
+        }
+        deferred
+    }
+
+    /// Returns the current state.
+    pub fn status(&self) -> ThermalStatus {
+        ThermalStatus {
+            enabled: self.enabled,
+            severity: self.worst_severity(),
+            deferred: self.enabled && self.deferred.load(Ordering::Relaxed),
+            deferred_tasks: self.deferred_tasks.load(Ordering::Relaxed),
+        }
+    }
+
+    fn worst_severity(&self) -> ThrottlingSeverity {
+        self.sensors.lock().unwrap().values().copied().max().unwrap_or(ThrottlingSeverity::NONE)
+    }
+
+    /// Records the severity reported by the sensor `name`. Returns true if this ended a period
+    /// of deferral, in which case the caller must resume the deferred work.
+    fn update(&self, name: &str, severity: ThrottlingSeverity) -> bool {
+        let mut sensors = self.sensors.lock().unwrap();
+        sensors.insert(name.to_string(), severity);
+        let deferred = sensors.values().any(|s| *s >= DEFERRAL_SEVERITY);
+        let was_deferred = self.deferred.swap(deferred, Ordering::Relaxed);
+        if was_deferred != deferred {
+            if deferred {
+                log::info!("Deferring background work, sensor {name} is at {severity:?}.");
+            } else {
+                log::info!("Resuming background work.");
+            }
+        }
+        was_deferred && !deferred
+    }
+
+    fn report(&self, temperature: &Temperature) {
+        if self.update(&temperature.name, temperature.throttlingStatus) {
+            crate::globals::notify_gc();
+        }
+    }
+
+    /// Starts a thread that registers with the thermal HAL, if deferral is enabled.
+    pub fn watch(&'static self) {
+        if !self.enabled {
+            return;
+        }
+        std::thread::spawn(move || {
+            if let Err(e) = self.register() {
+                log::error!("Failed to register for thermal throttling events: {e:?}");
+            }
+        });
+    }
+
+    fn register(&'static self) -> Result<()> {
+        if !binder::is_declared(THERMAL_SERVICE_NAME).context(ks_err!("is_declared failed"))? {
+            log::info!("No thermal HAL, background work is never deferred.");
+            return Ok(());
+        }
+        let thermal: Strong<dyn IThermal> = binder::wait_for_interface(THERMAL_SERVICE_NAME)
+            .context(ks_err!("Failed to connect to thermal HAL."))?;
+        let callback = BnThermalChangedCallback::new_binder(
+            ThermalCallback { throttling: self },
+            BinderFeatures::default(),
+        );
+        thermal
+            .registerThermalChangedCallback(&callback)
+            .context(ks_err!("registerThermalChangedCallback failed"))?;
+        // Callbacks only report changes, so seed the state with the current temperatures.
+        for temperature in
+            thermal.getTemperatures().context(ks_err!("getTemperatures failed"))?.iter()
+        {
+            self.report(temperature);
+        }
+        Ok(())
+    }
+}
+
+struct ThermalCallback {
+    throttling: &'static ThermalThrottling,
+}
+
+impl Interface for ThermalCallback {}
+
+impl IThermalChangedCallback for ThermalCallback {
+    fn notifyThrottling(&self, temperature: &Temperature) -> binder::Result<()> {
+        self.throttling.report(temperature);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deferral() {
+        let throttling = ThermalThrottling::new(true);
+        assert!(!throttling.defer_background_work());
+
+        assert!(!throttling.update("cpu", ThrottlingSeverity::MODERATE));
+        assert!(!throttling.defer_background_work());
+        assert!(!throttling.update("skin", ThrottlingSeverity::SEVERE));
+        assert!(throttling.defer_background_work());
+        // The skin sensor still requires deferral.
+        assert!(!throttling.update("cpu", ThrottlingSeverity::NONE));
+        assert!(throttling.defer_background_work());
+        assert_eq!(
+            throttling.status(),
+            ThermalStatus {
+                enabled: true,
+                severity: ThrottlingSeverity::SEVERE,
+                deferred: true,
+                deferred_tasks: 2
+            }
+        );
+
+        assert!(throttling.update("skin", ThrottlingSeverity::LIGHT));
+        assert!(!throttling.defer_background_work());
+        assert_eq!(throttling.status().severity, ThrottlingSeverity::LIGHT);
+    }
+
+    #[test]
+    fn test_deferral_disabled() {
+        let throttling = ThermalThrottling::new(false);
+        throttling.update("skin", ThrottlingSeverity::EMERGENCY);
+        assert!(!throttling.defer_background_work());
+        assert!(!throttling.status().deferred);
+    }
+}
@@ -15,6 +15,7 @@
 //! This module implements utility functions used by the Keystore 2.0 service
 //! implementation.
 
+use crate::calling_identity;
 use crate::error::{map_binder_status, map_km_error, Error, ErrorCode};
 use crate::key_parameter::KeyParameter;
 use crate::ks_err;
@@ -22,7 +23,7 @@ use crate::permission;
 use crate::permission::{KeyPerm, KeyPermSet, KeystorePerm};
 pub use crate::watchdog_helper::watchdog;
 use crate::{
-    database::{KeyType, KeystoreDB},
+    database::{KeyEntryFilter, KeyType, KeystoreDB},
     globals::LEGACY_IMPORTER,
     km_compat,
     raw_device::KeyMintDevice,
@@ -30,6 +31,7 @@ use crate::{
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, IKeyMintDevice::IKeyMintDevice, KeyCharacteristics::KeyCharacteristics,
     KeyParameter::KeyParameter as KmKeyParameter, KeyParameterValue::KeyParameterValue, Tag::Tag,
+    TagType::TagType,
 };
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
 use android_security_apc::aidl::android::security::apc::{
@@ -41,7 +43,7 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     ResponseCode::ResponseCode,
 };
 use anyhow::{Context, Result};
-use binder::{FromIBinder, StatusCode, Strong, ThreadState};
+use binder::{FromIBinder, StatusCode, Strong};
 use keystore2_apc_compat::{
     ApcCompatUiOptions, APC_COMPAT_ERROR_ABORTED, APC_COMPAT_ERROR_CANCELLED,
     APC_COMPAT_ERROR_IGNORED, APC_COMPAT_ERROR_OK, APC_COMPAT_ERROR_OPERATION_PENDING,
@@ -64,7 +66,7 @@ pub const UNDEFINED_NOT_AFTER: i64 = 253402300799000i64;
 /// combination with with_calling_sid from the binder crate to check
 /// if the caller has the given keystore permission.
 pub fn check_keystore_permission(perm: KeystorePerm) -> anyhow::Result<()> {
-    ThreadState::with_calling_sid(|calling_sid| {
+    calling_identity::with_calling_sid(|calling_sid| {
         permission::check_keystore_permission(
             calling_sid
                 .ok_or_else(Error::sys)
@@ -78,7 +80,7 @@ pub fn check_keystore_permission(perm: KeystorePerm) -> anyhow::Result<()> {
 /// combination with with_calling_sid from the binder crate to check
 /// if the caller has the given grant permission.
 pub fn check_grant_permission(access_vec: KeyPermSet, key: &KeyDescriptor) -> anyhow::Result<()> {
-    ThreadState::with_calling_sid(|calling_sid| {
+    calling_identity::with_calling_sid(|calling_sid| {
         permission::check_grant_permission(
             calling_sid
                 .ok_or_else(Error::sys)
@@ -97,9 +99,9 @@ pub fn check_key_permission(
     key: &KeyDescriptor,
     access_vector: &Option<KeyPermSet>,
 ) -> anyhow::Result<()> {
-    ThreadState::with_calling_sid(|calling_sid| {
+    calling_identity::with_calling_sid(|calling_sid| {
         permission::check_key_permission(
-            ThreadState::get_calling_uid(),
+            calling_identity::get_calling_uid(),
             calling_sid
                 .ok_or_else(Error::sys)
                 .context(ks_err!("Cannot check permission without calling_sid."))?,
@@ -122,6 +124,26 @@ pub fn is_device_id_attestation_tag(tag: Tag) -> bool {
     )
 }
 
+/// This function checks whether keys of the given algorithm are asymmetric, i.e., whether they
+/// have a public key and get a certificate. Every algorithm that is not known to be symmetric is
+/// considered asymmetric, so that public key algorithms added to KeyMint after the ones known to
+/// Keystore (e.g., ML-DSA and ML-KEM) get certificate parameters and attestation keys without
+/// further changes to Keystore. Unsupported algorithms are diagnosed by the KeyMint backend.
+pub fn is_asymmetric_algorithm(algorithm: Algorithm) -> bool {
+    !matches!(algorithm, Algorithm::AES | Algorithm::TRIPLE_DES | Algorithm::HMAC)
+}
+
+/// This function checks whether the first ALGORITHM tag in the given key parameters denotes an
+/// asymmetric algorithm. See `is_asymmetric_algorithm`.
+pub fn is_asymmetric_key(params: &[KmKeyParameter]) -> bool {
+    match params.iter().find(|kp| kp.tag == Tag::ALGORITHM) {
+        Some(KmKeyParameter { tag: _, value: KeyParameterValue::Algorithm(algorithm) }) => {
+            is_asymmetric_algorithm(*algorithm)
+        }
+        _ => false,
+    }
+}
+
 /// This function checks whether the calling app has the Android permissions needed to attest device
 /// identifiers. It throws an error if the permissions cannot be verified or if the caller doesn't
 /// have the right permissions. Otherwise it returns silently.
@@ -168,8 +190,8 @@ fn check_android_permission(permission: &str, err: Error) -> anyhow::Result<()>
         let _wp = watchdog::watch("check_android_permission: calling checkPermission");
         permission_controller.checkPermission(
             permission,
-            ThreadState::get_calling_pid(),
-            ThreadState::get_calling_uid() as i32,
+            calling_identity::get_calling_pid(),
+            calling_identity::get_calling_uid() as i32,
         )
     };
     let has_permissions =
@@ -329,6 +351,9 @@ where
         map_km_error(km_dev.upgradeKey(key_blob, upgrade_params))
     }
     .context(ks_err!("Upgrade failed."))?;
+    // KeyMint may return an empty blob if the blob does not need an upgrade, which can only
+    // happen if the upgrade was forced by a test hook. The current blob is stored again then.
+    let upgraded_blob = if upgraded_blob.is_empty() { key_blob.to_vec() } else { upgraded_blob };
 
     new_blob_handler(&upgraded_blob).context(ks_err!("calling new_blob_handler."))?;
 
@@ -498,6 +523,13 @@ pub const AID_USER_OFFSET: u32 = rustutils::users::AID_USER_OFFSET;
 /// keystore generates for its own use.
 pub const AID_KEYSTORE: u32 = rustutils::users::AID_KEYSTORE;
 
+/// First app id of the application UID range, see AID_APP_START in
+/// system/core/libcutils/include/private/android_filesystem_config.h.
+pub const AID_APP_START: u32 = 10000;
+
+/// The android user id of the system user.
+pub const USER_SYSTEM: u32 = 0;
+
 /// Extracts the android user from the given uid.
 pub fn uid_to_android_user(uid: u32) -> u32 {
     rustutils::users::multiuser_get_user_id(uid)
@@ -578,6 +610,80 @@ pub(crate) fn estimate_safe_amount_to_return(
 /// Estimate for maximum size of a Binder response in bytes.
 pub(crate) const RESPONSE_SIZE_LIMIT: usize = 358400;
 
+/// The largest number of key descriptors that `estimate_safe_amount_to_return` lets into a
+/// response, i.e., key descriptors with empty aliases and no blob. Reading more aliases from the
+/// database is wasted effort.
+const MAX_KEY_DESCRIPTORS_PER_RESPONSE: usize = RESPONSE_SIZE_LIMIT / (4 + 8 + 4);
+
+/// A page of the key entries of a namespace, see `list_key_entries_page`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyEntryPage {
+    /// The key descriptors of the page, sorted by alias.
+    pub keys: Vec<KeyDescriptor>,
+    /// The token that selects the next page, or None if this is the last page.
+    pub continuation_token: Option<String>,
+}
+
+/// Returns the continuation token that continues listing `domain`/`namespace` after `alias`.
+/// The token is opaque to the client, but it is only the position in the alias ordering, so that
+/// keystore does not keep state for the cursors of its clients.
+fn encode_continuation_token(domain: Domain, namespace: i64, alias: &str) -> String {
+    let alias: String = alias.bytes().map(|b| format!("{b:02x}")).collect();
+    format!("{}:{}:{}", domain.0, namespace, alias)
+}
+
+/// Returns the alias after which the continuation token `token` continues listing
+/// `domain`/`namespace`. Fails with INVALID_ARGUMENT if the token is malformed or belongs to
+/// another namespace.
+fn decode_continuation_token(token: &str, domain: Domain, namespace: i64) -> Result<String> {
+    let parse = || -> Option<String> {
+        let mut fields = token.splitn(3, ':');
+        let token_domain: i32 = fields.next()?.parse().ok()?;
+        let token_namespace: i64 = fields.next()?.parse().ok()?;
+        if token_domain != domain.0 || token_namespace != namespace {
+            return None;
+        }
+        let alias = fields.next()?;
+        if alias.len() % 2 != 0 || !alias.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let bytes = (0..alias.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(alias.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        String::from_utf8(bytes).ok()
+    };
+    parse().ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+        "Invalid continuation token {:?} for {:?}:{}.",
+        token,
+        domain,
+        namespace
+    ))
+}
+
+/// List a page of the key aliases of a given domain + namespace. The first page is listed
+/// without a continuation token, later pages with the token of the previous page. Each page is
+/// read from the database with a bounded, indexed query, and keys that are added or removed
+/// between pages neither cause other keys to be listed twice nor to be skipped.
+pub fn list_key_entries_page(
+    db: &mut KeystoreDB,
+    domain: Domain,
+    namespace: i64,
+    continuation_token: Option<&str>,
+) -> Result<KeyEntryPage> {
+    let start_past_alias = continuation_token
+        .map(|token| decode_continuation_token(token, domain, namespace))
+        .transpose()?;
+    let (keys, more) =
+        list_key_entries_internal(db, domain, namespace, start_past_alias.as_deref())?;
+    let continuation_token = keys
+        .last()
+        .and_then(|kd| kd.alias.as_deref())
+        .filter(|_| more)
+        .map(|alias| encode_continuation_token(domain, namespace, alias));
+    Ok(KeyEntryPage { keys, continuation_token })
+}
+
 /// List all key aliases for a given domain + namespace. whose alias is greater
 /// than start_past_alias (if provided).
 pub fn list_key_entries(
@@ -586,13 +692,31 @@ pub fn list_key_entries(
     namespace: i64,
     start_past_alias: Option<&str>,
 ) -> Result<Vec<KeyDescriptor>> {
+    list_key_entries_internal(db, domain, namespace, start_past_alias).map(|(keys, _)| keys)
+}
+
+/// Implements `list_key_entries`, and also returns whether there are more key aliases past the
+/// returned ones.
+fn list_key_entries_internal(
+    db: &mut KeystoreDB,
+    domain: Domain,
+    namespace: i64,
+    start_past_alias: Option<&str>,
+) -> Result<(Vec<KeyDescriptor>, bool)> {
     let legacy_key_descriptors: Vec<KeyDescriptor> = LEGACY_IMPORTER
         .list_uid(domain, namespace)
         .context(ks_err!("Trying to list legacy keys."))?;
 
-    // The results from the database will be sorted and unique
+    // The results from the database will be sorted and unique. One more than fits into a
+    // response is read, which suffices to fill the response and to tell whether there are more.
     let db_key_descriptors: Vec<KeyDescriptor> = db
-        .list_past_alias(domain, namespace, KeyType::Client, start_past_alias)
+        .list_past_alias_limited(
+            domain,
+            namespace,
+            KeyType::Client,
+            start_past_alias,
+            Some(MAX_KEY_DESCRIPTORS_PER_RESPONSE + 1),
+        )
         .context(ks_err!("Trying to list keystore database past alias."))?;
 
     let merged_key_entries = merge_and_filter_key_entry_lists(
@@ -603,7 +727,27 @@ pub fn list_key_entries(
 
     let safe_amount_to_return =
         estimate_safe_amount_to_return(domain, namespace, &merged_key_entries, RESPONSE_SIZE_LIMIT);
-    Ok(merged_key_entries[..safe_amount_to_return].to_vec())
+    let more = safe_amount_to_return < merged_key_entries.len();
+    Ok((merged_key_entries[..safe_amount_to_return].to_vec(), more))
+}
+
+/// List the aliases of the keys for a given domain + namespace that match `filter`. The filter
+/// is evaluated against the key characteristics stored in the database, so legacy keys that have
+/// not been imported yet are not listed.
+pub fn list_key_entries_filtered(
+    db: &mut KeystoreDB,
+    domain: Domain,
+    namespace: i64,
+    start_past_alias: Option<&str>,
+    filter: &KeyEntryFilter,
+) -> Result<Vec<KeyDescriptor>> {
+    let key_entries = db
+        .list_past_alias_filtered(domain, namespace, KeyType::Client, start_past_alias, filter)
+        .context(ks_err!("Trying to list keystore database past alias."))?;
+
+    let safe_amount_to_return =
+        estimate_safe_amount_to_return(domain, namespace, &key_entries, RESPONSE_SIZE_LIMIT);
+    Ok(key_entries[..safe_amount_to_return].to_vec())
 }
 
 /// Count all key aliases for a given domain + namespace.
@@ -626,6 +770,56 @@ pub fn log_security_safe_params(params: &[KmKeyParameter]) -> Vec<KmKeyParameter
         .collect::<Vec<KmKeyParameter>>()
 }
 
+/// Pairs of tags that must not be specified together.
+const MUTUALLY_EXCLUSIVE_TAGS: &[(Tag, Tag)] = &[
+    (Tag::NO_AUTH_REQUIRED, Tag::USER_SECURE_ID),
+    (Tag::NO_AUTH_REQUIRED, Tag::USER_AUTH_TYPE),
+    (Tag::NO_AUTH_REQUIRED, Tag::AUTH_TIMEOUT),
+];
+
+/// Returns true if the tag may be specified more than once with different values.
+fn is_repeatable_tag(tag: Tag) -> bool {
+    matches!(
+        TagType((tag.0 as u32 & 0xF0000000) as i32),
+        TagType::ENUM_REP | TagType::UINT_REP | TagType::ULONG_REP
+    )
+}
+
+/// Brings key parameters into a canonical form before they are passed to KeyMint, so that the
+/// outcome does not depend on how the KeyMint implementation handles duplicates. The parameters
+/// are sorted by tag, preserving the order of the values of repeatable tags, and repeated
+/// identical parameters are removed. Fails with `ResponseCode::INVALID_ARGUMENT` naming the
+/// offending tags if a non-repeatable tag is specified with different values or if mutually
+/// exclusive tags are specified together.
+pub fn canonicalize_key_parameters(params: &[KmKeyParameter]) -> Result<Vec<KmKeyParameter>> {
+    let mut result: Vec<KmKeyParameter> = Vec::with_capacity(params.len());
+    for param in params {
+        if result.contains(param) {
+            continue;
+        }
+        if !is_repeatable_tag(param.tag) && result.iter().any(|kp| kp.tag == param.tag) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Tag {:?} is not repeatable but was specified with different values.",
+                param.tag
+            ));
+        }
+        result.push(param.clone());
+    }
+
+    for (a, b) in MUTUALLY_EXCLUSIVE_TAGS {
+        if result.iter().any(|kp| kp.tag == *a) && result.iter().any(|kp| kp.tag == *b) {
+            return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
+                "Tags {:?} and {:?} are mutually exclusive.",
+                a,
+                b
+            ));
+        }
+    }
+
+    result.sort_by_key(|kp| kp.tag.0);
+    Ok(result)
+}
+
 /// Trait implemented by objects that can be used to decrypt cipher text using AES-GCM.
 pub trait AesGcm {
     /// Deciphers `data` using the initialization vector `iv` and AEAD tag `tag`
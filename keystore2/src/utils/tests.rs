@@ -29,6 +29,20 @@ fn check_device_attestation_permissions_test() -> Result<()> {
     })
 }
 
+#[test]
+fn test_is_asymmetric_key() {
+    let algorithm =
+        |a| KmKeyParameter { tag: Tag::ALGORITHM, value: KeyParameterValue::Algorithm(a) };
+    assert!(is_asymmetric_key(&[algorithm(Algorithm::RSA)]));
+    assert!(is_asymmetric_key(&[algorithm(Algorithm::EC)]));
+    assert!(!is_asymmetric_key(&[algorithm(Algorithm::AES)]));
+    assert!(!is_asymmetric_key(&[algorithm(Algorithm::TRIPLE_DES)]));
+    assert!(!is_asymmetric_key(&[algorithm(Algorithm::HMAC)]));
+    // Algorithms that Keystore does not know yet are treated as public key algorithms.
+    assert!(is_asymmetric_key(&[algorithm(Algorithm(1000))]));
+    assert!(!is_asymmetric_key(&[]));
+}
+
 fn create_key_descriptors_from_aliases(key_aliases: &[&str]) -> Vec<KeyDescriptor> {
     key_aliases
         .iter()
@@ -101,6 +115,34 @@ fn test_merge_and_sort_lists_with_filtering_and_dups() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_continuation_token() -> Result<()> {
+    for alias in ["key_a", "", "wifi:ca/1", "\u{e9}t\u{e9}"] {
+        let token = encode_continuation_token(Domain::SELINUX, 102, alias);
+        assert_eq!(decode_continuation_token(&token, Domain::SELINUX, 102)?, alias);
+    }
+
+    let token = encode_continuation_token(Domain::SELINUX, 102, "key_a");
+    let invalid = |token: &str, domain: Domain, namespace: i64| {
+        matches!(
+            decode_continuation_token(token, domain, namespace)
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>(),
+            Some(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+        )
+    };
+    // Tokens only continue the listing of their own namespace.
+    assert!(invalid(&token, Domain::SELINUX, 103));
+    assert!(invalid(&token, Domain::APP, 102));
+    assert!(invalid("key_a", Domain::SELINUX, 102));
+    assert!(invalid("4:102:6b6", Domain::SELINUX, 102));
+    assert!(invalid("4:102:zz", Domain::SELINUX, 102));
+    assert!(invalid("4:102:ff", Domain::SELINUX, 102));
+    assert!(invalid("4:102:+1", Domain::SELINUX, 102));
+    Ok(())
+}
+
 #[test]
 fn test_list_key_parameters_with_filter_on_security_sensitive_info() -> Result<()> {
     let params = vec![
@@ -123,3 +165,58 @@ fn test_list_key_parameters_with_filter_on_security_sensitive_info() -> Result<(
     assert_eq!(log_security_safe_params(&params), wanted);
     Ok(())
 }
+
+fn kp(tag: Tag, value: KeyParameterValue) -> KmKeyParameter {
+    KmKeyParameter { tag, value }
+}
+
+fn invalid_argument(result: Result<Vec<KmKeyParameter>>) -> bool {
+    matches!(
+        result.unwrap_err().root_cause().downcast_ref::<Error>(),
+        Some(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+    )
+}
+
+#[test]
+fn test_canonicalize_key_parameters() -> Result<()> {
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+        Digest::Digest, KeyPurpose::KeyPurpose,
+    };
+    let params = [
+        kp(Tag::KEY_SIZE, KeyParameterValue::Integer(256)),
+        kp(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_512)),
+        kp(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC)),
+        kp(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_256)),
+        kp(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::SIGN)),
+        kp(Tag::KEY_SIZE, KeyParameterValue::Integer(256)),
+        kp(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_512)),
+    ];
+    let mut expected = vec![
+        kp(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::SIGN)),
+        kp(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC)),
+        kp(Tag::KEY_SIZE, KeyParameterValue::Integer(256)),
+        kp(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_512)),
+        kp(Tag::DIGEST, KeyParameterValue::Digest(Digest::SHA_2_256)),
+    ];
+    expected.sort_by_key(|kp| kp.tag.0);
+    assert_eq!(canonicalize_key_parameters(&params)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_canonicalize_key_parameters_rejects_conflicts() {
+    assert!(invalid_argument(canonicalize_key_parameters(&[
+        kp(Tag::KEY_SIZE, KeyParameterValue::Integer(256)),
+        kp(Tag::KEY_SIZE, KeyParameterValue::Integer(384)),
+    ])));
+    assert!(invalid_argument(canonicalize_key_parameters(&[
+        kp(Tag::NO_AUTH_REQUIRED, KeyParameterValue::BoolValue(true)),
+        kp(Tag::USER_SECURE_ID, KeyParameterValue::LongInteger(42)),
+    ])));
+    // Repeatable tags may have different values.
+    assert!(canonicalize_key_parameters(&[
+        kp(Tag::USER_SECURE_ID, KeyParameterValue::LongInteger(42)),
+        kp(Tag::USER_SECURE_ID, KeyParameterValue::LongInteger(43)),
+    ])
+    .is_ok());
+}
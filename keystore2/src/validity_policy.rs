@@ -0,0 +1,177 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the maximum key validity policy. Device policy may impose a maximum
+//! validity on new keys of selected Android users or SELinux namespaces, e.g., to implement
+//! enterprise key rotation mandates. Keys that are generated or imported into such a namespace
+//! get their expiry tags clamped to the end of the maximum validity, or added if the caller did
+//! not specify them. The resulting expiration date is recorded in the key metadata. Securely
+//! imported keys are not covered, because their authorizations are part of the wrapped key.
+//!
+//! The policy is configured through IKeystoreMaintenance and does not persist across restarts
+//! of keystore, so it must be re-applied by the policy owner.
+
+use crate::database::DateTime;
+use crate::utils::uid_to_android_user;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, Tag::Tag,
+};
+use android_system_keystore2::aidl::android::system::keystore2::Domain::Domain;
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+/// The maximum key validity policy of this keystore instance.
+pub static KEY_VALIDITY_POLICY: LazyLock<KeyValidityPolicy> = LazyLock::new(Default::default);
+
+/// The tags that limit the validity of a key.
+const KEY_EXPIRY_TAGS: &[Tag] = &[Tag::ORIGINATION_EXPIRE_DATETIME, Tag::USAGE_EXPIRE_DATETIME];
+
+/// Holds the maximum validity in milliseconds of new keys per Android user and per SELinux
+/// namespace.
+#[derive(Debug, Default)]
+pub struct KeyValidityPolicy {
+    by_user: RwLock<HashMap<u32, i64>>,
+    by_selinux_namespace: RwLock<HashMap<i64, i64>>,
+}
+
+impl KeyValidityPolicy {
+    /// Sets the maximum validity of new app keys of the Android user `user_id`. None removes
+    /// the limit.
+    pub fn set_user_max_validity(&self, user_id: u32, max_validity_ms: Option<i64>) {
+        log::info!("Maximum validity of keys of user {user_id}: {max_validity_ms:?} ms.");
+        let mut by_user = self.by_user.write().unwrap();
+        match max_validity_ms {
+            Some(v) => by_user.insert(user_id, v),
+            None => by_user.remove(&user_id),
+        };
+    }
+
+    /// Sets the maximum validity of new keys in the SELinux namespace `nspace`. None removes
+    /// the limit.
+    pub fn set_selinux_max_validity(&self, nspace: i64, max_validity_ms: Option<i64>) {
+        log::info!(
+            "Maximum validity of keys in SELinux namespace {nspace}: {max_validity_ms:?} ms."
+        );
+        let mut by_selinux_namespace = self.by_selinux_namespace.write().unwrap();
+        match max_validity_ms {
+            Some(v) => by_selinux_namespace.insert(nspace, v),
+            None => by_selinux_namespace.remove(&nspace),
+        };
+    }
+
+    /// Returns the maximum validity in milliseconds of new keys in the given namespace.
+    fn max_validity(&self, domain: Domain, nspace: i64) -> Option<i64> {
+        match domain {
+            Domain::APP => u32::try_from(nspace).ok().and_then(|uid| {
+                self.by_user.read().unwrap().get(&uid_to_android_user(uid)).copied()
+            }),
+            Domain::SELINUX => self.by_selinux_namespace.read().unwrap().get(&nspace).copied(),
+            _ => None,
+        }
+    }
+
+    /// Applies the policy to the parameters of a new key in the given namespace that is
+    /// created at `creation_ms` milliseconds since the epoch. Returns the expiration date
+    /// imposed on the key, if the namespace is subject to a maximum validity.
+    pub fn apply(
+        &self,
+        domain: Domain,
+        nspace: i64,
+        creation_ms: i64,
+        params: &mut Vec<KeyParameter>,
+    ) -> Option<DateTime> {
+        let max_validity_ms = self.max_validity(domain, nspace)?;
+        let not_after_ms = creation_ms.saturating_add(max_validity_ms);
+        clamp_expiry(params, not_after_ms);
+        Some(DateTime::from_millis_epoch(not_after_ms))
+    }
+}
+
+/// Clamps the key expiry tags and the certificate expiry in `params` to `not_after_ms`. The key
+/// expiry tags are added if they are missing. The certificate expiry is only clamped if present,
+/// because it is meaningless for symmetric keys.
+fn clamp_expiry(params: &mut Vec<KeyParameter>, not_after_ms: i64) {
+    for kp in params.iter_mut() {
+        if let KeyParameterValue::DateTime(t) = &mut kp.value {
+            if (KEY_EXPIRY_TAGS.contains(&kp.tag) || kp.tag == Tag::CERTIFICATE_NOT_AFTER)
+                && *t > not_after_ms
+            {
+                *t = not_after_ms;
+            }
+        }
+    }
+    for tag in KEY_EXPIRY_TAGS {
+        if !params.iter().any(|kp| kp.tag == *tag) {
+            params
+                .push(KeyParameter { tag: *tag, value: KeyParameterValue::DateTime(not_after_ms) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+    fn date_time(tag: Tag, millis: i64) -> KeyParameter {
+        KeyParameter { tag, value: KeyParameterValue::DateTime(millis) }
+    }
+
+    #[test]
+    fn test_clamp_expiry() {
+        let mut params = vec![
+            date_time(Tag::USAGE_EXPIRE_DATETIME, 5 * DAY_MS),
+            date_time(Tag::CERTIFICATE_NOT_AFTER, i64::MAX),
+            date_time(Tag::ACTIVE_DATETIME, 20 * DAY_MS),
+        ];
+        clamp_expiry(&mut params, 10 * DAY_MS);
+        assert_eq!(
+            params,
+            vec![
+                date_time(Tag::USAGE_EXPIRE_DATETIME, 5 * DAY_MS),
+                date_time(Tag::CERTIFICATE_NOT_AFTER, 10 * DAY_MS),
+                date_time(Tag::ACTIVE_DATETIME, 20 * DAY_MS),
+                date_time(Tag::ORIGINATION_EXPIRE_DATETIME, 10 * DAY_MS),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = KeyValidityPolicy::default();
+        policy.set_user_max_validity(10, Some(DAY_MS));
+        policy.set_selinux_max_validity(102, Some(2 * DAY_MS));
+
+        let mut params = vec![];
+        assert_eq!(policy.apply(Domain::APP, 10_010_123, 0, &mut params), None);
+        assert_eq!(policy.apply(Domain::SELINUX, 101, 0, &mut params), None);
+        assert!(params.is_empty());
+
+        assert_eq!(
+            policy.apply(Domain::APP, 1_010_123, 0, &mut params),
+            Some(DateTime::from_millis_epoch(DAY_MS))
+        );
+        assert_eq!(params.len(), KEY_EXPIRY_TAGS.len());
+        assert_eq!(
+            policy.apply(Domain::SELINUX, 102, DAY_MS, &mut params),
+            Some(DateTime::from_millis_epoch(3 * DAY_MS))
+        );
+        // The earlier, stricter expiry is kept.
+        assert!(params.iter().all(|kp| kp.value == KeyParameterValue::DateTime(DAY_MS)));
+
+        policy.set_user_max_validity(10, None);
+        assert_eq!(policy.apply(Domain::APP, 1_010_123, 0, &mut params), None);
+    }
+}
@@ -32,12 +32,16 @@ pub mod watchdog {
 
     /// Sets a watch point with `id` and a timeout of `millis` milliseconds.
     pub fn watch_millis(id: &'static str, millis: u64) -> Option<WatchPoint> {
-        Watchdog::watch(&WD, id, Duration::from_millis(millis))
+        let wp = Watchdog::watch(&WD, id, Duration::from_millis(millis));
+        crate::test_hooks::inject_hal_latency(id);
+        wp
     }
 
     /// Sets a watch point with `id` and a default timeout of [`DEFAULT_TIMEOUT_MS`] milliseconds.
     pub fn watch(id: &'static str) -> Option<WatchPoint> {
-        Watchdog::watch(&WD, id, DEFAULT_TIMEOUT)
+        let wp = Watchdog::watch(&WD, id, DEFAULT_TIMEOUT);
+        crate::test_hooks::inject_hal_latency(id);
+        wp
     }
 
     /// Like `watch_millis` but with context that is included every time a report is printed about
@@ -47,7 +51,9 @@ pub mod watchdog {
         millis: u64,
         context: impl std::fmt::Debug + Send + 'static,
     ) -> Option<WatchPoint> {
-        Watchdog::watch_with(&WD, id, Duration::from_millis(millis), context)
+        let wp = Watchdog::watch_with(&WD, id, Duration::from_millis(millis), context);
+        crate::test_hooks::inject_hal_latency(id);
+        wp
     }
 }
 
@@ -57,19 +63,22 @@ pub mod watchdog {
     /// Noop watch point.
     pub struct WatchPoint();
     /// Sets a Noop watch point.
-    fn watch_millis(_: &'static str, _: u64) -> Option<WatchPoint> {
+    fn watch_millis(id: &'static str, _: u64) -> Option<WatchPoint> {
+        crate::test_hooks::inject_hal_latency(id);
         None
     }
     /// Sets a Noop watch point.
-    fn watch(_: &'static str) -> Option<WatchPoint> {
+    fn watch(id: &'static str) -> Option<WatchPoint> {
+        crate::test_hooks::inject_hal_latency(id);
         None
     }
 
     pub fn watch_millis_with(
-        _: &'static str,
+        id: &'static str,
         _: u64,
         _: impl std::fmt::Debug + Send + 'static,
     ) -> Option<WatchPoint> {
+        crate::test_hooks::inject_hal_latency(id);
         None
     }
 }
@@ -0,0 +1,177 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements a pool of worker threads for jobs that may block for a long time,
+//! e.g., on a slow KeyMint instance, and must not hold up each other. Unlike AsyncTask, which
+//! runs its jobs one at a time, the pool runs up to a maximum number of jobs concurrently.
+//! Threads are spawned on demand and linger for a while after they ran out of jobs before they
+//! terminate. Jobs that are queued while all threads are busy run in the order they were queued.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    jobs: VecDeque<Job>,
+    /// The number of threads of the pool.
+    threads: usize,
+    /// The number of threads that are waiting for a job.
+    idle: usize,
+}
+
+struct Shared {
+    max_threads: usize,
+    idle_timeout: Duration,
+    state: Mutex<State>,
+    job_queued: Condvar,
+}
+
+/// A pool of worker threads.
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+}
+
+impl WorkerPool {
+    /// Creates a pool that runs at most `max_threads` jobs at a time. A thread terminates after
+    /// it has been idle for `idle_timeout`.
+    pub fn new(max_threads: usize, idle_timeout: Duration) -> Self {
+        assert!(max_threads > 0, "A worker pool needs at least one thread.");
+        Self {
+            shared: Arc::new(Shared {
+                max_threads,
+                idle_timeout,
+                state: Mutex::new(State::default()),
+                job_queued: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Queues `f` to run on one of the worker threads. A thread is spawned if there is no idle
+    /// thread to pick up the job and the pool is not at its maximum size.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        state.jobs.push_back(Box::new(f));
+        if state.idle < state.jobs.len() && state.threads < self.shared.max_threads {
+            state.threads += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || shared.work());
+        }
+        drop(state);
+        self.shared.job_queued.notify_one();
+    }
+}
+
+impl Shared {
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state.idle += 1;
+            let (guard, result) = self.job_queued.wait_timeout(state, self.idle_timeout).unwrap();
+            state = guard;
+            state.idle -= 1;
+            if result.timed_out() && state.jobs.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn thread_count(pool: &WorkerPool) -> usize {
+        pool.shared.state.lock().unwrap().threads
+    }
+
+    #[test]
+    fn test_jobs_run_concurrently() {
+        let pool = WorkerPool::new(2, Duration::from_secs(30));
+        let (release_sender, release_receiver) = channel::<()>();
+        let (done_sender, done_receiver) = channel();
+
+        let sender = done_sender.clone();
+        pool.execute(move || {
+            release_receiver.recv().unwrap();
+            sender.send("blocked").unwrap();
+        });
+        pool.execute(move || done_sender.send("unblocked").unwrap());
+
+        // The second job completes while the first one is blocked.
+        assert_eq!(done_receiver.recv_timeout(TIMEOUT), Ok("unblocked"));
+        release_sender.send(()).unwrap();
+        assert_eq!(done_receiver.recv_timeout(TIMEOUT), Ok("blocked"));
+        assert_eq!(thread_count(&pool), 2);
+    }
+
+    #[test]
+    fn test_thread_limit() {
+        let pool = WorkerPool::new(1, Duration::from_secs(30));
+        let (sender, receiver) = channel();
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                sender.send(i).unwrap();
+            });
+        }
+
+        // With a single thread, the jobs run one at a time in the order they were queued.
+        let order: Vec<_> = (0..10).map(|_| receiver.recv_timeout(TIMEOUT).unwrap()).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+        assert_eq!(thread_count(&pool), 1);
+    }
+
+    #[test]
+    fn test_idle_threads_terminate() {
+        let pool = WorkerPool::new(4, Duration::from_millis(50));
+        let (sender, receiver) = channel();
+        for _ in 0..4 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(()).unwrap());
+        }
+        for _ in 0..4 {
+            receiver.recv_timeout(TIMEOUT).unwrap();
+        }
+
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while thread_count(&pool) > 0 {
+            assert!(std::time::Instant::now() < deadline, "Idle threads did not terminate.");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The pool spawns a new thread for the next job.
+        pool.execute(move || sender.send(()).unwrap());
+        receiver.recv_timeout(TIMEOUT).unwrap();
+    }
+}
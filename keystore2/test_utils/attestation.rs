@@ -0,0 +1,528 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements helpers to validate the X.509 certificate chains that KeyMint returns
+//! and to read the attestation record of a certificate, in Rust and without the attestation
+//! parsers of libkeymint_support. See `KeyCreationResult.aidl` for documentation of the
+//! attestation record schema. The `assert_*` methods of `AttestationRecord` check the common
+//! properties of attestations and panic with a description of the mismatch.
+
+use crate::der::{self, AuthorizationList, Element, TAG_SEQUENCE};
+use crate::key_generations::{get_system_prop, Error};
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel, Tag::Tag,
+};
+use openssl::x509::X509;
+
+/// Arcs of the OID of the attestation record extension, 1.3.6.1.4.1.11129.2.1.17.
+const ATTESTATION_RECORD_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 11129, 2, 1, 17];
+
+/// The tags of the device identifiers that can be attested.
+const ATTESTATION_ID_TAGS: &[Tag] = &[
+    Tag::ATTESTATION_ID_BRAND,
+    Tag::ATTESTATION_ID_DEVICE,
+    Tag::ATTESTATION_ID_PRODUCT,
+    Tag::ATTESTATION_ID_SERIAL,
+    Tag::ATTESTATION_ID_IMEI,
+    Tag::ATTESTATION_ID_MEID,
+    Tag::ATTESTATION_ID_MANUFACTURER,
+    Tag::ATTESTATION_ID_MODEL,
+    Tag::ATTESTATION_ID_SECOND_IMEI,
+];
+
+/// Splits the concatenated DER-encoded certificates in `cert_buf` into the individual
+/// certificates.
+pub fn split_cert_chain(cert_buf: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let certs = der::parse_all(cert_buf).map_err(|_| Error::ValidateCertChainFailed)?;
+    if certs.is_empty() || certs.iter().any(|cert| cert.tag != [TAG_SEQUENCE]) {
+        return Err(Error::ValidateCertChainFailed);
+    }
+    Ok(certs.iter().map(|cert| cert.encoded).collect())
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Validates the given chain of concatenated DER-encoded certificates, which starts with the
+/// leaf certificate. Each certificate must be signed by the next one, and the last one must be
+/// self-signed. Without `strict_issuer_check`, the signature of the last certificate is not
+/// checked, as it is not self-signed in device unique attestation chains, and mismatches of the
+/// issuer of a certificate and the subject of the next one are not logged.
+pub fn validate_cert_chain(cert_buf: &[u8], strict_issuer_check: bool) -> Result<(), Error> {
+    let certs = split_cert_chain(cert_buf)?
+        .into_iter()
+        .map(X509::from_der)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            log::error!("Failed to parse certificate chain {}: {e:?}", to_hex(cert_buf));
+            Error::ValidateCertChainFailed
+        })?;
+
+    for (i, cert) in certs.iter().enumerate() {
+        let is_root = i == certs.len() - 1;
+        let signer = if is_root { cert } else { &certs[i + 1] };
+        let verified = signer.public_key().and_then(|key| cert.verify(&key)).unwrap_or_else(|e| {
+            log::error!("Failed to verify certificate {i}: {e:?}");
+            false
+        });
+        if !verified && (strict_issuer_check || !is_root) {
+            log::error!("Verification of certificate {i} failed: {}", to_hex(cert_buf));
+            return Err(Error::ValidateCertChainFailed);
+        }
+
+        if strict_issuer_check
+            && cert.issuer_name().to_der().ok() != signer.subject_name().to_der().ok()
+        {
+            // Like the former C++ implementation, this does not fail the validation.
+            log::error!("Certificate {i} has the wrong issuer.");
+        }
+    }
+    Ok(())
+}
+
+/// Validates the given chain like `validate_cert_chain` without strict issuer checks, and
+/// additionally requires it to end in one of the DER-encoded certificates in `roots`, or in a
+/// certificate signed by one of them. The latter allows chains that omit the root.
+pub fn validate_cert_chain_to_roots(cert_buf: &[u8], roots: &[&[u8]]) -> Result<(), Error> {
+    validate_cert_chain(cert_buf, false)?;
+    let certs = split_cert_chain(cert_buf)?;
+    let last = *certs.last().ok_or(Error::ValidateCertChainFailed)?;
+    if roots.contains(&last) {
+        return Ok(());
+    }
+    let last = X509::from_der(last).map_err(|_| Error::ValidateCertChainFailed)?;
+    let signed_by_root = roots.iter().any(|root| {
+        X509::from_der(root)
+            .and_then(|root| root.public_key())
+            .and_then(|key| last.verify(&key))
+            .unwrap_or(false)
+    });
+    if !signed_by_root {
+        log::error!("Certificate chain does not end in a trusted root: {}", to_hex(cert_buf));
+        return Err(Error::ValidateCertChainFailed);
+    }
+    Ok(())
+}
+
+/// Returns the DER-encoded attestation record extension of the given DER-encoded certificate.
+pub fn get_attestation_record(cert: &[u8]) -> Result<&[u8], Error> {
+    let (cert, _) = der::parse(cert)?;
+    let tbs_certificate = *cert.children()?.first().ok_or(Error::DerDecodeFailed)?;
+    let extensions = tbs_certificate
+        .children()?
+        .into_iter()
+        .find(|field| field.context_number() == Some(3))
+        .ok_or(Error::AttestRecordGetValueFailed)?;
+    let extensions: Vec<Element> = match extensions.children()?.as_slice() {
+        [extensions] => extensions.children()?,
+        _ => return Err(Error::DerDecodeFailed),
+    };
+    let oid = der::oid(ATTESTATION_RECORD_OID);
+    for extension in extensions {
+        // Extension ::= SEQUENCE { extnID OID, critical BOOLEAN DEFAULT FALSE, extnValue OCTET
+        // STRING }
+        let fields = extension.children()?;
+        if fields.first().map(|f| f.encoded) == Some(oid.as_slice()) {
+            return fields.last().ok_or(Error::DerDecodeFailed)?.octet_string();
+        }
+    }
+    Err(Error::AttestRecordGetValueFailed)
+}
+
+/// A decoded attestation record, i.e., the `KeyDescription` of the attestation record schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationRecord<'a> {
+    /// The version of the attestation record schema.
+    pub attestation_version: i64,
+    /// The security level of the attestation.
+    pub attestation_security_level: SecurityLevel,
+    /// The version of the KeyMint implementation.
+    pub keymint_version: i64,
+    /// The security level of the KeyMint implementation.
+    pub keymint_security_level: SecurityLevel,
+    /// The attestation challenge.
+    pub attestation_challenge: &'a [u8],
+    /// The unique ID, which is empty unless it was requested with INCLUDE_UNIQUE_ID.
+    pub unique_id: &'a [u8],
+    /// The authorizations enforced by software.
+    pub software_enforced: AuthorizationList<'a>,
+    /// The authorizations enforced by the secure environment.
+    pub hardware_enforced: AuthorizationList<'a>,
+}
+
+impl<'a> AttestationRecord<'a> {
+    /// Decodes the attestation record of the given DER-encoded certificate.
+    pub fn from_cert(cert: &'a [u8]) -> Result<Self, Error> {
+        Self::parse(get_attestation_record(cert)?)
+    }
+
+    /// Decodes the given DER-encoded attestation record.
+    pub fn parse(record: &'a [u8]) -> Result<Self, Error> {
+        let (record, _) = der::parse(record)?;
+        let fields = record.children()?;
+        if fields.len() != 8 {
+            return Err(Error::DerDecodeFailed);
+        }
+        let security_level = |e: &Element| -> Result<SecurityLevel, Error> {
+            Ok(SecurityLevel(i32::try_from(e.integer()?).map_err(|_| Error::DerDecodeFailed)?))
+        };
+        Ok(Self {
+            attestation_version: fields[0].integer()?,
+            attestation_security_level: security_level(&fields[1])?,
+            keymint_version: fields[2].integer()?,
+            keymint_security_level: security_level(&fields[3])?,
+            attestation_challenge: fields[4].octet_string()?,
+            unique_id: fields[5].octet_string()?,
+            software_enforced: AuthorizationList::parse(&fields[6])?,
+            hardware_enforced: AuthorizationList::parse(&fields[7])?,
+        })
+    }
+
+    /// Returns the authorizations enforced at the given security level.
+    pub fn enforced(&self, security_level: SecurityLevel) -> &AuthorizationList<'a> {
+        match security_level {
+            SecurityLevel::SOFTWARE | SecurityLevel::KEYSTORE => &self.software_enforced,
+            _ => &self.hardware_enforced,
+        }
+    }
+
+    /// Asserts that the attestation has the given challenge.
+    pub fn assert_challenge(&self, challenge: &[u8]) {
+        assert_eq!(
+            self.attestation_challenge,
+            challenge,
+            "Unexpected attestation challenge {}.",
+            to_hex(self.attestation_challenge)
+        );
+    }
+
+    /// Asserts that both the attestation and the KeyMint implementation have the given security
+    /// level.
+    pub fn assert_security_level(&self, security_level: SecurityLevel) {
+        assert_eq!(
+            self.attestation_security_level, security_level,
+            "Unexpected attestation security level."
+        );
+        assert_eq!(
+            self.keymint_security_level, security_level,
+            "Unexpected KeyMint security level."
+        );
+    }
+
+    /// Asserts that the hardware enforced authorizations attest exactly the given device
+    /// identifiers, given by their `Tag::ATTESTATION_ID_*` tag and value. Identifiers that are
+    /// not given must not be attested.
+    pub fn assert_attestation_ids(&self, ids: &[(Tag, &[u8])]) {
+        for tag in ATTESTATION_ID_TAGS {
+            let expected: Vec<KeyParameterValue> = ids
+                .iter()
+                .filter(|(id_tag, _)| id_tag == tag)
+                .map(|(_, value)| KeyParameterValue::Blob(value.to_vec()))
+                .collect();
+            let attested = self
+                .hardware_enforced
+                .get(*tag)
+                .unwrap_or_else(|e| panic!("Failed to decode {tag:?}: {e:?}"));
+            assert_eq!(attested, expected, "Unexpected value of {tag:?}.");
+        }
+        for (tag, _) in ids {
+            assert!(ATTESTATION_ID_TAGS.contains(tag), "{tag:?} is not an attestation ID.");
+        }
+    }
+}
+
+/// Returns the leading number of up to two digits of `s` and the rest of `s`.
+fn leading_number(s: &str) -> Option<(u32, &str)> {
+    let len = s.bytes().take(2).take_while(u8::is_ascii_digit).count();
+    Some((s[..len].parse().ok()?, &s[len..]))
+}
+
+/// Parses an OS release version such as "8.1.0" into the format of `Tag::OS_VERSION`,
+/// e.g., 80100, or 0 if the version is malformed.
+fn parse_os_version(version: &str) -> u32 {
+    let Some((major, mut rest)) = leading_number(version) else {
+        return 0;
+    };
+    let mut minor_versions = [0u32; 2];
+    for minor in minor_versions.iter_mut() {
+        match rest.strip_prefix('.').and_then(leading_number) {
+            Some((value, r)) => {
+                *minor = value;
+                rest = r;
+            }
+            None => break,
+        }
+    }
+    major * 10000 + minor_versions[0] * 100 + minor_versions[1]
+}
+
+/// Parses a security patch date of the form YYYY-MM-DD into YYYYMMDD, or 0 if the date is
+/// malformed.
+fn parse_patch_level(date: &str) -> u32 {
+    let parts: Vec<&str> = date.split('-').collect();
+    match parts.as_slice() {
+        [y, m, d]
+            if y.len() == 4
+                && m.len() == 2
+                && d.len() == 2
+                && parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            y.parse::<u32>().unwrap() * 10000
+                + m.parse::<u32>().unwrap() * 100
+                + d.parse::<u32>().unwrap()
+        }
+        _ => 0,
+    }
+}
+
+fn read_prop(name: &str) -> String {
+    String::from_utf8(get_system_prop(name)).unwrap_or_default()
+}
+
+/// Returns the OS version in the format that KeyMint uses for `Tag::OS_VERSION`.
+pub fn get_os_version() -> u32 {
+    parse_os_version(&read_prop("ro.build.version.release"))
+}
+
+/// Returns the OS patch level in the format that KeyMint uses for `Tag::OS_PATCHLEVEL`, i.e.,
+/// YYYYMM.
+pub fn get_os_patchlevel() -> u32 {
+    parse_patch_level(&read_prop("ro.build.version.security_patch")) / 100
+}
+
+/// Returns the vendor patch level in the format that KeyMint uses for `Tag::VENDOR_PATCHLEVEL`,
+/// i.e., YYYYMMDD.
+pub fn get_vendor_patchlevel() -> u32 {
+    parse_patch_level(&read_prop("ro.vendor.build.security_patch"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorizations::AuthSetBuilder;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::KeyPurpose::KeyPurpose;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::sign::Signer;
+
+    const CHALLENGE: &[u8] = b"challenge";
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        // Name ::= SEQUENCE OF SET OF AttributeTypeAndValue, with a UTF8String common name.
+        der::sequence(&[der::set_of(vec![der::sequence(&[
+            der::oid(&[2, 5, 4, 3]),
+            der::tlv(&[0x0c], common_name.as_bytes()),
+        ])])])
+    }
+
+    fn attestation_record(hardware_enforced: &AuthSetBuilder) -> Vec<u8> {
+        der::sequence(&[
+            der::integer(300),
+            der::tlv(&[der::TAG_ENUMERATED], &[1]),
+            der::integer(300),
+            der::tlv(&[der::TAG_ENUMERATED], &[1]),
+            der::octet_string(CHALLENGE),
+            der::octet_string(&[]),
+            der::authorization_list(&AuthSetBuilder::new().attestation_app_id(vec![7])).unwrap(),
+            der::authorization_list(hardware_enforced).unwrap(),
+        ])
+    }
+
+    /// Returns a DER-encoded X.509 certificate for `subject_key`, signed by `issuer_key` with
+    /// ECDSA and SHA-256, with the attestation record `record` if given.
+    fn cert(
+        subject: &str,
+        subject_key: &PKey<Private>,
+        issuer: &str,
+        issuer_key: &PKey<Private>,
+        record: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let ecdsa_with_sha256 = der::sequence(&[der::oid(&[1, 2, 840, 10045, 4, 3, 2])]);
+        let utc_time = |t: &str| der::tlv(&[0x17], t.as_bytes());
+        let mut tbs_fields = vec![
+            der::explicit(0, &der::integer(2)),
+            der::integer(1),
+            ecdsa_with_sha256.clone(),
+            name(issuer),
+            der::sequence(&[utc_time("200101000000Z"), utc_time("491231235959Z")]),
+            name(subject),
+            subject_key.public_key_to_der().unwrap(),
+        ];
+        if let Some(record) = record {
+            let extension =
+                der::sequence(&[der::oid(ATTESTATION_RECORD_OID), der::octet_string(record)]);
+            tbs_fields.push(der::explicit(3, &der::sequence(&[extension])));
+        }
+        let tbs = der::sequence(&tbs_fields);
+        let mut signer = Signer::new(MessageDigest::sha256(), issuer_key).unwrap();
+        let signature = signer.sign_oneshot_to_vec(&tbs).unwrap();
+        // BIT STRING without unused bits.
+        let signature = der::tlv(&[der::TAG_BIT_STRING], &[&[0u8][..], &signature].concat());
+        der::sequence(&[tbs, ecdsa_with_sha256, signature])
+    }
+
+    #[test]
+    fn test_validate_cert_chain() {
+        let (leaf_key, intermediate_key, root_key) = (ec_key(), ec_key(), ec_key());
+        let leaf = cert("leaf", &leaf_key, "intermediate", &intermediate_key, None);
+        let intermediate = cert("intermediate", &intermediate_key, "root", &root_key, None);
+        let root = cert("root", &root_key, "root", &root_key, None);
+        let chain = [leaf.clone(), intermediate.clone(), root.clone()].concat();
+        assert_eq!(
+            split_cert_chain(&chain).unwrap(),
+            vec![leaf.as_slice(), intermediate.as_slice(), root.as_slice()]
+        );
+        validate_cert_chain(&chain, true).unwrap();
+
+        // The certificates are out of order.
+        let chain = [intermediate.clone(), leaf.clone(), root.clone()].concat();
+        assert!(validate_cert_chain(&chain, true).is_err());
+
+        // The root is not self-signed, which is only accepted without strict issuer checks.
+        let other_root = cert("root", &root_key, "other", &leaf_key, None);
+        let chain = [leaf.clone(), intermediate.clone(), other_root].concat();
+        assert!(validate_cert_chain(&chain, true).is_err());
+        validate_cert_chain(&chain, false).unwrap();
+
+        // Malformed chains.
+        assert!(validate_cert_chain(&[], true).is_err());
+        assert!(validate_cert_chain(&leaf[..leaf.len() - 1], true).is_err());
+        assert!(validate_cert_chain(&der::octet_string(&leaf), true).is_err());
+    }
+
+    #[test]
+    fn test_validate_cert_chain_to_roots() {
+        let (leaf_key, intermediate_key, root_key) = (ec_key(), ec_key(), ec_key());
+        let leaf = cert("leaf", &leaf_key, "intermediate", &intermediate_key, None);
+        let intermediate = cert("intermediate", &intermediate_key, "root", &root_key, None);
+        let root = cert("root", &root_key, "root", &root_key, None);
+        let other_root = cert("other", &leaf_key, "other", &leaf_key, None);
+
+        let chain = [leaf.clone(), intermediate.clone(), root.clone()].concat();
+        validate_cert_chain_to_roots(&chain, &[&other_root, &root]).unwrap();
+        assert!(validate_cert_chain_to_roots(&chain, &[&other_root]).is_err());
+        assert!(validate_cert_chain_to_roots(&chain, &[]).is_err());
+
+        // The chain omits the root.
+        let chain = [leaf.clone(), intermediate.clone()].concat();
+        validate_cert_chain_to_roots(&chain, &[&root]).unwrap();
+        assert!(validate_cert_chain_to_roots(&chain, &[&other_root]).is_err());
+
+        // The chain is broken.
+        let chain = [leaf, root.clone()].concat();
+        assert!(validate_cert_chain_to_roots(&chain, &[&root]).is_err());
+    }
+
+    #[test]
+    fn test_attestation_record() {
+        let hardware_enforced = AuthSetBuilder::new()
+            .purpose(KeyPurpose::SIGN)
+            .usage_count_limit(1)
+            .attestation_device_brand(b"brand".to_vec());
+        let record = attestation_record(&hardware_enforced);
+        let key = ec_key();
+        let leaf = cert("leaf", &key, "leaf", &key, Some(record.as_slice()));
+        assert_eq!(get_attestation_record(&leaf).unwrap(), record);
+
+        let record = AttestationRecord::from_cert(&leaf).unwrap();
+        assert_eq!(record.attestation_version, 300);
+        assert_eq!(record.attestation_security_level, SecurityLevel::TRUSTED_ENVIRONMENT);
+        assert_eq!(record.keymint_security_level, SecurityLevel::TRUSTED_ENVIRONMENT);
+        assert_eq!(record.attestation_challenge, CHALLENGE);
+        assert!(record.unique_id.is_empty());
+        assert_eq!(
+            record.software_enforced.get(Tag::ATTESTATION_APPLICATION_ID).unwrap(),
+            vec![KeyParameterValue::Blob(vec![7])]
+        );
+        let hw = record.enforced(SecurityLevel::STRONGBOX);
+        assert_eq!(hw.get(Tag::USAGE_COUNT_LIMIT).unwrap(), vec![KeyParameterValue::Integer(1)]);
+        assert_eq!(
+            hw.get(Tag::ATTESTATION_ID_BRAND).unwrap(),
+            vec![KeyParameterValue::Blob(b"brand".to_vec())]
+        );
+
+        // Without an attestation record.
+        let leaf = cert("leaf", &key, "leaf", &key, None);
+        assert!(AttestationRecord::from_cert(&leaf).is_err());
+    }
+
+    #[test]
+    fn test_assertions() {
+        let hardware_enforced = AuthSetBuilder::new()
+            .attestation_device_brand(b"brand".to_vec())
+            .attestation_device_model(b"model".to_vec());
+        let record = attestation_record(&hardware_enforced);
+        let record = AttestationRecord::parse(&record).unwrap();
+        record.assert_challenge(CHALLENGE);
+        record.assert_security_level(SecurityLevel::TRUSTED_ENVIRONMENT);
+        record.assert_attestation_ids(&[
+            (Tag::ATTESTATION_ID_MODEL, b"model"),
+            (Tag::ATTESTATION_ID_BRAND, b"brand"),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected attestation challenge")]
+    fn test_assert_challenge_mismatch() {
+        let record = attestation_record(&AuthSetBuilder::new());
+        AttestationRecord::parse(&record).unwrap().assert_challenge(b"other challenge");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected KeyMint security level")]
+    fn test_assert_security_level_mismatch() {
+        let record = attestation_record(&AuthSetBuilder::new());
+        AttestationRecord::parse(&record).unwrap().assert_security_level(SecurityLevel::STRONGBOX);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected value of ATTESTATION_ID_MODEL")]
+    fn test_assert_attestation_ids_unexpected_id() {
+        let hardware_enforced = AuthSetBuilder::new()
+            .attestation_device_brand(b"brand".to_vec())
+            .attestation_device_model(b"model".to_vec());
+        let record = attestation_record(&hardware_enforced);
+        AttestationRecord::parse(&record)
+            .unwrap()
+            .assert_attestation_ids(&[(Tag::ATTESTATION_ID_BRAND, b"brand")]);
+    }
+
+    #[test]
+    fn test_parse_os_version() {
+        assert_eq!(parse_os_version("15"), 150000);
+        assert_eq!(parse_os_version("8.1.0"), 80100);
+        assert_eq!(parse_os_version("12.1"), 120100);
+        assert_eq!(parse_os_version("14-beta"), 140000);
+        assert_eq!(parse_os_version("Baklava"), 0);
+        assert_eq!(parse_os_version(""), 0);
+    }
+
+    #[test]
+    fn test_parse_patch_level() {
+        assert_eq!(parse_patch_level("2026-10-05"), 20261005);
+        assert_eq!(parse_patch_level("2026-10-05") / 100, 202610);
+        assert_eq!(parse_patch_level("2026-1-05"), 0);
+        assert_eq!(parse_patch_level("2026-10"), 0);
+        assert_eq!(parse_patch_level("abcd-ef-gh"), 0);
+        assert_eq!(parse_patch_level(""), 0);
+    }
+}
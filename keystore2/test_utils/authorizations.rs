@@ -18,8 +18,9 @@ use std::ops::Deref;
 
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
+    HardwareAuthenticatorType::HardwareAuthenticatorType, KeyOrigin::KeyOrigin,
     KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose,
-    PaddingMode::PaddingMode, Tag::Tag,
+    PaddingMode::PaddingMode, SecurityLevel::SecurityLevel, Tag::Tag,
 };
 
 /// Helper struct to create set of Authorizations.
@@ -369,6 +370,201 @@ impl AuthSetBuilder {
         });
         self
     }
+
+    /// Set rollback resistance.
+    pub fn rollback_resistance(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::ROLLBACK_RESISTANCE,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Add hardware type.
+    pub fn hardware_type(mut self, s: SecurityLevel) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::HARDWARE_TYPE,
+            value: KeyParameterValue::SecurityLevel(s),
+        });
+        self
+    }
+
+    /// Set min seconds between operations.
+    pub fn min_seconds_between_ops(mut self, seconds: i32) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::MIN_SECONDS_BETWEEN_OPS,
+            value: KeyParameterValue::Integer(seconds),
+        });
+        self
+    }
+
+    /// Add user ID.
+    pub fn user_id(mut self, id: i32) -> Self {
+        self.0.push(KeyParameter { tag: Tag::USER_ID, value: KeyParameterValue::Integer(id) });
+        self
+    }
+
+    /// Set allow while on body.
+    pub fn allow_while_on_body(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::ALLOW_WHILE_ON_BODY,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Set trusted user presence required.
+    pub fn trusted_user_presence_required(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::TRUSTED_USER_PRESENCE_REQUIRED,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Set trusted confirmation required.
+    pub fn trusted_confirmation_required(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::TRUSTED_CONFIRMATION_REQUIRED,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Add key origin.
+    pub fn origin(mut self, o: KeyOrigin) -> Self {
+        self.0.push(KeyParameter { tag: Tag::ORIGIN, value: KeyParameterValue::Origin(o) });
+        self
+    }
+
+    /// Add root of trust.
+    pub fn root_of_trust(mut self, b: Vec<u8>) -> Self {
+        self.0.push(KeyParameter { tag: Tag::ROOT_OF_TRUST, value: KeyParameterValue::Blob(b) });
+        self
+    }
+
+    /// Add OS version.
+    pub fn os_version(mut self, v: i32) -> Self {
+        self.0.push(KeyParameter { tag: Tag::OS_VERSION, value: KeyParameterValue::Integer(v) });
+        self
+    }
+
+    /// Add OS patch level.
+    pub fn os_patch_level(mut self, l: i32) -> Self {
+        self.0.push(KeyParameter { tag: Tag::OS_PATCHLEVEL, value: KeyParameterValue::Integer(l) });
+        self
+    }
+
+    /// Add vendor patch level.
+    pub fn vendor_patch_level(mut self, l: i32) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::VENDOR_PATCHLEVEL,
+            value: KeyParameterValue::Integer(l),
+        });
+        self
+    }
+
+    /// Add boot patch level.
+    pub fn boot_patch_level(mut self, l: i32) -> Self {
+        self.0
+            .push(KeyParameter { tag: Tag::BOOT_PATCHLEVEL, value: KeyParameterValue::Integer(l) });
+        self
+    }
+
+    /// Add unique ID.
+    pub fn unique_id(mut self, b: Vec<u8>) -> Self {
+        self.0.push(KeyParameter { tag: Tag::UNIQUE_ID, value: KeyParameterValue::Blob(b) });
+        self
+    }
+
+    /// Add Attestation-Application-Id.
+    pub fn attestation_app_id(mut self, b: Vec<u8>) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::ATTESTATION_APPLICATION_ID,
+            value: KeyParameterValue::Blob(b),
+        });
+        self
+    }
+
+    /// Set identity credential key.
+    pub fn identity_credential_key(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::IDENTITY_CREDENTIAL_KEY,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Set storage key.
+    pub fn storage_key(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::STORAGE_KEY,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Add associated data.
+    pub fn associated_data(mut self, b: Vec<u8>) -> Self {
+        self.0.push(KeyParameter { tag: Tag::ASSOCIATED_DATA, value: KeyParameterValue::Blob(b) });
+        self
+    }
+
+    /// Set reset since ID rotation.
+    pub fn reset_since_id_rotation(mut self) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::RESET_SINCE_ID_ROTATION,
+            value: KeyParameterValue::BoolValue(true),
+        });
+        self
+    }
+
+    /// Add confirmation token.
+    pub fn confirmation_token(mut self, b: Vec<u8>) -> Self {
+        self.0
+            .push(KeyParameter { tag: Tag::CONFIRMATION_TOKEN, value: KeyParameterValue::Blob(b) });
+        self
+    }
+
+    /// Set max boot level.
+    pub fn max_boot_level(mut self, l: i32) -> Self {
+        self.0
+            .push(KeyParameter { tag: Tag::MAX_BOOT_LEVEL, value: KeyParameterValue::Integer(l) });
+        self
+    }
+
+    /// Add an arbitrary key parameter.
+    pub fn param(mut self, kp: KeyParameter) -> Self {
+        self.0.push(kp);
+        self
+    }
+
+    /// Add user secure ID.
+    pub fn user_secure_id(mut self, sid: i64) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::USER_SECURE_ID,
+            value: KeyParameterValue::LongInteger(sid),
+        });
+        self
+    }
+
+    /// Add user authentication type.
+    pub fn user_auth_type(mut self, t: HardwareAuthenticatorType) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::USER_AUTH_TYPE,
+            value: KeyParameterValue::HardwareAuthenticatorType(t),
+        });
+        self
+    }
+
+    /// Add authentication timeout in seconds.
+    pub fn auth_timeout(mut self, seconds: i32) -> Self {
+        self.0.push(KeyParameter {
+            tag: Tag::AUTH_TIMEOUT,
+            value: KeyParameterValue::Integer(seconds),
+        });
+        self
+    }
 }
 
 impl Deref for AuthSetBuilder {
@@ -378,3 +574,100 @@ impl Deref for AuthSetBuilder {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::TagType::TagType;
+
+    fn value_matches_tag_type(kp: &KeyParameter) -> bool {
+        let tag_type = TagType(kp.tag.0 & 0xf000_0000u32 as i32);
+        match &kp.value {
+            KeyParameterValue::Integer(_) => {
+                matches!(tag_type, TagType::UINT | TagType::UINT_REP)
+            }
+            KeyParameterValue::LongInteger(_) => {
+                matches!(tag_type, TagType::ULONG | TagType::ULONG_REP)
+            }
+            KeyParameterValue::DateTime(_) => tag_type == TagType::DATE,
+            KeyParameterValue::BoolValue(_) => tag_type == TagType::BOOL,
+            KeyParameterValue::Blob(_) => matches!(tag_type, TagType::BYTES | TagType::BIGNUM),
+            KeyParameterValue::Invalid(_) => false,
+            _ => matches!(tag_type, TagType::ENUM | TagType::ENUM_REP),
+        }
+    }
+
+    #[test]
+    fn test_value_types_match_tags() {
+        let auths = AuthSetBuilder::new()
+            .purpose(KeyPurpose::SIGN)
+            .algorithm(Algorithm::EC)
+            .key_size(256)
+            .block_mode(BlockMode::GCM)
+            .digest(Digest::SHA_2_256)
+            .mgf_digest(Digest::SHA1)
+            .padding_mode(PaddingMode::NONE)
+            .caller_nonce()
+            .min_mac_length(128)
+            .ec_curve(EcCurve::P_256)
+            .rsa_public_exponent(65537)
+            .include_unique_id()
+            .boot_loader_only()
+            .rollback_resistance()
+            .hardware_type(SecurityLevel::TRUSTED_ENVIRONMENT)
+            .early_boot_only()
+            .active_date_time(0)
+            .origination_expire_date_time(0)
+            .usage_expire_date_time(0)
+            .min_seconds_between_ops(1)
+            .max_uses_per_boot(1)
+            .usage_count_limit(1)
+            .user_id(0)
+            .user_secure_id(1)
+            .no_auth_required()
+            .user_auth_type(HardwareAuthenticatorType::PASSWORD)
+            .auth_timeout(1)
+            .allow_while_on_body()
+            .trusted_user_presence_required()
+            .trusted_confirmation_required()
+            .unlocked_device_required()
+            .app_id(vec![1])
+            .app_data(vec![1])
+            .creation_date_time(0)
+            .origin(KeyOrigin::GENERATED)
+            .root_of_trust(vec![1])
+            .os_version(1)
+            .os_patch_level(1)
+            .unique_id(vec![1])
+            .attestation_challenge(vec![1])
+            .attestation_app_id(vec![1])
+            .attestation_device_brand(vec![1])
+            .attestation_device_name(vec![1])
+            .attestation_device_product_name(vec![1])
+            .attestation_device_serial(vec![1])
+            .attestation_device_imei(vec![1])
+            .attestation_device_second_imei(vec![1])
+            .attestation_device_meid(vec![1])
+            .attestation_device_manufacturer(vec![1])
+            .attestation_device_model(vec![1])
+            .vendor_patch_level(1)
+            .boot_patch_level(1)
+            .device_unique_attestation()
+            .identity_credential_key()
+            .storage_key()
+            .associated_data(vec![1])
+            .nonce(vec![1])
+            .mac_length(128)
+            .reset_since_id_rotation()
+            .confirmation_token(vec![1])
+            .cert_serial(vec![1])
+            .cert_subject_name(vec![1])
+            .cert_not_before(0)
+            .cert_not_after(0)
+            .max_boot_level(1);
+
+        for kp in auths.iter() {
+            assert!(value_matches_tag_type(kp), "{kp:?} has a value of the wrong type");
+        }
+    }
+}
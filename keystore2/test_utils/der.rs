@@ -0,0 +1,477 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the subset of ASN.1 DER that the test utils need: encoding of the
+//! schemas that tests send to KeyMint, e.g., `SecureKeyWrapper`, and decoding of the
+//! certificates and attestation records that KeyMint returns. See `IKeyMintDevice.aidl` and
+//! `KeyCreationResult.aidl` for documentation of the schemas.
+
+use crate::key_generations::Error;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
+    HardwareAuthenticatorType::HardwareAuthenticatorType, KeyOrigin::KeyOrigin,
+    KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose,
+    PaddingMode::PaddingMode, SecurityLevel::SecurityLevel, Tag::Tag, TagType::TagType,
+};
+
+/// Identifier octet of an INTEGER.
+pub const TAG_INTEGER: u8 = 0x02;
+/// Identifier octet of a BIT STRING.
+pub const TAG_BIT_STRING: u8 = 0x03;
+/// Identifier octet of an OCTET STRING.
+pub const TAG_OCTET_STRING: u8 = 0x04;
+/// Identifier octet of a NULL.
+pub const TAG_NULL: u8 = 0x05;
+/// Identifier octet of an OBJECT IDENTIFIER.
+pub const TAG_OID: u8 = 0x06;
+/// Identifier octet of an ENUMERATED.
+pub const TAG_ENUMERATED: u8 = 0x0a;
+/// Identifier octet of a SEQUENCE or SEQUENCE OF.
+pub const TAG_SEQUENCE: u8 = 0x30;
+/// Identifier octet of a SET OF.
+pub const TAG_SET: u8 = 0x31;
+
+/// Returns the number of the given tag without its type, which is the number of its field in an
+/// `AuthorizationList`.
+fn tag_number(tag: Tag) -> u32 {
+    (tag.0 & 0x0fff_ffff) as u32
+}
+
+fn tag_type(tag: Tag) -> TagType {
+    TagType(tag.0 & 0xf000_0000u32 as i32)
+}
+
+/// Encodes `content` with the given identifier octets.
+pub fn tlv(tag: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut encoded = tag.to_vec();
+    if content.len() < 0x80 {
+        encoded.push(content.len() as u8);
+    } else {
+        let len = content.len().to_be_bytes();
+        let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+        encoded.push(0x80 | len.len() as u8);
+        encoded.extend_from_slice(len);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Encodes an INTEGER.
+pub fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Strip leading bytes that only repeat the sign bit.
+    let mut start = 0;
+    while start < bytes.len() - 1
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(&[TAG_INTEGER], &bytes[start..])
+}
+
+/// Encodes an OCTET STRING.
+pub fn octet_string(value: &[u8]) -> Vec<u8> {
+    tlv(&[TAG_OCTET_STRING], value)
+}
+
+/// Encodes a NULL.
+pub fn null() -> Vec<u8> {
+    tlv(&[TAG_NULL], &[])
+}
+
+/// Encodes an OBJECT IDENTIFIER with the given arcs, of which there must be at least two.
+pub fn oid(arcs: &[u64]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let first = arcs[0] * 40 + arcs[1];
+    for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+        // Base 128 digits, most significant first, with the high bit set on all but the last.
+        let mut digits = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            digits.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(digits.iter().rev());
+    }
+    tlv(&[TAG_OID], &content)
+}
+
+/// Encodes a SEQUENCE of the given encoded elements.
+pub fn sequence(elements: &[Vec<u8>]) -> Vec<u8> {
+    tlv(&[TAG_SEQUENCE], &elements.concat())
+}
+
+/// Encodes a SET OF the given encoded elements.
+pub fn set_of(mut elements: Vec<Vec<u8>>) -> Vec<u8> {
+    // DER requires the elements of a SET OF in ascending order of their encodings.
+    elements.sort();
+    tlv(&[TAG_SET], &elements.concat())
+}
+
+/// Encodes `content` with an explicit, context-specific tag of the given number.
+pub fn explicit(number: u32, content: &[u8]) -> Vec<u8> {
+    let mut tag = Vec::new();
+    if number < 0x1f {
+        tag.push(0xa0 | number as u8);
+    } else {
+        // High tag number form: base 128 digits, most significant first, with the high bit set
+        // on all but the last.
+        tag.push(0xbf);
+        let mut digits = vec![(number & 0x7f) as u8];
+        let mut rest = number >> 7;
+        while rest > 0 {
+            digits.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        tag.extend(digits.iter().rev());
+    }
+    tlv(&tag, content)
+}
+
+/// Encodes the given key parameters as an `AuthorizationList`, in which each tag is an explicitly
+/// tagged field numbered by the tag without its type, in ascending order, and repeatable tags are
+/// a SET OF their values.
+pub fn authorization_list(params: &[KeyParameter]) -> Result<Vec<u8>, Error> {
+    let mut tags: Vec<Tag> = params.iter().map(|kp| kp.tag).collect();
+    tags.sort_by_key(|tag| tag_number(*tag));
+    tags.dedup();
+
+    let mut fields = Vec::new();
+    for tag in tags {
+        let values = params
+            .iter()
+            .filter(|kp| kp.tag == tag)
+            .map(|kp| key_parameter_value(tag_type(tag), &kp.value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let content = match tag_type(tag) {
+            TagType::ENUM_REP | TagType::UINT_REP | TagType::ULONG_REP => set_of(values),
+            _ if values.len() == 1 => values.into_iter().next().unwrap(),
+            _ => return Err(Error::DerEncodeFailed),
+        };
+        fields.push(explicit(tag_number(tag), &content));
+    }
+    Ok(sequence(&fields))
+}
+
+fn key_parameter_value(tag_type: TagType, value: &KeyParameterValue) -> Result<Vec<u8>, Error> {
+    let enum_value = match value {
+        KeyParameterValue::Algorithm(v) => Some(v.0),
+        KeyParameterValue::BlockMode(v) => Some(v.0),
+        KeyParameterValue::PaddingMode(v) => Some(v.0),
+        KeyParameterValue::Digest(v) => Some(v.0),
+        KeyParameterValue::EcCurve(v) => Some(v.0),
+        KeyParameterValue::Origin(v) => Some(v.0),
+        KeyParameterValue::KeyPurpose(v) => Some(v.0),
+        KeyParameterValue::HardwareAuthenticatorType(v) => Some(v.0),
+        KeyParameterValue::SecurityLevel(v) => Some(v.0),
+        _ => None,
+    };
+    match (tag_type, value) {
+        (TagType::ENUM | TagType::ENUM_REP, _) => {
+            enum_value.map(|v| integer(v.into())).ok_or(Error::DerEncodeFailed)
+        }
+        (TagType::UINT | TagType::UINT_REP, KeyParameterValue::Integer(v)) => {
+            Ok(integer((*v as u32).into()))
+        }
+        (
+            TagType::ULONG | TagType::ULONG_REP | TagType::DATE,
+            KeyParameterValue::LongInteger(v) | KeyParameterValue::DateTime(v),
+        ) => Ok(integer(*v)),
+        (TagType::BOOL, KeyParameterValue::BoolValue(true)) => Ok(null()),
+        (TagType::BYTES | TagType::BIGNUM, KeyParameterValue::Blob(v)) => Ok(octet_string(v)),
+        _ => Err(Error::DerEncodeFailed),
+    }
+}
+
+/// A DER-encoded element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Element<'a> {
+    /// The identifier octets.
+    pub tag: &'a [u8],
+    /// The contents octets.
+    pub content: &'a [u8],
+    /// The complete encoding, including the identifier and length octets.
+    pub encoded: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    /// Returns the elements within a constructed element, e.g., a SEQUENCE.
+    pub fn children(&self) -> Result<Vec<Element<'a>>, Error> {
+        if self.tag[0] & 0x20 == 0 {
+            return Err(Error::DerDecodeFailed);
+        }
+        parse_all(self.content)
+    }
+
+    /// Returns the number of a context-specific tag, or None for other classes of tags.
+    pub fn context_number(&self) -> Option<u32> {
+        if self.tag[0] & 0xc0 != 0x80 {
+            return None;
+        }
+        match self.tag {
+            [t] => Some((t & 0x1f) as u32),
+            [_, digits @ ..] => {
+                Some(digits.iter().fold(0, |number, d| number << 7 | (d & 0x7f) as u32))
+            }
+            [] => None,
+        }
+    }
+
+    /// Returns the value of an INTEGER or ENUMERATED that fits into an i64.
+    pub fn integer(&self) -> Result<i64, Error> {
+        if !matches!(self.tag, [TAG_INTEGER] | [TAG_ENUMERATED])
+            || self.content.is_empty()
+            || self.content.len() > 8
+        {
+            return Err(Error::DerDecodeFailed);
+        }
+        let sign = if self.content[0] & 0x80 != 0 { -1i64 } else { 0 };
+        Ok(self.content.iter().fold(sign, |value, b| value << 8 | *b as i64))
+    }
+
+    /// Returns the value of an OCTET STRING.
+    pub fn octet_string(&self) -> Result<&'a [u8], Error> {
+        match self.tag {
+            [TAG_OCTET_STRING] => Ok(self.content),
+            _ => Err(Error::DerDecodeFailed),
+        }
+    }
+
+    /// Returns the element with the given tag within an EXPLICIT tagged element.
+    fn explicit_content(&self) -> Result<Element<'a>, Error> {
+        match self.children()?.as_slice() {
+            [inner] => Ok(*inner),
+            _ => Err(Error::DerDecodeFailed),
+        }
+    }
+}
+
+/// Decodes the first element of `input` and returns it together with the remaining input.
+pub fn parse(input: &[u8]) -> Result<(Element<'_>, &[u8]), Error> {
+    let mut pos = 1;
+    if *input.first().ok_or(Error::DerDecodeFailed)? & 0x1f == 0x1f {
+        // High tag number form.
+        while *input.get(pos).ok_or(Error::DerDecodeFailed)? & 0x80 != 0 {
+            pos += 1;
+        }
+        pos += 1;
+    }
+    let tag_len = pos;
+    let len = match *input.get(pos).ok_or(Error::DerDecodeFailed)? {
+        l if l < 0x80 => {
+            pos += 1;
+            l as usize
+        }
+        l => {
+            let n = (l & 0x7f) as usize;
+            if n == 0 || n > std::mem::size_of::<usize>() {
+                return Err(Error::DerDecodeFailed);
+            }
+            let len_octets = input.get(pos + 1..pos + 1 + n).ok_or(Error::DerDecodeFailed)?;
+            pos += 1 + n;
+            len_octets.iter().fold(0, |len, b| len << 8 | *b as usize)
+        }
+    };
+    let end = pos.checked_add(len).ok_or(Error::DerDecodeFailed)?;
+    let content = input.get(pos..end).ok_or(Error::DerDecodeFailed)?;
+    Ok((Element { tag: &input[..tag_len], content, encoded: &input[..end] }, &input[end..]))
+}
+
+/// Decodes all elements of `input`, which must not have any trailing data.
+pub fn parse_all(mut input: &[u8]) -> Result<Vec<Element<'_>>, Error> {
+    let mut elements = Vec::new();
+    while !input.is_empty() {
+        let (element, rest) = parse(input)?;
+        elements.push(element);
+        input = rest;
+    }
+    Ok(elements)
+}
+
+/// A decoded `AuthorizationList`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthorizationList<'a> {
+    fields: Vec<(u32, Element<'a>)>,
+}
+
+impl<'a> AuthorizationList<'a> {
+    /// Decodes the given `AuthorizationList` element.
+    pub fn parse(list: &Element<'a>) -> Result<Self, Error> {
+        let fields = list
+            .children()?
+            .into_iter()
+            .map(|field| {
+                let number = field.context_number().ok_or(Error::DerDecodeFailed)?;
+                Ok((number, field.explicit_content()?))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { fields })
+    }
+
+    /// Returns true if the list has a field for the given tag.
+    pub fn contains(&self, tag: Tag) -> bool {
+        self.fields.iter().any(|(number, _)| *number == tag_number(tag))
+    }
+
+    /// Returns the values of the given tag, which are several for repeatable tags.
+    pub fn get(&self, tag: Tag) -> Result<Vec<KeyParameterValue>, Error> {
+        let Some((_, field)) = self.fields.iter().find(|(number, _)| *number == tag_number(tag))
+        else {
+            return Ok(Vec::new());
+        };
+        match tag_type(tag) {
+            TagType::ENUM_REP | TagType::UINT_REP | TagType::ULONG_REP => {
+                if field.tag != [TAG_SET] {
+                    return Err(Error::DerDecodeFailed);
+                }
+                field.children()?.iter().map(|value| decode_value(tag, value)).collect()
+            }
+            _ => Ok(vec![decode_value(tag, field)?]),
+        }
+    }
+
+    /// Returns the key parameter of the given tag, or None if the list has no field for it.
+    /// Fails for repeatable tags with more than one value.
+    pub fn get_one(&self, tag: Tag) -> Result<Option<KeyParameter>, Error> {
+        let mut values = self.get(tag)?;
+        match values.len() {
+            0 => Ok(None),
+            1 => Ok(Some(KeyParameter { tag, value: values.remove(0) })),
+            _ => Err(Error::DerDecodeFailed),
+        }
+    }
+}
+
+fn decode_value(tag: Tag, value: &Element) -> Result<KeyParameterValue, Error> {
+    match tag_type(tag) {
+        TagType::ENUM | TagType::ENUM_REP => {
+            let v = i32::try_from(value.integer()?).map_err(|_| Error::DerDecodeFailed)?;
+            match tag {
+                Tag::ALGORITHM => Ok(KeyParameterValue::Algorithm(Algorithm(v))),
+                Tag::BLOCK_MODE => Ok(KeyParameterValue::BlockMode(BlockMode(v))),
+                Tag::PADDING => Ok(KeyParameterValue::PaddingMode(PaddingMode(v))),
+                Tag::DIGEST | Tag::RSA_OAEP_MGF_DIGEST => Ok(KeyParameterValue::Digest(Digest(v))),
+                Tag::EC_CURVE => Ok(KeyParameterValue::EcCurve(EcCurve(v))),
+                Tag::ORIGIN => Ok(KeyParameterValue::Origin(KeyOrigin(v))),
+                Tag::PURPOSE => Ok(KeyParameterValue::KeyPurpose(KeyPurpose(v))),
+                Tag::USER_AUTH_TYPE => {
+                    Ok(KeyParameterValue::HardwareAuthenticatorType(HardwareAuthenticatorType(v)))
+                }
+                Tag::HARDWARE_TYPE => Ok(KeyParameterValue::SecurityLevel(SecurityLevel(v))),
+                _ => Err(Error::DerDecodeFailed),
+            }
+        }
+        TagType::UINT | TagType::UINT_REP => {
+            let v = u32::try_from(value.integer()?).map_err(|_| Error::DerDecodeFailed)?;
+            Ok(KeyParameterValue::Integer(v as i32))
+        }
+        TagType::ULONG | TagType::ULONG_REP => Ok(KeyParameterValue::LongInteger(value.integer()?)),
+        TagType::DATE => Ok(KeyParameterValue::DateTime(value.integer()?)),
+        TagType::BOOL if value.tag == [TAG_NULL] => Ok(KeyParameterValue::BoolValue(true)),
+        TagType::BYTES | TagType::BIGNUM => {
+            Ok(KeyParameterValue::Blob(value.octet_string()?.to_vec()))
+        }
+        _ => Err(Error::DerDecodeFailed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorizations::AuthSetBuilder;
+
+    #[test]
+    fn test_integer() {
+        assert_eq!(integer(0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(integer(127), vec![0x02, 0x01, 0x7f]);
+        assert_eq!(integer(128), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(256), vec![0x02, 0x02, 0x01, 0x00]);
+        assert_eq!(integer(-1), vec![0x02, 0x01, 0xff]);
+        assert_eq!(integer(-129), vec![0x02, 0x02, 0xff, 0x7f]);
+
+        for value in [0, 127, 128, 256, -1, -129, i64::MAX, i64::MIN] {
+            let encoded = integer(value);
+            let (element, rest) = parse(&encoded).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(element.integer().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_oid() {
+        assert_eq!(
+            oid(&[1, 3, 6, 1, 4, 1, 11129, 2, 1, 17]),
+            vec![0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x01, 0x11]
+        );
+    }
+
+    #[test]
+    fn test_parse_long_form_and_high_tags() {
+        let content = vec![0x5a; 300];
+        let encoded = [explicit(709, &octet_string(&content)), null()].concat();
+        let elements = parse_all(&encoded).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].context_number(), Some(709));
+        assert_eq!(elements[0].explicit_content().unwrap().octet_string().unwrap(), content);
+        assert_eq!(elements[1].tag, [TAG_NULL]);
+        assert_eq!(elements[1].context_number(), None);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let inputs: [&[u8]; 6] = [
+            &[0x04],
+            &[0x04, 0x02, 0x00],
+            &[0x04, 0x80],
+            &[0x04, 0x82, 0x01],
+            &[0xbf, 0x85],
+            &[0x04, 0x89, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for input in inputs {
+            assert!(parse_all(input).is_err(), "{input:02x?} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_authorization_list_round_trip() {
+        let params = AuthSetBuilder::new()
+            .purpose(KeyPurpose::SIGN)
+            .purpose(KeyPurpose::VERIFY)
+            .algorithm(Algorithm::EC)
+            .ec_curve(EcCurve::P_256)
+            .digest(Digest::SHA_2_256)
+            .usage_count_limit(3)
+            .user_secure_id(-2)
+            .active_date_time(1_700_000_000_000)
+            .no_auth_required()
+            .attestation_app_id(vec![1, 2, 3]);
+        let encoded = authorization_list(&params).unwrap();
+        let (element, _) = parse(&encoded).unwrap();
+        let list = AuthorizationList::parse(&element).unwrap();
+
+        assert_eq!(
+            list.get(Tag::PURPOSE).unwrap(),
+            vec![
+                KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+                KeyParameterValue::KeyPurpose(KeyPurpose::VERIFY)
+            ]
+        );
+        for kp in params.iter().filter(|kp| kp.tag != Tag::PURPOSE) {
+            assert_eq!(list.get_one(kp.tag).unwrap().as_ref(), Some(kp));
+        }
+        assert!(!list.contains(Tag::KEY_SIZE));
+        assert_eq!(list.get_one(Tag::KEY_SIZE).unwrap(), None);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements a deterministic test mode, which makes flaky tests reproducible. The
+//! helpers of this crate draw random numbers, e.g., for key material, grant ids and the names of
+//! temporary directories, and read the time, e.g., for auth tokens, through this module. While a
+//! test has deterministic mode enabled, the random numbers come from a process-wide generator
+//! seeded by the test, and the clocks are mocked: they stand still unless the test advances
+//! them.
+//!
+//! The seed is taken from the environment variable `KEYSTORE_TEST_SEED` if it is set, and is
+//! random otherwise. It is printed when the test fails, so that the failure can be reproduced
+//! by running the test again with the variable set to the printed seed.
+//!
+//! Deterministic tests of a process run one at a time. Tests that run concurrently without
+//! deterministic mode still draw from the seeded generator while it is enabled, so a test is
+//! only fully reproducible when it runs alone, e.g., with `--test-threads=1`.
+//!
+//! ## Example:
+//!
+//! ```
+//! let determinism = determinism::enable();
+//! let auth = UserAuth::new(user_id, b"password", 1234);
+//! auth.unlock_with_password()?;
+//! determinism.advance(Duration::from_secs(60));
+//! ```
+
+use crate::operation_workload::WorkloadGenerator;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// The environment variable that holds the seed of deterministic tests.
+pub const SEED_ENV: &str = "KEYSTORE_TEST_SEED";
+
+/// The initial time of the mocked CLOCK_BOOTTIME in milliseconds.
+const MOCK_BOOT_TIME_MILLIS: i64 = 1_000_000;
+
+/// The initial time of the mocked wall clock in milliseconds since the epoch,
+/// 2026-01-01T00:00:00Z.
+const MOCK_WALL_TIME_MILLIS: i64 = 1_767_225_600_000;
+
+/// The state of deterministic mode, if it is enabled.
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Held by the test that has deterministic mode enabled.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct State {
+    rng: WorkloadGenerator,
+    boot_time_millis: i64,
+    wall_time_millis: i64,
+}
+
+impl State {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: WorkloadGenerator::new(seed),
+            boot_time_millis: MOCK_BOOT_TIME_MILLIS,
+            wall_time_millis: MOCK_WALL_TIME_MILLIS,
+        }
+    }
+
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.rng.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        let millis = duration.as_millis() as i64;
+        self.boot_time_millis += millis;
+        self.wall_time_millis += millis;
+    }
+}
+
+fn state() -> MutexGuard<'static, Option<State>> {
+    // A test that fails while holding the lock must not fail all later tests.
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps deterministic mode enabled until it is dropped. Dropping it during a panic prints the
+/// seed.
+pub struct Determinism {
+    seed: u64,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Determinism {
+    /// Returns the seed of the random number generator.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Advances the mocked clocks by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        state().as_mut().expect("Deterministic mode is not enabled.").advance(duration);
+    }
+}
+
+impl Drop for Determinism {
+    fn drop(&mut self) {
+        *state() = None;
+        if std::thread::panicking() {
+            eprintln!(
+                "Deterministic test failed with seed {}; rerun with {SEED_ENV}={} to reproduce.",
+                self.seed, self.seed
+            );
+        }
+    }
+}
+
+/// Enables deterministic mode with the seed from `SEED_ENV`, or a random seed if it is not set.
+/// Waits until no other test of the process has deterministic mode enabled. Must not be called
+/// again by the test before the returned `Determinism` is dropped.
+pub fn enable() -> Determinism {
+    let seed = match std::env::var(SEED_ENV) {
+        Ok(value) => value.parse().unwrap_or_else(|e| panic!("Invalid {SEED_ENV} {value:?}: {e}")),
+        Err(_) => rand::random(),
+    };
+    enable_with_seed(seed)
+}
+
+/// Enables deterministic mode with the given seed. See `enable`.
+pub fn enable_with_seed(seed: u64) -> Determinism {
+    let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    *state() = Some(State::new(seed));
+    Determinism { seed, _serial: serial }
+}
+
+/// Returns true if deterministic mode is enabled.
+pub fn is_enabled() -> bool {
+    state().is_some()
+}
+
+/// Returns a random number, from the seeded generator in deterministic mode.
+pub fn random_u64() -> u64 {
+    match state().as_mut() {
+        Some(state) => state.rng.next_u64(),
+        None => rand::random(),
+    }
+}
+
+/// Fills `buf` with random bytes, from the seeded generator in deterministic mode.
+pub fn fill_bytes(buf: &mut [u8]) {
+    match state().as_mut() {
+        Some(state) => state.fill_bytes(buf),
+        None => openssl::rand::rand_bytes(buf).expect("Failed to generate random bytes."),
+    }
+}
+
+/// Returns the current time of CLOCK_BOOTTIME in milliseconds, which is the clock of the
+/// timestamps of auth tokens, or the mocked time in deterministic mode.
+#[allow(clippy::unnecessary_cast)]
+pub fn boot_time_millis() -> i64 {
+    if let Some(state) = state().as_ref() {
+        return state.boot_time_millis;
+    }
+    let mut current_time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: The pointer is valid because it comes from a reference, and clock_gettime doesn't
+    // retain it beyond the call.
+    unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut current_time) };
+    current_time.tv_sec as i64 * 1000 + (current_time.tv_nsec as i64 / 1_000_000)
+}
+
+/// Returns the current wall clock time in milliseconds since the epoch, or the mocked time in
+/// deterministic mode.
+pub fn now_millis_epoch() -> i64 {
+    match state().as_ref() {
+        Some(state) => state.wall_time_millis,
+        None => {
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as i64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_state() {
+        let mut a = State::new(42);
+        let mut b = State::new(42);
+        let (mut buf_a, mut buf_b) = ([0u8; 13], [0u8; 13]);
+        a.fill_bytes(&mut buf_a);
+        b.fill_bytes(&mut buf_b);
+        assert_eq!(buf_a, buf_b);
+        assert_eq!(a.rng.next_u64(), b.rng.next_u64());
+        assert_ne!(State::new(43).rng.next_u64(), State::new(42).rng.next_u64());
+
+        a.advance(Duration::from_millis(1500));
+        assert_eq!(a.boot_time_millis, MOCK_BOOT_TIME_MILLIS + 1500);
+        assert_eq!(a.wall_time_millis, MOCK_WALL_TIME_MILLIS + 1500);
+    }
+
+    #[test]
+    fn test_enable() {
+        {
+            let determinism = enable_with_seed(7);
+            assert!(is_enabled());
+            assert_eq!(determinism.seed(), 7);
+            assert_eq!(boot_time_millis(), MOCK_BOOT_TIME_MILLIS);
+            assert_eq!(now_millis_epoch(), MOCK_WALL_TIME_MILLIS);
+            determinism.advance(Duration::from_secs(1));
+            assert_eq!(boot_time_millis(), MOCK_BOOT_TIME_MILLIS + 1000);
+        }
+        assert!(!is_enabled());
+        let _determinism = enable_with_seed(8);
+        assert_eq!(boot_time_millis(), MOCK_BOOT_TIME_MILLIS);
+    }
+}
@@ -0,0 +1,464 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements helpers to decode and validate DICE certificate chains, which devices
+//! that rely on remote key provisioning use instead of X.509 certificates to attest to their
+//! boot stages. The format is defined by `DiceCertChain` in
+//! `hardware/interfaces/security/rkp/aidl/android/hardware/security/keymint/generateCertificateRequestV2.cddl`:
+//! the root public key (UDS_Pub) as COSE_Key followed by one COSE_Sign1 per boot stage, each
+//! signed by the key of the previous stage. The last entry certifies the key of the KeyMint
+//! instance, which signs the data returned by the remotely provisioned component.
+
+use crate::key_generations::Error;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sign::Verifier;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// COSE_Key label of the key type.
+const COSE_KEY_KTY: i128 = 1;
+/// COSE_Key label of the algorithm.
+const COSE_KEY_ALG: i128 = 3;
+/// COSE_Key label of the curve.
+const COSE_KEY_CRV: i128 = -1;
+/// COSE_Key label of the x coordinate, or the public key of an OKP key.
+const COSE_KEY_X: i128 = -2;
+/// COSE_Key label of the y coordinate.
+const COSE_KEY_Y: i128 = -3;
+
+/// COSE header label of the algorithm.
+const COSE_HEADER_ALG: i128 = 1;
+
+const KTY_OKP: i128 = 1;
+const KTY_EC2: i128 = 2;
+const CRV_P256: i128 = 1;
+const CRV_P384: i128 = 2;
+const CRV_ED25519: i128 = 6;
+const ALG_EDDSA: i128 = -8;
+const ALG_ES256: i128 = -7;
+const ALG_ES384: i128 = -35;
+
+/// CWT claim of the issuer.
+const CWT_ISSUER: i128 = 1;
+/// CWT claim of the subject.
+const CWT_SUBJECT: i128 = 2;
+/// DICE claim of the code hash.
+const DICE_CODE_HASH: i128 = -4670545;
+/// DICE claim of the configuration descriptor.
+const DICE_CONFIG_DESC: i128 = -4670548;
+/// DICE claim of the authority hash.
+const DICE_AUTHORITY_HASH: i128 = -4670549;
+/// DICE claim of the mode.
+const DICE_MODE: i128 = -4670551;
+/// DICE claim of the subject public key.
+const DICE_SUBJECT_PUBLIC_KEY: i128 = -4670552;
+/// DICE claim of the key usage.
+const DICE_KEY_USAGE: i128 = -4670553;
+
+/// The DICE mode of a boot stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiceMode {
+    /// The mode has not been configured.
+    NotConfigured,
+    /// The device is in normal operation.
+    Normal,
+    /// The device is debuggable.
+    Debug,
+    /// The device is in a recovery or maintenance mode.
+    Recovery,
+}
+
+/// A public key of a DICE certificate chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DicePublicKey {
+    /// An Ed25519 public key.
+    Ed25519(Vec<u8>),
+    /// An ECDSA public key on P-256, with the affine coordinates of the point.
+    P256 { x: Vec<u8>, y: Vec<u8> },
+    /// An ECDSA public key on P-384, with the affine coordinates of the point.
+    P384 { x: Vec<u8>, y: Vec<u8> },
+}
+
+/// One boot stage of a DICE certificate chain.
+#[derive(Debug, Clone)]
+pub struct DiceChainEntry {
+    /// The issuer, which must be the subject of the previous entry.
+    pub issuer: String,
+    /// The subject.
+    pub subject: String,
+    /// The key of the boot stage.
+    pub subject_public_key: DicePublicKey,
+    /// The DICE mode, if present.
+    pub mode: Option<DiceMode>,
+    /// The code hash, if present.
+    pub code_hash: Option<Vec<u8>>,
+    /// The authority hash, if present.
+    pub authority_hash: Option<Vec<u8>>,
+    /// The encoded configuration descriptor, if present.
+    pub config_desc: Option<Vec<u8>>,
+    /// All claims of the entry, including the ones above.
+    pub claims: BTreeMap<i128, Value>,
+}
+
+/// A decoded DICE certificate chain.
+#[derive(Debug, Clone)]
+pub struct DiceChain {
+    /// The root public key (UDS_Pub).
+    pub root_public_key: DicePublicKey,
+    /// The boot stages, starting with the one certified by the root public key.
+    pub entries: Vec<DiceChainEntry>,
+}
+
+impl DiceChain {
+    /// Returns the key of the last boot stage, which belongs to KeyMint.
+    pub fn leaf_public_key(&self) -> &DicePublicKey {
+        self.entries.last().map_or(&self.root_public_key, |e| &e.subject_public_key)
+    }
+
+    /// Returns true if any boot stage of the chain is in debug mode.
+    pub fn is_debug(&self) -> bool {
+        self.entries.iter().any(|e| e.mode == Some(DiceMode::Debug))
+    }
+
+    /// Verifies the COSE_Sign1 `sign1` with the key of the last boot stage and returns its
+    /// payload. Use this to check that data, e.g., the `SignedData` of a certificate request,
+    /// was signed by the KeyMint instance the chain certifies.
+    pub fn verify_signed_by_leaf(&self, sign1: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let sign1: Value =
+            serde_cbor::from_slice(sign1).map_err(|_| Error::DiceChainParseFailed)?;
+        verify_sign1(&sign1, self.leaf_public_key(), aad)
+    }
+}
+
+fn as_map(value: &Value) -> Result<&BTreeMap<Value, Value>, Error> {
+    match value {
+        Value::Map(m) => Ok(m),
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn as_bytes(value: &Value) -> Result<&[u8], Error> {
+    match value {
+        Value::Bytes(b) => Ok(b),
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn as_int(value: &Value) -> Result<i128, Error> {
+    match value {
+        Value::Integer(i) => Ok(*i),
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn as_text(value: &Value) -> Result<String, Error> {
+    match value {
+        Value::Text(t) => Ok(t.clone()),
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn get(map: &BTreeMap<Value, Value>, label: i128) -> Option<&Value> {
+    map.get(&Value::Integer(label))
+}
+
+fn get_required(map: &BTreeMap<Value, Value>, label: i128) -> Result<&Value, Error> {
+    get(map, label).ok_or(Error::DiceChainParseFailed)
+}
+
+fn parse_public_key(value: &Value) -> Result<DicePublicKey, Error> {
+    let key = as_map(value)?;
+    let kty = as_int(get_required(key, COSE_KEY_KTY)?)?;
+    let crv = as_int(get_required(key, COSE_KEY_CRV)?)?;
+    let x = as_bytes(get_required(key, COSE_KEY_X)?)?.to_vec();
+    let alg = get(key, COSE_KEY_ALG).map(as_int).transpose()?;
+    match (kty, crv, alg) {
+        (KTY_OKP, CRV_ED25519, None | Some(ALG_EDDSA)) => Ok(DicePublicKey::Ed25519(x)),
+        (KTY_EC2, CRV_P256, None | Some(ALG_ES256)) => {
+            Ok(DicePublicKey::P256 { x, y: as_bytes(get_required(key, COSE_KEY_Y)?)?.to_vec() })
+        }
+        (KTY_EC2, CRV_P384, None | Some(ALG_ES384)) => {
+            Ok(DicePublicKey::P384 { x, y: as_bytes(get_required(key, COSE_KEY_Y)?)?.to_vec() })
+        }
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn parse_mode(mode: &[u8]) -> Result<DiceMode, Error> {
+    match mode {
+        [0] => Ok(DiceMode::NotConfigured),
+        [1] => Ok(DiceMode::Normal),
+        [2] => Ok(DiceMode::Debug),
+        [3] => Ok(DiceMode::Recovery),
+        _ => Err(Error::DiceChainParseFailed),
+    }
+}
+
+fn ec_public_key(nid: Nid, x: &[u8], y: &[u8]) -> Result<PKey<Public>, Error> {
+    let group = EcGroup::from_curve_name(nid).map_err(|_| Error::DiceChainParseFailed)?;
+    let x = BigNum::from_slice(x).map_err(|_| Error::DiceChainParseFailed)?;
+    let y = BigNum::from_slice(y).map_err(|_| Error::DiceChainParseFailed)?;
+    let ec_key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+        .map_err(|_| Error::DiceChainParseFailed)?;
+    PKey::from_ec_key(ec_key).map_err(|_| Error::DiceChainParseFailed)
+}
+
+/// Verifies a COSE signature, which is the concatenation of r and s for ECDSA.
+fn verify_signature(key: &DicePublicKey, data: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let verified = match key {
+        DicePublicKey::Ed25519(raw) => {
+            let pkey = PKey::public_key_from_raw_bytes(raw, Id::ED25519)
+                .map_err(|_| Error::DiceChainParseFailed)?;
+            let mut verifier =
+                Verifier::new_without_digest(&pkey).map_err(|_| Error::DiceChainParseFailed)?;
+            verifier.verify_oneshot(signature, data)
+        }
+        DicePublicKey::P256 { x, y } | DicePublicKey::P384 { x, y } => {
+            let (nid, digest) = match key {
+                DicePublicKey::P256 { .. } => (Nid::X9_62_PRIME256V1, MessageDigest::sha256()),
+                _ => (Nid::SECP384R1, MessageDigest::sha384()),
+            };
+            if signature.len() != 2 * x.len() {
+                return Err(Error::DiceChainValidationFailed);
+            }
+            let (r, s) = signature.split_at(x.len());
+            let der_signature = BigNum::from_slice(r)
+                .and_then(|r| BigNum::from_slice(s).map(|s| (r, s)))
+                .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                .and_then(|sig| sig.to_der())
+                .map_err(|_| Error::DiceChainParseFailed)?;
+            let pkey = ec_public_key(nid, x, y)?;
+            let mut verifier =
+                Verifier::new(digest, &pkey).map_err(|_| Error::DiceChainParseFailed)?;
+            verifier.update(data).and_then(|_| verifier.verify(&der_signature))
+        }
+    };
+    match verified {
+        Ok(true) => Ok(()),
+        _ => Err(Error::DiceChainValidationFailed),
+    }
+}
+
+/// Verifies the untagged COSE_Sign1 `sign1` with `key` and returns its payload.
+fn verify_sign1(sign1: &Value, key: &DicePublicKey, aad: &[u8]) -> Result<Vec<u8>, Error> {
+    let Value::Array(items) = sign1 else {
+        return Err(Error::DiceChainParseFailed);
+    };
+    let [protected, _unprotected, payload, signature] = items.as_slice() else {
+        return Err(Error::DiceChainParseFailed);
+    };
+    let protected = as_bytes(protected)?;
+    let payload = as_bytes(payload)?;
+    let headers: Value =
+        serde_cbor::from_slice(protected).map_err(|_| Error::DiceChainParseFailed)?;
+    let alg = as_int(get_required(as_map(&headers)?, COSE_HEADER_ALG)?)?;
+    match (key, alg) {
+        (DicePublicKey::Ed25519(_), ALG_EDDSA)
+        | (DicePublicKey::P256 { .. }, ALG_ES256)
+        | (DicePublicKey::P384 { .. }, ALG_ES384) => {}
+        _ => return Err(Error::DiceChainValidationFailed),
+    }
+    let sig_structure = serde_cbor::to_vec(&Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(aad.to_vec()),
+        Value::Bytes(payload.to_vec()),
+    ]))
+    .map_err(|_| Error::DiceChainParseFailed)?;
+    verify_signature(key, &sig_structure, as_bytes(signature)?)?;
+    Ok(payload.to_vec())
+}
+
+fn parse_entry(payload: &[u8]) -> Result<DiceChainEntry, Error> {
+    let claims: Value = serde_cbor::from_slice(payload).map_err(|_| Error::DiceChainParseFailed)?;
+    let claims_map = as_map(&claims)?;
+    let subject_public_key: Value =
+        serde_cbor::from_slice(as_bytes(get_required(claims_map, DICE_SUBJECT_PUBLIC_KEY)?)?)
+            .map_err(|_| Error::DiceChainParseFailed)?;
+    let optional_bytes = |label| -> Result<Option<Vec<u8>>, Error> {
+        get(claims_map, label).map(|v| as_bytes(v).map(<[u8]>::to_vec)).transpose()
+    };
+    // The key of a boot stage must be usable for signing certificates (keyCertSign).
+    let key_usage = as_bytes(get_required(claims_map, DICE_KEY_USAGE)?)?;
+    if key_usage.first().map_or(true, |usage| usage & 0x20 == 0) {
+        return Err(Error::DiceChainValidationFailed);
+    }
+    Ok(DiceChainEntry {
+        issuer: as_text(get_required(claims_map, CWT_ISSUER)?)?,
+        subject: as_text(get_required(claims_map, CWT_SUBJECT)?)?,
+        subject_public_key: parse_public_key(&subject_public_key)?,
+        mode: optional_bytes(DICE_MODE)?.as_deref().map(parse_mode).transpose()?,
+        code_hash: optional_bytes(DICE_CODE_HASH)?,
+        authority_hash: optional_bytes(DICE_AUTHORITY_HASH)?,
+        config_desc: optional_bytes(DICE_CONFIG_DESC)?,
+        claims: claims_map
+            .iter()
+            .filter_map(|(k, v)| match k {
+                Value::Integer(k) => Some((*k, v.clone())),
+                _ => None,
+            })
+            .collect(),
+    })
+}
+
+/// Decodes the encoded `DiceCertChain` and validates it: every entry must be signed by the key
+/// of the previous entry, or by the root public key for the first entry, and its issuer must be
+/// the subject of the previous entry.
+pub fn validate_dice_chain(dice_chain: &[u8]) -> Result<DiceChain, Error> {
+    let dice_chain: Value =
+        serde_cbor::from_slice(dice_chain).map_err(|_| Error::DiceChainParseFailed)?;
+    let Value::Array(items) = dice_chain else {
+        return Err(Error::DiceChainParseFailed);
+    };
+    let (root, entries) = items.split_first().ok_or(Error::DiceChainParseFailed)?;
+    let root_public_key = parse_public_key(root)?;
+
+    let mut parsed_entries: Vec<DiceChainEntry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let key = parsed_entries.last().map_or(&root_public_key, |e| &e.subject_public_key);
+        let payload = verify_sign1(entry, key, &[])?;
+        let parsed = parse_entry(&payload)?;
+        if let Some(previous) = parsed_entries.last() {
+            if parsed.issuer != previous.subject {
+                return Err(Error::DiceChainValidationFailed);
+            }
+        }
+        parsed_entries.push(parsed);
+    }
+    Ok(DiceChain { root_public_key, entries: parsed_entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+
+    fn p256_key() -> EcKey<Private> {
+        EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()
+    }
+
+    fn cose_key(key: &EcKey<Private>) -> Value {
+        let mut ctx = openssl::bn::BigNumContext::new().unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        key.public_key().affine_coordinates(key.group(), &mut x, &mut y, &mut ctx).unwrap();
+        Value::Map(BTreeMap::from([
+            (Value::Integer(COSE_KEY_KTY), Value::Integer(KTY_EC2)),
+            (Value::Integer(COSE_KEY_ALG), Value::Integer(ALG_ES256)),
+            (Value::Integer(COSE_KEY_CRV), Value::Integer(CRV_P256)),
+            (Value::Integer(COSE_KEY_X), Value::Bytes(x.to_vec_padded(32).unwrap())),
+            (Value::Integer(COSE_KEY_Y), Value::Bytes(y.to_vec_padded(32).unwrap())),
+        ]))
+    }
+
+    fn sign1(signer: &EcKey<Private>, payload: Vec<u8>) -> Value {
+        let protected = serde_cbor::to_vec(&Value::Map(BTreeMap::from([(
+            Value::Integer(COSE_HEADER_ALG),
+            Value::Integer(ALG_ES256),
+        )])))
+        .unwrap();
+        let sig_structure = serde_cbor::to_vec(&Value::Array(vec![
+            Value::Text("Signature1".to_string()),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(vec![]),
+            Value::Bytes(payload.clone()),
+        ]))
+        .unwrap();
+        let pkey = PKey::from_ec_key(signer.clone()).unwrap();
+        let mut der_signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+        let der = der_signer.sign_oneshot_to_vec(&sig_structure).unwrap();
+        let sig = EcdsaSig::from_der(&der).unwrap();
+        let signature =
+            [sig.r().to_vec_padded(32).unwrap(), sig.s().to_vec_padded(32).unwrap()].concat();
+        Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature),
+        ])
+    }
+
+    fn entry(issuer: &str, subject: &str, signer: &EcKey<Private>, key: &EcKey<Private>) -> Value {
+        let payload = serde_cbor::to_vec(&Value::Map(BTreeMap::from([
+            (Value::Integer(CWT_ISSUER), Value::Text(issuer.to_string())),
+            (Value::Integer(CWT_SUBJECT), Value::Text(subject.to_string())),
+            (
+                Value::Integer(DICE_SUBJECT_PUBLIC_KEY),
+                Value::Bytes(serde_cbor::to_vec(&cose_key(key)).unwrap()),
+            ),
+            (Value::Integer(DICE_KEY_USAGE), Value::Bytes(vec![0x20])),
+            (Value::Integer(DICE_MODE), Value::Bytes(vec![1])),
+            (Value::Integer(DICE_CODE_HASH), Value::Bytes(vec![0xaa; 32])),
+        ])))
+        .unwrap();
+        sign1(signer, payload)
+    }
+
+    #[test]
+    fn test_validate_dice_chain() {
+        let (root, stage, leaf) = (p256_key(), p256_key(), p256_key());
+        let chain = serde_cbor::to_vec(&Value::Array(vec![
+            cose_key(&root),
+            entry("root", "bootloader", &root, &stage),
+            entry("bootloader", "keymint", &stage, &leaf),
+        ]))
+        .unwrap();
+
+        let dice_chain = validate_dice_chain(&chain).unwrap();
+        assert_eq!(dice_chain.entries.len(), 2);
+        assert_eq!(dice_chain.entries[1].subject, "keymint");
+        assert_eq!(dice_chain.entries[0].mode, Some(DiceMode::Normal));
+        assert_eq!(dice_chain.entries[0].code_hash, Some(vec![0xaa; 32]));
+        assert!(!dice_chain.is_debug());
+        assert_eq!(dice_chain.leaf_public_key(), &parse_public_key(&cose_key(&leaf)).unwrap());
+
+        let signed = serde_cbor::to_vec(&sign1(&leaf, b"csr".to_vec())).unwrap();
+        assert_eq!(dice_chain.verify_signed_by_leaf(&signed, &[]).unwrap(), b"csr".to_vec());
+        let signed = serde_cbor::to_vec(&sign1(&stage, b"csr".to_vec())).unwrap();
+        assert_eq!(
+            dice_chain.verify_signed_by_leaf(&signed, &[]).unwrap_err(),
+            Error::DiceChainValidationFailed
+        );
+    }
+
+    #[test]
+    fn test_invalid_dice_chain() {
+        let (root, stage, leaf) = (p256_key(), p256_key(), p256_key());
+        // The second entry is not signed by the key of the first.
+        let chain = serde_cbor::to_vec(&Value::Array(vec![
+            cose_key(&root),
+            entry("root", "bootloader", &root, &stage),
+            entry("bootloader", "keymint", &root, &leaf),
+        ]))
+        .unwrap();
+        assert_eq!(validate_dice_chain(&chain).unwrap_err(), Error::DiceChainValidationFailed);
+
+        // The issuer of the second entry is not the subject of the first.
+        let chain = serde_cbor::to_vec(&Value::Array(vec![
+            cose_key(&root),
+            entry("root", "bootloader", &root, &stage),
+            entry("os", "keymint", &stage, &leaf),
+        ]))
+        .unwrap();
+        assert_eq!(validate_dice_chain(&chain).unwrap_err(), Error::DiceChainValidationFailed);
+
+        assert_eq!(validate_dice_chain(&[0x80]).unwrap_err(), Error::DiceChainParseFailed);
+    }
+}
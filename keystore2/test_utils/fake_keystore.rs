@@ -0,0 +1,1688 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements an in-process fake of keystore2 with a software KeyMint backend, so
+//! that tests of `SecLevel`, `key_generations` and operations can run on a Linux host without a
+//! device. It is only built with the `fake_keystore` feature, which also makes
+//! `get_keystore_service` return the fake.
+//!
+//! The fake implements `IKeystoreService`, `IKeystoreSecurityLevel` and `IKeystoreOperation`
+//! on top of openssl, with a single TEE security level. It keeps its keys in memory and behaves
+//! like keystore2 and KeyMint where tests observe it, with these limitations:
+//!   * There are no permission checks; all callers may use all keys. Grants are only checked
+//!     for the grantee UID.
+//!   * Keys that require user authentication cannot be used, as there are no auth tokens.
+//!   * `importWrappedKey` and `convertStorageKeyToEphemeral` are not implemented.
+//!   * Operations buffer their input, so that all output is returned by `finish`.
+//!   * Attestations are signed by a fake root that is generated on first use.
+//!   * Asymmetric keys are generated by openssl and stay random in deterministic mode, see
+//!     `determinism`.
+
+use crate::attestation::{get_os_patchlevel, get_os_version, get_vendor_patchlevel};
+use crate::der;
+use crate::determinism;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
+    ErrorCode::ErrorCode, KeyOrigin::KeyOrigin, KeyParameter::KeyParameter,
+    KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, PaddingMode::PaddingMode,
+    SecurityLevel::SecurityLevel, Tag::Tag,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    AuthenticatorSpec::AuthenticatorSpec,
+    Authorization::Authorization,
+    CreateOperationResponse::CreateOperationResponse,
+    Domain::Domain,
+    EphemeralStorageKeyResponse::EphemeralStorageKeyResponse,
+    IKeystoreOperation::{BnKeystoreOperation, IKeystoreOperation},
+    IKeystoreSecurityLevel::{BnKeystoreSecurityLevel, IKeystoreSecurityLevel},
+    IKeystoreService::{BnKeystoreService, IKeystoreService},
+    KeyDescriptor::KeyDescriptor,
+    KeyEntryResponse::KeyEntryResponse,
+    KeyMetadata::KeyMetadata,
+    KeyParameters::KeyParameters,
+    ResponseCode::ResponseCode,
+};
+use binder::{BinderFeatures, Status, Strong, ThreadState};
+use openssl::bn::BigNum;
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::encrypt::Decrypter;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::{RsaPssSaltlen, Signer};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher, Crypter, Mode};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// The IKeyMintDevice version that the fake reports for its TEE security level.
+pub const KEYMINT_VERSION: i32 = 3;
+
+/// The maximum length of an attestation challenge.
+const MAX_CHALLENGE_LENGTH: usize = 128;
+
+static FAKE_KEYSTORE: LazyLock<Strong<dyn IKeystoreService>> = LazyLock::new(|| {
+    let store = Arc::new(Mutex::new(Store::default()));
+    let tee = BnKeystoreSecurityLevel::new_binder(
+        FakeSecurityLevel { store: store.clone() },
+        BinderFeatures::default(),
+    );
+    BnKeystoreService::new_binder(FakeKeystore { store, tee }, BinderFeatures::default())
+});
+
+/// Returns the fake keystore service of this process. All callers share its keys.
+pub fn get_keystore_service() -> Strong<dyn IKeystoreService> {
+    FAKE_KEYSTORE.clone()
+}
+
+fn km_error(e: ErrorCode) -> Status {
+    Status::new_service_specific_error(e.0, None)
+}
+
+fn rc_error(rc: ResponseCode) -> Status {
+    Status::new_service_specific_error(rc.0, None)
+}
+
+fn caller_uid() -> i64 {
+    ThreadState::get_calling_uid().into()
+}
+
+fn now_ms() -> i64 {
+    determinism::now_millis_epoch()
+}
+
+fn values(params: &[KeyParameter], tag: Tag) -> impl Iterator<Item = &KeyParameterValue> {
+    params.iter().filter(move |kp| kp.tag == tag).map(|kp| &kp.value)
+}
+
+fn has_tag(params: &[KeyParameter], tag: Tag) -> bool {
+    values(params, tag).next().is_some()
+}
+
+fn integer(params: &[KeyParameter], tag: Tag) -> Option<i32> {
+    values(params, tag).find_map(|v| match v {
+        KeyParameterValue::Integer(i) => Some(*i),
+        _ => None,
+    })
+}
+
+fn long_integer(params: &[KeyParameter], tag: Tag) -> Option<i64> {
+    values(params, tag).find_map(|v| match v {
+        KeyParameterValue::LongInteger(i) | KeyParameterValue::DateTime(i) => Some(*i),
+        _ => None,
+    })
+}
+
+fn blob(params: &[KeyParameter], tag: Tag) -> Option<&[u8]> {
+    values(params, tag).find_map(|v| match v {
+        KeyParameterValue::Blob(b) => Some(b.as_slice()),
+        _ => None,
+    })
+}
+
+fn algorithm(params: &[KeyParameter]) -> Option<Algorithm> {
+    values(params, Tag::ALGORITHM).find_map(|v| match v {
+        KeyParameterValue::Algorithm(a) => Some(*a),
+        _ => None,
+    })
+}
+
+fn purposes(params: &[KeyParameter]) -> Vec<KeyPurpose> {
+    values(params, Tag::PURPOSE)
+        .filter_map(|v| match v {
+            KeyParameterValue::KeyPurpose(p) => Some(*p),
+            _ => None,
+        })
+        .collect()
+}
+
+fn digests(params: &[KeyParameter], tag: Tag) -> Vec<Digest> {
+    values(params, tag)
+        .filter_map(|v| match v {
+            KeyParameterValue::Digest(d) => Some(*d),
+            _ => None,
+        })
+        .collect()
+}
+
+fn padding_modes(params: &[KeyParameter]) -> Vec<PaddingMode> {
+    values(params, Tag::PADDING)
+        .filter_map(|v| match v {
+            KeyParameterValue::PaddingMode(p) => Some(*p),
+            _ => None,
+        })
+        .collect()
+}
+
+fn block_modes(params: &[KeyParameter]) -> Vec<BlockMode> {
+    values(params, Tag::BLOCK_MODE)
+        .filter_map(|v| match v {
+            KeyParameterValue::BlockMode(b) => Some(*b),
+            _ => None,
+        })
+        .collect()
+}
+
+fn ec_curve(params: &[KeyParameter]) -> Option<EcCurve> {
+    values(params, Tag::EC_CURVE).find_map(|v| match v {
+        KeyParameterValue::EcCurve(c) => Some(*c),
+        _ => None,
+    })
+}
+
+/// Returns the single value of a tag of the operation parameters that must be authorized by the
+/// same tag of the key characteristics.
+fn authorized<T: PartialEq + Copy>(
+    requested: Vec<T>,
+    authorized: Vec<T>,
+    missing: ErrorCode,
+    incompatible: ErrorCode,
+) -> binder::Result<T> {
+    let value = *requested.first().ok_or_else(|| km_error(missing))?;
+    if authorized.contains(&value) {
+        Ok(value)
+    } else {
+        Err(km_error(incompatible))
+    }
+}
+
+fn message_digest(digest: Digest) -> binder::Result<Option<MessageDigest>> {
+    match digest {
+        Digest::NONE => Ok(None),
+        Digest::MD5 => Ok(Some(MessageDigest::md5())),
+        Digest::SHA1 => Ok(Some(MessageDigest::sha1())),
+        Digest::SHA_2_224 => Ok(Some(MessageDigest::sha224())),
+        Digest::SHA_2_256 => Ok(Some(MessageDigest::sha256())),
+        Digest::SHA_2_384 => Ok(Some(MessageDigest::sha384())),
+        Digest::SHA_2_512 => Ok(Some(MessageDigest::sha512())),
+        _ => Err(km_error(ErrorCode::UNSUPPORTED_DIGEST)),
+    }
+}
+
+/// Returns the block size of the given digest in bytes, as needed for HMAC.
+fn digest_block_size(digest: Digest) -> usize {
+    match digest {
+        Digest::SHA_2_384 | Digest::SHA_2_512 => 128,
+        _ => 64,
+    }
+}
+
+fn hmac(digest: Digest, key: &[u8], data: &[u8]) -> binder::Result<Vec<u8>> {
+    let md = message_digest(digest)?.ok_or_else(|| km_error(ErrorCode::UNSUPPORTED_DIGEST))?;
+    let mut block = if key.len() > digest_block_size(digest) {
+        hash(md, key).map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))?.to_vec()
+    } else {
+        key.to_vec()
+    };
+    block.resize(digest_block_size(digest), 0);
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = hash(md, &[pad(0x36), data.to_vec()].concat())
+        .map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))?;
+    let outer = hash(md, &[pad(0x5c), inner.to_vec()].concat())
+        .map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))?;
+    Ok(outer.to_vec())
+}
+
+/// Returns true if `tag` is reported in the key characteristics. Tags that only parameterize
+/// key generation, certificates or operations are not.
+fn is_characteristic(tag: Tag) -> bool {
+    !matches!(
+        tag,
+        Tag::INVALID
+            | Tag::APPLICATION_ID
+            | Tag::APPLICATION_DATA
+            | Tag::ASSOCIATED_DATA
+            | Tag::ATTESTATION_APPLICATION_ID
+            | Tag::ATTESTATION_CHALLENGE
+            | Tag::ATTESTATION_ID_BRAND
+            | Tag::ATTESTATION_ID_DEVICE
+            | Tag::ATTESTATION_ID_IMEI
+            | Tag::ATTESTATION_ID_MANUFACTURER
+            | Tag::ATTESTATION_ID_MEID
+            | Tag::ATTESTATION_ID_MODEL
+            | Tag::ATTESTATION_ID_PRODUCT
+            | Tag::ATTESTATION_ID_SECOND_IMEI
+            | Tag::ATTESTATION_ID_SERIAL
+            | Tag::CERTIFICATE_NOT_AFTER
+            | Tag::CERTIFICATE_NOT_BEFORE
+            | Tag::CERTIFICATE_SERIAL
+            | Tag::CERTIFICATE_SUBJECT
+            | Tag::CREATION_DATETIME
+            | Tag::DEVICE_UNIQUE_ATTESTATION
+            | Tag::INCLUDE_UNIQUE_ID
+            | Tag::MAC_LENGTH
+            | Tag::NONCE
+            | Tag::RESET_SINCE_ID_ROTATION
+            | Tag::USER_ID
+    )
+}
+
+/// Returns the security level at which keystore2 and KeyMint report a characteristic.
+fn enforcing_level(kp: &KeyParameter) -> SecurityLevel {
+    match kp.tag {
+        Tag::ACTIVE_DATETIME
+        | Tag::ORIGINATION_EXPIRE_DATETIME
+        | Tag::USAGE_EXPIRE_DATETIME
+        | Tag::CREATION_DATETIME
+        | Tag::USER_ID => SecurityLevel::KEYSTORE,
+        // KeyMint only enforces single use keys, keystore2 enforces all other usage counts.
+        Tag::USAGE_COUNT_LIMIT if kp.value != KeyParameterValue::Integer(1) => {
+            SecurityLevel::KEYSTORE
+        }
+        _ => SecurityLevel::TRUSTED_ENVIRONMENT,
+    }
+}
+
+enum Material {
+    Asymmetric(PKey<Private>),
+    Symmetric(Vec<u8>),
+}
+
+struct KeyEntry {
+    /// The key material, or None for a certificate-only entry.
+    material: Option<Material>,
+    /// The key characteristics, without their security levels.
+    characteristics: Vec<KeyParameter>,
+    /// APPLICATION_ID and APPLICATION_DATA, which must be passed to use the key.
+    app_params: Vec<KeyParameter>,
+    /// The DER-encoded subject of the certificate.
+    subject: Vec<u8>,
+    certificate: Option<Vec<u8>>,
+    certificate_chain: Option<Vec<u8>>,
+    modification_time_ms: i64,
+    /// The number of finished operations.
+    uses: i32,
+    /// The number of started operations.
+    begins: i32,
+}
+
+impl KeyEntry {
+    fn authorizations(&self) -> Vec<Authorization> {
+        self.characteristics
+            .iter()
+            .map(|kp| Authorization {
+                securityLevel: enforcing_level(kp),
+                keyParameter: kp.clone(),
+            })
+            .collect()
+    }
+}
+
+struct Grant {
+    key_id: i64,
+    grantee: i64,
+}
+
+#[derive(Default)]
+struct Store {
+    next_id: i64,
+    keys: HashMap<i64, KeyEntry>,
+    /// The key ids by domain, namespace and alias.
+    aliases: BTreeMap<(i32, i64, String), i64>,
+    grants: HashMap<i64, Grant>,
+}
+
+/// Returns the domain and namespace of the alias of `key`. Keys of Domain::APP always belong to
+/// the namespace of the caller.
+fn alias_key(key: &KeyDescriptor) -> binder::Result<(i32, i64, String)> {
+    let nspace = match key.domain {
+        Domain::APP => caller_uid(),
+        Domain::SELINUX => key.nspace,
+        _ => return Err(rc_error(ResponseCode::INVALID_ARGUMENT)),
+    };
+    let alias = key.alias.clone().ok_or_else(|| rc_error(ResponseCode::INVALID_ARGUMENT))?;
+    Ok((key.domain.0, nspace, alias))
+}
+
+fn list_namespace(domain: Domain, nspace: i64) -> binder::Result<i64> {
+    match domain {
+        Domain::APP => Ok(caller_uid()),
+        Domain::SELINUX => Ok(nspace),
+        _ => Err(rc_error(ResponseCode::INVALID_ARGUMENT)),
+    }
+}
+
+impl Store {
+    fn resolve(&self, key: &KeyDescriptor) -> binder::Result<i64> {
+        let id = match key.domain {
+            Domain::APP | Domain::SELINUX => self.aliases.get(&alias_key(key)?).copied(),
+            Domain::KEY_ID => Some(key.nspace),
+            Domain::GRANT => self
+                .grants
+                .get(&key.nspace)
+                .filter(|grant| grant.grantee == caller_uid())
+                .map(|grant| grant.key_id),
+            Domain::BLOB => {
+                let blob =
+                    key.blob.as_deref().ok_or_else(|| km_error(ErrorCode::INVALID_KEY_BLOB))?;
+                let id = blob.try_into().map_err(|_| km_error(ErrorCode::INVALID_KEY_BLOB))?;
+                Some(i64::from_le_bytes(id))
+            }
+            _ => return Err(rc_error(ResponseCode::INVALID_ARGUMENT)),
+        };
+        id.filter(|id| self.keys.contains_key(id))
+            .ok_or_else(|| rc_error(ResponseCode::KEY_NOT_FOUND))
+    }
+
+    fn get(&self, key: &KeyDescriptor) -> binder::Result<(i64, &KeyEntry)> {
+        let id = self.resolve(key)?;
+        Ok((id, &self.keys[&id]))
+    }
+
+    /// Stores `entry` under `key`, replacing any key with the same alias, and returns the
+    /// descriptor that refers to the new key.
+    fn insert(&mut self, key: &KeyDescriptor, entry: KeyEntry) -> binder::Result<KeyDescriptor> {
+        let alias = match key.domain {
+            Domain::BLOB => None,
+            _ => Some(alias_key(key)?),
+        };
+        self.next_id += 1;
+        let id = self.next_id;
+        self.keys.insert(id, entry);
+        match alias {
+            Some(alias) => {
+                if let Some(old_id) = self.aliases.insert(alias, id) {
+                    self.remove(old_id);
+                }
+                Ok(KeyDescriptor { domain: Domain::KEY_ID, nspace: id, alias: None, blob: None })
+            }
+            None => Ok(KeyDescriptor {
+                domain: Domain::BLOB,
+                nspace: 0,
+                alias: None,
+                blob: Some(id.to_le_bytes().to_vec()),
+            }),
+        }
+    }
+
+    fn remove(&mut self, id: i64) {
+        self.keys.remove(&id);
+        self.aliases.retain(|_, key_id| *key_id != id);
+        self.grants.retain(|_, grant| grant.key_id != id);
+    }
+
+    fn metadata(&self, id: i64, key: KeyDescriptor) -> KeyMetadata {
+        let entry = &self.keys[&id];
+        KeyMetadata {
+            key,
+            keySecurityLevel: if entry.material.is_some() {
+                SecurityLevel::TRUSTED_ENVIRONMENT
+            } else {
+                SecurityLevel::SOFTWARE
+            },
+            authorizations: entry.authorizations(),
+            certificate: entry.certificate.clone(),
+            certificateChain: entry.certificate_chain.clone(),
+            modificationTimeMs: entry.modification_time_ms,
+        }
+    }
+
+    fn list(
+        &self,
+        domain: Domain,
+        nspace: i64,
+        start_past_alias: Option<&str>,
+    ) -> binder::Result<Vec<KeyDescriptor>> {
+        let nspace = list_namespace(domain, nspace)?;
+        Ok(self
+            .aliases
+            .keys()
+            .filter(|(d, n, alias)| {
+                *d == domain.0
+                    && *n == nspace
+                    && start_past_alias.is_none_or(|s| alias.as_str() > s)
+            })
+            .map(|(_, _, alias)| KeyDescriptor {
+                domain,
+                nspace,
+                alias: Some(alias.clone()),
+                blob: None,
+            })
+            .collect())
+    }
+}
+
+struct FakeKeystore {
+    store: Arc<Mutex<Store>>,
+    tee: Strong<dyn IKeystoreSecurityLevel>,
+}
+
+impl binder::Interface for FakeKeystore {}
+
+impl IKeystoreService for FakeKeystore {
+    fn getSecurityLevel(
+        &self,
+        security_level: SecurityLevel,
+    ) -> binder::Result<Strong<dyn IKeystoreSecurityLevel>> {
+        match security_level {
+            SecurityLevel::TRUSTED_ENVIRONMENT => Ok(self.tee.clone()),
+            _ => Err(km_error(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)),
+        }
+    }
+
+    fn getKeyEntry(&self, key: &KeyDescriptor) -> binder::Result<KeyEntryResponse> {
+        let store = self.store.lock().unwrap();
+        let (id, entry) = store.get(key)?;
+        let key = match key.domain {
+            Domain::BLOB => key.clone(),
+            _ => KeyDescriptor { domain: Domain::KEY_ID, nspace: id, alias: None, blob: None },
+        };
+        Ok(KeyEntryResponse {
+            iSecurityLevel: entry.material.as_ref().map(|_| self.tee.clone()),
+            metadata: store.metadata(id, key),
+        })
+    }
+
+    fn updateSubcomponent(
+        &self,
+        key: &KeyDescriptor,
+        public_cert: Option<&[u8]>,
+        certificate_chain: Option<&[u8]>,
+    ) -> binder::Result<()> {
+        let mut store = self.store.lock().unwrap();
+        match store.resolve(key) {
+            Ok(id) => {
+                let entry = store.keys.get_mut(&id).unwrap();
+                entry.certificate = public_cert.map(|c| c.to_vec());
+                entry.certificate_chain = certificate_chain.map(|c| c.to_vec());
+                entry.modification_time_ms = now_ms();
+                Ok(())
+            }
+            // Like keystore2, create a certificate-only entry if only a chain is given.
+            Err(e) if public_cert.is_some() || certificate_chain.is_none() => Err(e),
+            Err(_) => {
+                let entry = KeyEntry {
+                    material: None,
+                    characteristics: vec![],
+                    app_params: vec![],
+                    subject: vec![],
+                    certificate: None,
+                    certificate_chain: certificate_chain.map(|c| c.to_vec()),
+                    modification_time_ms: now_ms(),
+                    uses: 0,
+                    begins: 0,
+                };
+                store.insert(key, entry).map(|_| ())
+            }
+        }
+    }
+
+    fn listEntries(&self, domain: Domain, nspace: i64) -> binder::Result<Vec<KeyDescriptor>> {
+        self.store.lock().unwrap().list(domain, nspace, None)
+    }
+
+    fn deleteKey(&self, key: &KeyDescriptor) -> binder::Result<()> {
+        let mut store = self.store.lock().unwrap();
+        let id = store.resolve(key)?;
+        store.remove(id);
+        Ok(())
+    }
+
+    fn grant(
+        &self,
+        key: &KeyDescriptor,
+        grantee_uid: i32,
+        _access_vector: i32,
+    ) -> binder::Result<KeyDescriptor> {
+        let mut store = self.store.lock().unwrap();
+        let key_id = store.resolve(key)?;
+        let grantee = grantee_uid.into();
+        let existing =
+            store.grants.iter().find(|(_, g)| g.key_id == key_id && g.grantee == grantee);
+        let grant_id = match existing {
+            Some((grant_id, _)) => *grant_id,
+            None => {
+                let mut grant_id = 0;
+                while grant_id == 0 || store.grants.contains_key(&grant_id) {
+                    grant_id = determinism::random_u64() as i64;
+                }
+                store.grants.insert(grant_id, Grant { key_id, grantee });
+                grant_id
+            }
+        };
+        Ok(KeyDescriptor { domain: Domain::GRANT, nspace: grant_id, alias: None, blob: None })
+    }
+
+    fn ungrant(&self, key: &KeyDescriptor, grantee_uid: i32) -> binder::Result<()> {
+        let mut store = self.store.lock().unwrap();
+        let key_id = store.resolve(key)?;
+        let grantee: i64 = grantee_uid.into();
+        store.grants.retain(|_, g| g.key_id != key_id || g.grantee != grantee);
+        Ok(())
+    }
+
+    fn listEntriesBatched(
+        &self,
+        domain: Domain,
+        nspace: i64,
+        start_past_alias: Option<&str>,
+    ) -> binder::Result<Vec<KeyDescriptor>> {
+        self.store.lock().unwrap().list(domain, nspace, start_past_alias)
+    }
+
+    fn getNumberOfEntries(&self, domain: Domain, nspace: i64) -> binder::Result<i32> {
+        let count = self.store.lock().unwrap().list(domain, nspace, None)?.len();
+        Ok(count as i32)
+    }
+}
+
+/// The root of the attestation chains of the fake.
+struct FakeRoot {
+    key: PKey<Private>,
+    subject: Vec<u8>,
+    certificate: Vec<u8>,
+}
+
+static FAKE_ROOT: LazyLock<FakeRoot> = LazyLock::new(|| {
+    let key = generate_ec(Nid::X9_62_PRIME256V1).unwrap();
+    let subject = name("Fake KeyMint Root");
+    let certificate = issue_certificate(1, &subject, &key, &subject, &key, None).unwrap();
+    FakeRoot { key, subject, certificate }
+});
+
+fn generate_ec(nid: Nid) -> Result<PKey<Private>, openssl::error::ErrorStack> {
+    let group = EcGroup::from_curve_name(nid)?;
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+/// Encodes a Name with a single UTF8String common name.
+fn name(common_name: &str) -> Vec<u8> {
+    der::sequence(&[der::set_of(vec![der::sequence(&[
+        der::oid(&[2, 5, 4, 3]),
+        der::tlv(&[0x0c], common_name.as_bytes()),
+    ])])])
+}
+
+/// Returns the DER-encoded X.509 certificate of `subject_key`, signed by `issuer_key` with SHA-256,
+/// with the attestation record `record` if given.
+fn issue_certificate(
+    serial: i64,
+    subject: &[u8],
+    subject_key: &PKey<Private>,
+    issuer: &[u8],
+    issuer_key: &PKey<Private>,
+    record: Option<&[u8]>,
+) -> binder::Result<Vec<u8>> {
+    let signature_algorithm = match issuer_key.id() {
+        Id::EC => der::sequence(&[der::oid(&[1, 2, 840, 10045, 4, 3, 2])]),
+        _ => der::sequence(&[der::oid(&[1, 2, 840, 113549, 1, 1, 11]), der::null()]),
+    };
+    let utc_time = |t: &str| der::tlv(&[0x17], t.as_bytes());
+    let mut tbs_fields = vec![
+        der::explicit(0, &der::integer(2)),
+        der::integer(serial),
+        signature_algorithm.clone(),
+        issuer.to_vec(),
+        der::sequence(&[utc_time("700101000000Z"), utc_time("491231235959Z")]),
+        subject.to_vec(),
+        subject_key.public_key_to_der().map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))?,
+    ];
+    if let Some(record) = record {
+        let extension = der::sequence(&[
+            der::oid(&[1, 3, 6, 1, 4, 1, 11129, 2, 1, 17]),
+            der::octet_string(record),
+        ]);
+        tbs_fields.push(der::explicit(3, &der::sequence(&[extension])));
+    }
+    let tbs = der::sequence(&tbs_fields);
+    let signature = Signer::new(MessageDigest::sha256(), issuer_key)
+        .and_then(|mut signer| signer.sign_oneshot_to_vec(&tbs))
+        .map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))?;
+    // BIT STRING without unused bits.
+    let signature = der::tlv(&[der::TAG_BIT_STRING], &[&[0u8][..], &signature].concat());
+    Ok(der::sequence(&[tbs, signature_algorithm, signature]))
+}
+
+/// Returns the attestation record of a key with the given generation parameters and
+/// characteristics.
+fn attestation_record(
+    params: &[KeyParameter],
+    characteristics: &[KeyParameter],
+    challenge: &[u8],
+) -> binder::Result<Vec<u8>> {
+    let security_level =
+        der::tlv(&[der::TAG_ENUMERATED], &[SecurityLevel::TRUSTED_ENVIRONMENT.0 as u8]);
+    let attestation_ids = params.iter().filter(|kp| {
+        matches!(
+            kp.tag,
+            Tag::ATTESTATION_ID_BRAND
+                | Tag::ATTESTATION_ID_DEVICE
+                | Tag::ATTESTATION_ID_IMEI
+                | Tag::ATTESTATION_ID_MANUFACTURER
+                | Tag::ATTESTATION_ID_MEID
+                | Tag::ATTESTATION_ID_MODEL
+                | Tag::ATTESTATION_ID_PRODUCT
+                | Tag::ATTESTATION_ID_SECOND_IMEI
+                | Tag::ATTESTATION_ID_SERIAL
+        )
+    });
+    let (software_enforced, hardware_enforced): (Vec<KeyParameter>, Vec<KeyParameter>) =
+        characteristics
+            .iter()
+            .cloned()
+            .partition(|kp| enforcing_level(kp) == SecurityLevel::KEYSTORE);
+    let software_enforced: Vec<KeyParameter> = software_enforced
+        .into_iter()
+        .filter(|kp| kp.tag != Tag::USER_ID)
+        .chain(params.iter().filter(|kp| kp.tag == Tag::ATTESTATION_APPLICATION_ID).cloned())
+        .collect();
+    let hardware_enforced: Vec<KeyParameter> =
+        hardware_enforced.into_iter().chain(attestation_ids.cloned()).collect();
+    let encode = |list: &[KeyParameter]| {
+        der::authorization_list(list).map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))
+    };
+    let version = der::integer((KEYMINT_VERSION * 100).into());
+    Ok(der::sequence(&[
+        version.clone(),
+        security_level.clone(),
+        version,
+        security_level,
+        der::octet_string(challenge),
+        der::octet_string(&[]),
+        encode(&software_enforced)?,
+        encode(&hardware_enforced)?,
+    ]))
+}
+
+struct FakeSecurityLevel {
+    store: Arc<Mutex<Store>>,
+}
+
+impl FakeSecurityLevel {
+    /// Checks `params` and returns the key characteristics of a key with the given material.
+    fn characteristics(
+        params: &[KeyParameter],
+        material: &Material,
+        origin: KeyOrigin,
+    ) -> binder::Result<Vec<KeyParameter>> {
+        let mut characteristics: Vec<KeyParameter> =
+            params.iter().filter(|kp| is_characteristic(kp.tag)).cloned().collect();
+        let mut add = |tag: Tag, value: KeyParameterValue| {
+            if !has_tag(&characteristics, tag) {
+                characteristics.push(KeyParameter { tag, value });
+            }
+        };
+        match material {
+            Material::Asymmetric(key) if key.id() == Id::EC => {
+                let ec_key = key.ec_key().unwrap();
+                let (curve, size) = match ec_key.group().curve_name() {
+                    Some(Nid::SECP224R1) => (EcCurve::P_224, 224),
+                    Some(Nid::X9_62_PRIME256V1) => (EcCurve::P_256, 256),
+                    Some(Nid::SECP384R1) => (EcCurve::P_384, 384),
+                    Some(Nid::SECP521R1) => (EcCurve::P_521, 521),
+                    _ => return Err(km_error(ErrorCode::UNSUPPORTED_EC_CURVE)),
+                };
+                add(Tag::EC_CURVE, KeyParameterValue::EcCurve(curve));
+                add(Tag::KEY_SIZE, KeyParameterValue::Integer(size));
+            }
+            Material::Asymmetric(key) => {
+                let rsa = key.rsa().unwrap();
+                let exponent = rsa.e().to_dec_str().unwrap().parse().unwrap_or(0);
+                add(Tag::KEY_SIZE, KeyParameterValue::Integer(rsa.size() as i32 * 8));
+                add(Tag::RSA_PUBLIC_EXPONENT, KeyParameterValue::LongInteger(exponent));
+            }
+            Material::Symmetric(key) => {
+                let size = if algorithm(params) == Some(Algorithm::TRIPLE_DES) {
+                    168
+                } else {
+                    key.len() as i32 * 8
+                };
+                add(Tag::KEY_SIZE, KeyParameterValue::Integer(size));
+            }
+        }
+        add(Tag::ORIGIN, KeyParameterValue::Origin(origin));
+        add(Tag::OS_VERSION, KeyParameterValue::Integer(get_os_version() as i32));
+        add(Tag::OS_PATCHLEVEL, KeyParameterValue::Integer(get_os_patchlevel() as i32));
+        add(Tag::VENDOR_PATCHLEVEL, KeyParameterValue::Integer(get_vendor_patchlevel() as i32));
+        add(Tag::BOOT_PATCHLEVEL, KeyParameterValue::Integer(get_vendor_patchlevel() as i32));
+        add(Tag::CREATION_DATETIME, KeyParameterValue::DateTime(now_ms()));
+        let user_id = rustutils::users::multiuser_get_user_id(caller_uid() as u32);
+        add(Tag::USER_ID, KeyParameterValue::Integer(user_id as i32));
+
+        if let Some(min_mac_length) = integer(params, Tag::MIN_MAC_LENGTH) {
+            let max = if algorithm(params) == Some(Algorithm::HMAC) { 512 } else { 128 };
+            if min_mac_length % 8 != 0 || min_mac_length < 64 || min_mac_length > max {
+                return Err(km_error(ErrorCode::UNSUPPORTED_MIN_MAC_LENGTH));
+            }
+        }
+        Ok(characteristics)
+    }
+
+    fn generate_material(params: &[KeyParameter]) -> binder::Result<Material> {
+        let key_size = integer(params, Tag::KEY_SIZE);
+        let unsupported_size = || km_error(ErrorCode::UNSUPPORTED_KEY_SIZE);
+        match algorithm(params) {
+            Some(Algorithm::EC) => {
+                let nid = match (ec_curve(params), key_size) {
+                    (Some(EcCurve::P_224), _) | (None, Some(224)) => Nid::SECP224R1,
+                    (Some(EcCurve::P_256), _) | (None, Some(256)) => Nid::X9_62_PRIME256V1,
+                    (Some(EcCurve::P_384), _) | (None, Some(384)) => Nid::SECP384R1,
+                    (Some(EcCurve::P_521), _) | (None, Some(521)) => Nid::SECP521R1,
+                    (None, _) => return Err(unsupported_size()),
+                    _ => return Err(km_error(ErrorCode::UNSUPPORTED_EC_CURVE)),
+                };
+                generate_ec(nid)
+                    .map(Material::Asymmetric)
+                    .map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))
+            }
+            Some(Algorithm::RSA) => {
+                let bits = key_size.filter(|s| (512..=4096).contains(s) && s % 8 == 0);
+                let bits = bits.ok_or_else(unsupported_size)?;
+                let exponent = long_integer(params, Tag::RSA_PUBLIC_EXPONENT).unwrap_or(65537);
+                let exponent = u32::try_from(exponent)
+                    .ok()
+                    .filter(|e| *e >= 3 && e % 2 == 1)
+                    .ok_or_else(|| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                BigNum::from_u32(exponent)
+                    .and_then(|e| Rsa::generate_with_e(bits as u32, &e))
+                    .and_then(PKey::from_rsa)
+                    .map(Material::Asymmetric)
+                    .map_err(|_| km_error(ErrorCode::UNKNOWN_ERROR))
+            }
+            Some(Algorithm::AES) => match key_size {
+                Some(size @ (128 | 192 | 256)) => random_key(size as usize / 8),
+                _ => Err(unsupported_size()),
+            },
+            Some(Algorithm::TRIPLE_DES) => match key_size {
+                Some(168) => random_key(24),
+                _ => Err(unsupported_size()),
+            },
+            Some(Algorithm::HMAC) => {
+                let size = key_size.filter(|s| (64..=512).contains(s) && s % 8 == 0);
+                let size = size.ok_or_else(unsupported_size)?;
+                if !has_tag(params, Tag::MIN_MAC_LENGTH) {
+                    return Err(km_error(ErrorCode::MISSING_MIN_MAC_LENGTH));
+                }
+                if !digests(params, Tag::DIGEST).iter().any(|d| *d != Digest::NONE) {
+                    return Err(km_error(ErrorCode::UNSUPPORTED_DIGEST));
+                }
+                random_key(size as usize / 8)
+            }
+            _ => Err(km_error(ErrorCode::UNSUPPORTED_ALGORITHM)),
+        }
+    }
+
+    fn import_material(params: &[KeyParameter], key_data: &[u8]) -> binder::Result<Material> {
+        let mismatch = || km_error(ErrorCode::IMPORT_PARAMETER_MISMATCH);
+        match algorithm(params) {
+            Some(algorithm @ (Algorithm::EC | Algorithm::RSA)) => {
+                let key = PKey::private_key_from_pkcs8(key_data)
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                let expected = if algorithm == Algorithm::EC { Id::EC } else { Id::RSA };
+                if key.id() != expected {
+                    return Err(mismatch());
+                }
+                Ok(Material::Asymmetric(key))
+            }
+            Some(Algorithm::AES | Algorithm::TRIPLE_DES | Algorithm::HMAC) => {
+                let size = if algorithm(params) == Some(Algorithm::TRIPLE_DES) {
+                    (key_data.len() == 24).then_some(168)
+                } else {
+                    Some(key_data.len() as i32 * 8)
+                };
+                if integer(params, Tag::KEY_SIZE).is_some_and(|s| Some(s) != size) {
+                    return Err(mismatch());
+                }
+                Ok(Material::Symmetric(key_data.to_vec()))
+            }
+            _ => Err(km_error(ErrorCode::UNSUPPORTED_ALGORITHM)),
+        }
+    }
+
+    /// Creates the key entry for `material`, with a certificate for asymmetric keys.
+    fn new_entry(
+        store: &Store,
+        attestation_key: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        material: Material,
+        origin: KeyOrigin,
+    ) -> binder::Result<KeyEntry> {
+        let characteristics = Self::characteristics(params, &material, origin)?;
+        let subject = blob(params, Tag::CERTIFICATE_SUBJECT)
+            .map(|s| s.to_vec())
+            .unwrap_or_else(|| name("Android Keystore Key"));
+        let challenge = blob(params, Tag::ATTESTATION_CHALLENGE);
+        if challenge.is_some_and(|c| c.len() > MAX_CHALLENGE_LENGTH) {
+            return Err(km_error(ErrorCode::INVALID_INPUT_LENGTH));
+        }
+        if attestation_key.is_some() && challenge.is_none() {
+            return Err(km_error(ErrorCode::ATTESTATION_CHALLENGE_MISSING));
+        }
+
+        let mut certificate_chain = None;
+        let certificate = match &material {
+            Material::Asymmetric(key) => {
+                let serial = blob(params, Tag::CERTIFICATE_SERIAL)
+                    .and_then(|s| BigNum::from_slice(s).ok())
+                    .and_then(|s| s.to_dec_str().ok()?.parse().ok())
+                    .unwrap_or(1);
+                let cert = match (challenge, attestation_key) {
+                    (Some(challenge), Some(attestation_key)) => {
+                        let (_, attester) = store.get(attestation_key)?;
+                        let Some(Material::Asymmetric(attester_key)) = &attester.material else {
+                            return Err(km_error(ErrorCode::INCOMPATIBLE_PURPOSE));
+                        };
+                        if !purposes(&attester.characteristics).contains(&KeyPurpose::ATTEST_KEY) {
+                            return Err(km_error(ErrorCode::INCOMPATIBLE_PURPOSE));
+                        }
+                        let record = attestation_record(params, &characteristics, challenge)?;
+                        certificate_chain = Some(
+                            [attester.certificate.clone(), attester.certificate_chain.clone()]
+                                .into_iter()
+                                .flatten()
+                                .collect::<Vec<_>>()
+                                .concat(),
+                        );
+                        issue_certificate(
+                            serial,
+                            &subject,
+                            key,
+                            &attester.subject,
+                            attester_key,
+                            Some(&record),
+                        )?
+                    }
+                    (Some(challenge), None) => {
+                        let record = attestation_record(params, &characteristics, challenge)?;
+                        certificate_chain = Some(FAKE_ROOT.certificate.clone());
+                        issue_certificate(
+                            serial,
+                            &subject,
+                            key,
+                            &FAKE_ROOT.subject,
+                            &FAKE_ROOT.key,
+                            Some(&record),
+                        )?
+                    }
+                    // Keys that can sign get a self-signed certificate.
+                    _ if purposes(params).contains(&KeyPurpose::SIGN) => {
+                        issue_certificate(serial, &subject, key, &subject, key, None)?
+                    }
+                    _ => issue_certificate(
+                        serial,
+                        &subject,
+                        key,
+                        &FAKE_ROOT.subject,
+                        &FAKE_ROOT.key,
+                        None,
+                    )?,
+                };
+                Some(cert)
+            }
+            Material::Symmetric(_) => None,
+        };
+
+        Ok(KeyEntry {
+            material: Some(material),
+            characteristics,
+            app_params: params
+                .iter()
+                .filter(|kp| matches!(kp.tag, Tag::APPLICATION_ID | Tag::APPLICATION_DATA))
+                .cloned()
+                .collect(),
+            subject,
+            certificate,
+            certificate_chain,
+            modification_time_ms: now_ms(),
+            uses: 0,
+            begins: 0,
+        })
+    }
+
+    fn store_new_key(
+        &self,
+        key: &KeyDescriptor,
+        attestation_key: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        material: Material,
+        origin: KeyOrigin,
+    ) -> binder::Result<KeyMetadata> {
+        let mut store = self.store.lock().unwrap();
+        let entry = Self::new_entry(&store, attestation_key, params, material, origin)?;
+        let descriptor = store.insert(key, entry)?;
+        let id = store.resolve(&descriptor)?;
+        Ok(store.metadata(id, descriptor))
+    }
+
+    fn create_operation(
+        &self,
+        key: &KeyDescriptor,
+        params: &[KeyParameter],
+    ) -> binder::Result<CreateOperationResponse> {
+        let mut store = self.store.lock().unwrap();
+        let (key_id, entry) = store.get(key)?;
+        let material =
+            entry.material.as_ref().ok_or_else(|| rc_error(ResponseCode::KEY_NOT_FOUND))?;
+        let chars = &entry.characteristics;
+
+        let purpose =
+            *purposes(params).first().ok_or_else(|| km_error(ErrorCode::UNSUPPORTED_PURPOSE))?;
+        if !purposes(chars).contains(&purpose) {
+            return Err(km_error(ErrorCode::INCOMPATIBLE_PURPOSE));
+        }
+        let app_params: Vec<&KeyParameter> = params
+            .iter()
+            .filter(|kp| matches!(kp.tag, Tag::APPLICATION_ID | Tag::APPLICATION_DATA))
+            .collect();
+        if app_params.len() != entry.app_params.len()
+            || !entry.app_params.iter().all(|kp| app_params.contains(&kp))
+        {
+            return Err(km_error(ErrorCode::INVALID_KEY_BLOB));
+        }
+        if has_tag(chars, Tag::USER_SECURE_ID) && !has_tag(chars, Tag::NO_AUTH_REQUIRED) {
+            return Err(km_error(ErrorCode::KEY_USER_NOT_AUTHENTICATED));
+        }
+        let now = now_ms();
+        if long_integer(chars, Tag::ACTIVE_DATETIME).is_some_and(|t| now < t) {
+            return Err(km_error(ErrorCode::KEY_NOT_YET_VALID));
+        }
+        let expires = match purpose {
+            KeyPurpose::SIGN | KeyPurpose::ENCRYPT => Tag::ORIGINATION_EXPIRE_DATETIME,
+            _ => Tag::USAGE_EXPIRE_DATETIME,
+        };
+        if long_integer(chars, expires).is_some_and(|t| now > t) {
+            return Err(km_error(ErrorCode::KEY_EXPIRED));
+        }
+        if integer(chars, Tag::MAX_USES_PER_BOOT).is_some_and(|max| entry.begins >= max) {
+            return Err(km_error(ErrorCode::KEY_MAX_OPS_EXCEEDED));
+        }
+
+        let (kind, nonce) = OperationKind::new(purpose, material, chars, params)?;
+        store.keys.get_mut(&key_id).unwrap().begins += 1;
+        let operation = FakeOperation {
+            store: self.store.clone(),
+            key_id,
+            state: Mutex::new(Some(OperationState {
+                kind,
+                aad: vec![],
+                input: vec![],
+                updated: false,
+            })),
+        };
+        Ok(CreateOperationResponse {
+            iOperation: Some(BnKeystoreOperation::new_binder(operation, BinderFeatures::default())),
+            operationChallenge: None,
+            parameters: nonce.map(|nonce| KeyParameters {
+                keyParameter: vec![KeyParameter {
+                    tag: Tag::NONCE,
+                    value: KeyParameterValue::Blob(nonce),
+                }],
+            }),
+            upgradedBlob: None,
+        })
+    }
+}
+
+fn random_key(len: usize) -> binder::Result<Material> {
+    let mut key = vec![0; len];
+    determinism::fill_bytes(&mut key);
+    Ok(Material::Symmetric(key))
+}
+
+impl binder::Interface for FakeSecurityLevel {}
+
+impl IKeystoreSecurityLevel for FakeSecurityLevel {
+    fn createOperation(
+        &self,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        _forced: bool,
+    ) -> binder::Result<CreateOperationResponse> {
+        self.create_operation(key, operation_parameters)
+    }
+
+    fn generateKey(
+        &self,
+        key: &KeyDescriptor,
+        attestation_key: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        _flags: i32,
+        _entropy: &[u8],
+    ) -> binder::Result<KeyMetadata> {
+        let material = Self::generate_material(params)?;
+        self.store_new_key(key, attestation_key, params, material, KeyOrigin::GENERATED)
+    }
+
+    fn importKey(
+        &self,
+        key: &KeyDescriptor,
+        attestation_key: Option<&KeyDescriptor>,
+        params: &[KeyParameter],
+        _flags: i32,
+        key_data: &[u8],
+    ) -> binder::Result<KeyMetadata> {
+        let material = Self::import_material(params, key_data)?;
+        self.store_new_key(key, attestation_key, params, material, KeyOrigin::IMPORTED)
+    }
+
+    fn importWrappedKey(
+        &self,
+        _key: &KeyDescriptor,
+        _wrapping_key: &KeyDescriptor,
+        _masking_key: Option<&[u8]>,
+        _params: &[KeyParameter],
+        _authenticators: &[AuthenticatorSpec],
+    ) -> binder::Result<KeyMetadata> {
+        Err(km_error(ErrorCode::UNIMPLEMENTED))
+    }
+
+    fn convertStorageKeyToEphemeral(
+        &self,
+        _storage_key: &KeyDescriptor,
+    ) -> binder::Result<EphemeralStorageKeyResponse> {
+        Err(km_error(ErrorCode::UNIMPLEMENTED))
+    }
+
+    fn deleteKey(&self, key: &KeyDescriptor) -> binder::Result<()> {
+        if key.domain != Domain::BLOB {
+            return Err(rc_error(ResponseCode::INVALID_ARGUMENT));
+        }
+        let mut store = self.store.lock().unwrap();
+        let id = store.resolve(key)?;
+        store.remove(id);
+        Ok(())
+    }
+}
+
+/// The cryptographic operation of a `FakeOperation` with its parameters.
+enum OperationKind {
+    EcSign {
+        key: PKey<Private>,
+        digest: Option<MessageDigest>,
+    },
+    AgreeKey {
+        key: PKey<Private>,
+    },
+    RsaSign {
+        key: PKey<Private>,
+        digest: Option<MessageDigest>,
+        padding: PaddingMode,
+    },
+    RsaDecrypt {
+        key: PKey<Private>,
+        padding: Padding,
+        digests: Option<(MessageDigest, MessageDigest)>,
+    },
+    Cipher {
+        cipher: Cipher,
+        mode: Mode,
+        key: Vec<u8>,
+        nonce: Option<Vec<u8>>,
+        padding: bool,
+        tag_len: Option<usize>,
+    },
+    HmacSign {
+        key: Vec<u8>,
+        digest: Digest,
+        mac_len: usize,
+    },
+    HmacVerify {
+        key: Vec<u8>,
+        digest: Digest,
+        min_mac_len: usize,
+    },
+}
+
+impl OperationKind {
+    /// Checks the operation parameters `params` against the key characteristics `chars` and
+    /// returns the operation, and the nonce if it was generated by the fake.
+    fn new(
+        purpose: KeyPurpose,
+        material: &Material,
+        chars: &[KeyParameter],
+        params: &[KeyParameter],
+    ) -> binder::Result<(Self, Option<Vec<u8>>)> {
+        let digest = || {
+            authorized(
+                digests(params, Tag::DIGEST),
+                digests(chars, Tag::DIGEST),
+                ErrorCode::UNSUPPORTED_DIGEST,
+                ErrorCode::INCOMPATIBLE_DIGEST,
+            )
+        };
+        let padding = || {
+            authorized(
+                padding_modes(params),
+                padding_modes(chars),
+                ErrorCode::UNSUPPORTED_PADDING_MODE,
+                ErrorCode::INCOMPATIBLE_PADDING_MODE,
+            )
+        };
+        match (algorithm(chars), material) {
+            (Some(Algorithm::EC), Material::Asymmetric(key)) => match purpose {
+                KeyPurpose::SIGN => {
+                    let digest = message_digest(digest()?)?;
+                    Ok((Self::EcSign { key: key.clone(), digest }, None))
+                }
+                KeyPurpose::AGREE_KEY => Ok((Self::AgreeKey { key: key.clone() }, None)),
+                _ => Err(km_error(ErrorCode::UNSUPPORTED_PURPOSE)),
+            },
+            (Some(Algorithm::RSA), Material::Asymmetric(key)) => match purpose {
+                KeyPurpose::SIGN => {
+                    let padding = padding()?;
+                    let digest = message_digest(digest()?)?;
+                    match (padding, digest) {
+                        (PaddingMode::RSA_PKCS1_1_5_SIGN, _)
+                        | (PaddingMode::NONE, None)
+                        | (PaddingMode::RSA_PSS, Some(_)) => {}
+                        (PaddingMode::NONE | PaddingMode::RSA_PSS, _) => {
+                            return Err(km_error(ErrorCode::INCOMPATIBLE_DIGEST))
+                        }
+                        _ => return Err(km_error(ErrorCode::UNSUPPORTED_PADDING_MODE)),
+                    }
+                    Ok((Self::RsaSign { key: key.clone(), digest, padding }, None))
+                }
+                KeyPurpose::DECRYPT => {
+                    let (padding, digests) = match padding()? {
+                        PaddingMode::NONE => (Padding::NONE, None),
+                        PaddingMode::RSA_PKCS1_1_5_ENCRYPT => (Padding::PKCS1, None),
+                        PaddingMode::RSA_OAEP => {
+                            let digest = message_digest(digest()?)?
+                                .ok_or_else(|| km_error(ErrorCode::INCOMPATIBLE_DIGEST))?;
+                            let mgf_digest = match digests(params, Tag::RSA_OAEP_MGF_DIGEST).first()
+                            {
+                                Some(d) => message_digest(*d)?
+                                    .ok_or_else(|| km_error(ErrorCode::UNSUPPORTED_MGF_DIGEST))?,
+                                None => MessageDigest::sha1(),
+                            };
+                            (Padding::PKCS1_OAEP, Some((digest, mgf_digest)))
+                        }
+                        _ => return Err(km_error(ErrorCode::UNSUPPORTED_PADDING_MODE)),
+                    };
+                    Ok((Self::RsaDecrypt { key: key.clone(), padding, digests }, None))
+                }
+                _ => Err(km_error(ErrorCode::UNSUPPORTED_PURPOSE)),
+            },
+            (
+                Some(algorithm @ (Algorithm::AES | Algorithm::TRIPLE_DES)),
+                Material::Symmetric(key),
+            ) => {
+                let mode = match purpose {
+                    KeyPurpose::ENCRYPT => Mode::Encrypt,
+                    KeyPurpose::DECRYPT => Mode::Decrypt,
+                    _ => return Err(km_error(ErrorCode::UNSUPPORTED_PURPOSE)),
+                };
+                let block_mode = authorized(
+                    block_modes(params),
+                    block_modes(chars),
+                    ErrorCode::UNSUPPORTED_BLOCK_MODE,
+                    ErrorCode::INCOMPATIBLE_BLOCK_MODE,
+                )?;
+                let padding = match padding_modes(params).first() {
+                    None | Some(&PaddingMode::NONE) => false,
+                    Some(&PaddingMode::PKCS7)
+                        if matches!(block_mode, BlockMode::ECB | BlockMode::CBC) =>
+                    {
+                        if !padding_modes(chars).contains(&PaddingMode::PKCS7) {
+                            return Err(km_error(ErrorCode::INCOMPATIBLE_PADDING_MODE));
+                        }
+                        true
+                    }
+                    _ => return Err(km_error(ErrorCode::INCOMPATIBLE_PADDING_MODE)),
+                };
+                let cipher = cipher(algorithm, block_mode, key.len())?;
+                let tag_len = if block_mode == BlockMode::GCM {
+                    let mac_len = integer(params, Tag::MAC_LENGTH)
+                        .ok_or_else(|| km_error(ErrorCode::MISSING_MAC_LENGTH))?;
+                    if mac_len % 8 != 0 || !(96..=128).contains(&mac_len) {
+                        return Err(km_error(ErrorCode::UNSUPPORTED_MAC_LENGTH));
+                    }
+                    if integer(chars, Tag::MIN_MAC_LENGTH).is_some_and(|min| mac_len < min) {
+                        return Err(km_error(ErrorCode::INVALID_MAC_LENGTH));
+                    }
+                    Some(mac_len as usize / 8)
+                } else {
+                    None
+                };
+                let nonce_len = cipher.iv_len().unwrap_or(0);
+                let (nonce, generated) = match (blob(params, Tag::NONCE), mode) {
+                    _ if nonce_len == 0 => (None, false),
+                    (Some(_), Mode::Encrypt) if !has_tag(chars, Tag::CALLER_NONCE) => {
+                        return Err(km_error(ErrorCode::CALLER_NONCE_PROHIBITED));
+                    }
+                    (Some(nonce), _) if nonce.len() == nonce_len => (Some(nonce.to_vec()), false),
+                    (None, Mode::Encrypt) => {
+                        let mut nonce = vec![0; nonce_len];
+                        determinism::fill_bytes(&mut nonce);
+                        (Some(nonce), true)
+                    }
+                    _ => return Err(km_error(ErrorCode::INVALID_NONCE)),
+                };
+                let returned_nonce = if generated { nonce.clone() } else { None };
+                Ok((
+                    Self::Cipher { cipher, mode, key: key.clone(), nonce, padding, tag_len },
+                    returned_nonce,
+                ))
+            }
+            (Some(Algorithm::HMAC), Material::Symmetric(key)) => {
+                let digest = digest()?;
+                let min_mac_len = integer(chars, Tag::MIN_MAC_LENGTH).unwrap_or(0) as usize / 8;
+                match purpose {
+                    KeyPurpose::SIGN => {
+                        let mac_len = integer(params, Tag::MAC_LENGTH)
+                            .ok_or_else(|| km_error(ErrorCode::MISSING_MAC_LENGTH))?;
+                        let digest_len = message_digest(digest)?
+                            .ok_or_else(|| km_error(ErrorCode::INCOMPATIBLE_DIGEST))?
+                            .size();
+                        if mac_len % 8 != 0 || mac_len as usize > digest_len * 8 {
+                            return Err(km_error(ErrorCode::UNSUPPORTED_MAC_LENGTH));
+                        }
+                        if (mac_len as usize / 8) < min_mac_len {
+                            return Err(km_error(ErrorCode::INVALID_MAC_LENGTH));
+                        }
+                        Ok((
+                            Self::HmacSign {
+                                key: key.clone(),
+                                digest,
+                                mac_len: mac_len as usize / 8,
+                            },
+                            None,
+                        ))
+                    }
+                    KeyPurpose::VERIFY => {
+                        Ok((Self::HmacVerify { key: key.clone(), digest, min_mac_len }, None))
+                    }
+                    _ => Err(km_error(ErrorCode::UNSUPPORTED_PURPOSE)),
+                }
+            }
+            _ => Err(km_error(ErrorCode::UNSUPPORTED_ALGORITHM)),
+        }
+    }
+
+    /// Performs the operation on the complete input.
+    fn finish(
+        self,
+        input: &[u8],
+        aad: &[u8],
+        signature: Option<&[u8]>,
+    ) -> binder::Result<Option<Vec<u8>>> {
+        let failed = |_| km_error(ErrorCode::UNKNOWN_ERROR);
+        match self {
+            Self::EcSign { key, digest: Some(digest) } => Signer::new(digest, &key)
+                .and_then(|mut signer| signer.sign_oneshot_to_vec(input))
+                .map(Some)
+                .map_err(failed),
+            Self::EcSign { key, digest: None } => {
+                let ec_key = key.ec_key().map_err(failed)?;
+                let order_len = (ec_key.group().degree() as usize).div_ceil(8);
+                EcdsaSig::sign(&input[..input.len().min(order_len)], &ec_key)
+                    .and_then(|sig| sig.to_der())
+                    .map(Some)
+                    .map_err(failed)
+            }
+            Self::AgreeKey { key } => {
+                let peer = PKey::public_key_from_der(input)
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                Deriver::new(&key)
+                    .and_then(|mut deriver| {
+                        deriver.set_peer(&peer)?;
+                        deriver.derive_to_vec()
+                    })
+                    .map(Some)
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))
+            }
+            Self::RsaSign { key, digest: Some(digest), padding } => {
+                let mut signer = Signer::new(digest, &key).map_err(failed)?;
+                if padding == PaddingMode::RSA_PSS {
+                    signer.set_rsa_padding(Padding::PKCS1_PSS).map_err(failed)?;
+                    signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH).map_err(failed)?;
+                    signer.set_rsa_mgf1_md(digest).map_err(failed)?;
+                }
+                signer.sign_oneshot_to_vec(input).map(Some).map_err(failed)
+            }
+            Self::RsaSign { key, digest: None, padding } => {
+                let rsa = key.rsa().map_err(failed)?;
+                let size = rsa.size() as usize;
+                let (input, padding) = match padding {
+                    PaddingMode::RSA_PKCS1_1_5_SIGN if input.len() + 11 <= size => {
+                        (input.to_vec(), Padding::PKCS1)
+                    }
+                    PaddingMode::NONE if input.len() <= size => {
+                        ([vec![0; size - input.len()], input.to_vec()].concat(), Padding::NONE)
+                    }
+                    _ => return Err(km_error(ErrorCode::INVALID_INPUT_LENGTH)),
+                };
+                let mut signature = vec![0; size];
+                rsa.private_encrypt(&input, &mut signature, padding)
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                Ok(Some(signature))
+            }
+            Self::RsaDecrypt { key, padding, digests } => {
+                let mut decrypter = Decrypter::new(&key).map_err(failed)?;
+                decrypter.set_rsa_padding(padding).map_err(failed)?;
+                if let Some((digest, mgf_digest)) = digests {
+                    decrypter.set_rsa_oaep_md(digest).map_err(failed)?;
+                    decrypter.set_rsa_mgf1_md(mgf_digest).map_err(failed)?;
+                }
+                let mut output = vec![0; decrypter.decrypt_len(input).map_err(failed)?];
+                let len = decrypter
+                    .decrypt(input, &mut output)
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                output.truncate(len);
+                Ok(Some(output))
+            }
+            Self::Cipher { cipher, mode, key, nonce, tag_len: Some(tag_len), .. } => {
+                let nonce = nonce.as_deref();
+                match mode {
+                    Mode::Encrypt => {
+                        let mut tag = vec![0; tag_len];
+                        let mut output = encrypt_aead(cipher, &key, nonce, aad, input, &mut tag)
+                            .map_err(failed)?;
+                        output.extend_from_slice(&tag);
+                        Ok(Some(output))
+                    }
+                    Mode::Decrypt => {
+                        if input.len() < tag_len {
+                            return Err(km_error(ErrorCode::INVALID_INPUT_LENGTH));
+                        }
+                        let (data, tag) = input.split_at(input.len() - tag_len);
+                        decrypt_aead(cipher, &key, nonce, aad, data, tag)
+                            .map(Some)
+                            .map_err(|_| km_error(ErrorCode::VERIFICATION_FAILED))
+                    }
+                }
+            }
+            Self::Cipher { cipher, mode, key, nonce, padding, tag_len: None } => {
+                if !padding && cipher.block_size() > 1 && input.len() % cipher.block_size() != 0 {
+                    return Err(km_error(ErrorCode::INVALID_INPUT_LENGTH));
+                }
+                let mut crypter =
+                    Crypter::new(cipher, mode, &key, nonce.as_deref()).map_err(failed)?;
+                crypter.pad(padding);
+                let mut output = vec![0; input.len() + cipher.block_size()];
+                let mut len = crypter.update(input, &mut output).map_err(failed)?;
+                len += crypter
+                    .finalize(&mut output[len..])
+                    .map_err(|_| km_error(ErrorCode::INVALID_ARGUMENT))?;
+                output.truncate(len);
+                Ok(Some(output))
+            }
+            Self::HmacSign { key, digest, mac_len } => {
+                let mut mac = hmac(digest, &key, input)?;
+                mac.truncate(mac_len);
+                Ok(Some(mac))
+            }
+            Self::HmacVerify { key, digest, min_mac_len } => {
+                let signature =
+                    signature.ok_or_else(|| km_error(ErrorCode::VERIFICATION_FAILED))?;
+                let mac = hmac(digest, &key, input)?;
+                if signature.len() < min_mac_len || signature.len() > mac.len() {
+                    return Err(km_error(ErrorCode::INVALID_MAC_LENGTH));
+                }
+                if !openssl::memcmp::eq(&mac[..signature.len()], signature) {
+                    return Err(km_error(ErrorCode::VERIFICATION_FAILED));
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn cipher(algorithm: Algorithm, block_mode: BlockMode, key_len: usize) -> binder::Result<Cipher> {
+    let cipher = match (algorithm, block_mode, key_len) {
+        (Algorithm::AES, BlockMode::ECB, 16) => Cipher::aes_128_ecb(),
+        (Algorithm::AES, BlockMode::ECB, 24) => Cipher::aes_192_ecb(),
+        (Algorithm::AES, BlockMode::ECB, 32) => Cipher::aes_256_ecb(),
+        (Algorithm::AES, BlockMode::CBC, 16) => Cipher::aes_128_cbc(),
+        (Algorithm::AES, BlockMode::CBC, 24) => Cipher::aes_192_cbc(),
+        (Algorithm::AES, BlockMode::CBC, 32) => Cipher::aes_256_cbc(),
+        (Algorithm::AES, BlockMode::CTR, 16) => Cipher::aes_128_ctr(),
+        (Algorithm::AES, BlockMode::CTR, 24) => Cipher::aes_192_ctr(),
+        (Algorithm::AES, BlockMode::CTR, 32) => Cipher::aes_256_ctr(),
+        (Algorithm::AES, BlockMode::GCM, 16) => Cipher::aes_128_gcm(),
+        (Algorithm::AES, BlockMode::GCM, 24) => Cipher::aes_192_gcm(),
+        (Algorithm::AES, BlockMode::GCM, 32) => Cipher::aes_256_gcm(),
+        (Algorithm::TRIPLE_DES, BlockMode::ECB, _) => Cipher::des_ede3(),
+        (Algorithm::TRIPLE_DES, BlockMode::CBC, _) => Cipher::des_ede3_cbc(),
+        _ => return Err(km_error(ErrorCode::UNSUPPORTED_BLOCK_MODE)),
+    };
+    Ok(cipher)
+}
+
+struct OperationState {
+    kind: OperationKind,
+    aad: Vec<u8>,
+    input: Vec<u8>,
+    /// Whether input was passed, after which no more AAD is accepted.
+    updated: bool,
+}
+
+struct FakeOperation {
+    store: Arc<Mutex<Store>>,
+    key_id: i64,
+    /// The state of the operation, or None once it was finished or aborted.
+    state: Mutex<Option<OperationState>>,
+}
+
+impl FakeOperation {
+    /// Counts a use of the key. Like keystore2, deletes the key once its usage count limit is
+    /// reached.
+    fn count_use(&self) {
+        let mut store = self.store.lock().unwrap();
+        let Some(entry) = store.keys.get_mut(&self.key_id) else { return };
+        entry.uses += 1;
+        if integer(&entry.characteristics, Tag::USAGE_COUNT_LIMIT)
+            .is_some_and(|limit| entry.uses >= limit)
+        {
+            store.remove(self.key_id);
+        }
+    }
+}
+
+impl binder::Interface for FakeOperation {}
+
+impl IKeystoreOperation for FakeOperation {
+    fn updateAad(&self, aad_input: &[u8]) -> binder::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut().ok_or_else(|| km_error(ErrorCode::INVALID_OPERATION_HANDLE))?;
+        if !matches!(state.kind, OperationKind::Cipher { tag_len: Some(_), .. }) || state.updated {
+            return Err(km_error(ErrorCode::INVALID_TAG));
+        }
+        state.aad.extend_from_slice(aad_input);
+        Ok(())
+    }
+
+    fn update(&self, input: &[u8]) -> binder::Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut().ok_or_else(|| km_error(ErrorCode::INVALID_OPERATION_HANDLE))?;
+        state.input.extend_from_slice(input);
+        state.updated = true;
+        Ok(None)
+    }
+
+    fn finish(
+        &self,
+        input: Option<&[u8]>,
+        signature: Option<&[u8]>,
+    ) -> binder::Result<Option<Vec<u8>>> {
+        let state = self.state.lock().unwrap().take();
+        let mut state = state.ok_or_else(|| km_error(ErrorCode::INVALID_OPERATION_HANDLE))?;
+        state.input.extend_from_slice(input.unwrap_or_default());
+        let output = state.kind.finish(&state.input, &state.aad, signature)?;
+        self.count_use();
+        Ok(output)
+    }
+
+    fn abort(&self) -> binder::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .take()
+            .map(|_| ())
+            .ok_or_else(|| km_error(ErrorCode::INVALID_OPERATION_HANDLE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::{validate_cert_chain, AttestationRecord};
+    use crate::authorizations::AuthSetBuilder;
+    use crate::key_generations::{self, map_ks_error, Error};
+    use crate::SecLevel;
+    use openssl::sign::Verifier;
+    use openssl::x509::X509;
+
+    fn app_key(alias: &str) -> KeyDescriptor {
+        KeyDescriptor {
+            domain: Domain::APP,
+            nspace: -1,
+            alias: Some(alias.to_string()),
+            blob: None,
+        }
+    }
+
+    #[test]
+    fn test_ec_sign() {
+        let sl = SecLevel::tee();
+        let alias = "fake_keystore_test_ec_sign".to_string();
+        let metadata =
+            key_generations::generate_ec_p256_signing_key(&sl, Domain::APP, -1, Some(alias), None)
+                .unwrap();
+        let op = sl
+            .binder
+            .createOperation(
+                &metadata.key,
+                &AuthSetBuilder::new().purpose(KeyPurpose::SIGN).digest(Digest::SHA_2_256),
+                false,
+            )
+            .unwrap()
+            .iOperation
+            .unwrap();
+        assert_eq!(op.update(b"my message").unwrap(), None);
+        let signature = op.finish(None, None).unwrap().unwrap();
+        // The operation is gone once it is finished.
+        assert_eq!(
+            map_ks_error(op.finish(None, None)),
+            Err(Error::Km(ErrorCode::INVALID_OPERATION_HANDLE))
+        );
+
+        let public_key = X509::from_der(&metadata.certificate.unwrap()).unwrap().public_key();
+        let public_key = public_key.unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        assert!(verifier.verify_oneshot(&signature, b"my message").unwrap());
+    }
+
+    #[test]
+    fn test_aes_gcm() {
+        let sl = SecLevel::tee();
+        let params = AuthSetBuilder::new()
+            .no_auth_required()
+            .algorithm(Algorithm::AES)
+            .purpose(KeyPurpose::ENCRYPT)
+            .purpose(KeyPurpose::DECRYPT)
+            .key_size(256)
+            .block_mode(BlockMode::GCM)
+            .padding_mode(PaddingMode::NONE)
+            .min_mac_length(128);
+        let key = app_key("fake_keystore_test_aes_gcm");
+        let metadata = sl.binder.generateKey(&key, None, &params, 0, b"entropy").unwrap();
+        assert!(metadata.certificate.is_none());
+
+        let op_params = AuthSetBuilder::new()
+            .block_mode(BlockMode::GCM)
+            .padding_mode(PaddingMode::NONE)
+            .mac_length(128);
+        let response = sl
+            .binder
+            .createOperation(&metadata.key, &op_params.clone().purpose(KeyPurpose::ENCRYPT), false)
+            .unwrap();
+        let nonce = response.parameters.unwrap().keyParameter[0].value.clone();
+        let op = response.iOperation.unwrap();
+        op.updateAad(b"aad").unwrap();
+        let ciphertext = op.finish(Some(b"my message"), None).unwrap().unwrap();
+        assert_eq!(ciphertext.len(), b"my message".len() + 16);
+
+        let KeyParameterValue::Blob(nonce) = nonce else { panic!("Unexpected nonce {nonce:?}") };
+        let decrypt_params = op_params.clone().purpose(KeyPurpose::DECRYPT).nonce(nonce.clone());
+        let op = sl.binder.createOperation(&metadata.key, &decrypt_params, false).unwrap();
+        let op = op.iOperation.unwrap();
+        op.updateAad(b"aad").unwrap();
+        assert_eq!(op.finish(Some(&ciphertext), None).unwrap().unwrap(), b"my message");
+
+        // A nonce without CALLER_NONCE is rejected.
+        assert_eq!(
+            map_ks_error(sl.binder.createOperation(
+                &metadata.key,
+                &op_params.purpose(KeyPurpose::ENCRYPT).nonce(nonce),
+                false
+            ))
+            .err(),
+            Some(Error::Km(ErrorCode::CALLER_NONCE_PROHIBITED))
+        );
+    }
+
+    #[test]
+    fn test_delete_key() {
+        let sl = SecLevel::tee();
+        let key = app_key("fake_keystore_test_delete_key");
+        key_generations::generate_ec_p256_signing_key(
+            &sl,
+            Domain::APP,
+            -1,
+            key.alias.clone(),
+            None,
+        )
+        .unwrap();
+        let listed = |sl: &SecLevel| {
+            sl.keystore2.listEntries(Domain::APP, -1).unwrap().iter().any(|k| k.alias == key.alias)
+        };
+        assert!(listed(&sl));
+        let grant = sl.keystore2.grant(&key, caller_uid() as i32, 0).unwrap();
+        sl.keystore2.getKeyEntry(&grant).unwrap();
+
+        sl.keystore2.deleteKey(&key).unwrap();
+        assert_eq!(
+            map_ks_error(sl.keystore2.getKeyEntry(&key)).err(),
+            Some(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+        );
+        assert_eq!(
+            map_ks_error(sl.keystore2.getKeyEntry(&grant)).err(),
+            Some(Error::Rc(ResponseCode::KEY_NOT_FOUND))
+        );
+        assert!(!listed(&sl));
+    }
+
+    #[test]
+    fn test_attestation() {
+        let sl = SecLevel::tee();
+        let alias = "fake_keystore_test_attestation".to_string();
+        let metadata = key_generations::generate_ec_p256_signing_key(
+            &sl,
+            Domain::APP,
+            -1,
+            Some(alias),
+            Some(b"challenge"),
+        )
+        .unwrap();
+        let cert = metadata.certificate.unwrap();
+        validate_cert_chain(&[cert.clone(), metadata.certificateChain.unwrap()].concat(), true)
+            .unwrap();
+
+        let record = AttestationRecord::from_cert(&cert).unwrap();
+        assert_eq!(record.attestation_challenge, b"challenge");
+        assert_eq!(record.keymint_version, i64::from(KEYMINT_VERSION * 100));
+        assert_eq!(record.keymint_security_level, SecurityLevel::TRUSTED_ENVIRONMENT);
+        assert_eq!(
+            record.hardware_enforced.get(Tag::PURPOSE).unwrap(),
+            vec![
+                KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+                KeyParameterValue::KeyPurpose(KeyPurpose::VERIFY)
+            ]
+        );
+        assert!(record.software_enforced.contains(Tag::CREATION_DATETIME));
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements a fake of the RKPD service, i.e., of IRemoteProvisioning and
+//! IRegistration, for tests of the remotely provisioned attestation path. Tests script the
+//! response of each call: the fake answers calls in order with the scripted responses of the
+//! method, and with its default response once they run out. Every response is delivered on a
+//! thread of its own after an optional delay, like RKPD does, so that tests can exercise the
+//! timeouts of the caller. A response that hangs is never delivered, unless the caller cancels
+//! the call, which the fake acknowledges with onCancel.
+//!
+//! ## Example:
+//!
+//! ```
+//! let rkpd = FakeRkpd::new();
+//! rkpd.set_default_key(KeyResponse::Key(key));
+//! rkpd.push_key(KeyResponse::Error(GetKeyErrorCode::ERROR_PENDING_INTERNET_CONNECTIVITY,
+//!     "No network.".to_string()), Duration::ZERO);
+//! rkpd.register(FAKE_RKPD_SERVICE_NAME)?;
+//! ```
+
+use android_security_rkp_aidl::aidl::android::security::rkp::{
+    IGetKeyCallback::ErrorCode::ErrorCode as GetKeyErrorCode,
+    IGetKeyCallback::IGetKeyCallback,
+    IGetRegistrationCallback::IGetRegistrationCallback,
+    IRegistration::{BnRegistration, IRegistration},
+    IRemoteProvisioning::{BnRemoteProvisioning, IRemoteProvisioning},
+    IStoreUpgradedKeyCallback::IStoreUpgradedKeyCallback,
+    RemotelyProvisionedKey::RemotelyProvisionedKey,
+};
+use anyhow::{Context, Result};
+use binder::{BinderFeatures, FromIBinder, Interface, Strong};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The service name under which tests register the fake. Unlike "remote_provisioning", the name
+/// of RKPD, it does not clash with the real service.
+pub const FAKE_RKPD_SERVICE_NAME: &str = "android.security.rkp.IRemoteProvisioning/fake";
+
+/// A scripted response of IRemoteProvisioning::getRegistration.
+#[derive(Debug, Clone)]
+pub enum RegistrationResponse {
+    /// Calls IGetRegistrationCallback::onSuccess with the registration of the fake.
+    Registration,
+    /// Calls IGetRegistrationCallback::onError with the description.
+    Error(String),
+    /// Does not respond.
+    Hang,
+}
+
+/// A scripted response of IRegistration::getKey.
+#[derive(Debug)]
+pub enum KeyResponse {
+    /// Calls IGetKeyCallback::onSuccess with the key.
+    Key(RemotelyProvisionedKey),
+    /// Calls IGetKeyCallback::onError with the error code and description.
+    Error(GetKeyErrorCode, String),
+    /// Does not respond.
+    Hang,
+}
+
+// RemotelyProvisionedKey does not implement Clone.
+impl Clone for KeyResponse {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Key(key) => Self::Key(RemotelyProvisionedKey {
+                keyBlob: key.keyBlob.clone(),
+                encodedCertChain: key.encodedCertChain.clone(),
+            }),
+            Self::Error(error, description) => Self::Error(*error, description.clone()),
+            Self::Hang => Self::Hang,
+        }
+    }
+}
+
+/// A scripted response of IRegistration::storeUpgradedKeyAsync.
+#[derive(Debug, Clone)]
+pub enum StoreResponse {
+    /// Calls IStoreUpgradedKeyCallback::onSuccess.
+    Success,
+    /// Calls IStoreUpgradedKeyCallback::onError with the description.
+    Error(String),
+    /// Does not respond.
+    Hang,
+}
+
+/// The calls that the fake received.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Calls {
+    /// The IRemotelyProvisionedComponent names of the calls of getRegistration.
+    pub get_registration: Vec<String>,
+    /// The number of calls of cancelGetRegistration that cancelled a pending call.
+    pub cancel_get_registration: usize,
+    /// The key ids of the calls of getKey.
+    pub get_key: Vec<i32>,
+    /// The number of calls of cancelGetKey that cancelled a pending call.
+    pub cancel_get_key: usize,
+    /// The old and new key blobs of the calls of storeUpgradedKeyAsync.
+    pub store_upgraded_key: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// The responses of one method: the scripted ones, in order, and the default.
+struct Script<R> {
+    queue: VecDeque<(R, Duration)>,
+    default: R,
+}
+
+impl<R: Clone> Script<R> {
+    fn new(default: R) -> Self {
+        Self { queue: VecDeque::new(), default }
+    }
+
+    fn next(&mut self) -> (R, Duration) {
+        self.queue.pop_front().unwrap_or_else(|| (self.default.clone(), Duration::ZERO))
+    }
+}
+
+/// A call whose response has not been delivered yet.
+struct Pending<C: FromIBinder + ?Sized> {
+    cb: Strong<C>,
+    /// Set once the call was answered or cancelled.
+    done: Arc<AtomicBool>,
+}
+
+/// Cancels the pending calls with the callback `cb`, and forgets the calls that are done.
+/// Returns true if a call was cancelled.
+fn cancel<C: FromIBinder + ?Sized>(pending: &mut Vec<Pending<C>>, cb: &Strong<C>) -> bool {
+    let mut cancelled = false;
+    for p in pending.iter().filter(|p| p.cb == *cb) {
+        cancelled |= !p.done.swap(true, Ordering::SeqCst);
+    }
+    pending.retain(|p| !p.done.load(Ordering::SeqCst));
+    cancelled
+}
+
+/// Calls `respond` after `delay` on a new thread, unless the call is done by then.
+fn deliver(delay: Duration, done: Arc<AtomicBool>, respond: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        if !done.swap(true, Ordering::SeqCst) {
+            respond();
+        }
+    });
+}
+
+fn log_callback_error(method: &str, result: binder::Result<()>) {
+    if let Err(e) = result {
+        log::error!("Fake RKPD failed to call {method}: {e:?}");
+    }
+}
+
+struct State {
+    registrations: Script<RegistrationResponse>,
+    keys: Script<KeyResponse>,
+    stores: Script<StoreResponse>,
+    calls: Calls,
+    pending_registrations: Vec<Pending<dyn IGetRegistrationCallback>>,
+    pending_keys: Vec<Pending<dyn IGetKeyCallback>>,
+}
+
+struct FakeRemoteProvisioning {
+    state: Arc<Mutex<State>>,
+    registration: Strong<dyn IRegistration>,
+}
+
+impl Interface for FakeRemoteProvisioning {}
+
+impl IRemoteProvisioning for FakeRemoteProvisioning {
+    fn getRegistration(
+        &self,
+        irpc_name: &str,
+        cb: &Strong<dyn IGetRegistrationCallback>,
+    ) -> binder::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.get_registration.push(irpc_name.to_string());
+        let (response, delay) = state.registrations.next();
+        let done = Arc::new(AtomicBool::new(false));
+        state.pending_registrations.retain(|p| !p.done.load(Ordering::SeqCst));
+        state.pending_registrations.push(Pending { cb: cb.clone(), done: done.clone() });
+        let cb = cb.clone();
+        let registration = self.registration.clone();
+        match response {
+            RegistrationResponse::Registration => deliver(delay, done, move || {
+                log_callback_error("onSuccess", cb.onSuccess(&registration))
+            }),
+            RegistrationResponse::Error(description) => deliver(delay, done, move || {
+                log_callback_error("onError", cb.onError(&description))
+            }),
+            RegistrationResponse::Hang => {}
+        }
+        Ok(())
+    }
+
+    fn cancelGetRegistration(
+        &self,
+        cb: &Strong<dyn IGetRegistrationCallback>,
+    ) -> binder::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if cancel(&mut state.pending_registrations, cb) {
+            state.calls.cancel_get_registration += 1;
+            log_callback_error("onCancel", cb.onCancel());
+        }
+        Ok(())
+    }
+}
+
+struct FakeRegistration(Arc<Mutex<State>>);
+
+impl Interface for FakeRegistration {}
+
+impl IRegistration for FakeRegistration {
+    fn getKey(&self, key_id: i32, cb: &Strong<dyn IGetKeyCallback>) -> binder::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.calls.get_key.push(key_id);
+        let (response, delay) = state.keys.next();
+        let done = Arc::new(AtomicBool::new(false));
+        state.pending_keys.retain(|p| !p.done.load(Ordering::SeqCst));
+        state.pending_keys.push(Pending { cb: cb.clone(), done: done.clone() });
+        let cb = cb.clone();
+        match response {
+            KeyResponse::Key(key) => {
+                deliver(delay, done, move || log_callback_error("onSuccess", cb.onSuccess(&key)))
+            }
+            KeyResponse::Error(error, description) => deliver(delay, done, move || {
+                log_callback_error("onError", cb.onError(error, &description))
+            }),
+            KeyResponse::Hang => {}
+        }
+        Ok(())
+    }
+
+    fn cancelGetKey(&self, cb: &Strong<dyn IGetKeyCallback>) -> binder::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        if cancel(&mut state.pending_keys, cb) {
+            state.calls.cancel_get_key += 1;
+            log_callback_error("onCancel", cb.onCancel());
+        }
+        Ok(())
+    }
+
+    fn storeUpgradedKeyAsync(
+        &self,
+        old_key_blob: &[u8],
+        new_key_blob: &[u8],
+        cb: &Strong<dyn IStoreUpgradedKeyCallback>,
+    ) -> binder::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.calls.store_upgraded_key.push((old_key_blob.to_vec(), new_key_blob.to_vec()));
+        let (response, delay) = state.stores.next();
+        let done = Arc::new(AtomicBool::new(false));
+        let cb = cb.clone();
+        match response {
+            StoreResponse::Success => {
+                deliver(delay, done, move || log_callback_error("onSuccess", cb.onSuccess()))
+            }
+            StoreResponse::Error(description) => deliver(delay, done, move || {
+                log_callback_error("onError", cb.onError(&description))
+            }),
+            StoreResponse::Hang => {}
+        }
+        Ok(())
+    }
+}
+
+/// A fake RKPD service whose responses are scripted by the test. By default, getRegistration
+/// succeeds, getKey fails with ERROR_UNKNOWN, and storeUpgradedKeyAsync succeeds, all without
+/// delay.
+pub struct FakeRkpd {
+    state: Arc<Mutex<State>>,
+    registration: Strong<dyn IRegistration>,
+    binder: Strong<dyn IRemoteProvisioning>,
+}
+
+impl Default for FakeRkpd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeRkpd {
+    /// Creates a fake with the default responses.
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            registrations: Script::new(RegistrationResponse::Registration),
+            keys: Script::new(KeyResponse::Error(
+                GetKeyErrorCode::ERROR_UNKNOWN,
+                "Fake RKPD has no key.".to_string(),
+            )),
+            stores: Script::new(StoreResponse::Success),
+            calls: Default::default(),
+            pending_registrations: vec![],
+            pending_keys: vec![],
+        }));
+        let registration =
+            BnRegistration::new_binder(FakeRegistration(state.clone()), BinderFeatures::default());
+        let binder = BnRemoteProvisioning::new_binder(
+            FakeRemoteProvisioning { state: state.clone(), registration: registration.clone() },
+            BinderFeatures::default(),
+        );
+        Self { state, registration, binder }
+    }
+
+    /// Returns the IRemoteProvisioning binder of the fake, for callers in this process.
+    pub fn binder(&self) -> Strong<dyn IRemoteProvisioning> {
+        self.binder.clone()
+    }
+
+    /// Registers the fake with the service manager as `service_name`, e.g.,
+    /// `FAKE_RKPD_SERVICE_NAME`, and starts the binder thread pool so that it can serve
+    /// other processes.
+    pub fn register(&self, service_name: &str) -> Result<()> {
+        binder::add_service(service_name, self.binder.as_binder())
+            .with_context(|| format!("Failed to register the fake RKPD as {service_name}."))?;
+        binder::ProcessState::start_thread_pool();
+        Ok(())
+    }
+
+    /// Answers the next unscripted call of getRegistration with `response` after `delay`.
+    pub fn push_registration(&self, response: RegistrationResponse, delay: Duration) {
+        self.state.lock().unwrap().registrations.queue.push_back((response, delay));
+    }
+
+    /// Answers calls of getRegistration with `response` once the scripted responses run out.
+    pub fn set_default_registration(&self, response: RegistrationResponse) {
+        self.state.lock().unwrap().registrations.default = response;
+    }
+
+    /// Answers the next unscripted call of getKey with `response` after `delay`.
+    pub fn push_key(&self, response: KeyResponse, delay: Duration) {
+        self.state.lock().unwrap().keys.queue.push_back((response, delay));
+    }
+
+    /// Answers calls of getKey with `response` once the scripted responses run out.
+    pub fn set_default_key(&self, response: KeyResponse) {
+        self.state.lock().unwrap().keys.default = response;
+    }
+
+    /// Answers the next unscripted call of storeUpgradedKeyAsync with `response` after `delay`.
+    pub fn push_store(&self, response: StoreResponse, delay: Duration) {
+        self.state.lock().unwrap().stores.queue.push_back((response, delay));
+    }
+
+    /// Answers calls of storeUpgradedKeyAsync with `response` once the scripted responses run
+    /// out.
+    pub fn set_default_store(&self, response: StoreResponse) {
+        self.state.lock().unwrap().stores.default = response;
+    }
+
+    /// Returns the calls that the fake received so far.
+    pub fn calls(&self) -> Calls {
+        self.state.lock().unwrap().calls.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_security_rkp_aidl::aidl::android::security::rkp::IGetKeyCallback::BnGetKeyCallback;
+    use std::sync::mpsc;
+
+    /// The callbacks of a call of getKey.
+    #[derive(Debug, PartialEq)]
+    enum KeyCallback {
+        Success(Vec<u8>),
+        Cancel,
+        Error(GetKeyErrorCode),
+    }
+
+    struct GetKeyCallback(Mutex<mpsc::Sender<KeyCallback>>);
+
+    impl Interface for GetKeyCallback {}
+
+    impl IGetKeyCallback for GetKeyCallback {
+        fn onSuccess(&self, key: &RemotelyProvisionedKey) -> binder::Result<()> {
+            self.0.lock().unwrap().send(KeyCallback::Success(key.keyBlob.clone())).unwrap();
+            Ok(())
+        }
+
+        fn onCancel(&self) -> binder::Result<()> {
+            self.0.lock().unwrap().send(KeyCallback::Cancel).unwrap();
+            Ok(())
+        }
+
+        fn onError(&self, error: GetKeyErrorCode, _: &str) -> binder::Result<()> {
+            self.0.lock().unwrap().send(KeyCallback::Error(error)).unwrap();
+            Ok(())
+        }
+    }
+
+    fn get_key_callback() -> (Strong<dyn IGetKeyCallback>, mpsc::Receiver<KeyCallback>) {
+        let (tx, rx) = mpsc::channel();
+        (BnGetKeyCallback::new_binder(GetKeyCallback(Mutex::new(tx)), Default::default()), rx)
+    }
+
+    fn key(blob: &[u8]) -> RemotelyProvisionedKey {
+        RemotelyProvisionedKey { keyBlob: blob.to_vec(), encodedCertChain: vec![] }
+    }
+
+    #[test]
+    fn test_scripted_keys() {
+        let rkpd = FakeRkpd::new();
+        rkpd.set_default_key(KeyResponse::Key(key(b"default")));
+        rkpd.push_key(
+            KeyResponse::Error(GetKeyErrorCode::ERROR_PERMANENT, String::new()),
+            Duration::ZERO,
+        );
+        rkpd.push_key(KeyResponse::Key(key(b"scripted")), Duration::from_millis(10));
+        let registration = rkpd.registration.clone();
+
+        let (cb, rx) = get_key_callback();
+        for key_id in 1..=3 {
+            registration.getKey(key_id, &cb).unwrap();
+            let response = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            let expected = match key_id {
+                1 => KeyCallback::Error(GetKeyErrorCode::ERROR_PERMANENT),
+                2 => KeyCallback::Success(b"scripted".to_vec()),
+                _ => KeyCallback::Success(b"default".to_vec()),
+            };
+            assert_eq!(response, expected);
+        }
+        assert_eq!(rkpd.calls().get_key, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_cancel_hanging_key() {
+        let rkpd = FakeRkpd::new();
+        rkpd.push_key(KeyResponse::Hang, Duration::ZERO);
+        let registration = rkpd.registration.clone();
+
+        let (cb, rx) = get_key_callback();
+        registration.getKey(7, &cb).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        registration.cancelGetKey(&cb).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), KeyCallback::Cancel);
+        // A call that is done cannot be cancelled again.
+        registration.cancelGetKey(&cb).unwrap();
+        assert_eq!(rkpd.calls().cancel_get_key, 1);
+    }
+}
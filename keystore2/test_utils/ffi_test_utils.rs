@@ -13,38 +13,25 @@
 // limitations under the License.
 
 //! This module implements helper methods to access the functionalities implemented in CPP.
-
-use crate::key_generations::Error;
+//!
+//! Only `perform_crypto_op_using_keystore_engine` still calls into C++, because keystore-engine is
+//! a C library. The other methods are thin wrappers of the Rust implementations in the
+//! `attestation` and `der` modules, kept for existing callers; new code should use those modules
+//! directly.
+
+use crate::attestation::{self, AttestationRecord};
+use crate::der;
+use crate::key_generations::{Error, WrappedKeyBuilder};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    SecurityLevel::SecurityLevel, Tag::Tag,
+    KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel, Tag::Tag,
 };
 
+#[cfg(not(feature = "fake_keystore"))]
 #[cxx::bridge]
 mod ffi {
-    struct CxxResult {
-        data: Vec<u8>,
-        error: bool,
-    }
-
     unsafe extern "C++" {
         include!("ffi_test_utils.hpp");
-        fn validateCertChain(cert_buf: Vec<u8>, cert_len: u32, strict_issuer_check: bool) -> bool;
-        fn createWrappedKey(
-            encrypted_secure_key: Vec<u8>,
-            encrypted_transport_key: Vec<u8>,
-            iv: Vec<u8>,
-            tag: Vec<u8>,
-        ) -> CxxResult;
-        fn buildAsn1DerEncodedWrappedKeyDescription() -> CxxResult;
         fn performCryptoOpUsingKeystoreEngine(grant_id: i64) -> bool;
-        fn getValueFromAttestRecord(
-            cert_buf: Vec<u8>,
-            tag: i32,
-            expected_sec_level: i32,
-        ) -> CxxResult;
-        fn getOsVersion() -> u32;
-        fn getOsPatchlevel() -> u32;
-        fn getVendorPatchlevel() -> u32;
     }
 }
 
@@ -58,41 +45,32 @@ pub fn validate_certchain_with_strict_issuer_check(
     cert_buf: &[u8],
     strict_issuer_check: bool,
 ) -> Result<bool, Error> {
-    if ffi::validateCertChain(
-        cert_buf.to_vec(),
-        cert_buf.len().try_into().unwrap(),
-        strict_issuer_check,
-    ) {
-        return Ok(true);
-    }
-
-    Err(Error::ValidateCertChainFailed)
+    attestation::validate_cert_chain(cert_buf, strict_issuer_check).map(|()| true)
 }
 
-/// Collect the result from CxxResult into a Rust supported structure.
-fn get_result(result: ffi::CxxResult) -> Result<Vec<u8>, Error> {
-    if !result.error && !result.data.is_empty() {
-        Ok(result.data)
-    } else {
-        Err(Error::DerEncodeFailed)
-    }
+/// Returns the `KeyDescription` of the keys that `create_wrapped_key` wraps.
+fn wrapped_key_description() -> Result<Vec<u8>, Error> {
+    // Only the size of the key material goes into the description.
+    WrappedKeyBuilder::aes(&[0; 32]).key_description()
 }
 
 /// Creates wrapped key material to import in ASN.1 DER-encoded data corresponding to
 /// `SecureKeyWrapper`. See `IKeyMintDevice.aidl` for documentation of the `SecureKeyWrapper`
-/// schema.
+/// schema. The key description is the one of `create_wrapped_key_additional_auth_data`.
 pub fn create_wrapped_key(
     encrypted_secure_key: &[u8],
     encrypted_transport_key: &[u8],
     iv: &[u8],
     tag: &[u8],
 ) -> Result<Vec<u8>, Error> {
-    get_result(ffi::createWrappedKey(
-        encrypted_secure_key.to_vec(),
-        encrypted_transport_key.to_vec(),
-        iv.to_vec(),
-        tag.to_vec(),
-    ))
+    Ok(der::sequence(&[
+        der::integer(0),
+        der::octet_string(encrypted_transport_key),
+        der::octet_string(iv),
+        wrapped_key_description()?,
+        der::octet_string(encrypted_secure_key),
+        der::octet_string(tag),
+    ]))
 }
 
 /// Creates ASN.1 DER-encoded data corresponding to `KeyDescription` schema.
@@ -103,10 +81,11 @@ pub fn create_wrapped_key(
 ///     Blockmode: ECB
 ///     Purpose: Encrypt, Decrypt
 pub fn create_wrapped_key_additional_auth_data() -> Result<Vec<u8>, Error> {
-    get_result(ffi::buildAsn1DerEncodedWrappedKeyDescription())
+    wrapped_key_description()
 }
 
 /// Performs crypto operation using Keystore-Engine APIs.
+#[cfg(not(feature = "fake_keystore"))]
 pub fn perform_crypto_op_using_keystore_engine(grant_id: i64) -> Result<bool, Error> {
     if ffi::performCryptoOpUsingKeystoreEngine(grant_id) {
         return Ok(true);
@@ -115,30 +94,65 @@ pub fn perform_crypto_op_using_keystore_engine(grant_id: i64) -> Result<bool, Er
     Err(Error::Keystore2EngineOpFailed)
 }
 
-/// Get the value of the given `Tag` from attestation record.
+/// Keystore-Engine talks to the real keystore2 service, so it cannot be used with the fake.
+#[cfg(feature = "fake_keystore")]
+pub fn perform_crypto_op_using_keystore_engine(_grant_id: i64) -> Result<bool, Error> {
+    Err(Error::Keystore2EngineOpFailed)
+}
+
+/// Get the value of the given `Tag` from attestation record. `Tag::ATTESTATION_APPLICATION_ID`
+/// is looked up in the software enforced list and `Tag::USAGE_COUNT_LIMIT` in the list of
+/// `expected_sec_level`, and returned as decimal string. All other tags are looked up in the
+/// hardware enforced list and must have blob values.
 pub fn get_value_from_attest_record(
     cert_buf: &[u8],
     tag: Tag,
     expected_sec_level: SecurityLevel,
 ) -> Result<Vec<u8>, Error> {
-    let result = ffi::getValueFromAttestRecord(cert_buf.to_vec(), tag.0, expected_sec_level.0);
-    if !result.error && !result.data.is_empty() {
-        return Ok(result.data);
+    let record = AttestationRecord::from_cert(cert_buf).map_err(|e| {
+        log::error!("get_value_from_attest_record - Failed to parse attestation record: {e:?}");
+        Error::AttestRecordGetValueFailed
+    })?;
+    let list = match tag {
+        Tag::ATTESTATION_CHALLENGE => return non_empty(record.attestation_challenge),
+        Tag::UNIQUE_ID => return non_empty(record.unique_id),
+        Tag::ATTESTATION_APPLICATION_ID => &record.software_enforced,
+        Tag::USAGE_COUNT_LIMIT => record.enforced(expected_sec_level),
+        _ => &record.hardware_enforced,
+    };
+    match list.get_one(tag) {
+        Ok(Some(kp)) => match kp.value {
+            KeyParameterValue::Blob(value) => non_empty(&value),
+            KeyParameterValue::Integer(value) if tag == Tag::USAGE_COUNT_LIMIT => {
+                Ok(value.to_string().into_bytes())
+            }
+            _ => Err(Error::AttestRecordGetValueFailed),
+        },
+        _ => {
+            log::error!("get_value_from_attest_record - {tag:?} not found.");
+            Err(Error::AttestRecordGetValueFailed)
+        }
+    }
+}
+
+fn non_empty(value: &[u8]) -> Result<Vec<u8>, Error> {
+    if value.is_empty() {
+        return Err(Error::AttestRecordGetValueFailed);
     }
-    Err(Error::AttestRecordGetValueFailed)
+    Ok(value.to_vec())
 }
 
 /// Get OS Version
 pub fn get_os_version() -> u32 {
-    ffi::getOsVersion()
+    attestation::get_os_version()
 }
 
 /// Get OS Patch Level
 pub fn get_os_patchlevel() -> u32 {
-    ffi::getOsPatchlevel()
+    attestation::get_os_patchlevel()
 }
 
 /// Get vendor Patch Level
 pub fn get_vendor_patchlevel() -> u32 {
-    ffi::getVendorPatchlevel()
+    attestation::get_vendor_patchlevel()
 }
@@ -0,0 +1,53 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils to inject latency into the HAL calls made by keystore2.
+//! Latency injection requires IKeystoreTestHooks, see `test_hooks`.
+
+use crate::test_hooks::get_test_hooks;
+use anyhow::{Context, Result};
+
+/// Injects latency into HAL calls for as long as it is alive. Dropping it removes the injected
+/// latency.
+pub struct HalLatency {
+    methods: Vec<String>,
+}
+
+impl HalLatency {
+    /// Makes keystore2 sleep for the given number of milliseconds before each call of the given
+    /// HAL methods, e.g., `&[("IKeyMintDevice::begin", 600)]`. Replaces any latency injected
+    /// into these methods before.
+    pub fn inject(latencies: &[(&str, i32)]) -> Result<Self> {
+        let hooks = get_test_hooks().context("keystore2 has no test hooks on this build.")?;
+        let mut result = Self { methods: vec![] };
+        for (method, millis) in latencies {
+            hooks.setHalLatency(method, *millis).with_context(|| {
+                format!("Failed to inject {millis} ms of latency into {method}.")
+            })?;
+            result.methods.push(method.to_string());
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for HalLatency {
+    fn drop(&mut self) {
+        let Some(hooks) = get_test_hooks() else { return };
+        for method in &self.methods {
+            if let Err(e) = hooks.setHalLatency(method, 0) {
+                log::error!("Failed to remove the latency injected into {method}: {e:?}");
+            }
+        }
+    }
+}
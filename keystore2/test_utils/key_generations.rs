@@ -15,10 +15,13 @@
 //! This module implements test utils to generate various types of keys.
 
 use crate::authorizations::AuthSetBuilder;
+use crate::der;
+use crate::determinism;
 use crate::ffi_test_utils::{
     get_os_patchlevel, get_os_version, get_value_from_attest_record, get_vendor_patchlevel,
     validate_certchain_with_strict_issuer_check,
 };
+use crate::rkp_only_simulation;
 use crate::SecLevel;
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
@@ -36,6 +39,12 @@ use anyhow::Result;
 use binder::ThreadState;
 use core::ops::Range;
 use nix::unistd::getuid;
+use openssl::encrypt::Encrypter;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Padding;
+use openssl::symm::{encrypt_aead, Cipher};
+use openssl::x509::X509;
 use std::collections::HashSet;
 use std::fmt::Write;
 use std::path::PathBuf;
@@ -352,12 +361,15 @@ pub enum Error {
     /// Exception
     #[error("Binder exception {0:?}")]
     Binder(ExceptionCode),
-    /// This is returned if the C implementation of extractSubjectFromCertificate failed.
+    /// This is returned if a certificate chain is malformed or its signatures do not verify.
     #[error("Failed to validate certificate chain.")]
     ValidateCertChainFailed,
     /// Error code to indicate error in ASN.1 DER-encoded data creation.
     #[error("Failed to create and encode ASN.1 data.")]
     DerEncodeFailed,
+    /// Error code to indicate malformed ASN.1 DER-encoded data.
+    #[error("Failed to decode ASN.1 data.")]
+    DerDecodeFailed,
     /// Error code to indicate error while using keystore-engine API.
     #[error("Failed to perform crypto op using keystore-engine APIs.")]
     Keystore2EngineOpFailed,
@@ -367,6 +379,15 @@ pub enum Error {
     /// Error code to indicate error in getting value from attest record.
     #[error("Failed to get value from attest record.")]
     AttestRecordGetValueFailed,
+    /// Error code to indicate a malformed DICE certificate chain.
+    #[error("Failed to parse DICE certificate chain.")]
+    DiceChainParseFailed,
+    /// Error code to indicate a DICE certificate chain with invalid signatures or links.
+    #[error("Failed to validate DICE certificate chain.")]
+    DiceChainValidationFailed,
+    /// Error code to indicate error while encrypting key material for a wrapped key import.
+    #[error("Failed to wrap key material.")]
+    WrapKeyFailed,
 }
 
 /// Keystore2 error mapping.
@@ -410,9 +431,12 @@ pub fn is_gsi() -> bool {
 /// Determines whether the test is on a GSI build where the rkp-only status of the device is
 /// unknown. GSI replaces the values for remote_prov_prop properties (since they’re
 /// system_internal_prop properties), so on GSI the properties are not reliable indicators of
-/// whether StrongBox/TEE is RKP-only or not.
+/// whether StrongBox/TEE is RKP-only or not. The status is known while keystore2 simulates an
+/// RKP-only device.
 pub fn is_rkp_only_unknown_on_gsi(sec_level: SecurityLevel) -> bool {
-    if sec_level == SecurityLevel::TRUSTED_ENVIRONMENT {
+    if rkp_only_simulation::is_enabled() {
+        false
+    } else if sec_level == SecurityLevel::TRUSTED_ENVIRONMENT {
         is_gsi() && get_system_prop(TEE_KEYMINT_RKP_ONLY).is_empty()
     } else {
         is_gsi() && get_system_prop(STRONGBOX_KEYMINT_RKP_ONLY).is_empty()
@@ -1359,6 +1383,211 @@ pub fn import_transport_key(
     )
 }
 
+/// `KeyFormat::RAW`, the format of the key material in a `SecureKeyWrapper`.
+const KEY_FORMAT_RAW: i64 = 3;
+
+/// Length of the AES-256-GCM initialization vector used to encrypt the secure key.
+const WRAPPED_KEY_IV_LEN: usize = 12;
+
+/// Length of the AES-256-GCM tag of the encrypted secure key.
+const WRAPPED_KEY_TAG_LEN: usize = 16;
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    determinism::fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Builds ASN.1 DER-encoded wrapped key material corresponding to `SecureKeyWrapper` and imports
+/// it, without hand-rolling the encoding and the encryption in each test. See `IKeyMintDevice.aidl`
+/// for documentation of the `SecureKeyWrapper` schema.
+///
+/// The secure key is encrypted with a transport key using AES-256-GCM, with the DER-encoded
+/// `KeyDescription` as additional authenticated data. The transport key, XORed with the masking
+/// key, is encrypted with the public part of the wrapping key using RSA-OAEP with SHA-256 and
+/// MGF1 with SHA-1, which matches the unwrap parameters used by `import`. The transport key and
+/// the IV are random unless set explicitly.
+///
+/// ## Example:
+///
+/// ```
+/// let wrapping_key_metadata = import_wrapping_key(&sl, RSA_2048_KEY, Some(alias))?;
+/// let key_metadata = WrappedKeyBuilder::aes(&[0; 32]).import(&sl, Some(wrapped_alias),
+///     &wrapping_key_metadata)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct WrappedKeyBuilder {
+    key_material: Vec<u8>,
+    key_params: AuthSetBuilder,
+    masking_key: Option<Vec<u8>>,
+    transport_key: Vec<u8>,
+    iv: Vec<u8>,
+}
+
+impl WrappedKeyBuilder {
+    /// Starts wrapping the given raw key material, described by `key_params`. The parameters
+    /// become the `KeyDescription` of the wrapped key and must only use tags of the
+    /// `AuthorizationList` schema.
+    pub fn new(key_material: &[u8], key_params: AuthSetBuilder) -> Self {
+        Self {
+            key_material: key_material.to_vec(),
+            key_params,
+            masking_key: None,
+            transport_key: random_bytes(32),
+            iv: random_bytes(WRAPPED_KEY_IV_LEN),
+        }
+    }
+
+    /// Starts wrapping the given AES key material with below key parameters -
+    ///     Purposes: ENCRYPT and DECRYPT
+    ///     Padding: PKCS7
+    ///     Blockmode: ECB
+    ///     Key size: the size of the key material
+    pub fn aes(key_material: &[u8]) -> Self {
+        let key_size = (key_material.len() * 8).try_into().unwrap();
+        Self::new(key_material, Self::sym_key_params(Algorithm::AES, key_size))
+    }
+
+    /// Starts wrapping the given 3DES key material with below key parameters -
+    ///     Purposes: ENCRYPT and DECRYPT
+    ///     Padding: PKCS7
+    ///     Blockmode: ECB
+    ///     Key size: 168
+    pub fn triple_des(key_material: &[u8]) -> Self {
+        Self::new(key_material, Self::sym_key_params(Algorithm::TRIPLE_DES, 168))
+    }
+
+    fn sym_key_params(algorithm: Algorithm, key_size: i32) -> AuthSetBuilder {
+        AuthSetBuilder::new()
+            .no_auth_required()
+            .algorithm(algorithm)
+            .purpose(KeyPurpose::ENCRYPT)
+            .purpose(KeyPurpose::DECRYPT)
+            .key_size(key_size)
+            .padding_mode(PaddingMode::PKCS7)
+            .block_mode(BlockMode::ECB)
+    }
+
+    /// Replaces the key parameters describing the wrapped key.
+    pub fn key_params(mut self, key_params: AuthSetBuilder) -> Self {
+        self.key_params = key_params;
+        self
+    }
+
+    /// Sets the 32 byte masking key. Without it, keystore2 uses a masking key of all zeroes.
+    pub fn masking_key(mut self, masking_key: &[u8]) -> Self {
+        self.masking_key = Some(masking_key.to_vec());
+        self
+    }
+
+    /// Sets the 32 byte AES transport key.
+    pub fn transport_key(mut self, transport_key: &[u8]) -> Self {
+        self.transport_key = transport_key.to_vec();
+        self
+    }
+
+    /// Sets the 12 byte IV used to encrypt the secure key.
+    pub fn iv(mut self, iv: &[u8]) -> Self {
+        self.iv = iv.to_vec();
+        self
+    }
+
+    /// Returns the DER-encoded `KeyDescription` of the wrapped key, which is also the additional
+    /// authenticated data of the encrypted secure key.
+    pub fn key_description(&self) -> Result<Vec<u8>, Error> {
+        Ok(der::sequence(&[
+            der::integer(KEY_FORMAT_RAW),
+            der::authorization_list(&self.key_params)?,
+        ]))
+    }
+
+    /// Encrypts the secure key and the transport key and returns the DER-encoded
+    /// `SecureKeyWrapper`, to be unwrapped by the private part of `wrapping_key`.
+    pub fn build(&self, wrapping_key: &PKey<Public>) -> Result<Vec<u8>, Error> {
+        let key_description = self.key_description()?;
+        let mut tag = [0u8; WRAPPED_KEY_TAG_LEN];
+        let encrypted_key = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.transport_key,
+            Some(self.iv.as_slice()),
+            &key_description,
+            &self.key_material,
+            &mut tag,
+        )
+        .map_err(|_| Error::WrapKeyFailed)?;
+
+        let masking_key = self.masking_key.as_deref().unwrap_or(&[0; 32]);
+        if masking_key.len() != self.transport_key.len() {
+            return Err(Error::WrapKeyFailed);
+        }
+        let masked_transport_key: Vec<u8> =
+            self.transport_key.iter().zip(masking_key).map(|(t, m)| t ^ m).collect();
+        let encrypted_transport_key = rsa_oaep_encrypt(wrapping_key, &masked_transport_key)
+            .map_err(|_| Error::WrapKeyFailed)?;
+
+        Ok(der::sequence(&[
+            der::integer(0),
+            der::octet_string(&encrypted_transport_key),
+            der::octet_string(&self.iv),
+            key_description,
+            der::octet_string(&encrypted_key),
+            der::octet_string(&tag),
+        ]))
+    }
+
+    /// Like `build`, but takes the wrapping key from the certificate of an imported or generated
+    /// wrapping key.
+    pub fn build_for(&self, wrapping_key_metadata: &KeyMetadata) -> Result<Vec<u8>, Error> {
+        let cert = wrapping_key_metadata.certificate.as_ref().ok_or(Error::WrapKeyFailed)?;
+        let public_key = X509::from_der(cert)
+            .and_then(|cert| cert.public_key())
+            .map_err(|_| Error::WrapKeyFailed)?;
+        self.build(&public_key)
+    }
+
+    /// Builds the wrapped key material for the given wrapping key and imports it under `alias`
+    /// in `Domain::APP`.
+    pub fn import(
+        &self,
+        sl: &SecLevel,
+        alias: Option<String>,
+        wrapping_key_metadata: &KeyMetadata,
+    ) -> binder::Result<KeyMetadata> {
+        let wrapped_key = self.build_for(wrapping_key_metadata).map_err(|e| {
+            binder::Status::new_exception_str(ExceptionCode::ILLEGAL_ARGUMENT, Some(e.to_string()))
+        })?;
+        let unwrap_params =
+            AuthSetBuilder::new().digest(Digest::SHA_2_256).padding_mode(PaddingMode::RSA_OAEP);
+        let authenticator_spec: &[AuthenticatorSpec] = &[AuthenticatorSpec {
+            authenticatorType: HardwareAuthenticatorType::NONE,
+            authenticatorId: 0,
+        }];
+
+        sl.binder.importWrappedKey(
+            &KeyDescriptor { domain: Domain::APP, nspace: -1, alias, blob: Some(wrapped_key) },
+            &wrapping_key_metadata.key,
+            self.masking_key.as_deref(),
+            &unwrap_params,
+            authenticator_spec,
+        )
+    }
+}
+
+/// Encrypts `data` with RSA-OAEP, using SHA-256 and MGF1 with SHA-1.
+fn rsa_oaep_encrypt(
+    public_key: &PKey<Public>,
+    data: &[u8],
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let mut encrypter = Encrypter::new(public_key)?;
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    encrypter.set_rsa_mgf1_md(MessageDigest::sha1())?;
+    let mut encrypted = vec![0u8; encrypter.encrypt_len(data)?];
+    let len = encrypter.encrypt(data, &mut encrypted)?;
+    encrypted.truncate(len);
+    Ok(encrypted)
+}
+
 /// Generate EC key with purpose AGREE_KEY.
 pub fn generate_ec_agree_key(
     sl: &SecLevel,
@@ -1500,7 +1729,8 @@ pub fn generate_key(
     gen_params: &AuthSetBuilder,
     alias: &str,
 ) -> binder::Result<Option<KeyMetadata>> {
-    let key_metadata = match sl.binder.generateKey(
+    generate_key_with_descriptor(
+        sl,
         &KeyDescriptor {
             domain: Domain::APP,
             nspace: -1,
@@ -1509,9 +1739,18 @@ pub fn generate_key(
         },
         None,
         gen_params,
-        0,
-        b"entropy",
-    ) {
+    )
+}
+
+/// Generate a key for the given descriptor, optionally attested by `attest_key`, and validate key
+/// characteristics. Returns `Ok(None)` if the test should be skipped, see `generate_key`.
+fn generate_key_with_descriptor(
+    sl: &SecLevel,
+    key: &KeyDescriptor,
+    attest_key: Option<&KeyDescriptor>,
+    gen_params: &AuthSetBuilder,
+) -> binder::Result<Option<KeyMetadata>> {
+    let key_metadata = match sl.binder.generateKey(key, attest_key, gen_params, 0, b"entropy") {
         Ok(metadata) => metadata,
         Err(e) => {
             return if is_rkp_only_unknown_on_gsi(sl.level)
@@ -1537,7 +1776,8 @@ pub fn generate_key(
         )
     }) {
         assert!(key_metadata.certificate.is_some());
-        if gen_params.iter().any(|kp| kp.tag == Tag::ATTESTATION_CHALLENGE) {
+        if attest_key.is_none() && gen_params.iter().any(|kp| kp.tag == Tag::ATTESTATION_CHALLENGE)
+        {
             assert!(key_metadata.certificateChain.is_some());
             let mut cert_chain: Vec<u8> = Vec::new();
             cert_chain.extend(key_metadata.certificate.as_ref().unwrap());
@@ -1570,6 +1810,9 @@ pub fn generate_key(
             assert!(!att_app_id.is_empty());
         }
     }
+    if key.domain == Domain::BLOB {
+        assert!(key_metadata.key.blob.is_some());
+    }
     check_key_authorizations(sl, &key_metadata.authorizations, gen_params, KeyOrigin::GENERATED);
 
     Ok(Some(key_metadata))
@@ -1588,3 +1831,459 @@ pub fn create_key_and_operation(
 
     sl.binder.createOperation(&key_metadata.key, op_params, false).map(Some)
 }
+
+/// Fluent builder for key generation parameters, so that tests covering a matrix of algorithms,
+/// purposes and tags don't each need a bespoke `generate_*` helper.
+///
+/// Parameters that KeyMint requires but that are not set explicitly get defaults:
+///     Purposes: SIGN and VERIFY (EC, RSA, HMAC) or ENCRYPT and DECRYPT (AES, 3DES)
+///     EC: Curve P_256, unless a key size is given
+///     RSA: Key size 2048, public exponent 65537
+///     AES: Key size 256
+///     3DES: Key size 168
+///     HMAC: Key size 256, digest SHA_2_256, minimum MAC length 256
+/// Keys are generated with NO_AUTH_REQUIRED unless `auth_bound` is used.
+///
+/// ## Example:
+///
+/// ```
+/// let key = KeyGenBuilder::new(Algorithm::EC)
+///     .curve(EcCurve::P_384)
+///     .purposes(&[KeyPurpose::SIGN])
+///     .digests(&[Digest::SHA_2_384])
+///     .alias("ec_p384_key")
+///     .generate(&sl)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeyGenBuilder {
+    algorithm: Algorithm,
+    key_size: Option<i32>,
+    curve: Option<EcCurve>,
+    purposes: Vec<KeyPurpose>,
+    digests: Vec<Digest>,
+    paddings: Vec<PaddingMode>,
+    block_modes: Vec<BlockMode>,
+    mgf_digest: Option<Digest>,
+    min_mac_length: Option<i32>,
+    auth: Option<(i64, HardwareAuthenticatorType)>,
+    auth_timeout: Option<i32>,
+    attestation_challenge: Option<Vec<u8>>,
+    extra: Vec<KeyParameter>,
+    key: KeyDescriptor,
+}
+
+impl KeyGenBuilder {
+    /// Starts a parameter set for a key of the given algorithm, to be stored with
+    /// `Domain::APP` under a default alias.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            key_size: None,
+            curve: None,
+            purposes: Vec::new(),
+            digests: Vec::new(),
+            paddings: Vec::new(),
+            block_modes: Vec::new(),
+            mgf_digest: None,
+            min_mac_length: None,
+            auth: None,
+            auth_timeout: None,
+            attestation_challenge: None,
+            extra: Vec::new(),
+            key: KeyDescriptor {
+                domain: Domain::APP,
+                nspace: -1,
+                alias: Some(format!("ks_{:?}_test_key", algorithm).to_lowercase()),
+                blob: None,
+            },
+        }
+    }
+
+    /// Sets the key size in bits.
+    pub fn key_size(mut self, size: i32) -> Self {
+        self.key_size = Some(size);
+        self
+    }
+
+    /// Sets the EC curve.
+    pub fn curve(mut self, curve: EcCurve) -> Self {
+        self.curve = Some(curve);
+        self
+    }
+
+    /// Replaces the key purposes.
+    pub fn purposes(mut self, purposes: &[KeyPurpose]) -> Self {
+        self.purposes = purposes.to_vec();
+        self
+    }
+
+    /// Replaces the digests.
+    pub fn digests(mut self, digests: &[Digest]) -> Self {
+        self.digests = digests.to_vec();
+        self
+    }
+
+    /// Replaces the padding modes.
+    pub fn paddings(mut self, paddings: &[PaddingMode]) -> Self {
+        self.paddings = paddings.to_vec();
+        self
+    }
+
+    /// Replaces the block modes.
+    pub fn block_modes(mut self, block_modes: &[BlockMode]) -> Self {
+        self.block_modes = block_modes.to_vec();
+        self
+    }
+
+    /// Sets the RSA OAEP MGF digest.
+    pub fn mgf_digest(mut self, digest: Digest) -> Self {
+        self.mgf_digest = Some(digest);
+        self
+    }
+
+    /// Sets the minimum MAC length in bits.
+    pub fn min_mac_length(mut self, length: i32) -> Self {
+        self.min_mac_length = Some(length);
+        self
+    }
+
+    /// Binds the key to user authentication with the given secure user ID and authenticator
+    /// type, instead of NO_AUTH_REQUIRED.
+    pub fn auth_bound(mut self, sid: i64, auth_type: HardwareAuthenticatorType) -> Self {
+        self.auth = Some((sid, auth_type));
+        self
+    }
+
+    /// Sets the authentication timeout in seconds. Only meaningful with `auth_bound`.
+    pub fn auth_timeout(mut self, seconds: i32) -> Self {
+        self.auth_timeout = Some(seconds);
+        self
+    }
+
+    /// Requests an attestation with the given challenge.
+    pub fn attestation_challenge(mut self, challenge: &[u8]) -> Self {
+        self.attestation_challenge = Some(challenge.to_vec());
+        self
+    }
+
+    /// Adds any other key parameter.
+    pub fn param(mut self, kp: KeyParameter) -> Self {
+        self.extra.push(kp);
+        self
+    }
+
+    /// Stores the key under the given alias.
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.key.alias = Some(alias.to_string());
+        self
+    }
+
+    /// Stores the key in the given domain and namespace.
+    pub fn domain(mut self, domain: Domain, nspace: i64) -> Self {
+        self.key.domain = domain;
+        self.key.nspace = nspace;
+        self
+    }
+
+    /// Returns the key descriptor the key is generated for.
+    pub fn descriptor(&self) -> &KeyDescriptor {
+        &self.key
+    }
+
+    /// Returns the generation parameters, with defaults applied.
+    pub fn build(&self) -> AuthSetBuilder {
+        let mut params = match self.auth {
+            Some((sid, auth_type)) => {
+                AuthSetBuilder::new().user_secure_id(sid).user_auth_type(auth_type)
+            }
+            None => AuthSetBuilder::new().no_auth_required(),
+        };
+        if let Some(seconds) = self.auth_timeout {
+            params = params.auth_timeout(seconds);
+        }
+        params = params.algorithm(self.algorithm);
+
+        let default_purposes: &[KeyPurpose] = match self.algorithm {
+            Algorithm::AES | Algorithm::TRIPLE_DES => &[KeyPurpose::ENCRYPT, KeyPurpose::DECRYPT],
+            _ => &[KeyPurpose::SIGN, KeyPurpose::VERIFY],
+        };
+        let purposes = if self.purposes.is_empty() { default_purposes } else { &self.purposes };
+        for purpose in purposes {
+            params = params.purpose(*purpose);
+        }
+
+        let default_key_size = match self.algorithm {
+            Algorithm::RSA => Some(2048),
+            Algorithm::AES => Some(256),
+            Algorithm::TRIPLE_DES => Some(168),
+            Algorithm::HMAC => Some(256),
+            _ => None,
+        };
+        if let Some(size) = self.key_size.or(default_key_size) {
+            params = params.key_size(size);
+        }
+        match (self.algorithm, self.curve) {
+            (_, Some(curve)) => params = params.ec_curve(curve),
+            (Algorithm::EC, None) if self.key_size.is_none() => {
+                params = params.ec_curve(EcCurve::P_256)
+            }
+            _ => {}
+        }
+        if self.algorithm == Algorithm::RSA {
+            params = params.rsa_public_exponent(65537);
+        }
+
+        let default_digests: &[Digest] =
+            if self.algorithm == Algorithm::HMAC { &[Digest::SHA_2_256] } else { &[] };
+        let digests = if self.digests.is_empty() { default_digests } else { &self.digests };
+        for digest in digests {
+            params = params.digest(*digest);
+        }
+        for padding in &self.paddings {
+            params = params.padding_mode(*padding);
+        }
+        for block_mode in &self.block_modes {
+            params = params.block_mode(*block_mode);
+        }
+        if let Some(digest) = self.mgf_digest {
+            params = params.mgf_digest(digest);
+        }
+        let default_min_mac_length = (self.algorithm == Algorithm::HMAC).then_some(256);
+        if let Some(length) = self.min_mac_length.or(default_min_mac_length) {
+            params = params.min_mac_length(length);
+        }
+        if let Some(challenge) = &self.attestation_challenge {
+            params = params.attestation_challenge(challenge.clone());
+        }
+        for kp in &self.extra {
+            params = params.param(kp.clone());
+        }
+        params
+    }
+
+    /// Generates the key and validates its characteristics. Returns `Ok(None)` if the test
+    /// should be skipped, see `generate_key`.
+    pub fn generate(&self, sl: &SecLevel) -> binder::Result<Option<GeneratedKey>> {
+        self.generate_with_attest_key(sl, None)
+    }
+
+    /// Like `generate`, but has the key attested by `attest_key` instead of the factory
+    /// provisioned or remotely provisioned attestation key.
+    pub fn generate_with_attest_key(
+        &self,
+        sl: &SecLevel,
+        attest_key: Option<&KeyDescriptor>,
+    ) -> binder::Result<Option<GeneratedKey>> {
+        let gen_params = self.build();
+        Ok(generate_key_with_descriptor(sl, &self.key, attest_key, &gen_params)?
+            .map(|metadata| GeneratedKey { metadata, gen_params }))
+    }
+}
+
+/// Generates a batch of keys, one per builder, optionally attested by `attest_key`, and
+/// validates their characteristics. Returns a result per builder, in the order of the builders;
+/// a failed key does not fail the others. A result is `Ok(None)` if the test should be skipped,
+/// see `generate_key`.
+///
+/// This mirrors the batched `generateKeys` of the security level service. It generates the keys
+/// with one `generateKey` call each until `IKeystoreSecurityLevel` exposes `generateKeys`.
+pub fn generate_keys(
+    sl: &SecLevel,
+    builders: &[KeyGenBuilder],
+    attest_key: Option<&KeyDescriptor>,
+) -> Vec<binder::Result<Option<GeneratedKey>>> {
+    builders.iter().map(|builder| builder.generate_with_attest_key(sl, attest_key)).collect()
+}
+
+/// A key generated with [`KeyGenBuilder`].
+#[derive(Debug)]
+pub struct GeneratedKey {
+    /// The metadata returned by `generateKey`.
+    pub metadata: KeyMetadata,
+    /// The parameters the key was generated with.
+    pub gen_params: AuthSetBuilder,
+}
+
+impl GeneratedKey {
+    /// Returns the key descriptor to use the key with.
+    pub fn descriptor(&self) -> &KeyDescriptor {
+        &self.metadata.key
+    }
+
+    /// Returns the first authorization of the key with the given tag.
+    pub fn authorization(&self, tag: Tag) -> Option<&Authorization> {
+        get_key_auth(&self.metadata.authorizations, tag)
+    }
+
+    /// Creates an operation using the key.
+    pub fn create_operation(
+        &self,
+        sl: &SecLevel,
+        op_params: &AuthSetBuilder,
+    ) -> binder::Result<CreateOperationResponse> {
+        sl.binder.createOperation(&self.metadata.key, op_params, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::encrypt::Decrypter;
+    use openssl::rsa::Rsa;
+    use openssl::symm::decrypt_aead;
+
+    fn tags(params: &AuthSetBuilder) -> Vec<Tag> {
+        params.iter().map(|kp| kp.tag).collect()
+    }
+
+    #[test]
+    fn test_key_gen_builder_defaults() {
+        let params = KeyGenBuilder::new(Algorithm::EC).build();
+        assert_eq!(
+            *params,
+            *AuthSetBuilder::new()
+                .no_auth_required()
+                .algorithm(Algorithm::EC)
+                .purpose(KeyPurpose::SIGN)
+                .purpose(KeyPurpose::VERIFY)
+                .ec_curve(EcCurve::P_256)
+        );
+
+        let params = KeyGenBuilder::new(Algorithm::HMAC).build();
+        assert_eq!(
+            *params,
+            *AuthSetBuilder::new()
+                .no_auth_required()
+                .algorithm(Algorithm::HMAC)
+                .purpose(KeyPurpose::SIGN)
+                .purpose(KeyPurpose::VERIFY)
+                .key_size(256)
+                .digest(Digest::SHA_2_256)
+                .min_mac_length(256)
+        );
+
+        let params = KeyGenBuilder::new(Algorithm::RSA).key_size(3072).build();
+        assert!(params.contains(&KeyParameter {
+            tag: Tag::KEY_SIZE,
+            value: KeyParameterValue::Integer(3072)
+        }));
+        assert!(tags(&params).contains(&Tag::RSA_PUBLIC_EXPONENT));
+        assert!(!tags(&params).contains(&Tag::EC_CURVE));
+
+        let params = KeyGenBuilder::new(Algorithm::AES).build();
+        assert!(params.contains(&KeyParameter {
+            tag: Tag::PURPOSE,
+            value: KeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT)
+        }));
+        assert!(!tags(&params).contains(&Tag::DIGEST));
+    }
+
+    #[test]
+    fn test_key_gen_builder_overrides() {
+        let builder = KeyGenBuilder::new(Algorithm::EC)
+            .curve(EcCurve::P_384)
+            .purposes(&[KeyPurpose::SIGN])
+            .digests(&[Digest::SHA_2_384, Digest::NONE])
+            .auth_bound(42, HardwareAuthenticatorType::PASSWORD)
+            .auth_timeout(30)
+            .attestation_challenge(b"challenge")
+            .param(KeyParameter {
+                tag: Tag::UNLOCKED_DEVICE_REQUIRED,
+                value: KeyParameterValue::BoolValue(true),
+            })
+            .alias("ec_key")
+            .domain(Domain::SELINUX, SELINUX_SHELL_NAMESPACE);
+        let params = builder.build();
+
+        assert!(!tags(&params).contains(&Tag::NO_AUTH_REQUIRED));
+        assert_eq!(
+            tags(&params),
+            vec![
+                Tag::USER_SECURE_ID,
+                Tag::USER_AUTH_TYPE,
+                Tag::AUTH_TIMEOUT,
+                Tag::ALGORITHM,
+                Tag::PURPOSE,
+                Tag::EC_CURVE,
+                Tag::DIGEST,
+                Tag::DIGEST,
+                Tag::ATTESTATION_CHALLENGE,
+                Tag::UNLOCKED_DEVICE_REQUIRED,
+            ]
+        );
+        assert_eq!(
+            builder.descriptor(),
+            &KeyDescriptor {
+                domain: Domain::SELINUX,
+                nspace: SELINUX_SHELL_NAMESPACE,
+                alias: Some("ec_key".to_string()),
+                blob: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_wrapped_key_description() {
+        #[rustfmt::skip]
+        let expected = vec![
+            0x30, 0x2e,
+            0x02, 0x01, 0x03, // keyFormat: RAW
+            0x30, 0x29,
+            0xa1, 0x08, 0x31, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x01, // purpose
+            0xa2, 0x03, 0x02, 0x01, 0x20, // algorithm: AES
+            0xa3, 0x04, 0x02, 0x02, 0x01, 0x00, // keySize: 256
+            0xa4, 0x05, 0x31, 0x03, 0x02, 0x01, 0x01, // blockMode: ECB
+            0xa6, 0x05, 0x31, 0x03, 0x02, 0x01, 0x40, // padding: PKCS7
+            0xbf, 0x83, 0x77, 0x02, 0x05, 0x00, // noAuthRequired
+        ];
+        assert_eq!(WrappedKeyBuilder::aes(&[0; 32]).key_description().unwrap(), expected);
+    }
+
+    /// Splits DER-encoded `data` into its top level elements, returning their contents.
+    fn der_contents(data: &[u8]) -> Vec<&[u8]> {
+        der::parse_all(data).unwrap().iter().map(|e| e.content).collect()
+    }
+
+    #[test]
+    fn test_wrapped_key_round_trip() {
+        let private_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let public_key =
+            PKey::public_key_from_der(&private_key.public_key_to_der().unwrap()).unwrap();
+        let key_material = [0x5a; 24];
+        let masking_key = [0xa5; 32];
+        let transport_key = [0x11; 32];
+        let builder = WrappedKeyBuilder::triple_des(&key_material)
+            .masking_key(&masking_key)
+            .transport_key(&transport_key);
+
+        let wrapped_key = builder.build(&public_key).unwrap();
+        let wrapper = der_contents(&wrapped_key);
+        assert_eq!(wrapper.len(), 1);
+        let fields = der_contents(wrapper[0]);
+        assert_eq!(fields.len(), 6);
+        assert_eq!(fields[0], &[0x00]);
+
+        let mut decrypter = Decrypter::new(&private_key).unwrap();
+        decrypter.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+        decrypter.set_rsa_oaep_md(MessageDigest::sha256()).unwrap();
+        decrypter.set_rsa_mgf1_md(MessageDigest::sha1()).unwrap();
+        let mut masked = vec![0; decrypter.decrypt_len(fields[1]).unwrap()];
+        let len = decrypter.decrypt(fields[1], &mut masked).unwrap();
+        let unmasked: Vec<u8> = masked[..len].iter().zip(masking_key).map(|(t, m)| t ^ m).collect();
+        assert_eq!(unmasked, transport_key);
+
+        let key_description = builder.key_description().unwrap();
+        assert_eq!(fields[2].len(), WRAPPED_KEY_IV_LEN);
+        assert_eq!(der_contents(&key_description), vec![fields[3]]);
+        let decrypted = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &transport_key,
+            Some(fields[2]),
+            &key_description,
+            fields[4],
+            fields[5],
+        )
+        .unwrap();
+        assert_eq!(decrypted, key_material);
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils to exercise the key blob upgrade path of keystore2 on
+//! devices whose KeyMint never asks for an upgrade. The upgrade is forced with
+//! IKeystoreTestHooks, see `test_hooks`.
+
+use crate::test_hooks;
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::IKeystoreMaintenance;
+use anyhow::{bail, Context, Result};
+
+static MAINTENANCE_SERVICE_NAME: &str = "android.security.maintenance";
+
+/// Number of blobs processed by each garbage collection pass.
+const GC_MAX_BLOBS: i32 = 64;
+/// Maximum number of garbage collection passes to drain the garbage collector.
+const GC_MAX_PASSES: usize = 50;
+
+/// Returns true if keystore2 supports forced key upgrades on this build.
+pub fn is_supported() -> bool {
+    test_hooks::is_supported()
+}
+
+/// Runs garbage collection passes until no superseded or orphaned blobs are left.
+fn drain_gc() -> Result<()> {
+    let maintenance: binder::Strong<dyn IKeystoreMaintenance> =
+        binder::get_interface(MAINTENANCE_SERVICE_NAME)
+            .context("Failed to get the maintenance service.")?;
+    for _ in 0..GC_MAX_PASSES {
+        let result = maintenance
+            .runGarbageCollection(GC_MAX_BLOBS)
+            .context("Garbage collection pass failed.")?;
+        if result.blobsRemaining == 0 {
+            return Ok(());
+        }
+    }
+    bail!("Garbage collection did not drain after {GC_MAX_PASSES} passes.");
+}
+
+/// Drives the key `key_id` through a forced upgrade of its blob. `use_key` must make keystore2
+/// pass the blob to KeyMint, e.g., by performing an operation with the key. It is called to
+/// upgrade the blob, then to use the upgraded blob, and once more after the superseded blob was
+/// garbage collected, which fails if the garbage collector deleted the wrong blob. Fails if a
+/// blob is left to the garbage collector afterwards.
+///
+/// The garbage collector deletes the superseded blob from KeyMint, so the key must not be
+/// rollback resistant.
+pub fn exercise_key_upgrade(key_id: i64, use_key: impl Fn() -> Result<()>) -> Result<()> {
+    // Start from an empty garbage collector, so that leaked blobs are attributed to the upgrade.
+    drain_gc().context("Failed to drain the garbage collector before the upgrade.")?;
+    test_hooks::force_key_upgrade(key_id)?;
+    use_key().context("Failed to use the key with a forced upgrade.")?;
+    use_key().context("Failed to use the key after its upgrade.")?;
+    drain_gc().context("Failed to collect the superseded blob.")?;
+    use_key().context("Failed to use the key after garbage collection.")
+}
@@ -0,0 +1,63 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils to make keystore2 treat the device as Keymaster-only, so
+//! that all keys are routed through km_compat even if KeyMint devices are present. The emulation
+//! is only honored on debuggable builds and requires restarting keystore2, so the tests using it
+//! must run as root.
+
+use crate::service_control::{start_keystore2, stop_keystore2};
+use anyhow::{Context, Result};
+use rustutils::system_properties;
+
+/// The system property read by keystore2. Must be kept in sync with keystore2's test_hooks
+/// module.
+const FORCE_KEYMASTER_PROPERTY: &str = "keystore.test.force_keymaster";
+
+/// Returns true if keystore2 was asked to emulate a Keymaster-only device.
+pub fn is_enabled() -> bool {
+    system_properties::read_bool(FORCE_KEYMASTER_PROPERTY, false).unwrap_or(false)
+}
+
+/// Restarts keystore2 with the given emulation setting.
+fn restart_keystore2(force_keymaster: bool) -> Result<()> {
+    stop_keystore2()?;
+    let value = if force_keymaster { "true" } else { "" };
+    system_properties::write(FORCE_KEYMASTER_PROPERTY, value)
+        .with_context(|| format!("Failed to set {FORCE_KEYMASTER_PROPERTY} to {value:?}."))?;
+    start_keystore2()
+}
+
+/// Makes keystore2 emulate a Keymaster-only device for as long as it is alive. Dropping it
+/// restarts keystore2 without the emulation.
+pub struct KeymasterEmulation;
+
+impl KeymasterEmulation {
+    /// Restarts keystore2 so that it connects to all security levels through km_compat. On
+    /// devices without Keymaster hardware, the software KeyMint device of km_compat stands in for
+    /// the TEE and there is no StrongBox. Keys created before the restart may not be usable while
+    /// the emulation is enabled.
+    pub fn enable() -> Result<Self> {
+        restart_keystore2(true).context("Failed to enable Keymaster-only emulation.")?;
+        Ok(Self)
+    }
+}
+
+impl Drop for KeymasterEmulation {
+    fn drop(&mut self) {
+        if let Err(e) = restart_keystore2(false) {
+            log::error!("Failed to disable Keymaster-only emulation: {e:?}");
+        }
+    }
+}
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Implements TempDir which aids in creating an cleaning up temporary directories for testing.
+//! Implements TempDir and TempFile which aid in creating and cleaning up temporary directories
+//! and files for testing.
 
-use std::fs::{create_dir, remove_dir_all};
+use std::fs::{create_dir, remove_dir_all, remove_file, OpenOptions};
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::{env::temp_dir, ops::Deref};
@@ -24,45 +25,66 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     IKeystoreSecurityLevel::IKeystoreSecurityLevel,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    ErrorCode::ErrorCode, IKeyMintDevice::IKeyMintDevice, SecurityLevel::SecurityLevel,
+    ErrorCode::ErrorCode, SecurityLevel::SecurityLevel,
 };
+#[cfg(not(feature = "fake_keystore"))]
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::IKeyMintDevice::IKeyMintDevice;
 use android_security_authorization::aidl::android::security::authorization::IKeystoreAuthorization::IKeystoreAuthorization;
 
+pub mod attestation;
 pub mod authorizations;
+pub mod der;
+pub mod determinism;
+pub mod dice_chain;
+#[cfg(feature = "fake_keystore")]
+pub mod fake_keystore;
+pub mod fake_rkpd;
 pub mod ffi_test_utils;
+pub mod hal_latency;
 pub mod key_generations;
+pub mod key_upgrade;
+pub mod keymaster_emulation;
+pub mod operation_workload;
+pub mod quirks;
+pub mod rkp_only_simulation;
 pub mod run_as;
+pub mod service_control;
+pub mod stress;
+pub mod test_hooks;
+pub mod user_auth;
 
+#[cfg(not(feature = "fake_keystore"))]
 static KS2_SERVICE_NAME: &str = "android.system.keystore2.IKeystoreService/default";
 static AUTH_SERVICE_NAME: &str = "android.security.authorization";
 
+/// If this environment variable is set, temporary directories and files are retained when the
+/// test that owns them panics, as if `persist_on_panic` had been called on each of them.
+pub const KEEP_TMP_ENV: &str = "KEYSTORE_TEST_KEEP_TMP";
+
 /// Represents the lifecycle of a temporary directory for testing.
 #[derive(Debug)]
 pub struct TempDir {
     path: std::path::PathBuf,
     do_drop: bool,
+    persist_on_panic: bool,
 }
 
 impl TempDir {
     /// Creates a temporary directory with a name of the form <prefix>_NNNNN where NNNNN is a zero
     /// padded random number with 5 figures. The prefix must not contain file system separators.
-    /// The location of the directory cannot be chosen.
+    /// The directory is created in the system's temporary directory; use `new_in` to choose
+    /// the location.
     /// The directory with all of its content is removed from the file system when the resulting
     /// object gets dropped.
     pub fn new(prefix: &str) -> std::io::Result<Self> {
-        let tmp = loop {
-            let mut tmp = temp_dir();
-            let number: u16 = rand::random();
-            tmp.push(format!("{}_{:05}", prefix, number));
-            match create_dir(&tmp) {
-                Err(e) => match e.kind() {
-                    ErrorKind::AlreadyExists => continue,
-                    _ => return Err(e),
-                },
-                Ok(()) => break tmp,
-            }
-        };
-        Ok(Self { path: tmp, do_drop: true })
+        Self::new_in(&temp_dir(), prefix)
+    }
+
+    /// Like `new`, but creates the directory in `parent`, e.g., next to the keystore database
+    /// so that it gets the SELinux label of that location. `parent` must exist.
+    pub fn new_in(parent: &Path, prefix: &str) -> std::io::Result<Self> {
+        let path = create_unique(parent, prefix, |path| create_dir(path))?;
+        Ok(Self { path, do_drop: true, persist_on_panic: keep_tmp_env_set() })
     }
 
     /// Returns the absolute path of the temporary directory.
@@ -91,16 +113,112 @@ impl TempDir {
         log::info!("Disabled automatic cleanup for: {:?}", self.path);
         self.do_drop = false;
     }
+
+    /// Retains the directory if it is dropped while the owning thread panics, i.e., when the test
+    /// fails, so that it can be inspected without editing the test. See also `KEEP_TMP_ENV`.
+    pub fn persist_on_panic(mut self) -> Self {
+        self.persist_on_panic = true;
+        self
+    }
 }
 
 impl Drop for TempDir {
     fn drop(&mut self) {
-        if self.do_drop {
+        if self.do_drop && !retain_after_panic(self.persist_on_panic, &self.path) {
             remove_dir_all(&self.path).expect("Cannot delete temporary dir.");
         }
     }
 }
 
+/// Represents the lifecycle of a temporary file for testing.
+#[derive(Debug)]
+pub struct TempFile {
+    path: std::path::PathBuf,
+    do_drop: bool,
+    persist_on_panic: bool,
+}
+
+impl TempFile {
+    /// Creates an empty temporary file with a name of the form <prefix>_NNNNN in the system's
+    /// temporary directory. See `TempDir::new`.
+    /// The file is removed from the file system when the resulting object gets dropped.
+    pub fn new(prefix: &str) -> std::io::Result<Self> {
+        Self::new_in(&temp_dir(), prefix)
+    }
+
+    /// Like `new`, but creates the file in `parent`. See `TempDir::new_in`.
+    pub fn new_in(parent: &Path, prefix: &str) -> std::io::Result<Self> {
+        let path = create_unique(parent, prefix, |path| {
+            OpenOptions::new().write(true).create_new(true).open(path).map(|_| ())
+        })?;
+        Ok(Self { path, do_drop: true, persist_on_panic: keep_tmp_env_set() })
+    }
+
+    /// Returns the absolute path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// When a test is failing you can set this to false in order to inspect
+    /// the file after the test failed.
+    #[allow(dead_code)]
+    pub fn do_not_drop(&mut self) {
+        println!("Disabled automatic cleanup for: {:?}", self.path);
+        log::info!("Disabled automatic cleanup for: {:?}", self.path);
+        self.do_drop = false;
+    }
+
+    /// Retains the file if it is dropped while the owning thread panics. See
+    /// `TempDir::persist_on_panic`.
+    pub fn persist_on_panic(mut self) -> Self {
+        self.persist_on_panic = true;
+        self
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.do_drop && !retain_after_panic(self.persist_on_panic, &self.path) {
+            remove_file(&self.path).expect("Cannot delete temporary file.");
+        }
+    }
+}
+
+fn keep_tmp_env_set() -> bool {
+    std::env::var_os(KEEP_TMP_ENV).is_some()
+}
+
+/// Returns true, and logs the path, if `path` is to be retained because the current thread
+/// panics.
+fn retain_after_panic(persist_on_panic: bool, path: &Path) -> bool {
+    if persist_on_panic && std::thread::panicking() {
+        println!("Test panicked, retaining: {:?}", path);
+        log::info!("Test panicked, retaining: {:?}", path);
+        true
+    } else {
+        false
+    }
+}
+
+/// Creates a file system entry named <prefix>_NNNNN in `parent` with `create`, retrying with
+/// another random number if the name is taken.
+fn create_unique<F>(parent: &Path, prefix: &str, create: F) -> std::io::Result<PathBuf>
+where
+    F: Fn(&Path) -> std::io::Result<()>,
+{
+    loop {
+        let number = determinism::random_u64() as u16;
+        let path = parent.join(format!("{}_{:05}", prefix, number));
+        match create(&path) {
+            Err(e) => match e.kind() {
+                ErrorKind::AlreadyExists => continue,
+                _ => return Err(e),
+            },
+            Ok(()) => return Ok(path),
+        }
+    }
+}
+
 /// Allows for convenient building of paths from a TempDir. See TempDir.build() for more details.
 pub struct PathBuilder(PathBuf);
 
@@ -121,10 +239,36 @@ impl Deref for PathBuilder {
 }
 
 /// Get Keystore2 service.
+#[cfg(not(feature = "fake_keystore"))]
 pub fn get_keystore_service() -> binder::Strong<dyn IKeystoreService> {
     binder::get_interface(KS2_SERVICE_NAME).unwrap()
 }
 
+/// Get the in-process fake of the Keystore2 service, see `fake_keystore`.
+#[cfg(feature = "fake_keystore")]
+pub fn get_keystore_service() -> binder::Strong<dyn IKeystoreService> {
+    fake_keystore::get_keystore_service()
+}
+
+/// Returns the version of the given IKeyMintDevice instance, or None if it is not declared.
+#[cfg(not(feature = "fake_keystore"))]
+fn keymint_device_version(instance: &str) -> Option<i32> {
+    let name = format!("android.hardware.security.keymint.IKeyMintDevice/{instance}");
+    if binder::is_declared(&name).expect("Could not check for declared keymint interface") {
+        let km: binder::Strong<dyn IKeyMintDevice> = binder::get_interface(&name).unwrap();
+        Some(km.getInterfaceVersion().unwrap())
+    } else {
+        None
+    }
+}
+
+/// Returns the version of the given IKeyMintDevice instance of the fake keystore, which only
+/// has the default instance.
+#[cfg(feature = "fake_keystore")]
+fn keymint_device_version(instance: &str) -> Option<i32> {
+    (instance == "default").then_some(fake_keystore::KEYMINT_VERSION)
+}
+
 /// Get Keystore auth service.
 pub fn get_keystore_auth_service() -> binder::Strong<dyn IKeystoreAuthorization> {
     binder::get_interface(AUTH_SERVICE_NAME).unwrap()
@@ -151,7 +295,19 @@ impl SecLevel {
     }
     /// Return security level data for StrongBox, if present.
     pub fn strongbox() -> Option<Self> {
-        let level = SecurityLevel::STRONGBOX;
+        Self::probe(SecurityLevel::STRONGBOX)
+    }
+    /// Return security level data for a software KeyMint instance, if present.
+    pub fn software() -> Option<Self> {
+        Self::probe(SecurityLevel::SOFTWARE)
+    }
+    /// Return security level data for every security level present on the device, starting
+    /// with TEE. Tests that should pass on every security level iterate over these, so that
+    /// they also cover levels that are added later.
+    pub fn all() -> Vec<Self> {
+        [Self::tee()].into_iter().chain(Self::strongbox()).chain(Self::software()).collect()
+    }
+    fn probe(level: SecurityLevel) -> Option<Self> {
         let keystore2 = get_keystore_service();
         match key_generations::map_ks_error(keystore2.getSecurityLevel(level)) {
             Ok(binder) => Some(Self { keystore2, binder, level }),
@@ -161,15 +317,24 @@ impl SecLevel {
             }
         }
     }
+    /// Returns the name of the IKeyMintDevice instance backing this security level. A software
+    /// security level has no instance of its own.
+    fn keymint_instance(&self) -> Option<&'static str> {
+        match self.level {
+            SecurityLevel::TRUSTED_ENVIRONMENT => Some("default"),
+            SecurityLevel::STRONGBOX => Some("strongbox"),
+            SecurityLevel::SOFTWARE => None,
+            l => panic!("unexpected level {l:?}"),
+        }
+    }
     /// Indicate whether this security level is a KeyMint implementation (not Keymaster).
+    /// Returns false while keystore2 emulates a Keymaster-only device, and for a software
+    /// security level.
     pub fn is_keymint(&self) -> bool {
-        let instance = match self.level {
-            SecurityLevel::TRUSTED_ENVIRONMENT => "default",
-            SecurityLevel::STRONGBOX => "strongbox",
-            l => panic!("unexpected level {l:?}"),
-        };
-        let name = format!("android.hardware.security.keymint.IKeyMintDevice/{instance}");
-        binder::is_declared(&name).expect("Could not check for declared keymint interface")
+        if keymaster_emulation::is_enabled() {
+            return false;
+        }
+        self.keymint_instance().and_then(keymint_device_version).is_some()
     }
 
     /// Indicate whether this security level is a Keymaster implementation (not KeyMint).
@@ -178,19 +343,80 @@ impl SecLevel {
     }
 
     /// Get KeyMint version.
-    /// Returns 0 if the underlying device is Keymaster not KeyMint.
+    /// Returns 0 if the underlying device is Keymaster not KeyMint, if keystore2 emulates a
+    /// Keymaster-only device, or for a software security level.
     pub fn get_keymint_version(&self) -> i32 {
-        let instance = match self.level {
-            SecurityLevel::TRUSTED_ENVIRONMENT => "default",
-            SecurityLevel::STRONGBOX => "strongbox",
-            l => panic!("unexpected level {l:?}"),
-        };
-        let name = format!("android.hardware.security.keymint.IKeyMintDevice/{instance}");
-        if binder::is_declared(&name).expect("Could not check for declared keymint interface") {
-            let km: binder::Strong<dyn IKeyMintDevice> = binder::get_interface(&name).unwrap();
-            km.getInterfaceVersion().unwrap()
-        } else {
-            0
+        if keymaster_emulation::is_enabled() {
+            return 0;
         }
+        self.keymint_instance().and_then(keymint_device_version).unwrap_or(0)
+    }
+}
+
+/// Defines a test that runs its body once for every security level returned by
+/// `SecLevel::all()`, with the given identifier bound to the level:
+///
+/// ```ignore
+/// for_each_level!(my_test, |sl| {
+///     key_generations::generate_ec_p256_signing_key(&sl, Domain::APP, -1, None, None).unwrap();
+/// });
+/// ```
+#[macro_export]
+macro_rules! for_each_level {
+    ( $test_name:ident, |$sl:ident| $body:block ) => {
+        #[test]
+        fn $test_name() {
+            for $sl in $crate::SecLevel::all() {
+                eprintln!("{}: running on {:?}", stringify!($test_name), $sl.level);
+                $body
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_dir_new_in() {
+        let parent = TempDir::new("temp_dir_new_in_test").unwrap();
+        let tdir = TempDir::new_in(parent.path(), "child").unwrap();
+        assert_eq!(tdir.path().parent(), Some(parent.path()));
+        assert!(tdir.path().is_dir());
+        let path = tdir.path().to_owned();
+        drop(tdir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_temp_file() {
+        let parent = TempDir::new("temp_file_test").unwrap();
+        let tfile = TempFile::new_in(parent.path(), "file").unwrap();
+        assert_eq!(tfile.path().parent(), Some(parent.path()));
+        assert!(tfile.path().is_file());
+        let path = tfile.path().to_owned();
+        drop(tfile);
+        assert!(!path.exists());
+        assert!(parent.path().is_dir());
+    }
+
+    #[test]
+    fn test_persist_on_panic() {
+        let parent = TempDir::new("persist_on_panic_test").unwrap();
+        let parent_path = parent.path().to_owned();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = std::thread::spawn(move || {
+            let kept = TempDir::new_in(&parent_path, "kept").unwrap().persist_on_panic();
+            let removed = TempDir::new_in(&parent_path, "removed").unwrap();
+            sender.send((kept.path().to_owned(), removed.path().to_owned())).unwrap();
+            panic!("Simulated test failure.");
+        })
+        .join();
+        assert!(result.is_err());
+        let (kept, removed) = receiver.recv().unwrap();
+        // Unless KEEP_TMP_ENV is set, only the directory that opted in is retained.
+        assert!(kept.is_dir());
+        assert_eq!(removed.exists(), keep_tmp_env_set());
     }
 }
@@ -0,0 +1,136 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements generators of synthetic operation workloads for simulating
+//! keystore2's operation pruning policy. All times are offsets from the start of a simulated
+//! scenario, and a workload is fully determined by the seed of its generator, so a scenario
+//! that exposes a problem can be replayed exactly.
+
+use std::ops::Range;
+use std::time::Duration;
+
+/// An operation that is running at the start of a scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntheticOperation {
+    /// Uid of the operation's owner.
+    pub owner: u32,
+    /// The time the operation was last used.
+    pub last_usage: Duration,
+    /// Whether the operation is forced.
+    pub forced: bool,
+}
+
+/// A request to create an operation during a scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrival {
+    /// Uid of the caller.
+    pub owner: u32,
+    /// The time of the request.
+    pub at: Duration,
+    /// Whether the operation is forced.
+    pub forced: bool,
+}
+
+/// Generates workloads from a seed. The generator uses its own pseudo random number generator,
+/// so that the workloads do not change with the implementation of the rand crate.
+#[derive(Debug, Clone)]
+pub struct WorkloadGenerator {
+    state: u64,
+}
+
+impl WorkloadGenerator {
+    /// Creates a new generator.
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns the next number of the SplitMix64 sequence.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a duration in `range`, or `range.start` if the range is empty.
+    fn duration_in(&mut self, range: &Range<Duration>) -> Duration {
+        let span = range.end.saturating_sub(range.start).as_nanos() as u64;
+        if span == 0 {
+            return range.start;
+        }
+        range.start + Duration::from_nanos(self.next_u64() % span)
+    }
+
+    /// Returns `count` operations of `owner` that were last used within `idle` before `now`.
+    pub fn running(
+        &mut self,
+        owner: u32,
+        count: usize,
+        idle: Range<Duration>,
+        now: Duration,
+    ) -> Vec<SyntheticOperation> {
+        (0..count)
+            .map(|_| SyntheticOperation {
+                owner,
+                last_usage: now.saturating_sub(self.duration_in(&idle)),
+                forced: false,
+            })
+            .collect()
+    }
+
+    /// Returns `count` requests starting at `start`, with gaps within `gaps` between them. The
+    /// caller of each request is picked from `owners` uniformly at random, so listing an owner
+    /// more than once makes it request more operations.
+    pub fn arrivals(
+        &mut self,
+        owners: &[u32],
+        count: usize,
+        gaps: Range<Duration>,
+        start: Duration,
+    ) -> Vec<Arrival> {
+        assert!(!owners.is_empty(), "A workload needs at least one owner.");
+        let mut at = start;
+        (0..count)
+            .map(|_| {
+                at += self.duration_in(&gaps);
+                let owner = owners[(self.next_u64() % owners.len() as u64) as usize];
+                Arrival { owner, at, forced: false }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workloads_are_deterministic() {
+        let generate = || {
+            let mut generator = WorkloadGenerator::new(42);
+            let now = Duration::from_secs(100);
+            let running = generator.running(1, 8, Duration::ZERO..Duration::from_secs(10), now);
+            let gaps = Duration::from_millis(1)..Duration::from_millis(5);
+            let arrivals = generator.arrivals(&[1, 2, 3], 100, gaps, Duration::from_secs(10));
+            (running, arrivals)
+        };
+        let (running, arrivals) = generate();
+        assert_eq!((running.clone(), arrivals.clone()), generate());
+
+        assert!(running.iter().all(|op| op.last_usage > Duration::from_secs(90)));
+        assert!(arrivals.windows(2).all(|w| w[1].at > w[0].at));
+        assert!(arrivals.iter().all(|a| (1..=3).contains(&a.owner)));
+    }
+}
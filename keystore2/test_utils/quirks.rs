@@ -0,0 +1,183 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements the reporting of known vendor quirks in keystore2 client tests.
+//! Quirks are documented deviations of KeyMint implementations from the specification. They
+//! are listed in the quirk database `quirks.txt` next to this file, keyed by the fingerprint of
+//! the KeyMint HAL. A test that runs its body with `run_with_quirks` and fails on a HAL with a
+//! matching quirk is reported as a known quirk and passes, so that one documented deviation does
+//! not fail an entire test module.
+//!
+//! Every outcome of `run_with_quirks` is reported on stdout and in logcat as one line starting
+//! with `REPORT_PREFIX`, so that the report of a test run can be collected from its logs.
+
+use crate::SecLevel;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    IKeyMintDevice::IKeyMintDevice, SecurityLevel::SecurityLevel,
+};
+use anyhow::{anyhow, Result};
+use std::panic::{catch_unwind, resume_unwind, UnwindSafe};
+use std::sync::LazyLock;
+
+/// The prefix of the report lines.
+pub const REPORT_PREFIX: &str = "keystore2_quirk_report:";
+
+/// The checked-in quirk database.
+static QUIRKS: LazyLock<Vec<Quirk>> =
+    LazyLock::new(|| parse(include_str!("quirks.txt")).expect("Malformed quirk database."));
+
+/// A documented deviation of a KeyMint implementation from the specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quirk {
+    /// Pattern for the fingerprints of the affected HALs.
+    pub hal: String,
+    /// Pattern for the paths of the affected tests.
+    pub test: String,
+    /// The bug or vendor errata that documents the deviation.
+    pub reference: String,
+    /// What the implementation does differently.
+    pub description: String,
+}
+
+impl Quirk {
+    /// Returns true if the quirk applies to the test `test` on the HAL `hal`.
+    pub fn matches(&self, hal: &str, test: &str) -> bool {
+        matches_pattern(&self.hal, hal) && matches_pattern(&self.test, test)
+    }
+}
+
+/// The outcome of a test run with `run_with_quirks`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The test passed.
+    Passed,
+    /// The test failed because of the given known quirk.
+    KnownQuirk(Quirk),
+}
+
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    }
+}
+
+/// Parses a quirk database. Empty lines and lines starting with '#' are ignored.
+pub fn parse(db: &str) -> Result<Vec<Quirk>> {
+    db.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            match fields[..] {
+                [hal, test, reference, description]
+                    if !hal.is_empty() && !test.is_empty() && !reference.is_empty() =>
+                {
+                    Ok(Quirk {
+                        hal: hal.to_string(),
+                        test: test.to_string(),
+                        reference: reference.to_string(),
+                        description: description.to_string(),
+                    })
+                }
+                _ => Err(anyhow!("Malformed quirk in line {}: {line:?}", i + 1)),
+            }
+        })
+        .collect()
+}
+
+/// Returns the fingerprint of the KeyMint HAL of the given security level, i.e.,
+/// "<keyMintAuthorName>/<keyMintName>/<versionNumber>".
+pub fn hal_fingerprint(level: SecurityLevel) -> Result<String> {
+    let instance = match level {
+        SecurityLevel::TRUSTED_ENVIRONMENT => "default",
+        SecurityLevel::STRONGBOX => "strongbox",
+        l => return Err(anyhow!("Unexpected security level {l:?}.")),
+    };
+    let name = format!("android.hardware.security.keymint.IKeyMintDevice/{instance}");
+    let km: binder::Strong<dyn IKeyMintDevice> =
+        binder::get_interface(&name).map_err(|e| anyhow!("Failed to get {name}: {e:?}"))?;
+    let info = km.getHardwareInfo().map_err(|e| anyhow!("Failed to get hardware info: {e:?}"))?;
+    Ok(format!("{}/{}/{}", info.keyMintAuthorName, info.keyMintName, info.versionNumber))
+}
+
+/// Returns the name of the current test, which the test harness uses as the thread name.
+fn current_test() -> String {
+    std::thread::current().name().unwrap_or("unknown").to_string()
+}
+
+fn report(test: &str, hal: &str, outcome: &str) {
+    let line = format!("{REPORT_PREFIX} test={test} hal={hal} outcome={outcome}");
+    println!("{line}");
+    log::info!("{line}");
+}
+
+/// Runs the test body `f` against the KeyMint instance of `sl`. If `f` panics and the quirk
+/// database has a quirk for the current test on that HAL, the failure is reported as a known
+/// quirk and swallowed. Any other failure is reported and propagated.
+pub fn run_with_quirks<F: FnOnce() + UnwindSafe>(sl: &SecLevel, f: F) -> Outcome {
+    let test = current_test();
+    let hal = hal_fingerprint(sl.level).unwrap_or_else(|e| {
+        log::warn!("{e:?}");
+        "unknown".to_string()
+    });
+    match catch_unwind(f) {
+        Ok(()) => {
+            report(&test, &hal, "passed");
+            Outcome::Passed
+        }
+        Err(panic) => match QUIRKS.iter().find(|q| q.matches(&hal, &test)) {
+            Some(quirk) => {
+                report(&test, &hal, &format!("known_quirk reference={}", quirk.reference));
+                Outcome::KnownQuirk(quirk.clone())
+            }
+            None => {
+                report(&test, &hal, "failed");
+                resume_unwind(panic)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_in_database_parses() {
+        assert!(parse(include_str!("quirks.txt")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        let quirks = parse(
+            "# comment\n\
+             \n\
+             Vendor/KM/* | mod::test_* | b/1 | Rejects something valid.\n\
+             Vendor/KM/300 | mod::other | b/2 | \n",
+        )
+        .unwrap();
+        assert_eq!(quirks.len(), 2);
+        assert_eq!(quirks[0].reference, "b/1");
+        assert!(quirks[0].matches("Vendor/KM/200", "mod::test_aes"));
+        assert!(!quirks[0].matches("Vendor/KM2/200", "mod::test_aes"));
+        assert!(!quirks[0].matches("Vendor/KM/200", "other::test_aes"));
+        assert!(quirks[1].matches("Vendor/KM/300", "mod::other"));
+        assert!(!quirks[1].matches("Vendor/KM/3000", "mod::other"));
+
+        // Entries need all fields and a reference.
+        assert!(parse("Vendor/KM/* | mod::test | b/1").is_err());
+        assert!(parse("Vendor/KM/* | mod::test |  | Undocumented.").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils to make keystore2 behave as if the device were RKP-only, so
+//! that the RKP-only code paths get coverage on devices that still have factory attestation keys.
+//! The simulation requires IKeystoreTestHooks, see `test_hooks`.
+
+use crate::test_hooks::{self, get_test_hooks};
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether an RkpOnlySimulation of this process is alive.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if keystore2 supports the RKP-only simulation on this build.
+pub fn is_supported() -> bool {
+    test_hooks::is_supported()
+}
+
+/// Returns true if this process asked keystore2 to simulate an RKP-only device.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn set_simulated(simulated: bool) -> Result<()> {
+    get_test_hooks()
+        .context("keystore2 has no test hooks on this build.")?
+        .setRkpOnlySimulated(simulated)
+        .context("Failed to call setRkpOnlySimulated.")?;
+    ENABLED.store(simulated, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Makes keystore2 simulate an RKP-only device for as long as it is alive. Dropping it ends the
+/// simulation.
+pub struct RkpOnlySimulation;
+
+impl RkpOnlySimulation {
+    /// Makes keystore2 treat the TEE and StrongBox as RKP-only: keys are attested only with
+    /// remotely provisioned attestation keys, and requests that would otherwise be attested with
+    /// the factory key fail with ATTESTATION_KEYS_NOT_PROVISIONED.
+    pub fn enable() -> Result<Self> {
+        set_simulated(true).context("Failed to enable RKP-only simulation.")?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RkpOnlySimulation {
+    fn drop(&mut self) {
+        if let Err(e) = set_simulated(false) {
+            log::error!("Failed to disable RKP-only simulation: {e:?}");
+        }
+    }
+}
@@ -21,26 +21,136 @@
 //! `run_as` forks, transitions to the given identity, and executes the closure in the newly
 //! forked process. If the closure returns, i.e., does not panic, the forked process exits with
 //! a status of `0`, and the return value is serialized and sent through a pipe to the parent where
-//! it gets deserialized and returned. The STDIO is not changed and the parent's panic handler
-//! remains unchanged. So if the closure panics, the panic message is printed on the parent's STDERR
-//! and the exit status is set to a non `0` value. The latter causes the parent to panic as well,
-//! and if run in a test context, the test to fail.
+//! it gets deserialized and returned.
+//!
+//! The child captures everything written to its STDOUT and STDERR file descriptors, while still
+//! forwarding it to the original destination. If the closure panics, the child catches the panic
+//! and sends its message together with the captured output to the parent, and exits with a non
+//! `0` status. The parent then panics with a message that holds the child's panic message, exit
+//! status, and output, so that the failure of a test that spans processes can be diagnosed from
+//! the test result alone. Output written with `print!` and `eprint!` while the test harness
+//! captures output is not written to the file descriptors and is therefore not included.
+//!
+//! `run_as_with` and `run_as_child_with` additionally take `Credentials`, which set the
+//! supplementary groups and the Linux capabilities of the new identity. This allows testing
+//! callers whose access depends on more than their UID and SELinux context.
+//!
+//! `run_as_child` connects parent and child with typed channels that carry serialized messages.
+//! With `run_as_child_rpc`, commands and responses have distinct types, and the parent can call
+//! into a child that handles the commands with `serve`.
+//!
+//! `run_as_app_in_user` and `run_as_app_in_user_child` run the closure as an app in a given
+//! Android user, e.g., a secondary user or a profile. They derive the UID, GID, and the MLS
+//! categories of the SELinux context from the Android user id and app id the way Android does,
+//! so that tests can validate the isolation of keys between users.
 
 use keystore2_selinux as selinux;
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::{
-    fork, pipe as nix_pipe, read as nix_read, setgid, setuid, write as nix_write, ForkResult, Gid,
-    Pid, Uid,
+    fork, pipe as nix_pipe, read as nix_read, setgid, setgroups, setuid, write as nix_write,
+    ForkResult, Gid, Pid, Uid,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use rustutils::users::AID_USER_OFFSET;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::File;
 use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::os::fd::AsRawFd;
-use std::os::fd::OwnedFd;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::thread::JoinHandle;
+
+/// A Linux capability, identified by its number as defined in `linux/capability.h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Capability(pub u32);
+
+impl Capability {
+    /// CAP_CHOWN
+    pub const CHOWN: Self = Self(0);
+    /// CAP_DAC_OVERRIDE
+    pub const DAC_OVERRIDE: Self = Self(1);
+    /// CAP_SETGID
+    pub const SETGID: Self = Self(6);
+    /// CAP_SETUID
+    pub const SETUID: Self = Self(7);
+    /// CAP_NET_ADMIN
+    pub const NET_ADMIN: Self = Self(12);
+    /// CAP_NET_RAW
+    pub const NET_RAW: Self = Self(13);
+    /// CAP_SYS_ADMIN
+    pub const SYS_ADMIN: Self = Self(21);
+    /// CAP_WAKE_ALARM
+    pub const WAKE_ALARM: Self = Self(35);
+    /// CAP_BLOCK_SUSPEND
+    pub const BLOCK_SUSPEND: Self = Self(36);
+}
+
+/// Credentials of the new identity beyond its UID, GID, and SELinux context.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    /// The supplementary groups. If None, the groups of the parent are inherited.
+    pub groups: Option<Vec<Gid>>,
+    /// The capabilities that are kept in the permitted and effective sets, all others are
+    /// dropped. Only capabilities that the parent has can be kept. If None, the capabilities
+    /// change as usual on `setuid`, i.e., they are all dropped for a non root UID.
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
 
-fn transition(se_context: selinux::Context, uid: Uid, gid: Gid) {
+/// Corresponds to `struct __user_cap_header_struct`.
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// Corresponds to `struct __user_cap_data_struct`.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn set_capabilities(capabilities: &[Capability]) {
+    // Version 3 capability sets are 64 bits wide, split over two data structs.
+    let mut data = [CapUserData::default(); 2];
+    for Capability(cap) in capabilities {
+        let d = &mut data[(*cap / 32) as usize];
+        d.effective |= 1 << (cap % 32);
+        d.permitted |= 1 << (cap % 32);
+    }
+    let header = CapUserHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    // SAFETY: The header is valid and data holds the two structs that capset reads for
+    // version 3. Both outlive the call.
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        panic!(
+            "Failed to set capabilities: {:?}. This test might need more privileges.",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+fn transition(se_context: selinux::Context, uid: Uid, gid: Gid, credentials: &Credentials) {
+    if let Some(groups) = &credentials.groups {
+        setgroups(groups)
+            .expect("Failed to set supplementary groups. This test might need more privileges.");
+    }
+    if credentials.capabilities.is_some() {
+        // Keep the permitted capabilities across setuid, so that the requested ones can be
+        // raised again below.
+        // SAFETY: PR_SET_KEEPCAPS takes a single integer argument and has no memory effects.
+        if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+            panic!("Failed to set PR_SET_KEEPCAPS: {:?}", std::io::Error::last_os_error());
+        }
+    }
     setgid(gid).expect("Failed to set GID. This test might need more privileges.");
     setuid(uid).expect("Failed to set UID. This test might need more privileges.");
+    if let Some(capabilities) = &credentials.capabilities {
+        set_capabilities(capabilities);
+    }
 
     selinux::setcon(&se_context)
         .expect("Failed to set SELinux context. This test might need more privileges.");
@@ -76,9 +186,9 @@ impl Write for PipeWriter {
 }
 
 /// Denotes the sender side of a serializing channel.
-pub struct ChannelWriter<T: Serialize + DeserializeOwned>(PipeWriter, PhantomData<T>);
+pub struct ChannelWriter<T>(PipeWriter, PhantomData<T>);
 
-impl<T: Serialize + DeserializeOwned> ChannelWriter<T> {
+impl<T: Serialize> ChannelWriter<T> {
     /// Sends a serializable object to a the corresponding ChannelReader.
     /// Sending is always non blocking. Panics if any error occurs during io or serialization.
     pub fn send(&mut self, value: &T) {
@@ -114,36 +224,36 @@ impl<T: Serialize + DeserializeOwned> ChannelWriter<T> {
 /// Represents the receiving and of a serializing channel.
 pub struct ChannelReader<T>(PipeReader, PhantomData<T>);
 
-impl<T: Serialize + DeserializeOwned> ChannelReader<T> {
+impl<T: DeserializeOwned> ChannelReader<T> {
     /// Receives a serializable object from the corresponding ChannelWriter.
     /// Receiving blocks until an object of type T has been read from the channel.
-    /// Panics if an error occurs during io or deserialization.
+    /// Panics if an error occurs during io or deserialization, or if the channel was closed.
     pub fn recv(&mut self) -> T {
+        self.try_recv().expect("In ChannelReader::recv: The channel was closed.")
+    }
+
+    /// Like `recv`, but returns None if the channel was closed before an object was sent, e.g.,
+    /// because the sender exited.
+    pub fn try_recv(&mut self) -> Option<T> {
         let mut size_buffer = [0u8; std::mem::size_of::<usize>()];
-        match self.0.read(&mut size_buffer).expect("In ChannelReader::recv: Failed to read size.") {
-            r if r != size_buffer.len() => {
-                panic!("In ChannelReader::recv: Failed to read size. Insufficient data: {}", r);
-            }
-            _ => {}
-        };
+        let r =
+            self.0.read(&mut size_buffer).expect("In ChannelReader::recv: Failed to read size.");
+        if r == 0 {
+            return None;
+        }
+        self.0
+            .read_exact(&mut size_buffer[r..])
+            .expect("In ChannelReader::recv: Failed to read size. Insufficient data.");
         let size = usize::from_be_bytes(size_buffer);
         let mut data_buffer = vec![0u8; size];
-        match self
-            .0
-            .read(&mut data_buffer)
-            .expect("In ChannelReader::recv: Failed to read serialized data.")
-        {
-            r if r != data_buffer.len() => {
-                panic!(
-                    "In ChannelReader::recv: Failed to read serialized data. Insufficient data: {}",
-                    r
-                );
-            }
-            _ => {}
-        };
-
-        serde_cbor::from_slice(&data_buffer)
-            .expect("In ChannelReader::recv: Failed to deserialize data.")
+        self.0
+            .read_exact(&mut data_buffer)
+            .expect("In ChannelReader::recv: Failed to read serialized data. Insufficient data.");
+
+        Some(
+            serde_cbor::from_slice(&data_buffer)
+                .expect("In ChannelReader::recv: Failed to deserialize data."),
+        )
     }
 }
 
@@ -152,10 +262,7 @@ fn pipe() -> Result<(PipeReader, PipeWriter), nix::Error> {
     Ok((PipeReader(read_fd), PipeWriter(write_fd)))
 }
 
-fn pipe_channel<T>() -> Result<(ChannelReader<T>, ChannelWriter<T>), nix::Error>
-where
-    T: Serialize + DeserializeOwned,
-{
+fn pipe_channel<T>() -> Result<(ChannelReader<T>, ChannelWriter<T>), nix::Error> {
     let (reader, writer) = pipe()?;
     Ok((
         ChannelReader::<T>(reader, Default::default()),
@@ -163,49 +270,178 @@ where
     ))
 }
 
-/// Handle for handling child processes.
-pub struct ChildHandle<R: Serialize + DeserializeOwned, M: Serialize + DeserializeOwned> {
+/// How the closure run by the child ended.
+#[derive(Serialize, Deserialize)]
+enum ChildOutcome<R> {
+    /// The closure returned this value.
+    Returned(R),
+    /// The closure panicked with this message.
+    Panicked(String),
+}
+
+/// What the child sends to the parent when the closure has ended.
+#[derive(Serialize, Deserialize)]
+struct ChildReport<R> {
+    outcome: ChildOutcome<R>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// Captures the output written to a file descriptor of the child by redirecting it into a pipe.
+/// A thread drains the pipe and forwards the output to the original destination.
+struct OutputCapture {
+    fd: RawFd,
+    saved: OwnedFd,
+    drain: JoinHandle<Vec<u8>>,
+}
+
+impl OutputCapture {
+    fn start(fd: RawFd) -> Self {
+        // SAFETY: dup has no memory effects.
+        let saved = match unsafe { libc::dup(fd) } {
+            -1 => panic!("Failed to dup {fd}: {:?}", std::io::Error::last_os_error()),
+            // SAFETY: On success dup returns a new file descriptor, which nothing else owns.
+            saved => unsafe { OwnedFd::from_raw_fd(saved) },
+        };
+        let mut forward = File::from(saved.try_clone().expect("Failed to clone saved fd."));
+        let (mut reader, writer) = pipe().expect("Failed to create output pipe.");
+        // SAFETY: dup2 has no memory effects. It replaces `fd`, which stays owned by the
+        // process, with a copy of the write end of the pipe.
+        if unsafe { libc::dup2(writer.0.as_raw_fd(), fd) } == -1 {
+            panic!("Failed to redirect {fd}: {:?}", std::io::Error::last_os_error());
+        }
+        drop(writer);
+        let drain = std::thread::spawn(move || {
+            let mut captured = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while let Ok(n @ 1..) = reader.read(&mut buffer) {
+                let _ = forward.write_all(&buffer[..n]);
+                captured.extend_from_slice(&buffer[..n]);
+            }
+            captured
+        });
+        Self { fd, saved, drain }
+    }
+
+    /// Restores the original file descriptor and returns the captured output.
+    fn finish(self) -> Vec<u8> {
+        // SAFETY: See `start`. Restoring `fd` closes the last write end of the pipe, which ends
+        // the drain thread.
+        if unsafe { libc::dup2(self.saved.as_raw_fd(), self.fd) } == -1 {
+            return b"<failed to restore output>".to_vec();
+        }
+        self.drain.join().unwrap_or_default()
+    }
+}
+
+/// Runs `f` in the child with its output captured, sends the report through `result_writer`, and
+/// exits with status `0` if `f` returned or `1` if it panicked.
+fn run_child<F, R>(f: F, mut result_writer: ChannelWriter<ChildReport<R>>) -> !
+where
+    R: Serialize + DeserializeOwned,
+    F: FnOnce() -> R,
+{
+    let stdout = OutputCapture::start(libc::STDOUT_FILENO);
+    let stderr = OutputCapture::start(libc::STDERR_FILENO);
+    let outcome = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => ChildOutcome::Returned(result),
+        Err(payload) => ChildOutcome::Panicked(
+            payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "<non-string panic payload>".to_string()),
+        ),
+    };
+    let exit_code = match outcome {
+        ChildOutcome::Returned(_) => 0,
+        ChildOutcome::Panicked(_) => 1,
+    };
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    let report = ChildReport { outcome, stdout: stdout.finish(), stderr: stderr.finish() };
+    result_writer.send(&report);
+    std::process::exit(exit_code);
+}
+
+/// Waits for the child and returns its report, if it sent one, and its exit status.
+fn wait_for_child<R>(
+    pid: Pid,
+    result_reader: &mut ChannelReader<ChildReport<R>>,
+) -> (Option<ChildReport<R>>, WaitStatus)
+where
+    R: Serialize + DeserializeOwned,
+{
+    // Read the report before waiting, so that the child does not block on a full pipe.
+    let report = result_reader.try_recv();
+    let status = waitpid(pid, None).expect("Failed while waiting for child.");
+    (report, status)
+}
+
+/// Returns the result of the child's closure. Panics with the child's panic message, exit
+/// status, and output if the closure did not return or the child did not exit with status `0`.
+fn child_result<R>(report: Option<ChildReport<R>>, status: WaitStatus) -> R {
+    let Some(report) = report else {
+        panic!("Child exited without a report: {:?}", status);
+    };
+    let message = match (report.outcome, status) {
+        (ChildOutcome::Returned(result), WaitStatus::Exited(_, 0)) => return result,
+        (ChildOutcome::Returned(_), status) => {
+            format!("Child did not exit as expected: {:?}", status)
+        }
+        (ChildOutcome::Panicked(message), status) => {
+            format!("Child panicked: {message}\nExit status: {:?}", status)
+        }
+    };
+    panic!(
+        "{message}\n--- child stdout ---\n{}\n--- child stderr ---\n{}",
+        String::from_utf8_lossy(&report.stdout),
+        String::from_utf8_lossy(&report.stderr)
+    );
+}
+
+/// Handle for handling child processes. The parent sends commands of type `M` to the child, and
+/// the child sends responses of type `S` to the parent.
+pub struct ChildHandle<R: Serialize + DeserializeOwned, M: Serialize, S: DeserializeOwned = M> {
     pid: Pid,
-    result_reader: ChannelReader<R>,
-    cmd_writer: ChannelWriter<M>,
-    response_reader: ChannelReader<M>,
+    result_reader: ChannelReader<ChildReport<R>>,
+    cmd_writer: Option<ChannelWriter<M>>,
+    response_reader: ChannelReader<S>,
     exit_status: Option<WaitStatus>,
 }
 
-impl<R: Serialize + DeserializeOwned, M: Serialize + DeserializeOwned> ChildHandle<R, M> {
+impl<R: Serialize + DeserializeOwned, M: Serialize, S: DeserializeOwned> ChildHandle<R, M, S> {
     /// Send a command message to the child.
     pub fn send(&mut self, data: &M) {
-        self.cmd_writer.send(data)
+        self.cmd_writer.as_mut().expect("The command channel is closed.").send(data)
     }
 
     /// Receive a response from the child.
-    pub fn recv(&mut self) -> M {
+    pub fn recv(&mut self) -> S {
         self.response_reader.recv()
     }
 
+    /// Send a request to a child that serves requests with `serve`, and receive its response.
+    pub fn call(&mut self, request: &M) -> S {
+        self.send(request);
+        self.recv()
+    }
+
     /// Get child result. Panics if the child did not exit with status 0 or if a serialization
-    /// error occurred.
+    /// error occurred. If the closure panicked, the panic message includes the child's panic
+    /// message, exit status, and output. The command channel is closed first, which ends
+    /// `serve` in the child.
     pub fn get_result(mut self) -> R {
-        let status =
-            waitpid(self.pid, None).expect("ChildHandle::wait: Failed while waiting for child.");
-        match status {
-            WaitStatus::Exited(pid, 0) => {
-                // Child exited successfully.
-                // Read the result from the pipe.
-                self.exit_status = Some(WaitStatus::Exited(pid, 0));
-                self.result_reader.recv()
-            }
-            WaitStatus::Exited(pid, c) => {
-                panic!("Child did not exit as expected: {:?}", WaitStatus::Exited(pid, c));
-            }
-            status => {
-                panic!("Child did not exit at all: {:?}", status);
-            }
-        }
+        self.cmd_writer = None;
+        let (report, status) = wait_for_child(self.pid, &mut self.result_reader);
+        self.exit_status = Some(status);
+        child_result(report, status)
     }
 }
 
-impl<R: Serialize + DeserializeOwned, M: Serialize + DeserializeOwned> Drop for ChildHandle<R, M> {
+impl<R: Serialize + DeserializeOwned, M: Serialize, S: DeserializeOwned> Drop
+    for ChildHandle<R, M, S>
+{
     fn drop(&mut self) {
         if self.exit_status.is_none() {
             panic!("Child result not checked.")
@@ -213,6 +449,98 @@ impl<R: Serialize + DeserializeOwned, M: Serialize + DeserializeOwned> Drop for
     }
 }
 
+/// Serves the requests that the parent sends with `ChildHandle::call` by replying with the
+/// result of `handler` for each request, until the parent closes the command channel, e.g.,
+/// by calling `ChildHandle::get_result`.
+pub fn serve<M, S>(
+    requests: &mut ChannelReader<M>,
+    responses: &mut ChannelWriter<S>,
+    mut handler: impl FnMut(M) -> S,
+) where
+    M: DeserializeOwned,
+    S: Serialize,
+{
+    while let Some(request) = requests.try_recv() {
+        responses.send(&handler(request));
+    }
+}
+
+/// The first app id. Android derives the MLS categories of an app from its offset to this.
+const AID_APP_START: u32 = 10000;
+
+/// The identity of an app in an Android user as assigned by Android. The UID and GID are
+/// `user_id * AID_USER_OFFSET + app_id`, and the MLS categories of the SELinux context are
+/// derived from the app id and the user id, like for apps with `levelFrom=all` in
+/// `seapp_contexts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppIdentity {
+    /// The UID of the app in the Android user.
+    pub uid: Uid,
+    /// The GID of the app in the Android user.
+    pub gid: Gid,
+    /// The SELinux context of the app in the Android user.
+    pub se_context: String,
+}
+
+impl AppIdentity {
+    /// Returns the identity of the app `app_id` in the Android user `user_id`. `se_context` is
+    /// the context of the app without MLS categories, e.g., `u:r:untrusted_app:s0`. If it
+    /// already has categories, or if `app_id` is below the app range, it is used as is.
+    /// Panics if `app_id` is not an app id, i.e., if it is a UID of a secondary user.
+    pub fn new(user_id: u32, app_id: u32, se_context: &str) -> Self {
+        assert!(app_id < AID_USER_OFFSET, "{app_id} is not an app id.");
+        let uid = user_id * AID_USER_OFFSET + app_id;
+        let has_categories = se_context.split(':').count() > 4;
+        let se_context = match app_id.checked_sub(AID_APP_START) {
+            Some(id) if !has_categories => format!(
+                "{se_context}:c{},c{},c{},c{}",
+                id & 0xff,
+                256 + ((id >> 8) & 0xff),
+                512 + (user_id & 0xff),
+                768 + ((user_id >> 8) & 0xff)
+            ),
+            _ => se_context.to_string(),
+        };
+        Self { uid: Uid::from_raw(uid), gid: Gid::from_raw(uid), se_context }
+    }
+}
+
+/// Run the given closure in a new process running as the app `app_id` in the Android user
+/// `user_id`. See `AppIdentity::new` for how the identity is derived.
+///
+/// # Safety
+/// See `run_as`.
+pub unsafe fn run_as_app_in_user<F, R>(user_id: u32, app_id: u32, se_context: &str, f: F) -> R
+where
+    R: Serialize + DeserializeOwned,
+    F: 'static + Send + FnOnce() -> R,
+{
+    let identity = AppIdentity::new(user_id, app_id, se_context);
+    // SAFETY: Our caller guarantees the safety requirements of run_as.
+    unsafe { run_as(&identity.se_context, identity.uid, identity.gid, f) }
+}
+
+/// Like `run_as_app_in_user`, but the parent process runs without waiting for the child, and
+/// the closure can exchange messages with the parent through the returned `ChildHandle`.
+///
+/// # Safety
+/// See `run_as_child`.
+pub unsafe fn run_as_app_in_user_child<F, R, M>(
+    user_id: u32,
+    app_id: u32,
+    se_context: &str,
+    f: F,
+) -> Result<ChildHandle<R, M>, nix::Error>
+where
+    R: Serialize + DeserializeOwned,
+    M: Serialize + DeserializeOwned,
+    F: 'static + Send + FnOnce(&mut ChannelReader<M>, &mut ChannelWriter<M>) -> R,
+{
+    let identity = AppIdentity::new(user_id, app_id, se_context);
+    // SAFETY: Our caller guarantees the safety requirements of run_as_child.
+    unsafe { run_as_child(&identity.se_context, identity.uid, identity.gid, f) }
+}
+
 /// Run the given closure in a new process running with the new identity given as
 /// `uid`, `gid`, and `se_context`. Parent process will run without waiting for child status.
 ///
@@ -236,10 +564,56 @@ where
     R: Serialize + DeserializeOwned,
     M: Serialize + DeserializeOwned,
     F: 'static + Send + FnOnce(&mut ChannelReader<M>, &mut ChannelWriter<M>) -> R,
+{
+    // SAFETY: Our caller guarantees the safety requirements of run_as_child_with.
+    unsafe { run_as_child_with(se_context, uid, gid, &Credentials::default(), f) }
+}
+
+/// Like `run_as_child`, but additionally sets the supplementary groups and capabilities of the
+/// new identity as given by `credentials`.
+///
+/// # Safety
+/// See `run_as_child`.
+pub unsafe fn run_as_child_with<F, R, M>(
+    se_context: &str,
+    uid: Uid,
+    gid: Gid,
+    credentials: &Credentials,
+    f: F,
+) -> Result<ChildHandle<R, M>, nix::Error>
+where
+    R: Serialize + DeserializeOwned,
+    M: Serialize + DeserializeOwned,
+    F: 'static + Send + FnOnce(&mut ChannelReader<M>, &mut ChannelWriter<M>) -> R,
+{
+    // SAFETY: Our caller guarantees the safety requirements of run_as_child_rpc.
+    unsafe { run_as_child_rpc(se_context, uid, gid, credentials, f) }
+}
+
+/// Like `run_as_child_with`, but the commands that the parent sends to the child and the
+/// responses that the child sends to the parent have distinct types. Together with
+/// `ChildHandle::call` and `serve`, this allows the parent to make typed remote procedure calls
+/// into the child, e.g., requests for keystore operations as the child's identity that return
+/// key descriptors or error codes.
+///
+/// # Safety
+/// See `run_as_child`.
+pub unsafe fn run_as_child_rpc<F, R, M, S>(
+    se_context: &str,
+    uid: Uid,
+    gid: Gid,
+    credentials: &Credentials,
+    f: F,
+) -> Result<ChildHandle<R, M, S>, nix::Error>
+where
+    R: Serialize + DeserializeOwned,
+    M: Serialize + DeserializeOwned,
+    S: Serialize + DeserializeOwned,
+    F: 'static + Send + FnOnce(&mut ChannelReader<M>, &mut ChannelWriter<S>) -> R,
 {
     let se_context =
         selinux::Context::new(se_context).expect("Unable to construct selinux::Context.");
-    let (result_reader, mut result_writer) = pipe_channel().expect("Failed to create pipe.");
+    let (result_reader, result_writer) = pipe_channel().expect("Failed to create pipe.");
     let (mut cmd_reader, cmd_writer) = pipe_channel().expect("Failed to create cmd pipe.");
     let (response_reader, mut response_writer) =
         pipe_channel().expect("Failed to create cmd pipe.");
@@ -252,11 +626,11 @@ where
             drop(cmd_reader);
             drop(result_writer);
 
-            Ok(ChildHandle::<R, M> {
+            Ok(ChildHandle::<R, M, S> {
                 pid: child,
                 result_reader,
                 response_reader,
-                cmd_writer,
+                cmd_writer: Some(cmd_writer),
                 exit_status: None,
             })
         }
@@ -265,17 +639,16 @@ where
             drop(response_reader);
             drop(result_reader);
 
-            // This will panic on error or insufficient privileges.
-            transition(se_context, uid, gid);
-
-            // Run the closure.
-            let result = f(&mut cmd_reader, &mut response_writer);
-
-            // Serialize the result of the closure.
-            result_writer.send(&result);
+            run_child(
+                || {
+                    // This will panic on error or insufficient privileges.
+                    transition(se_context, uid, gid, credentials);
 
-            // Set exit status to `0`.
-            std::process::exit(0);
+                    // Run the closure.
+                    f(&mut cmd_reader, &mut response_writer)
+                },
+                result_writer,
+            )
         }
         Err(errno) => {
             panic!("Failed to fork: {:?}", errno);
@@ -297,44 +670,54 @@ where
 /// if the parent initialized libbinder already. So do not use binder outside of the closure
 /// in your test.
 pub unsafe fn run_as<F, R>(se_context: &str, uid: Uid, gid: Gid, f: F) -> R
+where
+    R: Serialize + DeserializeOwned,
+    F: 'static + Send + FnOnce() -> R,
+{
+    // SAFETY: Our caller guarantees the safety requirements of run_as_with.
+    unsafe { run_as_with(se_context, uid, gid, &Credentials::default(), f) }
+}
+
+/// Like `run_as`, but additionally sets the supplementary groups and capabilities of the new
+/// identity as given by `credentials`.
+///
+/// # Safety
+/// See `run_as`.
+pub unsafe fn run_as_with<F, R>(
+    se_context: &str,
+    uid: Uid,
+    gid: Gid,
+    credentials: &Credentials,
+    f: F,
+) -> R
 where
     R: Serialize + DeserializeOwned,
     F: 'static + Send + FnOnce() -> R,
 {
     let se_context =
         selinux::Context::new(se_context).expect("Unable to construct selinux::Context.");
-    let (mut reader, mut writer) = pipe_channel::<R>().expect("Failed to create pipe.");
+    let (mut reader, writer) = pipe_channel::<ChildReport<R>>().expect("Failed to create pipe.");
 
     // SAFETY: Our caller guarantees that the process only has a single thread, so calling
     // non-async-signal-safe functions in the child is in fact safe.
     match unsafe { fork() } {
         Ok(ForkResult::Parent { child, .. }) => {
             drop(writer);
-            let status = waitpid(child, None).expect("Failed while waiting for child.");
-            if let WaitStatus::Exited(_, 0) = status {
-                // Child exited successfully.
-                // Read the result from the pipe.
-                // let serialized_result =
-                //     reader.read_all().expect("Failed to read result from child.");
-
-                // Deserialize the result and return it.
-                reader.recv()
-            } else {
-                panic!("Child did not exit as expected {:?}", status);
-            }
+            let (report, status) = wait_for_child(child, &mut reader);
+            child_result(report, status)
         }
         Ok(ForkResult::Child) => {
-            // This will panic on error or insufficient privileges.
-            transition(se_context, uid, gid);
-
-            // Run the closure.
-            let result = f();
-
-            // Serialize the result of the closure.
-            writer.send(&result);
-
-            // Set exit status to `0`.
-            std::process::exit(0);
+            drop(reader);
+            run_child(
+                || {
+                    // This will panic on error or insufficient privileges.
+                    transition(se_context, uid, gid, credentials);
+
+                    // Run the closure.
+                    f()
+                },
+                writer,
+            )
         }
         Err(errno) => {
             panic!("Failed to fork: {:?}", errno);
@@ -342,11 +725,12 @@ where
     }
 }
 
-#[cfg(test)]
+// These tests need root and SELinux, which host tests with the fake keystore do not have.
+#[cfg(all(test, not(feature = "fake_keystore")))]
 mod test {
     use super::*;
     use keystore2_selinux as selinux;
-    use nix::unistd::{getgid, getuid};
+    use nix::unistd::{getgid, getgroups, getuid};
     use serde::{Deserialize, Serialize};
 
     /// This test checks that the closure does not produce an exit status of `0` when run inside a
@@ -366,6 +750,32 @@ mod test {
         };
     }
 
+    /// Tests that the panic message and the output of the child are part of the parent's panic.
+    #[test]
+    fn test_run_as_propagates_panic_message_and_output() {
+        let result = std::panic::catch_unwind(|| {
+            // Safety: run_as must be called from a single threaded process.
+            // This device test is run as a separate single threaded process.
+            unsafe {
+                run_as::<_, ()>(
+                    selinux::getcon().unwrap().to_str().unwrap(),
+                    getuid(),
+                    getgid(),
+                    || {
+                        // Write to the file descriptor, because `eprintln!` is captured by the
+                        // test harness.
+                        std::io::stderr().write_all(b"Output of the child.\n").unwrap();
+                        panic!("Closure panics on purpose.");
+                    },
+                )
+            }
+        });
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("Closure panics on purpose."), "{message}");
+        assert!(message.contains("Exited("), "{message}");
+        assert!(message.contains("Output of the child."), "{message}");
+    }
+
     static TARGET_UID: Uid = Uid::from_raw(10020);
     static TARGET_GID: Gid = Gid::from_raw(10020);
     static TARGET_CTX: &str = "u:r:untrusted_app:s0:c91,c256,c10,c20";
@@ -384,6 +794,84 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_app_identity() {
+        let identity = AppIdentity::new(10, 10300, "u:r:untrusted_app:s0");
+        assert_eq!(identity.uid, Uid::from_raw(1_010_300));
+        assert_eq!(identity.gid, Gid::from_raw(1_010_300));
+        assert_eq!(identity.se_context, "u:r:untrusted_app:s0:c44,c257,c522,c768");
+
+        // Existing categories and contexts of system apps are kept.
+        assert_eq!(AppIdentity::new(10, 10020, TARGET_CTX).se_context, TARGET_CTX);
+        assert_eq!(AppIdentity::new(10, 1000, "u:r:system_app:s0").se_context, "u:r:system_app:s0");
+    }
+
+    /// Tests that the closure is running as the app in the secondary user, and that it can
+    /// exchange messages with the parent.
+    #[test]
+    fn test_run_as_app_in_user() {
+        const USER_ID: u32 = 10;
+        const APP_ID: u32 = 10020;
+        let expected = AppIdentity::new(USER_ID, APP_ID, "u:r:untrusted_app:s0");
+
+        // Safety: run_as_app_in_user must be called from a single threaded process.
+        // This device test is run as a separate single threaded process.
+        unsafe {
+            run_as_app_in_user(USER_ID, APP_ID, "u:r:untrusted_app:s0", move || {
+                assert_eq!(expected.uid, getuid());
+                assert_eq!(expected.gid, getgid());
+                assert_eq!(expected.se_context, selinux::getcon().unwrap().to_str().unwrap());
+            })
+        };
+
+        // Safety: run_as_app_in_user_child must be called from a single threaded process.
+        // This device test is run as a separate single threaded process.
+        let mut child_handle: ChildHandle<(), u32> = unsafe {
+            run_as_app_in_user_child(USER_ID, APP_ID, "u:r:untrusted_app:s0", |_, writer| {
+                writer.send(&getuid().as_raw());
+            })
+            .unwrap()
+        };
+        assert_eq!(child_handle.recv(), USER_ID * AID_USER_OFFSET + APP_ID);
+        child_handle.get_result();
+    }
+
+    /// Returns the effective capability set of the calling process.
+    fn effective_capabilities() -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find_map(|l| l.strip_prefix("CapEff:")).unwrap();
+        u64::from_str_radix(line.trim(), 16).unwrap()
+    }
+
+    /// Tests that the closure is running with the given supplementary groups and capabilities.
+    #[test]
+    fn test_transition_with_credentials() {
+        let credentials = Credentials {
+            groups: Some(vec![Gid::from_raw(3003), Gid::from_raw(1065)]),
+            capabilities: Some(vec![Capability::NET_ADMIN, Capability::BLOCK_SUSPEND]),
+        };
+        // Safety: run_as_with must be called from a single threaded process.
+        // This device test is run as a separate single threaded process.
+        unsafe {
+            run_as_with(TARGET_CTX, TARGET_UID, TARGET_GID, &credentials, || {
+                assert_eq!(TARGET_UID, getuid());
+                let mut groups = getgroups().unwrap();
+                groups.sort_by_key(|g| g.as_raw());
+                assert_eq!(groups, vec![Gid::from_raw(1065), Gid::from_raw(3003)]);
+                assert_eq!(effective_capabilities(), (1 << 12) | (1 << 36));
+            })
+        };
+
+        // Without credentials, all capabilities are dropped.
+        // Safety: run_as must be called from a single threaded process.
+        // This device test is run as a separate single threaded process.
+        unsafe {
+            run_as(TARGET_CTX, TARGET_UID, TARGET_GID, || {
+                assert_eq!(effective_capabilities(), 0);
+            })
+        };
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
     struct SomeResult {
         a: u32,
@@ -467,4 +955,48 @@ mod test {
 
         assert_eq!(child_handle.get_result(), test_result);
     }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+    enum Request {
+        Add(u32, u32),
+        WhoAmI,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+    enum Response {
+        Sum(u32),
+        Uid(u32),
+    }
+
+    /// Tests that the parent can make typed calls into a child that serves them.
+    #[test]
+    fn test_run_as_child_rpc() {
+        // Safety: run_as_child_rpc must be called from a single threaded process.
+        // This device test is run as a separate single threaded process.
+        let mut child_handle: ChildHandle<u32, Request, Response> = unsafe {
+            run_as_child_rpc(
+                TARGET_CTX,
+                TARGET_UID,
+                TARGET_GID,
+                &Credentials::default(),
+                |requests, responses| {
+                    let mut served = 0;
+                    serve(requests, responses, |request| {
+                        served += 1;
+                        match request {
+                            Request::Add(a, b) => Response::Sum(a + b),
+                            Request::WhoAmI => Response::Uid(getuid().as_raw()),
+                        }
+                    });
+                    served
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(child_handle.call(&Request::Add(2, 3)), Response::Sum(5));
+        assert_eq!(child_handle.call(&Request::WhoAmI), Response::Uid(TARGET_UID.as_raw()));
+        // Getting the result closes the command channel, which ends `serve`.
+        assert_eq!(child_handle.get_result(), 2);
+    }
 }
@@ -0,0 +1,54 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils to stop and start keystore2 through init. Controlling
+//! services requires root, so the tests using it must run as root.
+
+use anyhow::{Context, Result};
+use rustutils::system_properties::{self, PropertyWatcher};
+use std::time::Duration;
+
+/// The name of the keystore2 init service.
+const KEYSTORE2_SERVICE: &str = "keystore2";
+
+/// How long to wait for keystore2 to stop or start.
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sets the service state of keystore2 through init and waits until the state is reached.
+fn set_keystore2_state(control: &str, state: &str) -> Result<()> {
+    system_properties::write(control, KEYSTORE2_SERVICE)
+        .with_context(|| format!("Failed to set {control} to {KEYSTORE2_SERVICE}."))?;
+    let status_property = format!("init.svc.{KEYSTORE2_SERVICE}");
+    PropertyWatcher::new(&status_property)
+        .with_context(|| format!("Failed to watch {status_property}."))?
+        .wait_for_value(state, Some(RESTART_TIMEOUT))
+        .with_context(|| format!("{KEYSTORE2_SERVICE} did not reach state {state:?}."))
+}
+
+/// Stops keystore2 and waits until it has stopped.
+pub fn stop_keystore2() -> Result<()> {
+    set_keystore2_state("ctl.stop", "stopped")
+}
+
+/// Starts keystore2 and waits until it is running.
+pub fn start_keystore2() -> Result<()> {
+    set_keystore2_state("ctl.start", "running")
+}
+
+/// Restarts keystore2. Binder connections to keystore2 obtained before the restart are dead
+/// afterwards.
+pub fn restart_keystore2() -> Result<()> {
+    stop_keystore2()?;
+    start_keystore2()
+}
@@ -0,0 +1,348 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements a harness for stress tests of keystore operations, which finds
+//! pruning and locking bugs by running many operations concurrently. Each thread of a test
+//! signs with a key of its own in a loop, picking the kind of each operation from a configured
+//! mix, and the test reports the latency and errors of every kind of call. The sequence of
+//! operations of each thread is fully determined by the seed of the test, so that a test
+//! can be reproduced.
+//!
+//! ## Example:
+//!
+//! ```
+//! let config =
+//!     StressConfig { threads: 16, duration: Duration::from_secs(60), ..Default::default() };
+//! let report = stress::run(&SecLevel::tee(), &config)?;
+//! log::info!("{report}");
+//! assert_eq!(report.finish.error_count(), 0);
+//! ```
+
+use crate::authorizations::AuthSetBuilder;
+use crate::key_generations::{generate_ec_p256_signing_key, map_ks_error, Error};
+use crate::operation_workload::WorkloadGenerator;
+use crate::SecLevel;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Digest::Digest, KeyPurpose::KeyPurpose,
+};
+use android_system_keystore2::aidl::android::system::keystore2::{
+    CreateOperationResponse::CreateOperationResponse, Domain::Domain, KeyDescriptor::KeyDescriptor,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The kinds of operations of a stress test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    /// Creates an operation, calls update `StressConfig::updates` times, and finishes it.
+    Complete,
+    /// Creates an operation and aborts it.
+    Abort,
+    /// Creates an operation and drops it without finishing it, which leaves it to pruning.
+    Abandon,
+}
+
+/// The relative frequencies of the kinds of operations of a stress test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperationMix {
+    /// The weight of `OperationKind::Complete`.
+    pub complete: u32,
+    /// The weight of `OperationKind::Abort`.
+    pub abort: u32,
+    /// The weight of `OperationKind::Abandon`.
+    pub abandon: u32,
+}
+
+impl Default for OperationMix {
+    fn default() -> Self {
+        Self { complete: 8, abort: 1, abandon: 1 }
+    }
+}
+
+impl OperationMix {
+    /// Picks the kind of an operation using the random number `random`.
+    fn pick(&self, random: u64) -> OperationKind {
+        let total = self.complete as u64 + self.abort as u64 + self.abandon as u64;
+        assert!(total > 0, "An operation mix needs at least one non-zero weight.");
+        match random % total {
+            r if r < self.complete as u64 => OperationKind::Complete,
+            r if r < self.complete as u64 + self.abort as u64 => OperationKind::Abort,
+            _ => OperationKind::Abandon,
+        }
+    }
+}
+
+/// The configuration of a stress test.
+#[derive(Debug, Clone)]
+pub struct StressConfig {
+    /// The number of threads that run operations concurrently.
+    pub threads: usize,
+    /// How long the threads run operations.
+    pub duration: Duration,
+    /// The kinds of operations the threads run.
+    pub mix: OperationMix,
+    /// The number of update calls of each completed operation.
+    pub updates: usize,
+    /// The seed of the sequences of operations.
+    pub seed: u64,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self {
+            threads: 8,
+            duration: Duration::from_secs(10),
+            mix: Default::default(),
+            updates: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// The latencies and errors of the calls of one method.
+#[derive(Debug, Default, Clone)]
+pub struct CallStats {
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, usize>,
+}
+
+impl CallStats {
+    fn record<T>(&mut self, start: Instant, result: &Result<T, Error>) {
+        self.latencies.push(start.elapsed());
+        if let Err(e) = result {
+            *self.errors.entry(format!("{e:?}")).or_default() += 1;
+        }
+    }
+
+    fn merge(&mut self, other: CallStats) {
+        self.latencies.extend(other.latencies);
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+    }
+
+    /// Returns the number of calls.
+    pub fn calls(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Returns the number of calls that failed.
+    pub fn error_count(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    /// Returns the number of failed calls by error.
+    pub fn errors(&self) -> &BTreeMap<String, usize> {
+        &self.errors
+    }
+
+    /// Returns the `percentile`th percentile of the latencies, or None if there were no calls.
+    pub fn percentile(&self, percentile: usize) -> Option<Duration> {
+        assert!((1..=100).contains(&percentile), "Invalid percentile {percentile}.");
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let index = (latencies.len() * percentile).div_ceil(100).checked_sub(1)?;
+        latencies.get(index).copied()
+    }
+}
+
+impl fmt::Display for CallStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "calls={} errors={}", self.calls(), self.error_count())?;
+        if let (Some(p50), Some(p99), Some(max)) =
+            (self.percentile(50), self.percentile(99), self.percentile(100))
+        {
+            write!(f, " p50={p50:?} p99={p99:?} max={max:?}")?;
+        }
+        for (error, count) in &self.errors {
+            write!(f, "\n    {error}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a stress test.
+#[derive(Debug, Default, Clone)]
+pub struct StressReport {
+    /// The calls of createOperation.
+    pub create: CallStats,
+    /// The calls of IKeystoreOperation::update.
+    pub update: CallStats,
+    /// The calls of IKeystoreOperation::finish.
+    pub finish: CallStats,
+    /// The calls of IKeystoreOperation::abort.
+    pub abort: CallStats,
+    /// How long the test ran.
+    pub elapsed: Duration,
+}
+
+impl StressReport {
+    fn merge(&mut self, other: StressReport) {
+        self.create.merge(other.create);
+        self.update.merge(other.update);
+        self.finish.merge(other.finish);
+        self.abort.merge(other.abort);
+    }
+
+    /// Returns the number of failed calls of all methods.
+    pub fn error_count(&self) -> usize {
+        self.create.error_count()
+            + self.update.error_count()
+            + self.finish.error_count()
+            + self.abort.error_count()
+    }
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Stress test ran for {:?}.", self.elapsed)?;
+        writeln!(f, "createOperation: {}", self.create)?;
+        writeln!(f, "update: {}", self.update)?;
+        writeln!(f, "finish: {}", self.finish)?;
+        write!(f, "abort: {}", self.abort)
+    }
+}
+
+/// Runs operations with the key `key` on `sl` until `deadline`.
+fn run_thread(
+    sl: &SecLevel,
+    key: &KeyDescriptor,
+    config: &StressConfig,
+    seed: u64,
+    deadline: Instant,
+) -> StressReport {
+    let op_params = AuthSetBuilder::new().purpose(KeyPurpose::SIGN).digest(Digest::SHA_2_256);
+    let mut generator = WorkloadGenerator::new(seed);
+    let mut report = StressReport::default();
+    'operations: while Instant::now() < deadline {
+        let kind = config.mix.pick(generator.next_u64());
+        let start = Instant::now();
+        let result = map_ks_error(sl.binder.createOperation(key, &op_params, false));
+        report.create.record(start, &result);
+        let Ok(CreateOperationResponse { iOperation: Some(op), .. }) = result else {
+            continue;
+        };
+        match kind {
+            OperationKind::Complete => {
+                for _ in 0..config.updates {
+                    let start = Instant::now();
+                    let result = map_ks_error(op.update(b"keystore2 stress test"));
+                    report.update.record(start, &result);
+                    if result.is_err() {
+                        continue 'operations;
+                    }
+                }
+                let start = Instant::now();
+                let result = map_ks_error(op.finish(None, None));
+                report.finish.record(start, &result);
+            }
+            OperationKind::Abort => {
+                let start = Instant::now();
+                let result = map_ks_error(op.abort());
+                report.abort.record(start, &result);
+            }
+            OperationKind::Abandon => {}
+        }
+    }
+    report
+}
+
+fn stress_key(i: usize) -> KeyDescriptor {
+    KeyDescriptor {
+        domain: Domain::APP,
+        nspace: -1,
+        alias: Some(format!("ks_stress_test_key_{i}")),
+        blob: None,
+    }
+}
+
+fn run_threads(sl: &SecLevel, config: &StressConfig) -> Result<StressReport, Error> {
+    for i in 0..config.threads {
+        map_ks_error(generate_ec_p256_signing_key(sl, Domain::APP, -1, stress_key(i).alias, None))?;
+    }
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let mut report = std::thread::scope(|s| {
+        let threads: Vec<_> = (0..config.threads)
+            .map(|i| {
+                let seed = config.seed.wrapping_add(i as u64);
+                s.spawn(move || run_thread(sl, &stress_key(i), config, seed, deadline))
+            })
+            .collect();
+        threads.into_iter().fold(StressReport::default(), |mut report, thread| {
+            report.merge(thread.join().expect("Stress test thread panicked."));
+            report
+        })
+    });
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Runs a stress test on `sl` as configured by `config`. The test generates a signing key for
+/// each thread and deletes the keys when it is done.
+pub fn run(sl: &SecLevel, config: &StressConfig) -> Result<StressReport, Error> {
+    let result = run_threads(sl, config);
+    for i in 0..config.threads {
+        let _ = sl.keystore2.deleteKey(&stress_key(i));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::ErrorCode::ErrorCode;
+
+    #[test]
+    fn test_operation_mix() {
+        let mix = OperationMix { complete: 2, abort: 0, abandon: 1 };
+        let kinds: Vec<_> = (0..6).map(|r| mix.pick(r)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                OperationKind::Complete,
+                OperationKind::Complete,
+                OperationKind::Abandon,
+                OperationKind::Complete,
+                OperationKind::Complete,
+                OperationKind::Abandon,
+            ]
+        );
+        let mix = OperationMix { complete: 0, abort: 1, abandon: 0 };
+        assert!((0..10).all(|r| mix.pick(r) == OperationKind::Abort));
+    }
+
+    #[test]
+    fn test_call_stats() {
+        let mut stats = CallStats::default();
+        assert_eq!(stats.percentile(50), None);
+        stats.latencies = (1..=100).map(Duration::from_millis).collect();
+        stats.errors.insert("Rc(ResponseCode(2))".to_string(), 3);
+        assert_eq!(stats.percentile(100), Some(Duration::from_millis(100)));
+        assert_eq!(stats.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(stats.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(stats.percentile(1), Some(Duration::from_millis(1)));
+
+        let mut other = CallStats::default();
+        other.record::<()>(Instant::now(), &Err(Error::Km(ErrorCode::TOO_MANY_OPERATIONS)));
+        other.record::<()>(Instant::now(), &Ok(()));
+        stats.merge(other);
+
+        assert_eq!(stats.calls(), 102);
+        assert_eq!(stats.error_count(), 4);
+        assert_eq!(stats.errors().len(), 2);
+        assert_eq!(stats.percentile(100), Some(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils around IKeystoreTestHooks, the interface through which
+//! tests change the behavior of keystore2. The service only exists on debuggable builds, and
+//! the caller needs the keystore2 permission `test_hooks`, which tests running as root have.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::ErrorCode::ErrorCode;
+use android_security_testhooks::aidl::android::security::testhooks::IKeystoreTestHooks::IKeystoreTestHooks;
+use anyhow::{Context, Result};
+
+/// The name of the IKeystoreTestHooks service. Must be kept in sync with keystore2's test_hooks
+/// module.
+const TEST_HOOKS_SERVICE_NAME: &str = "android.security.testhooks";
+
+/// Returns the IKeystoreTestHooks service, or None if keystore2 was built without test hooks.
+pub fn get_test_hooks() -> Option<binder::Strong<dyn IKeystoreTestHooks>> {
+    binder::check_interface(TEST_HOOKS_SERVICE_NAME).ok()
+}
+
+/// Returns true if keystore2 has test hooks on this build.
+pub fn is_supported() -> bool {
+    get_test_hooks().is_some()
+}
+
+fn test_hooks() -> Result<binder::Strong<dyn IKeystoreTestHooks>> {
+    get_test_hooks().context("keystore2 has no test hooks on this build.")
+}
+
+/// Injects an error into calls of a HAL method for as long as it is alive. Dropping it removes
+/// the error if it was not consumed yet.
+pub struct HalError {
+    method: String,
+}
+
+impl HalError {
+    /// Makes the next `count` calls of the HAL method `method`, e.g., "IKeyMintDevice::begin",
+    /// fail with `error` without reaching the HAL. Replaces any error injected into the method
+    /// before.
+    pub fn inject(method: &str, error: ErrorCode, count: i32) -> Result<Self> {
+        test_hooks()?
+            .injectHalError(method, error.0, count)
+            .with_context(|| format!("Failed to inject {error:?} into {method}."))?;
+        Ok(Self { method: method.to_string() })
+    }
+}
+
+impl Drop for HalError {
+    fn drop(&mut self) {
+        if let Err(e) = test_hooks().and_then(|hooks| {
+            hooks.injectHalError(&self.method, ErrorCode::UNKNOWN_ERROR.0, 0).map_err(Into::into)
+        }) {
+            log::error!("Failed to remove the error injected into {}: {e:?}", self.method);
+        }
+    }
+}
+
+/// Advances the boot time clock of keystore2 by `millis` milliseconds, so that auth tokens age
+/// without waiting. The clock stays advanced until keystore2 restarts or `reset` is called.
+pub fn advance_clock(millis: i64) -> Result<()> {
+    test_hooks()?
+        .advanceClock(millis)
+        .with_context(|| format!("Failed to advance the clock by {millis} ms."))
+}
+
+/// Makes keystore2 upgrade the blob of the key `key_id` the next time it passes the blob to
+/// KeyMint, as if KeyMint required the upgrade. Must not be used with rollback resistant keys.
+pub fn force_key_upgrade(key_id: i64) -> Result<()> {
+    test_hooks()?
+        .forceKeyUpgrade(key_id)
+        .with_context(|| format!("Failed to force an upgrade of key {key_id}."))
+}
+
+/// Removes all test hooks from keystore2.
+pub fn reset() -> Result<()> {
+    test_hooks()?.reset().context("Failed to reset the test hooks.")
+}
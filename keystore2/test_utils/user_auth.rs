@@ -0,0 +1,256 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements test utils for keys that are bound to user authentication, i.e., with
+//! USER_SECURE_ID or UNLOCKED_DEVICE_REQUIRED. `AuthTokenBuilder` mints HardwareAuthTokens, and
+//! `UserAuth` plays the part of the lock screen and Gatekeeper of a user: it locks and unlocks
+//! the device and delivers the auth tokens of successful authentications through
+//! IKeystoreAuthorization.
+//!
+//! KeyMint only accepts auth tokens that are signed with the HMAC key it shares with the
+//! authenticators, which tests do not know. Tokens with a placeholder MAC are good enough for
+//! checks done by keystore2 itself, e.g., whether the device is unlocked. Tests that need KeyMint
+//! to accept the tokens must run against an implementation whose HMAC key they know, and set the
+//! key with `UserAuth::with_mac_key`.
+
+use crate::determinism::boot_time_millis;
+use crate::get_keystore_auth_service;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
+};
+use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::Timestamp::Timestamp;
+use android_security_authorization::aidl::android::security::authorization::IKeystoreAuthorization::IKeystoreAuthorization;
+use anyhow::{Context, Result};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// The MAC of auth tokens that are not signed.
+const PLACEHOLDER_MAC: [u8; 32] = [0; 32];
+
+/// Computes the MAC of `token` with the HMAC key `key` as specified by HardwareAuthToken.aidl.
+pub fn auth_token_mac(token: &HardwareAuthToken, key: &[u8]) -> Result<Vec<u8>> {
+    let mut data = vec![0u8];
+    data.extend_from_slice(&token.challenge.to_ne_bytes());
+    data.extend_from_slice(&token.userId.to_ne_bytes());
+    data.extend_from_slice(&token.authenticatorId.to_ne_bytes());
+    data.extend_from_slice(&token.authenticatorType.0.to_be_bytes());
+    data.extend_from_slice(&token.timestamp.milliSeconds.to_be_bytes());
+
+    let key = PKey::hmac(key).context("Failed to create HMAC key.")?;
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &key).context("Failed to create signer.")?;
+    signer.update(&data).context("Failed to compute MAC.")?;
+    signer.sign_to_vec().context("Failed to compute MAC.")
+}
+
+/// Builds HardwareAuthTokens. The timestamp defaults to the current time.
+pub struct AuthTokenBuilder(HardwareAuthToken);
+
+impl AuthTokenBuilder {
+    /// Creates a builder for a token of an authentication of the user with the secure user id
+    /// `user_sid` with an authenticator of type `authenticator_type`.
+    pub fn new(user_sid: i64, authenticator_type: HardwareAuthenticatorType) -> Self {
+        Self(HardwareAuthToken {
+            challenge: 0,
+            userId: user_sid,
+            authenticatorId: 0,
+            authenticatorType: authenticator_type,
+            timestamp: Timestamp { milliSeconds: boot_time_millis() },
+            mac: vec![],
+        })
+    }
+
+    /// Sets the challenge, i.e., the id of the operation that the authentication authorizes.
+    pub fn challenge(mut self, challenge: i64) -> Self {
+        self.0.challenge = challenge;
+        self
+    }
+
+    /// Sets the authenticator id, i.e., the secure id of the biometric enrollment.
+    pub fn authenticator_id(mut self, authenticator_id: i64) -> Self {
+        self.0.authenticatorId = authenticator_id;
+        self
+    }
+
+    /// Sets the timestamp in milliseconds of CLOCK_BOOTTIME.
+    pub fn timestamp(mut self, milli_seconds: i64) -> Self {
+        self.0.timestamp = Timestamp { milliSeconds: milli_seconds };
+        self
+    }
+
+    /// Returns the token with a placeholder MAC, which KeyMint rejects.
+    pub fn build(mut self) -> HardwareAuthToken {
+        self.0.mac = PLACEHOLDER_MAC.to_vec();
+        self.0
+    }
+
+    /// Returns the token signed with the HMAC key `key`.
+    pub fn sign(mut self, key: &[u8]) -> Result<HardwareAuthToken> {
+        self.0.mac = auth_token_mac(&self.0, key)?;
+        Ok(self.0)
+    }
+}
+
+/// Simulates the lock screen and Gatekeeper of a user. The user must exist and have a password,
+/// see IKeystoreMaintenance::initUserSuperKeys.
+pub struct UserAuth {
+    user_id: i32,
+    password: Vec<u8>,
+    gk_sid: i64,
+    biometric_sids: Vec<i64>,
+    mac_key: Option<Vec<u8>>,
+    auth: binder::Strong<dyn IKeystoreAuthorization>,
+}
+
+impl UserAuth {
+    /// Creates a simulation for the user `user_id` whose password is `password` and whose
+    /// Gatekeeper secure user id is `gk_sid`.
+    pub fn new(user_id: i32, password: &[u8], gk_sid: i64) -> Self {
+        Self {
+            user_id,
+            password: password.to_vec(),
+            gk_sid,
+            biometric_sids: vec![],
+            mac_key: None,
+            auth: get_keystore_auth_service(),
+        }
+    }
+
+    /// Sets the secure user ids of the biometrics that the user has enrolled.
+    pub fn with_biometric_sids(mut self, sids: &[i64]) -> Self {
+        self.biometric_sids = sids.to_vec();
+        self
+    }
+
+    /// Signs the delivered auth tokens with the HMAC key `key` instead of a placeholder MAC.
+    pub fn with_mac_key(mut self, key: &[u8]) -> Self {
+        self.mac_key = Some(key.to_vec());
+        self
+    }
+
+    /// Returns the current Gatekeeper secure user id of the user.
+    pub fn gk_sid(&self) -> i64 {
+        self.gk_sid
+    }
+
+    fn token(&self, builder: AuthTokenBuilder) -> Result<HardwareAuthToken> {
+        match &self.mac_key {
+            Some(key) => builder.sign(key),
+            None => Ok(builder.build()),
+        }
+    }
+
+    fn add_auth_token(&self, builder: AuthTokenBuilder) -> Result<()> {
+        let token = self.token(builder)?;
+        self.auth.addAuthToken(&token).context("Failed to add auth token.")
+    }
+
+    /// Locks the device. If `weak_unlock_enabled`, the user may unlock it again with a biometric
+    /// or trust agent, see `unlock_weak`.
+    pub fn lock(&self, weak_unlock_enabled: bool) -> Result<()> {
+        self.auth
+            .onDeviceLocked(self.user_id, &self.biometric_sids, weak_unlock_enabled)
+            .context("Failed to lock the device.")
+    }
+
+    /// Unlocks the device with the password, and delivers the auth token that Gatekeeper issues
+    /// when it verifies the password.
+    pub fn unlock_with_password(&self) -> Result<()> {
+        self.auth
+            .onDeviceUnlocked(self.user_id, Some(&self.password))
+            .context("Failed to unlock the device with the password.")?;
+        self.authenticate_with_password(0)
+    }
+
+    /// Unlocks the device without the password, e.g., with a biometric or trust agent. This only
+    /// works if the device was locked with weak unlock enabled.
+    pub fn unlock_weak(&self) -> Result<()> {
+        self.auth.onDeviceUnlocked(self.user_id, None).context("Failed to unlock the device.")
+    }
+
+    /// Delivers the auth token of a successful password verification for the operation with the
+    /// challenge `challenge`, or 0 if it is not bound to an operation.
+    pub fn authenticate_with_password(&self, challenge: i64) -> Result<()> {
+        self.add_auth_token(
+            AuthTokenBuilder::new(self.gk_sid, HardwareAuthenticatorType::PASSWORD)
+                .challenge(challenge),
+        )
+    }
+
+    /// Delivers the auth token of a successful authentication with the biometric with the
+    /// secure user id `sid` for the operation with the challenge `challenge`, or 0 if it is not
+    /// bound to an operation.
+    pub fn authenticate_with_biometric(&self, sid: i64, challenge: i64) -> Result<()> {
+        self.add_auth_token(
+            AuthTokenBuilder::new(sid, HardwareAuthenticatorType::FINGERPRINT)
+                .authenticator_id(sid)
+                .challenge(challenge),
+        )
+    }
+
+    /// Gives the user a new Gatekeeper secure user id, as happens when the password is removed
+    /// and set again. Keys that are bound to the old id can no longer be authorized.
+    pub fn change_gk_sid(&mut self, gk_sid: i64) {
+        self.gk_sid = gk_sid;
+    }
+
+    /// Makes the biometric and trust agent unlock methods of the user expire, so that the device
+    /// can only be unlocked with the password until it is locked with weak unlock enabled again.
+    pub fn expire_weak_unlock(&self) -> Result<()> {
+        self.auth
+            .onWeakUnlockMethodsExpired(self.user_id)
+            .context("Failed to expire the weak unlock methods.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"auth token test hmac key";
+
+    #[test]
+    fn test_auth_token_mac() {
+        let token = AuthTokenBuilder::new(1234, HardwareAuthenticatorType::PASSWORD)
+            .challenge(42)
+            .timestamp(1000)
+            .sign(KEY)
+            .unwrap();
+        assert_eq!(token.mac.len(), 32);
+        assert_eq!(token.mac, auth_token_mac(&token, KEY).unwrap());
+
+        // The MAC covers every field.
+        let mut other = token.clone();
+        other.timestamp = Timestamp { milliSeconds: 1001 };
+        assert_ne!(auth_token_mac(&other, KEY).unwrap(), token.mac);
+        let mut other = token.clone();
+        other.authenticatorType = HardwareAuthenticatorType::FINGERPRINT;
+        assert_ne!(auth_token_mac(&other, KEY).unwrap(), token.mac);
+        assert_ne!(auth_token_mac(&token, b"another key").unwrap(), token.mac);
+    }
+
+    #[test]
+    fn test_build() {
+        let before = boot_time_millis();
+        let token = AuthTokenBuilder::new(1234, HardwareAuthenticatorType::FINGERPRINT)
+            .authenticator_id(5678)
+            .build();
+        assert_eq!(token.userId, 1234);
+        assert_eq!(token.authenticatorId, 5678);
+        assert_eq!(token.challenge, 0);
+        assert!(token.timestamp.milliSeconds >= before);
+        assert_eq!(token.mac, PLACEHOLDER_MAC);
+    }
+}
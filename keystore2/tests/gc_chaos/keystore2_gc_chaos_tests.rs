@@ -0,0 +1,231 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chaos test for the interaction of key deletion and garbage collection. Keys are replaced,
+//! which supersedes their old blobs, and then deleted by several threads at once while another
+//! thread keeps triggering garbage collection passes and keystore2 gets restarted underneath
+//! them. Afterwards, the test checks the state of the database against KeyMint:
+//!  * Garbage collection drains completely, so no blob was leaked in the database.
+//!  * Deleted keys are gone.
+//!  * Every remaining key can still be used in KeyMint, so garbage collection did not delete
+//!    a blob that is still referenced.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Digest::Digest, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
+};
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::IKeystoreMaintenance;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, IKeystoreService::IKeystoreService, KeyDescriptor::KeyDescriptor,
+    ResponseCode::ResponseCode,
+};
+use keystore2_test_utils::{
+    authorizations::AuthSetBuilder, key_generations, key_generations::Error, service_control,
+    SecLevel,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+static KS2_SERVICE_NAME: &str = "android.system.keystore2.IKeystoreService/default";
+static MAINTENANCE_SERVICE_NAME: &str = "android.security.maintenance";
+
+/// Number of keys per round. Every other key gets deleted.
+const NUM_KEYS: usize = 24;
+/// Number of threads deleting keys. Every deleted key is deleted by two of them.
+const NUM_DELETERS: usize = 4;
+/// Number of rounds. keystore2 is restarted once per round, at a different point each time.
+const ROUNDS: usize = 3;
+/// Number of blobs processed by each garbage collection pass while keys are being deleted.
+const CHAOS_GC_MAX_BLOBS: i32 = 2;
+/// Number of blobs processed by each garbage collection pass when draining.
+const DRAIN_GC_MAX_BLOBS: i32 = 64;
+/// Maximum number of garbage collection passes to drain the garbage collector.
+const DRAIN_MAX_PASSES: usize = 50;
+/// Maximum number of attempts to reach keystore2 while it is being restarted.
+const MAX_ATTEMPTS: usize = 100;
+/// Time between attempts to reach keystore2.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+fn key_descriptor(round: usize, index: usize) -> KeyDescriptor {
+    KeyDescriptor {
+        domain: Domain::SELINUX,
+        nspace: key_generations::SELINUX_SHELL_NAMESPACE,
+        alias: Some(format!("gc_chaos_{round}_{index}")),
+        blob: None,
+    }
+}
+
+fn is_deleted(index: usize) -> bool {
+    index % 2 == 0
+}
+
+/// Runs `f` with fresh connections to keystore2, retrying as long as keystore2 cannot be
+/// reached, e.g., because it is being restarted.
+fn retry_while_unreachable<T, S: ?Sized + binder::FromIBinder>(
+    service_name: &str,
+    f: impl Fn(&binder::Strong<S>) -> binder::Result<T>,
+) -> Result<T, Error> {
+    for _ in 0..MAX_ATTEMPTS {
+        let result = binder::wait_for_interface::<S>(service_name)
+            .map_err(binder::Status::from)
+            .and_then(|service| f(&service));
+        match key_generations::map_ks_error(result) {
+            Err(Error::Binder(_)) => thread::sleep(RETRY_INTERVAL),
+            result => return result,
+        }
+    }
+    panic!("{service_name} could not be reached.");
+}
+
+fn with_keystore2<T>(f: impl Fn(&SecLevel) -> binder::Result<T>) -> Result<T, Error> {
+    retry_while_unreachable(KS2_SERVICE_NAME, |keystore2: &binder::Strong<dyn IKeystoreService>| {
+        let level = SecurityLevel::TRUSTED_ENVIRONMENT;
+        let binder = keystore2.getSecurityLevel(level)?;
+        f(&SecLevel { keystore2: keystore2.clone(), binder, level })
+    })
+}
+
+fn with_maintenance<T>(
+    f: impl Fn(&binder::Strong<dyn IKeystoreMaintenance>) -> binder::Result<T>,
+) -> Result<T, Error> {
+    retry_while_unreachable(MAINTENANCE_SERVICE_NAME, f)
+}
+
+/// Runs garbage collection passes until no blobs are left.
+fn drain_gc() {
+    for _ in 0..DRAIN_MAX_PASSES {
+        let result = with_maintenance(|maint| maint.runGarbageCollection(DRAIN_GC_MAX_BLOBS))
+            .expect("Garbage collection pass failed.");
+        if result.blobsRemaining == 0 {
+            return;
+        }
+    }
+    panic!("Garbage collection did not drain after {DRAIN_MAX_PASSES} passes.");
+}
+
+/// Generates the key twice, so that the first blob is superseded and left to the garbage
+/// collector.
+fn generate_superseded_key(key: &KeyDescriptor) {
+    for _ in 0..2 {
+        with_keystore2(|sl| {
+            key_generations::generate_ec_p256_signing_key(
+                sl,
+                key.domain,
+                key.nspace,
+                key.alias.clone(),
+                None,
+            )
+        })
+        .expect("Failed to generate key.");
+    }
+}
+
+/// Deletes the key. The key may already have been deleted by another thread, or the deletion
+/// may have completed before a restart interrupted the call.
+fn delete_key(key: &KeyDescriptor) {
+    match with_keystore2(|sl| sl.keystore2.deleteKey(key)) {
+        Ok(()) | Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)) => {}
+        Err(e) => panic!("Failed to delete {key:?}: {e:?}"),
+    }
+}
+
+/// Uses the key for a signing operation, which requires KeyMint to still know its blob.
+fn sign_with_key(key: &KeyDescriptor) -> Result<(), Error> {
+    with_keystore2(|sl| {
+        let op = sl
+            .binder
+            .createOperation(
+                key,
+                &AuthSetBuilder::new().purpose(KeyPurpose::SIGN).digest(Digest::SHA_2_256),
+                false,
+            )?
+            .iOperation
+            .expect("No operation created.");
+        op.update(b"gc chaos")?;
+        op.finish(None, None)
+    })
+    .map(|signature| assert!(signature.is_some()))
+}
+
+fn run_round(round: usize) {
+    for index in 0..NUM_KEYS {
+        generate_superseded_key(&key_descriptor(round, index));
+    }
+
+    let stop_gc = Arc::new(AtomicBool::new(false));
+    let gc = {
+        let stop_gc = stop_gc.clone();
+        thread::spawn(move || {
+            while !stop_gc.load(Ordering::Relaxed) {
+                // Passes fail while keystore2 is restarting; the next pass will catch up.
+                let _ = with_maintenance(|maint| maint.runGarbageCollection(CHAOS_GC_MAX_BLOBS));
+            }
+        })
+    };
+    // Restart keystore2 at a different point of the deletions in every round.
+    let restarter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(30 * round as u64));
+        service_control::restart_keystore2().expect("Failed to restart keystore2.");
+    });
+    // Every deleted key is deleted concurrently by two threads.
+    let deleters: Vec<_> = (0..NUM_DELETERS)
+        .map(|deleter| {
+            thread::spawn(move || {
+                for index in (0..NUM_KEYS).filter(|i| is_deleted(*i)) {
+                    if (index / 2) % (NUM_DELETERS / 2) == deleter % (NUM_DELETERS / 2) {
+                        delete_key(&key_descriptor(round, index));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for deleter in deleters {
+        deleter.join().expect("Deleter panicked.");
+    }
+    restarter.join().expect("Restarter panicked.");
+    stop_gc.store(true, Ordering::Relaxed);
+    gc.join().expect("Garbage collector thread panicked.");
+
+    drain_gc();
+
+    for index in 0..NUM_KEYS {
+        let key = key_descriptor(round, index);
+        if is_deleted(index) {
+            assert_eq!(
+                with_keystore2(|sl| sl.keystore2.getKeyEntry(&key)).map(|_| ()),
+                Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)),
+                "Deleted key {key:?} still exists."
+            );
+        } else {
+            sign_with_key(&key).unwrap_or_else(|e| panic!("Remaining key {key:?} broke: {e:?}"));
+        }
+    }
+
+    for index in (0..NUM_KEYS).filter(|i| !is_deleted(*i)) {
+        delete_key(&key_descriptor(round, index));
+    }
+    drain_gc();
+}
+
+/// Deletes keys, runs garbage collection, and restarts keystore2 concurrently, and checks that
+/// no blob was leaked, no deleted key survived, and no remaining key lost its blob.
+#[test]
+fn keystore2_gc_concurrent_deletion_chaos_test() {
+    drain_gc();
+    for round in 0..ROUNDS {
+        run_round(round);
+    }
+}
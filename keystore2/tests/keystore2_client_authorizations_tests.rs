@@ -26,10 +26,7 @@ use android_hardware_security_keymint::aidl::android::hardware::security::keymin
     SecurityLevel::SecurityLevel, Tag::Tag,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
-};
-use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::{
-    Timestamp::Timestamp
+    HardwareAuthenticatorType::HardwareAuthenticatorType,
 };
 use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, KeyDescriptor::KeyDescriptor, KeyMetadata::KeyMetadata,
@@ -38,7 +35,7 @@ use android_system_keystore2::aidl::android::system::keystore2::{
 use keystore2_test_utils::ffi_test_utils::get_value_from_attest_record;
 use keystore2_test_utils::{
     authorizations, get_keystore_auth_service, key_generations,
-    key_generations::Error, SecLevel,
+    key_generations::Error, user_auth::AuthTokenBuilder, SecLevel,
 };
 use openssl::bn::{BigNum, MsbOption};
 use openssl::x509::X509NameBuilder;
@@ -867,14 +864,7 @@ fn keystore2_gen_attestation_key_with_auth_app_id_app_data_test_fail() {
 fn add_hardware_token(auth_type: HardwareAuthenticatorType) {
     let keystore_auth = get_keystore_auth_service();
 
-    let token = HardwareAuthToken {
-        challenge: 0,
-        userId: 0,
-        authenticatorId: 0,
-        authenticatorType: auth_type,
-        timestamp: Timestamp { milliSeconds: 500 },
-        mac: vec![],
-    };
+    let token = AuthTokenBuilder::new(0, auth_type).timestamp(500).build();
     keystore_auth.addAuthToken(&token).unwrap();
 }
 
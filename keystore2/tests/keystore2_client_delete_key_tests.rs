@@ -17,15 +17,13 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
 };
 use keystore2_test_utils::{
-    get_keystore_service, key_generations, key_generations::Error, SecLevel,
+    for_each_level, get_keystore_service, key_generations, key_generations::Error, SecLevel,
 };
 use nix::unistd::getuid;
 
 /// Generate a key and delete it using keystore2 service `deleteKey` API. Test should successfully
 /// delete the generated key.
-#[test]
-fn keystore2_delete_key_success() {
-    let sl = SecLevel::tee();
+for_each_level!(keystore2_delete_key_success, |sl| {
     let alias = "delete_key_success_key";
 
     let key_metadata = key_generations::generate_ec_p256_signing_key(
@@ -43,7 +41,7 @@ fn keystore2_delete_key_success() {
     let result = key_generations::map_ks_error(sl.keystore2.getKeyEntry(&key_metadata.key));
     assert!(result.is_err());
     assert_eq!(Error::Rc(ResponseCode::KEY_NOT_FOUND), result.unwrap_err());
-}
+});
 
 /// Try to delete non-existing key with domain other than BLOB using keystore2 service `deleteKey`
 /// API. Test should fail with an error code `KEY_NOT_FOUND`.
@@ -749,3 +749,127 @@ fn keystore2_grant_key_to_multi_users_delete_fails_with_key_not_found_error() {
         )
     };
 }
+
+/// Grant a key to the user with only `USE` access. In grantee context the key entry should load
+/// without its certificates, and the grantee should be able to perform a crypto operation with
+/// the granted key.
+#[test]
+fn keystore2_grant_use_key_perm_withholds_certificates() {
+    static GRANTOR_SU_CTX: &str = "u:r:su:s0";
+    static GRANTEE_CTX: &str = "u:r:untrusted_app:s0:c91,c256,c10,c20";
+    const USER_ID: u32 = 99;
+    const APPLICATION_ID: u32 = 10001;
+    static GRANTEE_UID: u32 = USER_ID * AID_USER_OFFSET + APPLICATION_ID;
+    static GRANTEE_GID: u32 = GRANTEE_UID;
+    static ALIAS: &str = "ks_grant_use_only_key";
+
+    // Generate a key and grant it to a user with USE permission.
+    // SAFETY: The test is run in a separate process with no other threads.
+    let grant_key_nspace = unsafe {
+        run_as::run_as(GRANTOR_SU_CTX, Uid::from_raw(0), Gid::from_raw(0), || {
+            let sl = SecLevel::tee();
+            let mut grant_keys = generate_ec_key_and_grant_to_users(
+                &sl,
+                Some(ALIAS.to_string()),
+                vec![GRANTEE_UID.try_into().unwrap()],
+                KeyPermission::USE.0,
+            )
+            .unwrap();
+
+            grant_keys.remove(0)
+        })
+    };
+
+    // Grantee context, load the key without certificates and use it.
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe {
+        run_as::run_as(
+            GRANTEE_CTX,
+            Uid::from_raw(GRANTEE_UID),
+            Gid::from_raw(GRANTEE_GID),
+            move || {
+                let sl = SecLevel::tee();
+                let key_entry_response = sl
+                    .keystore2
+                    .getKeyEntry(&KeyDescriptor {
+                        domain: Domain::GRANT,
+                        nspace: grant_key_nspace,
+                        alias: None,
+                        blob: None,
+                    })
+                    .unwrap();
+                assert!(key_entry_response.metadata.certificate.is_none());
+                assert!(key_entry_response.metadata.certificateChain.is_none());
+                assert!(!key_entry_response.metadata.authorizations.is_empty());
+
+                assert_eq!(
+                    Ok(()),
+                    key_generations::map_ks_error(load_grant_key_and_perform_sign_operation(
+                        &sl,
+                        grant_key_nspace
+                    ))
+                );
+            },
+        )
+    };
+}
+
+/// Grant a key to the user with only `GET_INFO` access. In grantee context the key entry should
+/// load with its certificate, but creating an operation with the granted key should fail with
+/// `PERMISSION_DENIED`.
+#[test]
+fn keystore2_grant_get_info_key_perm_denies_use() {
+    static GRANTOR_SU_CTX: &str = "u:r:su:s0";
+    static GRANTEE_CTX: &str = "u:r:untrusted_app:s0:c91,c256,c10,c20";
+    const USER_ID: u32 = 99;
+    const APPLICATION_ID: u32 = 10001;
+    static GRANTEE_UID: u32 = USER_ID * AID_USER_OFFSET + APPLICATION_ID;
+    static GRANTEE_GID: u32 = GRANTEE_UID;
+    static ALIAS: &str = "ks_grant_get_info_only_key";
+
+    // Generate a key and grant it to a user with GET_INFO permission.
+    // SAFETY: The test is run in a separate process with no other threads.
+    let grant_key_nspace = unsafe {
+        run_as::run_as(GRANTOR_SU_CTX, Uid::from_raw(0), Gid::from_raw(0), || {
+            let sl = SecLevel::tee();
+            let mut grant_keys = generate_ec_key_and_grant_to_users(
+                &sl,
+                Some(ALIAS.to_string()),
+                vec![GRANTEE_UID.try_into().unwrap()],
+                KeyPermission::GET_INFO.0,
+            )
+            .unwrap();
+
+            grant_keys.remove(0)
+        })
+    };
+
+    // Grantee context, load the key with its certificate and try to use it.
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe {
+        run_as::run_as(
+            GRANTEE_CTX,
+            Uid::from_raw(GRANTEE_UID),
+            Gid::from_raw(GRANTEE_GID),
+            move || {
+                let sl = SecLevel::tee();
+                let key_entry_response = sl
+                    .keystore2
+                    .getKeyEntry(&KeyDescriptor {
+                        domain: Domain::GRANT,
+                        nspace: grant_key_nspace,
+                        alias: None,
+                        blob: None,
+                    })
+                    .unwrap();
+                assert!(key_entry_response.metadata.certificate.is_some());
+
+                let result = key_generations::map_ks_error(
+                    load_grant_key_and_perform_sign_operation(&sl, grant_key_nspace),
+                );
+                assert!(result.is_err());
+                assert_eq!(Error::Rc(ResponseCode::PERMISSION_DENIED), result.unwrap_err());
+            },
+        )
+    };
+}
@@ -322,6 +322,43 @@ fn keystore2_import_ec_key_success() {
     perform_sample_asym_sign_verify_op(&sl.binder, &key_metadata, None, Some(Digest::SHA_2_256));
 }
 
+/// Import an EC key given as a JSON Web Key, which keystore translates into PKCS#8 for KeyMint.
+/// Try to create an operation using the imported key. Test should be able to create an operation
+/// successfully.
+#[test]
+fn keystore2_import_ec_key_from_jwk_success() {
+    let sl = SecLevel::tee();
+
+    let alias = format!("ks_ec_key_test_import_jwk_{}{}", getuid(), 256);
+    let jwk = r#"{"kty":"EC","crv":"P-256",
+        "d":"NHd7h5IHSG2ZcNKadk3iL4knZ8b3D96uOZ2CakuTpHE",
+        "x":"jm8NOc3NzT8brrCxi8abAOHw-eVDMxn98rLmYOXtJ8M",
+        "y":"eiu8Bve8b6Gq4aP7PMIkluQfzPGmntysspvV8lI-bjk"}"#;
+
+    let import_params = authorizations::AuthSetBuilder::new()
+        .no_auth_required()
+        .algorithm(Algorithm::EC)
+        .ec_curve(EcCurve::P_256)
+        .digest(Digest::SHA_2_256)
+        .purpose(KeyPurpose::SIGN)
+        .purpose(KeyPurpose::VERIFY)
+        .cert_not_before(0)
+        .cert_not_after(253402300799000);
+
+    let key_metadata = sl
+        .binder
+        .importKey(
+            &KeyDescriptor { domain: Domain::APP, nspace: -1, alias: Some(alias), blob: None },
+            None,
+            &import_params,
+            0,
+            jwk.as_bytes(),
+        )
+        .expect("Failed to import JWK.");
+
+    perform_sample_asym_sign_verify_op(&sl.binder, &key_metadata, None, Some(Digest::SHA_2_256));
+}
+
 /// Try to import EC key with wrong ec-curve as import-key-parameter. Test should fail to import a
 /// key with `IMPORT_PARAMETER_MISMATCH` error code.
 #[test]
@@ -538,6 +575,43 @@ fn keystore2_import_wrapped_key_success() {
     perform_sym_key_encrypt_decrypt_op(&sl.binder, &key_metadata);
 }
 
+/// Build wrapped AES and 3DES keys with `WrappedKeyBuilder`, using a masking key, import them and
+/// use them for crypto operations. Test should import the wrapped keys and perform crypto
+/// operations successfully.
+#[test]
+fn keystore2_import_wrapped_aes_and_3des_keys_with_builder_success() {
+    let sl = SecLevel::tee();
+
+    let wrapping_key_alias = format!("ks_wrapping_key_test_import_5_{}_2048", getuid());
+    let wrapping_key_metadata = key_generations::import_wrapping_key(
+        &sl,
+        key_generations::RSA_2048_KEY,
+        Some(wrapping_key_alias),
+    )
+    .unwrap();
+
+    let mut masking_key = [0; 32];
+    rand_bytes(&mut masking_key).unwrap();
+
+    let mut aes_key = [0; 32];
+    rand_bytes(&mut aes_key).unwrap();
+    let mut triple_des_key = [0; 24];
+    rand_bytes(&mut triple_des_key).unwrap();
+
+    for (name, builder) in [
+        ("aes", key_generations::WrappedKeyBuilder::aes(&aes_key)),
+        ("3des", key_generations::WrappedKeyBuilder::triple_des(&triple_des_key)),
+    ] {
+        let alias = format!("ks_wrapped_{}_key_builder_{}", name, getuid());
+        let key_metadata = builder
+            .masking_key(&masking_key)
+            .import(&sl, Some(alias), &wrapping_key_metadata)
+            .expect("Failed to import wrapped key.");
+
+        perform_sym_key_encrypt_decrypt_op(&sl.binder, &key_metadata);
+    }
+}
+
 /// Import wrapping-key without specifying KeyPurpose::WRAP_KEY in import key parameters. Try to
 /// use this as wrapping-key for importing wrapped-key. Test should fail with an error code
 /// `INCOMPATIBLE_PURPOSE` to import wrapped-key using a wrapping-key which doesn't possess
@@ -0,0 +1,126 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the policy for callers in isolated processes, as selected by the property
+//! `ro.keystore.isolated_process_policy`. Each test checks the behavior of one policy and
+//! passes trivially on devices with the other one. The tests run with an app context, because
+//! `isolated_app` cannot find the keystore service.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, KeyPermission::KeyPermission,
+    ResponseCode::ResponseCode,
+};
+use keystore2_test_utils::{
+    get_keystore_service, key_generations, key_generations::Error, run_as, SecLevel,
+};
+use nix::unistd::{Gid, Uid};
+
+static APP_CTX: &str = "u:r:untrusted_app:s0:c91,c256,c10,c20";
+
+/// An isolated UID of user 0, see AID_ISOLATED_START.
+const ISOLATED_UID: u32 = 99123;
+
+const APP_UID: u32 = 10011;
+
+fn isolated_process_policy() -> String {
+    rustutils::system_properties::read("ro.keystore.isolated_process_policy")
+        .unwrap()
+        .unwrap_or_else(|| "reject".to_string())
+}
+
+fn app_key(alias: &str) -> KeyDescriptor {
+    KeyDescriptor { domain: Domain::APP, nspace: -1, alias: Some(alias.to_string()), blob: None }
+}
+
+/// Runs `f` in a new process with the given UID.
+fn run_as_uid<F, R>(uid: u32, f: F) -> R
+where
+    R: serde::Serialize + serde::de::DeserializeOwned,
+    F: 'static + Send + FnOnce() -> R,
+{
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe { run_as::run_as(APP_CTX, Uid::from_raw(uid), Gid::from_raw(uid), f) }
+}
+
+/// With the reject policy, every call of an isolated process fails with `PERMISSION_DENIED`,
+/// whatever the key or the domain.
+#[test]
+fn keystore2_isolated_process_rejected() {
+    if isolated_process_policy() != "reject" {
+        return;
+    }
+    run_as_uid(ISOLATED_UID, || {
+        let keystore2 = get_keystore_service();
+        let denied = Err(Error::Rc(ResponseCode::PERMISSION_DENIED));
+
+        let result = key_generations::map_ks_error(
+            keystore2.getSecurityLevel(SecurityLevel::TRUSTED_ENVIRONMENT),
+        );
+        assert_eq!(denied, result.map(|_| ()));
+        let result = key_generations::map_ks_error(keystore2.listEntries(Domain::APP, -1));
+        assert_eq!(denied, result.map(|_| ()));
+        let result = key_generations::map_ks_error(keystore2.getKeyEntry(&app_key("missing")));
+        assert_eq!(denied, result.map(|_| ()));
+        let result = key_generations::map_ks_error(keystore2.getKeyEntry(&KeyDescriptor {
+            domain: Domain::SELINUX,
+            nspace: 0,
+            alias: Some("missing".to_string()),
+            blob: None,
+        }));
+        assert_eq!(denied, result.map(|_| ()));
+        let result = key_generations::map_ks_error(keystore2.deleteKey(&app_key("missing")));
+        assert_eq!(denied, result);
+    });
+}
+
+/// With the ephemeral policy, an isolated process can use the keys of its namespace, but the
+/// next process with the same UID starts with an empty namespace, and isolated processes cannot
+/// grant their keys.
+#[test]
+fn keystore2_isolated_process_namespace_is_ephemeral() {
+    static ALIAS: &str = "ks_isolated_process_test_key";
+    if isolated_process_policy() != "ephemeral" {
+        return;
+    }
+
+    run_as_uid(ISOLATED_UID, || {
+        let sl = SecLevel::tee();
+        key_generations::generate_ec_p256_signing_key(
+            &sl,
+            Domain::APP,
+            -1,
+            Some(ALIAS.to_string()),
+            None,
+        )
+        .unwrap();
+        let entries = sl.keystore2.listEntries(Domain::APP, -1).unwrap();
+        assert!(entries.iter().any(|k| k.alias.as_deref() == Some(ALIAS)));
+
+        let result = key_generations::map_ks_error(sl.keystore2.grant(
+            &app_key(ALIAS),
+            APP_UID.try_into().unwrap(),
+            KeyPermission::USE.0,
+        ));
+        assert_eq!(Err(Error::Rc(ResponseCode::PERMISSION_DENIED)), result.map(|_| ()));
+    });
+
+    // `run_as` forks a new process, which gets the UID after the first process died.
+    run_as_uid(ISOLATED_UID, || {
+        let keystore2 = get_keystore_service();
+        let result = key_generations::map_ks_error(keystore2.getKeyEntry(&app_key(ALIAS)));
+        assert_eq!(Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)), result.map(|_| ()));
+        assert!(keystore2.listEntries(Domain::APP, -1).unwrap().is_empty());
+    });
+}
@@ -0,0 +1,108 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of IKeystoreKeyLiveness, which signs a fresh statement with an attested key.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Digest::Digest, EcCurve::EcCurve,
+};
+use android_security_keyliveness::aidl::android::security::keyliveness::IKeystoreKeyLiveness::IKeystoreKeyLiveness;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
+};
+use keystore2_test_utils::{key_generations, key_generations::Error, SecLevel};
+use openssl::hash::MessageDigest;
+use openssl::sign::Verifier;
+use openssl::x509::X509;
+use serde_cbor::Value;
+
+static KEY_LIVENESS_SERVICE_NAME: &str = "android.security.keyliveness";
+
+fn get_key_liveness_service() -> binder::Strong<dyn IKeystoreKeyLiveness> {
+    binder::get_interface(KEY_LIVENESS_SERVICE_NAME).unwrap()
+}
+
+fn selinux_key(alias: &str) -> KeyDescriptor {
+    KeyDescriptor {
+        domain: Domain::SELINUX,
+        nspace: key_generations::SELINUX_SHELL_NAMESPACE,
+        alias: Some(alias.to_string()),
+        blob: None,
+    }
+}
+
+/// The statement of an attested key holds the challenge and the attestation certificate of the
+/// key, and is signed with the key.
+#[test]
+fn keystore2_key_liveness_statement_signed_by_key() {
+    static ALIAS: &str = "ks_key_liveness_test_key";
+    let sl = SecLevel::tee();
+    let key_metadata = key_generations::generate_ec_p256_signing_key(
+        &sl,
+        Domain::SELINUX,
+        key_generations::SELINUX_SHELL_NAMESPACE,
+        Some(ALIAS.to_string()),
+        Some(b"creation challenge"),
+    )
+    .unwrap();
+
+    let attestation = get_key_liveness_service()
+        .attestKeyLiveness(sl.level, &selinux_key(ALIAS), b"liveness challenge")
+        .unwrap();
+    assert_eq!(Some(&attestation.certificate), key_metadata.certificate.as_ref());
+    assert_eq!(Some(&attestation.certificateChain), key_metadata.certificateChain.as_ref());
+
+    let Value::Array(fields) = serde_cbor::from_slice(&attestation.statement).unwrap() else {
+        panic!("Statement is not a CBOR array.");
+    };
+    assert_eq!(fields.len(), 4);
+    assert_eq!(fields[0], Value::Text("android.keystore2.key_liveness.v1".to_string()));
+    assert_eq!(fields[1], Value::Bytes(b"liveness challenge".to_vec()));
+    assert!(matches!(fields[2], Value::Integer(t) if t > 0));
+    assert_eq!(fields[3], Value::Bytes(attestation.certificate.clone()));
+
+    let cert = X509::from_der(&attestation.certificate).unwrap();
+    let pub_key = cert.public_key().unwrap();
+    let mut verifier = Verifier::new(MessageDigest::sha256(), pub_key.as_ref()).unwrap();
+    verifier.update(&attestation.statement).unwrap();
+    assert!(verifier.verify(&attestation.signature).unwrap());
+
+    sl.keystore2.deleteKey(&selinux_key(ALIAS)).unwrap();
+}
+
+/// Keys without an attestation certificate chain cannot vouch for their properties, so they are
+/// rejected.
+#[test]
+fn keystore2_key_liveness_requires_attestation() {
+    static ALIAS: &str = "ks_key_liveness_unattested_test_key";
+    let sl = SecLevel::tee();
+    key_generations::generate_ec_key(
+        &sl,
+        Domain::SELINUX,
+        key_generations::SELINUX_SHELL_NAMESPACE,
+        Some(ALIAS.to_string()),
+        EcCurve::P_256,
+        Digest::SHA_2_256,
+    )
+    .unwrap();
+
+    let result = key_generations::map_ks_error(get_key_liveness_service().attestKeyLiveness(
+        sl.level,
+        &selinux_key(ALIAS),
+        b"liveness challenge",
+    ));
+    assert_eq!(Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)), result.map(|_| ()));
+
+    sl.keystore2.deleteKey(&selinux_key(ALIAS)).unwrap();
+}
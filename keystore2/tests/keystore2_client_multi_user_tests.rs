@@ -0,0 +1,293 @@
+// Copyright 2026, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the visibility of keys across Android users. Work, clone, and private profiles are
+//! Android users of their own, so the tests model them as users with the same app installed.
+
+use crate::keystore2_client_test_utils::perform_sample_sign_operation;
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Digest::Digest, KeyPurpose::KeyPurpose,
+};
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::IKeystoreMaintenance;
+use android_system_keystore2::aidl::android::system::keystore2::{
+    Domain::Domain, KeyDescriptor::KeyDescriptor, KeyPermission::KeyPermission,
+    ResponseCode::ResponseCode,
+};
+use keystore2_test_utils::{
+    authorizations, get_keystore_service, key_generations, key_generations::Error, run_as, SecLevel,
+};
+use nix::unistd::{Gid, Uid};
+use rustutils::users::AID_USER_OFFSET;
+
+static ROOT_CTX: &str = "u:r:su:s0";
+static APP_CTX: &str = "u:r:untrusted_app:s0:c91,c256,c10,c20";
+
+const APPLICATION_ID: u32 = 10011;
+const PARENT_USER: u32 = 93;
+const WORK_PROFILE: u32 = 94;
+const CLONE_PROFILE: u32 = 95;
+const PRIVATE_PROFILE: u32 = 96;
+const PROFILES: [u32; 3] = [WORK_PROFILE, CLONE_PROFILE, PRIVATE_PROFILE];
+
+fn app_uid(user_id: u32) -> u32 {
+    user_id * AID_USER_OFFSET + APPLICATION_ID
+}
+
+fn get_maintenance() -> binder::Strong<dyn IKeystoreMaintenance> {
+    binder::get_interface("android.security.maintenance").unwrap()
+}
+
+fn app_key(alias: &str) -> KeyDescriptor {
+    KeyDescriptor { domain: Domain::APP, nspace: -1, alias: Some(alias.to_string()), blob: None }
+}
+
+/// Runs `f` as the test app in the given Android user.
+fn run_as_app<F, R>(user_id: u32, f: F) -> R
+where
+    R: serde::Serialize + serde::de::DeserializeOwned,
+    F: 'static + Send + FnOnce() -> R,
+{
+    let uid = app_uid(user_id);
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe { run_as::run_as(APP_CTX, Uid::from_raw(uid), Gid::from_raw(uid), f) }
+}
+
+/// Generates a signing key with the given alias and returns its key id.
+fn generate_app_key(alias: &'static str) -> i64 {
+    let sl = SecLevel::tee();
+    let _ = sl.keystore2.deleteKey(&app_key(alias));
+    let key_metadata = key_generations::generate_ec_p256_signing_key(
+        &sl,
+        Domain::APP,
+        -1,
+        Some(alias.to_string()),
+        None,
+    )
+    .unwrap();
+    assert_eq!(key_metadata.key.domain, Domain::KEY_ID);
+    key_metadata.key.nspace
+}
+
+fn sign_with(sl: &SecLevel, key: &KeyDescriptor) -> Result<(), Error> {
+    let op_response = key_generations::map_ks_error(sl.binder.createOperation(
+        key,
+        &authorizations::AuthSetBuilder::new().purpose(KeyPurpose::SIGN).digest(Digest::SHA_2_256),
+        false,
+    ))?;
+    key_generations::map_ks_error(perform_sample_sign_operation(&op_response.iOperation.unwrap()))
+}
+
+/// Generate a key in the parent user and try to access it as the same app in each profile by
+/// alias, by key id, and by listing entries. The key must not be visible in any profile.
+#[test]
+fn keystore2_app_key_not_visible_in_other_profiles() {
+    static ALIAS: &str = "ks_multi_user_test_key_1";
+
+    let key_id = run_as_app(PARENT_USER, || generate_app_key(ALIAS));
+
+    for profile in PROFILES {
+        run_as_app(profile, move || {
+            let keystore2 = get_keystore_service();
+
+            let result = key_generations::map_ks_error(keystore2.getKeyEntry(&app_key(ALIAS)));
+            assert_eq!(Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)), result.map(|_| ()));
+
+            let result = key_generations::map_ks_error(keystore2.getKeyEntry(&KeyDescriptor {
+                domain: Domain::KEY_ID,
+                nspace: key_id,
+                alias: None,
+                blob: None,
+            }));
+            assert_eq!(Err(Error::Rc(ResponseCode::PERMISSION_DENIED)), result.map(|_| ()));
+
+            let entries = keystore2.listEntries(Domain::APP, -1).unwrap();
+            assert!(entries.iter().all(|k| k.alias.as_deref() != Some(ALIAS)));
+        });
+    }
+
+    run_as_app(PARENT_USER, || {
+        get_keystore_service().deleteKey(&app_key(ALIAS)).unwrap();
+    });
+}
+
+/// Generate a key with the same alias in the parent user and in each profile. Every user must
+/// see its own key, and deleting the key in one user must leave the others untouched.
+#[test]
+fn keystore2_same_alias_in_profiles_are_independent_keys() {
+    static ALIAS: &str = "ks_multi_user_test_key_2";
+
+    let mut key_ids = vec![run_as_app(PARENT_USER, || generate_app_key(ALIAS))];
+    for profile in PROFILES {
+        key_ids.push(run_as_app(profile, || generate_app_key(ALIAS)));
+    }
+    let mut unique = key_ids.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), key_ids.len());
+
+    run_as_app(WORK_PROFILE, || {
+        get_keystore_service().deleteKey(&app_key(ALIAS)).unwrap();
+    });
+
+    for (user_id, key_id) in [PARENT_USER, CLONE_PROFILE, PRIVATE_PROFILE]
+        .into_iter()
+        .zip([key_ids[0], key_ids[2], key_ids[3]])
+    {
+        run_as_app(user_id, move || {
+            let sl = SecLevel::tee();
+            let key_entry = sl.keystore2.getKeyEntry(&app_key(ALIAS)).unwrap();
+            assert_eq!(key_entry.metadata.key.nspace, key_id);
+            assert_eq!(Ok(()), sign_with(&sl, &key_entry.metadata.key));
+            sl.keystore2.deleteKey(&app_key(ALIAS)).unwrap();
+        });
+    }
+}
+
+/// Grant a key of the parent user to the same app in the work profile. The work profile can
+/// use the key through the grant, but the other profiles can not.
+#[test]
+fn keystore2_grant_to_work_profile() {
+    static ALIAS: &str = "ks_multi_user_test_key_3";
+
+    let grant_id = run_as_app(PARENT_USER, || {
+        generate_app_key(ALIAS);
+        let grant = get_keystore_service()
+            .grant(
+                &app_key(ALIAS),
+                app_uid(WORK_PROFILE).try_into().unwrap(),
+                KeyPermission::USE.0 | KeyPermission::GET_INFO.0,
+            )
+            .unwrap();
+        assert_eq!(grant.domain, Domain::GRANT);
+        grant.nspace
+    });
+
+    let grant = KeyDescriptor { domain: Domain::GRANT, nspace: grant_id, alias: None, blob: None };
+    let granted_key = grant.clone();
+    run_as_app(WORK_PROFILE, move || {
+        let sl = SecLevel::tee();
+        let key_entry = sl.keystore2.getKeyEntry(&granted_key).unwrap();
+        assert_eq!(Ok(()), sign_with(&sl, &key_entry.metadata.key));
+        // The grant does not make the key visible by alias.
+        let result = key_generations::map_ks_error(sl.keystore2.getKeyEntry(&app_key(ALIAS)));
+        assert_eq!(Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)), result.map(|_| ()));
+    });
+
+    for profile in [CLONE_PROFILE, PRIVATE_PROFILE] {
+        let granted_key = grant.clone();
+        run_as_app(profile, move || {
+            let result =
+                key_generations::map_ks_error(get_keystore_service().getKeyEntry(&granted_key));
+            assert_eq!(Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)), result.map(|_| ()));
+        });
+    }
+
+    run_as_app(PARENT_USER, || {
+        get_keystore_service().deleteKey(&app_key(ALIAS)).unwrap();
+    });
+}
+
+/// Query the visibility of a key that was granted within its user and across users, and check
+/// that ungranting the key is reflected.
+#[test]
+fn keystore2_get_key_visibility() {
+    static ALIAS: &str = "ks_multi_user_test_key_4";
+    let other_app_uid = app_uid(PARENT_USER) + 1;
+
+    run_as_app(PARENT_USER, move || {
+        generate_app_key(ALIAS);
+        let keystore2 = get_keystore_service();
+        for uid in [app_uid(WORK_PROFILE), other_app_uid] {
+            keystore2
+                .grant(&app_key(ALIAS), uid.try_into().unwrap(), KeyPermission::USE.0)
+                .unwrap();
+        }
+    });
+
+    let owner_key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: app_uid(PARENT_USER).into(),
+        alias: Some(ALIAS.to_string()),
+        blob: None,
+    };
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe {
+        run_as::run_as(ROOT_CTX, Uid::from_raw(0), Gid::from_raw(0), move || {
+            let visibility = get_maintenance().getKeyVisibility(&owner_key).unwrap();
+            assert_eq!(visibility.key, owner_key);
+            assert_eq!(visibility.ownerUserId, PARENT_USER as i32);
+            let grantees: Vec<(i32, i32, bool)> =
+                visibility.grantees.iter().map(|g| (g.uid, g.userId, g.crossUser)).collect();
+            assert_eq!(
+                grantees,
+                vec![
+                    (other_app_uid as i32, PARENT_USER as i32, false),
+                    (app_uid(WORK_PROFILE) as i32, WORK_PROFILE as i32, true),
+                ]
+            );
+            assert!(visibility.grantees.iter().all(|g| g.accessVector == KeyPermission::USE.0));
+        })
+    };
+
+    run_as_app(PARENT_USER, move || {
+        let keystore2 = get_keystore_service();
+        keystore2.ungrant(&app_key(ALIAS), app_uid(WORK_PROFILE).try_into().unwrap()).unwrap();
+    });
+
+    let owner_key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: app_uid(PARENT_USER).into(),
+        alias: Some(ALIAS.to_string()),
+        blob: None,
+    };
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe {
+        run_as::run_as(ROOT_CTX, Uid::from_raw(0), Gid::from_raw(0), move || {
+            let maintenance = get_maintenance();
+            let visibility = maintenance.getKeyVisibility(&owner_key).unwrap();
+            assert_eq!(visibility.grantees.len(), 1);
+            assert_eq!(visibility.grantees[0].uid, other_app_uid as i32);
+
+            // Keys of the same app in a profile are distinct keys.
+            let profile_key =
+                KeyDescriptor { nspace: app_uid(WORK_PROFILE).into(), ..owner_key.clone() };
+            let result = key_generations::map_ks_error(maintenance.getKeyVisibility(&profile_key));
+            assert_eq!(Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)), result.map(|_| ()));
+        })
+    };
+
+    run_as_app(PARENT_USER, || {
+        get_keystore_service().deleteKey(&app_key(ALIAS)).unwrap();
+    });
+}
+
+/// Try to query the visibility of a key as an app UID, which does not have the DUMP
+/// permission. This must fail with `PERMISSION_DENIED`.
+#[test]
+fn keystore2_get_key_visibility_fails_perm_denied() {
+    let uid = app_uid(PARENT_USER);
+    // SAFETY: The test is run in a separate process with no other threads.
+    unsafe {
+        run_as::run_as(ROOT_CTX, Uid::from_raw(uid), Gid::from_raw(uid), || {
+            let key = KeyDescriptor {
+                domain: Domain::APP,
+                nspace: app_uid(PARENT_USER).into(),
+                alias: Some("ks_multi_user_test_key_5".to_string()),
+                blob: None,
+            };
+            let result = key_generations::map_ks_error(get_maintenance().getKeyVisibility(&key));
+            assert_eq!(Err(Error::Rc(ResponseCode::PERMISSION_DENIED)), result.map(|_| ()));
+        })
+    };
+}
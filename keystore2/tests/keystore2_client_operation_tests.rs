@@ -24,7 +24,7 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     IKeystoreOperation::IKeystoreOperation, ResponseCode::ResponseCode,
 };
 use keystore2_test_utils::{
-    authorizations, key_generations, key_generations::Error, run_as, SecLevel,
+    authorizations, key_generations, key_generations::Error, key_upgrade, run_as, SecLevel,
 };
 use nix::unistd::{getuid, Gid, Uid};
 use rustutils::users::AID_USER_OFFSET;
@@ -515,6 +515,38 @@ fn keystore2_op_abort_success_test() {
     assert_eq!(Error::Km(ErrorCode::INVALID_OPERATION_HANDLE), result.unwrap_err());
 }
 
+/// Forces an upgrade of the blob of a key and checks that the key can be used with the upgraded
+/// blob, and that the superseded blob is garbage collected.
+#[test]
+fn keystore2_op_with_forced_key_upgrade_success_test() {
+    if !key_upgrade::is_supported() {
+        return;
+    }
+    let sl = SecLevel::tee();
+    let key_metadata = key_generations::generate_ec_p256_signing_key(
+        &sl,
+        Domain::APP,
+        -1,
+        Some("ks_op_forced_upgrade_key".to_string()),
+        None,
+    )
+    .unwrap();
+    let key_id = sl.keystore2.getKeyEntry(&key_metadata.key).unwrap().metadata.key.nspace;
+
+    key_upgrade::exercise_key_upgrade(key_id, || {
+        let op_response = sl.binder.createOperation(
+            &key_metadata.key,
+            &authorizations::AuthSetBuilder::new()
+                .purpose(KeyPurpose::SIGN)
+                .digest(Digest::SHA_2_256),
+            false,
+        )?;
+        perform_sample_sign_operation(&op_response.iOperation.unwrap())?;
+        Ok(())
+    })
+    .unwrap();
+}
+
 /// Executes an operation in a thread. Performs an `update` operation repeatedly till the user
 /// interrupts it or encounters any error other than `OPERATION_BUSY`.
 /// Return `false` in case of any error other than `OPERATION_BUSY`, otherwise it returns true.
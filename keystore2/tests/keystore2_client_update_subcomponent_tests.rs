@@ -246,7 +246,9 @@ fn keystore2_update_subcomponent_fails_permission_denied() {
                 let keystore2 = get_keystore_service();
 
                 let other_cert: [u8; 32] = [124; 32];
-                let other_cert_chain: [u8; 32] = [13; 32];
+                // Chains stored by apps must consist of DER SEQUENCEs.
+                let mut other_cert_chain: [u8; 32] = [13; 32];
+                other_cert_chain[..2].copy_from_slice(&[0x30, 30]);
 
                 keystore2
                     .updateSubcomponent(
@@ -15,28 +15,19 @@
 //! Tests for user authentication interactions (via `IKeystoreAuthorization`).
 
 use crate::keystore2_client_test_utils::BarrierReached;
-use android_security_authorization::aidl::android::security::authorization::{
-    IKeystoreAuthorization::IKeystoreAuthorization
-};
-use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::{
-     IKeystoreMaintenance,
-};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    Algorithm::Algorithm, Digest::Digest, EcCurve::EcCurve, HardwareAuthToken::HardwareAuthToken,
-    HardwareAuthenticatorType::HardwareAuthenticatorType, SecurityLevel::SecurityLevel,
-    KeyPurpose::KeyPurpose
+    Algorithm::Algorithm, Digest::Digest, EcCurve::EcCurve, KeyPurpose::KeyPurpose,
+    SecurityLevel::SecurityLevel,
 };
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::IKeystoreMaintenance;
 use android_system_keystore2::aidl::android::system::keystore2::{
     CreateOperationResponse::CreateOperationResponse, Domain::Domain, KeyDescriptor::KeyDescriptor,
     KeyMetadata::KeyMetadata,
 };
-use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::{
-    Timestamp::Timestamp,
-};
 use keystore2_test_utils::{
-    get_keystore_service, run_as, authorizations::AuthSetBuilder,
+    authorizations::AuthSetBuilder, get_keystore_service, run_as, user_auth::UserAuth,
 };
-use log::{warn, info};
+use log::{info, warn};
 use nix::unistd::{Gid, Uid};
 use rustutils::users::AID_USER_OFFSET;
 
@@ -60,10 +51,6 @@ const WEAK_UNLOCK_ENABLED: bool = true;
 const WEAK_UNLOCK_DISABLED: bool = false;
 const UNFORCED: bool = false;
 
-fn get_authorization() -> binder::Strong<dyn IKeystoreAuthorization> {
-    binder::get_interface("android.security.authorization").unwrap()
-}
-
 fn get_maintenance() -> binder::Strong<dyn IKeystoreMaintenance> {
     binder::get_interface("android.security.maintenance").unwrap()
 }
@@ -211,35 +198,34 @@ fn keystore2_test_unlocked_device_required() {
     // Now that the separate process has been forked off, it's safe to use binder.
     let user = TestUser::new();
     let user_id = user.id;
-    let auth_service = get_authorization();
+    let user_auth =
+        UserAuth::new(user_id, PASSWORD, GK_SID).with_biometric_sids(&[BIO_SID1, BIO_SID2]);
 
     // Lock and unlock to ensure super keys are already created.
-    auth_service.onDeviceLocked(user_id, &[BIO_SID1, BIO_SID2], WEAK_UNLOCK_DISABLED).unwrap();
-    auth_service.onDeviceUnlocked(user_id, Some(PASSWORD)).unwrap();
-    auth_service.addAuthToken(&fake_lskf_token(GK_SID)).unwrap();
+    user_auth.lock(WEAK_UNLOCK_DISABLED).unwrap();
+    user_auth.unlock_with_password().unwrap();
 
     info!("trigger child process action A while unlocked and wait for completion");
     child_handle.send(&BarrierReached {});
     child_handle.recv();
 
     // Move to locked and don't allow weak unlock, so super keys are wiped.
-    auth_service.onDeviceLocked(user_id, &[BIO_SID1, BIO_SID2], WEAK_UNLOCK_DISABLED).unwrap();
+    user_auth.lock(WEAK_UNLOCK_DISABLED).unwrap();
 
     info!("trigger child process action B while locked and wait for completion");
     child_handle.send(&BarrierReached {});
     child_handle.recv();
 
     // Unlock with password => loads super key from database.
-    auth_service.onDeviceUnlocked(user_id, Some(PASSWORD)).unwrap();
-    auth_service.addAuthToken(&fake_lskf_token(GK_SID)).unwrap();
+    user_auth.unlock_with_password().unwrap();
 
     info!("trigger child process action C while lskf-unlocked and wait for completion");
     child_handle.send(&BarrierReached {});
     child_handle.recv();
 
     // Move to locked and allow weak unlock, then do a weak unlock.
-    auth_service.onDeviceLocked(user_id, &[BIO_SID1, BIO_SID2], WEAK_UNLOCK_ENABLED).unwrap();
-    auth_service.onDeviceUnlocked(user_id, None).unwrap();
+    user_auth.lock(WEAK_UNLOCK_ENABLED).unwrap();
+    user_auth.unlock_weak().unwrap();
 
     info!("trigger child process action D while weak-unlocked and wait for completion");
     child_handle.send(&BarrierReached {});
@@ -247,15 +233,3 @@ fn keystore2_test_unlocked_device_required() {
 
     assert_eq!(child_handle.get_result(), Ok(()), "child process failed");
 }
-
-/// Generate a fake [`HardwareAuthToken`] for the given sid.
-fn fake_lskf_token(gk_sid: i64) -> HardwareAuthToken {
-    HardwareAuthToken {
-        challenge: 0,
-        userId: gk_sid,
-        authenticatorId: 0,
-        authenticatorType: HardwareAuthenticatorType::PASSWORD,
-        timestamp: Timestamp { milliSeconds: 123 },
-        mac: vec![1, 2, 3],
-    }
-}